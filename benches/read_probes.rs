@@ -0,0 +1,53 @@
+//! Benchmark of the client CSV reader against the previous, sequential reader
+use caracat::models::Probe;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use csv::ReaderBuilder;
+use saimiris::client::handler::read_probes_from_csv;
+use std::io::Cursor;
+
+/// Reader of the probes before the batched rewrite, one record allocated per line.
+fn sequential_read_probes_from_csv(data: &[u8]) -> Vec<Probe> {
+    ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .from_reader(data)
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+fn probes_csv(probes: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(probes * 32);
+    for i in 0..probes {
+        data.extend_from_slice(
+            format!(
+                "10.{}.{}.{},24000,33434,{},udp\n",
+                (i >> 16) & 0xff,
+                (i >> 8) & 0xff,
+                i & 0xff,
+                i % 32 + 1
+            )
+            .as_bytes(),
+        );
+    }
+    data
+}
+
+fn bench_read_probes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read_probes_from_csv");
+    group.sample_size(10);
+    for probes in [100_000, 1_000_000] {
+        let data = probes_csv(probes);
+        group.throughput(Throughput::Elements(probes as u64));
+        group.bench_with_input(BenchmarkId::new("sequential", probes), &data, |b, data| {
+            b.iter(|| sequential_read_probes_from_csv(data))
+        });
+        group.bench_with_input(BenchmarkId::new("batched", probes), &data, |b, data| {
+            b.iter(|| read_probes_from_csv(Cursor::new(data)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_read_probes);
+criterion_main!(benches);
@@ -0,0 +1,158 @@
+use anyhow::{Context, Result};
+use metrics::counter;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
+
+use crate::agent::commit::MessageOffset;
+
+/// Name of the current audit log file, the rotated ones are suffixed with `.1`, `.2`, ...
+pub const AUDIT_LOG_FILE: &str = "audit.log";
+
+/// Decision of the agent on a probes message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditDecision {
+    /// The probes were queued to the caracat instances
+    Accepted,
+    /// The probes were dropped
+    Rejected,
+}
+
+/// Entry of the audit log, one per probes message consumed by the agent.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: String,
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    // Signing key ID claimed by the message headers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature_key_id: Option<String>,
+    // Client whose signature was verified
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub measurement_id: Option<String>,
+    // Probes queued, or probes of the message if rejected once deserialized
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probes: Option<usize>,
+    // Source IP requested by the message headers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_ip: Option<String>,
+    pub decision: AuditDecision,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl AuditEntry {
+    /// Entry of an accepted message, completed as the message is processed.
+    pub fn new(offset: MessageOffset) -> Self {
+        AuditEntry {
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            topic: offset.topic,
+            partition: offset.partition,
+            offset: offset.offset,
+            signature_key_id: None,
+            client_id: None,
+            measurement_id: None,
+            probes: None,
+            source_ip: None,
+            decision: AuditDecision::Accepted,
+            reason: None,
+        }
+    }
+
+    pub fn rejected(&self, reason: &str) -> Self {
+        AuditEntry {
+            decision: AuditDecision::Rejected,
+            reason: Some(reason.to_string()),
+            ..self.clone()
+        }
+    }
+}
+
+/// Append-only log of the probes messages consumed by the agent, as JSON lines, for the
+/// handling of abuse reports.
+///
+/// Once the current file would exceed `max_bytes`, it is rotated to `audit.log.1` (and the
+/// previous ones shifted), keeping up to `max_files` rotated files.
+#[derive(Debug)]
+pub struct AuditLog {
+    dir: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl AuditLog {
+    pub fn open(dir: &Path, max_bytes: u64, max_files: usize) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create the audit log directory {:?}", dir))?;
+        let (file, size) = open_current(dir)?;
+        info!("Audit log in {:?}", dir);
+        Ok(AuditLog {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(AUDIT_LOG_FILE)
+    }
+
+    /// Append an entry, rotating the files first if needed.
+    pub fn write(&mut self, entry: &AuditEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(&line)?;
+        self.file.flush()?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Append an entry, the errors are only logged so that the probes are still processed.
+    pub fn record(&mut self, entry: &AuditEntry) {
+        if let Err(e) = self.write(entry) {
+            error!("Failed to write the audit log entry: {:#}", e);
+            counter!("saimiris_audit_log_errors_total").increment(1);
+        }
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        let rotated = |index: usize| self.dir.join(format!("{}.{}", AUDIT_LOG_FILE, index));
+        if self.max_files == 0 {
+            std::fs::remove_file(self.path())?;
+        } else {
+            // The oldest file is overwritten by the next one
+            for index in (1..self.max_files).rev() {
+                let from = rotated(index);
+                if from.exists() {
+                    std::fs::rename(from, rotated(index + 1))?;
+                }
+            }
+            std::fs::rename(self.path(), rotated(1))?;
+        }
+        (self.file, self.size) = open_current(&self.dir)?;
+        Ok(())
+    }
+}
+
+fn open_current(dir: &Path) -> Result<(File, u64)> {
+    let path = dir.join(AUDIT_LOG_FILE);
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open the audit log {:?}", path))?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
@@ -0,0 +1,70 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+
+use crate::agent::events::{EventKind, EventLog};
+
+/// Interval at which the sender queues are checked while the consumption is paused.
+pub const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Fill of the fullest queue, from 0 (empty) to 1 (full).
+pub fn queue_fill<'a, T: 'a>(senders: impl IntoIterator<Item = &'a Sender<T>>) -> f64 {
+    senders
+        .into_iter()
+        .map(|sender| 1.0 - sender.capacity() as f64 / sender.max_capacity().max(1) as f64)
+        .fold(0.0, f64::max)
+}
+
+/// Pauses the consumption of the probes while the sender queues are filled above the pause
+/// watermark, until they are drained below the resume watermark, so that the probes are left
+/// in Kafka rather than queued by the agent.
+#[derive(Debug, Clone)]
+pub struct Backpressure {
+    pause_watermark: f64,
+    resume_watermark: f64,
+    paused: bool,
+    events: EventLog,
+}
+
+impl Backpressure {
+    pub fn new(pause_watermark: f64, resume_watermark: f64) -> Self {
+        Backpressure {
+            pause_watermark,
+            resume_watermark,
+            paused: false,
+            events: EventLog::disabled(),
+        }
+    }
+
+    /// Emit a `throttling_engaged` event whenever the consumption is paused.
+    pub fn with_events(mut self, events: EventLog) -> Self {
+        self.events = events;
+        self
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Update with the fill of the queues. Returns whether the consumption is paused,
+    /// if it changed.
+    pub fn update(&mut self, fill: f64) -> Option<bool> {
+        let paused = if self.paused {
+            fill > self.resume_watermark
+        } else {
+            fill >= self.pause_watermark
+        };
+        if paused == self.paused {
+            return None;
+        }
+        self.paused = paused;
+        if paused {
+            self.events.emit(
+                EventKind::ThrottlingEngaged,
+                None,
+                BTreeMap::from([("queue_fill".to_string(), format!("{:.2}", fill))]),
+            );
+        }
+        Some(paused)
+    }
+}
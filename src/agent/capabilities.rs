@@ -0,0 +1,196 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::probe::ProbeTags;
+
+/// Versions of the probe schema understood by the agent: 1 is the original schema,
+/// 2 adds the probe `round`, 3 the probe `dscp`, 4 the probe `src_addr`, 5 the probe `instance`.
+pub const PROBE_SCHEMA_VERSIONS: [u32; 5] = [1, 2, 3, 4, 5];
+
+/// Probes messages with a `measurement_id` header, reported to the gateway.
+pub const FEATURE_MEASUREMENT_TRACKING: &str = "measurement_tracking";
+/// Messages with a `cancel_measurement` header, dropping the probes not sent yet.
+pub const FEATURE_CANCELLATION: &str = "cancellation";
+/// Probes sent with their DSCP, by the agents whose instances all mark them: on Linux, all but
+/// the `pcap` sender backend, which sends every probe with the default traffic class.
+pub const FEATURE_DSCP: &str = "dscp";
+
+/// Probes sent from their own source address, within the prefixes of the agent.
+pub const FEATURE_SRC_ADDR: &str = "src_addr";
+/// Messages with an `expand_ttl` header, whose targets are expanded into probes by the agent.
+pub const FEATURE_TTL_EXPANSION: &str = "ttl_expansion";
+/// Probes pinned to a caracat instance by name, with the `instance` of the probe or header.
+pub const FEATURE_INSTANCE_PINNING: &str = "instance_pinning";
+/// Messages with a `src_ports` header, whose source ports are selected per flow by the agent.
+pub const FEATURE_SRC_PORTS: &str = "src_ports";
+
+pub const FEATURES: [&str; 6] = [
+    FEATURE_MEASUREMENT_TRACKING,
+    FEATURE_CANCELLATION,
+    FEATURE_SRC_ADDR,
+    FEATURE_TTL_EXPANSION,
+    FEATURE_INSTANCE_PINNING,
+    FEATURE_SRC_PORTS,
+];
+
+/// Capabilities of an agent, published as JSON to the agents topic, keyed by agent ID.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentCapabilities {
+    pub agent_id: String,
+    pub version: String,
+    pub probe_schema_versions: Vec<u32>,
+    #[serde(default)]
+    pub features: Vec<String>,
+    // Random ID of the running agent process, to tell apart agents sharing an agent ID
+    #[serde(default)]
+    pub run_id: String,
+    #[serde(default)]
+    pub published_at_ns: u64,
+    // Start of the running agent process, electing the leader of a warm standby pair
+    #[serde(default)]
+    pub started_at_ns: u64,
+}
+
+impl AgentCapabilities {
+    /// Capabilities of this build of the agent.
+    pub fn current(agent_id: &str) -> Self {
+        AgentCapabilities {
+            agent_id: agent_id.to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            probe_schema_versions: PROBE_SCHEMA_VERSIONS.to_vec(),
+            features: FEATURES.iter().map(|feature| feature.to_string()).collect(),
+            run_id: String::new(),
+            published_at_ns: 0,
+            started_at_ns: 0,
+        }
+    }
+
+    /// Advertise the DSCP marking of the probes, if all the instances of the agent support it.
+    pub fn with_dscp(mut self, supported: bool) -> Self {
+        if supported {
+            self.features.push(FEATURE_DSCP.to_string());
+        }
+        self
+    }
+
+    /// Fail if the agent cannot handle the messages the client would produce.
+    pub fn check(&self, requirements: &Requirements) -> Result<()> {
+        if !self
+            .probe_schema_versions
+            .contains(&requirements.probe_schema_version)
+        {
+            anyhow::bail!(
+                "Agent {} (saimiris {}) does not support probe schema version {} (supported: {:?}). Upgrade the agent",
+                self.agent_id,
+                self.version,
+                requirements.probe_schema_version,
+                self.probe_schema_versions
+            );
+        }
+        let missing: Vec<&str> = requirements
+            .features
+            .iter()
+            .filter(|feature| !self.features.iter().any(|f| f == *feature))
+            .copied()
+            .collect();
+        if !missing.is_empty() {
+            anyhow::bail!(
+                "Agent {} (saimiris {}) does not support: {}. Upgrade the agent",
+                self.agent_id,
+                self.version,
+                missing.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Probe schema version and features used by the messages of the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requirements {
+    pub probe_schema_version: u32,
+    pub features: Vec<&'static str>,
+}
+
+impl Requirements {
+    pub fn submission(tags: &ProbeTags, measurement_id: Option<&str>) -> Self {
+        let mut features = Vec::new();
+        if measurement_id.is_some() {
+            features.push(FEATURE_MEASUREMENT_TRACKING);
+        }
+        if tags.dscp != 0 {
+            features.push(FEATURE_DSCP);
+        }
+        // Agents of older schemas would silently drop the newer fields
+        let probe_schema_version = if tags.dscp != 0 {
+            3
+        } else if tags.round != 0 {
+            2
+        } else {
+            1
+        };
+        Requirements {
+            probe_schema_version,
+            features,
+        }
+    }
+
+    /// Require per-probe source addresses, if any probe has one.
+    pub fn with_sources(mut self, has_sources: bool) -> Self {
+        if has_sources {
+            self.probe_schema_version = self.probe_schema_version.max(4);
+            self.features.push(FEATURE_SRC_ADDR);
+        }
+        self
+    }
+
+    /// Require the expansion of targets into probes by the agent.
+    pub fn with_ttl_expansion(mut self, expand_ttl: bool) -> Self {
+        if expand_ttl {
+            self.features.push(FEATURE_TTL_EXPANSION);
+        }
+        self
+    }
+
+    /// Require the selection of the source ports by the agent.
+    pub fn with_src_ports(mut self, src_ports: bool) -> Self {
+        if src_ports {
+            self.features.push(FEATURE_SRC_PORTS);
+        }
+        self
+    }
+
+    /// Require the pinning of the probes to a caracat instance.
+    pub fn with_instance_pinning(mut self, pinned: bool) -> Self {
+        if pinned {
+            self.features.push(FEATURE_INSTANCE_PINNING);
+        }
+        self
+    }
+
+    pub fn cancellation() -> Self {
+        Requirements {
+            probe_schema_version: 1,
+            features: vec![FEATURE_CANCELLATION],
+        }
+    }
+}
+
+/// Check the published capabilities of the agents against the requirements of the client.
+/// Agents which have not published their capabilities predate the handshake, and are
+/// only reported.
+pub fn check_agents(
+    capabilities: &HashMap<String, AgentCapabilities>,
+    agents: &[String],
+    requirements: &Requirements,
+) -> Result<Vec<String>> {
+    let mut unknown = Vec::new();
+    for agent in agents {
+        match capabilities.get(agent) {
+            Some(agent_capabilities) => agent_capabilities.check(requirements)?,
+            None => unknown.push(agent.clone()),
+        }
+    }
+    Ok(unknown)
+}
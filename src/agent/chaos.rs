@@ -0,0 +1,84 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::LazyLock;
+use std::time::Duration;
+use tracing::trace;
+
+/// Percentage of the replies dropped by the receivers.
+pub const DROP_REPLIES_ENV: &str = "SAIMIRIS_CHAOS_DROP_REPLIES_PERCENT";
+/// Delay of the responses of the gateway, in milliseconds.
+pub const GATEWAY_DELAY_ENV: &str = "SAIMIRIS_CHAOS_GATEWAY_DELAY_MS";
+/// Percentage of the caracat sender creations failing.
+pub const FAIL_SENDER_ENV: &str = "SAIMIRIS_CHAOS_FAIL_SENDER_PERCENT";
+/// Percentage of the probes messages panicking the SendLoop sending them.
+pub const PANIC_SENDER_ENV: &str = "SAIMIRIS_CHAOS_PANIC_SENDER_PERCENT";
+
+/// Failure injected in a percentage of the occurrences of an operation. Injections are
+/// evenly spread rather than random, so that a test run can be reproduced.
+#[derive(Debug, Default)]
+pub struct Fault {
+    percent: u64,
+    occurrences: AtomicU64,
+}
+
+impl Fault {
+    pub fn new(percent: u64) -> Self {
+        Fault {
+            percent: percent.min(100),
+            occurrences: AtomicU64::new(0),
+        }
+    }
+
+    /// Fault of the percentage in the `name` environment variable, none if unset or invalid.
+    pub fn from_env(name: &str) -> Self {
+        std::env::var(name)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .map(Fault::new)
+            .unwrap_or_default()
+    }
+
+    /// Whether this occurrence of the operation fails.
+    pub fn inject(&self) -> bool {
+        if self.percent == 0 {
+            return false;
+        }
+        let n = self.occurrences.fetch_add(1, Ordering::Relaxed);
+        (n + 1) * self.percent / 100 > n * self.percent / 100
+    }
+}
+
+static DROP_REPLIES: LazyLock<Fault> = LazyLock::new(|| Fault::from_env(DROP_REPLIES_ENV));
+static FAIL_SENDER: LazyLock<Fault> = LazyLock::new(|| Fault::from_env(FAIL_SENDER_ENV));
+static PANIC_SENDER: LazyLock<Fault> = LazyLock::new(|| Fault::from_env(PANIC_SENDER_ENV));
+static GATEWAY_DELAY: LazyLock<Duration> = LazyLock::new(|| {
+    std::env::var(GATEWAY_DELAY_ENV)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or_default()
+});
+
+// The injection points are no-ops unless built with the `testing` feature
+
+/// Whether to drop a reply received by a receiver.
+pub fn drop_reply() -> bool {
+    cfg!(feature = "testing") && DROP_REPLIES.inject()
+}
+
+/// Whether to fail the creation of a caracat sender.
+pub fn fail_sender_creation() -> bool {
+    cfg!(feature = "testing") && FAIL_SENDER.inject()
+}
+
+/// Whether a SendLoop panics on a probes message.
+pub fn panic_sender() -> bool {
+    cfg!(feature = "testing") && PANIC_SENDER.inject()
+}
+
+/// Delay the processing of a response of the gateway.
+pub async fn delay_gateway_response() {
+    if cfg!(feature = "testing") && !GATEWAY_DELAY.is_zero() {
+        trace!("Delaying the gateway response by {:?}", *GATEWAY_DELAY);
+        tokio::time::sleep(*GATEWAY_DELAY).await;
+    }
+}
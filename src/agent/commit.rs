@@ -0,0 +1,177 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{unbounded_channel, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::time::sleep;
+
+const INITIAL_ENQUEUE_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ENQUEUE_BACKOFF: Duration = Duration::from_secs(1);
+
+/// When the offsets of the probes messages are committed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CommitStrategy {
+    /// Periodically committed by the Kafka client, whether the probes were sent or not.
+    Auto,
+    /// Committed once the probes are queued to the senders.
+    #[default]
+    AfterQueue,
+    /// Committed once the probes are sent, in offset order within each partition.
+    AfterSend,
+}
+
+impl CommitStrategy {
+    pub fn parse(strategy: &str) -> Result<Self> {
+        match strategy {
+            "auto" => Ok(CommitStrategy::Auto),
+            "" | "after-queue" => Ok(CommitStrategy::AfterQueue),
+            "after-send" => Ok(CommitStrategy::AfterSend),
+            other => anyhow::bail!(
+                "Invalid kafka.commit_strategy '{}'. Expected one of: auto, after-queue, after-send",
+                other
+            ),
+        }
+    }
+}
+
+/// Position of a message in a topic partition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageOffset {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+}
+
+/// Acknowledgment of the probes of a message, sent when dropped, that is once all the
+/// senders (and their workers) are done with the probes.
+#[derive(Debug)]
+pub struct MessageAck {
+    offset: MessageOffset,
+    tx: UnboundedSender<MessageOffset>,
+}
+
+impl Drop for MessageAck {
+    fn drop(&mut self) {
+        let _ = self.tx.send(self.offset.clone());
+    }
+}
+
+/// Messages awaiting acknowledgment, per partition.
+#[derive(Debug, Default)]
+pub struct OffsetTracker {
+    // Offsets of the messages, and whether they are acknowledged
+    pending: HashMap<(String, i32), BTreeMap<i64, bool>>,
+}
+
+impl OffsetTracker {
+    pub fn track(&mut self, message: &MessageOffset) {
+        self.pending
+            .entry((message.topic.clone(), message.partition))
+            .or_default()
+            .insert(message.offset, false);
+    }
+
+    /// Acknowledge a message. Returns the offset to commit (the next offset to consume)
+    /// if all the previous messages of the partition are acknowledged.
+    pub fn ack(&mut self, message: &MessageOffset) -> Option<i64> {
+        let offsets = self
+            .pending
+            .get_mut(&(message.topic.clone(), message.partition))?;
+        *offsets.get_mut(&message.offset)? = true;
+
+        let mut committable = None;
+        while let Some(entry) = offsets.first_entry() {
+            if !*entry.get() {
+                break;
+            }
+            committable = Some(*entry.key() + 1);
+            entry.remove();
+        }
+        committable
+    }
+}
+
+/// Decides which offsets to commit, according to the commit strategy.
+#[derive(Debug)]
+pub struct Committer {
+    strategy: CommitStrategy,
+    tracker: OffsetTracker,
+    ack_tx: UnboundedSender<MessageOffset>,
+}
+
+impl Committer {
+    /// Returns the committer, and the receiver of the acknowledgments of the sent probes.
+    pub fn new(strategy: CommitStrategy) -> (Self, UnboundedReceiver<MessageOffset>) {
+        let (ack_tx, ack_rx) = unbounded_channel();
+        (
+            Committer {
+                strategy,
+                tracker: OffsetTracker::default(),
+                ack_tx,
+            },
+            ack_rx,
+        )
+    }
+
+    /// Acknowledgment to attach to the probes of a message, with the `AfterSend` strategy.
+    pub fn probes_ack(&mut self, message: MessageOffset) -> Option<Arc<MessageAck>> {
+        if self.strategy != CommitStrategy::AfterSend {
+            return None;
+        }
+        self.tracker.track(&message);
+        Some(Arc::new(MessageAck {
+            offset: message,
+            tx: self.ack_tx.clone(),
+        }))
+    }
+
+    /// A message was processed by the handler without any probes to wait for.
+    /// Returns the offset to commit, if any.
+    pub fn processed(&mut self, message: MessageOffset) -> Option<MessageOffset> {
+        match self.strategy {
+            CommitStrategy::Auto => None,
+            CommitStrategy::AfterQueue => Some(MessageOffset {
+                offset: message.offset + 1,
+                ..message
+            }),
+            CommitStrategy::AfterSend => {
+                self.tracker.track(&message);
+                self.sent(message)
+            }
+        }
+    }
+
+    /// The probes of a message were sent. Returns the offset to commit, if any.
+    pub fn sent(&mut self, message: MessageOffset) -> Option<MessageOffset> {
+        self.tracker
+            .ack(&message)
+            .map(|offset| MessageOffset { offset, ..message })
+    }
+}
+
+/// Delay before the `attempt`-th retry (starting at 1) to queue probes to a full sender,
+/// doubling at each attempt.
+pub fn enqueue_backoff(attempt: u32) -> Duration {
+    INITIAL_ENQUEUE_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_ENQUEUE_BACKOFF)
+}
+
+/// Queue `item` to a sender, retrying with a backoff while its channel is full, so that the
+/// message is only committed once its probes are queued. Returns the number of retries, or
+/// the item if the sender has exited.
+pub async fn enqueue<T>(sender: &Sender<T>, mut item: T) -> Result<u32, T> {
+    let mut attempt = 0;
+    loop {
+        match sender.try_send(item) {
+            Ok(()) => return Ok(attempt),
+            Err(TrySendError::Full(rejected)) => {
+                attempt += 1;
+                sleep(enqueue_backoff(attempt)).await;
+                item = rejected;
+            }
+            Err(TrySendError::Closed(rejected)) => return Err(rejected),
+        }
+    }
+}
@@ -3,43 +3,110 @@ use rdkafka::consumer::stream_consumer::StreamConsumer;
 use rdkafka::consumer::{Consumer, DefaultConsumerContext};
 use tracing::info;
 
+use crate::agent::commit::CommitStrategy;
 use crate::auth::KafkaAuth;
 use crate::config::AppConfig;
 
-pub async fn init_consumer(config: &AppConfig, auth: KafkaAuth) -> StreamConsumer {
+fn create_consumer(
+    config: &AppConfig,
+    auth: KafkaAuth,
+    group_id: &str,
+    auto_commit: bool,
+) -> StreamConsumer {
     let context = DefaultConsumerContext;
     info!("Brokers: {}", config.kafka.brokers);
-    info!("Group ID: {}", config.kafka.in_group_id);
-    let consumer: StreamConsumer<DefaultConsumerContext> = match auth {
-        KafkaAuth::PlainText => ClientConfig::new()
-            .set("bootstrap.servers", config.kafka.brokers.clone())
-            .set("group.id", config.kafka.in_group_id.clone())
-            .set("enable.partition.eof", "false")
-            .set("session.timeout.ms", "6000")
-            .set("enable.auto.commit", "true")
-            .set_log_level(RDKafkaLogLevel::Debug)
-            .create_with_context(context.clone())
-            .expect("Consumer creation error"),
-        KafkaAuth::SasalPlainText(scram_auth) => ClientConfig::new()
-            .set("bootstrap.servers", config.kafka.brokers.clone())
-            .set("group.id", config.kafka.in_group_id.clone())
-            .set("enable.partition.eof", "false")
-            .set("session.timeout.ms", "6000")
-            .set("enable.auto.commit", "true")
+    info!("Group ID: {}", group_id);
+    info!("Auto offset reset: {}", config.kafka.auto_offset_reset());
+    info!("Auto commit: {}", auto_commit);
+
+    let mut client_config = ClientConfig::new();
+    client_config
+        .set("bootstrap.servers", config.kafka.brokers.clone())
+        .set("group.id", group_id)
+        .set("enable.partition.eof", "false")
+        .set(
+            "session.timeout.ms",
+            config.kafka.session_timeout_ms().to_string(),
+        )
+        .set("enable.auto.commit", auto_commit.to_string())
+        .set("auto.offset.reset", config.kafka.auto_offset_reset());
+    if let Some(fetch_min_bytes) = config.kafka.in_fetch_min_bytes {
+        client_config.set("fetch.min.bytes", fetch_min_bytes.to_string());
+    }
+    if let Some(max_poll_interval_ms) = config.kafka.in_max_poll_interval_ms {
+        client_config.set("max.poll.interval.ms", max_poll_interval_ms.to_string());
+    }
+    if let KafkaAuth::SasalPlainText(scram_auth) = auth {
+        client_config
             .set("sasl.username", scram_auth.username)
             .set("sasl.password", scram_auth.password)
             .set("sasl.mechanisms", scram_auth.mechanism)
-            .set("security.protocol", "SASL_PLAINTEXT")
-            .set_log_level(RDKafkaLogLevel::Debug)
-            .create_with_context(context)
-            .expect("Consumer creation error"),
-    };
+            .set("security.protocol", "SASL_PLAINTEXT");
+    }
+    config.kafka.apply_properties(&mut client_config);
+
+    client_config
+        .set_log_level(RDKafkaLogLevel::Debug)
+        .create_with_context(context)
+        .expect("Consumer creation error")
+}
+
+/// Consumer of the probes topics. A standby agent only subscribes once elected leader.
+pub async fn init_consumer(config: &AppConfig, auth: KafkaAuth, subscribe: bool) -> StreamConsumer {
+    // Offsets are committed by the handler, unless with the auto commit strategy
+    let auto_commit = CommitStrategy::parse(&config.kafka.commit_strategy)
+        .is_ok_and(|strategy| strategy == CommitStrategy::Auto);
+    let consumer = create_consumer(config, auth, &config.kafka.in_group_id, auto_commit);
+    if subscribe {
+        subscribe_probes(&consumer, &config.kafka.agent_in_topics(&config.agent.id));
+    }
+    consumer
+}
 
-    let topics: Vec<&str> = config.kafka.in_topics.split(',').collect();
+pub fn subscribe_probes(consumer: &StreamConsumer, topics: &[String]) {
+    let topics: Vec<&str> = topics.iter().map(|t| t.as_str()).collect();
     info!("Subscribing to topics: {:?}", topics);
     consumer
         .subscribe(&topics)
         .expect("Cannot subscribe to specified topics");
+}
+
+/// Consumer of the control topic. Every agent has its own consumer group,
+/// so that each of them receives all the commands.
+pub async fn init_control_consumer(
+    config: &AppConfig,
+    auth: KafkaAuth,
+    topic: &str,
+) -> StreamConsumer {
+    let group_id = format!("{}-control-{}", config.kafka.in_group_id, config.agent.id);
+    let consumer = create_consumer(config, auth, &group_id, true);
+
+    info!("Subscribing to control topic: {}", topic);
+    consumer
+        .subscribe(&[topic])
+        .expect("Cannot subscribe to control topic");
+
+    consumer
+}
+
+/// Consumer of the agents topic. Agents sharing an agent ID must all receive the
+/// records of each other, so every run of an agent has its own consumer group.
+pub async fn init_agents_consumer(
+    config: &AppConfig,
+    auth: KafkaAuth,
+    topic: &str,
+    run_id: &str,
+) -> StreamConsumer {
+    let group_id = format!(
+        "{}-agents-{}-{}",
+        config.kafka.in_group_id, config.agent.id, run_id
+    );
+    let consumer = create_consumer(config, auth, &group_id, false);
+
+    info!("Subscribing to agents topic: {}", topic);
+    consumer
+        .subscribe(&[topic])
+        .expect("Cannot subscribe to agents topic");
 
     consumer
 }
@@ -0,0 +1,136 @@
+use anyhow::Result;
+use rdkafka::consumer::StreamConsumer;
+use rdkafka::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::watch;
+use tracing::{error, info, trace, warn};
+
+use crate::agent::events::{EventKind, EventLog};
+
+/// Agent ID addressing every agent in a control message.
+pub const ALL_AGENTS: &str = "*";
+
+/// Commands published by operators on the control topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlCommand {
+    /// Stop consuming and sending probes.
+    Pause,
+    /// Consume and send probes again.
+    Resume,
+    /// Stop consuming probes, but send those already queued.
+    Drain,
+}
+
+/// Operating mode of the agent, driven by the control commands.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentMode {
+    #[default]
+    Running,
+    Paused,
+    Draining,
+}
+
+impl AgentMode {
+    pub fn apply(self, command: ControlCommand) -> AgentMode {
+        match command {
+            ControlCommand::Pause => AgentMode::Paused,
+            ControlCommand::Resume => AgentMode::Running,
+            ControlCommand::Drain => AgentMode::Draining,
+        }
+    }
+
+    /// Whether new probes are consumed from Kafka.
+    pub fn consumes_probes(self) -> bool {
+        self == AgentMode::Running
+    }
+
+    /// Whether the queued probes are sent.
+    pub fn sends_probes(self) -> bool {
+        self != AgentMode::Paused
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ControlMessage {
+    agent_id: String,
+    command: ControlCommand,
+}
+
+/// Parse a control message, e.g. `{"agent_id": "agent1", "command": "pause"}`.
+/// Returns `None` if the message is intended for another agent.
+pub fn parse_control_message(payload: &[u8], agent_id: &str) -> Result<Option<ControlCommand>> {
+    let message: ControlMessage = serde_json::from_slice(payload)?;
+    if message.agent_id == agent_id || message.agent_id == ALL_AGENTS {
+        Ok(Some(message.command))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Apply the commands received on the control topic to the agent mode.
+pub async fn control_loop(
+    consumer: StreamConsumer,
+    agent_id: String,
+    mode: Arc<watch::Sender<AgentMode>>,
+    events: EventLog,
+) {
+    loop {
+        let message = match consumer.recv().await {
+            Ok(m) => m,
+            Err(e) => {
+                error!("Kafka control consumer error: {}. Retrying in 5s...", e);
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+
+        let command = match message
+            .payload()
+            .map(|p| parse_control_message(p, &agent_id))
+        {
+            Some(Ok(Some(command))) => command,
+            Some(Ok(None)) => {
+                trace!("Control message not intended for this agent. Ignored.");
+                continue;
+            }
+            Some(Err(e)) => {
+                warn!("Invalid control message: {}. Ignored.", e);
+                continue;
+            }
+            None => {
+                warn!("Received control message with empty payload. Ignored.");
+                continue;
+            }
+        };
+
+        apply_command(&mode, command, &events);
+    }
+}
+
+/// Apply a command, from the control topic or the gateway, to the agent mode.
+pub fn apply_command(mode: &watch::Sender<AgentMode>, command: ControlCommand, events: &EventLog) {
+    let previous = *mode.borrow();
+    let next = previous.apply(command);
+    if next != previous {
+        info!(
+            "Control command {:?}: agent mode {:?} -> {:?}",
+            command, previous, next
+        );
+        mode.send_replace(next);
+        events.emit(
+            EventKind::ModeChanged,
+            None,
+            BTreeMap::from([
+                (
+                    "command".to_string(),
+                    format!("{:?}", command).to_lowercase(),
+                ),
+                ("mode".to_string(), format!("{:?}", next).to_lowercase()),
+            ]),
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use caracat::models::{Probe, Reply};
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::probe::ProbeContext;
+
+// Maximum number of probes remembered for reply correlation
+pub const DEFAULT_CORRELATION_CAPACITY: usize = 1_000_000;
+
+pub type SharedCorrelationTable = Arc<Mutex<CorrelationTable>>;
+
+/// Fields identifying a probe, found both in the sent probe and in the quoted packet of its replies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProbeKey {
+    pub dst_addr: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub ttl: u8,
+}
+
+pub(crate) fn normalize_ip_addr(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(ipv6) => ipv6
+            .to_ipv4_mapped()
+            .map(IpAddr::V4)
+            .unwrap_or(IpAddr::V6(ipv6)),
+        IpAddr::V4(_) => addr,
+    }
+}
+
+impl ProbeKey {
+    pub fn from_probe(probe: &Probe) -> Self {
+        ProbeKey {
+            dst_addr: normalize_ip_addr(probe.dst_addr),
+            src_port: probe.src_port,
+            dst_port: probe.dst_port,
+            ttl: probe.ttl,
+        }
+    }
+
+    pub fn from_reply(reply: &Reply) -> Self {
+        ProbeKey {
+            dst_addr: normalize_ip_addr(reply.probe_dst_addr),
+            src_port: reply.probe_src_port,
+            dst_port: reply.probe_dst_port,
+            ttl: reply.probe_ttl,
+        }
+    }
+}
+
+/// Bounded map from sent probes to their context, used to attribute the replies they elicit.
+/// The oldest entries are evicted first once the capacity is reached.
+#[derive(Debug)]
+pub struct CorrelationTable {
+    capacity: usize,
+    entries: HashMap<ProbeKey, ProbeContext>,
+    order: VecDeque<ProbeKey>,
+}
+
+impl CorrelationTable {
+    pub fn new(capacity: usize) -> Self {
+        CorrelationTable {
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn shared(capacity: usize) -> SharedCorrelationTable {
+        Arc::new(Mutex::new(CorrelationTable::new(capacity)))
+    }
+
+    pub fn insert(&mut self, key: ProbeKey, context: ProbeContext) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.insert(key, context).is_none() {
+            self.order.push_back(key);
+        }
+        while self.entries.len() > self.capacity {
+            match self.order.pop_front() {
+                Some(oldest) => {
+                    self.entries.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+
+    pub fn get(&self, key: &ProbeKey) -> Option<&ProbeContext> {
+        self.entries.get(key)
+    }
+}
@@ -0,0 +1,83 @@
+use caracat::models::Probe;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+use crate::agent::correlation::normalize_ip_addr;
+use crate::probe::ProbeTags;
+
+fn probe_hash(measurement_id: &str, probe: &Probe, round: u32) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    measurement_id.hash(&mut hasher);
+    normalize_ip_addr(probe.dst_addr).hash(&mut hasher);
+    probe.ttl.hash(&mut hasher);
+    probe.src_port.hash(&mut hasher);
+    probe.dst_port.hash(&mut hasher);
+    u8::from(probe.protocol).hash(&mut hasher);
+    round.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Debug, Default)]
+struct Seen {
+    hashes: HashSet<u64>,
+    order: VecDeque<u64>,
+}
+
+/// Probes recently sent by a caracat instance, to drop the probes repeated inside a
+/// measurement, such as those submitted several times by a buggy client.
+///
+/// The probes are remembered by a hash of their measurement, destination, TTL, ports,
+/// protocol and round, up to `window` probes: the oldest ones are forgotten first.
+#[derive(Debug, Clone)]
+pub struct ProbeDedup {
+    window: usize,
+    seen: Arc<Mutex<Seen>>,
+}
+
+impl ProbeDedup {
+    pub fn new(window: usize) -> Self {
+        ProbeDedup {
+            window,
+            seen: Arc::new(Mutex::new(Seen::default())),
+        }
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Drop the probes already seen in the measurement (or repeated in `probes`), and
+    /// remember the others. Returns the number of probes dropped.
+    pub fn dedup(&self, measurement_id: &str, probes: &mut Vec<(Probe, ProbeTags)>) -> usize {
+        if self.window == 0 {
+            return 0;
+        }
+        let before = probes.len();
+        let mut seen = self.seen.lock().unwrap();
+        probes.retain(|(probe, tags)| {
+            let hash = probe_hash(measurement_id, probe, tags.round);
+            if !seen.hashes.insert(hash) {
+                return false;
+            }
+            seen.order.push_back(hash);
+            while seen.order.len() > self.window {
+                if let Some(oldest) = seen.order.pop_front() {
+                    seen.hashes.remove(&oldest);
+                }
+            }
+            true
+        });
+        before - probes.len()
+    }
+
+    /// Number of probes remembered.
+    pub fn len(&self) -> usize {
+        self.seen.lock().unwrap().order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,86 @@
+use metrics::gauge;
+use rdkafka::consumer::StreamConsumer;
+use rdkafka::Message;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tracing::{error, trace, warn};
+
+use crate::agent::capabilities::AgentCapabilities;
+use crate::agent::control::AgentMode;
+use crate::agent::events::{EventKind, EventLog};
+use crate::agent::server::AgentState;
+
+/// Whether a record of the agents topic was published, since the start of this agent,
+/// by another agent running with the same agent ID.
+pub fn is_duplicate(
+    record: &AgentCapabilities,
+    agent_id: &str,
+    run_id: &str,
+    started_at_ns: u64,
+) -> bool {
+    record.agent_id == agent_id
+        && !record.run_id.is_empty()
+        && record.run_id != run_id
+        // Records of previous runs of this agent are older
+        && record.published_at_ns >= started_at_ns
+}
+
+/// Watch the agents topic for other agents running with the same agent ID. Their
+/// measurements are silently mixed up with ours, so the agent is marked as degraded,
+/// and paused if `pause` is given (`agent.refuse_duplicate_id`).
+pub async fn duplicate_loop(
+    consumer: StreamConsumer,
+    agent_id: String,
+    run_id: String,
+    started_at_ns: u64,
+    state: Arc<AgentState>,
+    events: EventLog,
+    pause: Option<Arc<watch::Sender<AgentMode>>>,
+) {
+    // Runs of the duplicate agents already reported
+    let mut duplicates: HashSet<String> = HashSet::new();
+    loop {
+        let message = match consumer.recv().await {
+            Ok(m) => m,
+            Err(e) => {
+                error!("Kafka agents consumer error: {}. Retrying in 5s...", e);
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+
+        let Some(payload) = message.payload() else {
+            continue;
+        };
+        let record: AgentCapabilities = match serde_json::from_slice(payload) {
+            Ok(record) => record,
+            Err(e) => {
+                trace!("Invalid agent capabilities: {}. Ignored.", e);
+                continue;
+            }
+        };
+        if !is_duplicate(&record, &agent_id, &run_id, started_at_ns)
+            || !duplicates.insert(record.run_id.clone())
+        {
+            continue;
+        }
+
+        error!(
+            "Another agent is running with agent ID {} (run {}, saimiris {}). Their measurements are mixed up with ours: every agent must have a unique agent.id",
+            agent_id, record.run_id, record.version
+        );
+        state.set_degraded(true);
+        gauge!("saimiris_agent_duplicate_id", "agent" => agent_id.clone()).set(1.0);
+        events.emit(
+            EventKind::DuplicateAgentId,
+            None,
+            BTreeMap::from([("run_id".to_string(), record.run_id)]),
+        );
+        if let Some(mode) = &pause {
+            if mode.send_replace(AgentMode::Paused) != AgentMode::Paused {
+                warn!("Pausing the agent until resumed (agent.refuse_duplicate_id)");
+            }
+        }
+    }
+}
@@ -0,0 +1,518 @@
+use caracat::models::{Probe, L4};
+use ipnet::IpNet;
+use metrics::{counter, gauge};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+use crate::agent::correlation::normalize_ip_addr;
+use crate::agent::failover::instance_label;
+use crate::config::CaracatConfig;
+
+// Captured bytes of each packet, enough for the headers and payload of the probes
+const CAPTURE_SNAPLEN: i32 = 512;
+// Read timeout of the capture (ms), so that sampled probes expire while no packet is sent
+const CAPTURE_TIMEOUT_MS: i32 = 500;
+// Sampled probes awaiting their capture, beyond which new samples are skipped
+const MAX_PENDING_SAMPLES: usize = 1024;
+
+const LINKTYPE_NULL: i32 = 0;
+const LINKTYPE_ETHERNET: i32 = 1;
+const LINKTYPE_DLT_RAW: i32 = 12;
+const LINKTYPE_RAW: i32 = 101;
+const LINKTYPE_LINUX_SLL: i32 = 113;
+const LINKTYPE_LINUX_SLL2: i32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: [u16; 2] = [0x8100, 0x88a8];
+
+const PROTOCOL_ICMP: u8 = 1;
+const PROTOCOL_UDP: u8 = 17;
+const PROTOCOL_ICMPV6: u8 = 58;
+
+/// Probe sent by the agent, as it is expected on the wire.
+#[derive(Debug, Clone)]
+pub struct ExpectedProbe {
+    pub probe: Probe,
+    // Source address of the sender, or any address of the instance prefixes if unset
+    pub src_addr: Option<IpAddr>,
+}
+
+/// How a captured probe differs from the probe sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmissionMismatch {
+    Malformed,
+    Truncated,
+    Ttl { expected: u8, actual: u8 },
+    SourceAddress { actual: IpAddr },
+    Ports,
+    IpChecksum,
+    L4Checksum,
+}
+
+impl EmissionMismatch {
+    /// Label of the mismatch in the metrics.
+    pub fn label(&self) -> &'static str {
+        match self {
+            EmissionMismatch::Malformed => "malformed",
+            EmissionMismatch::Truncated => "truncated",
+            EmissionMismatch::Ttl { .. } => "ttl",
+            EmissionMismatch::SourceAddress { .. } => "source_address",
+            EmissionMismatch::Ports => "ports",
+            EmissionMismatch::IpChecksum => "ip_checksum",
+            EmissionMismatch::L4Checksum => "l4_checksum",
+        }
+    }
+}
+
+impl std::fmt::Display for EmissionMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmissionMismatch::Malformed => write!(f, "malformed packet"),
+            EmissionMismatch::Truncated => write!(f, "truncated capture"),
+            EmissionMismatch::Ttl { expected, actual } => {
+                write!(f, "TTL {} instead of {}", actual, expected)
+            }
+            EmissionMismatch::SourceAddress { actual } => {
+                write!(f, "unexpected source address {}", actual)
+            }
+            EmissionMismatch::Ports => write!(f, "unexpected ports"),
+            EmissionMismatch::IpChecksum => write!(f, "invalid IP checksum"),
+            EmissionMismatch::L4Checksum => write!(f, "invalid L4 checksum"),
+        }
+    }
+}
+
+fn be16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([
+        *bytes.get(offset)?,
+        *bytes.get(offset + 1)?,
+    ]))
+}
+
+/// IP packet of a captured frame, given the link type of the capture.
+pub fn ip_packet(linktype: i32, frame: &[u8]) -> Option<&[u8]> {
+    let (offset, ethertype) = match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = be16(frame, offset)?;
+            while ETHERTYPE_VLAN.contains(&ethertype) {
+                offset += 4;
+                ethertype = be16(frame, offset)?;
+            }
+            (offset + 2, Some(ethertype))
+        }
+        LINKTYPE_LINUX_SLL => (16, Some(be16(frame, 14)?)),
+        LINKTYPE_LINUX_SLL2 => (20, Some(be16(frame, 0)?)),
+        LINKTYPE_NULL => (4, None),
+        LINKTYPE_RAW | LINKTYPE_DLT_RAW => (0, None),
+        _ => return None,
+    };
+    if ethertype.is_some_and(|ethertype| ethertype != ETHERTYPE_IPV4 && ethertype != ETHERTYPE_IPV6)
+    {
+        return None;
+    }
+    frame.get(offset..)
+}
+
+/// Fields of an IP packet checked against the probe sent.
+struct IpPacket<'a> {
+    src_addr: IpAddr,
+    dst_addr: IpAddr,
+    ttl: u8,
+    protocol: u8,
+    // IPv4 header checksum (always valid for IPv6, which has none)
+    header_checksum_valid: bool,
+    truncated: bool,
+    // Pseudo-header of the L4 checksum, and L4 header and payload
+    pseudo_header: Vec<u8>,
+    l4: &'a [u8],
+}
+
+fn parse_ip_packet(packet: &[u8]) -> Option<IpPacket<'_>> {
+    match packet.first()? >> 4 {
+        4 => {
+            let header_len = (*packet.first()? as usize & 0x0f) * 4;
+            let total_len = be16(packet, 2)? as usize;
+            if header_len < 20 || packet.len() < header_len || total_len < header_len {
+                return None;
+            }
+            let src: [u8; 4] = packet[12..16].try_into().ok()?;
+            let dst: [u8; 4] = packet[16..20].try_into().ok()?;
+            let protocol = packet[9];
+            let l4 = &packet[header_len..total_len.min(packet.len())];
+            let mut pseudo_header = Vec::with_capacity(12);
+            pseudo_header.extend_from_slice(&src);
+            pseudo_header.extend_from_slice(&dst);
+            pseudo_header.extend_from_slice(&[0, protocol]);
+            pseudo_header.extend_from_slice(&((total_len - header_len) as u16).to_be_bytes());
+            Some(IpPacket {
+                src_addr: IpAddr::V4(Ipv4Addr::from(src)),
+                dst_addr: IpAddr::V4(Ipv4Addr::from(dst)),
+                ttl: packet[8],
+                protocol,
+                header_checksum_valid: is_valid_checksum(&[&packet[..header_len]]),
+                truncated: packet.len() < total_len,
+                pseudo_header,
+                l4,
+            })
+        }
+        6 => {
+            if packet.len() < 40 {
+                return None;
+            }
+            let payload_len = be16(packet, 4)? as usize;
+            let src: [u8; 16] = packet[8..24].try_into().ok()?;
+            let dst: [u8; 16] = packet[24..40].try_into().ok()?;
+            // Probes carry no extension headers
+            let protocol = packet[6];
+            let mut pseudo_header = Vec::with_capacity(40);
+            pseudo_header.extend_from_slice(&src);
+            pseudo_header.extend_from_slice(&dst);
+            pseudo_header.extend_from_slice(&(payload_len as u32).to_be_bytes());
+            pseudo_header.extend_from_slice(&[0, 0, 0, protocol]);
+            Some(IpPacket {
+                src_addr: IpAddr::V6(Ipv6Addr::from(src)),
+                dst_addr: IpAddr::V6(Ipv6Addr::from(dst)),
+                ttl: packet[7],
+                protocol,
+                header_checksum_valid: true,
+                truncated: packet.len() < 40 + payload_len,
+                pseudo_header,
+                l4: &packet[40..(40 + payload_len).min(packet.len())],
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Whether the one's complement sum of the 16-bit words of `parts` is zero (0xffff),
+/// i.e. the checksum they contain is valid.
+fn is_valid_checksum(parts: &[&[u8]]) -> bool {
+    let mut sum: u32 = 0;
+    for part in parts {
+        for word in part.chunks(2) {
+            sum += u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u32;
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum == 0xffff
+}
+
+fn protocol_number(protocol: L4) -> u8 {
+    match protocol {
+        L4::ICMP => PROTOCOL_ICMP,
+        L4::UDP => PROTOCOL_UDP,
+        L4::ICMPv6 => PROTOCOL_ICMPV6,
+    }
+}
+
+/// Verify that a captured IP packet is the expected probe: its TTL, source address, ports
+/// and checksums. Source addresses outside `prefixes` are rejected when the probe has none.
+pub fn verify_packet(
+    expected: &ExpectedProbe,
+    prefixes: &[IpNet],
+    packet: &[u8],
+) -> Result<(), EmissionMismatch> {
+    let packet = parse_ip_packet(packet).ok_or(EmissionMismatch::Malformed)?;
+    if packet.truncated {
+        return Err(EmissionMismatch::Truncated);
+    }
+    if packet.ttl != expected.probe.ttl {
+        return Err(EmissionMismatch::Ttl {
+            expected: expected.probe.ttl,
+            actual: packet.ttl,
+        });
+    }
+    let src_addr = normalize_ip_addr(packet.src_addr);
+    let valid_source = match expected.src_addr {
+        Some(expected) => normalize_ip_addr(expected) == src_addr,
+        None => prefixes.is_empty() || prefixes.iter().any(|p| p.contains(&src_addr)),
+    };
+    if !valid_source {
+        return Err(EmissionMismatch::SourceAddress { actual: src_addr });
+    }
+    if packet.protocol == PROTOCOL_UDP
+        && (be16(packet.l4, 0) != Some(expected.probe.src_port)
+            || be16(packet.l4, 2) != Some(expected.probe.dst_port))
+    {
+        return Err(EmissionMismatch::Ports);
+    }
+    if !packet.header_checksum_valid {
+        return Err(EmissionMismatch::IpChecksum);
+    }
+    let l4_checksum_valid = match packet.protocol {
+        PROTOCOL_ICMP => is_valid_checksum(&[packet.l4]),
+        // The UDP checksum is optional over IPv4
+        PROTOCOL_UDP if packet.src_addr.is_ipv4() && be16(packet.l4, 6) == Some(0) => true,
+        _ => is_valid_checksum(&[&packet.pseudo_header, packet.l4]),
+    };
+    if !l4_checksum_valid {
+        return Err(EmissionMismatch::L4Checksum);
+    }
+    Ok(())
+}
+
+/// Outcome of the last sampled probe of a caracat instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmissionStatus {
+    /// No sampled probe was checked yet
+    Unverified,
+    /// The sampled probe was captured as sent
+    Verified,
+    /// The sampled probe was captured, but differs from the probe sent
+    Mismatch,
+    /// The sampled probe was not captured in time
+    Missing,
+}
+
+/// Emission status of each caracat instance checking its probes, reported in the gateway health.
+#[derive(Debug, Clone, Default)]
+pub struct EmissionChecks {
+    statuses: Arc<Mutex<BTreeMap<String, EmissionStatus>>>,
+}
+
+impl EmissionChecks {
+    pub fn set(&self, instance: &str, status: EmissionStatus) {
+        self.statuses
+            .lock()
+            .unwrap()
+            .insert(instance.to_string(), status);
+    }
+
+    pub fn statuses(&self) -> BTreeMap<String, EmissionStatus> {
+        self.statuses.lock().unwrap().clone()
+    }
+
+    /// Instances whose last sampled probe was not emitted as sent.
+    pub fn failing(&self) -> Vec<String> {
+        self.statuses
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, status)| {
+                matches!(status, EmissionStatus::Mismatch | EmissionStatus::Missing)
+            })
+            .map(|(instance, _)| instance.clone())
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct SampledProbe {
+    expected: ExpectedProbe,
+    deadline: Instant,
+    // Last mismatch of the captured packets of the same flow, as another probe
+    // of the flow (e.g. with another TTL) can be captured in between
+    mismatch: Option<EmissionMismatch>,
+}
+
+/// Samples the probes sent by a caracat instance, and verifies them once captured
+/// on its interface.
+#[derive(Debug, Clone)]
+pub struct EmissionChecker {
+    agent_id: String,
+    instance: String,
+    sample_every: u64,
+    timeout: Duration,
+    prefixes: Vec<IpNet>,
+    sent: Arc<AtomicU64>,
+    pending: Arc<Mutex<HashMap<(IpAddr, u8), Vec<SampledProbe>>>>,
+    checks: EmissionChecks,
+}
+
+impl EmissionChecker {
+    /// Checker of a caracat instance, unless `emission_check_sample_every` is 0
+    /// or the instance does not send probes (dry run).
+    pub fn new(agent_id: &str, config: &CaracatConfig, checks: EmissionChecks) -> Option<Self> {
+        if config.emission_check_sample_every == 0 || config.dry_run {
+            return None;
+        }
+        let prefixes = [&config.src_ipv4_prefix, &config.src_ipv6_prefix]
+            .into_iter()
+            .flatten()
+            .filter_map(|prefix| prefix.parse().ok())
+            .collect();
+        let instance = instance_label(config);
+        checks.set(&instance, EmissionStatus::Unverified);
+        Some(EmissionChecker {
+            agent_id: agent_id.to_string(),
+            instance,
+            sample_every: config.emission_check_sample_every,
+            timeout: Duration::from_secs(config.emission_check_timeout),
+            prefixes,
+            sent: Arc::new(AtomicU64::new(0)),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            checks,
+        })
+    }
+
+    /// Count a sent probe, sampling one in `sample_every`.
+    pub fn sample(&self, probe: &Probe, src_addr: Option<IpAddr>) {
+        if self.sent.fetch_add(1, Ordering::Relaxed) % self.sample_every != 0 {
+            return;
+        }
+        let mut pending = self.pending.lock().unwrap();
+        if pending.values().map(Vec::len).sum::<usize>() >= MAX_PENDING_SAMPLES {
+            return;
+        }
+        pending
+            .entry((
+                normalize_ip_addr(probe.dst_addr),
+                protocol_number(probe.protocol),
+            ))
+            .or_default()
+            .push(SampledProbe {
+                expected: ExpectedProbe {
+                    probe: probe.clone(),
+                    src_addr,
+                },
+                deadline: Instant::now() + self.timeout,
+                mismatch: None,
+            });
+    }
+
+    /// Verify a captured IP packet against the sampled probes of its flow.
+    /// Returns the outcome if the packet matched a sampled probe.
+    pub fn observe(&self, packet: &[u8]) -> Option<EmissionStatus> {
+        let (dst_addr, protocol) = parse_ip_packet(packet)
+            .map(|packet| (normalize_ip_addr(packet.dst_addr), packet.protocol))?;
+        let mut pending = self.pending.lock().unwrap();
+        let sampled = pending.get_mut(&(dst_addr, protocol))?;
+        let mut verified = None;
+        for (i, probe) in sampled.iter_mut().enumerate() {
+            match verify_packet(&probe.expected, &self.prefixes, packet) {
+                Ok(()) => {
+                    verified = Some(i);
+                    break;
+                }
+                Err(mismatch) => probe.mismatch = Some(mismatch),
+            }
+        }
+        let i = verified?;
+        sampled.remove(i);
+        if sampled.is_empty() {
+            pending.remove(&(dst_addr, protocol));
+        }
+        drop(pending);
+        self.record(Ok(()));
+        Some(EmissionStatus::Verified)
+    }
+
+    /// Expire the sampled probes not captured as sent before their deadline.
+    /// Returns the number of expired probes.
+    pub fn expire(&self, now: Instant) -> usize {
+        let mut expired = Vec::new();
+        self.pending.lock().unwrap().retain(|_, sampled| {
+            sampled.retain_mut(|probe| {
+                if probe.deadline > now {
+                    return true;
+                }
+                expired.push(probe.mismatch.take());
+                false
+            });
+            !sampled.is_empty()
+        });
+        for mismatch in &expired {
+            self.record(Err(mismatch.clone()));
+        }
+        expired.len()
+    }
+
+    /// Record the outcome of a sampled probe: verified, differing (mismatch) or missing (none).
+    fn record(&self, result: Result<(), Option<EmissionMismatch>>) {
+        let (status, label) = match &result {
+            Ok(()) => (EmissionStatus::Verified, "verified"),
+            Err(Some(mismatch)) => (EmissionStatus::Mismatch, mismatch.label()),
+            Err(None) => (EmissionStatus::Missing, "missing"),
+        };
+        match &result {
+            Ok(()) => debug!("Sampled probe of instance {} verified", self.instance),
+            Err(Some(mismatch)) => warn!(
+                "Sampled probe of instance {} not emitted as sent: {}",
+                self.instance, mismatch
+            ),
+            Err(None) => warn!(
+                "Sampled probe of instance {} not captured within {:?}",
+                self.instance, self.timeout
+            ),
+        }
+        counter!(
+            "saimiris_sender_emission_checks_total",
+            "agent" => self.agent_id.clone(),
+            "instance" => self.instance.clone(),
+            "result" => label
+        )
+        .increment(1);
+        gauge!(
+            "saimiris_sender_emission_verified",
+            "agent" => self.agent_id.clone(),
+            "instance" => self.instance.clone()
+        )
+        .set(if status == EmissionStatus::Verified {
+            1.0
+        } else {
+            0.0
+        });
+        self.checks.set(&self.instance, status);
+    }
+
+    /// Capture the probes sent on `interface` in a thread of its own, verifying the sampled ones.
+    pub fn spawn_capture(self, interface: String) {
+        thread::spawn(move || {
+            let capture = pcap::Capture::from_device(interface.as_str())
+                .and_then(|capture| {
+                    capture
+                        .snaplen(CAPTURE_SNAPLEN)
+                        .timeout(CAPTURE_TIMEOUT_MS)
+                        .immediate_mode(true)
+                        .open()
+                })
+                .and_then(|mut capture| {
+                    capture.direction(pcap::Direction::Out)?;
+                    capture.filter("icmp or icmp6 or udp", true)?;
+                    Ok(capture)
+                });
+            let mut capture = match capture {
+                Ok(capture) => capture,
+                Err(e) => {
+                    error!(
+                        "Failed to capture the probes sent on interface {}: {}. Emission check disabled for instance {}",
+                        interface, e, self.instance
+                    );
+                    return;
+                }
+            };
+            let linktype = capture.get_datalink().0;
+            info!(
+                "Checking one in {} probes sent by instance {} on interface {}",
+                self.sample_every, self.instance, interface
+            );
+
+            loop {
+                match capture.next_packet() {
+                    Ok(frame) => {
+                        if let Some(packet) = ip_packet(linktype, frame.data) {
+                            self.observe(packet);
+                        }
+                    }
+                    Err(pcap::Error::TimeoutExpired) => {}
+                    Err(e) => {
+                        error!("Capture on interface {} failed: {}", interface, e);
+                        return;
+                    }
+                }
+                self.expire(Instant::now());
+            }
+        });
+    }
+}
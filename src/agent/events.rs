@@ -0,0 +1,121 @@
+use metrics::counter;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::Sender;
+use tracing::trace;
+
+/// Notable events in the life of an agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Startup,
+    MeasurementAccepted,
+    MeasurementCompleted,
+    MeasurementCancelled,
+    LoopRestart,
+    ThrottlingEngaged,
+    ModeChanged,
+    DuplicateAgentId,
+    RoleChanged,
+    MeasurementLimitExceeded,
+}
+
+/// Structured record of an agent event, published as JSON to the events topic.
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub time_ns: u64,
+    pub agent_id: String,
+    pub kind: EventKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub measurement_id: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub details: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+struct RateLimit {
+    max_per_second: u64,
+    window_start: Instant,
+    count: u64,
+}
+
+impl RateLimit {
+    fn allow(&mut self) -> bool {
+        if self.window_start.elapsed() >= Duration::from_secs(1) {
+            self.window_start = Instant::now();
+            self.count = 0;
+        }
+        if self.count >= self.max_per_second {
+            return false;
+        }
+        self.count += 1;
+        true
+    }
+}
+
+/// Rate-limited emitter of agent events. Never blocks: events are dropped when
+/// the rate limit is exceeded or the publishing queue is full.
+#[derive(Debug, Clone)]
+pub struct EventLog {
+    agent_id: String,
+    tx: Option<Sender<Event>>,
+    rate_limit: Arc<Mutex<RateLimit>>,
+}
+
+impl EventLog {
+    pub fn new(agent_id: String, tx: Option<Sender<Event>>, max_per_second: u64) -> Self {
+        EventLog {
+            agent_id,
+            tx,
+            rate_limit: Arc::new(Mutex::new(RateLimit {
+                max_per_second,
+                window_start: Instant::now(),
+                count: 0,
+            })),
+        }
+    }
+
+    /// An event log which publishes nothing.
+    pub fn disabled() -> Self {
+        EventLog::new(String::new(), None, 0)
+    }
+
+    pub fn emit(
+        &self,
+        kind: EventKind,
+        measurement_id: Option<&str>,
+        details: BTreeMap<String, String>,
+    ) {
+        let tx = match &self.tx {
+            Some(tx) => tx,
+            None => return,
+        };
+
+        if !self.rate_limit.lock().unwrap().allow() {
+            counter!("saimiris_events_dropped_total", "agent" => self.agent_id.clone())
+                .increment(1);
+            return;
+        }
+
+        let event = Event {
+            time_ns: chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64,
+            agent_id: self.agent_id.clone(),
+            kind,
+            measurement_id: measurement_id.map(|id| id.to_string()),
+            details,
+        };
+        trace!("{:?}", event);
+        if tx.try_send(event).is_err() {
+            counter!("saimiris_events_dropped_total", "agent" => self.agent_id.clone())
+                .increment(1);
+        }
+    }
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        EventLog::disabled()
+    }
+}
@@ -0,0 +1,298 @@
+use metrics::Label;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Content type of the metrics rendered with their exemplars.
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+/// W3C trace context header, from which the trace ID of the exemplars is taken.
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+// Exemplars kept per series, so that the buckets of a histogram get their own
+const MAX_EXEMPLARS_PER_SERIES: usize = 16;
+// OpenMetrics limit on the length of the exemplar labels
+const MAX_EXEMPLAR_LABELS_LENGTH: usize = 128;
+
+/// Observation of a metric, linking it to the measurement it belongs to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exemplar {
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+    // Seconds since the Unix epoch
+    pub timestamp: f64,
+}
+
+impl Exemplar {
+    pub fn new(measurement_id: &str, trace_id: Option<&str>, value: f64) -> Self {
+        let mut labels = vec![("measurement_id".to_string(), measurement_id.to_string())];
+        if let Some(trace_id) = trace_id {
+            labels.push(("trace_id".to_string(), trace_id.to_string()));
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs_f64())
+            .unwrap_or_default();
+        Exemplar {
+            labels,
+            value,
+            timestamp,
+        }
+    }
+
+    /// ` # {labels} value timestamp`, or `None` if the labels are too long to be exposed.
+    fn render(&self) -> Option<String> {
+        let length: usize = self
+            .labels
+            .iter()
+            .map(|(key, value)| key.chars().count() + value.chars().count())
+            .sum();
+        if length > MAX_EXEMPLAR_LABELS_LENGTH {
+            return None;
+        }
+        let labels: Vec<String> = self
+            .labels
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, escape_label_value(value)))
+            .collect();
+        Some(format!(
+            " # {{{}}} {} {:.3}",
+            labels.join(","),
+            self.value,
+            self.timestamp
+        ))
+    }
+}
+
+/// Trace ID of a W3C `traceparent` header (`version-trace_id-parent_id-flags`).
+pub fn trace_id_from_traceparent(traceparent: &str) -> Option<String> {
+    let parts: Vec<&str> = traceparent.trim().split('-').collect();
+    let [version, trace_id, parent_id, flags] = parts[..] else {
+        return None;
+    };
+    let is_hex = |value: &str, len: usize| {
+        value.len() == len && value.chars().all(|c| c.is_ascii_hexdigit())
+    };
+    if !is_hex(version, 2)
+        || !is_hex(trace_id, 32)
+        || !is_hex(parent_id, 16)
+        || !is_hex(flags, 2)
+        || trace_id.chars().all(|c| c == '0')
+    {
+        return None;
+    }
+    Some(trace_id.to_lowercase())
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn sorted_labels(labels: impl IntoIterator<Item = (String, String)>) -> Vec<(String, String)> {
+    let mut labels: Vec<_> = labels.into_iter().collect();
+    labels.sort();
+    labels
+}
+
+/// Name and labels of a sample line, e.g. `name{key="value"} 1`.
+fn parse_sample(line: &str) -> Option<(&str, Vec<(String, String)>)> {
+    let Some(start) = line.find('{') else {
+        return line.split_once(' ').map(|(name, _)| (name, Vec::new()));
+    };
+    let name = &line[..start];
+    let mut labels = Vec::new();
+    let mut chars = line[start + 1..].chars();
+    loop {
+        let mut key = String::new();
+        for c in chars.by_ref() {
+            match c {
+                '=' => break,
+                '}' if key.is_empty() => return Some((name, labels)),
+                ',' | ' ' => {}
+                c => key.push(c),
+            }
+        }
+        if chars.next() != Some('"') {
+            return None;
+        }
+        let mut value = String::new();
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => match chars.next()? {
+                    'n' => value.push('\n'),
+                    c => value.push(c),
+                },
+                c => value.push(c),
+            }
+        }
+        labels.push((key, value));
+        match chars.next()? {
+            ',' => continue,
+            '}' => return Some((name, labels)),
+            _ => return None,
+        }
+    }
+}
+
+type SeriesKey = (String, Vec<(String, String)>);
+// Series of a histogram bucket, and its upper bound
+type Bucket = (String, Vec<(String, String)>, f64);
+
+/// Latest exemplars of each series, exposed with the metrics in the OpenMetrics format.
+///
+/// The `metrics` facade has no notion of exemplars, so they are recorded alongside the
+/// metrics, and attached to the rendering of the Prometheus exporter.
+#[derive(Debug, Default)]
+pub struct Exemplars {
+    series: Mutex<HashMap<SeriesKey, VecDeque<Exemplar>>>,
+}
+
+impl Exemplars {
+    /// Record an exemplar of a counter or histogram series.
+    pub fn record(&self, name: &str, labels: &[Label], exemplar: Exemplar) {
+        let labels = sorted_labels(
+            labels
+                .iter()
+                .map(|label| (label.key().to_string(), label.value().to_string())),
+        );
+        let mut series = self.series.lock().unwrap();
+        let exemplars = series.entry((name.to_string(), labels)).or_default();
+        if exemplars.len() >= MAX_EXEMPLARS_PER_SERIES {
+            exemplars.pop_front();
+        }
+        exemplars.push_back(exemplar);
+    }
+
+    /// Latest exemplar of a series, with a value in `(lower, upper]` if given.
+    fn latest(
+        &self,
+        name: &str,
+        labels: Vec<(String, String)>,
+        bounds: Option<(f64, f64)>,
+    ) -> Option<Exemplar> {
+        let series = self.series.lock().unwrap();
+        series
+            .get(&(name.to_string(), labels))?
+            .iter()
+            .rev()
+            .find(|exemplar| {
+                bounds
+                    .is_none_or(|(lower, upper)| exemplar.value > lower && exemplar.value <= upper)
+            })
+            .cloned()
+    }
+
+    /// Convert the Prometheus text format rendered by the exporter to the OpenMetrics format,
+    /// attaching the latest exemplar to the counters and to each bucket of the histograms.
+    pub fn render_openmetrics(&self, text: &str) -> String {
+        let mut counters = HashSet::new();
+        let mut histograms = HashSet::new();
+        for line in text.lines() {
+            if let Some(family) = line.strip_prefix("# TYPE ") {
+                match family.split_once(' ') {
+                    Some((name, "counter")) => {
+                        counters.insert(name);
+                    }
+                    Some((name, "histogram")) => {
+                        histograms.insert(name);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut output = String::with_capacity(text.len());
+        let mut previous_bucket: Option<Bucket> = None;
+        for line in text.lines() {
+            if line.is_empty() {
+                continue;
+            }
+            // OpenMetrics counter families are named without their `_total` suffix,
+            // counters without it are exposed as unknown
+            if let Some((prefix, family)) = ["# HELP ", "# TYPE "]
+                .iter()
+                .find_map(|prefix| line.strip_prefix(prefix).map(|family| (*prefix, family)))
+            {
+                let (name, rest) = family.split_once(' ').unwrap_or((family, ""));
+                match (counters.contains(name), name.strip_suffix("_total")) {
+                    (true, Some(stripped)) => {
+                        let _ = writeln!(output, "{}{} {}", prefix, stripped, rest);
+                    }
+                    (true, None) if prefix == "# TYPE " => {
+                        let _ = writeln!(output, "{}{} unknown", prefix, name);
+                    }
+                    _ => {
+                        let _ = writeln!(output, "{}", line);
+                    }
+                }
+                continue;
+            }
+            output.push_str(line);
+            if !line.starts_with('#') {
+                if let Some(exemplar) =
+                    self.sample_exemplar(line, &counters, &histograms, &mut previous_bucket)
+                {
+                    if let Some(rendered) = exemplar.render() {
+                        output.push_str(&rendered);
+                    }
+                }
+            }
+            output.push('\n');
+        }
+        output.push_str("# EOF\n");
+        output
+    }
+
+    fn sample_exemplar(
+        &self,
+        line: &str,
+        counters: &HashSet<&str>,
+        histograms: &HashSet<&str>,
+        previous_bucket: &mut Option<Bucket>,
+    ) -> Option<Exemplar> {
+        let (name, mut labels) = parse_sample(line)?;
+        if counters.contains(name) && name.ends_with("_total") {
+            return self.latest(name, sorted_labels(labels), None);
+        }
+
+        let family = name.strip_suffix("_bucket")?;
+        if !histograms.contains(family) {
+            return None;
+        }
+        let le = labels.iter().position(|(key, _)| key == "le")?;
+        let (_, le) = labels.remove(le);
+        let upper = if le == "+Inf" {
+            f64::INFINITY
+        } else {
+            le.parse().ok()?
+        };
+        let labels = sorted_labels(labels);
+        let lower = match previous_bucket.take() {
+            Some((previous, previous_labels, lower))
+                if previous == family && previous_labels == labels =>
+            {
+                lower
+            }
+            _ => f64::NEG_INFINITY,
+        };
+        *previous_bucket = Some((family.to_string(), labels.clone(), upper));
+        self.latest(family, labels, Some((lower, upper)))
+    }
+}
+
+static EXEMPLARS: LazyLock<Exemplars> = LazyLock::new(Exemplars::default);
+
+/// Record an exemplar of a counter or histogram series of the global metrics recorder.
+pub fn record(name: &str, labels: &[Label], exemplar: Exemplar) {
+    EXEMPLARS.record(name, labels, exemplar);
+}
+
+/// Render the metrics of the Prometheus exporter in the OpenMetrics format, with exemplars.
+pub fn render_openmetrics(text: &str) -> String {
+    EXEMPLARS.render_openmetrics(text)
+}
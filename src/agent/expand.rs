@@ -0,0 +1,86 @@
+use anyhow::Result;
+use caracat::models::Probe;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::probe::ProbeTags;
+
+/// Inclusive range of TTLs, in format `MIN-MAX`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TtlRange {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl TtlRange {
+    pub fn ttls(&self) -> std::ops::RangeInclusive<u8> {
+        self.min..=self.max
+    }
+
+    /// Number of probes per target.
+    pub fn len(&self) -> usize {
+        self.ttls().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl FromStr for TtlRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (min, max) = s
+            .trim()
+            .split_once('-')
+            .ok_or_else(|| anyhow::anyhow!("Invalid TTL range '{}'. Expected 'MIN-MAX'", s))?;
+        let min: u8 = min
+            .trim()
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid minimum TTL in '{}': {}", s, e))?;
+        let max: u8 = max
+            .trim()
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid maximum TTL in '{}': {}", s, e))?;
+        if min == 0 || min > max {
+            anyhow::bail!("Invalid TTL range '{}'. Expected 1 <= MIN <= MAX", s);
+        }
+        Ok(TtlRange { min, max })
+    }
+}
+
+impl fmt::Display for TtlRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.min, self.max)
+    }
+}
+
+/// Probes of a target, one per TTL of `range`. The TTL of the target is ignored.
+pub fn expand_target(target: &Probe, range: TtlRange) -> impl Iterator<Item = Probe> + '_ {
+    range.ttls().map(move |ttl| Probe {
+        dst_addr: target.dst_addr,
+        src_port: target.src_port,
+        dst_port: target.dst_port,
+        ttl,
+        protocol: target.protocol,
+    })
+}
+
+/// Expand each target into one probe per TTL of `range`, in traceroute order.
+/// The tags of the targets are copied to all their probes.
+pub fn expand_targets(
+    targets: Vec<Probe>,
+    tags: Vec<ProbeTags>,
+    range: TtlRange,
+) -> (Vec<Probe>, Vec<ProbeTags>) {
+    let mut probes = Vec::with_capacity(targets.len() * range.len());
+    let mut expanded_tags = Vec::with_capacity(probes.capacity());
+    let mut tags = tags.into_iter();
+    for target in targets {
+        let tag = tags.next().unwrap_or_default();
+        probes.extend(expand_target(&target, range));
+        expanded_tags.resize(probes.len(), tag);
+    }
+    (probes, expanded_tags)
+}
@@ -0,0 +1,195 @@
+use anyhow::Result;
+use ipnet::{Ipv4Net, Ipv6Net};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::Sender;
+
+use crate::agent::sender::ProbesWithSource;
+use crate::config::CaracatConfig;
+
+/// Name of a caracat instance, in logs, metrics and gateway health.
+pub fn instance_label(config: &CaracatConfig) -> String {
+    config
+        .name
+        .clone()
+        .unwrap_or_else(|| format!("instance_{}", config.instance_id))
+}
+
+/// Check that `backup` can send the probes of `primary`: each prefix of the primary
+/// must be contained in the prefix of the same family of the backup.
+pub fn check_compatible_prefixes(primary: &CaracatConfig, backup: &CaracatConfig) -> Result<()> {
+    if let Some(prefix) = &primary.src_ipv4_prefix {
+        let prefix: Ipv4Net = prefix
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid IPv4 prefix format: {}", prefix))?;
+        let contained = match &backup.src_ipv4_prefix {
+            Some(backup_prefix) => backup_prefix
+                .parse::<Ipv4Net>()
+                .map_err(|_| anyhow::anyhow!("Invalid IPv4 prefix format: {}", backup_prefix))?
+                .contains(&prefix),
+            None => false,
+        };
+        if !contained {
+            anyhow::bail!(
+                "Backup instance {} does not cover the IPv4 prefix {} of instance {}",
+                instance_label(backup),
+                prefix,
+                instance_label(primary)
+            );
+        }
+    }
+    if let Some(prefix) = &primary.src_ipv6_prefix {
+        let prefix: Ipv6Net = prefix
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid IPv6 prefix format: {}", prefix))?;
+        let contained = match &backup.src_ipv6_prefix {
+            Some(backup_prefix) => backup_prefix
+                .parse::<Ipv6Net>()
+                .map_err(|_| anyhow::anyhow!("Invalid IPv6 prefix format: {}", backup_prefix))?
+                .contains(&prefix),
+            None => false,
+        };
+        if !contained {
+            anyhow::bail!(
+                "Backup instance {} does not cover the IPv6 prefix {} of instance {}",
+                instance_label(backup),
+                prefix,
+                instance_label(primary)
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Validate the `backup_instance` of each caracat instance: the backup must exist,
+/// differ from the instance, have no backup itself, and cover its prefixes.
+pub fn validate_backups(configs: &[CaracatConfig]) -> Result<()> {
+    for config in configs {
+        let Some(backup_name) = &config.backup_instance else {
+            continue;
+        };
+        let backup = configs
+            .iter()
+            .find(|c| c.name.as_deref() == Some(backup_name.as_str()))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Unknown backup_instance '{}' for instance {}",
+                    backup_name,
+                    instance_label(config)
+                )
+            })?;
+        if backup.instance_id == config.instance_id {
+            anyhow::bail!(
+                "Instance {} cannot be its own backup",
+                instance_label(config)
+            );
+        }
+        if backup.backup_instance.is_some() {
+            anyhow::bail!(
+                "Backup instance {} cannot have a backup_instance itself",
+                backup_name
+            );
+        }
+        check_compatible_prefixes(config, backup)?;
+    }
+    Ok(())
+}
+
+/// Whether a network interface is up, from its operational state in sysfs.
+/// Interfaces whose state is unknown (e.g. loopback, or not on Linux) are assumed up.
+pub fn interface_is_up(interface: &str) -> bool {
+    match std::fs::read_to_string(format!("/sys/class/net/{}/operstate", interface)) {
+        Ok(state) => !matches!(state.trim(), "down" | "lowerlayerdown" | "notpresent"),
+        Err(_) => true,
+    }
+}
+
+/// Instances currently failed over to their backup, with the time of the failover.
+/// Shared by the sender workers and the gateway health reporting.
+#[derive(Debug, Clone, Default)]
+pub struct FailoverState {
+    active: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl FailoverState {
+    /// Mark an instance as failed over. Returns false if it already was.
+    pub fn activate(&self, instance: &str) -> bool {
+        let mut active = self.active.lock().unwrap();
+        if active.contains_key(instance) {
+            return false;
+        }
+        active.insert(instance.to_string(), Instant::now());
+        true
+    }
+
+    /// Return to the primary. Returns false if the instance was not failed over.
+    pub fn deactivate(&self, instance: &str) -> bool {
+        self.active.lock().unwrap().remove(instance).is_some()
+    }
+
+    pub fn is_active(&self, instance: &str) -> bool {
+        self.active.lock().unwrap().contains_key(instance)
+    }
+
+    /// Time since the failover of an instance, if failed over.
+    pub fn elapsed(&self, instance: &str) -> Option<Duration> {
+        self.active
+            .lock()
+            .unwrap()
+            .get(instance)
+            .map(|since| since.elapsed())
+    }
+
+    /// Failed over instances, sorted by name.
+    pub fn active(&self) -> Vec<String> {
+        let mut active: Vec<String> = self.active.lock().unwrap().keys().cloned().collect();
+        active.sort();
+        active
+    }
+}
+
+/// Counts the consecutive send failures of a sender worker.
+#[derive(Debug, Clone, Copy)]
+pub struct FailureCounter {
+    consecutive: u64,
+    threshold: u64,
+}
+
+impl FailureCounter {
+    pub fn new(threshold: u64) -> Self {
+        FailureCounter {
+            consecutive: 0,
+            threshold: threshold.max(1),
+        }
+    }
+
+    /// Record the outcome of a send. Returns true once the threshold of consecutive
+    /// failures is reached.
+    pub fn record(&mut self, success: bool) -> bool {
+        if success {
+            self.consecutive = 0;
+        } else {
+            self.consecutive += 1;
+        }
+        self.consecutive >= self.threshold
+    }
+
+    pub fn reset(&mut self) {
+        self.consecutive = 0;
+    }
+}
+
+/// Failover of a caracat instance to its backup, given to its sender workers.
+#[derive(Debug, Clone)]
+pub struct Failover {
+    /// Label of the primary instance
+    pub instance: String,
+    /// Label of the backup instance
+    pub backup: String,
+    /// Probes channel of the backup instance
+    pub backup_sender: Sender<ProbesWithSource>,
+    pub state: FailoverState,
+    pub threshold: u64,
+    pub cooldown: Duration,
+}
@@ -0,0 +1,151 @@
+use anyhow::{Context, Result};
+use caracat::models::Probe;
+use ipnet::IpNet;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::agent::ratelimit::KeyedRateLimiter;
+use crate::client::rib::read_prefix_list;
+use crate::config::CaracatConfig;
+
+/// Network a probe is sent to, over which the probes of a message are interleaved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FairnessKey {
+    Asn(u32),
+    Prefix(IpNet),
+}
+
+/// How the probes of a caracat instance are spread across destination networks, from its
+/// `fairness` option: `prefix` interleaves the destination prefixes of
+/// `fairness_ipv4_prefix_len` and `fairness_ipv6_prefix_len` bits, `asn` interleaves the
+/// origin ASNs of `fairness_asn_file` (destinations outside of it are keyed by prefix).
+/// With `fairness_max_pps`, the probing rate towards each network is also capped, across the
+/// messages of the instance.
+#[derive(Debug, Clone, Default)]
+pub struct FairnessScheduler {
+    ipv4_prefix_len: u8,
+    ipv6_prefix_len: u8,
+    // Origin ASNs by prefix length, looked up from the most specific
+    asns: BTreeMap<u8, HashMap<IpNet, u32>>,
+    // Probing rate towards each network, shared by the clones of the scheduler
+    rate: Option<KeyedRateLimiter<FairnessKey>>,
+}
+
+impl FairnessScheduler {
+    /// Scheduler of the instance, `None` if its probes are sent in their order.
+    pub fn new(config: &CaracatConfig) -> Result<Option<Self>> {
+        let Some(fairness) = config.fairness.as_deref() else {
+            if config.fairness_max_pps.is_some() {
+                anyhow::bail!("fairness_max_pps requires fairness 'prefix' or 'asn'");
+            }
+            return Ok(None);
+        };
+        if config.fairness_max_pps == Some(0) {
+            anyhow::bail!("Invalid fairness_max_pps 0. Expected at least 1 probe per second");
+        }
+        if config.fairness_ipv4_prefix_len > 32 || config.fairness_ipv6_prefix_len > 128 {
+            anyhow::bail!(
+                "Invalid fairness prefix length /{} or /{}. Expected at most /32 (IPv4) and /128 (IPv6)",
+                config.fairness_ipv4_prefix_len,
+                config.fairness_ipv6_prefix_len
+            );
+        }
+        let mut scheduler = FairnessScheduler {
+            ipv4_prefix_len: config.fairness_ipv4_prefix_len,
+            ipv6_prefix_len: config.fairness_ipv6_prefix_len,
+            asns: BTreeMap::new(),
+            rate: config.fairness_max_pps.map(KeyedRateLimiter::new),
+        };
+        match (fairness.to_lowercase().as_str(), &config.fairness_asn_file) {
+            ("prefix", None) => {}
+            ("asn", Some(path)) => {
+                let file = std::fs::File::open(path)
+                    .with_context(|| format!("Failed to open fairness_asn_file {}", path))?;
+                for routed in read_prefix_list(std::io::BufReader::new(file))? {
+                    scheduler
+                        .asns
+                        .entry(routed.prefix.prefix_len())
+                        .or_default()
+                        .entry(routed.prefix)
+                        .or_insert(routed.origin_asn);
+                }
+            }
+            ("asn", None) => anyhow::bail!("fairness 'asn' requires a fairness_asn_file"),
+            ("prefix", Some(_)) => {
+                anyhow::bail!("fairness_asn_file is only used with fairness 'asn'")
+            }
+            (other, _) => anyhow::bail!("Invalid fairness '{}'. Expected 'prefix' or 'asn'", other),
+        }
+        Ok(Some(scheduler))
+    }
+
+    /// Network of a destination: its origin ASN if known, its prefix otherwise.
+    pub fn key(&self, dst_addr: &IpAddr) -> FairnessKey {
+        for (prefix_len, asns) in self.asns.iter().rev() {
+            if let Ok(prefix) = IpNet::new(*dst_addr, *prefix_len) {
+                if let Some(asn) = asns.get(&prefix.trunc()) {
+                    return FairnessKey::Asn(*asn);
+                }
+            }
+        }
+        let prefix_len = match dst_addr {
+            IpAddr::V4(_) => self.ipv4_prefix_len,
+            IpAddr::V6(_) => self.ipv6_prefix_len,
+        };
+        // Prefix lengths are validated at creation
+        FairnessKey::Prefix(
+            IpNet::new(*dst_addr, prefix_len)
+                .map(|prefix| prefix.trunc())
+                .unwrap_or_else(|_| IpNet::from(*dst_addr)),
+        )
+    }
+
+    /// Reorder probes (with their tags) so that consecutive probes go to different networks:
+    /// one probe of each network in turn, in their order of first appearance. The probes to a
+    /// network keep their relative order.
+    pub fn interleave<T>(&self, probes: Vec<(Probe, T)>) -> Vec<(Probe, T)> {
+        let total = probes.len();
+        let mut networks: HashMap<FairnessKey, usize> = HashMap::new();
+        let mut queues: Vec<VecDeque<(Probe, T)>> = Vec::new();
+        for (probe, value) in probes {
+            let index = *networks
+                .entry(self.key(&probe.dst_addr))
+                .or_insert_with(|| {
+                    queues.push(VecDeque::new());
+                    queues.len() - 1
+                });
+            queues[index].push_back((probe, value));
+        }
+
+        let mut interleaved = Vec::with_capacity(total);
+        while !queues.is_empty() {
+            queues.retain_mut(|queue| match queue.pop_front() {
+                Some(item) => {
+                    interleaved.push(item);
+                    true
+                }
+                None => false,
+            });
+        }
+        interleaved
+    }
+
+    /// Count a probe to `dst_addr` at `now` if the rate of its network allows it, returning
+    /// zero. Otherwise, the probe is not counted, and the delay before trying again is returned.
+    pub fn take(&self, dst_addr: &IpAddr, now: Instant) -> Duration {
+        match self.rate {
+            Some(ref rate) => rate.take(self.key(dst_addr), now),
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Count a probe to `dst_addr`, sleeping until the rate of its network allows it.
+    /// Returns whether the probe was delayed.
+    pub fn wait(&self, dst_addr: &IpAddr) -> bool {
+        match self.rate {
+            Some(ref rate) => rate.wait(self.key(dst_addr)),
+            None => false,
+        }
+    }
+}
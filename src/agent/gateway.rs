@@ -1,28 +1,181 @@
-use reqwest::Client;
+use anyhow::Context;
+use caracat::models::Probe;
+use reqwest::{Certificate, Client, ClientBuilder, Identity, Proxy, Url};
 use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{mpsc, watch};
 use tokio::task::spawn;
 use tokio::time::{sleep, Duration};
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
 
-use crate::config::CaracatConfig;
+use crate::agent::chaos;
+use crate::agent::control::{apply_command, AgentMode, ControlCommand};
+use crate::agent::emission::EmissionChecks;
+use crate::agent::events::EventLog;
+use crate::agent::failover::FailoverState;
+use crate::agent::ratelimit::SharedRateLimiter;
+use crate::config::{CaracatConfig, GatewayConfig, ProbesFormat};
+use crate::measurement::MeasurementStatus;
+
+const PROXY_SCHEMES: [&str; 4] = ["http", "https", "socks5", "socks5h"];
+
+const CARACAT_CONFIG_TIMEOUT: Duration = Duration::from_secs(10);
+const COMMANDS_TIMEOUT_MARGIN: Duration = Duration::from_secs(10);
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+/// HTTP client of the requests to the gateway, authenticated with the client certificate of
+/// `gateway.tls_cert` and `gateway.tls_key` if set, through `gateway.proxy` if set.
+pub fn http_client(config: &GatewayConfig) -> anyhow::Result<Client> {
+    let builder = tls(Client::builder(), config)?;
+    let builder = match proxy(config)? {
+        Some(proxy) => builder.proxy(proxy),
+        None => builder,
+    };
+    builder
+        .build()
+        .context("Failed to create the gateway HTTP client")
+}
+
+fn read_pem(path: &Path, option: &str) -> anyhow::Result<Vec<u8>> {
+    std::fs::read(path)
+        .with_context(|| format!("Failed to read gateway.{} '{}'", option, path.display()))
+}
+
+/// Trust the CA certificates of `gateway.tls_ca_cert` in addition to the system roots, and
+/// present the client certificate for mutual TLS.
+fn tls(builder: ClientBuilder, config: &GatewayConfig) -> anyhow::Result<ClientBuilder> {
+    let mut builder = builder;
+    if let Some(path) = &config.tls_ca_cert {
+        let certificates = Certificate::from_pem_bundle(&read_pem(path, "tls_ca_cert")?)
+            .with_context(|| format!("Invalid gateway.tls_ca_cert '{}'", path.display()))?;
+        if certificates.is_empty() {
+            anyhow::bail!(
+                "Invalid gateway.tls_ca_cert '{}': no certificate",
+                path.display()
+            );
+        }
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            // The identity is read from the certificate chain followed by the private key
+            let mut pem = read_pem(cert, "tls_cert")?;
+            pem.push(b'\n');
+            pem.extend(read_pem(key, "tls_key")?);
+            let identity =
+                Identity::from_pem(&pem).context("Invalid gateway.tls_cert or gateway.tls_key")?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => anyhow::bail!("gateway.tls_cert and gateway.tls_key must be set together"),
+    }
+    Ok(builder)
+}
+
+/// Proxy of `gateway.proxy`, authenticated with `gateway.proxy_username` and
+/// `gateway.proxy_password`.
+fn proxy(config: &GatewayConfig) -> anyhow::Result<Option<Proxy>> {
+    let Some(proxy) = &config.proxy else {
+        if config.proxy_username.is_some() || config.proxy_password.is_some() {
+            anyhow::bail!(
+                "gateway.proxy_username and gateway.proxy_password require gateway.proxy"
+            );
+        }
+        return Ok(None);
+    };
+    let mut url =
+        Url::parse(proxy).with_context(|| format!("Invalid gateway.proxy '{}'", proxy))?;
+    if !PROXY_SCHEMES.contains(&url.scheme()) {
+        anyhow::bail!(
+            "Invalid gateway.proxy scheme '{}'. Expected one of: {}",
+            url.scheme(),
+            PROXY_SCHEMES.join(", ")
+        );
+    }
+    // Credentials are passed in the URL, for both HTTP and SOCKS5 proxies
+    match (&config.proxy_username, &config.proxy_password) {
+        (Some(username), password) => {
+            if url.set_username(username).is_err() || url.set_password(password.as_deref()).is_err()
+            {
+                anyhow::bail!("Invalid gateway.proxy '{}': no credentials allowed", proxy);
+            }
+        }
+        (None, Some(_)) => anyhow::bail!("gateway.proxy_password requires gateway.proxy_username"),
+        (None, None) => {}
+    }
+    Ok(Some(Proxy::all(url).context("Invalid gateway.proxy")?))
+}
+
+/// Set the client of the requests to the gateway from its configuration, once at startup.
+pub fn init_client(config: &GatewayConfig) -> anyhow::Result<()> {
+    let client = http_client(config)?;
+    let _ = CLIENT.set(client);
+    Ok(())
+}
+
+/// Client of the requests to the gateway, direct if not initialized.
+pub fn client() -> Client {
+    CLIENT.get_or_init(Client::new).clone()
+}
 
 // Structure to hold measurement tracking information from Kafka headers
 #[derive(Debug, Clone)]
 pub struct MeasurementInfo {
     pub measurement_id: String,
     pub end_of_measurement: bool,
+    // Time the message was produced by the client (Kafka message timestamp), in milliseconds
+    pub submitted_at_ms: Option<i64>,
+    // Trace ID of the message (W3C `traceparent` header), attached to the metrics exemplars
+    pub trace_id: Option<String>,
+    // Client of the message (ID of its signing key), if the messages are signed
+    pub client_id: Option<String>,
+    // Weight of the measurement in the fair scheduling of the instances (1 if not given)
+    pub weight: Option<u32>,
 }
 
 // Structure for reporting measurement status to gateway
-#[derive(Debug, Clone, Serialize)]
-struct MeasurementStatusUpdate {
-    sent_probes: u32,
-    is_complete: bool,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeasurementStatusUpdate {
+    pub sent_probes: u32,
+    pub is_complete: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_cancelled: bool,
+    // Maximum number of probes of the measurement on the agent, if exceeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_probes_exceeded: Option<u64>,
+}
+
+impl MeasurementStatusUpdate {
+    pub fn new(status: MeasurementStatus, sent_probes: u32) -> Self {
+        MeasurementStatusUpdate {
+            sent_probes,
+            is_complete: status.is_final(),
+            is_cancelled: status == MeasurementStatus::Cancelled,
+            max_probes_exceeded: None,
+        }
+    }
+
+    /// Status of the measurement on the agent, as reported.
+    pub fn status(&self) -> MeasurementStatus {
+        match (self.is_cancelled, self.is_complete, self.sent_probes) {
+            (true, _, _) => MeasurementStatus::Cancelled,
+            (false, true, _) => MeasurementStatus::Completed,
+            (false, false, 0) => MeasurementStatus::Pending,
+            (false, false, _) => MeasurementStatus::Running,
+        }
+    }
 }
 
 // This struct matches the AgentConfig expected by the gateway
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
-struct GatewayAgentConfig {
+#[serde(default)]
+pub struct GatewayAgentConfig {
     #[serde(default)]
     pub name: Option<String>,
     pub batch_size: u64,
@@ -59,13 +212,105 @@ impl From<&CaracatConfig> for GatewayAgentConfig {
     }
 }
 
+/// Delays of the healthcheck loop: the healthcheck interval after a successful cycle, and an
+/// exponential backoff on consecutive failures, both with a random jitter.
+#[derive(Debug, Clone)]
+pub struct HealthcheckSchedule {
+    interval: Duration,
+    retry_backoff: Duration,
+    max_retry_backoff: Duration,
+    jitter: f64,
+    failures: u32,
+}
+
+impl HealthcheckSchedule {
+    pub fn new(config: &GatewayConfig) -> anyhow::Result<Self> {
+        if config.healthcheck_interval == 0 {
+            anyhow::bail!("Invalid gateway.healthcheck_interval. Expected > 0");
+        }
+        if config.registration_retry_backoff == 0 {
+            anyhow::bail!("Invalid gateway.registration_retry_backoff. Expected > 0");
+        }
+        if config.registration_retry_max_backoff < config.registration_retry_backoff {
+            anyhow::bail!(
+                "Invalid gateway.registration_retry_max_backoff. Expected >= gateway.registration_retry_backoff"
+            );
+        }
+        if !(0.0..1.0).contains(&config.healthcheck_jitter) {
+            anyhow::bail!("Invalid gateway.healthcheck_jitter. Expected in [0, 1)");
+        }
+        Ok(HealthcheckSchedule {
+            interval: Duration::from_secs(config.healthcheck_interval),
+            retry_backoff: Duration::from_secs(config.registration_retry_backoff),
+            max_retry_backoff: Duration::from_secs(config.registration_retry_max_backoff),
+            jitter: config.healthcheck_jitter,
+            failures: 0,
+        })
+    }
+
+    /// Delay before the next cycle after a successful one.
+    pub fn success(&mut self) -> Duration {
+        self.failures = 0;
+        self.jittered(self.interval)
+    }
+
+    /// Delay before retrying after a failure, doubled on each consecutive failure.
+    pub fn failure(&mut self) -> Duration {
+        let backoff = self
+            .retry_backoff
+            .saturating_mul(2u32.saturating_pow(self.failures))
+            .min(self.max_retry_backoff);
+        self.failures = self.failures.saturating_add(1);
+        self.jittered(backoff)
+    }
+
+    /// `delay` shortened or lengthened by up to the jitter fraction.
+    fn jittered(&self, delay: Duration) -> Duration {
+        // Uniform in [0, 1), from the randomly keyed hasher of the standard library
+        let sample = RandomState::new().build_hasher().finish() as f64 / (u64::MAX as f64 + 1.0);
+        delay.mul_f64(1.0 + self.jitter * (2.0 * sample - 1.0))
+    }
+}
+
+/// Response of the gateway to the healthcheck.
+#[derive(Debug, Default, Deserialize)]
+pub struct HealthResponse {
+    /// Probes topics the agent should consume, instead of its configured topics.
+    #[serde(default)]
+    pub topics: Option<Vec<String>>,
+}
+
+impl HealthResponse {
+    /// Topics assigned by the gateway, if any.
+    pub fn assigned_topics(&self) -> Option<Vec<String>> {
+        let topics: Vec<String> = self
+            .topics
+            .as_ref()?
+            .iter()
+            .map(|topic| topic.trim())
+            .filter(|topic| !topic.is_empty())
+            .map(str::to_string)
+            .collect();
+        (!topics.is_empty()).then_some(topics)
+    }
+}
+
+/// Spawn the registration and health reporting loop. The probes topics assigned by the
+/// gateway in the healthcheck responses are sent to `topics`, the configured topics when
+/// the gateway no longer assigns any.
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_healthcheck_loop(
     gateway_url: String,
     agent_id: String,
     agent_key: String,
     agent_secret: String,
     caracat_configs: Vec<CaracatConfig>,
+    failover: FailoverState,
+    emission: EmissionChecks,
+    topics: Arc<watch::Sender<Vec<String>>>,
+    mut schedule: HealthcheckSchedule,
 ) {
+    let configured_topics = topics.borrow().clone();
     let base_url = gateway_url.trim_end_matches('/').to_string();
     let agent_url = format!("{}/api/agent/{}", base_url, agent_id);
     let config_url = format!("{}/agent-api/agent/{}/config", base_url, agent_id);
@@ -77,7 +322,7 @@ pub fn spawn_healthcheck_loop(
             "Starting healthcheck loop for agent {} with gateway {}",
             agent_id, base_url
         );
-        let client = Client::new();
+        let client = client();
 
         // Add initial delay to allow gateway to start up
         sleep(Duration::from_secs(5)).await;
@@ -108,7 +353,7 @@ pub fn spawn_healthcheck_loop(
                     error!("Failed to check if agent exists: {}", e);
                     debug!("Network error during agent existence check, gateway might not be ready yet");
                     // Skip this iteration if we can't connect to the gateway
-                    sleep(Duration::from_secs(30)).await;
+                    sleep(schedule.failure()).await;
                     continue;
                 }
             }
@@ -137,14 +382,14 @@ pub fn spawn_healthcheck_loop(
                     Ok(r) => {
                         error!("Failed to register agent: {}", r.status());
                         // Don't continue with config/health updates if registration failed
-                        debug!("Skipping config and health updates due to registration failure, will retry in 30 seconds");
-                        sleep(Duration::from_secs(30)).await;
+                        debug!("Skipping config and health updates due to registration failure");
+                        sleep(schedule.failure()).await;
                         continue;
                     }
                     Err(e) => {
                         error!("Failed to register agent: {}", e);
-                        debug!("Network error during registration, will retry in 30 seconds");
-                        sleep(Duration::from_secs(30)).await;
+                        debug!("Network error during registration");
+                        sleep(schedule.failure()).await;
                         continue;
                     }
                 }
@@ -172,17 +417,36 @@ pub fn spawn_healthcheck_loop(
                 }
                 Err(e) => {
                     error!("Failed to send agent config: {}", e);
-                    debug!("Network error during config update, will retry in 30 seconds");
-                    sleep(Duration::from_secs(30)).await;
+                    debug!("Network error during config update");
+                    sleep(schedule.failure()).await;
                     continue;
                 }
             }
 
-            // Step 5: Send healthcheck update
+            // Step 5: Send healthcheck update, flagging the instances failed over to their backup
+            // and those whose probes are not emitted as sent
+            let failed_over = failover.active();
+            let unverified = emission.failing();
+            let mut messages = Vec::new();
+            if !failed_over.is_empty() {
+                messages.push(format!(
+                    "Instances failed over to their backup: {}",
+                    failed_over.join(", ")
+                ));
+            }
+            if !unverified.is_empty() {
+                messages.push(format!(
+                    "Instances whose probes are not emitted as sent: {}",
+                    unverified.join(", ")
+                ));
+            }
+            let message = (!messages.is_empty()).then(|| messages.join(". "));
             let health = serde_json::json!({
                 "healthy": true,
                 "last_check": chrono::Utc::now().to_rfc3339(),
-                "message": null
+                "message": message,
+                "failover": failed_over,
+                "emission": emission.statuses()
             });
 
             match client
@@ -194,25 +458,183 @@ pub fn spawn_healthcheck_loop(
             {
                 Ok(r) if r.status().is_success() => {
                     debug!("Healthcheck sent to gateway");
+                    // Older gateways answer without a body
+                    let response: HealthResponse = r.json().await.unwrap_or_default();
+                    let assigned = response
+                        .assigned_topics()
+                        .unwrap_or_else(|| configured_topics.clone());
+                    topics.send_if_modified(|current| {
+                        if *current == assigned {
+                            return false;
+                        }
+                        info!("Probes topics assigned by the gateway: {:?}", assigned);
+                        *current = assigned;
+                        true
+                    });
                 }
                 Ok(r) => {
                     warn!("Failed to send healthcheck: {}", r.status());
-                    // Don't fail the entire loop, just log and continue
+                    sleep(schedule.failure()).await;
+                    continue;
                 }
                 Err(e) => {
                     error!("Failed to send healthcheck: {}", e);
-                    debug!("Network error during healthcheck, will retry in 30 seconds");
-                    sleep(Duration::from_secs(30)).await;
+                    debug!("Network error during healthcheck");
+                    sleep(schedule.failure()).await;
                     continue;
                 }
             }
 
-            debug!("Healthcheck cycle completed, sleeping for 30 seconds");
-            sleep(Duration::from_secs(30)).await; // TODO: make interval configurable
+            let delay = schedule.success();
+            debug!("Healthcheck cycle completed, sleeping for {:?}", delay);
+            sleep(delay).await;
         }
     });
 }
 
+/// Fetch the caracat instances of the agent configured on the gateway. The options not set
+/// by the gateway take their default value.
+pub async fn fetch_caracat_configs(
+    client: &Client,
+    gateway_url: &str,
+    agent_id: &str,
+    agent_key: &str,
+) -> Result<Vec<CaracatConfig>, Box<dyn std::error::Error + Send + Sync>> {
+    let base_url = gateway_url.trim_end_matches('/').to_string();
+    let config_url = format!("{}/agent-api/agent/{}/config", base_url, agent_id);
+
+    let response = client
+        .get(&config_url)
+        .header("authorization", format!("Bearer {}", agent_key))
+        .timeout(CARACAT_CONFIG_TIMEOUT)
+        .send()
+        .await?;
+    chaos::delay_gateway_response().await;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Failed to fetch the caracat configuration: HTTP {}",
+            response.status()
+        )
+        .into());
+    }
+    let configs = response.json::<Vec<CaracatConfig>>().await?;
+    if configs.is_empty() {
+        return Err("No caracat instance configured on the gateway".into());
+    }
+    Ok(configs)
+}
+
+/// Commands pushed by the gateway to the agent.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum GatewayCommand {
+    Pause,
+    Resume,
+    Drain,
+    /// Probing rate of the agent, in packets per second (`agent.max_total_pps`).
+    SetRate {
+        probing_rate: u64,
+    },
+    CancelMeasurement {
+        measurement_id: String,
+    },
+}
+
+/// Where the commands of the gateway are applied.
+#[derive(Clone)]
+pub struct CommandTargets {
+    pub mode: Arc<watch::Sender<AgentMode>>,
+    pub events: EventLog,
+    /// Agent-wide rate limiter, set with `agent.max_total_pps`.
+    pub total_rate: Option<SharedRateLimiter>,
+    /// Measurements to cancel, by the handler.
+    pub cancellations: mpsc::Sender<String>,
+}
+
+impl CommandTargets {
+    pub fn apply(&self, command: GatewayCommand) {
+        match command {
+            GatewayCommand::Pause => apply_command(&self.mode, ControlCommand::Pause, &self.events),
+            GatewayCommand::Resume => {
+                apply_command(&self.mode, ControlCommand::Resume, &self.events)
+            }
+            GatewayCommand::Drain => apply_command(&self.mode, ControlCommand::Drain, &self.events),
+            GatewayCommand::SetRate { probing_rate: 0 } => {
+                warn!("Invalid probing rate 0 from the gateway. Ignored.");
+            }
+            GatewayCommand::SetRate { probing_rate } => match &self.total_rate {
+                Some(total_rate) => {
+                    info!("Probing rate set to {} pps by the gateway", probing_rate);
+                    total_rate.set_rate(probing_rate);
+                }
+                None => warn!("Setting the probing rate requires agent.max_total_pps. Ignored."),
+            },
+            GatewayCommand::CancelMeasurement { measurement_id } => {
+                if self.cancellations.try_send(measurement_id).is_err() {
+                    warn!("Failed to queue the measurement cancellation from the gateway");
+                }
+            }
+        }
+    }
+}
+
+/// Wait for the commands of the gateway, for up to `wait` (long polling).
+pub async fn fetch_commands(
+    client: &Client,
+    gateway_url: &str,
+    agent_id: &str,
+    agent_key: &str,
+    wait: Duration,
+) -> Result<Vec<GatewayCommand>, Box<dyn std::error::Error + Send + Sync>> {
+    let base_url = gateway_url.trim_end_matches('/').to_string();
+    let commands_url = format!("{}/agent-api/agent/{}/commands", base_url, agent_id);
+
+    let response = client
+        .get(&commands_url)
+        .query(&[("wait", wait.as_secs())])
+        .header("authorization", format!("Bearer {}", agent_key))
+        // The gateway holds the request until a command is available
+        .timeout(wait + COMMANDS_TIMEOUT_MARGIN)
+        .send()
+        .await?;
+    chaos::delay_gateway_response().await;
+
+    match response.status() {
+        reqwest::StatusCode::NO_CONTENT => Ok(Vec::new()),
+        status if status.is_success() => Ok(response.json::<Vec<GatewayCommand>>().await?),
+        status => Err(format!("Failed to fetch commands: HTTP {}", status).into()),
+    }
+}
+
+/// Apply the commands pushed by the gateway, polling for them again as soon as they are
+/// received, and backing off while the gateway cannot be reached.
+pub async fn commands_loop(
+    gateway_url: String,
+    agent_id: String,
+    agent_key: String,
+    wait: Duration,
+    mut schedule: HealthcheckSchedule,
+    targets: CommandTargets,
+) {
+    let client = client();
+    loop {
+        match fetch_commands(&client, &gateway_url, &agent_id, &agent_key, wait).await {
+            Ok(commands) => {
+                schedule.success();
+                for command in commands {
+                    debug!("Command from the gateway: {:?}", command);
+                    targets.apply(command);
+                }
+            }
+            Err(e) => {
+                warn!("Failed to fetch the commands of the gateway: {}", e);
+                sleep(schedule.failure()).await;
+            }
+        }
+    }
+}
+
 /// Report measurement status to the gateway
 pub async fn report_measurement_status(
     gateway_url: &str,
@@ -222,29 +644,104 @@ pub async fn report_measurement_status(
     sent_probes: u32,
     is_complete: bool,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let base_url = gateway_url.trim_end_matches('/').to_string();
-    let status_url = format!(
-        "{}/agent-api/agent/{}/measurement/{}/status",
-        base_url, agent_id, measurement_id
+    let status = if is_complete {
+        MeasurementStatus::Completed
+    } else {
+        MeasurementStatus::Running
+    };
+    let status_update = MeasurementStatusUpdate::new(status, sent_probes);
+
+    debug!(
+        "Reporting measurement status to gateway: measurement_id={}, sent_probes={}, is_complete={}",
+        measurement_id, sent_probes, is_complete
+    );
+
+    post_measurement_status(
+        gateway_url,
+        agent_id,
+        agent_key,
+        measurement_id,
+        &status_update,
+    )
+    .await
+}
+
+/// Report the cancellation of a measurement to the gateway
+pub async fn report_measurement_cancellation(
+    gateway_url: &str,
+    agent_id: &str,
+    agent_key: &str,
+    measurement_id: &str,
+    sent_probes: u32,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let status_update = MeasurementStatusUpdate::new(MeasurementStatus::Cancelled, sent_probes);
+
+    debug!(
+        "Reporting measurement cancellation to gateway: measurement_id={}, sent_probes={}",
+        measurement_id, sent_probes
     );
 
-    let client = Client::new();
+    post_measurement_status(
+        gateway_url,
+        agent_id,
+        agent_key,
+        measurement_id,
+        &status_update,
+    )
+    .await
+}
+
+/// Report to the gateway that a measurement exceeded the maximum number of probes of the
+/// agent, its next probes being dropped.
+pub async fn report_measurement_limit_exceeded(
+    gateway_url: &str,
+    agent_id: &str,
+    agent_key: &str,
+    measurement_id: &str,
+    sent_probes: u32,
+    max_probes: u64,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let status_update = MeasurementStatusUpdate {
-        sent_probes,
-        is_complete,
+        max_probes_exceeded: Some(max_probes),
+        ..MeasurementStatusUpdate::new(MeasurementStatus::Running, sent_probes)
     };
 
     debug!(
-        "Reporting measurement status to gateway: measurement_id={}, sent_probes={}, is_complete={}",
-        measurement_id, sent_probes, is_complete
+        "Reporting measurement limit exceeded to gateway: measurement_id={}, max_probes={}",
+        measurement_id, max_probes
     );
 
+    post_measurement_status(
+        gateway_url,
+        agent_id,
+        agent_key,
+        measurement_id,
+        &status_update,
+    )
+    .await
+}
+
+async fn post_measurement_status(
+    gateway_url: &str,
+    agent_id: &str,
+    agent_key: &str,
+    measurement_id: &str,
+    status_update: &MeasurementStatusUpdate,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let base_url = gateway_url.trim_end_matches('/').to_string();
+    let status_url = format!(
+        "{}/agent-api/agent/{}/measurement/{}/status",
+        base_url, agent_id, measurement_id
+    );
+
+    let client = client();
     let response = client
         .post(&status_url)
         .header("authorization", format!("Bearer {}", agent_key))
-        .json(&status_update)
+        .json(status_update)
         .send()
         .await?;
+    chaos::delay_gateway_response().await;
 
     if response.status().is_success() {
         debug!(
@@ -262,6 +759,142 @@ pub async fn report_measurement_status(
     }
 }
 
+/// Measurement assigned to an agent by the gateway, with its probes inline or to download.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssignedMeasurement {
+    pub measurement_id: String,
+    // Source IP of the probes, selecting the caracat instance
+    #[serde(default)]
+    pub src_ip: Option<String>,
+    #[serde(default)]
+    pub probes: Vec<Probe>,
+    // URL of a probes file, used instead of inline probes
+    #[serde(default)]
+    pub probes_url: Option<String>,
+    #[serde(default)]
+    pub probes_format: ProbesFormat,
+}
+
+/// Health last reported by an agent to the gateway.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AgentHealth {
+    pub healthy: bool,
+    pub last_check: Option<String>,
+    pub message: Option<String>,
+}
+
+/// Agent registered on the gateway, with the configuration of its caracat instances.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisteredAgent {
+    pub id: String,
+    #[serde(default)]
+    pub config: Vec<GatewayAgentConfig>,
+    #[serde(default)]
+    pub health: Option<AgentHealth>,
+}
+
+/// Fetch the agents registered on the gateway.
+pub async fn fetch_agents(
+    client: &Client,
+    gateway_url: &str,
+    agent_key: &str,
+) -> Result<Vec<RegisteredAgent>, Box<dyn std::error::Error + Send + Sync>> {
+    let base_url = gateway_url.trim_end_matches('/').to_string();
+    let agents_url = format!("{}/api/agents", base_url);
+
+    let response = client
+        .get(&agents_url)
+        .header("authorization", format!("Bearer {}", agent_key))
+        .send()
+        .await?;
+
+    if response.status().is_success() {
+        Ok(response.json::<Vec<RegisteredAgent>>().await?)
+    } else {
+        Err(format!("Failed to fetch agents: HTTP {}", response.status()).into())
+    }
+}
+
+/// Fetch the measurements assigned to an agent and not completed yet.
+pub async fn fetch_assigned_measurements(
+    client: &Client,
+    gateway_url: &str,
+    agent_id: &str,
+    agent_key: &str,
+) -> Result<Vec<AssignedMeasurement>, Box<dyn std::error::Error + Send + Sync>> {
+    let base_url = gateway_url.trim_end_matches('/').to_string();
+    let measurements_url = format!("{}/agent-api/agent/{}/measurements", base_url, agent_id);
+
+    let response = client
+        .get(&measurements_url)
+        .header("authorization", format!("Bearer {}", agent_key))
+        .send()
+        .await?;
+    chaos::delay_gateway_response().await;
+
+    if response.status().is_success() {
+        Ok(response.json::<Vec<AssignedMeasurement>>().await?)
+    } else {
+        Err(format!(
+            "Failed to fetch assigned measurements: HTTP {}",
+            response.status()
+        )
+        .into())
+    }
+}
+
+/// Download the probes file of an assigned measurement.
+pub async fn download_probes(
+    client: &Client,
+    probes_url: &str,
+    agent_key: &str,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let response = client
+        .get(probes_url)
+        .header("authorization", format!("Bearer {}", agent_key))
+        .send()
+        .await?;
+    chaos::delay_gateway_response().await;
+
+    if response.status().is_success() {
+        Ok(response.bytes().await?.to_vec())
+    } else {
+        Err(format!("Failed to download probes: HTTP {}", response.status()).into())
+    }
+}
+
+/// Fetch the status of a measurement on an agent, as last reported to the gateway.
+pub async fn fetch_measurement_status(
+    client: &Client,
+    gateway_url: &str,
+    agent_id: &str,
+    agent_key: &str,
+    measurement_id: &str,
+) -> Result<MeasurementStatusUpdate, Box<dyn std::error::Error + Send + Sync>> {
+    let base_url = gateway_url.trim_end_matches('/').to_string();
+    let status_url = format!(
+        "{}/agent-api/agent/{}/measurement/{}/status",
+        base_url, agent_id, measurement_id
+    );
+
+    let response = client
+        .get(&status_url)
+        .header("authorization", format!("Bearer {}", agent_key))
+        .send()
+        .await?;
+
+    if response.status().is_success() {
+        Ok(response.json::<MeasurementStatusUpdate>().await?)
+    } else {
+        Err(format!(
+            "Failed to fetch measurement status: HTTP {}",
+            response.status()
+        )
+        .into())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -276,12 +909,44 @@ mod tests {
             min_ttl: Some(10),
             max_ttl: Some(255),
             integrity_check: true,
+            integrity_encoding: "instance_id".to_string(),
+            integrity_accept_instance_id: false,
             interface: "eth0".to_string(),
             src_ipv4_prefix: Some("192.168.1.0/24".to_string()),
             src_ipv6_prefix: Some("2001:db8::/32".to_string()),
             packets: 1000,
             probing_rate: 100,
+            source_rate_limits: vec![],
             rate_limiting_method: "None".to_string(),
+            send_batch_size: 64,
+            sender_threads: 1,
+            sender_backend: "caracat".to_string(),
+            reply_filter: "all".to_string(),
+            reply_icmp_allowlist: vec![],
+            reply_exclude_unreachable: false,
+            receiver_backend: "pcap".to_string(),
+            receiver_ring_blocks: 64,
+            pcap_dump: None,
+            pcap_dump_max_bytes: 100 * 1024 * 1024,
+            pcap_dump_max_files: 5,
+            dst_denylist: vec![],
+            dst_allowlist: vec![],
+            dst_allowlist_file: None,
+            allowed_protocols: vec![],
+            max_probes_per_destination: None,
+            backup_instance: None,
+            failover_threshold: 100,
+            failover_cooldown: 60,
+            integrity_check_ipv4: None,
+            integrity_check_ipv6: None,
+            emission_check_sample_every: 0,
+            emission_check_timeout: 5,
+            fairness: None,
+            fairness_ipv4_prefix_len: 24,
+            fairness_ipv6_prefix_len: 48,
+            fairness_asn_file: None,
+            fairness_max_pps: None,
+            measurement_scheduling: None,
         };
 
         let gateway_config: GatewayAgentConfig = (&caracat_config).into();
@@ -1,22 +1,205 @@
-use anyhow::Result;
-use caracat::models::Reply;
+use anyhow::{Context, Result};
+use caracat::models::Probe;
+use metrics::{counter, gauge};
+use metrics_exporter_prometheus::PrometheusHandle;
 use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
-use rdkafka::message::Headers;
-use rdkafka::Message;
-use std::collections::HashMap;
+use rdkafka::message::{BorrowedMessage, Headers};
+use rdkafka::{Message, Offset, TopicPartitionList};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use tokio::runtime::Handle as TokioHandle;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::watch;
 use tokio::task::spawn;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, trace, warn};
 
-use crate::agent::consumer::init_consumer;
-use crate::agent::gateway::spawn_healthcheck_loop;
+use crate::agent::audit::{AuditEntry, AuditLog};
+use crate::agent::backpressure::{self, queue_fill, Backpressure};
+use crate::agent::commit::{enqueue, CommitStrategy, Committer, MessageOffset};
+use crate::agent::consumer::{
+    init_agents_consumer, init_consumer, init_control_consumer, subscribe_probes,
+};
+use crate::agent::control::{control_loop, AgentMode};
+use crate::agent::correlation::{CorrelationTable, DEFAULT_CORRELATION_CAPACITY};
+use crate::agent::dedup::ProbeDedup;
+use crate::agent::duplicate::duplicate_loop;
+use crate::agent::emission::{EmissionChecker, EmissionChecks};
+use crate::agent::events::{EventKind, EventLog};
+use crate::agent::exemplars::{trace_id_from_traceparent, TRACEPARENT_HEADER};
+use crate::agent::expand::{expand_targets, TtlRange};
+use crate::agent::failover::{instance_label, Failover, FailoverState};
+use crate::agent::gateway::{
+    commands_loop, fetch_caracat_configs, spawn_healthcheck_loop, CommandTargets,
+    HealthcheckSchedule, MeasurementInfo,
+};
+use crate::agent::identity::persisted_agent_id;
+use crate::agent::lag::{
+    consumer_lag, queue_depth_loop, report_consumer_lag, PartitionLag, LAG_INTERVAL,
+};
+use crate::agent::measurement_labels;
+use crate::agent::poll::poll_loop;
+use crate::agent::ports::{apply_port_policy, PortPolicy};
+use crate::agent::prefix_set::PrefixSet;
+use crate::agent::priority::{TopicPriorities, PRIORITY_INTERVAL};
 use crate::agent::producer;
+use crate::agent::quota::MeasurementQuota;
+use crate::agent::ratelimit::{DestinationRateLimiter, SharedRateLimiter};
 use crate::agent::receiver::ReceiveLoop;
-use crate::agent::sender::{ProbesWithSource, SendLoop};
+use crate::agent::s3;
+use crate::agent::scheduling::{schedule_loop, MeasurementQueues};
+use crate::agent::sender::{
+    shard_loop, MeasurementProgress, ProbesWithSource, SendLoop, SharedMeasurementProgress,
+};
+use crate::agent::server::{AgentState, LoopKind, Server};
+use crate::agent::spoof::SpoofDetector;
+use crate::agent::standby::{standby_loop, Election, LEASE_HEARTBEATS};
+use crate::agent::statistics;
+use crate::agent::supervisor::{supervise, Backoff, LoopHealth};
+use crate::agent::upload;
 use crate::auth::{KafkaAuth, SaslAuth};
-use crate::config::{AppConfig, CaracatConfig};
-use crate::probe::deserialize_probes;
+use crate::config::{validate_caracat_configs, AppConfig, CaracatConfig, KeyStrategy};
+use crate::probe::{deserialize_tagged_probes, ProbeTags};
+use crate::protocol::{
+    CANCEL_MEASUREMENT_HEADER, END_OF_MEASUREMENT_HEADER, EXPAND_TTL_HEADER, MEASUREMENT_ID_HEADER,
+    PRIORITY_HEADER, SRC_PORTS_HEADER, WEIGHT_HEADER,
+};
+use crate::reply::{CapturedReply, ReplyFormat};
+use crate::schema_registry::{unframe, SchemaRegistry};
+use crate::signing::{SignatureVerifier, SIGNATURE_KEY_ID_HEADER};
+
+/// Return the measurement to cancel if the message headers hold a cancellation
+/// request intended for `agent_id`.
+pub fn parse_cancellation<'a>(
+    headers: impl IntoIterator<Item = (&'a str, Option<&'a [u8]>)>,
+    agent_id: &str,
+) -> Option<String> {
+    let mut is_intended_for_this_agent = false;
+    let mut measurement_id = None;
+    for (key, value) in headers {
+        if key == agent_id {
+            is_intended_for_this_agent = true;
+        } else if key == CANCEL_MEASUREMENT_HEADER {
+            measurement_id = value
+                .and_then(|v| std::str::from_utf8(v).ok())
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty());
+        }
+    }
+    measurement_id.filter(|_| is_intended_for_this_agent)
+}
+
+/// Return the measurement of the probes of a message from its headers, if any.
+/// `submitted_at_ms` is the timestamp of the message.
+pub fn parse_measurement_info<'a>(
+    headers: impl IntoIterator<Item = (&'a str, Option<&'a [u8]>)>,
+    submitted_at_ms: Option<i64>,
+) -> Option<MeasurementInfo> {
+    let mut measurement_id = None;
+    let mut end_of_measurement = false;
+    let mut weight = None;
+    for (key, value) in headers {
+        let value = value
+            .and_then(|v| std::str::from_utf8(v).ok())
+            .map(str::trim);
+        if key == MEASUREMENT_ID_HEADER {
+            measurement_id = value.filter(|v| !v.is_empty()).map(str::to_string);
+        } else if key == END_OF_MEASUREMENT_HEADER {
+            end_of_measurement = value.and_then(|v| v.parse().ok()).unwrap_or(false);
+        } else if key == WEIGHT_HEADER {
+            weight = value.and_then(|v| v.parse().ok());
+        }
+    }
+    Some(MeasurementInfo {
+        measurement_id: measurement_id?,
+        end_of_measurement,
+        submitted_at_ms,
+        trace_id: None,
+        client_id: None,
+        weight,
+    })
+}
+
+/// Probes of a message sharing a source address and a caracat instance.
+#[derive(Debug, Default)]
+pub struct ProbeGroup {
+    pub source_ip: Option<String>,
+    pub instance: Option<String>,
+    pub probes: Vec<Probe>,
+    pub tags: Vec<ProbeTags>,
+}
+
+/// Group the probes of a message by source address and caracat instance, in order of
+/// first appearance. The source address and the instance of a probe override the
+/// `src_ip` and the `instance` of the message header.
+pub fn group_probes(
+    probes: Vec<Probe>,
+    tags: Vec<ProbeTags>,
+    header_src_ip: Option<&String>,
+    header_instance: Option<&String>,
+) -> Vec<ProbeGroup> {
+    let mut groups: Vec<ProbeGroup> = Vec::new();
+    let mut indexes: HashMap<(Option<String>, Option<String>), usize> = HashMap::new();
+    let mut tags = tags.into_iter();
+    for probe in probes {
+        let tag = tags.next().unwrap_or_default();
+        let source_ip = tag
+            .src_addr
+            .map(|addr| addr.to_string())
+            .or_else(|| header_src_ip.cloned());
+        let instance = tag.instance.clone().or_else(|| header_instance.cloned());
+        let index = *indexes
+            .entry((source_ip.clone(), instance.clone()))
+            .or_insert_with(|| {
+                groups.push(ProbeGroup {
+                    source_ip,
+                    instance,
+                    ..Default::default()
+                });
+                groups.len() - 1
+            });
+        groups[index].probes.push(probe);
+        groups[index].tags.push(tag);
+    }
+    groups
+}
+
+/// Sender of the caracat instance named `instance`, overriding the prefix-based selection.
+/// The source IP, if any, must still be within the prefixes of the instance.
+pub fn determine_pinned_sender(
+    probe_senders_map: &HashMap<String, Sender<ProbesWithSource>>,
+    caracat_configs: &[CaracatConfig],
+    instance: &str,
+    sender_ip: Option<&String>,
+) -> Result<(Option<Sender<ProbesWithSource>>, bool)> {
+    let caracat_cfg = caracat_configs
+        .iter()
+        .find(|caracat_cfg| caracat_cfg.name.as_deref() == Some(instance))
+        .ok_or_else(|| anyhow::anyhow!("No caracat instance named {} on this agent", instance))?;
+    let sender = probe_senders_map
+        .get(&format!("instance_{}", caracat_cfg.instance_id))
+        .cloned();
+
+    let has_prefix = caracat_cfg.src_ipv4_prefix.is_some() || caracat_cfg.src_ipv6_prefix.is_some();
+    match sender_ip {
+        Some(ip_addr_str) if has_prefix => {
+            crate::config::validate_ip_against_prefixes(
+                ip_addr_str,
+                &caracat_cfg.src_ipv4_prefix,
+                &caracat_cfg.src_ipv6_prefix,
+            )
+            .map_err(|e| {
+                e.context(format!(
+                    "Source IP address {} is not within the prefixes of instance {}",
+                    ip_addr_str, instance
+                ))
+            })?;
+            Ok((sender, true))
+        }
+        // Instances without prefixes use their default source IP
+        _ => Ok((sender, false)),
+    }
+}
 
 pub fn determine_target_sender(
     probe_senders_map: &HashMap<String, Sender<ProbesWithSource>>,
@@ -84,12 +267,245 @@ pub fn determine_target_sender(
     }
 }
 
-pub async fn handle(config: &AppConfig) -> Result<()> {
+fn message_offset(message: &BorrowedMessage) -> MessageOffset {
+    MessageOffset {
+        topic: message.topic().to_string(),
+        partition: message.partition(),
+        offset: message.offset(),
+    }
+}
+
+fn header_value(message: &BorrowedMessage, key: &str) -> Option<String> {
+    message.headers().and_then(|headers| {
+        headers
+            .iter()
+            .find(|header| header.key == key)
+            .and_then(|header| header.value)
+            .map(|value| String::from_utf8_lossy(value).into_owned())
+    })
+}
+
+fn record_audit(audit_log: &mut Option<AuditLog>, entry: &AuditEntry) {
+    if let Some(audit_log) = audit_log {
+        audit_log.record(entry);
+    }
+}
+
+/// Lag of the partitions of the consumer. Fetching the watermarks blocks, so it runs on the
+/// blocking threads, off the other tasks of the runtime (whatever its flavor).
+async fn fetch_consumer_lag(consumer: &Arc<StreamConsumer>) -> Vec<PartitionLag> {
+    let consumer = consumer.clone();
+    match tokio::task::spawn_blocking(move || consumer_lag(consumer.as_ref())).await {
+        Ok(lags) => lags,
+        Err(e) => {
+            warn!("Failed to fetch the consumer lag: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Pause or resume the consumption of the assigned probes partitions. The partitions of the
+/// `deferred` topics are left paused.
+fn pause_partitions(consumer: &StreamConsumer, paused: bool, deferred: &HashSet<String>) {
+    let Ok(assignment) = consumer.assignment() else {
+        return;
+    };
+    let (mut to_pause, mut to_resume) = (TopicPartitionList::new(), TopicPartitionList::new());
+    for element in assignment.elements() {
+        let partitions = if paused || deferred.contains(element.topic()) {
+            &mut to_pause
+        } else {
+            &mut to_resume
+        };
+        partitions.add_partition(element.topic(), element.partition());
+    }
+    for result in [consumer.pause(&to_pause), consumer.resume(&to_resume)] {
+        if let Err(e) = result {
+            warn!("Failed to update the consumer partitions: {}", e);
+        }
+    }
+}
+
+/// Commit the offset of the next message to consume in a partition, if any.
+fn commit_offset(consumer: &StreamConsumer, offset: Option<MessageOffset>) {
+    let Some(offset) = offset else {
+        return;
+    };
+    let mut partitions = TopicPartitionList::new();
+    if let Err(e) = partitions.add_partition_offset(
+        &offset.topic,
+        offset.partition,
+        Offset::Offset(offset.offset),
+    ) {
+        error!("Invalid offset to commit {:?}: {}", offset, e);
+        return;
+    }
+    if let Err(e) = consumer.commit(&partitions, CommitMode::Async) {
+        error!(
+            "Failed to commit offset {} of {}[{}]: {}",
+            offset.offset, offset.topic, offset.partition, e
+        );
+    }
+}
+
+/// The configuration with the caracat instances of the gateway, with
+/// `gateway.fetch_caracat_config`, or as loaded if they cannot be fetched.
+async fn with_gateway_caracat_configs(config: &AppConfig) -> Result<AppConfig> {
+    let mut config = config.clone();
+    let Some(gateway) = config
+        .gateway
+        .as_ref()
+        .filter(|gateway| gateway.fetch_caracat_config)
+    else {
+        return Ok(config);
+    };
+    let (Some(gateway_url), Some(agent_key)) = (&gateway.url, &gateway.agent_key) else {
+        warn!("gateway.fetch_caracat_config requires gateway.url and gateway.agent_key");
+        return Ok(config);
+    };
+    crate::agent::gateway::init_client(gateway)?;
+    let fetched = fetch_caracat_configs(
+        &crate::agent::gateway::client(),
+        gateway_url,
+        &config.agent.id,
+        agent_key,
+    )
+    .await
+    .map_err(|e| anyhow::anyhow!("{}", e))
+    .and_then(|mut caracat_configs| {
+        validate_caracat_configs(
+            &mut caracat_configs,
+            &config.agent.id,
+            config.agent.integrity_key.as_deref(),
+        )?;
+        Ok(caracat_configs)
+    });
+    match fetched {
+        Ok(caracat_configs) => {
+            info!(
+                "Using the {} caracat instances configured on the gateway",
+                caracat_configs.len()
+            );
+            config.caracat = caracat_configs;
+        }
+        Err(e) => warn!(
+            "Failed to fetch the caracat configuration from the gateway, using the local configuration: {}",
+            e
+        ),
+    }
+    Ok(config)
+}
+
+/// Cancel a measurement on every instance, and report it to the gateway.
+fn cancel_measurement(
+    config: &AppConfig,
+    progresses: &[SharedMeasurementProgress],
+    events: &EventLog,
+    measurement_id: String,
+) {
+    let sent_probes: u32 = progresses
+        .iter()
+        .map(|progress| progress.lock().unwrap().cancel(&measurement_id))
+        .sum();
+    info!(
+        "Measurement {} cancelled after {} probes sent",
+        measurement_id, sent_probes
+    );
+    events.emit(
+        EventKind::MeasurementCancelled,
+        Some(&measurement_id),
+        BTreeMap::from([("sent_probes".to_string(), sent_probes.to_string())]),
+    );
+    if let Some((gateway_url, agent_key)) = config
+        .gateway
+        .as_ref()
+        .and_then(|g| g.url.clone().zip(g.agent_key.clone()))
+    {
+        let agent_id = config.agent.id.clone();
+        spawn(async move {
+            if let Err(e) = crate::agent::gateway::report_measurement_cancellation(
+                &gateway_url,
+                &agent_id,
+                &agent_key,
+                &measurement_id,
+                sent_probes,
+            )
+            .await
+            {
+                error!("Failed to report measurement cancellation: {}", e);
+            }
+        });
+    }
+}
+
+/// The configuration with the agent ID persisted in `agent.id_file`, if `agent.id` is empty.
+fn with_persisted_agent_id(config: &AppConfig) -> Result<AppConfig> {
+    let mut config = config.clone();
+    if config.agent.id.is_empty() {
+        config.agent.id = persisted_agent_id(&config.agent.id_file)?;
+    }
+    Ok(config)
+}
+
+/// Run the agent until it stops, serving the metrics of the agent recorder, installed in
+/// the process (see `metrics::install_recorder`).
+pub async fn run(config: &AppConfig) -> Result<()> {
+    let metrics = crate::agent::metrics::install_recorder()?;
+    handle(config, metrics).await
+}
+
+/// Run the agent until it stops, serving the metrics of the recorder of `metrics`.
+pub async fn handle(config: &AppConfig, metrics: PrometheusHandle) -> Result<()> {
     trace!("Agent handler");
+    // A stable identity across re-deployments, without configuring it
+    let config = &with_persisted_agent_id(config)?;
+    crate::logging::set_agent_id(&config.agent.id);
+    measurement_labels::set_limit(config.agent.measurement_labels_limit);
     info!("Agent ID: {}", config.agent.id);
+    // Probing parameters managed centrally on the gateway
+    let config = &with_gateway_caracat_configs(config).await?;
+
+    // Agent events, published to the events topic if configured
+    let (tx_events, rx_events) = channel(1000);
+    let events = EventLog::new(
+        config.agent.id.clone(),
+        config.kafka.events_topic.as_ref().map(|_| tx_events),
+        config.kafka.events_max_rate,
+    );
+
+    // Operating mode of the agent, driven by the control topic and routes if configured
+    let (mode_tx, mut mode_rx) = watch::channel(AgentMode::default());
+    let mode_tx = Arc::new(mode_tx);
+
+    // --- Metrics, status, health and control endpoints, on a single port ---
+    let state = AgentState::new(config.agent.id.clone(), config.caracat.len());
+    let server = Arc::new(
+        Server::new(
+            state.clone(),
+            metrics,
+            config.agent.http_auth_tokens.clone(),
+        )
+        .with_control(mode_tx.clone(), events.clone()),
+    );
+    let server_address = config.agent.metrics_address;
+    spawn(async move {
+        if let Err(e) = server.serve(server_address).await {
+            error!("HTTP server error: {}", e);
+        }
+    });
+
+    // Instances failed over to their backup, reported in the gateway health
+    let failover_state = FailoverState::default();
+    let emission_checks = EmissionChecks::default();
+
+    // Probes topics, as configured unless assigned by the gateway
+    let configured_topics = config.kafka.agent_in_topics(&config.agent.id);
+    let (topics_tx, mut topics_rx) = watch::channel(configured_topics.clone());
+    let topics_tx = Arc::new(topics_tx);
 
     // --- Gateway registration and health reporting ---
     if let Some(gateway) = &config.gateway {
+        crate::agent::gateway::init_client(gateway)?;
         if let (Some(gateway_url), Some(agent_key), Some(agent_secret)) =
             (&gateway.url, &gateway.agent_key, &gateway.agent_secret)
         {
@@ -99,6 +515,10 @@ pub async fn handle(config: &AppConfig) -> Result<()> {
                 agent_key.clone(),
                 agent_secret.clone(),
                 config.caracat.clone(),
+                failover_state.clone(),
+                emission_checks.clone(),
+                topics_tx.clone(),
+                HealthcheckSchedule::new(gateway)?,
             );
         }
     }
@@ -118,24 +538,108 @@ pub async fn handle(config: &AppConfig) -> Result<()> {
 
     // Channel for all replies from all ReceiveLoops to the single Kafka producer
     let (tx_async_reply_to_producer, rx_async_reply_for_producer): (
-        Sender<Reply>,
-        Receiver<Reply>,
+        Sender<CapturedReply>,
+        Receiver<CapturedReply>,
     ) = channel(100000);
 
+    // Whether the agent leads its warm standby pair, always when not in standby
+    let (leader_tx, mut leader_rx) = watch::channel(!config.agent.standby);
+    let leader_tx = Arc::new(leader_tx);
+
+    // Sampled replies failing the integrity check while quoting our prefixes
+    let (tx_spoofed_reply, rx_spoofed_reply): (Sender<CapturedReply>, Receiver<CapturedReply>) =
+        channel(1000);
+
+    // Tags of sent probes, looked up by the producer to tag the corresponding replies
+    let correlation = CorrelationTable::shared(DEFAULT_CORRELATION_CAPACITY);
+
     let mut probe_senders_map: HashMap<String, Sender<ProbesWithSource>> = HashMap::new();
     let mut default_probe_sender_channel: Option<Sender<ProbesWithSource>> = None;
+    // Progress of the measurements of every instance, used to cancel them
+    let mut progresses: Vec<SharedMeasurementProgress> = Vec::new();
+
+    // Probes for each SendLoop, created upfront so that instances can fail over to a backup
+    let (instance_channels, instance_receivers): (
+        Vec<Sender<ProbesWithSource>>,
+        Vec<Receiver<ProbesWithSource>>,
+    ) = config.caracat.iter().map(|_| channel(100)).unzip();
+    // Probes batches queued to each instance, and replies queued to the producer
+    spawn(queue_depth_loop(
+        config
+            .caracat
+            .iter()
+            .zip(&instance_channels)
+            .map(|(caracat_cfg, tx)| (instance_label(caracat_cfg), tx.downgrade()))
+            .collect(),
+        tx_async_reply_to_producer.downgrade(),
+    ));
+
+    if config.agent.statistics_interval > 0 {
+        spawn(statistics::log_loop(std::time::Duration::from_secs(
+            config.agent.statistics_interval,
+        )));
+    }
+
+    // Probing rate cap shared by the SendLoops of all the instances
+    let total_rate = config.agent.max_total_pps.map(SharedRateLimiter::new);
+    let dst_rate = config
+        .agent
+        .max_dst_prefix_pps
+        .map(DestinationRateLimiter::new);
+    // Do-not-probe prefixes, enforced by the SendLoops of all the instances
+    let blocklist = Arc::new(match &config.agent.blocklist_file {
+        Some(path) => PrefixSet::load(path)?,
+        None => PrefixSet::default(),
+    });
+    if !blocklist.is_empty() {
+        info!("Blocking the probes towards {} prefixes", blocklist.len());
+    }
+    // Probes per measurement, counted across the SendLoops of all the instances
+    let measurement_quota = config
+        .agent
+        .max_probes_per_measurement
+        .map(MeasurementQuota::new);
+
+    // Stops the SendLoops and ReceiveLoops once the agent stops, including when the future of
+    // the agent is dropped (e.g. by a service embedding it)
+    let shutdown = CancellationToken::new();
+    let _shutdown = shutdown.clone().drop_guard();
 
     // --- Setup SendLoops (one per CaracatConfig) ---
-    for caracat_cfg in &config.caracat {
+    for ((caracat_cfg, tx_probe_to_sender), rx_probes_for_sender) in config
+        .caracat
+        .iter()
+        .zip(instance_channels.iter().cloned())
+        .zip(instance_receivers)
+    {
         debug!(
                 "Initializing SendLoop for Caracat instance: interface: {}, src_ipv4_prefix: {:?}, src_ipv6_prefix: {:?}, instance_id: {}",
                 caracat_cfg.interface, caracat_cfg.src_ipv4_prefix, caracat_cfg.src_ipv6_prefix, caracat_cfg.instance_id
             );
 
-        let (tx_probe_to_sender, rx_probes_for_sender): (
-            Sender<ProbesWithSource>,
-            Receiver<ProbesWithSource>,
-        ) = channel(100); // Probes for this specific SendLoop
+        // Backup instance, validated at startup
+        let failover = caracat_cfg
+            .backup_instance
+            .as_ref()
+            .and_then(|backup_name| {
+                let backup = config
+                    .caracat
+                    .iter()
+                    .position(|c| c.name.as_deref() == Some(backup_name.as_str()))?;
+                info!(
+                    "Instance {} fails over to instance {}",
+                    instance_label(caracat_cfg),
+                    backup_name
+                );
+                Some(Failover {
+                    instance: instance_label(caracat_cfg),
+                    backup: backup_name.clone(),
+                    backup_sender: instance_channels[backup].clone(),
+                    state: failover_state.clone(),
+                    threshold: caracat_cfg.failover_threshold,
+                    cooldown: std::time::Duration::from_secs(caracat_cfg.failover_cooldown),
+                })
+            });
 
         if default_probe_sender_channel.is_none() {
             default_probe_sender_channel = Some(tx_probe_to_sender.clone());
@@ -168,12 +672,100 @@ pub async fn handle(config: &AppConfig) -> Result<()> {
             }
         }
 
-        let _send_loop = SendLoop::new(
+        // Sample of the probes sent, verified on the wire
+        let emission = EmissionChecker::new(&config.agent.id, caracat_cfg, emission_checks.clone());
+        if let Some(ref emission) = emission {
+            emission
+                .clone()
+                .spawn_capture(caracat_cfg.interface.clone());
+        }
+
+        // Probes recently sent by the workers of this instance, to drop the repeated ones
+        let dedup = config.agent.dedup_window.map(ProbeDedup::new);
+        // Probes sent per measurement, shared by the workers of this instance
+        let progress: SharedMeasurementProgress =
+            Arc::new(Mutex::new(MeasurementProgress::with_events(events.clone())));
+        progresses.push(progress.clone());
+        // Messages drained by priority, and in weighted round-robin with the fair scheduling
+        // (validated at startup): they are queued by the scheduler, rather than in order in the
+        // SendLoops channels, so that high-priority messages are sent after the current ones
+        let measurement_queues =
+            MeasurementQueues::for_instance(caracat_cfg).with_context(|| {
+                format!(
+                    "Invalid scheduling of caracat instance {}",
+                    instance_label(caracat_cfg)
+                )
+            })?;
+        let (tx_scheduled, rx_scheduled) = channel(1);
+        current_tokio_handle.spawn(schedule_loop(
             rx_probes_for_sender,
-            caracat_cfg.clone(),
-            config,
-            current_tokio_handle.clone(),
-        );
+            tx_scheduled,
+            measurement_queues,
+        ));
+        let rx_probes_for_sender = rx_scheduled;
+        let sender_threads = caracat_cfg.sender_threads.max(1) as usize;
+        if sender_threads == 1 {
+            let _send_loop = SendLoop::new(
+                rx_probes_for_sender,
+                caracat_cfg.clone(),
+                config,
+                correlation.clone(),
+                progress,
+                mode_rx.clone(),
+                0,
+                failover,
+                emission,
+                total_rate.clone(),
+                dst_rate.clone(),
+                blocklist.clone(),
+                measurement_quota.clone(),
+                dedup.clone(),
+                state.register_loop(LoopKind::Send),
+                shutdown.child_token(),
+            )?;
+        } else {
+            // Shard probes by destination across workers, each probing at a fraction of the rate
+            let mut worker_cfg = caracat_cfg.clone();
+            worker_cfg.probing_rate = caracat_cfg
+                .probing_rate
+                .div_ceil(sender_threads as u64)
+                .max(1);
+            for limit in &mut worker_cfg.source_rate_limits {
+                limit.probing_rate = limit.probing_rate.div_ceil(sender_threads as u64).max(1);
+            }
+            worker_cfg.fairness_max_pps = caracat_cfg
+                .fairness_max_pps
+                .map(|rate| rate.div_ceil(sender_threads as u64).max(1));
+
+            let mut worker_senders = Vec::with_capacity(sender_threads);
+            for worker in 0..sender_threads {
+                let (tx_worker, rx_worker) = channel(1);
+                let _send_loop = SendLoop::new(
+                    rx_worker,
+                    worker_cfg.clone(),
+                    config,
+                    correlation.clone(),
+                    progress.clone(),
+                    mode_rx.clone(),
+                    worker,
+                    failover.clone(),
+                    emission.clone(),
+                    total_rate.clone(),
+                    dst_rate.clone(),
+                    blocklist.clone(),
+                    measurement_quota.clone(),
+                    dedup.clone(),
+                    state.register_loop(LoopKind::Send),
+                    shutdown.child_token(),
+                )?;
+                worker_senders.push(tx_worker);
+            }
+            current_tokio_handle.spawn(shard_loop(rx_probes_for_sender, worker_senders));
+            debug!(
+                "Sharding probes across {} sender workers for instance ID: {}",
+                sender_threads, caracat_cfg.instance_id
+            );
+        }
         debug!(
             "Caracat SendLoop instance started for interface {} (Instance ID: {})",
             caracat_cfg.interface, caracat_cfg.instance_id
@@ -200,29 +792,76 @@ pub async fn handle(config: &AppConfig) -> Result<()> {
         }
         // All configs_for_interface share the same interface_name.
         // We need to pass all relevant instance IDs for this physical interface.
-        let instance_ids_for_interface: Vec<u16> = configs_for_interface
-            .iter()
-            .map(|cfg| cfg.instance_id)
-            .collect();
+        let mut instance_ids_for_interface: Vec<u16> = Vec::new();
+        for cfg in &configs_for_interface {
+            instance_ids_for_interface.extend(crate::agent::integrity::accepted_ids(
+                cfg,
+                &config.agent.id,
+                config.agent.integrity_key.as_deref(),
+            )?);
+        }
 
         // The ReceiveLoop will use the first config for basic settings like integrity_check,
         // but it needs all instance_ids for demultiplexing.
         // Or, you might define a "shared" config for the receiver if some params differ.
         // For simplicity, let's assume the first config's integrity_check flag is representative.
-        let representative_cfg = configs_for_interface[0].clone(); // Used for general receiver settings
+        let mut representative_cfg = configs_for_interface[0].clone(); // Used for general receiver settings
+
+        // The replies captured on the interface are dumped by the first instance setting `pcap_dump`
+        if let Some(dump_cfg) = configs_for_interface
+            .iter()
+            .find(|cfg| cfg.pcap_dump.is_some())
+        {
+            representative_cfg.pcap_dump = dump_cfg.pcap_dump.clone();
+            representative_cfg.pcap_dump_max_bytes = dump_cfg.pcap_dump_max_bytes;
+            representative_cfg.pcap_dump_max_files = dump_cfg.pcap_dump_max_files;
+        }
 
         info!(
             "Initializing ReceiveLoop for physical interface: {} (Associated Instance IDs: {:?})",
             interface_name, instance_ids_for_interface
         );
 
-        let _receive_loop = ReceiveLoop::new(
-            tx_async_reply_to_producer.clone(), // All receivers send to the same producer channel
-            config.agent.id.clone(),
-            representative_cfg,         // Use the first config for basic settings
-            instance_ids_for_interface, // Pass all valid instance IDs for this interface
-            current_tokio_handle.clone(),
-        );
+        let spoof_detector =
+            SpoofDetector::new(&configs_for_interface, config.kafka.spoof_sample_every)?;
+
+        // Restarted with backoff when it fails (e.g. its interface disappeared), the agent
+        // being not ready meanwhile
+        let health = LoopHealth::new(state.clone(), format!("receive:{}", interface_name))
+            .with_events(events.clone());
+        let cancel = shutdown.child_token();
+        let start_receive_loop = {
+            let tx = tx_async_reply_to_producer.clone(); // All receivers send to the same producer channel
+            let agent_id = config.agent.id.clone();
+            let spoof_tx = config
+                .kafka
+                .spoof_topic
+                .as_ref()
+                .map(|_| tx_spoofed_reply.clone());
+            let state = state.clone();
+            let cancel = cancel.clone();
+            let runtime_handle = current_tokio_handle.clone();
+            move || {
+                ReceiveLoop::new(
+                    tx.clone(),
+                    agent_id.clone(),
+                    representative_cfg.clone(), // Use the first config for basic settings
+                    instance_ids_for_interface.clone(), // Pass all valid instance IDs for this interface
+                    spoof_detector.clone(),
+                    spoof_tx.clone(),
+                    state.register_loop(LoopKind::Receive),
+                    cancel.clone(),
+                    runtime_handle.clone(),
+                )
+                .join()
+            }
+        };
+        spawn(supervise(
+            health,
+            cancel,
+            Backoff::default(),
+            start_receive_loop,
+        ));
         debug!(
             "Caracat ReceiveLoop started for physical interface {}",
             interface_name
@@ -244,35 +883,399 @@ pub async fn handle(config: &AppConfig) -> Result<()> {
         }
     };
 
+    // Replies are serialized once, then dispatched to Kafka and to the other sinks
+    let mut reply_sinks = Vec::new();
+    if let Some(s3_config) = &config.s3 {
+        info!(
+            "S3 reply sink enabled. Uploading replies to bucket: {}",
+            s3_config.bucket
+        );
+        let (tx, rx) = channel(100000);
+        spawn(s3::upload_loop(
+            s3_config.clone(),
+            config.agent.id.clone(),
+            rx,
+        ));
+        reply_sinks.push(("s3", tx));
+    }
+    if let Some(gateway) = config.gateway.as_ref().filter(|g| g.upload_replies) {
+        let (Some(gateway_url), Some(agent_key)) = (&gateway.url, &gateway.agent_key) else {
+            anyhow::bail!("gateway.upload_replies requires gateway.url and gateway.agent_key");
+        };
+        info!(
+            "Gateway reply sink enabled. Uploading replies to: {}",
+            gateway_url
+        );
+        let (tx, rx) = channel(100000);
+        spawn(upload::upload_loop(
+            gateway_url.clone(),
+            config.agent.id.clone(),
+            agent_key.clone(),
+            gateway.clone(),
+            rx,
+        ));
+        reply_sinks.push(("gateway", tx));
+    }
+
     if config.kafka.out_enable {
         info!("Kafka producer enabled. Spawning async producer task.");
+        let (tx_replies_to_kafka, rx_replies_for_kafka) = channel(100000);
         let producer_config = config.clone();
         let producer_auth_clone = kafka_auth.clone();
         spawn(async move {
             producer::produce(
                 &producer_config,
                 producer_auth_clone,
-                rx_async_reply_for_producer, // Single receiver for all replies
+                rx_replies_for_kafka, // Single receiver for all replies
             )
             .await
         });
+        spawn(producer::dispatch_replies(
+            config.agent.id.clone(),
+            rx_async_reply_for_producer,
+            correlation,
+            Some(tx_replies_to_kafka),
+            ReplyFormat::parse(&config.kafka.out_format)?,
+            KeyStrategy::parse(&config.kafka.key_strategy)?,
+            reply_sinks,
+        ));
         debug!("Async Kafka producer task spawned.");
+
+        if let Some(events_topic) = config.kafka.events_topic.clone() {
+            info!("Publishing agent events to topic: {}", events_topic);
+            let events_producer_config = config.clone();
+            let events_producer_auth = kafka_auth.clone();
+            spawn(async move {
+                producer::produce_events(
+                    &events_producer_config,
+                    events_producer_auth,
+                    events_topic,
+                    rx_events,
+                )
+                .await
+            });
+        }
+
+        if let Some(spoof_topic) = config.kafka.spoof_topic.clone() {
+            info!(
+                "Publishing sampled spoofed replies to topic: {}",
+                spoof_topic
+            );
+            let spoof_producer_config = config.clone();
+            let spoof_producer_auth = kafka_auth.clone();
+            spawn(async move {
+                producer::produce_spoofed(
+                    &spoof_producer_config,
+                    spoof_producer_auth,
+                    spoof_topic,
+                    rx_spoofed_reply,
+                )
+                .await
+            });
+        }
+    } else if !reply_sinks.is_empty() {
+        info!(
+            "Kafka producer disabled. Caracat replies will only be uploaded to: {}",
+            reply_sinks
+                .iter()
+                .map(|(sink, _)| *sink)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        spawn(producer::dispatch_replies(
+            config.agent.id.clone(),
+            rx_async_reply_for_producer,
+            correlation,
+            None,
+            ReplyFormat::default(),
+            KeyStrategy::default(),
+            reply_sinks,
+        ));
     } else {
         info!("Kafka producer disabled. Caracat replies will be ignored.");
         drop(rx_async_reply_for_producer);
         drop(tx_async_reply_to_producer);
     }
 
-    let consumer: StreamConsumer<rdkafka::consumer::DefaultConsumerContext> =
-        init_consumer(config, kafka_auth).await;
+    if let Some(control_topic) = &config.kafka.control_topic {
+        let control_consumer =
+            init_control_consumer(config, kafka_auth.clone(), control_topic).await;
+        spawn(control_loop(
+            control_consumer,
+            config.agent.id.clone(),
+            mode_tx.clone(),
+            events.clone(),
+        ));
+    }
+
+    // Measurements cancelled by the gateway
+    let (tx_cancellations, mut rx_cancellations) = channel::<String>(100);
+    if let Some(gateway) = config.gateway.as_ref().filter(|g| g.remote_commands) {
+        if let (Some(gateway_url), Some(agent_key)) = (&gateway.url, &gateway.agent_key) {
+            info!("Receiving the commands of the gateway");
+            spawn(commands_loop(
+                gateway_url.clone(),
+                config.agent.id.clone(),
+                agent_key.clone(),
+                std::time::Duration::from_secs(gateway.commands_wait.max(1)),
+                HealthcheckSchedule::new(gateway)?,
+                CommandTargets {
+                    mode: mode_tx.clone(),
+                    events: events.clone(),
+                    total_rate: total_rate.clone(),
+                    cancellations: tx_cancellations,
+                },
+            ));
+        } else {
+            warn!("gateway.remote_commands requires gateway.url and gateway.agent_key");
+        }
+    }
+
+    // -- Agent capabilities, also used to detect agents running with the same ID --
+    if let Some(agents_topic) = config.kafka.agents_topic.clone() {
+        let run_id = uuid::Uuid::new_v4().simple().to_string();
+        let started_at_ns = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64;
+        info!(
+            "Publishing the agent capabilities to topic: {} (run {})",
+            agents_topic, run_id
+        );
+        let agents_consumer =
+            init_agents_consumer(config, kafka_auth.clone(), &agents_topic, &run_id).await;
+        if config.agent.standby {
+            // Agents sharing the agent ID are expected, they elect the one consuming probes
+            let lease = std::time::Duration::from_secs(
+                config.kafka.agents_heartbeat_interval.max(1) * LEASE_HEARTBEATS as u64,
+            );
+            info!(
+                "Warm standby: electing the leader among the agents {} (lease {}s)",
+                config.agent.id,
+                lease.as_secs()
+            );
+            spawn(standby_loop(
+                agents_consumer,
+                Election::new(&config.agent.id, &run_id, started_at_ns, lease),
+                leader_tx.clone(),
+                state.clone(),
+                events.clone(),
+            ));
+        } else {
+            spawn(duplicate_loop(
+                agents_consumer,
+                config.agent.id.clone(),
+                run_id.clone(),
+                started_at_ns,
+                state.clone(),
+                events.clone(),
+                config.agent.refuse_duplicate_id.then(|| mode_tx.clone()),
+            ));
+        }
+
+        let capabilities_config = config.clone();
+        let capabilities_auth = kafka_auth.clone();
+        spawn(async move {
+            producer::publish_capabilities(
+                &capabilities_config,
+                capabilities_auth,
+                &agents_topic,
+                &run_id,
+                started_at_ns,
+            )
+            .await
+        });
+    }
+
+    let announce_startup = || {
+        state.set_ready(true);
+        events.emit(
+            EventKind::Startup,
+            None,
+            BTreeMap::from([
+                ("version".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+                (
+                    "caracat_instances".to_string(),
+                    config.caracat.len().to_string(),
+                ),
+            ]),
+        );
+    };
+
+    // -- Pull model: the measurements are polled from the gateway instead of Kafka --
+    if let Some(gateway) = config.gateway.as_ref().filter(|g| g.poll_measurements) {
+        let (Some(gateway_url), Some(agent_key)) = (&gateway.url, &gateway.agent_key) else {
+            anyhow::bail!("gateway.poll_measurements requires gateway.url and gateway.agent_key");
+        };
+        info!(
+            "Polling the gateway for assigned measurements every {}s",
+            gateway.poll_interval.max(1)
+        );
+        announce_startup();
+        poll_loop(
+            gateway_url.clone(),
+            config.agent.id.clone(),
+            agent_key.clone(),
+            std::time::Duration::from_secs(gateway.poll_interval.max(1)),
+            probe_senders_map,
+            config.caracat.clone(),
+            mode_rx,
+        )
+        .await;
+        return Ok(());
+    }
+
+    // Shared with the blocking tasks fetching the watermarks
+    let consumer: Arc<StreamConsumer<rdkafka::consumer::DefaultConsumerContext>> =
+        Arc::new(init_consumer(config, kafka_auth, *leader_rx.borrow()).await);
     info!(
         "Kafka consumer initialized. Listening for probes on topics: {}",
-        config.kafka.in_topics
+        config.kafka.agent_in_topics(&config.agent.id).join(",")
     );
+    let commit_strategy = CommitStrategy::parse(&config.kafka.commit_strategy)?;
+    info!("Commit strategy: {:?}", commit_strategy);
+    let (mut committer, mut rx_sent_messages) = Committer::new(commit_strategy);
+    // With a topic per agent, every message is intended for this agent
+    let mut has_own_topic = config.kafka.in_topic_template.is_some();
+    let signature_verifier = SignatureVerifier::new(&config.agent.signing_keys)?;
+    if signature_verifier.is_enabled() {
+        info!("Probes messages must be signed by a client key");
+    }
+    let schema_registry = config
+        .kafka
+        .schema_registry
+        .clone()
+        .map(SchemaRegistry::new);
+    let mut audit_log = config
+        .agent
+        .audit_log_dir
+        .as_deref()
+        .map(|dir| {
+            AuditLog::open(
+                dir,
+                config.agent.audit_log_max_bytes,
+                config.agent.audit_log_max_files,
+            )
+        })
+        .transpose()?;
+    announce_startup();
+    let mut backpressure = Backpressure::new(
+        config.kafka.pause_watermark(),
+        config.kafka.resume_watermark(),
+    )
+    .with_events(events.clone());
+    let mut backpressure_check = tokio::time::interval(backpressure::CHECK_INTERVAL);
+    backpressure_check.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut lag_check = tokio::time::interval(LAG_INTERVAL);
+    lag_check.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // Topics paused while topics of a higher priority have probes to consume
+    let priorities = TopicPriorities::new(&config.kafka.in_topic_priorities);
+    let mut deferred_topics: HashSet<String> = HashSet::new();
+    // Watches left once their sender is dropped (the control, gateway or standby loop stopped)
+    let (mut watch_mode, mut watch_topics, mut watch_leader) = (true, true, true);
+    let mut priority_check = tokio::time::interval(PRIORITY_INTERVAL);
+    priority_check.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
     // -- Start the main loop --
     loop {
-        let message = match consumer.recv().await {
+        // Probes are left in Kafka while the sender queues are full
+        if let Some(paused) = backpressure.update(queue_fill(probe_senders_map.values())) {
+            if paused {
+                warn!("Sender queues full, pausing the consumption of the probes");
+            } else {
+                info!("Sender queues drained, resuming the consumption of the probes");
+            }
+            gauge!("saimiris_consumer_backpressure").set(if paused { 1.0 } else { 0.0 });
+            pause_partitions(
+                &consumer,
+                paused || !mode_rx.borrow().consumes_probes(),
+                &deferred_topics,
+            );
+        }
+        // Probes are left in Kafka while the agent is paused or draining,
+        // keeping the consumer group offsets, and while it is standby
+        let consumes_probes =
+            mode_rx.borrow().consumes_probes() && *leader_rx.borrow() && !backpressure.is_paused();
+        let message = tokio::select! {
+            message = consumer.recv(), if consumes_probes => message,
+            _ = backpressure_check.tick(), if backpressure.is_paused() => continue,
+            _ = lag_check.tick() => {
+                report_consumer_lag(&fetch_consumer_lag(&consumer).await);
+                continue;
+            }
+            _ = priority_check.tick(), if priorities.is_some() => {
+                let Some(ref priorities) = priorities else {
+                    continue;
+                };
+                let lags = fetch_consumer_lag(&consumer).await;
+                let assignment = consumer.assignment().unwrap_or_default();
+                let deferred = priorities.deferred_topics(
+                    assignment.elements().iter().map(|element| element.topic()),
+                    &lags,
+                );
+                if deferred != deferred_topics {
+                    debug!("Deferred probes topics: {:?}", deferred);
+                    deferred_topics = deferred;
+                    pause_partitions(
+                        &consumer,
+                        !mode_rx.borrow().consumes_probes() || backpressure.is_paused(),
+                        &deferred_topics,
+                    );
+                }
+                continue;
+            }
+            Some(measurement_id) = rx_cancellations.recv() => {
+                cancel_measurement(config, &progresses, &events, measurement_id);
+                continue;
+            }
+            Some(sent) = rx_sent_messages.recv() => {
+                commit_offset(&consumer, committer.sent(sent));
+                continue;
+            }
+            changed = mode_rx.changed(), if watch_mode => {
+                if changed.is_err() {
+                    // The control loop has stopped, the mode no longer changes
+                    watch_mode = false;
+                    continue;
+                }
+                let mode = *mode_rx.borrow_and_update();
+                info!("Agent mode: {:?}", mode);
+                pause_partitions(
+                    &consumer,
+                    !mode.consumes_probes() || backpressure.is_paused(),
+                    &deferred_topics,
+                );
+                continue;
+            }
+            changed = topics_rx.changed(), if watch_topics => {
+                if changed.is_err() {
+                    watch_topics = false;
+                    continue;
+                }
+                let topics = topics_rx.borrow_and_update().clone();
+                // Topics assigned by the gateway may be shared with other agents
+                has_own_topic =
+                    config.kafka.in_topic_template.is_some() && topics == configured_topics;
+                deferred_topics.clear();
+                // A standby subscribes to the assigned topics once elected leader
+                if *leader_rx.borrow() {
+                    subscribe_probes(&consumer, &topics);
+                }
+                continue;
+            }
+            changed = leader_rx.changed(), if watch_leader => {
+                if changed.is_err() {
+                    watch_leader = false;
+                    continue;
+                }
+                // The standby leaves the consumer group, so that the leader gets every partition
+                if *leader_rx.borrow_and_update() {
+                    subscribe_probes(&consumer, &topics_rx.borrow());
+                } else {
+                    info!("Unsubscribing from the probes topics");
+                    consumer.unsubscribe();
+                }
+                continue;
+            }
+        };
+        let message = match message {
             Ok(m) => m,
             Err(e) => {
                 error!("Kafka consumer error: {}. Retrying in 5s...", e);
@@ -281,13 +1284,70 @@ pub async fn handle(config: &AppConfig) -> Result<()> {
             }
         };
 
+        // Decision on the probes of the message, recorded in the audit log
+        let mut audit = AuditEntry {
+            signature_key_id: header_value(&message, SIGNATURE_KEY_ID_HEADER),
+            ..AuditEntry::new(message_offset(&message))
+        };
+
+        // Messages intended for this agent must be signed by a known client
+        // (identified by its signing key in the metrics)
+        let mut client_id: Option<String> = None;
+        if signature_verifier.is_enabled() {
+            let headers: Vec<(&str, Option<&[u8]>)> = message
+                .headers()
+                .map(|headers| {
+                    headers
+                        .iter()
+                        .map(|header| (header.key, header.value))
+                        .collect()
+                })
+                .unwrap_or_default();
+            let is_intended_for_this_agent =
+                has_own_topic || headers.iter().any(|(key, _)| *key == config.agent.id);
+            if is_intended_for_this_agent {
+                match signature_verifier.verify(message.payload().unwrap_or_default(), headers) {
+                    Ok(key_id) => client_id = Some(key_id),
+                    Err(e) => {
+                        warn!(
+                            "Rejected message {}:{}:{}: {}",
+                            message.topic(),
+                            message.partition(),
+                            message.offset(),
+                            e
+                        );
+                        counter!(
+                            "saimiris_probes_messages_rejected_total",
+                            "agent" => config.agent.id.clone(),
+                            "reason" => e.reason()
+                        )
+                        .increment(1);
+                        record_audit(&mut audit_log, &audit.rejected(e.reason()));
+                        commit_offset(&consumer, committer.processed(message_offset(&message)));
+                        continue;
+                    }
+                }
+            }
+        }
+
+        // Cancellation requests carry no probes
+        let cancellation = message.headers().and_then(|headers| {
+            parse_cancellation(
+                headers.iter().map(|header| (header.key, header.value)),
+                &config.agent.id,
+            )
+        });
+        if let Some(measurement_id) = cancellation {
+            cancel_measurement(config, &progresses, &events, measurement_id);
+            commit_offset(&consumer, committer.processed(message_offset(&message)));
+            continue;
+        }
+
         let payload_bytes = match message.payload() {
             Some(bytes) => bytes,
             None => {
                 warn!("Received message with empty payload. Ignored.");
-                if let Err(e) = consumer.commit_message(&message, CommitMode::Async) {
-                    error!("Failed to commit empty message: {}", e);
-                }
+                commit_offset(&consumer, committer.processed(message_offset(&message)));
                 continue;
             }
         };
@@ -296,23 +1356,66 @@ pub async fn handle(config: &AppConfig) -> Result<()> {
             payload_bytes.len()
         );
 
-        let mut is_intended_for_this_agent = false;
+        let mut is_intended_for_this_agent = has_own_topic;
         let mut sender_ip_from_header: Option<String> = None;
-        let mut measurement_info: Option<crate::agent::gateway::MeasurementInfo> = None;
+        let mut instance_from_header: Option<String> = None;
+        let mut measurement_info: Option<MeasurementInfo> = None;
+        let mut expand_ttl: Option<Result<TtlRange>> = None;
+        let mut src_ports: Option<Result<PortPolicy>> = None;
+        let mut trace_id: Option<String> = None;
+        let mut priority: u32 = 0;
 
         if let Some(headers) = message.headers() {
             debug!("Message has {} headers", headers.count());
+            measurement_info = parse_measurement_info(
+                headers.iter().map(|header| (header.key, header.value)),
+                message.timestamp().to_millis(),
+            );
+            if let Some(info) = &measurement_info {
+                debug!(
+                    "Extracted measurement info: measurement_id={}, end_of_measurement={}",
+                    info.measurement_id, info.end_of_measurement
+                );
+            }
             for header in headers.iter() {
                 debug!(
                     "Header: key='{}', value_len={}",
                     header.key,
                     header.value.map(|v| v.len()).unwrap_or(0)
                 );
-                if header.key == config.agent.id {
+                if header.key == TRACEPARENT_HEADER {
+                    trace_id = header
+                        .value
+                        .and_then(|value| std::str::from_utf8(value).ok())
+                        .and_then(trace_id_from_traceparent);
+                } else if header.key == EXPAND_TTL_HEADER {
+                    expand_ttl = Some(
+                        std::str::from_utf8(header.value.unwrap_or_default())
+                            .map_err(anyhow::Error::from)
+                            .and_then(str::parse),
+                    );
+                } else if header.key == SRC_PORTS_HEADER {
+                    src_ports = Some(
+                        std::str::from_utf8(header.value.unwrap_or_default())
+                            .map_err(anyhow::Error::from)
+                            .and_then(str::parse),
+                    );
+                } else if header.key == PRIORITY_HEADER {
+                    match std::str::from_utf8(header.value.unwrap_or_default())
+                        .map_err(anyhow::Error::from)
+                        .and_then(|value| Ok(value.trim().parse::<u32>()?))
+                    {
+                        Ok(value) => priority = value,
+                        Err(e) => warn!(
+                            "Invalid {} header: {}. Default priority used.",
+                            PRIORITY_HEADER, e
+                        ),
+                    }
+                } else if header.key == config.agent.id {
                     debug!("Found header for agent ID: {}", config.agent.id);
                     is_intended_for_this_agent = true;
                     if let Some(value_bytes) = header.value {
-                        // Parse the JSON header value to extract measurement info
+                        // Parse the JSON header value to extract the source of the probes
                         if let Ok(header_str) = String::from_utf8(value_bytes.to_vec()) {
                             if let Ok(agent_info) =
                                 serde_json::from_str::<serde_json::Value>(&header_str)
@@ -324,23 +1427,11 @@ pub async fn handle(config: &AppConfig) -> Result<()> {
                                     .map(|s| s.to_string());
                                 debug!("Extracted src_ip: {:?}", sender_ip_from_header);
 
-                                // Extract measurement tracking information
-                                if let (Some(measurement_id), Some(end_of_measurement)) = (
-                                    agent_info.get("measurement_id").and_then(|v| v.as_str()),
-                                    agent_info
-                                        .get("end_of_measurement")
-                                        .and_then(|v| v.as_bool()),
-                                ) {
-                                    measurement_info =
-                                        Some(crate::agent::gateway::MeasurementInfo {
-                                            measurement_id: measurement_id.to_string(),
-                                            end_of_measurement,
-                                        });
-                                    debug!(
-                                        "Extracted measurement info: measurement_id={}, end_of_measurement={}",
-                                        measurement_id, end_of_measurement
-                                    );
-                                }
+                                // Extract the caracat instance the probes are pinned to
+                                instance_from_header = agent_info
+                                    .get("instance")
+                                    .and_then(|v| v.as_str())
+                                    .map(|s| s.to_string());
                             }
                         }
                     }
@@ -349,106 +1440,244 @@ pub async fn handle(config: &AppConfig) -> Result<()> {
         } else {
             debug!("Message has no headers");
         }
+        audit.client_id = client_id.clone();
+        audit.source_ip = sender_ip_from_header.clone();
+        if let Some(info) = measurement_info.as_mut() {
+            info.trace_id = trace_id;
+            info.client_id = client_id;
+            audit.measurement_id = Some(info.measurement_id.clone());
+        }
 
         if !is_intended_for_this_agent && !config.caracat.is_empty() {
             debug!(
                 "Message not intended for this agent (ID: {}). Ignored.",
                 config.agent.id
             );
-            if let Err(e) = consumer.commit_message(&message, CommitMode::Async) {
-                warn!("Failed to commit ignored message (not intended): {}", e);
-            }
+            commit_offset(&consumer, committer.processed(message_offset(&message)));
             continue;
         }
 
         info!("Message intended for this agent. Processing probes.");
 
-        let probes_to_send = match deserialize_probes(payload_bytes.to_vec()) {
-            Ok(probes) if probes.is_empty() => {
-                debug!("No probes to send after deserialization (empty list). Ignored.");
-                if let Err(e) = consumer.commit_message(&message, CommitMode::Async) {
-                    warn!("Failed to commit ignored message (empty probes): {}", e);
-                }
+        let expand_ttl = match expand_ttl.transpose() {
+            Ok(expand_ttl) => expand_ttl,
+            Err(e) => {
+                error!(
+                    "Invalid {} header: {}. Message ignored.",
+                    EXPAND_TTL_HEADER, e
+                );
+                record_audit(&mut audit_log, &audit.rejected("invalid_expand_ttl"));
+                commit_offset(&consumer, committer.processed(message_offset(&message)));
                 continue;
             }
-            Ok(probes) => {
-                trace!("{} probes deserialized successfully.", probes.len());
-                probes
-            }
+        };
+        let src_ports = match src_ports.transpose() {
+            Ok(src_ports) => src_ports.or(config.agent.src_ports),
             Err(e) => {
                 error!(
-                    "Failed to deserialize probes from Kafka message: {:?}. Message ignored.",
-                    e
+                    "Invalid {} header: {}. Message ignored.",
+                    SRC_PORTS_HEADER, e
                 );
-                if let Err(e) = consumer.commit_message(&message, CommitMode::Async) {
+                record_audit(&mut audit_log, &audit.rejected("invalid_src_ports"));
+                commit_offset(&consumer, committer.processed(message_offset(&message)));
+                continue;
+            }
+        };
+
+        // Probes are framed with the ID of a schema registered for the topic
+        let payload_bytes = match &schema_registry {
+            Some(registry) => match registry.validate(message.topic(), payload_bytes).await {
+                Ok(Ok(payload)) => payload,
+                Ok(Err(e)) => {
                     warn!(
-                        "Failed to commit ignored message (deserialization error): {}",
+                        "Rejected message {}:{}:{}: {}",
+                        message.topic(),
+                        message.partition(),
+                        message.offset(),
                         e
                     );
+                    counter!(
+                        "saimiris_probes_messages_rejected_total",
+                        "agent" => config.agent.id.clone(),
+                        "reason" => e.reason()
+                    )
+                    .increment(1);
+                    record_audit(&mut audit_log, &audit.rejected(e.reason()));
+                    commit_offset(&consumer, committer.processed(message_offset(&message)));
+                    continue;
                 }
-                continue;
-            }
+                Err(e) => {
+                    // Probes are not dropped while the registry is unavailable
+                    warn!("Schema of the message not validated: {:#}", e);
+                    unframe(payload_bytes)
+                        .map(|(_, payload)| payload)
+                        .unwrap_or(payload_bytes)
+                }
+            },
+            None => payload_bytes,
         };
 
-        let target_sender_result = determine_target_sender(
-            &probe_senders_map,
-            &config.caracat,
-            sender_ip_from_header.as_ref(),
-        );
+        let (mut probes_to_send, mut tags): (Vec<_>, Vec<_>) =
+            match deserialize_tagged_probes(payload_bytes.to_vec()) {
+                Ok(probes) if probes.is_empty() => {
+                    debug!("No probes to send after deserialization (empty list). Ignored.");
+                    commit_offset(&consumer, committer.processed(message_offset(&message)));
+                    continue;
+                }
+                Ok(probes) => {
+                    trace!("{} probes deserialized successfully.", probes.len());
+                    probes.into_iter().unzip()
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to deserialize probes from Kafka message: {:?}. Message ignored.",
+                        e
+                    );
+                    record_audit(&mut audit_log, &audit.rejected("invalid_probes"));
+                    commit_offset(&consumer, committer.processed(message_offset(&message)));
+                    continue;
+                }
+            };
 
-        match target_sender_result {
-            Ok((Some(sender_channel), use_source_ip_flag)) => {
-                debug!(
-                    "Distributing {} probes to selected Caracat sender.",
-                    probes_to_send.len()
-                );
+        // Targets are expanded into one probe per TTL
+        if let Some(range) = expand_ttl {
+            let targets = probes_to_send.len();
+            (probes_to_send, tags) = expand_targets(probes_to_send, tags, range);
+            debug!(
+                "Expanded {} targets into {} probes (TTL {})",
+                targets,
+                probes_to_send.len(),
+                range
+            );
+        }
 
-                let probes_count = probes_to_send.len();
-                // Create ProbesWithSource, use source IP from header only if use_source_ip_flag is true
-                let probes_with_source = if use_source_ip_flag {
-                    ProbesWithSource {
-                        probes: probes_to_send,
-                        source_ip: sender_ip_from_header.unwrap().clone(),
-                        measurement_info: measurement_info.clone(),
-                    }
-                } else {
-                    // Use empty string to indicate no specific source IP (default behavior)
-                    ProbesWithSource {
-                        probes: probes_to_send,
-                        source_ip: String::new(),
-                        measurement_info: measurement_info.clone(),
-                    }
-                };
+        // Source ports are selected per flow, the TTL is not part of it
+        if let Some(policy) = src_ports {
+            apply_port_policy(&mut probes_to_send, policy);
+            debug!(
+                "Source ports of {} probes set to {}",
+                probes_to_send.len(),
+                policy
+            );
+        }
 
-                trace!("Attempting to send {} probes to selected sender instance via async channel", probes_count);
-                match sender_channel.try_send(probes_with_source) {
-                    Ok(()) => {
-                        trace!("Probes successfully queued for the selected sender instance via async send.");
-                    }
-                    Err(send_err) => {
-                        error!("Failed to send probes to selected Caracat sender (async channel error): {}. SendLoop may have exited.", send_err);
-                    }
+        // Probes with their own source address or instance may be sent by another caracat instance
+        let probes_count = probes_to_send.len();
+        let mut batches = Vec::new();
+        for ProbeGroup {
+            source_ip,
+            instance,
+            probes,
+            tags,
+        } in group_probes(
+            probes_to_send,
+            tags,
+            sender_ip_from_header.as_ref(),
+            instance_from_header.as_ref(),
+        ) {
+            let target_sender_result = match &instance {
+                Some(instance) => determine_pinned_sender(
+                    &probe_senders_map,
+                    &config.caracat,
+                    instance,
+                    source_ip.as_ref(),
+                ),
+                None => {
+                    determine_target_sender(&probe_senders_map, &config.caracat, source_ip.as_ref())
+                }
+            };
+            match target_sender_result {
+                Ok((Some(sender_channel), use_source_ip_flag)) => {
+                    // Use empty string to indicate no specific source IP (default behavior)
+                    let source_ip = source_ip.filter(|_| use_source_ip_flag).unwrap_or_default();
+                    batches.push((sender_channel, source_ip, probes, tags));
+                }
+                Ok((None, _)) => {
+                    error!("No suitable sender found for the provided source IP");
+                }
+                Err(e) => {
+                    error!("Failed to select a caracat instance: {}", e);
+                    warn!(
+                        "{} probes not sent due to validation error (source IP: {:?}, instance: {:?}): {}",
+                        probes.len(),
+                        source_ip,
+                        instance,
+                        e
+                    );
                 }
             }
-            Ok((None, _)) => {
-                error!("No suitable sender found for the provided source IP");
-            }
-            Err(e) => {
-                error!(
-                    "Failed to validate source IP against configured prefixes: {}",
-                    e
-                );
-                if !probes_to_send.is_empty() {
+        }
+
+        if batches.is_empty() {
+            audit.probes = Some(probes_count);
+            record_audit(&mut audit_log, &audit.rejected("no_instance"));
+        } else {
+            audit.probes = Some(batches.iter().map(|(_, _, probes, _)| probes.len()).sum());
+            record_audit(&mut audit_log, &audit);
+        }
+
+        // With the after-send strategy, the message is committed once its probes are sent
+        let ack = if batches.is_empty() {
+            None
+        } else {
+            committer.probes_ack(message_offset(&message))
+        };
+        let awaiting_send = ack.is_some();
+        // The end of the measurement is only reported by the last batch of each sender
+        let is_last: Vec<bool> = (0..batches.len())
+            .map(|i| {
+                !batches[i + 1..]
+                    .iter()
+                    .any(|(other, ..)| other.same_channel(&batches[i].0))
+            })
+            .collect();
+        for ((sender_channel, source_ip, probes, tags), is_last) in batches.into_iter().zip(is_last)
+        {
+            debug!(
+                "Distributing {} probes to selected Caracat sender (source IP: {:?}).",
+                probes.len(),
+                source_ip
+            );
+            let probes_with_source = ProbesWithSource {
+                probes,
+                tags,
+                source_ip,
+                measurement_info: measurement_info.clone().map(|info| MeasurementInfo {
+                    end_of_measurement: info.end_of_measurement && is_last,
+                    ..info
+                }),
+                priority,
+                ack: ack.clone(),
+            };
+            let probes_len = probes_with_source.probes.len();
+            match enqueue(&sender_channel, probes_with_source).await {
+                Ok(0) => {
+                    trace!("Probes successfully queued for the selected sender instance via async send.");
+                }
+                Ok(retries) => {
+                    counter!("saimiris_handler_enqueue_retries_total").increment(retries as u64);
                     warn!(
-                        "Probes not sent due to validation error (source IP: {:?}): {}",
-                        sender_ip_from_header, e
+                        "Sender queue full: {} probes queued after {} retries",
+                        probes_len, retries
+                    );
+                }
+                Err(_) => {
+                    // Committing a later message would also commit this one, the agent
+                    // stops so that its probes are consumed again
+                    let offset = message_offset(&message);
+                    anyhow::bail!(
+                        "Failed to queue {} probes of {}[{}]@{}: the SendLoop has exited",
+                        probes_len,
+                        offset.topic,
+                        offset.partition,
+                        offset.offset
                     );
                 }
             }
         }
 
-        if let Err(e) = consumer.commit_message(&message, CommitMode::Async) {
-            error!("Failed to commit processed message: {}", e);
+        if !awaiting_send {
+            commit_offset(&consumer, committer.processed(message_offset(&message)));
         }
     }
 }
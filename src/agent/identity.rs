@@ -0,0 +1,46 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use tracing::info;
+
+/// Characters of the generated agent IDs.
+const ID_ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+/// Length of the generated agent IDs.
+pub const ID_LEN: usize = 10;
+
+/// Random agent ID, of `ID_LEN` lowercase alphanumeric characters (e.g. `wbmwwp9vna`).
+pub fn generate_agent_id() -> String {
+    let mut id = String::with_capacity(ID_LEN);
+    while id.len() < ID_LEN {
+        // Bytes past the largest multiple of the alphabet size are skipped, to avoid a bias
+        let limit = (u8::MAX as usize + 1) / ID_ALPHABET.len() * ID_ALPHABET.len();
+        for byte in uuid::Uuid::new_v4().into_bytes() {
+            if (byte as usize) < limit && id.len() < ID_LEN {
+                id.push(ID_ALPHABET[byte as usize % ID_ALPHABET.len()] as char);
+            }
+        }
+    }
+    id
+}
+
+/// Agent ID persisted in `id_file`, generated and written there on the first run, so that the
+/// agent keeps its identity across re-deployments.
+pub fn persisted_agent_id(id_file: &Path) -> Result<String> {
+    match std::fs::read_to_string(id_file) {
+        Ok(id) if !id.trim().is_empty() => return Ok(id.trim().to_string()),
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read agent ID {}", id_file.display()))
+        }
+    }
+
+    let id = generate_agent_id();
+    if let Some(parent) = id_file.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    std::fs::write(id_file, format!("{}\n", id))
+        .with_context(|| format!("Failed to write agent ID {}", id_file.display()))?;
+    info!("Generated agent ID {}, saved to {}", id, id_file.display());
+    Ok(id)
+}
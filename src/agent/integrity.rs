@@ -0,0 +1,91 @@
+use anyhow::Result;
+use ring::hmac;
+use std::net::IpAddr;
+
+use crate::agent::correlation::normalize_ip_addr;
+use crate::config::CaracatConfig;
+
+const INTEGRITY_ENCODING_INSTANCE_ID: &str = "instance_id";
+const INTEGRITY_ENCODING_HMAC: &str = "hmac";
+
+/// How the 16-bit ID that caracat encodes in probes (and checks in replies) is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrityEncoding {
+    /// Use the configured instance ID as is
+    InstanceId,
+    /// Derive the ID from an HMAC of the agent and instance IDs, keyed by the agent integrity key,
+    /// so that it cannot be guessed from the (public) instance ID. The ID is the same for all
+    /// the probes of the instance: it only obscures the instance ID, and is learnt from any
+    /// probe or reply
+    Hmac,
+}
+
+impl IntegrityEncoding {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            INTEGRITY_ENCODING_INSTANCE_ID => Ok(IntegrityEncoding::InstanceId),
+            INTEGRITY_ENCODING_HMAC => Ok(IntegrityEncoding::Hmac),
+            other => Err(anyhow::anyhow!(
+                "Unknown integrity_encoding '{}'. Expected '{}' or '{}'",
+                other,
+                INTEGRITY_ENCODING_INSTANCE_ID,
+                INTEGRITY_ENCODING_HMAC
+            )),
+        }
+    }
+}
+
+/// 16-bit ID of the instance with the HMAC encoding, fixed per agent and instance.
+fn hmac_id(key: &str, agent_id: &str, instance_id: u16) -> u16 {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+    let mut message = agent_id.as_bytes().to_vec();
+    message.extend_from_slice(&instance_id.to_be_bytes());
+    let tag = hmac::sign(&key, &message);
+    u16::from_be_bytes([tag.as_ref()[0], tag.as_ref()[1]])
+}
+
+/// ID encoded in the probes sent by this instance.
+pub fn encoding_id(config: &CaracatConfig, agent_id: &str, key: Option<&str>) -> Result<u16> {
+    match IntegrityEncoding::parse(&config.integrity_encoding)? {
+        IntegrityEncoding::InstanceId => Ok(config.instance_id),
+        IntegrityEncoding::Hmac => match key {
+            Some(key) => Ok(hmac_id(key, agent_id, config.instance_id)),
+            None => Err(anyhow::anyhow!(
+                "integrity_encoding 'hmac' requires agent.integrity_key to be set"
+            )),
+        },
+    }
+}
+
+/// IDs accepted in replies for this instance.
+/// With the HMAC encoding, the plain instance ID can still be accepted while transitioning.
+pub fn accepted_ids(config: &CaracatConfig, agent_id: &str, key: Option<&str>) -> Result<Vec<u16>> {
+    let id = encoding_id(config, agent_id, key)?;
+    let mut ids = vec![id];
+    if config.integrity_accept_instance_id && id != config.instance_id {
+        ids.push(config.instance_id);
+    }
+    Ok(ids)
+}
+
+/// Name of the address family of `addr`, in the metrics.
+pub fn family_label(addr: IpAddr) -> &'static str {
+    match normalize_ip_addr(addr) {
+        IpAddr::V4(_) => "ipv4",
+        IpAddr::V6(_) => "ipv6",
+    }
+}
+
+/// Override of `integrity_check` for the address family of `addr`, if any.
+pub fn family_override(config: &CaracatConfig, addr: IpAddr) -> Option<bool> {
+    match normalize_ip_addr(addr) {
+        IpAddr::V4(_) => config.integrity_check_ipv4,
+        IpAddr::V6(_) => config.integrity_check_ipv6,
+    }
+}
+
+/// Whether the replies from `addr` are checked, as some middleboxes mangle the fields
+/// used by the check in one address family only.
+pub fn is_checked(config: &CaracatConfig, addr: IpAddr) -> bool {
+    family_override(config, addr).unwrap_or(config.integrity_check)
+}
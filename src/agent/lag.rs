@@ -0,0 +1,104 @@
+use metrics::gauge;
+use rdkafka::consumer::{Consumer, ConsumerContext};
+use rdkafka::Offset;
+use std::time::Duration;
+use tokio::sync::mpsc::{Sender, WeakSender};
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::debug;
+
+use crate::agent::sender::ProbesWithSource;
+use crate::reply::CapturedReply;
+
+/// Interval at which the consumer lag is reported.
+pub const LAG_INTERVAL: Duration = Duration::from_secs(10);
+/// Interval at which the depth of the queues is reported.
+pub const QUEUE_DEPTH_INTERVAL: Duration = Duration::from_secs(1);
+const WATERMARKS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Messages of a partition not consumed yet, from the position of the consumer (the next
+/// offset to consume) to the high watermark. Unknown until the consumer has a position.
+pub fn partition_lag(position: Offset, high_watermark: i64) -> Option<i64> {
+    match position {
+        Offset::Offset(offset) => Some((high_watermark - offset).max(0)),
+        _ => None,
+    }
+}
+
+/// Number of items in the queue of a channel.
+pub fn queue_depth<T>(sender: &Sender<T>) -> usize {
+    sender.max_capacity() - sender.capacity()
+}
+
+/// Lag of a partition assigned to the consumer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionLag {
+    pub topic: String,
+    pub partition: i32,
+    pub lag: i64,
+}
+
+/// Lag of the partitions assigned to the consumer, for those with a position. The high
+/// watermarks are fetched from the brokers, so this blocks for up to a second per partition.
+pub fn consumer_lag<C: ConsumerContext, T: Consumer<C>>(consumer: &T) -> Vec<PartitionLag> {
+    let positions = match consumer.position() {
+        Ok(positions) => positions,
+        Err(e) => {
+            debug!("Failed to get the consumer position: {}", e);
+            return Vec::new();
+        }
+    };
+    let mut lags = Vec::new();
+    for element in positions.elements() {
+        let (topic, partition) = (element.topic(), element.partition());
+        let high_watermark = match consumer.fetch_watermarks(topic, partition, WATERMARKS_TIMEOUT) {
+            Ok((_, high)) => high,
+            Err(e) => {
+                debug!(
+                    "Failed to fetch the watermarks of {}[{}]: {}",
+                    topic, partition, e
+                );
+                continue;
+            }
+        };
+        if let Some(lag) = partition_lag(element.offset(), high_watermark) {
+            lags.push(PartitionLag {
+                topic: topic.to_string(),
+                partition,
+                lag,
+            });
+        }
+    }
+    lags
+}
+
+/// Report the lag of the partitions assigned to the consumer, in `saimiris_consumer_lag`.
+pub fn report_consumer_lag(lags: &[PartitionLag]) {
+    for lag in lags {
+        gauge!("saimiris_consumer_lag", "topic" => lag.topic.clone(), "partition" => lag.partition.to_string())
+            .set(lag.lag as f64);
+    }
+}
+
+/// Report the probes batches queued to each sender (by instance), and the replies queued to
+/// the producer. The channels are not kept open by the reports.
+pub async fn queue_depth_loop(
+    senders: Vec<(String, WeakSender<ProbesWithSource>)>,
+    replies: WeakSender<CapturedReply>,
+) {
+    let mut report = interval(QUEUE_DEPTH_INTERVAL);
+    report.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        report.tick().await;
+        for (instance, sender) in &senders {
+            if let Some(sender) = sender.upgrade() {
+                gauge!("saimiris_sender_queue_depth", "instance" => instance.clone())
+                    .set(queue_depth(&sender) as f64);
+            }
+        }
+        match replies.upgrade() {
+            Some(replies) => gauge!("saimiris_reply_queue_depth").set(queue_depth(&replies) as f64),
+            // The agent is shutting down
+            None => return,
+        }
+    }
+}
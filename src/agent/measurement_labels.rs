@@ -0,0 +1,73 @@
+use metrics::Label;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+/// Distinct measurements labelled by default, before the others are grouped.
+pub const DEFAULT_MEASUREMENT_LABELS_LIMIT: usize = 100;
+/// Label value of the measurements past the limit, and of their clients.
+pub const OTHER_LABEL: &str = "other";
+/// Label value of the probes and replies of no measurement, or of no known client.
+pub const NONE_LABEL: &str = "none";
+
+/// Bounded-cardinality guard of the `measurement_id` and `client_id` labels of the counters.
+///
+/// The first `limit` distinct measurements get their own labels, the next ones are all
+/// counted under `other`, so that the number of series exposed stays bounded whatever the
+/// number of measurements over the lifetime of the agent. No labels are added if `limit` is 0.
+#[derive(Debug)]
+pub struct MeasurementLabels {
+    limit: usize,
+    // Client of each labelled measurement, if known
+    clients: Mutex<HashMap<String, Option<String>>>,
+}
+
+impl MeasurementLabels {
+    pub fn new(limit: usize) -> Self {
+        MeasurementLabels {
+            limit,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `measurement_id` and `client_id` labels of the probes or replies of a measurement.
+    /// The client is remembered with the measurement, for the replies that only know the latter.
+    pub fn labels(&self, measurement_id: Option<&str>, client_id: Option<&str>) -> Vec<Label> {
+        if self.limit == 0 {
+            return Vec::new();
+        }
+        let Some(measurement_id) = measurement_id else {
+            return Self::to_labels(NONE_LABEL, NONE_LABEL);
+        };
+
+        let mut clients = self.clients.lock().unwrap();
+        if !clients.contains_key(measurement_id) && clients.len() >= self.limit {
+            return Self::to_labels(OTHER_LABEL, OTHER_LABEL);
+        }
+        let client = clients.entry(measurement_id.to_string()).or_default();
+        if client.is_none() {
+            *client = client_id.map(str::to_string);
+        }
+        Self::to_labels(measurement_id, client.as_deref().unwrap_or(NONE_LABEL))
+    }
+
+    fn to_labels(measurement_id: &str, client_id: &str) -> Vec<Label> {
+        vec![
+            Label::new("measurement_id", measurement_id.to_string()),
+            Label::new("client_id", client_id.to_string()),
+        ]
+    }
+}
+
+static MEASUREMENT_LABELS: OnceLock<MeasurementLabels> = OnceLock::new();
+
+/// Set the limit of distinct measurements labelled, once at startup.
+pub fn set_limit(limit: usize) {
+    let _ = MEASUREMENT_LABELS.set(MeasurementLabels::new(limit));
+}
+
+/// `measurement_id` and `client_id` labels, shared by the sender and the producer.
+pub fn labels(measurement_id: Option<&str>, client_id: Option<&str>) -> Vec<Label> {
+    MEASUREMENT_LABELS
+        .get_or_init(|| MeasurementLabels::new(DEFAULT_MEASUREMENT_LABELS_LIMIT))
+        .labels(measurement_id, client_id)
+}
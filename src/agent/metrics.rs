@@ -0,0 +1,211 @@
+use anyhow::{Context, Result};
+use metrics::{describe_counter, describe_gauge, describe_histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+// Buckets of the measurement duration histograms, in seconds, so that their exemplars are exposed
+const MEASUREMENT_BUCKETS: [f64; 14] = [
+    1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0, 7200.0, 14400.0, 43200.0,
+    86400.0,
+];
+
+/// Install the Prometheus metrics recorder of the agent, with the descriptions of its metrics.
+/// Fails if a metrics recorder is already installed in the process.
+pub fn install_recorder() -> Result<PrometheusHandle> {
+    // Metrics are served by the agent HTTP server
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Prefix("saimiris_measurement_".to_string()),
+            &MEASUREMENT_BUCKETS,
+        )
+        .context("Invalid measurement histogram buckets")?
+        .install_recorder()
+        .context("Failed to install Prometheus metrics recorder")?;
+
+    // Producer metrics
+    describe_counter!(
+        "saimiris_kafka_messages_total",
+        "Total number of Kafka messages produced"
+    );
+
+    describe_counter!(
+        "saimiris_handler_enqueue_retries_total",
+        "Total number of retries to queue probes to a sender whose queue was full"
+    );
+    describe_counter!(
+        "saimiris_replies_dropped_total",
+        "Total number of replies dropped because the queue of a reply sink (kafka, s3, gateway) was full"
+    );
+    describe_counter!(
+        "saimiris_replies_dispatched_total",
+        "Total number of replies dispatched to the reply sinks, by measurement and client"
+    );
+    describe_counter!(
+        "saimiris_s3_objects_total",
+        "Total number of reply objects uploaded to S3, by status (success, failure, dropped)"
+    );
+    describe_counter!(
+        "saimiris_gateway_chunks_total",
+        "Total number of reply chunks uploaded to the gateway, by status (success, failure, dropped)"
+    );
+
+    describe_counter!(
+        "saimiris_events_dropped_total",
+        "Total number of agent events dropped by the rate limit or a full publishing queue"
+    );
+
+    describe_counter!(
+        "saimiris_probes_messages_rejected_total",
+        "Total number of probes messages rejected by the signature verification, by reason (missing, unknown_key, invalid)"
+    );
+
+    describe_counter!(
+        "saimiris_audit_log_errors_total",
+        "Total number of audit log entries that could not be written"
+    );
+
+    // Receiver Metrics
+    describe_counter!(
+        "saimiris_pcap_dump_errors_total",
+        "Total number of failed rotations or flushes of the pcap dumps of the captured replies"
+    );
+    describe_counter!(
+        "saimiris_receiver_received_valid_total",
+        "Total number of valid replies received from the caracat receiver thread"
+    );
+    describe_counter!(
+        "saimiris_receiver_received_invalid_total",
+        "Total number of invalid replies received that failed the integrity check, per address family"
+    );
+    describe_counter!(
+        "saimiris_receiver_unchecked_invalid_total",
+        "Total number of invalid replies accepted because the integrity check is disabled for their address family"
+    );
+    describe_counter!(
+        "saimiris_receiver_filtered_total",
+        "Total number of valid replies dropped by the reply filter"
+    );
+    describe_counter!(
+        "saimiris_receiver_spoofed_total",
+        "Total number of invalid replies quoting a probe sent from our prefixes (spoofed or reflected)"
+    );
+    describe_counter!(
+        "saimiris_receiver_spoofed_dropped_total",
+        "Total number of sampled spoofed replies dropped because the publishing queue was full"
+    );
+
+    // Sender Metrics
+    describe_counter!(
+        "saimiris_sender_read_total",
+        "Total number of probes read from the sender thread"
+    );
+    describe_counter!(
+        "saimiris_sender_probes_sent_total",
+        "Total number of probes sent by the sender thread"
+    );
+    describe_counter!(
+        "saimiris_sender_failed_total",
+        "Total number of errors encountered by the sender thread while sending probes"
+    );
+    describe_counter!(
+        "saimiris_sender_dst_rate_limited_total",
+        "Total number of packets delayed by the sender thread to stay within the probing rate of their destination prefix"
+    );
+    describe_counter!(
+        "saimiris_sender_network_rate_limited_total",
+        "Total number of packets delayed by the sender thread to stay within the fairness_max_pps of their destination network"
+    );
+    describe_counter!(
+        "saimiris_sender_filtered_total",
+        "Total number of probes filtered by the sender thread against the probing policy (TTL, destination, protocol, DSCP)"
+    );
+    describe_counter!(
+        "saimiris_sender_blocked_total",
+        "Total number of probes dropped by the sender thread because their destination is in the agent blocklist"
+    );
+    describe_counter!(
+        "saimiris_sender_over_limit_total",
+        "Total number of probes dropped by the sender thread because their measurement exceeded the maximum number of probes of the agent"
+    );
+    describe_counter!(
+        "saimiris_sender_duplicate_total",
+        "Total number of probes dropped by the sender thread because they were already sent in their measurement"
+    );
+    describe_counter!(
+        "saimiris_sender_cancelled_total",
+        "Total number of probes dropped by the sender thread because their measurement was cancelled"
+    );
+    describe_counter!(
+        "saimiris_sender_failover_forwarded_total",
+        "Total number of probes forwarded by the sender thread to the backup instance of its caracat instance"
+    );
+    describe_gauge!(
+        "saimiris_consumer_lag",
+        "Number of probes messages not consumed yet, by topic and partition"
+    );
+    describe_gauge!(
+        "saimiris_sender_queue_depth",
+        "Number of probes batches queued to the sender of each caracat instance"
+    );
+    describe_gauge!(
+        "saimiris_reply_queue_depth",
+        "Number of replies queued to the producer"
+    );
+    describe_gauge!(
+        "saimiris_consumer_backpressure",
+        "Whether the consumption of the probes is paused because the sender queues are full (1) or not (0)"
+    );
+    describe_gauge!(
+        "saimiris_agent_duplicate_id",
+        "Set to 1 when another agent runs with the same agent ID"
+    );
+    describe_gauge!(
+        "saimiris_agent_leader",
+        "Set to 1 when the agent is the leader of its warm standby pair, 0 when standby"
+    );
+    describe_gauge!(
+        "saimiris_sender_pps",
+        "Packets per second achieved by the sender thread"
+    );
+    describe_gauge!(
+        "saimiris_receiver_pcap_received",
+        "Number of packets received by the capture of the interface since it was opened"
+    );
+    describe_gauge!(
+        "saimiris_receiver_pcap_dropped",
+        "Number of packets dropped by the capture of the interface because its buffer was full"
+    );
+    describe_gauge!(
+        "saimiris_receiver_pcap_if_dropped",
+        "Number of packets dropped by the interface or its driver"
+    );
+    describe_histogram!(
+        "saimiris_measurement_send_duration_seconds",
+        "Wall-clock duration from the first to the last probe sent of each measurement"
+    );
+    describe_histogram!(
+        "saimiris_measurement_completion_latency_seconds",
+        "Delay from the client submission of each measurement to its completion by the agent"
+    );
+    describe_counter!(
+        "saimiris_sender_emission_checks_total",
+        "Total number of sampled probes checked on the wire, by result (verified, missing, or the mismatching field)"
+    );
+    describe_gauge!(
+        "saimiris_sender_emission_verified",
+        "Whether the last sampled probe of the instance was captured as sent (1) or not (0)"
+    );
+    describe_gauge!(
+        "saimiris_sender_failover",
+        "Set to 1 while a caracat instance is failed over to its backup instance"
+    );
+    describe_gauge!(
+        "saimiris_loop_failed",
+        "Set to 1 while a send or receive loop is failed, until it is restarted and healthy again"
+    );
+    describe_counter!(
+        "saimiris_loop_restarts_total",
+        "Total number of restarts of the send and receive loops after a failure"
+    );
+
+    Ok(handle)
+}
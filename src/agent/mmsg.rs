@@ -0,0 +1,291 @@
+#[cfg(target_os = "linux")]
+pub use linux::{bind_packet_socket, open_packet_socket, MmsgSender, PacketBuilder};
+
+/// Probes handed to the kernel by a single `sendmmsg`, its maximum vector length (UIO_MAXIOV).
+/// Larger bursts take several system calls.
+pub const MMSG_BATCH_MAX: usize = 1024;
+/// Size of the packet buffer, the largest probe (TTL 255) taking less than 400 bytes.
+pub const PACKET_BUFFER_SIZE: usize = 2048;
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use anyhow::{Context, Result};
+    use caracat::builder::{
+        build_ethernet, build_icmp, build_icmpv6, build_ipv4, build_ipv6, build_udp, Packet,
+    };
+    use caracat::models::{Probe, L2, L4};
+    use caracat::neighbors::{resolve_mac_address, RoutingTable};
+    use caracat::timestamp::{encode, tenth_ms};
+    use caracat::utilities::{get_ipv4_address, get_ipv6_address, get_mac_address};
+    use pnet_base::MacAddr;
+    use std::ffi::CString;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use tracing::info;
+
+    use super::{MMSG_BATCH_MAX, PACKET_BUFFER_SIZE};
+    use crate::agent::tx_ring::set_dscp;
+
+    /// Builds the packets of the probes as caracat's `Sender`, from the addresses of the
+    /// interface resolved likewise.
+    pub struct PacketBuilder {
+        buffer: Vec<u8>,
+        instance_id: u16,
+        l2_protocol: L2,
+        src_mac: MacAddr,
+        dst_mac_v4: MacAddr,
+        dst_mac_v6: MacAddr,
+        src_ip_v4: Ipv4Addr,
+        src_ip_v6: Ipv6Addr,
+    }
+
+    impl PacketBuilder {
+        /// Same parameters as caracat's `Sender::new`.
+        pub fn new(
+            interface: &str,
+            ipv4_src_addr: Option<Ipv4Addr>,
+            ipv6_src_addr: Option<Ipv6Addr>,
+            instance_id: u16,
+        ) -> Result<Self> {
+            let l2_protocol = match pcap::Capture::from_device(interface)?
+                .open()?
+                .get_datalink()
+            {
+                pcap::Linktype::ETHERNET => L2::Ethernet,
+                pcap::Linktype(12) => L2::None,
+                other => anyhow::bail!("Unsupported link type for a packet socket: {}", other.0),
+            };
+            let (src_mac, dst_mac_v4, dst_mac_v6) = if l2_protocol == L2::Ethernet {
+                let src_mac =
+                    get_mac_address(interface).context("Ethernet device has no MAC address")?;
+                let table = RoutingTable::from_native()?;
+                let gateway_mac = |route: Option<&caracat::neighbors::Route>| {
+                    route
+                        .and_then(|r| resolve_mac_address(interface, r.gateway).ok())
+                        .unwrap_or(MacAddr::zero())
+                };
+                (
+                    src_mac,
+                    gateway_mac(table.default_route_v4()),
+                    gateway_mac(table.default_route_v6()),
+                )
+            } else {
+                (MacAddr::zero(), MacAddr::zero(), MacAddr::zero())
+            };
+            Ok(PacketBuilder {
+                buffer: vec![0; PACKET_BUFFER_SIZE],
+                instance_id,
+                l2_protocol,
+                src_mac,
+                dst_mac_v4,
+                dst_mac_v6,
+                src_ip_v4: ipv4_src_addr
+                    .unwrap_or(get_ipv4_address(interface).unwrap_or(Ipv4Addr::UNSPECIFIED)),
+                src_ip_v6: ipv6_src_addr
+                    .unwrap_or(get_ipv6_address(interface).unwrap_or(Ipv6Addr::UNSPECIFIED)),
+            })
+        }
+
+        pub fn src_ip_v4(&self) -> Ipv4Addr {
+            self.src_ip_v4
+        }
+
+        pub fn src_ip_v6(&self) -> Ipv6Addr {
+            self.src_ip_v6
+        }
+
+        /// Build the packet of the probe, as caracat's `Sender::send` but marked with `dscp`,
+        /// and hand it over to `write`.
+        pub fn build<R>(&mut self, probe: &Probe, dscp: u8, write: impl FnOnce(&[u8]) -> R) -> R {
+            let timestamp = tenth_ms(SystemTime::now().duration_since(UNIX_EPOCH).unwrap());
+            let timestamp_enc = encode(timestamp);
+            let payload_size = probe.ttl as usize + 2;
+            let mut packet = Packet::new(
+                &mut self.buffer,
+                self.l2_protocol,
+                probe.l3_protocol(),
+                probe.l4_protocol(),
+                payload_size,
+            );
+            packet.l2_mut().fill(0);
+            if self.l2_protocol == L2::Ethernet {
+                match probe.dst_addr {
+                    IpAddr::V4(_) => build_ethernet(&mut packet, self.src_mac, self.dst_mac_v4),
+                    IpAddr::V6(_) => build_ethernet(&mut packet, self.src_mac, self.dst_mac_v6),
+                }
+            }
+            match probe.dst_addr {
+                IpAddr::V4(dst_addr) => build_ipv4(
+                    &mut packet,
+                    self.src_ip_v4,
+                    dst_addr,
+                    probe.ttl,
+                    probe.checksum(self.instance_id),
+                ),
+                IpAddr::V6(dst_addr) => {
+                    build_ipv6(&mut packet, self.src_ip_v6, dst_addr, probe.ttl)
+                }
+            }
+            match probe.l4_protocol() {
+                L4::ICMP => build_icmp(&mut packet, probe.src_port, timestamp_enc),
+                L4::ICMPv6 => build_icmpv6(&mut packet, probe.src_port, timestamp_enc),
+                L4::UDP => build_udp(&mut packet, timestamp_enc, probe.src_port, probe.dst_port),
+            }
+            // The traffic class is left out of the L4 checksums
+            if dscp != 0 {
+                set_dscp(packet.l3_mut(), dscp);
+            }
+            write(packet.l2())
+        }
+    }
+
+    fn check(result: libc::c_int, action: &str) -> Result<libc::c_int> {
+        if result < 0 {
+            return Err(std::io::Error::last_os_error()).context(action.to_string());
+        }
+        Ok(result)
+    }
+
+    /// Packet socket sending the frames as is. Protocol 0: the socket only sends.
+    pub fn open_packet_socket() -> Result<OwnedFd> {
+        let fd = check(
+            unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, 0) },
+            "Failed to open a packet socket",
+        )?;
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Bind a packet socket to `interface`, which its frames are sent on.
+    pub fn bind_packet_socket(fd: &OwnedFd, interface: &str) -> Result<()> {
+        let name = CString::new(interface)?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Unknown interface {}", interface));
+        }
+        let mut address: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        address.sll_family = libc::AF_PACKET as u16;
+        address.sll_ifindex = ifindex as libc::c_int;
+        check(
+            unsafe {
+                libc::bind(
+                    fd.as_raw_fd(),
+                    &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+                )
+            },
+            "Failed to bind the packet socket",
+        )
+        .map(|_| ())
+    }
+
+    /// Sends the probes in batches, with one `sendmmsg` system call per burst: the packets
+    /// built by caracat are queued by `send`, and handed to the kernel on `flush`.
+    pub struct MmsgSender {
+        fd: OwnedFd,
+        builder: PacketBuilder,
+        dry_run: bool,
+        // Packets queued since the last flush, back to back, and the end of each
+        packets: Vec<u8>,
+        ends: Vec<usize>,
+    }
+
+    impl MmsgSender {
+        /// Same parameters as caracat's `Sender::new`.
+        pub fn new(
+            interface: &str,
+            ipv4_src_addr: Option<Ipv4Addr>,
+            ipv6_src_addr: Option<Ipv6Addr>,
+            instance_id: u16,
+            dry_run: bool,
+        ) -> Result<Self> {
+            let builder = PacketBuilder::new(interface, ipv4_src_addr, ipv6_src_addr, instance_id)?;
+            let fd = open_packet_socket()?;
+            bind_packet_socket(&fd, interface)?;
+            info!(
+                "Batched sender on {}, src_ip_v4={} src_ip_v6={}",
+                interface,
+                builder.src_ip_v4(),
+                builder.src_ip_v6()
+            );
+            Ok(MmsgSender {
+                fd,
+                builder,
+                dry_run,
+                packets: Vec::with_capacity(MMSG_BATCH_MAX * 128),
+                ends: Vec::with_capacity(MMSG_BATCH_MAX),
+            })
+        }
+
+        /// Queue the packet of the probe, marked with `dscp`, until the next flush.
+        pub fn send(&mut self, probe: &Probe, dscp: u8) -> Result<()> {
+            let packets = &mut self.packets;
+            self.builder
+                .build(probe, dscp, |packet| packets.extend_from_slice(packet));
+            self.ends.push(self.packets.len());
+            Ok(())
+        }
+
+        /// Hand the packets queued since the last flush to the kernel.
+        pub fn flush(&mut self) -> Result<()> {
+            let result = self.send_queued();
+            self.packets.clear();
+            self.ends.clear();
+            result
+        }
+
+        fn send_queued(&mut self) -> Result<()> {
+            if self.dry_run || self.ends.is_empty() {
+                return Ok(());
+            }
+            let mut iovecs: Vec<libc::iovec> = Vec::with_capacity(self.ends.len());
+            let mut start = 0;
+            for &end in &self.ends {
+                iovecs.push(libc::iovec {
+                    iov_base: self.packets[start..end].as_ptr() as *mut libc::c_void,
+                    iov_len: end - start,
+                });
+                start = end;
+            }
+            // The socket is bound to the interface, the messages have no address
+            let mut messages: Vec<libc::mmsghdr> = iovecs
+                .iter_mut()
+                .map(|iovec| {
+                    let mut message: libc::mmsghdr = unsafe { std::mem::zeroed() };
+                    message.msg_hdr.msg_iov = iovec as *mut libc::iovec;
+                    message.msg_hdr.msg_iovlen = 1;
+                    message
+                })
+                .collect();
+
+            // The kernel may send fewer messages than requested
+            let mut sent = 0;
+            while sent < messages.len() {
+                let result = unsafe {
+                    libc::sendmmsg(
+                        self.fd.as_raw_fd(),
+                        messages[sent..].as_mut_ptr(),
+                        (messages.len() - sent).min(MMSG_BATCH_MAX) as libc::c_uint,
+                        0,
+                    )
+                };
+                if result < 0 {
+                    let error = std::io::Error::last_os_error();
+                    if error.kind() == std::io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return Err(error).with_context(|| {
+                        format!(
+                            "Failed to send {} of {} probes",
+                            messages.len() - sent,
+                            messages.len()
+                        )
+                    });
+                }
+                sent += result as usize;
+            }
+            Ok(())
+        }
+    }
+}
@@ -1,9 +1,189 @@
+// The internals of the agent are only public with the `internals` feature, for the
+// integration tests: the library exposes the re-exports below. Without it, the items only
+// used by the integration tests are unused.
+#![cfg_attr(not(feature = "internals"), allow(dead_code))]
+
+#[cfg(feature = "internals")]
+pub mod audit;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod audit;
+#[cfg(feature = "internals")]
+pub mod backpressure;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod backpressure;
+#[cfg(feature = "internals")]
+pub mod capabilities;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod capabilities;
+#[cfg(feature = "internals")]
+pub mod chaos;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod chaos;
+#[cfg(feature = "internals")]
+pub mod commit;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod commit;
 mod consumer;
+#[cfg(feature = "internals")]
+pub mod control;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod control;
+#[cfg(feature = "internals")]
+pub mod correlation;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod correlation;
+#[cfg(feature = "internals")]
+pub mod dedup;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod dedup;
+#[cfg(feature = "internals")]
+pub mod duplicate;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod duplicate;
+#[cfg(feature = "internals")]
+pub mod emission;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod emission;
+#[cfg(feature = "internals")]
+pub mod events;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod events;
+#[cfg(feature = "internals")]
+pub mod exemplars;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod exemplars;
+#[cfg(feature = "internals")]
+pub mod expand;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod expand;
+#[cfg(feature = "internals")]
+pub mod failover;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod failover;
+#[cfg(feature = "internals")]
+pub mod fairness;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod fairness;
+#[cfg(feature = "internals")]
 pub mod gateway;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod gateway;
+#[cfg(feature = "internals")]
 pub mod handler;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod handler;
+#[cfg(feature = "internals")]
+pub mod identity;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod identity;
+#[cfg(feature = "internals")]
+pub mod integrity;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod integrity;
+#[cfg(feature = "internals")]
+pub mod lag;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod lag;
+#[cfg(feature = "internals")]
+pub mod measurement_labels;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod measurement_labels;
+#[cfg(feature = "internals")]
+pub mod metrics;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod metrics;
+#[cfg(feature = "internals")]
+pub mod mmsg;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod mmsg;
+#[cfg(feature = "internals")]
+pub mod packet_ring;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod packet_ring;
+#[cfg(feature = "internals")]
+pub mod pcap_dump;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod pcap_dump;
+#[cfg(feature = "internals")]
+pub mod policy;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod policy;
+#[cfg(feature = "internals")]
+pub mod poll;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod poll;
+#[cfg(feature = "internals")]
+pub mod ports;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod ports;
+#[cfg(feature = "internals")]
+pub mod prefix_set;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod prefix_set;
+#[cfg(feature = "internals")]
+pub mod priority;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod priority;
 mod producer;
+#[cfg(feature = "internals")]
+pub mod quota;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod quota;
+#[cfg(feature = "internals")]
+pub mod ratelimit;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod ratelimit;
 mod receiver;
+#[cfg(feature = "internals")]
+pub mod reply_filter;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod reply_filter;
+#[cfg(feature = "internals")]
+pub mod s3;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod s3;
+#[cfg(feature = "internals")]
+pub mod scheduling;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod scheduling;
+#[cfg(feature = "internals")]
 pub mod sender;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod sender;
+#[cfg(feature = "internals")]
+pub mod server;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod server;
+#[cfg(feature = "internals")]
+pub mod spoof;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod spoof;
+#[cfg(feature = "internals")]
+pub mod standby;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod standby;
+#[cfg(feature = "internals")]
+pub mod statistics;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod statistics;
+#[cfg(feature = "internals")]
+pub mod supervisor;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod supervisor;
+#[cfg(feature = "internals")]
+pub mod tx_ring;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod tx_ring;
+#[cfg(feature = "internals")]
+pub mod upload;
+#[cfg(not(feature = "internals"))]
+pub(crate) mod upload;
 
 // Re-exports
-pub use handler::handle;
+pub use capabilities::{AgentCapabilities, Requirements};
+pub use expand::{expand_target, TtlRange};
+pub use gateway::{AgentHealth, GatewayAgentConfig, MeasurementStatusUpdate, RegisteredAgent};
+pub use handler::{handle, run};
+pub use metrics::install_recorder;
+pub use policy::check as check_policy;
+pub use ports::{PortPolicy, PortStrategy};
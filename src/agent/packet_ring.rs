@@ -0,0 +1,392 @@
+use anyhow::Result;
+use std::fmt;
+use std::time::Duration;
+
+use crate::config::CaracatConfig;
+
+#[cfg(target_os = "linux")]
+pub use linux::RingReceiver;
+
+/// Size of a block of the TPACKET_V3 ring, which holds `receiver_ring_blocks` blocks.
+pub const RING_BLOCK_SIZE: usize = 1 << 20;
+/// Delay after which `next_reply` returns `RingTimeout` if no reply was received, so that the
+/// receive loop can check whether it is stopped.
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Backend capturing the replies of a caracat instance (`receiver_backend`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReceiverBackend {
+    /// libpcap, as caracat
+    #[default]
+    Pcap,
+    /// A TPACKET_V3 ring memory-mapped with the kernel, copying the packets once, in blocks
+    TpacketV3,
+}
+
+impl ReceiverBackend {
+    pub fn new(config: &CaracatConfig) -> Result<Self> {
+        match config.receiver_backend.as_str() {
+            "" | "pcap" => Ok(ReceiverBackend::Pcap),
+            "tpacket_v3" if cfg!(target_os = "linux") => Ok(ReceiverBackend::TpacketV3),
+            "tpacket_v3" => {
+                anyhow::bail!("receiver_backend 'tpacket_v3' is only supported on Linux")
+            }
+            other => anyhow::bail!(
+                "Invalid receiver_backend '{}'. Expected 'pcap' or 'tpacket_v3'",
+                other
+            ),
+        }
+    }
+}
+
+/// No reply was received within the poll timeout.
+#[derive(Debug)]
+pub struct RingTimeout;
+
+impl fmt::Display for RingTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no reply received within {:?}", POLL_TIMEOUT)
+    }
+}
+
+impl std::error::Error for RingTimeout {}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use anyhow::{Context, Result};
+    use std::ffi::CString;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::sync::atomic::{fence, Ordering};
+    use std::time::Duration;
+
+    use super::{RingTimeout, POLL_TIMEOUT, RING_BLOCK_SIZE};
+    use crate::agent::pcap_dump::PcapDump;
+    use crate::agent::receiver::parse_reply;
+    use crate::reply::CapturedReply;
+
+    /// Delay after which a block is handed to the receiver even if it is not full.
+    const RING_BLOCK_TIMEOUT: Duration = Duration::from_millis(10);
+    /// Only the ICMP replies are copied to the ring.
+    const RING_FILTER: &str = "icmp or icmp6";
+
+    // From linux/if_packet.h
+    const SOL_PACKET: libc::c_int = 263;
+    const PACKET_RX_RING: libc::c_int = 5;
+    const PACKET_VERSION: libc::c_int = 10;
+    const TPACKET_V3: libc::c_int = 2;
+    const TP_STATUS_KERNEL: u32 = 0;
+    const TP_STATUS_USER: u32 = 1;
+    const PACKET_OUTGOING: u8 = 4;
+    const PACKET_STATISTICS: libc::c_int = 6;
+    // Offset of the `sockaddr_ll` following the header of a packet, TPACKET_ALIGN(sizeof(tpacket3_hdr))
+    const SOCKADDR_LL_OFFSET: usize = 48;
+
+    // struct tpacket_req3
+    #[repr(C)]
+    struct TpacketReq3 {
+        tp_block_size: u32,
+        tp_block_nr: u32,
+        tp_frame_size: u32,
+        tp_frame_nr: u32,
+        tp_retire_blk_tov: u32,
+        tp_sizeof_priv: u32,
+        tp_feature_req_word: u32,
+    }
+
+    // struct tpacket_block_desc, with its struct tpacket_hdr_v1 up to the first packet offset
+    #[repr(C)]
+    struct BlockDesc {
+        _version: u32,
+        _offset_to_priv: u32,
+        block_status: u32,
+        num_pkts: u32,
+        offset_to_first_pkt: u32,
+    }
+
+    // struct tpacket3_hdr, up to the MAC header offset
+    #[repr(C)]
+    struct Tpacket3Hdr {
+        tp_next_offset: u32,
+        tp_sec: u32,
+        tp_nsec: u32,
+        tp_snaplen: u32,
+        tp_len: u32,
+        _tp_status: u32,
+        tp_mac: u16,
+    }
+
+    /// Receives the replies of an interface from a TPACKET_V3 ring: the kernel fills blocks of
+    /// packets, handed to the receiver at once, without a copy or a system call per packet.
+    pub struct RingReceiver {
+        fd: OwnedFd,
+        ring: *mut u8,
+        blocks: usize,
+        // Block being read, and the offset and number of its packets left to read
+        block: usize,
+        cursor: Option<(usize, u32)>,
+        // Packets received and dropped since the socket was opened, the kernel counters being
+        // reset on each read
+        received: u32,
+        dropped: u32,
+    }
+
+    // The ring is only accessed by the receive loop thread owning the receiver
+    unsafe impl Send for RingReceiver {}
+
+    fn check(result: libc::c_int, action: &str) -> Result<libc::c_int> {
+        if result < 0 {
+            return Err(std::io::Error::last_os_error()).context(action.to_string());
+        }
+        Ok(result)
+    }
+
+    fn setsockopt<T>(fd: &OwnedFd, option: libc::c_int, value: &T, action: &str) -> Result<()> {
+        let result = unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                SOL_PACKET,
+                option,
+                value as *const T as *const libc::c_void,
+                std::mem::size_of::<T>() as libc::socklen_t,
+            )
+        };
+        check(result, action).map(|_| ())
+    }
+
+    /// Attach the `RING_FILTER` BPF program to the socket, compiled by libpcap.
+    fn attach_filter(fd: &OwnedFd) -> Result<()> {
+        let program = pcap::Capture::dead(pcap::Linktype::ETHERNET)?.compile(RING_FILTER, true)?;
+        let instructions = program.get_instructions();
+        // struct sock_fprog, struct bpf_insn having the layout of struct sock_filter
+        let fprog = libc::sock_fprog {
+            len: instructions.len() as libc::c_ushort,
+            filter: instructions.as_ptr() as *mut libc::sock_filter,
+        };
+        let result = unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_ATTACH_FILTER,
+                &fprog as *const libc::sock_fprog as *const libc::c_void,
+                std::mem::size_of::<libc::sock_fprog>() as libc::socklen_t,
+            )
+        };
+        check(result, "Failed to attach the reply filter").map(|_| ())
+    }
+
+    impl RingReceiver {
+        /// Capture the replies of `interface`, in a ring of `blocks` blocks of `RING_BLOCK_SIZE`.
+        pub fn new(interface: &str, blocks: usize) -> Result<Self> {
+            let protocol = (libc::ETH_P_ALL as u16).to_be();
+            let fd = check(
+                unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, protocol as libc::c_int) },
+                "Failed to open a packet socket",
+            )?;
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+            attach_filter(&fd)?;
+            setsockopt(
+                &fd,
+                PACKET_VERSION,
+                &TPACKET_V3,
+                "Failed to select TPACKET_V3",
+            )?;
+            let request = TpacketReq3 {
+                tp_block_size: RING_BLOCK_SIZE as u32,
+                tp_block_nr: blocks as u32,
+                // Packets are variable-sized within a block, the frame size is only checked
+                tp_frame_size: 2048,
+                tp_frame_nr: (RING_BLOCK_SIZE / 2048 * blocks) as u32,
+                tp_retire_blk_tov: RING_BLOCK_TIMEOUT.as_millis() as u32,
+                tp_sizeof_priv: 0,
+                tp_feature_req_word: 0,
+            };
+            setsockopt(
+                &fd,
+                PACKET_RX_RING,
+                &request,
+                "Failed to set up the receive ring",
+            )?;
+
+            let ring_len = RING_BLOCK_SIZE * blocks;
+            let ring = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    ring_len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    fd.as_raw_fd(),
+                    0,
+                )
+            };
+            if ring == libc::MAP_FAILED {
+                return Err(std::io::Error::last_os_error())
+                    .context("Failed to map the receive ring");
+            }
+            // From now on, the ring is unmapped when the receiver is dropped
+            let receiver = RingReceiver {
+                fd,
+                ring: ring as *mut u8,
+                blocks,
+                block: 0,
+                cursor: None,
+                received: 0,
+                dropped: 0,
+            };
+
+            let name = CString::new(interface)?;
+            let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+            if ifindex == 0 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| format!("Unknown interface {}", interface));
+            }
+            let mut address: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+            address.sll_family = libc::AF_PACKET as u16;
+            address.sll_protocol = protocol;
+            address.sll_ifindex = ifindex as libc::c_int;
+            check(
+                unsafe {
+                    libc::bind(
+                        receiver.fd.as_raw_fd(),
+                        &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+                        std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+                    )
+                },
+                "Failed to bind the packet socket",
+            )?;
+            Ok(receiver)
+        }
+
+        fn block_desc(&self) -> *mut BlockDesc {
+            unsafe { self.ring.add(self.block * RING_BLOCK_SIZE) as *mut BlockDesc }
+        }
+
+        /// Wait for the current block to be handed to the receiver. Returns whether it was.
+        fn wait_block(&self) -> Result<bool> {
+            let status =
+                unsafe { std::ptr::addr_of!((*self.block_desc()).block_status).read_volatile() };
+            if status & TP_STATUS_USER != 0 {
+                fence(Ordering::Acquire);
+                return Ok(true);
+            }
+            let mut poll = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN | libc::POLLERR,
+                revents: 0,
+            };
+            match unsafe { libc::poll(&mut poll, 1, POLL_TIMEOUT.as_millis() as libc::c_int) } {
+                0 => Ok(false),
+                result => check(result, "Failed to poll the receive ring").map(|_| false),
+            }
+        }
+
+        /// Hand the current block back to the kernel, and move to the next one.
+        fn release_block(&mut self) {
+            fence(Ordering::Release);
+            unsafe {
+                std::ptr::addr_of_mut!((*self.block_desc()).block_status)
+                    .write_volatile(TP_STATUS_KERNEL)
+            };
+            self.block = (self.block + 1) % self.blocks;
+            self.cursor = None;
+        }
+
+        /// Next reply of the ring, or `RingTimeout` if none was received within the poll timeout.
+        /// Its packet is written to `dump` before it is parsed.
+        pub fn next_reply(&mut self, dump: Option<&mut PcapDump>) -> Result<CapturedReply> {
+            loop {
+                let (offset, remaining) = match self.cursor {
+                    Some(cursor) => cursor,
+                    None => {
+                        if !self.wait_block()? {
+                            // Either the poll timed out, or the block is now ready
+                            if !self.wait_block()? {
+                                return Err(RingTimeout.into());
+                            }
+                        }
+                        let desc = unsafe { &*self.block_desc() };
+                        (desc.offset_to_first_pkt as usize, desc.num_pkts)
+                    }
+                };
+                if remaining == 0 {
+                    self.release_block();
+                    continue;
+                }
+
+                let block = unsafe { self.ring.add(self.block * RING_BLOCK_SIZE) };
+                let header = unsafe { &*(block.add(offset) as *const Tpacket3Hdr) };
+                self.cursor = Some((offset + header.tp_next_offset as usize, remaining - 1));
+
+                // Our own probes are captured too
+                let pkttype = unsafe { *block.add(offset + SOCKADDR_LL_OFFSET + 10) };
+                if pkttype == PACKET_OUTGOING {
+                    continue;
+                }
+                let data = unsafe {
+                    std::slice::from_raw_parts(
+                        block.add(offset + header.tp_mac as usize),
+                        header.tp_snaplen as usize,
+                    )
+                };
+                let packet_header = pcap::PacketHeader {
+                    ts: libc::timeval {
+                        tv_sec: header.tp_sec as libc::time_t,
+                        tv_usec: (header.tp_nsec / 1000) as libc::suseconds_t,
+                    },
+                    caplen: header.tp_snaplen,
+                    len: header.tp_len,
+                };
+                let packet = pcap::Packet::new(&packet_header, data);
+                return parse_reply(&packet, pcap::Linktype::ETHERNET, dump);
+            }
+        }
+    }
+
+    // struct tpacket_stats_v3
+    #[repr(C)]
+    #[derive(Default)]
+    struct TpacketStatsV3 {
+        tp_packets: u32,
+        tp_drops: u32,
+        tp_freeze_q_cnt: u32,
+    }
+
+    impl RingReceiver {
+        /// Packets received and dropped by the ring since it was opened, as those of libpcap.
+        pub fn statistics(&mut self) -> Result<pcap::Stat> {
+            let mut stats = TpacketStatsV3::default();
+            let mut len = std::mem::size_of::<TpacketStatsV3>() as libc::socklen_t;
+            check(
+                unsafe {
+                    libc::getsockopt(
+                        self.fd.as_raw_fd(),
+                        SOL_PACKET,
+                        PACKET_STATISTICS,
+                        &mut stats as *mut TpacketStatsV3 as *mut libc::c_void,
+                        &mut len,
+                    )
+                },
+                "Failed to get the ring statistics",
+            )?;
+            // The received packets include the dropped ones
+            self.received = self.received.wrapping_add(stats.tp_packets);
+            self.dropped = self.dropped.wrapping_add(stats.tp_drops);
+            Ok(pcap::Stat {
+                received: self.received,
+                dropped: self.dropped,
+                if_dropped: 0,
+            })
+        }
+    }
+
+    impl Drop for RingReceiver {
+        fn drop(&mut self) {
+            unsafe {
+                libc::munmap(
+                    self.ring as *mut libc::c_void,
+                    RING_BLOCK_SIZE * self.blocks,
+                )
+            };
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::{Context, Result};
+use metrics::counter;
+use pcap::{Capture, Linktype, Packet, Savefile};
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
+
+use crate::config::CaracatConfig;
+
+/// Size of the global header of a pcap file.
+const PCAP_FILE_HEADER_LEN: u64 = 24;
+/// Size of the header of each packet of a pcap file.
+const PCAP_PACKET_HEADER_LEN: u64 = 16;
+
+/// Raw reply packets captured on the interface of an instance (`pcap_dump`), written to a pcap
+/// file as they are received, before their parsing and integrity check.
+///
+/// Once the file would exceed `pcap_dump_max_bytes`, it is rotated to `<pcap_dump>.1` (and the
+/// previous ones shifted), keeping up to `pcap_dump_max_files` rotated files. The capture of a
+/// previous run is rotated too, rather than overwritten.
+pub struct PcapDump {
+    path: PathBuf,
+    linktype: Linktype,
+    max_bytes: u64,
+    max_files: u64,
+    savefile: Savefile,
+    size: u64,
+}
+
+impl PcapDump {
+    pub fn new(path: &Path, linktype: Linktype, max_bytes: u64, max_files: u64) -> Result<Self> {
+        if path.metadata().is_ok_and(|metadata| metadata.len() > 0) {
+            rotate_files(path, max_files)?;
+        }
+        let savefile = create_savefile(path, linktype)?;
+        info!("Dumping the captured replies to {:?}", path);
+        Ok(PcapDump {
+            path: path.to_path_buf(),
+            linktype,
+            max_bytes,
+            max_files,
+            savefile,
+            size: PCAP_FILE_HEADER_LEN,
+        })
+    }
+
+    /// Dump of the replies of the instance, if it sets `pcap_dump`.
+    pub fn for_instance(config: &CaracatConfig, linktype: Linktype) -> Result<Option<Self>> {
+        config
+            .pcap_dump
+            .as_ref()
+            .map(|path| {
+                PcapDump::new(
+                    Path::new(path),
+                    linktype,
+                    config.pcap_dump_max_bytes,
+                    config.pcap_dump_max_files,
+                )
+            })
+            .transpose()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write a packet, rotating the files first if needed. A failed rotation is only logged,
+    /// the packets are then written to the current file.
+    pub fn write(&mut self, packet: &Packet) {
+        let len = PCAP_PACKET_HEADER_LEN + packet.data.len() as u64;
+        if self.size > PCAP_FILE_HEADER_LEN && self.size + len > self.max_bytes {
+            if let Err(e) = self.rotate() {
+                error!("Failed to rotate the pcap dump {:?}: {:#}", self.path, e);
+                counter!("saimiris_pcap_dump_errors_total").increment(1);
+            }
+        }
+        self.savefile.write(packet);
+        self.size += len;
+    }
+
+    /// Flush the packets buffered by libpcap, so that the dump can be read while it is written.
+    pub fn flush(&mut self) {
+        if let Err(e) = self.savefile.flush() {
+            error!("Failed to flush the pcap dump {:?}: {}", self.path, e);
+            counter!("saimiris_pcap_dump_errors_total").increment(1);
+        }
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        self.savefile.flush()?;
+        rotate_files(&self.path, self.max_files)?;
+        self.savefile = create_savefile(&self.path, self.linktype)?;
+        self.size = PCAP_FILE_HEADER_LEN;
+        Ok(())
+    }
+}
+
+fn create_savefile(path: &Path, linktype: Linktype) -> Result<Savefile> {
+    Capture::dead(linktype)?
+        .savefile(path)
+        .with_context(|| format!("Failed to create the pcap dump {:?}", path))
+}
+
+fn rotated_path(path: &Path, index: u64) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+/// Shift `path` to `<path>.1`, and the previous rotated files, the oldest being overwritten.
+fn rotate_files(path: &Path, max_files: u64) -> Result<()> {
+    for index in (1..max_files).rev() {
+        let from = rotated_path(path, index);
+        if from.exists() {
+            std::fs::rename(from, rotated_path(path, index + 1))?;
+        }
+    }
+    std::fs::rename(path, rotated_path(path, 1))?;
+    Ok(())
+}
@@ -0,0 +1,255 @@
+use anyhow::{Context, Result};
+use caracat::models::Probe;
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{stdout, BufReader, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use tracing::info;
+
+use crate::agent::prefix_set::PrefixSet;
+use crate::client::handler::read_probes;
+use crate::config::{AppConfig, CaracatConfig, ProbesFormat};
+use crate::join::protocol_name;
+
+/// Why a probe is rejected by the policy of a caracat instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    TtlTooLow(u8),
+    TtlTooHigh(u8),
+    DestinationDenied(IpNet),
+    DestinationNotAllowed,
+    ProtocolNotAllowed,
+    DestinationRateCeiling(u64),
+}
+
+impl Rejection {
+    /// Label of the rejection, as reported by the `saimiris_sender_filtered_total` metric.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Rejection::TtlTooLow(_) => "ttl_too_low",
+            Rejection::TtlTooHigh(_) => "ttl_too_high",
+            Rejection::DestinationDenied(_) => "dst_denied",
+            Rejection::DestinationNotAllowed => "dst_not_allowed",
+            Rejection::ProtocolNotAllowed => "protocol_not_allowed",
+            Rejection::DestinationRateCeiling(_) => "dst_rate_ceiling",
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::TtlTooLow(min) => write!(f, "TTL below min_ttl {}", min),
+            Rejection::TtlTooHigh(max) => write!(f, "TTL above max_ttl {}", max),
+            Rejection::DestinationDenied(prefix) => {
+                write!(f, "destination in denied prefix {}", prefix)
+            }
+            Rejection::DestinationNotAllowed => write!(f, "destination outside allowed prefixes"),
+            Rejection::ProtocolNotAllowed => write!(f, "protocol not allowed"),
+            Rejection::DestinationRateCeiling(max) => {
+                write!(f, "more than {} probes towards the destination", max)
+            }
+        }
+    }
+}
+
+fn parse_prefixes(prefixes: &[String], setting: &str) -> Result<Vec<IpNet>> {
+    prefixes
+        .iter()
+        .map(|prefix| {
+            prefix
+                .trim()
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid {} prefix '{}'", setting, prefix))
+        })
+        .collect()
+}
+
+/// Allowed destinations of `dst_allowlist` and `dst_allowlist_file`.
+fn dst_allowlist(config: &CaracatConfig) -> Result<PrefixSet> {
+    let mut prefixes = parse_prefixes(&config.dst_allowlist, "dst_allowlist")?;
+    if let Some(path) = &config.dst_allowlist_file {
+        let file = PrefixSet::load(Path::new(path))
+            .with_context(|| format!("Invalid dst_allowlist_file {}", path))?;
+        if file.is_empty() {
+            // An empty allowlist would allow every destination
+            anyhow::bail!("dst_allowlist_file {} has no prefixes", path);
+        }
+        prefixes.extend(file.prefixes());
+    }
+    Ok(PrefixSet::new(&prefixes))
+}
+
+fn parse_protocol(protocol: &str) -> Result<&'static str> {
+    match protocol.trim().to_lowercase().as_str() {
+        "icmp" => Ok("icmp"),
+        "icmpv6" => Ok("icmpv6"),
+        "udp" => Ok("udp"),
+        other => anyhow::bail!(
+            "Invalid allowed_protocols entry '{}'. Expected one of: icmp, icmpv6, udp",
+            other
+        ),
+    }
+}
+
+/// Probing policy of a caracat instance, enforced by its sender before sending the probes.
+#[derive(Debug, Clone, Default)]
+pub struct ProbePolicy {
+    min_ttl: Option<u8>,
+    max_ttl: Option<u8>,
+    dst_denylist: Vec<IpNet>,
+    // All destinations allowed if empty
+    dst_allowlist: PrefixSet,
+    // All protocols allowed if empty
+    allowed_protocols: Vec<&'static str>,
+    max_probes_per_destination: Option<u64>,
+}
+
+impl ProbePolicy {
+    pub fn new(config: &CaracatConfig) -> Result<Self> {
+        Ok(ProbePolicy {
+            min_ttl: config.min_ttl,
+            max_ttl: config.max_ttl,
+            dst_denylist: parse_prefixes(&config.dst_denylist, "dst_denylist")?,
+            dst_allowlist: dst_allowlist(config)?,
+            allowed_protocols: config
+                .allowed_protocols
+                .iter()
+                .map(|protocol| parse_protocol(protocol))
+                .collect::<Result<_>>()?,
+            max_probes_per_destination: config.max_probes_per_destination,
+        })
+    }
+
+    /// Check a single probe, regardless of the other probes of the message.
+    pub fn check(&self, probe: &Probe) -> Option<Rejection> {
+        if let Some(ttl) = self.min_ttl {
+            if probe.ttl < ttl {
+                return Some(Rejection::TtlTooLow(ttl));
+            }
+        }
+        if let Some(ttl) = self.max_ttl {
+            if probe.ttl > ttl {
+                return Some(Rejection::TtlTooHigh(ttl));
+            }
+        }
+        if let Some(prefix) = self
+            .dst_denylist
+            .iter()
+            .find(|prefix| prefix.contains(&probe.dst_addr))
+        {
+            return Some(Rejection::DestinationDenied(*prefix));
+        }
+        if !self.dst_allowlist.is_empty() && !self.dst_allowlist.contains(&probe.dst_addr) {
+            return Some(Rejection::DestinationNotAllowed);
+        }
+        if !self.allowed_protocols.is_empty()
+            && !self
+                .allowed_protocols
+                .contains(&protocol_name(probe.protocol))
+        {
+            return Some(Rejection::ProtocolNotAllowed);
+        }
+        None
+    }
+
+    /// Check the probes of a message, in order. The probes towards a destination
+    /// beyond `max_probes_per_destination` are rejected.
+    pub fn evaluate(&self, probes: &[Probe]) -> Vec<Option<Rejection>> {
+        let mut per_destination: HashMap<IpAddr, u64> = HashMap::new();
+        probes
+            .iter()
+            .map(|probe| {
+                if let Some(rejection) = self.check(probe) {
+                    return Some(rejection);
+                }
+                if let Some(max) = self.max_probes_per_destination {
+                    let count = per_destination.entry(probe.dst_addr).or_default();
+                    *count += 1;
+                    if *count > max {
+                        return Some(Rejection::DestinationRateCeiling(max));
+                    }
+                }
+                None
+            })
+            .collect()
+    }
+}
+
+/// Probe of the checked set rejected by the policy of a caracat instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation {
+    pub instance: String,
+    // Index of the probe in the probe set
+    pub index: usize,
+    pub rejection: Rejection,
+}
+
+fn instance_name(config: &CaracatConfig, index: usize) -> String {
+    config.name.clone().unwrap_or_else(|| index.to_string())
+}
+
+/// Evaluate the probes against the policy of every caracat instance of the agent.
+pub fn audit(config: &AppConfig, probes: &[Probe]) -> Result<Vec<PolicyViolation>> {
+    let mut violations = Vec::new();
+    for (i, caracat_config) in config.caracat.iter().enumerate() {
+        let policy = ProbePolicy::new(caracat_config)?;
+        let instance = instance_name(caracat_config, i);
+        for (index, rejection) in policy.evaluate(probes).into_iter().enumerate() {
+            if let Some(rejection) = rejection {
+                violations.push(PolicyViolation {
+                    instance: instance.clone(),
+                    index,
+                    rejection,
+                });
+            }
+        }
+    }
+    Ok(violations)
+}
+
+/// `policy check` command: print the probes that the agent would reject, as CSV.
+pub fn check(config: &AppConfig, probes_file: &Path, format: ProbesFormat) -> Result<()> {
+    let file = File::open(probes_file)?;
+    let probes = read_probes(BufReader::new(file), format)?;
+    let violations = audit(config, &probes)?;
+
+    let mut out = stdout().lock();
+    writeln!(
+        out,
+        "instance,line,dst_addr,src_port,dst_port,ttl,protocol,reason"
+    )?;
+    for violation in &violations {
+        let probe = &probes[violation.index];
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{}",
+            violation.instance,
+            violation.index + 1,
+            probe.dst_addr,
+            probe.src_port,
+            probe.dst_port,
+            probe.ttl,
+            protocol_name(probe.protocol),
+            violation.rejection
+        )?;
+    }
+
+    for (i, caracat_config) in config.caracat.iter().enumerate() {
+        let instance = instance_name(caracat_config, i);
+        let rejected = violations
+            .iter()
+            .filter(|violation| violation.instance == instance)
+            .count();
+        info!(
+            "Instance {}: {} of {} probes would be rejected",
+            instance,
+            rejected,
+            probes.len()
+        );
+    }
+    Ok(())
+}
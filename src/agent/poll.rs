@@ -0,0 +1,144 @@
+use anyhow::Result;
+use caracat::models::Probe;
+use reqwest::Client;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufReader, Cursor};
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
+use tokio::time::sleep;
+use tracing::{debug, error, info, warn};
+
+use crate::agent::control::AgentMode;
+use crate::agent::gateway::{
+    self, download_probes, fetch_assigned_measurements, AssignedMeasurement, MeasurementInfo,
+};
+use crate::agent::handler::determine_target_sender;
+use crate::agent::sender::ProbesWithSource;
+use crate::client::handler::read_probes;
+use crate::config::CaracatConfig;
+
+/// Probes of an assigned measurement, inline or downloaded from the gateway.
+pub async fn measurement_probes(
+    client: &Client,
+    measurement: &AssignedMeasurement,
+    agent_key: &str,
+) -> Result<Vec<Probe>> {
+    match &measurement.probes_url {
+        Some(probes_url) => {
+            let bytes = download_probes(client, probes_url, agent_key)
+                .await
+                .map_err(|e| anyhow::anyhow!("{}", e))?;
+            read_probes(
+                BufReader::new(Cursor::new(bytes)),
+                measurement.probes_format,
+            )
+        }
+        None => Ok(measurement.probes.clone()),
+    }
+}
+
+/// Poll the gateway for the measurements assigned to the agent, and queue their probes
+/// to the caracat instances. The senders report the measurements as complete once sent.
+pub async fn poll_loop(
+    gateway_url: String,
+    agent_id: String,
+    agent_key: String,
+    poll_interval: Duration,
+    probe_senders_map: HashMap<String, Sender<ProbesWithSource>>,
+    caracat_configs: Vec<CaracatConfig>,
+    mut mode: watch::Receiver<AgentMode>,
+) {
+    let client = gateway::client();
+    // Measurements already queued, until the gateway no longer assigns them
+    let mut queued: HashSet<String> = HashSet::new();
+
+    loop {
+        // No new measurements while the agent is paused or draining
+        while !mode.borrow_and_update().consumes_probes() {
+            if mode.changed().await.is_err() {
+                break;
+            }
+        }
+
+        let measurements =
+            match fetch_assigned_measurements(&client, &gateway_url, &agent_id, &agent_key).await {
+                Ok(measurements) => measurements,
+                Err(e) => {
+                    error!("{}. Retrying in {:?}.", e, poll_interval);
+                    sleep(poll_interval).await;
+                    continue;
+                }
+            };
+        debug!(
+            "{} measurements assigned by the gateway",
+            measurements.len()
+        );
+        queued.retain(|id| measurements.iter().any(|m| &m.measurement_id == id));
+
+        for measurement in measurements {
+            if queued.contains(&measurement.measurement_id) {
+                continue;
+            }
+
+            let probes = match measurement_probes(&client, &measurement, &agent_key).await {
+                Ok(probes) => probes,
+                Err(e) => {
+                    error!(
+                        "Failed to get the probes of measurement {}: {}. Retrying at the next poll.",
+                        measurement.measurement_id, e
+                    );
+                    continue;
+                }
+            };
+
+            let (sender, use_source_ip) = match determine_target_sender(
+                &probe_senders_map,
+                &caracat_configs,
+                measurement.src_ip.as_ref(),
+            ) {
+                Ok((Some(sender), use_source_ip)) => (sender, use_source_ip),
+                Ok((None, _)) => {
+                    error!("No suitable sender found for the provided source IP");
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Measurement {} ignored: {}", measurement.measurement_id, e);
+                    queued.insert(measurement.measurement_id);
+                    continue;
+                }
+            };
+
+            info!(
+                "Queuing {} probes of measurement {}",
+                probes.len(),
+                measurement.measurement_id
+            );
+            let probes_with_source = ProbesWithSource {
+                probes,
+                tags: Vec::new(),
+                source_ip: match (use_source_ip, &measurement.src_ip) {
+                    (true, Some(src_ip)) => src_ip.clone(),
+                    _ => String::new(),
+                },
+                measurement_info: Some(MeasurementInfo {
+                    measurement_id: measurement.measurement_id.clone(),
+                    end_of_measurement: true,
+                    submitted_at_ms: None,
+                    trace_id: None,
+                    client_id: None,
+                    weight: None,
+                }),
+                priority: 0,
+                ack: None,
+            };
+            if let Err(e) = sender.send(probes_with_source).await {
+                error!("Failed to queue probes to the Caracat sender: {}", e);
+                return;
+            }
+            queued.insert(measurement.measurement_id);
+        }
+
+        sleep(poll_interval).await;
+    }
+}
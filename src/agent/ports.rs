@@ -0,0 +1,139 @@
+use anyhow::Result;
+use caracat::models::Probe;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Flow key hashed into the source port of a probe.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PortStrategy {
+    /// Destination, destination port, protocol and source port of the probe: the probes of a
+    /// traceroute share a flow, and the source ports of the client act as flow IDs
+    #[default]
+    Flow,
+    /// Destination of the probe: all the probes towards a destination share a flow
+    Destination,
+}
+
+impl FromStr for PortStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "flow" => Ok(PortStrategy::Flow),
+            "destination" => Ok(PortStrategy::Destination),
+            _ => anyhow::bail!(
+                "Invalid source port strategy '{}'. Expected 'flow' or 'destination'",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for PortStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortStrategy::Flow => write!(f, "flow"),
+            PortStrategy::Destination => write!(f, "destination"),
+        }
+    }
+}
+
+/// Source ports selected by the agent per flow, within an inclusive range, in format
+/// `MIN-MAX[:STRATEGY]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortPolicy {
+    pub min: u16,
+    pub max: u16,
+    pub strategy: PortStrategy,
+}
+
+impl PortPolicy {
+    /// Source port of `probe`, the same for all the probes of a flow.
+    pub fn src_port(&self, probe: &Probe) -> u16 {
+        let mut hash = Fnv1a::default();
+        hash.write_addr(probe.dst_addr);
+        if self.strategy == PortStrategy::Flow {
+            hash.write(&probe.dst_port.to_be_bytes());
+            hash.write(&[u8::from(probe.protocol)]);
+            hash.write(&probe.src_port.to_be_bytes());
+        }
+        let ports = (self.max - self.min) as u64 + 1;
+        self.min + (hash.finish() % ports) as u16
+    }
+}
+
+impl FromStr for PortPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (range, strategy) = match s.trim().split_once(':') {
+            Some((range, strategy)) => (range, strategy.parse()?),
+            None => (s.trim(), PortStrategy::default()),
+        };
+        let (min, max) = range.split_once('-').ok_or_else(|| {
+            anyhow::anyhow!(
+                "Invalid source port range '{}'. Expected 'MIN-MAX[:STRATEGY]'",
+                s
+            )
+        })?;
+        let min: u16 = min
+            .trim()
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid minimum source port in '{}': {}", s, e))?;
+        let max: u16 = max
+            .trim()
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid maximum source port in '{}': {}", s, e))?;
+        if min == 0 || min > max {
+            anyhow::bail!(
+                "Invalid source port range '{}'. Expected 1 <= MIN <= MAX",
+                s
+            );
+        }
+        Ok(PortPolicy { min, max, strategy })
+    }
+}
+
+impl fmt::Display for PortPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}:{}", self.min, self.max, self.strategy)
+    }
+}
+
+/// Rewrite the source port of each probe with the one selected by `policy`.
+pub fn apply_port_policy(probes: &mut [Probe], policy: PortPolicy) {
+    for probe in probes {
+        probe.src_port = policy.src_port(probe);
+    }
+}
+
+/// 64-bit FNV-1a, stable across builds so that the clients can predict the source ports and
+/// the sharding of the probes between agents.
+pub(crate) struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub(crate) fn write_addr(&mut self, addr: IpAddr) {
+        match addr {
+            IpAddr::V4(addr) => self.write(&addr.to_ipv6_mapped().octets()),
+            IpAddr::V6(addr) => self.write(&addr.octets()),
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
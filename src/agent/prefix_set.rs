@@ -0,0 +1,79 @@
+use anyhow::{Context, Result};
+use ipnet::IpNet;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Set of destination prefixes, such as the do-not-probe blocklist of the agent or the
+/// allowlist of an instance.
+///
+/// The prefixes are aggregated into disjoint prefixes sorted by network address, so that
+/// the prefix of a destination is found with a binary search, even for large lists.
+#[derive(Debug, Clone, Default)]
+pub struct PrefixSet {
+    prefixes: Vec<IpNet>,
+}
+
+impl PrefixSet {
+    pub fn new(prefixes: &[IpNet]) -> Self {
+        let prefixes: Vec<IpNet> = prefixes.iter().map(IpNet::trunc).collect();
+        let mut prefixes = IpNet::aggregate(&prefixes);
+        prefixes.sort_by_key(|prefix| prefix.network());
+        PrefixSet { prefixes }
+    }
+
+    /// Parse a prefix list: one prefix (or address) per line, `#` starting a comment.
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut prefixes = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let prefix = match line.parse::<IpNet>() {
+                Ok(prefix) => prefix,
+                Err(_) => line
+                    .parse::<IpAddr>()
+                    .map(IpNet::from)
+                    .map_err(|_| anyhow::anyhow!("Invalid prefix '{}' on line {}", line, i + 1))?,
+            };
+            prefixes.push(prefix);
+        }
+        Ok(PrefixSet::new(&prefixes))
+    }
+
+    /// Read and parse a prefix list file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read prefix list {}", path.display()))?;
+        PrefixSet::parse(&content)
+            .with_context(|| format!("Failed to parse prefix list {}", path.display()))
+    }
+
+    /// Prefix of the set containing an address, if any.
+    pub fn find(&self, addr: &IpAddr) -> Option<IpNet> {
+        // Last prefix starting at or before the address, the only one which can contain it
+        let index = self
+            .prefixes
+            .partition_point(|prefix| prefix.network() <= *addr);
+        let prefix = self.prefixes.get(index.checked_sub(1)?)?;
+        prefix.contains(addr).then_some(*prefix)
+    }
+
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        self.find(addr).is_some()
+    }
+
+    /// Disjoint prefixes of the set, sorted by network address.
+    pub fn prefixes(&self) -> &[IpNet] {
+        &self.prefixes
+    }
+
+    /// Number of disjoint prefixes.
+    pub fn len(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty()
+    }
+}
@@ -0,0 +1,53 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use crate::agent::lag::PartitionLag;
+
+/// Interval at which the lag of the prioritized topics is checked.
+pub const PRIORITY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Priorities of the input topics (`kafka.in_topic_priorities`, 0 if not set): a topic is
+/// deferred, that is its partitions are paused, while a topic of a higher priority has
+/// probes messages to consume.
+#[derive(Debug, Clone, Default)]
+pub struct TopicPriorities {
+    priorities: HashMap<String, u32>,
+}
+
+impl TopicPriorities {
+    /// Priorities of the topics, `None` if they all have the same priority.
+    pub fn new(priorities: &HashMap<String, u32>) -> Option<Self> {
+        if priorities.values().all(|priority| *priority == 0) {
+            return None;
+        }
+        Some(TopicPriorities {
+            priorities: priorities.clone(),
+        })
+    }
+
+    pub fn priority(&self, topic: &str) -> u32 {
+        self.priorities.get(topic).copied().unwrap_or_default()
+    }
+
+    /// Assigned `topics` to defer given the lag of their partitions: those of a lower
+    /// priority than the highest priority topic with messages to consume.
+    pub fn deferred_topics<'a>(
+        &self,
+        topics: impl IntoIterator<Item = &'a str>,
+        lags: &[PartitionLag],
+    ) -> HashSet<String> {
+        let Some(highest) = lags
+            .iter()
+            .filter(|lag| lag.lag > 0)
+            .map(|lag| self.priority(&lag.topic))
+            .max()
+        else {
+            return HashSet::new();
+        };
+        topics
+            .into_iter()
+            .filter(|topic| self.priority(topic) < highest)
+            .map(str::to_string)
+            .collect()
+    }
+}
@@ -1,21 +1,365 @@
 use caracat::models::Reply;
-use metrics::counter;
+use metrics::{counter, Label};
 use rdkafka::config::ClientConfig;
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
 use rdkafka::message::OwnedHeaders;
 use rdkafka::producer::{FutureProducer, FutureRecord};
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
 use std::time::Duration;
-use tokio::sync::mpsc::Receiver;
-use tracing::{debug, error, warn};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::interval;
+use tracing::{debug, error, info, warn};
 
+use crate::agent::capabilities::AgentCapabilities;
+use crate::agent::correlation::{ProbeKey, SharedCorrelationTable};
+use crate::agent::events::Event;
+use crate::agent::measurement_labels;
+use crate::agent::statistics::{produce_statistics, ProduceStatistics};
+use crate::agent::tx_ring::SenderBackend;
 use crate::auth::KafkaAuth;
-use crate::config::AppConfig;
-use crate::reply::serialize_reply;
+use crate::config::{AppConfig, KeyStrategy};
+use crate::probe::ProbeContext;
+use crate::reply::{serialize_reply, serialize_reply_as, CapturedReply, ReplyFormat};
+use crate::schema_registry::{frame, subject, SchemaRegistry, FRAME_LEN, REPLY_SCHEMA};
 
-pub async fn produce(
+/// A reply for Kafka, with the key of its message.
+pub type KeyedMessage = (Option<String>, Vec<u8>);
+
+fn probe_context(reply: &Reply, correlation: &SharedCorrelationTable) -> ProbeContext {
+    correlation
+        .lock()
+        .unwrap()
+        .get(&ProbeKey::from_reply(reply))
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn producer_config(config: &AppConfig, brokers: &str, auth: KafkaAuth) -> ClientConfig {
+    let mut client_config = ClientConfig::new();
+    client_config
+        .set("bootstrap.servers", brokers)
+        .set("message.timeout.ms", "5000");
+    if let Some(compression) = &config.kafka.compression {
+        client_config.set("compression.type", compression);
+    }
+    if let KafkaAuth::SasalPlainText(scram_auth) = auth {
+        client_config
+            .set("sasl.username", scram_auth.username)
+            .set("sasl.password", scram_auth.password)
+            .set("sasl.mechanisms", scram_auth.mechanism)
+            .set("security.protocol", "SASL_PLAINTEXT");
+    }
+    client_config
+}
+
+fn create_producer(config: &AppConfig, auth: KafkaAuth) -> FutureProducer {
+    let mut client_config = producer_config(config, &config.kafka.brokers, auth);
+    config.kafka.apply_properties(&mut client_config);
+    client_config.create().expect("Producer creation error")
+}
+
+/// Producer of the replies, to the `kafka.out` cluster if configured (with its own
+/// authentication and properties), to the cluster of the probes with `auth` otherwise.
+fn create_replies_producer(config: &AppConfig, auth: KafkaAuth) -> FutureProducer {
+    let Some(out) = &config.kafka.out else {
+        return create_producer(config, auth);
+    };
+    // Validated at startup
+    let out_auth = out.auth().unwrap_or(KafkaAuth::PlainText);
+    let mut client_config = producer_config(config, &out.brokers, out_auth);
+    for (key, value) in &out.properties {
+        client_config.set(key, value);
+    }
+    client_config.create().expect("Producer creation error")
+}
+
+/// Publish sampled spoofed replies, one reply per message.
+pub async fn produce_spoofed(
+    config: &AppConfig,
+    auth: KafkaAuth,
+    topic: String,
+    mut rx: Receiver<CapturedReply>,
+) {
+    let producer = create_replies_producer(config, auth);
+    let format = ReplyFormat::parse(&config.kafka.out_format).unwrap_or_default();
+    while let Some(reply) = rx.recv().await {
+        let message = serialize_reply_as(
+            format,
+            config.agent.id.clone(),
+            &reply,
+            &ProbeContext::default(),
+        );
+        let delivery_status = producer
+            .send(
+                FutureRecord::to(topic.as_str())
+                    .payload(&message)
+                    .key("")
+                    .headers(OwnedHeaders::new()),
+                Duration::from_secs(0),
+            )
+            .await;
+        if let Err((error, _)) = delivery_status {
+            error!("failed to send spoofed reply sample: {}", error);
+        }
+    }
+}
+
+/// Publish agent events as JSON records.
+pub async fn produce_events(
     config: &AppConfig,
     auth: KafkaAuth,
-    mut rx: Receiver<Reply>,
+    topic: String,
+    mut rx: Receiver<Event>,
+) {
+    let producer = create_producer(config, auth);
+    while let Some(event) = rx.recv().await {
+        let message = match serde_json::to_vec(&event) {
+            Ok(message) => message,
+            Err(e) => {
+                error!("failed to serialize event: {}", e);
+                continue;
+            }
+        };
+        let delivery_status = producer
+            .send(
+                FutureRecord::to(topic.as_str())
+                    .payload(&message)
+                    .key(config.agent.id.as_str())
+                    .headers(OwnedHeaders::new()),
+                Duration::from_secs(0),
+            )
+            .await;
+        if let Err((error, _)) = delivery_status {
+            error!("failed to send event: {}", error);
+        }
+    }
+}
+
+/// Publish the capabilities of the agent to the agents topic every heartbeat interval,
+/// keyed by agent ID so that the compacted topic keeps the latest capabilities of every agent.
+pub async fn publish_capabilities(
+    config: &AppConfig,
+    auth: KafkaAuth,
+    topic: &str,
+    run_id: &str,
+    started_at_ns: u64,
+) {
+    let producer = create_producer(config, auth);
+    let mut heartbeat = interval(Duration::from_secs(
+        config.kafka.agents_heartbeat_interval.max(1),
+    ));
+    let marks_dscp = config
+        .caracat
+        .iter()
+        .all(|caracat| SenderBackend::new(caracat).is_ok_and(|backend| backend.marks_dscp()));
+    loop {
+        heartbeat.tick().await;
+        let capabilities = AgentCapabilities {
+            run_id: run_id.to_string(),
+            published_at_ns: chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64,
+            started_at_ns,
+            ..AgentCapabilities::current(&config.agent.id).with_dscp(marks_dscp)
+        };
+        let message = match serde_json::to_vec(&capabilities) {
+            Ok(message) => message,
+            Err(e) => {
+                error!("failed to serialize the agent capabilities: {}", e);
+                continue;
+            }
+        };
+        let delivery_status = producer
+            .send(
+                FutureRecord::to(topic)
+                    .payload(&message)
+                    .key(config.agent.id.as_str())
+                    .headers(OwnedHeaders::new()),
+                Duration::from_secs(0),
+            )
+            .await;
+        if let Err((error, _)) = delivery_status {
+            warn!("failed to publish the agent capabilities: {}", error);
+        }
+    }
+}
+
+/// Serialize the replies, attributed with their probe context, and dispatch them to the
+/// Kafka producer (in `kafka_format`, keyed with `key_strategy`) and the other sinks (S3, gateway, always Cap'n Proto).
+/// With other sinks, replies are dropped for Kafka rather than blocking the sinks when Kafka
+/// is unavailable.
+pub async fn dispatch_replies(
+    agent_id: String,
+    mut rx: Receiver<CapturedReply>,
+    correlation: SharedCorrelationTable,
+    kafka_tx: Option<Sender<KeyedMessage>>,
+    kafka_format: ReplyFormat,
+    key_strategy: KeyStrategy,
+    sinks: Vec<(&'static str, Sender<Vec<u8>>)>,
+) {
+    while let Some(reply) = rx.recv().await {
+        let context = probe_context(&reply.reply, &correlation);
+        let message = serialize_reply(agent_id.clone(), &reply, &context);
+        // Replies counted by measurement and client, within the limit of distinct labels
+        let mut labels = vec![Label::new("agent", agent_id.clone())];
+        labels.extend(measurement_labels::labels(
+            context.measurement_id.as_deref(),
+            None,
+        ));
+        counter!("saimiris_replies_dispatched_total", labels.clone()).increment(1);
+        let dropped = |sink: &'static str| {
+            let mut labels = labels.clone();
+            labels.push(Label::new("sink", sink));
+            counter!("saimiris_replies_dropped_total", labels).increment(1);
+        };
+        for (sink, tx) in &sinks {
+            if tx.try_send(message.clone()).is_err() {
+                dropped(*sink);
+            }
+        }
+        if let Some(kafka_tx) = &kafka_tx {
+            let message = match kafka_format {
+                ReplyFormat::Capnp => message,
+                format => serialize_reply_as(format, agent_id.clone(), &reply, &context),
+            };
+            let key = key_strategy.key(
+                &agent_id,
+                context.measurement_id.as_deref(),
+                Some(reply.reply.probe_dst_addr),
+            );
+            let message = (key, message);
+            if sinks.is_empty() {
+                if kafka_tx.send(message).await.is_err() {
+                    error!("Kafka producer channel closed");
+                    return;
+                }
+            } else if kafka_tx.try_send(message).is_err() {
+                dropped("kafka");
+            }
+        }
+    }
+}
+
+/// Register the schema of the replies for the replies topic, retrying until the registry
+/// is available. Replies are queued meanwhile.
+async fn register_reply_schema(registry: &SchemaRegistry, config: &AppConfig) -> u32 {
+    let subject = subject(&config.kafka.out_topic);
+    loop {
+        match registry.register(&subject, REPLY_SCHEMA).await {
+            Ok(schema_id) => {
+                info!("Replies framed with schema {} ({})", schema_id, subject);
+                return schema_id;
+            }
+            Err(e) => {
+                error!("{:#}. Retrying in 5s...", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+/// Produce each JSON reply as its own message, as expected by JSON consumers, without
+/// waiting for the delivery of the previous ones.
+async fn produce_json(
+    config: &AppConfig,
+    producer: &FutureProducer,
+    mut rx: Receiver<KeyedMessage>,
+) {
+    while let Some((key, message)) = rx.recv().await {
+        let mut record = FutureRecord::to(config.kafka.out_topic.as_str())
+            .payload(&message)
+            .headers(OwnedHeaders::new());
+        if let Some(key) = &key {
+            record = record.key(key.as_str());
+        }
+        let delivery = loop {
+            match producer.send_result(record) {
+                Ok(delivery) => break Some(delivery),
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), returned)) => {
+                    record = returned;
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                Err((error, _)) => {
+                    error!("failed to send message: {}", error);
+                    break None;
+                }
+            }
+        };
+
+        let agent_id = config.agent.id.clone();
+        let statistics = produce_statistics();
+        tokio::spawn(async move {
+            let status = match delivery {
+                Some(delivery) => match delivery.await {
+                    Ok(Ok(_)) => "success",
+                    Ok(Err((error, _))) => {
+                        error!("failed to send message: {}", error);
+                        "failure"
+                    }
+                    Err(_) => "failure",
+                },
+                None => "failure",
+            };
+            counter!("saimiris_kafka_messages_total", "agent" => agent_id, "status" => status)
+                .increment(1);
+            record_message(&statistics, status == "success");
+        });
+    }
+}
+
+/// Count a message of replies in the statistics, produced or failed.
+fn record_message(statistics: &ProduceStatistics, success: bool) {
+    let counter = if success {
+        &statistics.messages_produced
+    } else {
+        &statistics.messages_failed
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Send a batch of Cap'n Proto replies as one message, framed with the schema ID if any.
+async fn send_batch(
+    config: &AppConfig,
+    producer: &FutureProducer,
+    key: Option<&str>,
+    payload: Vec<u8>,
+    n_messages: usize,
+    schema_id: Option<u32>,
 ) {
+    let payload = match schema_id {
+        Some(schema_id) => frame(schema_id, &payload),
+        None => payload,
+    };
+
+    debug!("Sending {} replies to Kafka", n_messages);
+    let mut record = FutureRecord::to(config.kafka.out_topic.as_str())
+        .payload(&payload)
+        .headers(OwnedHeaders::new()); // TODO
+    if let Some(key) = key {
+        record = record.key(key);
+    }
+    let delivery_status = producer.send(record, Duration::from_secs(0)).await;
+
+    let metric_name = "saimiris_kafka_messages_total";
+    match delivery_status {
+        Ok(delivery) => {
+            counter!(metric_name, "agent" => config.agent.id.clone(), "status" => "success")
+                .increment(1);
+            record_message(&produce_statistics(), true);
+            debug!(
+                "successfully sent message to partition {} at offset {}",
+                delivery.partition, delivery.offset
+            );
+        }
+        Err((error, _)) => {
+            counter!(metric_name, "agent" => config.agent.id.clone(), "status" => "failure")
+                .increment(1);
+            record_message(&produce_statistics(), false);
+            error!("failed to send message: {}", error);
+        }
+    }
+}
+
+pub async fn produce(config: &AppConfig, auth: KafkaAuth, mut rx: Receiver<KeyedMessage>) {
     if config.kafka.out_enable == false {
         warn!("Kafka producer is disabled");
         loop {
@@ -25,94 +369,60 @@ pub async fn produce(
         }
     }
 
-    let producer: &FutureProducer = match auth {
-        KafkaAuth::PlainText => &ClientConfig::new()
-            .set("bootstrap.servers", config.kafka.brokers.clone())
-            .set("message.timeout.ms", "5000")
-            .create()
-            .expect("Producer creation error"),
-        KafkaAuth::SasalPlainText(scram_auth) => &ClientConfig::new()
-            .set("bootstrap.servers", config.kafka.brokers.clone())
-            .set("message.timeout.ms", "5000")
-            .set("sasl.username", scram_auth.username)
-            .set("sasl.password", scram_auth.password)
-            .set("sasl.mechanisms", scram_auth.mechanism)
-            .set("security.protocol", "SASL_PLAINTEXT")
-            .create()
-            .expect("Producer creation error"),
+    let producer = &create_replies_producer(config, auth);
+    if ReplyFormat::parse(&config.kafka.out_format).unwrap_or_default() == ReplyFormat::Json {
+        return produce_json(config, producer, rx).await;
+    }
+    let schema_id = match config.kafka.schema_registry.clone() {
+        Some(registry) => Some(register_reply_schema(&SchemaRegistry::new(registry), config).await),
+        None => None,
+    };
+    // Leave room for the schema framing
+    let message_max_bytes = match schema_id {
+        Some(_) => config.kafka.message_max_bytes.saturating_sub(FRAME_LEN),
+        None => config.kafka.message_max_bytes,
     };
 
-    let mut additional_message = None;
+    let wait_time = Duration::from_millis(config.kafka.out_batch_wait_time);
     loop {
         let start_time = std::time::Instant::now();
-        let mut final_message = Vec::new();
-        let mut n_messages = 0;
-
-        // Send the additional reply first
-        if let Some(message) = additional_message {
-            let message = serialize_reply(config.agent.id.clone(), &message);
-            final_message.extend_from_slice(&message);
-            n_messages += 1;
-            additional_message = None;
-        }
-
-        loop {
-            if std::time::Instant::now().duration_since(start_time)
-                > std::time::Duration::from_millis(config.kafka.out_batch_wait_time)
-            {
-                break;
-            }
-
-            let message = rx.try_recv();
-            if message.is_err() {
+        // Replies are batched per message key
+        let mut batches: HashMap<Option<String>, (Vec<u8>, usize)> = HashMap::new();
+        while start_time.elapsed() <= wait_time {
+            let Ok((key, message_bin)) = rx.try_recv() else {
                 tokio::time::sleep(Duration::from_millis(config.kafka.out_batch_wait_interval))
                     .await;
                 continue;
-            }
-
-            let message = message.unwrap();
-            let message_bin = serialize_reply(config.agent.id.clone(), &message);
+            };
 
             // Max message size is 1048576 bytes (including headers)
-            if final_message.len() + message_bin.len() > config.kafka.message_max_bytes {
-                additional_message = Some(message);
-                break;
+            let batch = batches.entry(key.clone()).or_default();
+            if !batch.0.is_empty() && batch.0.len() + message_bin.len() > message_max_bytes {
+                let (payload, n_messages) = std::mem::take(batch);
+                send_batch(
+                    config,
+                    producer,
+                    key.as_deref(),
+                    payload,
+                    n_messages,
+                    schema_id,
+                )
+                .await;
             }
-
-            final_message.extend_from_slice(&message_bin);
-            n_messages += 1;
-        }
-
-        if final_message.is_empty() {
-            continue;
+            batch.0.extend_from_slice(&message_bin);
+            batch.1 += 1;
         }
 
-        debug!("Sending {} replies to Kafka", n_messages);
-        let delivery_status = producer
-            .send(
-                FutureRecord::to(config.kafka.out_topic.as_str())
-                    .payload(&final_message)
-                    .key(&format!("")) // TODO
-                    .headers(OwnedHeaders::new()), // TODO
-                Duration::from_secs(0),
+        for (key, (payload, n_messages)) in batches {
+            send_batch(
+                config,
+                producer,
+                key.as_deref(),
+                payload,
+                n_messages,
+                schema_id,
             )
             .await;
-
-        let metric_name = "saimiris_kafka_messages_total";
-        match delivery_status {
-            Ok(delivery) => {
-                counter!(metric_name, "agent" => config.agent.id.clone(), "status" => "success")
-                    .increment(1);
-                debug!(
-                    "successfully sent message to partition {} at offset {}",
-                    delivery.partition, delivery.offset
-                );
-            }
-            Err((error, _)) => {
-                counter!(metric_name, "agent" => config.agent.id.clone(), "status" => "failure")
-                    .increment(1);
-                error!("failed to send message: {}", error);
-            }
         }
     }
 }
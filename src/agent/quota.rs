@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Default)]
+struct Usage {
+    probes: u64,
+    exceeded: bool,
+}
+
+/// Maximum number of probes sent per measurement by the agent, shared by its SendLoops.
+#[derive(Debug, Clone)]
+pub struct MeasurementQuota {
+    max_probes: u64,
+    usage: Arc<Mutex<HashMap<String, Usage>>>,
+}
+
+impl MeasurementQuota {
+    pub fn new(max_probes: u64) -> Self {
+        MeasurementQuota {
+            max_probes,
+            usage: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn max_probes(&self) -> u64 {
+        self.max_probes
+    }
+
+    /// Take up to `probes` probes from the quota of a measurement.
+    /// Returns the number of probes granted, and whether the maximum is exceeded for the
+    /// first time (so that the violation is reported once).
+    pub fn take(&self, measurement_id: &str, probes: u64) -> (u64, bool) {
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(measurement_id.to_string()).or_default();
+        let granted = probes.min(self.max_probes.saturating_sub(usage.probes));
+        usage.probes += granted;
+        let newly_exceeded = granted < probes && !usage.exceeded;
+        usage.exceeded |= granted < probes;
+        (granted, newly_exceeded)
+    }
+
+    /// Probes sent for a measurement, against the quota.
+    pub fn used(&self, measurement_id: &str) -> u64 {
+        self.usage
+            .lock()
+            .unwrap()
+            .get(measurement_id)
+            .map_or(0, |usage| usage.probes)
+    }
+
+    /// Forget a complete measurement, a measurement reusing its ID starts over.
+    pub fn forget(&self, measurement_id: &str) {
+        self.usage.lock().unwrap().remove(measurement_id);
+    }
+}
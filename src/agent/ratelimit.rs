@@ -0,0 +1,308 @@
+use anyhow::Result;
+use ipnet::IpNet;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::CaracatConfig;
+
+/// Token bucket of `rate` probes per second, allowing bursts of `burst` probes.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64, burst: u64) -> Self {
+        let burst = burst.clamp(1, rate.max(1)) as f64;
+        TokenBucket {
+            rate: rate.max(1) as f64,
+            burst,
+            tokens: burst,
+            last: Instant::now(),
+        }
+    }
+
+    /// Change the rate and the burst, the tokens in excess of the burst are dropped.
+    pub fn set_rate(&mut self, rate: u64, burst: u64) {
+        self.rate = rate.max(1) as f64;
+        self.burst = burst.clamp(1, rate.max(1)) as f64;
+        self.tokens = self.tokens.min(self.burst);
+    }
+
+    /// Take a token at `now`, returning how long to wait before sending.
+    /// Tokens taken while the bucket is empty are owed, so that the rate holds on average.
+    pub fn take(&mut self, now: Instant) -> Duration {
+        self.take_many(now, 1)
+    }
+
+    /// Take `tokens` tokens at once at `now`, returning how long to wait before sending.
+    pub fn take_many(&mut self, now: Instant, tokens: u64) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = self.last.max(now);
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst) - tokens as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    /// Take a token, sleeping until it is available.
+    pub fn wait(&mut self) {
+        let delay = self.take(Instant::now());
+        if !delay.is_zero() {
+            std::thread::sleep(delay);
+        }
+    }
+}
+
+/// Probing rate of each source address of a caracat instance, from its `source_rate_limits`.
+/// Each source address within a prefix has its own bucket, so that the probes of one source
+/// address cannot use up the probing rate of the instance.
+#[derive(Debug, Clone, Default)]
+pub struct SourceRateLimiter {
+    limits: Vec<(IpNet, u64)>,
+    burst: u64,
+    buckets: HashMap<IpAddr, TokenBucket>,
+}
+
+impl SourceRateLimiter {
+    pub fn new(config: &CaracatConfig) -> Result<Self> {
+        let limits = config
+            .source_rate_limits
+            .iter()
+            .map(|limit| {
+                let prefix: IpNet = limit.prefix.trim().parse().map_err(|_| {
+                    anyhow::anyhow!("Invalid source_rate_limits prefix '{}'", limit.prefix)
+                })?;
+                if limit.probing_rate == 0 {
+                    anyhow::bail!(
+                        "Invalid source_rate_limits probing_rate for prefix {}. Expected > 0",
+                        prefix
+                    );
+                }
+                Ok((prefix, limit.probing_rate))
+            })
+            .collect::<Result<_>>()?;
+        Ok(SourceRateLimiter {
+            limits,
+            burst: config.batch_size,
+            buckets: HashMap::new(),
+        })
+    }
+
+    /// Rate of a source address, from the most specific prefix containing it.
+    pub fn rate(&self, source: &IpAddr) -> Option<u64> {
+        self.limits
+            .iter()
+            .filter(|(prefix, _)| prefix.contains(source))
+            .max_by_key(|(prefix, _)| prefix.prefix_len())
+            .map(|(_, rate)| *rate)
+    }
+
+    /// Bucket of a source address, if it is rate limited.
+    pub fn bucket(&mut self, source: &IpAddr) -> Option<&mut TokenBucket> {
+        let rate = self.rate(source)?;
+        let burst = self.burst;
+        Some(
+            self.buckets
+                .entry(*source)
+                .or_insert_with(|| TokenBucket::new(rate, burst)),
+        )
+    }
+}
+
+// Destination prefixes of the destination rate limit
+const DST_IPV4_PREFIX_LEN: u8 = 24;
+const DST_IPV6_PREFIX_LEN: u8 = 48;
+const DST_WINDOW: Duration = Duration::from_secs(1);
+
+/// Sliding window counter: the probes of the current window, plus the probes of the previous
+/// window weighted by its overlap with the last second.
+#[derive(Debug, Clone)]
+struct WindowCounter {
+    start: Instant,
+    current: u64,
+    previous: u64,
+}
+
+impl WindowCounter {
+    fn roll(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.start);
+        if elapsed >= 2 * DST_WINDOW {
+            self.start = now;
+            self.previous = 0;
+            self.current = 0;
+        } else if elapsed >= DST_WINDOW {
+            self.start += DST_WINDOW;
+            self.previous = self.current;
+            self.current = 0;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct WindowCounters<K> {
+    windows: HashMap<K, WindowCounter>,
+    last_prune: Instant,
+}
+
+/// Probing rate towards each key (e.g. a destination network), the same for every key, with a
+/// sliding window over the last second.
+#[derive(Debug, Clone)]
+pub struct KeyedRateLimiter<K> {
+    rate: u64,
+    counters: Arc<Mutex<WindowCounters<K>>>,
+}
+
+impl<K: Eq + Hash> KeyedRateLimiter<K> {
+    pub fn new(rate: u64) -> Self {
+        KeyedRateLimiter {
+            rate: rate.max(1),
+            counters: Arc::new(Mutex::new(WindowCounters {
+                windows: HashMap::new(),
+                last_prune: Instant::now(),
+            })),
+        }
+    }
+
+    /// Count a probe to `key` at `now` if its rate allows it, returning zero. Otherwise, the
+    /// probe is not counted, and the delay before trying again is returned.
+    pub fn take(&self, key: K, now: Instant) -> Duration {
+        let mut counters = self.counters.lock().unwrap();
+        // Forget the keys not probed for two windows
+        if now.saturating_duration_since(counters.last_prune) >= 2 * DST_WINDOW {
+            counters
+                .windows
+                .retain(|_, counter| now.saturating_duration_since(counter.start) < 2 * DST_WINDOW);
+            counters.last_prune = now;
+        }
+
+        let counter = counters
+            .windows
+            .entry(key)
+            .or_insert_with(|| WindowCounter {
+                start: now,
+                current: 0,
+                previous: 0,
+            });
+        counter.roll(now);
+        let progress =
+            now.saturating_duration_since(counter.start).as_secs_f64() / DST_WINDOW.as_secs_f64();
+        let estimate = counter.previous as f64 * (1.0 - progress) + counter.current as f64;
+        if estimate + 1.0 <= self.rate as f64 {
+            counter.current += 1;
+            return Duration::ZERO;
+        }
+
+        let window_end = counter.start + DST_WINDOW;
+        let delay = if counter.current + 1 > self.rate {
+            // Wait for the next window
+            window_end.saturating_duration_since(now)
+        } else {
+            // Wait until enough of the previous window has slid out
+            let available = (self.rate - counter.current - 1) as f64;
+            let slid = 1.0 - available / counter.previous as f64;
+            (counter.start + DST_WINDOW.mul_f64(slid)).saturating_duration_since(now)
+        };
+        delay.max(Duration::from_micros(1))
+    }
+
+    /// Count a probe to `key`, sleeping until its rate allows it (without holding the lock).
+    /// Returns whether the probe was delayed.
+    pub fn wait(&self, key: K) -> bool
+    where
+        K: Copy,
+    {
+        let mut delayed = false;
+        loop {
+            let delay = self.take(key, Instant::now());
+            if delay.is_zero() {
+                return delayed;
+            }
+            delayed = true;
+            std::thread::sleep(delay);
+        }
+    }
+}
+
+/// Probing rate towards each destination /24 (IPv4) or /48 (IPv6), shared by all the sender
+/// workers of the agent (`agent.max_dst_prefix_pps`), so that poorly shuffled inputs do not
+/// burst into a single network.
+#[derive(Debug, Clone)]
+pub struct DestinationRateLimiter {
+    limiter: KeyedRateLimiter<IpNet>,
+}
+
+impl DestinationRateLimiter {
+    pub fn new(rate: u64) -> Self {
+        DestinationRateLimiter {
+            limiter: KeyedRateLimiter::new(rate),
+        }
+    }
+
+    /// Prefix of a destination the rate applies to.
+    pub fn prefix(dst_addr: &IpAddr) -> IpNet {
+        let prefix_len = match dst_addr {
+            IpAddr::V4(_) => DST_IPV4_PREFIX_LEN,
+            IpAddr::V6(_) => DST_IPV6_PREFIX_LEN,
+        };
+        IpNet::new(*dst_addr, prefix_len)
+            .map(|prefix| prefix.trunc())
+            .unwrap_or_else(|_| IpNet::from(*dst_addr))
+    }
+
+    /// Count a probe to `dst_addr` at `now` if the rate of its prefix allows it, returning
+    /// zero. Otherwise, the probe is not counted, and the delay before trying again is returned.
+    pub fn take(&self, dst_addr: &IpAddr, now: Instant) -> Duration {
+        self.limiter.take(Self::prefix(dst_addr), now)
+    }
+
+    /// Count a probe to `dst_addr`, sleeping until the rate of its prefix allows it
+    /// (without holding the lock). Returns whether the probe was delayed.
+    pub fn wait(&self, dst_addr: &IpAddr) -> bool {
+        self.limiter.wait(Self::prefix(dst_addr))
+    }
+}
+
+/// Probing rate shared by all the sender workers of the agent (`agent.max_total_pps`).
+#[derive(Debug, Clone)]
+pub struct SharedRateLimiter {
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+impl SharedRateLimiter {
+    /// Bursts are limited to 10ms worth of probes.
+    pub fn new(rate: u64) -> Self {
+        SharedRateLimiter {
+            bucket: Arc::new(Mutex::new(TokenBucket::new(rate, rate.div_ceil(100)))),
+        }
+    }
+
+    /// Change the probing rate, e.g. on a command of the gateway.
+    pub fn set_rate(&self, rate: u64) {
+        self.bucket
+            .lock()
+            .unwrap()
+            .set_rate(rate, rate.div_ceil(100));
+    }
+
+    /// Take `tokens` tokens at `now`, returning how long to wait before sending.
+    pub fn take(&self, now: Instant, tokens: u64) -> Duration {
+        self.bucket.lock().unwrap().take_many(now, tokens)
+    }
+
+    /// Take `tokens` tokens, sleeping until they are available (without holding the lock).
+    pub fn wait(&self, tokens: u64) {
+        let delay = self.take(Instant::now(), tokens);
+        if !delay.is_zero() {
+            std::thread::sleep(delay);
+        }
+    }
+}
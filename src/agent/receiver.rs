@@ -1,19 +1,145 @@
 use caracat::models::Reply;
-use caracat::receiver::Receiver;
-use metrics::counter;
 use metrics::Label;
-use std::sync::{Arc, Mutex};
+use metrics::{counter, gauge};
+use pcap::{Active, Capture, Direction, Linktype, Packet};
+use std::sync::atomic::Ordering;
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use tokio::runtime::Handle as TokioHandle;
 use tokio::sync::mpsc::Sender as TokioSender;
-use tracing::{debug, error, info, trace};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, info_span, trace};
 
+use crate::agent::chaos;
+use crate::agent::failover::interface_is_up;
+use crate::agent::integrity::{family_label, family_override, is_checked};
+#[cfg(target_os = "linux")]
+use crate::agent::packet_ring::RingReceiver;
+use crate::agent::packet_ring::{ReceiverBackend, RingTimeout};
+use crate::agent::pcap_dump::PcapDump;
+use crate::agent::reply_filter::ReplyFilter;
+use crate::agent::server::LoopGuard;
+use crate::agent::spoof::SpoofDetector;
+use crate::agent::statistics;
+use crate::agent::supervisor::LoopExit;
 use crate::config::CaracatConfig;
+use crate::icmp_extension::{self, IcmpExtension};
+use crate::reply::CapturedReply;
+
+/// Interval between two reads of the capture statistics.
+const STATISTICS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Replies captured by the pcap backend, as caracat's batch receiver.
+const CAPTURE_FILTER: &str = "(ip and icmp and (
+        icmp[icmptype] = icmp-echoreply or
+        icmp[icmptype] = icmp-timxceed or
+        icmp[icmptype] = icmp-unreach))
+        or
+        (ip6 and icmp6 and (
+        icmp6[icmp6type] = icmp6-echoreply or
+        icmp6[icmp6type] = icmp6-timeexceeded or
+        icmp6[icmp6type] = icmp6-destinationunreach))";
+
+/// Open the capture of the replies on `interface` with the settings of caracat's batch
+/// receiver, the raw packets being needed for the ICMP extensions and the dump.
+fn open_capture(interface: &str) -> anyhow::Result<Capture<Active>> {
+    let mut capture = Capture::from_device(interface)?
+        .buffer_size(64 * 1024 * 1024)
+        .timeout(100)
+        .immediate_mode(false)
+        .open()?;
+    capture.direction(Direction::In)?;
+    capture.filter(CAPTURE_FILTER, true)?;
+    Ok(capture)
+}
+
+/// Parse a captured packet into a reply with its ICMP extension objects, writing it to `dump`
+/// first. The MPLS label stacks are reported as the labels of the reply, the other objects as
+/// they are received.
+pub(crate) fn parse_reply(
+    packet: &Packet,
+    linktype: Linktype,
+    dump: Option<&mut PcapDump>,
+) -> anyhow::Result<CapturedReply> {
+    if let Some(dump) = dump {
+        dump.write(packet);
+    }
+    let mut reply = caracat::parser::parse(packet, linktype)?;
+    let (mpls, extensions): (Vec<_>, Vec<_>) = icmp_extension::parse_packet(packet.data, linktype)
+        .into_iter()
+        .partition(IcmpExtension::is_mpls_label_stack);
+    if reply.reply_mpls_labels.is_empty() {
+        reply.reply_mpls_labels = mpls.iter().flat_map(IcmpExtension::mpls_labels).collect();
+    }
+    Ok(CapturedReply { reply, extensions })
+}
+
+/// Capture of the replies, with the backend of the instance.
+enum ReplySource {
+    Pcap(Capture<Active>, Linktype),
+    #[cfg(target_os = "linux")]
+    Ring(RingReceiver),
+}
+
+impl ReplySource {
+    fn new(config: &CaracatConfig) -> anyhow::Result<Self> {
+        match ReceiverBackend::new(config)? {
+            ReceiverBackend::Pcap => {
+                let capture = open_capture(&config.interface)?;
+                let linktype = capture.get_datalink();
+                Ok(ReplySource::Pcap(capture, linktype))
+            }
+            #[cfg(target_os = "linux")]
+            ReceiverBackend::TpacketV3 => Ok(ReplySource::Ring(RingReceiver::new(
+                &config.interface,
+                config.receiver_ring_blocks as usize,
+            )?)),
+            #[cfg(not(target_os = "linux"))]
+            ReceiverBackend::TpacketV3 => unreachable!(),
+        }
+    }
+
+    /// Link type of the captured packets.
+    fn linktype(&self) -> Linktype {
+        match self {
+            ReplySource::Pcap(_, linktype) => *linktype,
+            #[cfg(target_os = "linux")]
+            ReplySource::Ring(_) => Linktype::ETHERNET,
+        }
+    }
+
+    /// Next reply, its packet written to `dump` before it is parsed.
+    fn next_reply(&mut self, dump: Option<&mut PcapDump>) -> anyhow::Result<CapturedReply> {
+        match self {
+            ReplySource::Pcap(capture, linktype) => {
+                let packet = capture.next_packet()?;
+                parse_reply(&packet, *linktype, dump)
+            }
+            #[cfg(target_os = "linux")]
+            ReplySource::Ring(receiver) => receiver.next_reply(dump),
+        }
+    }
+
+    /// Packets received and dropped by the capture since it was opened.
+    fn statistics(&mut self) -> anyhow::Result<pcap::Stat> {
+        match self {
+            ReplySource::Pcap(capture, _) => Ok(capture.stats()?),
+            #[cfg(target_os = "linux")]
+            ReplySource::Ring(receiver) => receiver.statistics(),
+        }
+    }
+}
+
+/// Publish the capture statistics, so that replies lost before reaching the agent are visible.
+fn publish_statistics(stats: &pcap::Stat, labels: &[Label]) {
+    gauge!("saimiris_receiver_pcap_received", labels.to_vec()).set(stats.received as f64);
+    gauge!("saimiris_receiver_pcap_dropped", labels.to_vec()).set(stats.dropped as f64);
+    gauge!("saimiris_receiver_pcap_if_dropped", labels.to_vec()).set(stats.if_dropped as f64);
+}
 
 pub struct ReceiveLoop {
-    handle: JoinHandle<()>,
-    stopped: Arc<Mutex<bool>>,
+    handle: JoinHandle<LoopExit>,
 }
 
 impl ReceiveLoop {
@@ -23,66 +149,152 @@ impl ReceiveLoop {
             .any(|&instance_id| reply.is_valid(instance_id))
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        tx: TokioSender<Reply>,
+        tx: TokioSender<CapturedReply>,
         agent_id: String,
         config: CaracatConfig,
         valid_instance_ids: Vec<u16>,
+        mut spoof_detector: SpoofDetector,
+        spoof_tx: Option<TokioSender<CapturedReply>>,
+        loop_guard: LoopGuard,
+        cancel: CancellationToken,
         runtime_handle: TokioHandle,
     ) -> Self {
-        let stopped = Arc::new(Mutex::new(false));
-        let stopped_thr = stopped.clone();
+        let thread_cancel = cancel;
 
         let metrics_labels = vec![Label::new("agent", agent_id.to_string())];
         let interface_name = config.interface.clone();
+        let statistics = statistics::receive_statistics(&config.interface);
 
         let thread_runtime_handle = runtime_handle.clone();
 
+        // Validated at startup
+        let reply_filter = ReplyFilter::new(&config).unwrap_or_default();
+        if !reply_filter.is_noop() {
+            info!(
+                "Filtering replies on interface {}: {:?}",
+                config.interface, reply_filter
+            );
+        }
+
         let handle = thread::spawn(move || {
+            // Counted as running until the thread exits
+            let _loop_guard = loop_guard;
+            let _interface_span = info_span!("receiver", interface = %interface_name).entered();
             debug!(
                 "ReceiveLoop thread started for interface: {}",
                 interface_name
             );
-            let mut receiver = match Receiver::new_batch(&config.interface) {
+            let mut receiver = match ReplySource::new(&config) {
                 Ok(r) => r,
                 Err(e) => {
                     error!(
                         "Failed to create Caracat receiver for interface {}: {}. ReceiveLoop thread exiting.",
                         config.interface, e
                     );
-                    if let Ok(mut s) = stopped_thr.lock() {
-                        *s = true;
-                    }
-                    return;
+                    return LoopExit::Failed(format!("receiver creation failed: {}", e));
                 }
             };
 
+            let mut pcap_dump = PcapDump::for_instance(&config, receiver.linktype())
+                .unwrap_or_else(|e| {
+                    error!(
+                        "Failed to dump the replies of interface {}: {:#}",
+                        config.interface, e
+                    );
+                    None
+                });
+
+            let mut statistics_labels = metrics_labels.clone();
+            statistics_labels.push(Label::new("interface", config.interface.clone()));
+            let mut statistics_at = Instant::now();
+
             loop {
-                if *stopped_thr.lock().unwrap() {
+                if thread_cancel.is_cancelled() {
                     trace!("Stopping receive loop for interface: {}", config.interface);
                     break;
                 }
 
+                if statistics_at.elapsed() >= STATISTICS_INTERVAL {
+                    statistics_at = Instant::now();
+                    match receiver.statistics() {
+                        Ok(stats) => publish_statistics(&stats, &statistics_labels),
+                        Err(e) => debug!(
+                            "Failed to get the capture statistics of interface {}: {}",
+                            config.interface, e
+                        ),
+                    }
+                    if let Some(pcap_dump) = pcap_dump.as_mut() {
+                        pcap_dump.flush();
+                    }
+                }
+
                 // The `next_reply()` might block, which is fine for a std::thread.
-                let result = receiver.next_reply();
+                let result = receiver.next_reply(pcap_dump.as_mut());
                 match result {
-                    Ok(reply) => {
+                    Ok(captured) => {
+                        let reply = &captured.reply;
                         counter!("saimiris_receiver_received_total", metrics_labels.clone())
                             .increment(1);
-                        if !config.integrity_check
-                            || (config.integrity_check
-                                && Self::is_valid_for_any_instance(&reply, &valid_instance_ids))
-                        {
+                        statistics.replies_received.fetch_add(1, Ordering::Relaxed);
+                        let mut family_labels = metrics_labels.clone();
+                        family_labels
+                            .push(Label::new("family", family_label(reply.reply_src_addr)));
+                        let is_valid = if is_checked(&config, reply.reply_src_addr) {
+                            Self::is_valid_for_any_instance(reply, &valid_instance_ids)
+                        } else {
+                            // Quantify the replies accepted while the check is disabled for their family
+                            if family_override(&config, reply.reply_src_addr) == Some(false)
+                                && !Self::is_valid_for_any_instance(reply, &valid_instance_ids)
+                            {
+                                counter!(
+                                    "saimiris_receiver_unchecked_invalid_total",
+                                    family_labels.clone()
+                                )
+                                .increment(1);
+                            }
+                            true
+                        };
+                        if is_valid {
+                            if !reply_filter.accepts_reply(reply) {
+                                counter!(
+                                    "saimiris_receiver_filtered_total",
+                                    metrics_labels.clone()
+                                )
+                                .increment(1);
+                                continue;
+                            }
+                            if chaos::drop_reply() {
+                                trace!("Dropping reply (fault injection)");
+                                continue;
+                            }
+
                             // Send to the Tokio MPSC channel. This is an async operation,
-                            // so we need to block on it from this synchronous thread.
-                            match thread_runtime_handle.block_on(tx.send(reply)) {
-                                Ok(_) => {
+                            // so we need to block on it from this synchronous thread
+                            // (until the loop is stopped, if the channel stays full).
+                            let sent = thread_runtime_handle.block_on(async {
+                                tokio::select! {
+                                    biased;
+                                    _ = thread_cancel.cancelled() => None,
+                                    result = tx.send(captured) => Some(result),
+                                }
+                            });
+                            match sent {
+                                None => {
+                                    trace!(
+                                        "Stopping receive loop for interface {} while sending a reply.",
+                                        config.interface
+                                    );
+                                    break;
+                                }
+                                Some(Ok(_)) => {
                                     trace!(
                                         "Reply sent from ReceiveLoop for interface: {}",
                                         config.interface
                                     );
                                 }
-                                Err(e) => {
+                                Some(Err(e)) => {
                                     error!(
                                         "Failed to send reply from ReceiveLoop for interface {}: {}. Receiver (Kafka producer) might have shut down. Stopping loop.",
                                         config.interface, e
@@ -91,15 +303,31 @@ impl ReceiveLoop {
                                 }
                             }
                         } else {
-                            counter!(
-                                "saimiris_receiver_received_invalid_total",
-                                metrics_labels.clone()
-                            )
-                            .increment(1);
+                            counter!("saimiris_receiver_received_invalid_total", family_labels)
+                                .increment(1);
+                            statistics.replies_invalid.fetch_add(1, Ordering::Relaxed);
+
+                            if spoof_detector.quotes_our_prefixes(reply) {
+                                counter!("saimiris_receiver_spoofed_total", metrics_labels.clone())
+                                    .increment(1);
+                                if spoof_detector.observe() {
+                                    trace!("{:?} spoofed=true", reply);
+                                    if let Some(ref spoof_tx) = spoof_tx {
+                                        // Never block the receiver on the samples
+                                        if spoof_tx.try_send(captured).is_err() {
+                                            counter!(
+                                                "saimiris_receiver_spoofed_dropped_total",
+                                                metrics_labels.clone()
+                                            )
+                                            .increment(1);
+                                        }
+                                    }
+                                }
+                            }
                         }
                     }
                     Err(error) => {
-                        if *stopped_thr.lock().unwrap() {
+                        if thread_cancel.is_cancelled() {
                             trace!(
                                 "Stopping receive loop for interface {} during error handling.",
                                 config.interface
@@ -107,6 +335,10 @@ impl ReceiveLoop {
                             break;
                         }
 
+                        if error.is::<RingTimeout>() {
+                            // The ring is polled with a timeout, to check whether the loop is stopped
+                            continue;
+                        }
                         counter!(
                             "saimiris_receiver_received_error_total",
                             metrics_labels.clone()
@@ -130,6 +362,13 @@ impl ReceiveLoop {
                                 );
                             }
                         }
+                        // The capture is reopened by the supervisor once the interface is back
+                        if !interface_is_up(&config.interface) {
+                            return LoopExit::Failed(format!(
+                                "interface {} is down",
+                                config.interface
+                            ));
+                        }
                     }
                 }
             }
@@ -137,22 +376,17 @@ impl ReceiveLoop {
                 "ReceiveLoop thread finished for interface: {}",
                 interface_name
             );
+            LoopExit::Stopped
         });
 
-        ReceiveLoop { handle, stopped }
+        ReceiveLoop { handle }
     }
 
-    #[allow(dead_code)]
-    pub fn stop(self) {
-        info!("Requesting stop for ReceiveLoop.");
-        if let Ok(mut stopped_lock) = self.stopped.lock() {
-            *stopped_lock = true;
-        } else {
-            error!("Failed to acquire lock to stop ReceiveLoop.");
-        }
-        match self.handle.join() {
-            Ok(_) => info!("ReceiveLoop successfully joined."),
-            Err(e) => error!("Error joining ReceiveLoop thread: {:?}", e),
+    /// Wait for the ReceiveLoop thread to exit.
+    pub async fn join(self) -> LoopExit {
+        match tokio::task::spawn_blocking(move || self.handle.join()).await {
+            Ok(Ok(exit)) => exit,
+            _ => LoopExit::Failed("the ReceiveLoop thread panicked".to_string()),
         }
     }
 }
@@ -0,0 +1,102 @@
+use anyhow::Result;
+use caracat::models::Reply;
+
+use crate::config::CaracatConfig;
+
+const IPPROTO_ICMP: u8 = 1;
+const IPPROTO_ICMPV6: u8 = 58;
+const ICMP_DEST_UNREACHABLE: u8 = 3;
+const ICMP_TIME_EXCEEDED: u8 = 11;
+const ICMPV6_DEST_UNREACHABLE: u8 = 1;
+const ICMPV6_TIME_EXCEEDED: u8 = 3;
+
+/// Filter applied to the replies before they are forwarded to the producer,
+/// to cut the results volume of traceroute-style campaigns.
+#[derive(Debug, Clone, Default)]
+pub struct ReplyFilter {
+    time_exceeded_only: bool,
+    // ICMP (type, code) allowed, any code if `None`; all types if empty
+    icmp_allowlist: Vec<(u8, Option<u8>)>,
+    exclude_unreachable: bool,
+}
+
+impl ReplyFilter {
+    pub fn new(config: &CaracatConfig) -> Result<Self> {
+        let time_exceeded_only = match config.reply_filter.as_str() {
+            "" | "all" => false,
+            "time-exceeded-only" => true,
+            other => anyhow::bail!(
+                "Invalid reply_filter '{}'. Expected 'all' or 'time-exceeded-only'",
+                other
+            ),
+        };
+
+        let icmp_allowlist = config
+            .reply_icmp_allowlist
+            .iter()
+            .map(|entry| {
+                let invalid = || {
+                    anyhow::anyhow!(
+                        "Invalid reply_icmp_allowlist entry '{}'. Expected 'type' or 'type:code'",
+                        entry
+                    )
+                };
+                match entry.split_once(':') {
+                    Some((icmp_type, icmp_code)) => Ok((
+                        icmp_type.trim().parse().map_err(|_| invalid())?,
+                        Some(icmp_code.trim().parse().map_err(|_| invalid())?),
+                    )),
+                    None => Ok((entry.trim().parse().map_err(|_| invalid())?, None)),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(ReplyFilter {
+            time_exceeded_only,
+            icmp_allowlist,
+            exclude_unreachable: config.reply_exclude_unreachable,
+        })
+    }
+
+    /// Whether the filter lets every reply through.
+    pub fn is_noop(&self) -> bool {
+        !self.time_exceeded_only && self.icmp_allowlist.is_empty() && !self.exclude_unreachable
+    }
+
+    pub fn accepts(&self, protocol: u8, icmp_type: u8, icmp_code: u8) -> bool {
+        let (time_exceeded, unreachable) = match protocol {
+            IPPROTO_ICMP => (
+                icmp_type == ICMP_TIME_EXCEEDED,
+                icmp_type == ICMP_DEST_UNREACHABLE,
+            ),
+            IPPROTO_ICMPV6 => (
+                icmp_type == ICMPV6_TIME_EXCEEDED,
+                icmp_type == ICMPV6_DEST_UNREACHABLE,
+            ),
+            // Not an ICMP reply (e.g. TCP reset)
+            _ => return !self.time_exceeded_only,
+        };
+
+        if self.time_exceeded_only && !time_exceeded {
+            return false;
+        }
+        if self.exclude_unreachable && unreachable {
+            return false;
+        }
+        self.icmp_allowlist.is_empty()
+            || self
+                .icmp_allowlist
+                .iter()
+                .any(|(allowed_type, allowed_code)| {
+                    *allowed_type == icmp_type && allowed_code.is_none_or(|code| code == icmp_code)
+                })
+    }
+
+    pub fn accepts_reply(&self, reply: &Reply) -> bool {
+        self.accepts(
+            reply.reply_protocol,
+            reply.reply_icmp_type,
+            reply.reply_icmp_code,
+        )
+    }
+}
@@ -0,0 +1,227 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use metrics::counter;
+use reqwest::{Client, Url};
+use ring::{digest, hmac};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, error, info, warn};
+
+use crate::config::S3Config;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex(digest::digest(&digest::SHA256, data).as_ref())
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    hmac::sign(&key, data).as_ref().to_vec()
+}
+
+/// AWS Signature Version 4 signing key.
+pub fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let k_date = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let k_region = hmac_sha256(&k_date, region.as_bytes());
+    let k_service = hmac_sha256(&k_region, service.as_bytes());
+    hmac_sha256(&k_service, b"aws4_request")
+}
+
+/// URI-encode an object key, keeping the `/` separators.
+fn encode_key(key: &str) -> String {
+    key.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Key of the `sequence`-th object uploaded by an agent, partitioned by day.
+pub fn object_key(prefix: &str, agent_id: &str, time: DateTime<Utc>, sequence: u64) -> String {
+    format!(
+        "{}{}/{}/{}-{:06}.bin",
+        prefix,
+        agent_id,
+        time.format("%Y/%m/%d"),
+        time.format("%Y%m%dT%H%M%SZ"),
+        sequence
+    )
+}
+
+/// Minimal S3 client, uploading objects with signed PUT requests.
+pub struct S3Client {
+    config: S3Config,
+    client: Client,
+}
+
+impl S3Client {
+    pub fn new(config: S3Config) -> Self {
+        S3Client {
+            config,
+            client: Client::new(),
+        }
+    }
+
+    pub fn object_url(&self, key: &str) -> Result<Url> {
+        let endpoint = Url::parse(&self.config.endpoint)?;
+        let host = endpoint
+            .host_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid S3 endpoint: {}", self.config.endpoint))?;
+        let port = endpoint
+            .port()
+            .map(|port| format!(":{}", port))
+            .unwrap_or_default();
+        let url = if self.config.path_style {
+            format!(
+                "{}://{}{}/{}/{}",
+                endpoint.scheme(),
+                host,
+                port,
+                self.config.bucket,
+                encode_key(key)
+            )
+        } else {
+            format!(
+                "{}://{}.{}{}/{}",
+                endpoint.scheme(),
+                self.config.bucket,
+                host,
+                port,
+                encode_key(key)
+            )
+        };
+        Ok(Url::parse(&url)?)
+    }
+
+    pub async fn put_object(&self, key: &str, body: Vec<u8>) -> Result<()> {
+        let url = self.object_url(key)?;
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = sha256_hex(&body);
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+
+        let canonical_request = format!(
+            "PUT\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+            url.path(),
+            host,
+            payload_hash,
+            amz_date,
+            payload_hash
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            sha256_hex(canonical_request.as_bytes())
+        );
+        let k_signing = signing_key(&self.config.secret_key, &date, &self.config.region, "s3");
+        let signature = hex(&hmac_sha256(&k_signing, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+            self.config.access_key, scope, signature
+        );
+
+        let response = self
+            .client
+            .put(url)
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            anyhow::bail!(
+                "Failed to upload object {}: HTTP {}",
+                key,
+                response.status()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Buffer the serialized replies, and upload them to S3 at every rotation interval,
+/// or once the maximum object size is reached. Objects failing to upload are retried
+/// at the next rotation.
+pub async fn upload_loop(config: S3Config, agent_id: String, mut rx: Receiver<Vec<u8>>) {
+    let max_object_bytes = config.max_object_bytes.max(1);
+    let max_pending_objects = config.max_pending_objects.max(1);
+    let mut rotation = interval(Duration::from_secs(config.rotation_interval.max(1)));
+    rotation.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let prefix = config.prefix.clone();
+    let s3 = S3Client::new(config);
+
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut pending: VecDeque<(String, Vec<u8>)> = VecDeque::new();
+    let mut sequence = 0;
+    let mut closed = false;
+    loop {
+        let rotate = tokio::select! {
+            message = rx.recv(), if !closed => match message {
+                Some(message) => {
+                    buffer.extend_from_slice(&message);
+                    buffer.len() >= max_object_bytes
+                }
+                None => {
+                    info!("Reply channel closed, uploading the remaining replies to S3");
+                    closed = true;
+                    true
+                }
+            },
+            _ = rotation.tick() => true,
+        };
+        if !rotate {
+            continue;
+        }
+
+        if !buffer.is_empty() {
+            let key = object_key(&prefix, &agent_id, Utc::now(), sequence);
+            sequence += 1;
+            pending.push_back((key, std::mem::take(&mut buffer)));
+            while pending.len() > max_pending_objects {
+                if let Some((key, _)) = pending.pop_front() {
+                    warn!("Too many S3 objects pending upload, dropping {}", key);
+                    counter!("saimiris_s3_objects_total", "agent" => agent_id.clone(), "status" => "dropped")
+                        .increment(1);
+                }
+            }
+        }
+
+        while let Some((key, body)) = pending.pop_front() {
+            let size = body.len();
+            match s3.put_object(&key, body.clone()).await {
+                Ok(()) => {
+                    debug!("Uploaded {} bytes of replies to {}", size, key);
+                    counter!("saimiris_s3_objects_total", "agent" => agent_id.clone(), "status" => "success")
+                        .increment(1);
+                }
+                Err(e) => {
+                    error!("{}. Retrying at the next rotation.", e);
+                    counter!("saimiris_s3_objects_total", "agent" => agent_id.clone(), "status" => "failure")
+                        .increment(1);
+                    pending.push_front((key, body));
+                    break;
+                }
+            }
+        }
+
+        if closed && pending.is_empty() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tracing::{debug, error};
+
+use crate::agent::sender::ProbesWithSource;
+use crate::config::CaracatConfig;
+
+/// Probes drained per round for a measurement of weight 1.
+pub const QUANTUM: u64 = 1_000;
+/// Messages queued by the scheduler of an instance, on top of its probes channel.
+pub const MAX_QUEUED_MESSAGES: usize = 100;
+
+/// Per-measurement queues of a caracat instance, drained by priority then in weighted
+/// round-robin (deficit round-robin, in probes), so that small measurements make progress
+/// during bulk scans instead of waiting for their messages to be sent in order.
+///
+/// The messages of a higher priority are sent first, preempting the lower priorities between
+/// messages. Within a priority, the messages of a measurement are kept in order, the messages
+/// without a measurement share a queue. A measurement of weight `w` gets `w * quantum` probes
+/// per round. In FIFO mode, the messages of a priority share a queue and are sent in order.
+#[derive(Debug)]
+pub struct MeasurementQueues {
+    quantum: u64,
+    fair: bool,
+    queues: HashMap<(u32, String), VecDeque<ProbesWithSource>>,
+    // Per priority, measurements with queued messages, in round-robin order, with their
+    // deficit and whether they were granted their quantum in this turn
+    active: BTreeMap<u32, VecDeque<(String, u64, bool)>>,
+    len: usize,
+}
+
+impl MeasurementQueues {
+    pub fn new(quantum: u64) -> Self {
+        MeasurementQueues {
+            quantum: quantum.max(1),
+            fair: true,
+            queues: HashMap::new(),
+            active: BTreeMap::new(),
+            len: 0,
+        }
+    }
+
+    /// Queues sending the messages of a priority in order.
+    pub fn fifo() -> Self {
+        MeasurementQueues {
+            fair: false,
+            ..MeasurementQueues::new(QUANTUM)
+        }
+    }
+
+    /// Queues of the instance, per its `measurement_scheduling`.
+    pub fn for_instance(config: &CaracatConfig) -> Result<Self> {
+        match config
+            .measurement_scheduling
+            .as_deref()
+            .map(str::to_lowercase)
+        {
+            None => Ok(MeasurementQueues::fifo()),
+            Some(scheduling) if scheduling == "fifo" => Ok(MeasurementQueues::fifo()),
+            Some(scheduling) if scheduling == "fair" => Ok(MeasurementQueues::new(QUANTUM)),
+            Some(other) => anyhow::bail!(
+                "Invalid measurement_scheduling '{}'. Expected 'fifo' or 'fair'",
+                other
+            ),
+        }
+    }
+
+    pub fn push(&mut self, message: ProbesWithSource) {
+        let measurement_id = match &message.measurement_info {
+            Some(info) if self.fair => info.measurement_id.clone(),
+            _ => String::new(),
+        };
+        let queue = self
+            .queues
+            .entry((message.priority, measurement_id.clone()))
+            .or_default();
+        if queue.is_empty() {
+            self.active
+                .entry(message.priority)
+                .or_default()
+                .push_back((measurement_id, 0, false));
+        }
+        queue.push_back(message);
+        self.len += 1;
+    }
+
+    /// Next message to send: the first message of the measurement whose turn it is in the
+    /// highest priority, once its deficit covers the probes of the message.
+    pub fn pop(&mut self) -> Option<ProbesWithSource> {
+        let mut active = self.active.last_entry()?;
+        let priority = *active.key();
+        loop {
+            let (measurement_id, mut deficit, mut granted) = active.get_mut().pop_front()?;
+            let key = (priority, measurement_id);
+            let queue = self.queues.get_mut(&key)?;
+            let head = queue.front()?;
+            let probes = head.probes.len() as u64;
+            // The quantum is granted once per turn
+            if deficit < probes && !granted {
+                let weight = head
+                    .measurement_info
+                    .as_ref()
+                    .and_then(|info| info.weight)
+                    .unwrap_or(1)
+                    .max(1) as u64;
+                deficit += weight * self.quantum;
+                granted = true;
+            }
+            if self.fair && deficit < probes {
+                // Next measurement, the deficit accumulates over the rounds
+                active.get_mut().push_back((key.1, deficit, false));
+                continue;
+            }
+            let message = queue.pop_front()?;
+            self.len -= 1;
+            if queue.is_empty() {
+                // Idle measurements do not keep their deficit
+                self.queues.remove(&key);
+                if active.get().is_empty() {
+                    active.remove();
+                }
+            } else {
+                active
+                    .get_mut()
+                    .push_front((key.1, deficit.saturating_sub(probes), granted));
+            }
+            return Some(message);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Drain the probes of an instance into its SendLoop (or its shards) by priority, and
+/// measurement by measurement in weighted round-robin with the fair scheduling.
+pub async fn schedule_loop(
+    mut rx: Receiver<ProbesWithSource>,
+    tx: Sender<ProbesWithSource>,
+    mut queues: MeasurementQueues,
+) {
+    let mut closed = false;
+    loop {
+        // Wait for probes when there is nothing to send, and queue those available otherwise
+        if queues.is_empty() {
+            if closed {
+                break;
+            }
+            match rx.recv().await {
+                Some(message) => queues.push(message),
+                None => break,
+            }
+        }
+        while !closed && queues.len() < MAX_QUEUED_MESSAGES {
+            match rx.try_recv() {
+                Ok(message) => queues.push(message),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => closed = true,
+            }
+        }
+
+        if let Some(message) = queues.pop() {
+            if let Err(e) = tx.send(message).await {
+                error!("Failed to schedule probes to the sender: {}", e);
+                return;
+            }
+        }
+    }
+    debug!("Probe channel closed, stopping the measurement scheduler");
+}
@@ -1,49 +1,582 @@
+use anyhow::Context;
 use caracat::models::Probe;
 use caracat::rate_limiter::RateLimiter;
 use caracat::rate_limiter::RateLimitingMethod;
 use caracat::sender::Sender as CaracatSender;
-use metrics::counter;
 use metrics::Label;
-use std::collections::HashMap;
-use std::net::IpAddr;
+use metrics::{counter, gauge, histogram};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::thread::JoinHandle;
-use tokio::runtime::Handle as TokioHandle;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
 use tracing::warn;
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, info, info_span, trace, Instrument, Span};
 
+use crate::agent::chaos;
+use crate::agent::commit::MessageAck;
+use crate::agent::control::AgentMode;
+use crate::agent::correlation::{ProbeKey, SharedCorrelationTable};
+use crate::agent::dedup::ProbeDedup;
+use crate::agent::emission::EmissionChecker;
+use crate::agent::events::{EventKind, EventLog};
+use crate::agent::exemplars::{self, Exemplar};
+use crate::agent::failover::{instance_label, interface_is_up, Failover, FailureCounter};
+use crate::agent::fairness::FairnessScheduler;
+use crate::agent::gateway::MeasurementInfo;
+use crate::agent::measurement_labels;
+#[cfg(target_os = "linux")]
+use crate::agent::mmsg::MmsgSender;
+use crate::agent::policy::ProbePolicy;
+use crate::agent::prefix_set::PrefixSet;
+use crate::agent::quota::MeasurementQuota;
+use crate::agent::ratelimit::{DestinationRateLimiter, SharedRateLimiter, SourceRateLimiter};
+use crate::agent::server::LoopGuard;
+use crate::agent::statistics::{self, SendStatistics};
+use crate::agent::supervisor::{catch_panic, supervise, Backoff, LoopExit, LoopHealth};
+use crate::agent::tx_ring::SenderBackend;
+#[cfg(all(target_os = "linux", feature = "tx-ring"))]
+use crate::agent::tx_ring::TxRingSender;
 use crate::config::CaracatConfig;
+use crate::measurement::{Measurement, MeasurementStatus, MeasurementTiming};
+use crate::probe::{ProbeContext, ProbeTags};
 
 // Type to represent probes with their source IP and measurement tracking info
 #[derive(Debug)]
 pub struct ProbesWithSource {
     pub probes: Vec<Probe>,
+    // Per-probe tags, parallel to `probes` (empty if the message carried none)
+    pub tags: Vec<ProbeTags>,
     pub source_ip: String,
-    pub measurement_info: Option<crate::agent::gateway::MeasurementInfo>,
+    pub measurement_info: Option<MeasurementInfo>,
+    // Messages of a higher priority are scheduled first (`priority` header)
+    pub priority: u32,
+    // Acknowledges the Kafka message once all its shards are sent (after-send commit strategy)
+    pub ack: Option<Arc<MessageAck>>,
 }
 
-pub struct SendLoop {
-    handle: JoinHandle<()>,
-    stopped: Arc<Mutex<bool>>,
+// Measurements sent by the workers of a caracat instance
+#[derive(Debug, Default)]
+pub struct MeasurementProgress {
+    measurements: HashMap<String, Measurement>,
+    events: EventLog,
 }
 
-impl SendLoop {
-    pub fn new(
-        mut rx: tokio::sync::mpsc::Receiver<ProbesWithSource>,
-        config: CaracatConfig,
-        app_config: &crate::config::AppConfig,
-        runtime_handle: TokioHandle,
-    ) -> Self {
-        // Extract needed values from app_config
-        let agent_id = app_config.agent.id.clone();
-        let gateway_url = app_config.gateway.as_ref().and_then(|g| g.url.clone());
-        let agent_key = app_config
-            .gateway
-            .as_ref()
-            .and_then(|g| g.agent_key.clone());
+pub type SharedMeasurementProgress = Arc<Mutex<MeasurementProgress>>;
+
+impl MeasurementProgress {
+    /// Track measurements, emitting an event when they are accepted and completed.
+    pub fn with_events(events: EventLog) -> Self {
+        MeasurementProgress {
+            events,
+            ..Default::default()
+        }
+    }
+
+    /// Event log of the agent the measurements are sent by.
+    pub fn events(&self) -> &EventLog {
+        &self.events
+    }
+
+    fn measurement(&mut self, measurement_id: &str) -> &mut Measurement {
+        self.measurements
+            .entry(measurement_id.to_string())
+            .or_insert_with(|| Measurement::new(measurement_id))
+    }
+
+    /// Status of a measurement, `None` if it is not tracked.
+    pub fn status(&self, measurement_id: &str) -> Option<MeasurementStatus> {
+        self.measurements
+            .get(measurement_id)
+            .map(Measurement::status)
+    }
+
+    /// Mark a measurement as being sent, once per measurement.
+    /// The submission time is the earliest of its messages.
+    pub fn start(&mut self, measurement_id: &str, submitted_at_ms: Option<i64>) {
+        self.measurement(measurement_id).start(submitted_at_ms);
+    }
+
+    /// Timing of a complete measurement, forgotten afterwards.
+    pub fn take_timing(&mut self, measurement_id: &str) -> Option<MeasurementTiming> {
+        let measurement = self.measurements.get_mut(measurement_id)?;
+        let timing = measurement.take_timing();
+        if measurement.is_done() {
+            self.measurements.remove(measurement_id);
+        }
+        timing
+    }
+
+    /// Record the probes sent by one worker for a measurement message.
+    /// Returns the total number of probes sent so far, and whether the measurement is complete,
+    /// that is, all `workers` have sent their share of the last message.
+    pub fn record(
+        &mut self,
+        measurement_id: &str,
+        sent: u32,
+        end_of_measurement: bool,
+        workers: usize,
+    ) -> (u32, bool) {
+        let measurement = self
+            .measurements
+            .entry(measurement_id.to_string())
+            .or_insert_with(|| Measurement::new(measurement_id));
+        // Cancelled measurements are no longer tracked
+        if measurement.status() == MeasurementStatus::Cancelled {
+            return (0, false);
+        }
+        if measurement.status() != MeasurementStatus::Running {
+            self.events.emit(
+                EventKind::MeasurementAccepted,
+                Some(measurement_id),
+                BTreeMap::new(),
+            );
+        }
+        measurement.record(sent, end_of_measurement, workers);
+        let total_sent = measurement.sent_probes();
+        let complete = measurement.status() == MeasurementStatus::Completed;
+        if complete {
+            self.events.emit(
+                EventKind::MeasurementCompleted,
+                Some(measurement_id),
+                BTreeMap::from([("sent_probes".to_string(), total_sent.to_string())]),
+            );
+            if measurement.is_done() {
+                self.measurements.remove(measurement_id);
+            }
+        }
+        (total_sent, complete)
+    }
+
+    /// Cancel a measurement, so that its remaining probes are dropped.
+    /// Returns the number of probes sent so far.
+    pub fn cancel(&mut self, measurement_id: &str) -> u32 {
+        self.measurement(measurement_id).cancel()
+    }
+
+    pub fn is_cancelled(&self, measurement_id: &str) -> bool {
+        self.status(measurement_id) == Some(MeasurementStatus::Cancelled)
+    }
+
+    /// Report that a measurement exceeded the maximum number of probes of the agent.
+    pub fn exceed_limit(&self, measurement_id: &str, max_probes: u64) {
+        self.events.emit(
+            EventKind::MeasurementLimitExceeded,
+            Some(measurement_id),
+            BTreeMap::from([("max_probes".to_string(), max_probes.to_string())]),
+        );
+    }
+}
+
+/// Split probes into `shards` messages by destination address, so that all the probes towards
+/// a destination are sent by the same worker. Always returns `shards` messages, some may be empty.
+pub fn shard_probes(probes_with_source: ProbesWithSource, shards: usize) -> Vec<ProbesWithSource> {
+    let shards = shards.max(1);
+    let mut sharded: Vec<ProbesWithSource> = (0..shards)
+        .map(|_| ProbesWithSource {
+            probes: Vec::new(),
+            tags: Vec::new(),
+            source_ip: probes_with_source.source_ip.clone(),
+            measurement_info: probes_with_source.measurement_info.clone(),
+            priority: probes_with_source.priority,
+            ack: probes_with_source.ack.clone(),
+        })
+        .collect();
+
+    let has_tags = !probes_with_source.tags.is_empty();
+    let mut tags = probes_with_source.tags.into_iter();
+    for probe in probes_with_source.probes {
+        let mut hasher = DefaultHasher::new();
+        probe.dst_addr.hash(&mut hasher);
+        let shard = &mut sharded[(hasher.finish() % shards as u64) as usize];
+        if has_tags {
+            shard.tags.push(tags.next().unwrap_or_default());
+        }
+        shard.probes.push(probe);
+    }
+    sharded
+}
+
+/// Dispatch probes received for a caracat instance to its workers.
+pub async fn shard_loop(
+    mut rx: tokio::sync::mpsc::Receiver<ProbesWithSource>,
+    workers: Vec<tokio::sync::mpsc::Sender<ProbesWithSource>>,
+) {
+    while let Some(probes_with_source) = rx.recv().await {
+        for (shard, worker) in shard_probes(probes_with_source, workers.len())
+            .into_iter()
+            .zip(workers.iter())
+        {
+            // Workers must see every measurement message to report its completion
+            if shard.probes.is_empty() && shard.measurement_info.is_none() {
+                continue;
+            }
+            if let Err(e) = worker.send(shard).await {
+                error!("Failed to dispatch probes to sender worker: {}", e);
+                return;
+            }
+        }
+    }
+    debug!("Probe channel closed, stopping sender workers dispatch");
+}
+
+/// Mark an instance as failed over to its backup, logging and flagging it in metrics
+/// if it was not already.
+fn activate_failover(failover: &Failover, agent_id: &str, reason: &str) {
+    if failover.state.activate(&failover.instance) {
+        warn!(
+            "Instance {} failing over to backup instance {}: {}",
+            failover.instance, failover.backup, reason
+        );
+        gauge!("saimiris_sender_failover", "agent" => agent_id.to_string(), "instance" => failover.instance.clone())
+            .set(1.0);
+    }
+}
+
+/// Sender of the probes of a source address, with the backend of the instance.
+enum ProbeSender {
+    Caracat(CaracatSender),
+    #[cfg(target_os = "linux")]
+    Mmsg(MmsgSender),
+    #[cfg(all(target_os = "linux", feature = "tx-ring"))]
+    TxRing(TxRingSender),
+}
+
+impl ProbeSender {
+    fn new(
+        backend: SenderBackend,
+        interface: &str,
+        src_ipv4: Option<Ipv4Addr>,
+        src_ipv6: Option<Ipv6Addr>,
+        instance_id: u16,
+        dry_run: bool,
+    ) -> anyhow::Result<Self> {
+        match backend {
+            #[cfg(target_os = "linux")]
+            SenderBackend::Caracat => Ok(ProbeSender::Mmsg(MmsgSender::new(
+                interface,
+                src_ipv4,
+                src_ipv6,
+                instance_id,
+                dry_run,
+            )?)),
+            #[cfg(not(target_os = "linux"))]
+            SenderBackend::Caracat => Ok(ProbeSender::Caracat(CaracatSender::new(
+                interface,
+                src_ipv4,
+                src_ipv6,
+                instance_id,
+                dry_run,
+            )?)),
+            SenderBackend::Pcap => Ok(ProbeSender::Caracat(CaracatSender::new(
+                interface,
+                src_ipv4,
+                src_ipv6,
+                instance_id,
+                dry_run,
+            )?)),
+            #[cfg(all(target_os = "linux", feature = "tx-ring"))]
+            SenderBackend::TxRing => Ok(ProbeSender::TxRing(TxRingSender::new(
+                interface,
+                src_ipv4,
+                src_ipv6,
+                instance_id,
+                dry_run,
+            )?)),
+            #[cfg(not(all(target_os = "linux", feature = "tx-ring")))]
+            SenderBackend::TxRing => unreachable!(),
+        }
+    }
+
+    /// Send a probe, marked with `dscp` by the backends supporting it.
+    fn send(&mut self, probe: &Probe, dscp: u8) -> anyhow::Result<()> {
+        match self {
+            ProbeSender::Caracat(sender) => {
+                // The marked probes are filtered out before
+                debug_assert_eq!(dscp, 0);
+                sender.send(probe)
+            }
+            #[cfg(target_os = "linux")]
+            ProbeSender::Mmsg(sender) => sender.send(probe, dscp),
+            #[cfg(all(target_os = "linux", feature = "tx-ring"))]
+            ProbeSender::TxRing(sender) => sender.send(probe, dscp),
+        }
+    }
+
+    /// Send the probes queued by the backend, at the end of a burst.
+    fn flush(&mut self) -> anyhow::Result<()> {
+        match self {
+            ProbeSender::Caracat(_) => Ok(()),
+            #[cfg(target_os = "linux")]
+            ProbeSender::Mmsg(sender) => sender.flush(),
+            #[cfg(all(target_os = "linux", feature = "tx-ring"))]
+            ProbeSender::TxRing(sender) => sender.flush(),
+        }
+    }
+}
+
+/// Outcome of the probes of a message sent by the blocking worker of a SendLoop.
+#[derive(Debug, Default)]
+struct SendOutcome {
+    sent: u64,
+    // Index of the first probe not sent when failing over mid-message
+    failover_at: Option<usize>,
+    // The SendLoop was stopped mid-message
+    stopped: bool,
+}
+
+/// Packet I/O of a SendLoop: the senders of its source addresses and the rate limiting of the
+/// probes. Moved to a blocking thread while it sends the probes of a message, and back to the
+/// SendLoop task in between.
+struct BlockingSender {
+    config: CaracatConfig,
+    encoding_id: u16,
+    // Cache of the senders per source IP
+    senders: HashMap<String, ProbeSender>,
+    rate_limiter: RateLimiter,
+    source_limiter: SourceRateLimiter,
+    // Consecutive send failures
+    failures: FailureCounter,
+    fails_over: bool,
+    mode: watch::Receiver<AgentMode>,
+    cancel: CancellationToken,
+    progress: SharedMeasurementProgress,
+    emission: Option<EmissionChecker>,
+    total_rate: Option<SharedRateLimiter>,
+    dst_rate: Option<DestinationRateLimiter>,
+    fairness: Option<FairnessScheduler>,
+    metrics_labels: Vec<Label>,
+    pps_labels: Vec<Label>,
+    // Window of the achieved sending rate, kept across the messages
+    pps_window_start: Instant,
+    pps_window_sent: u64,
+    statistics: Arc<SendStatistics>,
+}
+
+impl BlockingSender {
+    /// Send probes in bursts of `send_batch_size` with the sender of `sender_key` (handed to
+    /// the kernel at once, but by the libpcap sender), until they are all sent, their
+    /// measurement is cancelled, the instance fails over, or the SendLoop is stopped (checked
+    /// before each probe). The probes are marked with the DSCP of their `tags`.
+    fn send(
+        &mut self,
+        sender_key: &str,
+        probes: &[Probe],
+        tags: &[ProbeTags],
+        source_addr: Option<IpAddr>,
+        measurement_info: Option<&MeasurementInfo>,
+        counter_labels: &[Label],
+    ) -> SendOutcome {
+        let mut outcome = SendOutcome::default();
+        let Some(sender) = self.senders.get_mut(sender_key) else {
+            return outcome;
+        };
+        // On top of the instance rate, probes from a rate limited source address
+        // wait for a token of their own
+        let mut source_bucket = source_addr.and_then(|source| self.source_limiter.bucket(&source));
+
+        // The probes of a burst are queued by the sender and handed to the kernel on flush
+        // (the libpcap sender writes them one by one). The bookkeeping (pause and cancellation
+        // checks, metrics) is done once per burst.
+        let burst_size = self.config.send_batch_size.max(1) as usize;
+        for (burst_index, burst) in probes.chunks(burst_size).enumerate() {
+            // Hold the probes while the agent is paused
+            while !self.mode.borrow().sends_probes() && !self.cancel.is_cancelled() {
+                thread::sleep(Duration::from_millis(100));
+            }
+            if self.cancel.is_cancelled() {
+                trace!(
+                    "Stopping SendLoop mid-batch for interface: {}",
+                    self.config.interface
+                );
+                outcome.stopped = true;
+                break;
+            }
+
+            if is_cancelled(&self.progress, measurement_info) {
+                counter!(
+                    "saimiris_sender_cancelled_total",
+                    self.metrics_labels.clone()
+                )
+                .increment((probes.len() - burst_index * burst_size) as u64);
+                break;
+            }
+
+            // Stay within the probing rate of the whole agent
+            if let Some(ref total_rate) = self.total_rate {
+                total_rate.wait(burst.len() as u64 * self.config.packets);
+            }
+
+            let mut sent_count_burst = 0;
+            let mut failed_count_burst = 0;
+            'burst: for (j, probe) in burst.iter().enumerate() {
+                if self.cancel.is_cancelled() {
+                    outcome.stopped = true;
+                    break;
+                }
+                for i in 0..self.config.packets {
+                    trace!(
+                        "{:?} id={} packet={}",
+                        probe,
+                        probe.checksum(self.encoding_id),
+                        i + 1
+                    );
+                    if let Some(bucket) = source_bucket.as_mut() {
+                        bucket.wait();
+                    }
+                    if let Some(ref dst_rate) = self.dst_rate {
+                        if dst_rate.wait(&probe.dst_addr) {
+                            counter!(
+                                "saimiris_sender_dst_rate_limited_total",
+                                self.metrics_labels.clone()
+                            )
+                            .increment(1);
+                        }
+                    }
+                    if let Some(ref fairness) = self.fairness {
+                        if fairness.wait(&probe.dst_addr) {
+                            counter!(
+                                "saimiris_sender_network_rate_limited_total",
+                                self.metrics_labels.clone()
+                            )
+                            .increment(1);
+                        }
+                    }
+                    let dscp = tags
+                        .get(burst_index * burst_size + j)
+                        .map_or(0, |tags| tags.dscp);
+                    let result = sender.send(probe, dscp);
+                    let threshold_reached = self.failures.record(result.is_ok());
+                    match result {
+                        Ok(_) => {
+                            sent_count_burst += 1;
+                            outcome.sent += 1;
+                            if let Some(ref emission) = self.emission {
+                                emission.sample(probe, source_addr);
+                            }
+                        }
+                        Err(error) => {
+                            error!(
+                                "Error sending probe on interface {}: {}",
+                                self.config.interface, error
+                            );
+                            failed_count_burst += 1;
+                        }
+                    }
+                    if threshold_reached && self.fails_over {
+                        outcome.failover_at = Some(burst_index * burst_size + j);
+                        break 'burst;
+                    }
+                    if outcome.sent % self.config.batch_size == 0 && outcome.sent > 0 {
+                        self.rate_limiter.wait();
+                    }
+                }
+            }
+            if let Err(error) = sender.flush() {
+                error!(
+                    "Error flushing probes on interface {}: {}",
+                    self.config.interface, error
+                );
+                self.failures.record(false);
+                // The probes queued by the burst were not sent
+                outcome.sent -= sent_count_burst;
+                failed_count_burst += sent_count_burst;
+                sent_count_burst = 0;
+            }
+
+            counter!("saimiris_sender_sent_total", counter_labels.to_vec())
+                .increment(sent_count_burst);
+            self.statistics
+                .probes_sent
+                .fetch_add(sent_count_burst, Ordering::Relaxed);
+            if let Some(measurement_info) = measurement_info {
+                if sent_count_burst > 0 {
+                    exemplars::record(
+                        "saimiris_sender_sent_total",
+                        counter_labels,
+                        Exemplar::new(
+                            &measurement_info.measurement_id,
+                            measurement_info.trace_id.as_deref(),
+                            sent_count_burst as f64,
+                        ),
+                    );
+                }
+            }
+            counter!("saimiris_sender_failed_total", counter_labels.to_vec())
+                .increment(failed_count_burst);
+            self.statistics
+                .probes_failed
+                .fetch_add(failed_count_burst, Ordering::Relaxed);
+            if outcome.failover_at.is_some() || outcome.stopped {
+                break;
+            }
+
+            // Report the achieved sending rate about once per second
+            self.pps_window_sent += sent_count_burst;
+            let elapsed = self.pps_window_start.elapsed();
+            if elapsed >= Duration::from_secs(1) {
+                gauge!("saimiris_sender_pps", self.pps_labels.clone())
+                    .set(self.pps_window_sent as f64 / elapsed.as_secs_f64());
+                self.pps_window_start = Instant::now();
+                self.pps_window_sent = 0;
+            }
+        }
+        outcome
+    }
+}
+
+/// Whether the probes of a measurement are dropped, as it was cancelled.
+fn is_cancelled(
+    progress: &SharedMeasurementProgress,
+    measurement_info: Option<&MeasurementInfo>,
+) -> bool {
+    measurement_info.is_some_and(|m| progress.lock().unwrap().is_cancelled(&m.measurement_id))
+}
 
-        let method = match config.rate_limiting_method.to_lowercase().as_str() {
+/// Orchestration of a SendLoop: receives the messages of the instance, filters their probes and
+/// reports the progress of their measurements, and hands the probes over to its blocking sender.
+/// Restarted from a copy of its initial state when it fails.
+#[derive(Clone)]
+struct SendTask {
+    config: CaracatConfig,
+    agent_id: String,
+    gateway_url: Option<String>,
+    agent_key: Option<String>,
+    worker: usize,
+    workers: usize,
+    encoding_id: u16,
+    sender_backend: SenderBackend,
+    // Probing rate of the client-provided source addresses
+    source_limiter: SourceRateLimiter,
+    correlation: SharedCorrelationTable,
+    progress: SharedMeasurementProgress,
+    mode: watch::Receiver<AgentMode>,
+    cancel: CancellationToken,
+    failover: Option<Failover>,
+    // Whether this worker last saw the instance failed over
+    failed_over: bool,
+    policy: ProbePolicy,
+    fairness: Option<FairnessScheduler>,
+    blocklist: Arc<PrefixSet>,
+    measurement_quota: Option<MeasurementQuota>,
+    dedup: Option<ProbeDedup>,
+    emission: Option<EmissionChecker>,
+    total_rate: Option<SharedRateLimiter>,
+    dst_rate: Option<DestinationRateLimiter>,
+    statistics: Arc<SendStatistics>,
+    metrics_labels: Vec<Label>,
+    pps_labels: Vec<Label>,
+}
+
+impl SendTask {
+    /// Blocking sender of the SendLoop, created when it starts and after it failed.
+    fn blocking_sender(&self) -> BlockingSender {
+        let method = match self.config.rate_limiting_method.to_lowercase().as_str() {
             "auto" => RateLimitingMethod::Auto,
             "active" => RateLimitingMethod::Active,
             "sleep" => RateLimitingMethod::Sleep,
@@ -56,300 +589,717 @@ impl SendLoop {
                 RateLimitingMethod::Auto
             }
         };
-        let mut rate_limiter = RateLimiter::new(config.probing_rate, config.batch_size, method);
-
-        let stopped = Arc::new(Mutex::new(false));
-        let stopped_thr = stopped.clone();
-        let interface_name = config.interface.clone();
-
-        let metrics_labels = vec![Label::new("agent", agent_id.to_string())];
-
-        // Clone the handle to move into the thread
-        let thread_runtime_handle = runtime_handle.clone();
-
-        let handle = thread::spawn(move || {
-            debug!("SendLoop thread started for interface: {}", interface_name);
-
-            // Cache of CaracatSender instances per source IP
-            let mut caracat_senders: HashMap<String, CaracatSender> = HashMap::new();
-            // Track probes sent per measurement
-            let mut probes_sent_in_measurement: HashMap<String, u32> = HashMap::new();
+        BlockingSender {
+            config: self.config.clone(),
+            encoding_id: self.encoding_id,
+            senders: HashMap::new(),
+            rate_limiter: RateLimiter::new(
+                self.config.probing_rate,
+                self.config.batch_size,
+                method,
+            ),
+            source_limiter: self.source_limiter.clone(),
+            failures: FailureCounter::new(
+                self.failover
+                    .as_ref()
+                    .map_or(u64::MAX, |failover| failover.threshold),
+            ),
+            fails_over: self.failover.is_some(),
+            mode: self.mode.clone(),
+            cancel: self.cancel.clone(),
+            progress: self.progress.clone(),
+            emission: self.emission.clone(),
+            total_rate: self.total_rate.clone(),
+            dst_rate: self.dst_rate.clone(),
+            fairness: self.fairness.clone(),
+            metrics_labels: self.metrics_labels.clone(),
+            pps_labels: self.pps_labels.clone(),
+            pps_window_start: Instant::now(),
+            pps_window_sent: 0,
+            statistics: self.statistics.clone(),
+        }
+    }
 
-            // Extra logging for debugging SendLoop lifecycle
-            info!("SendLoop for interface {} is running.", config.interface);
+    /// Send the messages of `rx` until it is closed or the loop is stopped, or until the
+    /// blocking sender fails.
+    async fn run(mut self, rx: &mut tokio::sync::mpsc::Receiver<ProbesWithSource>) -> LoopExit {
+        info!(
+            "SendLoop for interface {} is running.",
+            self.config.interface
+        );
+        let cancel = self.cancel.clone();
+        let mut blocking = Some(self.blocking_sender());
 
-            loop {
-                if *stopped_thr.lock().unwrap() {
-                    trace!("Stopping SendLoop for interface: {}", config.interface);
-                    break;
+        loop {
+            trace!(
+                "SendLoop waiting for probes on interface: {}",
+                self.config.interface
+            );
+            let probes_with_source = tokio::select! {
+                biased;
+                _ = cancel.cancelled() => {
+                    trace!("Stopping SendLoop for interface: {}", self.config.interface);
+                    return LoopExit::Stopped;
                 }
-
-                trace!(
-                    "SendLoop waiting for probes on interface: {}",
-                    config.interface
-                );
-                let probes_with_source = match thread_runtime_handle.block_on(rx.recv()) {
-                    Some(p) => {
-                        trace!(
-                            "SendLoop successfully received probes from channel for interface: {}",
-                            config.interface
-                        );
-                        p
-                    }
+                probes_with_source = rx.recv() => match probes_with_source {
+                    Some(p) => p,
                     None => {
                         info!(
                             "Probe channel closed for SendLoop on interface {}. Exiting loop.",
-                            config.interface
+                            self.config.interface
                         );
-                        break;
+                        return LoopExit::Stopped;
                     }
-                };
+                },
+            };
 
-                let source_ip = probes_with_source.source_ip.clone();
-                let measurement_info = probes_with_source.measurement_info.clone();
-                let probes = probes_with_source.probes;
+            // Logged with the fields of the measurement being sent
+            let span = match probes_with_source.measurement_info {
+                Some(ref m) => info_span!("measurement", measurement_id = %m.measurement_id),
+                None => Span::none(),
+            };
+            // Stopping interrupts the gateway reports and forwarding, and the sending of the
+            // probes before the next one
+            let sent = tokio::select! {
+                biased;
+                _ = cancel.cancelled() => None,
+                result = self.send(&mut blocking, probes_with_source).instrument(span) => Some(result),
+            };
+            match sent {
+                None => {
+                    trace!("Stopping SendLoop for interface: {}", self.config.interface);
+                    return LoopExit::Stopped;
+                }
+                Some(Err(e)) => {
+                    // The probes of the message are lost, the loop is restarted by its supervisor
+                    error!(
+                        "SendLoop for interface {} failed: {:#}",
+                        self.config.interface, e
+                    );
+                    return LoopExit::Failed(format!("{:#}", e));
+                }
+                Some(Ok(())) => {}
+            }
+        }
+    }
 
-                trace!("SendLoop received {} probes for interface {}, source_ip: {}, measurement_id: {:?}",
-                       probes.len(), config.interface, source_ip, measurement_info.as_ref().map(|m| &m.measurement_id));
+    /// Send the probes of a message, or forward them to the backup instance.
+    async fn send(
+        &mut self,
+        blocking: &mut Option<BlockingSender>,
+        probes_with_source: ProbesWithSource,
+    ) -> anyhow::Result<()> {
+        let source_ip = probes_with_source.source_ip;
+        let measurement_info = probes_with_source.measurement_info;
+        // Probes counted by measurement and client, within the limit of distinct labels
+        let mut counter_labels = self.metrics_labels.clone();
+        counter_labels.extend(measurement_labels::labels(
+            measurement_info.as_ref().map(|m| m.measurement_id.as_str()),
+            measurement_info
+                .as_ref()
+                .and_then(|m| m.client_id.as_deref()),
+        ));
+        let probes = probes_with_source.probes;
+        let tags = probes_with_source.tags;
+        // Dropped at the end of the message, once the probes are sent
+        // (or forwarded with the probes to the backup instance)
+        let priority = probes_with_source.priority;
+        let ack = probes_with_source.ack;
 
-                counter!("saimiris_sender_read_total", metrics_labels.clone())
-                    .increment(probes.len().try_into().unwrap_or(0));
+        trace!(
+            "SendLoop received {} probes for interface {}, source_ip: {}, measurement_id: {:?}",
+            probes.len(),
+            self.config.interface,
+            source_ip,
+            measurement_info.as_ref().map(|m| &m.measurement_id)
+        );
 
-                // Determine if we should use a specific source IP or default behavior
-                let use_default_source = source_ip.is_empty();
-                let sender_key = if use_default_source {
-                    "default".to_string()
-                } else {
-                    source_ip.clone()
-                };
+        counter!("saimiris_sender_read_total", counter_labels.clone())
+            .increment(probes.len().try_into().unwrap_or(0));
+        self.statistics
+            .probes_read
+            .fetch_add(probes.len() as u64, Ordering::Relaxed);
+        if chaos::panic_sender() {
+            panic!("Injected SendLoop panic");
+        }
 
-                trace!(
-                    "SendLoop determining sender key: use_default_source={}, sender_key={}",
-                    use_default_source,
-                    sender_key
-                );
+        // Drop the probes of cancelled measurements
+        if is_cancelled(&self.progress, measurement_info.as_ref()) {
+            debug!(
+                "Dropping {} probes of cancelled measurement {:?}",
+                probes.len(),
+                measurement_info.as_ref().map(|m| &m.measurement_id)
+            );
+            counter!(
+                "saimiris_sender_cancelled_total",
+                self.metrics_labels.clone()
+            )
+            .increment(probes.len() as u64);
+            return Ok(());
+        }
 
-                // Get or create CaracatSender for this sender key
-                trace!(
-                    "SendLoop looking for existing sender for key: {}",
-                    sender_key
-                );
-                let caracat_sender = match caracat_senders.get_mut(&sender_key) {
-                    Some(sender) => {
-                        trace!("SendLoop found existing sender for key: {}", sender_key);
-                        sender
-                    }
-                    None => {
-                        trace!("SendLoop creating new sender for key: {}", sender_key);
-                        let (src_ipv4, src_ipv6) = if use_default_source {
-                            // Use default behavior - let CaracatSender choose source IPs
-                            (None, None)
-                        } else {
-                            // Parse the source IP to determine if it's IPv4 or IPv6
-                            let parsed_ip: IpAddr = match source_ip.parse() {
-                                Ok(ip) => ip,
-                                Err(e) => {
-                                    error!(
-                                        "Invalid source IP address '{}': {}. Skipping probes.",
-                                        source_ip, e
-                                    );
-                                    continue;
-                                }
-                            };
-
-                            match parsed_ip {
-                                IpAddr::V4(ipv4) => (Some(ipv4), None),
-                                IpAddr::V6(ipv6) => (None, Some(ipv6)),
-                            }
-                        };
-
-                        trace!("SendLoop attempting to create CaracatSender with src_ipv4: {:?}, src_ipv6: {:?}", src_ipv4, src_ipv6);
-
-                        // Create the sender with a timeout to prevent hanging
-                        let interface_name = config.interface.clone();
-                        let instance_id = config.instance_id;
-                        let dry_run = config.dry_run;
-
-                        let caracat_sender_result = thread_runtime_handle.block_on(async {
-                            match tokio::time::timeout(
-                                std::time::Duration::from_secs(5),
-                                tokio::task::spawn_blocking(move || {
-                                    CaracatSender::new(
-                                        &interface_name,
-                                        src_ipv4,
-                                        src_ipv6,
-                                        instance_id,
-                                        dry_run,
-                                    )
-                                }),
-                            )
-                            .await
-                            {
-                                Ok(Ok(join_result)) => join_result,
-                                Ok(Err(e)) => Err(anyhow::anyhow!(
-                                    "CaracatSender::new() task panicked: {:?}",
-                                    e
-                                )),
-                                Err(_) => Err(anyhow::anyhow!(
-                                    "CaracatSender::new() timed out after 5 seconds"
-                                )),
-                            }
-                        });
-
-                        match caracat_sender_result {
-                            Ok(sender) => {
-                                trace!(
-                                    "SendLoop successfully created CaracatSender for key: {}",
-                                    sender_key
-                                );
-                                if use_default_source {
-                                    debug!(
-                                        "Created new CaracatSender with default source IP behavior on interface {}",
-                                        config.interface
-                                    );
-                                } else {
-                                    debug!(
-                                        "Created new CaracatSender for source IP {} on interface {}",
-                                        source_ip, config.interface
-                                    );
-                                }
-                                caracat_senders.insert(sender_key.clone(), sender);
-                                caracat_senders.get_mut(&sender_key).unwrap()
-                            }
-                            Err(e) => {
-                                trace!("SendLoop failed to create CaracatSender for key: {}, error: {}", sender_key, e);
-                                if use_default_source {
-                                    error!(
-                                        "Failed to create Caracat sender with default source IP behavior on interface {}: {}. Skipping probes.",
-                                        config.interface, e
-                                    );
-                                } else {
-                                    error!(
-                                        "Failed to create Caracat sender for source IP {} on interface {}: {}. Skipping probes.",
-                                        source_ip, config.interface, e
-                                    );
-                                }
-                                continue;
-                            }
-                        }
-                    }
-                };
+        // Probes forwarded to the backup instance. Only the first worker forwards the end
+        // of a measurement, so that the backup completes it once.
+        let worker = self.worker;
+        let backup = |probes: Vec<Probe>, tags: Vec<ProbeTags>| ProbesWithSource {
+            probes,
+            tags,
+            source_ip: source_ip.clone(),
+            measurement_info: measurement_info.clone().map(|mut info| {
+                info.end_of_measurement &= worker == 0;
+                info
+            }),
+            priority,
+            ack: ack.clone(),
+        };
+
+        // Fail over when the interface goes down, and return to the primary once
+        // it is back up after the cooldown
+        if let Some(ref failover) = self.failover {
+            let interface_up = interface_is_up(&self.config.interface);
+            if failover.state.is_active(&failover.instance) {
+                let cooled_down = failover
+                    .state
+                    .elapsed(&failover.instance)
+                    .is_some_and(|elapsed| elapsed >= failover.cooldown);
+                if interface_up && cooled_down && failover.state.deactivate(&failover.instance) {
+                    info!(
+                        "Instance {} returns from backup instance {}",
+                        failover.instance, failover.backup
+                    );
+                    gauge!("saimiris_sender_failover", "agent" => self.agent_id.clone(), "instance" => failover.instance.clone())
+                        .set(0.0);
+                }
+            } else if !interface_up {
+                activate_failover(failover, &self.agent_id, "interface is down");
+            }
+
+            let active = failover.state.is_active(&failover.instance);
+            if self.failed_over && !active {
+                // Senders created before the failover may be bound to a stale interface
+                if let Some(blocking) = blocking.as_mut() {
+                    blocking.senders.clear();
+                    blocking.failures.reset();
+                }
+            }
+            self.failed_over = active;
+            if active {
+                self.forward(failover, backup(probes, tags)).await;
+                self.report_progress(measurement_info.as_ref(), 0).await;
+                return Ok(());
+            }
+        }
 
-                let mut sent_count_batch = 0;
+        // Determine if we should use a specific source IP or default behavior
+        let use_default_source = source_ip.is_empty();
+        let sender_key = if use_default_source {
+            "default".to_string()
+        } else {
+            source_ip.clone()
+        };
 
-                for probe in probes {
-                    if *stopped_thr.lock().unwrap() {
-                        trace!(
-                            "Stopping SendLoop mid-batch for interface: {}",
-                            config.interface
+        trace!(
+            "SendLoop determining sender key: use_default_source={}, sender_key={}",
+            use_default_source,
+            sender_key
+        );
+
+        // Create the sender for this sender key, unless it is cached
+        let cached = blocking
+            .as_ref()
+            .is_some_and(|blocking| blocking.senders.contains_key(&sender_key));
+        if !cached {
+            trace!("SendLoop creating new sender for key: {}", sender_key);
+            let (src_ipv4, src_ipv6) = if use_default_source {
+                // Use default behavior - let CaracatSender choose source IPs
+                (None, None)
+            } else {
+                // Parse the source IP to determine if it's IPv4 or IPv6
+                let parsed_ip: IpAddr = match source_ip.parse() {
+                    Ok(ip) => ip,
+                    Err(e) => {
+                        error!(
+                            "Invalid source IP address '{}': {}. Skipping probes.",
+                            source_ip, e
                         );
-                        return;
+                        return Ok(());
                     }
+                };
 
-                    if let Some(ttl) = config.min_ttl {
-                        if probe.ttl < ttl {
-                            trace!("{:?} filter=ttl_too_low", probe);
-                            counter!("saimiris_sender_filtered_total", "agent" => agent_id.clone(), "filter" => "ttl_too_low")
-                                .increment(1);
-                            continue;
-                        }
-                    }
+                match parsed_ip {
+                    IpAddr::V4(ipv4) => (Some(ipv4), None),
+                    IpAddr::V6(ipv6) => (None, Some(ipv6)),
+                }
+            };
 
-                    if let Some(ttl) = config.max_ttl {
-                        if probe.ttl > ttl {
-                            trace!("{:?} filter=ttl_too_high", probe);
-                            counter!("saimiris_sender_filtered_total", "agent" => agent_id.clone(), "filter" => "ttl_too_high")
-                                .increment(1);
-                            continue;
-                        }
+            trace!(
+                "SendLoop attempting to create CaracatSender with src_ipv4: {:?}, src_ipv6: {:?}",
+                src_ipv4,
+                src_ipv6
+            );
+
+            // Create the sender with a timeout to prevent hanging
+            let interface_name = self.config.interface.clone();
+            let sender_backend = self.sender_backend;
+            let instance_id = self.encoding_id;
+            let dry_run = self.config.dry_run;
+
+            let caracat_sender_result = match tokio::time::timeout(
+                Duration::from_secs(5),
+                tokio::task::spawn_blocking(move || {
+                    if chaos::fail_sender_creation() {
+                        anyhow::bail!("Injected sender creation failure");
                     }
+                    ProbeSender::new(
+                        sender_backend,
+                        &interface_name,
+                        src_ipv4,
+                        src_ipv6,
+                        instance_id,
+                        dry_run,
+                    )
+                }),
+            )
+            .await
+            {
+                Ok(Ok(join_result)) => join_result,
+                Ok(Err(e)) => Err(anyhow::anyhow!(
+                    "CaracatSender::new() task panicked: {:?}",
+                    e
+                )),
+                Err(_) => Err(anyhow::anyhow!(
+                    "CaracatSender::new() timed out after 5 seconds"
+                )),
+            };
 
-                    for i in 0..config.packets {
-                        trace!(
-                            "{:?} id={} packet={}",
-                            probe,
-                            probe.checksum(config.instance_id),
-                            i + 1
+            match caracat_sender_result {
+                Ok(sender) => {
+                    if use_default_source {
+                        debug!(
+                            "Created new CaracatSender with default source IP behavior on interface {}",
+                            self.config.interface
+                        );
+                    } else {
+                        debug!(
+                            "Created new CaracatSender for source IP {} on interface {}",
+                            source_ip, self.config.interface
                         );
-                        match caracat_sender.send(&probe) {
-                            Ok(_) => {
-                                sent_count_batch += 1;
-                                counter!("saimiris_sender_sent_total", metrics_labels.clone())
-                                    .increment(1);
-                            }
-                            Err(error) => {
-                                error!(
-                                    "Error sending probe on interface {}: {}",
-                                    config.interface, error
-                                );
-                                counter!("saimiris_sender_failed_total", metrics_labels.clone())
-                                    .increment(1);
-                            }
-                        }
-                        if (sent_count_batch) % config.batch_size == 0 && sent_count_batch > 0 {
-                            rate_limiter.wait();
-                        }
+                    }
+                    if let Some(blocking) = blocking.as_mut() {
+                        blocking.senders.insert(sender_key.clone(), sender);
                     }
                 }
+                Err(e) => {
+                    if use_default_source {
+                        error!(
+                            "Failed to create Caracat sender with default source IP behavior on interface {}: {}. Skipping probes.",
+                            self.config.interface, e
+                        );
+                    } else {
+                        error!(
+                            "Failed to create Caracat sender for source IP {} on interface {}: {}. Skipping probes.",
+                            source_ip, self.config.interface, e
+                        );
+                    }
+                    if let Some(ref failover) = self.failover {
+                        activate_failover(failover, &self.agent_id, "sender creation failed");
+                        self.failed_over = true;
+                        self.forward(failover, backup(probes, tags)).await;
+                        self.report_progress(measurement_info.as_ref(), 0).await;
+                    }
+                    return Ok(());
+                }
+            }
+        }
 
-                // Report measurement status if we have measurement info
-                if let Some(ref measurement_info) = measurement_info {
-                    *probes_sent_in_measurement
-                        .entry(measurement_info.measurement_id.clone())
-                        .or_insert(0) += sent_count_batch as u32;
-
-                    // Report status to gateway if configured
-                    if let (Some(ref gateway_url), Some(ref agent_key)) = (&gateway_url, &agent_key)
+        // Filter probes against the policy before sending them in bursts of `send_batch_size`
+        // (the tags are kept along, in case the probes are forwarded to the backup)
+        let mut allowed: Vec<(Probe, ProbeTags)> = self
+            .policy
+            .evaluate(&probes)
+            .into_iter()
+            .zip(probes)
+            .zip(
+                tags.into_iter()
+                    .chain(std::iter::repeat_with(ProbeTags::default)),
+            )
+            .filter_map(|((rejection, probe), probe_tags)| {
+                // Never probe the blocked prefixes, whatever the policy of the instance
+                if let Some(prefix) = self.blocklist.find(&probe.dst_addr) {
+                    trace!("{:?} blocked by {}", probe, prefix);
+                    counter!("saimiris_sender_blocked_total", counter_labels.clone()).increment(1);
+                    return None;
+                }
+                let filter = match rejection {
+                    Some(rejection) => rejection.label(),
+                    // caracat's libpcap sender sends every probe with the default
+                    // traffic class, so marked probes are not sent unmarked
+                    None if probe_tags.dscp != 0 && !self.sender_backend.marks_dscp() => {
+                        "dscp_unsupported"
+                    }
+                    None => return Some((probe, probe_tags)),
+                };
+                trace!("{:?} filter={}", probe, filter);
+                let mut filter_labels = counter_labels.clone();
+                filter_labels.push(Label::new("filter", filter));
+                counter!("saimiris_sender_filtered_total", filter_labels).increment(1);
+                self.statistics
+                    .probes_filtered
+                    .fetch_add(1, Ordering::Relaxed);
+                None
+            })
+            .collect();
+        // Drop the probes repeated inside the measurement, before they count against its maximum
+        if let (Some(dedup), Some(info)) = (&self.dedup, &measurement_info) {
+            let duplicates = dedup.dedup(&info.measurement_id, &mut allowed);
+            if duplicates > 0 {
+                debug!(
+                    "Dropping {} probes repeated in measurement {}",
+                    duplicates, info.measurement_id
+                );
+                counter!("saimiris_sender_duplicate_total", counter_labels.clone())
+                    .increment(duplicates as u64);
+            }
+        }
+        // Drop the probes of the measurement beyond the maximum of the agent
+        if let (Some(quota), Some(info)) = (&self.measurement_quota, &measurement_info) {
+            let (granted, newly_exceeded) = quota.take(&info.measurement_id, allowed.len() as u64);
+            let dropped = allowed.len() as u64 - granted;
+            if dropped > 0 {
+                allowed.truncate(granted as usize);
+                counter!("saimiris_sender_over_limit_total", counter_labels.clone())
+                    .increment(dropped);
+            }
+            if newly_exceeded {
+                warn!(
+                    "Measurement {} exceeded the maximum of {} probes, dropping its next probes",
+                    info.measurement_id,
+                    quota.max_probes()
+                );
+                self.progress
+                    .lock()
+                    .unwrap()
+                    .exceed_limit(&info.measurement_id, quota.max_probes());
+                if let (Some(ref gateway_url), Some(ref agent_key)) =
+                    (&self.gateway_url, &self.agent_key)
+                {
+                    let sent_probes =
+                        u32::try_from(quota.used(&info.measurement_id)).unwrap_or(u32::MAX);
+                    if let Err(e) = crate::agent::gateway::report_measurement_limit_exceeded(
+                        gateway_url.as_str(),
+                        &self.agent_id,
+                        agent_key.as_str(),
+                        &info.measurement_id,
+                        sent_probes,
+                        quota.max_probes(),
+                    )
+                    .await
                     {
-                        let total_sent = *probes_sent_in_measurement
-                            .get(&measurement_info.measurement_id)
-                            .unwrap_or(&0);
-
-                        // Use runtime handle to run async code in this thread
-                        match thread_runtime_handle.block_on(
-                            crate::agent::gateway::report_measurement_status(
-                                gateway_url.as_str(),
-                                &agent_id,
-                                agent_key.as_str(),
-                                &measurement_info.measurement_id,
-                                total_sent,
-                                measurement_info.end_of_measurement,
-                            ),
-                        ) {
-                            Ok(_) => tracing::debug!(
-                                "Reported measurement status for {}: {} probes sent, completed: {}",
-                                measurement_info.measurement_id,
-                                total_sent,
-                                measurement_info.end_of_measurement
-                            ),
-                            Err(e) => tracing::warn!("Failed to report measurement status: {}", e),
-                        }
-
-                        // Clean up tracking for completed measurements
-                        if measurement_info.end_of_measurement {
-                            probes_sent_in_measurement.remove(&measurement_info.measurement_id);
-                        }
+                        warn!("Failed to report measurement limit exceeded: {}", e);
                     }
                 }
             }
-            debug!("SendLoop thread finished for interface: {}", interface_name);
-        });
+        }
+
+        // Bound the probes sent in a row to a network, whatever the order of the message
+        let (probes, tags): (Vec<Probe>, Vec<ProbeTags>) = match self.fairness {
+            Some(ref fairness) => fairness.interleave(allowed),
+            None => allowed,
+        }
+        .into_iter()
+        .unzip();
+
+        // Remember the context of the tagged or measurement probes handed to the sender before
+        // sending them, so that early replies can be attributed too
+        let measurement_id: Option<Arc<str>> = measurement_info
+            .as_ref()
+            .map(|info| Arc::from(info.measurement_id.as_str()));
+        if measurement_id.is_some() || tags.iter().any(|t| !t.is_empty()) {
+            let mut table = self.correlation.lock().unwrap();
+            for (probe, probe_tags) in probes.iter().zip(&tags) {
+                if measurement_id.is_none() && probe_tags.is_empty() {
+                    continue;
+                }
+                table.insert(
+                    ProbeKey::from_probe(probe),
+                    ProbeContext {
+                        tags: probe_tags.clone(),
+                        measurement_id: measurement_id.clone(),
+                        instance_id: self.config.instance_id,
+                    },
+                );
+            }
+        }
+
+        if let Some(ref measurement_info) = measurement_info {
+            self.progress.lock().unwrap().start(
+                &measurement_info.measurement_id,
+                measurement_info.submitted_at_ms,
+            );
+        }
 
-        SendLoop { handle, stopped }
+        // Only the packet I/O runs on a blocking thread, the SendLoop task waits for it
+        let mut sender = blocking.take().context("the blocking sender was lost")?;
+        let source_addr = source_ip.parse::<IpAddr>().ok();
+        let sent_measurement_info = measurement_info.clone();
+        let span = Span::current();
+        let (sender, mut probes, mut tags, outcome) = tokio::task::spawn_blocking(move || {
+            let _span = span.entered();
+            let outcome = sender.send(
+                &sender_key,
+                &probes,
+                &tags,
+                source_addr,
+                sent_measurement_info.as_ref(),
+                &counter_labels,
+            );
+            (sender, probes, tags, outcome)
+        })
+        .await
+        .context("the blocking sender panicked")?;
+        *blocking = Some(sender);
+        if outcome.stopped {
+            return Ok(());
+        }
+
+        // Too many consecutive failures: the remaining probes are sent by the backup
+        if let (Some(offset), Some(ref failover)) = (outcome.failover_at, &self.failover) {
+            activate_failover(
+                failover,
+                &self.agent_id,
+                "too many consecutive send failures",
+            );
+            self.failed_over = true;
+            let remaining = backup(probes.split_off(offset), tags.split_off(offset));
+            self.forward(failover, remaining).await;
+        }
+
+        self.report_progress(measurement_info.as_ref(), outcome.sent as u32)
+            .await;
+        Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn stop(self) {
-        info!("Requesting stop for SendLoop.");
-        if let Ok(mut stopped_lock) = self.stopped.lock() {
-            *stopped_lock = true;
-        } else {
-            error!("Failed to acquire lock to stop SendLoop.");
+    /// Forward probes to the backup instance.
+    async fn forward(&self, failover: &Failover, probes_with_source: ProbesWithSource) {
+        debug!(
+            "Forwarding {} probes from instance {} to backup instance {}",
+            probes_with_source.probes.len(),
+            failover.instance,
+            failover.backup
+        );
+        counter!(
+            "saimiris_sender_failover_forwarded_total",
+            self.metrics_labels.clone()
+        )
+        .increment(probes_with_source.probes.len() as u64);
+        if let Err(e) = failover.backup_sender.send(probes_with_source).await {
+            error!(
+                "Failed to forward probes to backup instance {}: {}",
+                failover.backup, e
+            );
+        }
+    }
+
+    /// Report measurement status if we have measurement info
+    /// (cancellations are reported by the agent handler).
+    async fn report_progress(&self, measurement_info: Option<&MeasurementInfo>, sent: u32) {
+        let Some(measurement_info) = measurement_info else {
+            return;
+        };
+        if is_cancelled(&self.progress, Some(measurement_info)) {
+            return;
+        }
+        let (total_sent, is_complete, timing) = {
+            let mut progress = self.progress.lock().unwrap();
+            let (total_sent, is_complete) = progress.record(
+                &measurement_info.measurement_id,
+                sent,
+                measurement_info.end_of_measurement,
+                self.workers,
+            );
+            let timing = is_complete
+                .then(|| progress.take_timing(&measurement_info.measurement_id))
+                .flatten();
+            (total_sent, is_complete, timing)
+        };
+        // A measurement reusing the ID starts over
+        if let Some(quota) = self.measurement_quota.as_ref().filter(|_| is_complete) {
+            quota.forget(&measurement_info.measurement_id);
+        }
+        if let Some(timing) = timing {
+            // Exemplars link the latency spikes to the measurement
+            let observe = |name: &'static str, value: f64| {
+                histogram!(name, self.metrics_labels.clone()).record(value);
+                exemplars::record(
+                    name,
+                    &self.metrics_labels,
+                    Exemplar::new(
+                        &measurement_info.measurement_id,
+                        measurement_info.trace_id.as_deref(),
+                        value,
+                    ),
+                );
+            };
+            observe(
+                "saimiris_measurement_send_duration_seconds",
+                timing.send_duration().as_secs_f64(),
+            );
+            let now_ms = chrono::Utc::now().timestamp_millis();
+            if let Some(latency) = timing.completion_latency(now_ms) {
+                observe(
+                    "saimiris_measurement_completion_latency_seconds",
+                    latency.as_secs_f64(),
+                );
+            }
         }
-        // Consider adding a timeout to join if the thread might get stuck
-        match self.handle.join() {
-            Ok(_) => info!("SendLoop successfully joined."),
-            Err(e) => error!("Error joining SendLoop thread: {:?}", e),
+
+        // Report status to gateway if configured
+        if let (Some(ref gateway_url), Some(ref agent_key)) = (&self.gateway_url, &self.agent_key) {
+            match crate::agent::gateway::report_measurement_status(
+                gateway_url.as_str(),
+                &self.agent_id,
+                agent_key.as_str(),
+                &measurement_info.measurement_id,
+                total_sent,
+                is_complete,
+            )
+            .await
+            {
+                Ok(_) => tracing::debug!(
+                    "Reported measurement status for {}: {} probes sent, completed: {}",
+                    measurement_info.measurement_id,
+                    total_sent,
+                    is_complete
+                ),
+                Err(e) => tracing::warn!("Failed to report measurement status: {}", e),
+            }
+        }
+    }
+}
+
+/// Sender of the probes of a caracat instance (or of one of its workers), run as a Tokio task
+/// that hands the packet I/O over to a blocking thread, one message at a time.
+pub struct SendLoop {
+    handle: tokio::task::JoinHandle<()>,
+    cancel: CancellationToken,
+}
+
+impl SendLoop {
+    /// Spawn the SendLoop on the current Tokio runtime. It runs until its probes channel is
+    /// closed or `cancel` is cancelled, and keeps running in the background if dropped.
+    /// Fails on an invalid instance configuration, which is rejected at startup.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        rx: tokio::sync::mpsc::Receiver<ProbesWithSource>,
+        config: CaracatConfig,
+        app_config: &crate::config::AppConfig,
+        correlation: SharedCorrelationTable,
+        progress: SharedMeasurementProgress,
+        mode: watch::Receiver<AgentMode>,
+        worker: usize,
+        failover: Option<Failover>,
+        emission: Option<EmissionChecker>,
+        total_rate: Option<SharedRateLimiter>,
+        dst_rate: Option<DestinationRateLimiter>,
+        blocklist: Arc<PrefixSet>,
+        measurement_quota: Option<MeasurementQuota>,
+        dedup: Option<ProbeDedup>,
+        loop_guard: LoopGuard,
+        cancel: CancellationToken,
+    ) -> anyhow::Result<Self> {
+        // Extract needed values from app_config
+        let agent_id = app_config.agent.id.clone();
+        let gateway_url = app_config.gateway.as_ref().and_then(|g| g.url.clone());
+        let agent_key = app_config
+            .gateway
+            .as_ref()
+            .and_then(|g| g.agent_key.clone());
+
+        // ID encoded by caracat in the probes
+        let encoding_id = crate::agent::integrity::encoding_id(
+            &config,
+            &agent_id,
+            app_config.agent.integrity_key.as_deref(),
+        )?;
+
+        let metrics_labels = vec![Label::new("agent", agent_id.to_string())];
+        let mut pps_labels = metrics_labels.clone();
+        pps_labels.push(Label::new("worker", worker.to_string()));
+        let health = LoopHealth::new(
+            loop_guard.state().clone(),
+            format!("send:{}:{}", instance_label(&config), worker),
+        )
+        .with_events(progress.lock().unwrap().events().clone());
+
+        let task = SendTask {
+            agent_id,
+            gateway_url,
+            agent_key,
+            worker,
+            workers: config.sender_threads.max(1) as usize,
+            encoding_id,
+            sender_backend: SenderBackend::new(&config)?,
+            source_limiter: SourceRateLimiter::new(&config)?,
+            correlation,
+            progress,
+            mode,
+            cancel: cancel.clone(),
+            failover,
+            failed_over: false,
+            policy: ProbePolicy::new(&config)?,
+            // Interleaving of the probes across destination networks
+            fairness: FairnessScheduler::new(&config)?,
+            blocklist,
+            measurement_quota,
+            dedup,
+            emission,
+            total_rate,
+            dst_rate,
+            statistics: statistics::send_statistics(&instance_label(&config)),
+            metrics_labels,
+            pps_labels,
+            config,
+        };
+
+        // Logged with the fields of the instance
+        let span = info_span!("instance", instance_id = task.config.instance_id);
+        let interface_name = task.config.interface.clone();
+        // Restarted with backoff when it fails or panics, the agent being not ready meanwhile.
+        // The probes channel outlives the runs of the loop.
+        let supervisor_cancel = cancel.clone();
+        let rx = Arc::new(tokio::sync::Mutex::new(rx));
+        let start_send_loop = move || {
+            let task = task.clone();
+            let rx = rx.clone();
+            catch_panic(async move { task.run(&mut *rx.lock().await).await })
+        };
+        let handle = tokio::spawn(
+            async move {
+                // Counted as running until the task exits
+                let _loop_guard = loop_guard;
+                debug!("SendLoop task started for interface: {}", interface_name);
+                supervise(
+                    health,
+                    supervisor_cancel,
+                    Backoff::default(),
+                    start_send_loop,
+                )
+                .await;
+                debug!("SendLoop task finished for interface: {}", interface_name);
+            }
+            .instrument(span),
+        );
+
+        Ok(SendLoop { handle, cancel })
+    }
+
+    /// Stop the SendLoop, before its next probe, and wait for it to exit.
+    pub async fn stop(self) {
+        info!("Requesting stop for SendLoop.");
+        self.cancel.cancel();
+        match self.handle.await {
+            Ok(_) => info!("SendLoop successfully stopped."),
+            Err(e) => error!("Error joining SendLoop task: {:?}", e),
         }
     }
 }
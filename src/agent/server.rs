@@ -0,0 +1,385 @@
+use anyhow::Result;
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::{debug, error, info};
+
+use crate::agent::control::{apply_command, AgentMode, ControlCommand};
+use crate::agent::events::EventLog;
+use crate::agent::exemplars::{render_openmetrics, OPENMETRICS_CONTENT_TYPE};
+
+/// State of the agent, shared with the HTTP server.
+#[derive(Debug)]
+pub struct AgentState {
+    pub agent_id: String,
+    pub caracat_instances: usize,
+    started_at: Instant,
+    ready: AtomicBool,
+    // Set when the agent runs, but not as intended (e.g. duplicate agent ID)
+    degraded: AtomicBool,
+    // Role in a warm standby pair (`leader` or `standby`), if any
+    role: Mutex<Option<&'static str>>,
+    // SendLoop tasks and ReceiveLoop threads running
+    send_loops: AtomicUsize,
+    receive_loops: AtomicUsize,
+    // Loops failed and waiting to be restarted, or not yet healthy again
+    failed_loops: Mutex<BTreeSet<String>>,
+}
+
+/// Threads of the agent whose health is reflected by the readiness probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopKind {
+    Send,
+    Receive,
+}
+
+/// A SendLoop task or ReceiveLoop thread counted as running in the agent state until dropped,
+/// when it exits (or unwinds on a panic).
+#[derive(Debug)]
+pub struct LoopGuard {
+    state: Arc<AgentState>,
+    kind: LoopKind,
+}
+
+impl LoopGuard {
+    pub fn state(&self) -> &Arc<AgentState> {
+        &self.state
+    }
+}
+
+impl Drop for LoopGuard {
+    fn drop(&mut self) {
+        self.state.loops(self.kind).fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl AgentState {
+    pub fn new(agent_id: String, caracat_instances: usize) -> Arc<Self> {
+        Arc::new(AgentState {
+            agent_id,
+            caracat_instances,
+            started_at: Instant::now(),
+            ready: AtomicBool::new(false),
+            degraded: AtomicBool::new(false),
+            role: Mutex::new(None),
+            send_loops: AtomicUsize::new(0),
+            receive_loops: AtomicUsize::new(0),
+            failed_loops: Mutex::new(BTreeSet::new()),
+        })
+    }
+
+    fn loops(&self, kind: LoopKind) -> &AtomicUsize {
+        match kind {
+            LoopKind::Send => &self.send_loops,
+            LoopKind::Receive => &self.receive_loops,
+        }
+    }
+
+    /// Count a SendLoop task or ReceiveLoop thread as running, until the guard is dropped.
+    pub fn register_loop(self: &Arc<Self>, kind: LoopKind) -> LoopGuard {
+        self.loops(kind).fetch_add(1, Ordering::Relaxed);
+        LoopGuard {
+            state: self.clone(),
+            kind,
+        }
+    }
+
+    pub fn running_loops(&self, kind: LoopKind) -> usize {
+        self.loops(kind).load(Ordering::Relaxed)
+    }
+
+    /// At least one SendLoop and one ReceiveLoop thread are running, if caracat instances
+    /// are configured.
+    pub fn has_running_loops(&self) -> bool {
+        self.caracat_instances == 0
+            || (self.running_loops(LoopKind::Send) > 0 && self.running_loops(LoopKind::Receive) > 0)
+    }
+
+    /// Mark a loop as failed until it is healthy again, so that the agent is not ready
+    /// meanwhile. Returns whether its state changed.
+    pub fn set_loop_failed(&self, name: &str, failed: bool) -> bool {
+        let mut failed_loops = self.failed_loops.lock().unwrap();
+        if failed {
+            failed_loops.insert(name.to_string())
+        } else {
+            failed_loops.remove(name)
+        }
+    }
+
+    pub fn failed_loops(&self) -> Vec<String> {
+        self.failed_loops.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Relaxed);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    pub fn set_degraded(&self, degraded: bool) {
+        self.degraded.store(degraded, Ordering::Relaxed);
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    pub fn set_role(&self, role: Option<&'static str>) {
+        *self.role.lock().unwrap() = role;
+    }
+
+    pub fn role(&self) -> Option<&'static str> {
+        *self.role.lock().unwrap()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Status<'a> {
+    agent_id: &'a str,
+    version: &'static str,
+    ready: bool,
+    degraded: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<&'static str>,
+    uptime_secs: u64,
+    caracat_instances: usize,
+    send_loops: usize,
+    receive_loops: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    failed_loops: Vec<String>,
+}
+
+fn response(
+    status: StatusCode,
+    content_type: &str,
+    body: impl Into<Bytes>,
+) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(body.into()));
+    *response.status_mut() = status;
+    if let Ok(value) = content_type.parse() {
+        response.headers_mut().insert(CONTENT_TYPE, value);
+    }
+    response
+}
+
+/// Single HTTP server for the metrics, status, health and control endpoints of the agent.
+///
+/// Routes are protected by a bearer token when one is configured for their name
+/// (`metrics`, `status`, `health` or `control`) in `agent.http_auth_tokens`.
+/// The control routes are only served once a `control` token is configured.
+/// Metrics are rendered in the OpenMetrics format, with their exemplars, when accepted
+/// by the scraper.
+pub struct Server {
+    state: Arc<AgentState>,
+    metrics: PrometheusHandle,
+    auth_tokens: HashMap<String, String>,
+    // Mode of the agent driven by the control routes, and its event log
+    control: Option<(Arc<watch::Sender<AgentMode>>, EventLog)>,
+}
+
+impl Server {
+    pub fn new(
+        state: Arc<AgentState>,
+        metrics: PrometheusHandle,
+        auth_tokens: HashMap<String, String>,
+    ) -> Self {
+        Server {
+            state,
+            metrics,
+            auth_tokens,
+            control: None,
+        }
+    }
+
+    /// Apply the commands of the control routes (`POST /control/pause`, `/control/resume`
+    /// and `/control/drain`) to the agent mode, as those of the control topic.
+    pub fn with_control(mut self, mode: Arc<watch::Sender<AgentMode>>, events: EventLog) -> Self {
+        self.control = Some((mode, events));
+        self
+    }
+
+    fn is_authorized(&self, route: &str, authorization: Option<&str>) -> bool {
+        match self.auth_tokens.get(route) {
+            // Compared in constant time, not to leak the token through the response time
+            Some(token) => authorization
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .is_some_and(|value| bool::from(value.as_bytes().ct_eq(token.as_bytes()))),
+            None => true,
+        }
+    }
+
+    /// Apply the command of a control route, answering with the resulting agent mode.
+    fn control(&self, command: ControlCommand) -> Response<Full<Bytes>> {
+        let Some((mode, events)) = &self.control else {
+            return response(StatusCode::NOT_FOUND, "text/plain", "Not Found");
+        };
+        apply_command(mode, command, events);
+        let body = serde_json::json!({ "mode": *mode.borrow() });
+        response(StatusCode::OK, "application/json", body.to_string())
+    }
+
+    /// Answer a request, given its method, path, `Authorization` and `Accept` headers.
+    pub fn route(
+        &self,
+        method: &Method,
+        path: &str,
+        authorization: Option<&str>,
+        accept: Option<&str>,
+    ) -> Response<Full<Bytes>> {
+        let route = match path {
+            "/metrics" => "metrics",
+            "/status" => "status",
+            "/healthz" | "/readyz" => "health",
+            "/control/pause" | "/control/resume" | "/control/drain"
+                if self.control.is_some() && self.auth_tokens.contains_key("control") =>
+            {
+                "control"
+            }
+            _ => return response(StatusCode::NOT_FOUND, "text/plain", "Not Found"),
+        };
+        let allowed = if route == "control" {
+            Method::POST
+        } else {
+            Method::GET
+        };
+        if *method != allowed {
+            return response(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "text/plain",
+                "Method Not Allowed",
+            );
+        }
+        if !self.is_authorized(route, authorization) {
+            return response(StatusCode::UNAUTHORIZED, "text/plain", "Unauthorized");
+        }
+
+        match path {
+            "/control/pause" => self.control(ControlCommand::Pause),
+            "/control/resume" => self.control(ControlCommand::Resume),
+            "/control/drain" => self.control(ControlCommand::Drain),
+            "/metrics"
+                if accept.is_some_and(|accept| accept.contains("application/openmetrics-text")) =>
+            {
+                response(
+                    StatusCode::OK,
+                    OPENMETRICS_CONTENT_TYPE,
+                    render_openmetrics(&self.metrics.render()),
+                )
+            }
+            "/metrics" => response(
+                StatusCode::OK,
+                "text/plain; version=0.0.4",
+                self.metrics.render(),
+            ),
+            "/status" => {
+                let status = Status {
+                    agent_id: &self.state.agent_id,
+                    version: env!("CARGO_PKG_VERSION"),
+                    ready: self.state.is_ready(),
+                    degraded: self.state.is_degraded(),
+                    role: self.state.role(),
+                    uptime_secs: self.state.started_at.elapsed().as_secs(),
+                    caracat_instances: self.state.caracat_instances,
+                    send_loops: self.state.running_loops(LoopKind::Send),
+                    receive_loops: self.state.running_loops(LoopKind::Receive),
+                    failed_loops: self.state.failed_loops(),
+                };
+                match serde_json::to_string(&status) {
+                    Ok(body) => response(StatusCode::OK, "application/json", body),
+                    Err(e) => response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "text/plain",
+                        e.to_string(),
+                    ),
+                }
+            }
+            "/readyz" if !self.state.is_ready() => {
+                response(StatusCode::SERVICE_UNAVAILABLE, "text/plain", "Not Ready")
+            }
+            "/readyz" if !self.state.has_running_loops() => response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "text/plain",
+                "No Running Loops",
+            ),
+            "/readyz" if !self.state.failed_loops().is_empty() => response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "text/plain",
+                "Failed Loops",
+            ),
+            "/readyz" if self.state.is_degraded() => {
+                response(StatusCode::SERVICE_UNAVAILABLE, "text/plain", "Degraded")
+            }
+            _ => response(StatusCode::OK, "text/plain", "OK"),
+        }
+    }
+
+    async fn handle(
+        self: Arc<Self>,
+        request: Request<Incoming>,
+    ) -> Result<Response<Full<Bytes>>, Infallible> {
+        let header = |name| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        Ok(self.route(
+            request.method(),
+            request.uri().path(),
+            header(AUTHORIZATION),
+            header(ACCEPT),
+        ))
+    }
+
+    pub async fn serve(self: Arc<Self>, address: SocketAddr) -> Result<()> {
+        let listener = TcpListener::bind(address).await?;
+        info!("HTTP server listening on {}", address);
+
+        // Histograms and idle metrics are maintained by the exporter upkeep
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                metrics.run_upkeep();
+            }
+        });
+
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    error!("Failed to accept HTTP connection: {}", e);
+                    continue;
+                }
+            };
+            let server = self.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |request| server.clone().handle(request));
+                if let Err(e) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    debug!("HTTP connection with {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use anyhow::Result;
+use caracat::models::Reply;
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+use crate::agent::correlation::normalize_ip_addr;
+use crate::config::CaracatConfig;
+
+/// Detect replies failing the integrity check while quoting one of our source addresses,
+/// which indicates spoofed or reflected traffic rather than unrelated noise.
+#[derive(Debug, Clone)]
+pub struct SpoofDetector {
+    prefixes: Vec<IpNet>,
+    sample_every: u64,
+    seen: u64,
+}
+
+impl SpoofDetector {
+    /// Build a detector for the source prefixes of the given caracat instances.
+    /// One in `sample_every` suspicious replies is sampled (never if 0).
+    pub fn new(configs: &[CaracatConfig], sample_every: u64) -> Result<Self> {
+        let mut prefixes = Vec::new();
+        for config in configs {
+            for prefix in [&config.src_ipv4_prefix, &config.src_ipv6_prefix]
+                .into_iter()
+                .flatten()
+            {
+                prefixes.push(
+                    prefix.parse::<IpNet>().map_err(|e| {
+                        anyhow::anyhow!("Invalid source prefix '{}': {}", prefix, e)
+                    })?,
+                );
+            }
+        }
+        Ok(SpoofDetector {
+            prefixes,
+            sample_every,
+            seen: 0,
+        })
+    }
+
+    /// Whether the probe quoted in the reply was sent from one of our addresses.
+    pub fn quotes_our_prefixes(&self, reply: &Reply) -> bool {
+        self.is_our_addr(reply.probe_src_addr, reply.reply_dst_addr)
+    }
+
+    /// Whether `probe_src_addr` is one of our addresses.
+    /// Without configured prefixes, it must be the address the reply was sent to.
+    pub fn is_our_addr(&self, probe_src_addr: IpAddr, reply_dst_addr: IpAddr) -> bool {
+        let probe_src_addr = normalize_ip_addr(probe_src_addr);
+        if self.prefixes.is_empty() {
+            return probe_src_addr == normalize_ip_addr(reply_dst_addr);
+        }
+        self.prefixes
+            .iter()
+            .any(|prefix| prefix.contains(&probe_src_addr))
+    }
+
+    /// Record a suspicious reply, and return whether it should be sampled.
+    pub fn observe(&mut self) -> bool {
+        self.seen += 1;
+        self.sample_every > 0 && (self.seen - 1) % self.sample_every == 0
+    }
+}
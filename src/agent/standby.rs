@@ -0,0 +1,145 @@
+use metrics::gauge;
+use rdkafka::consumer::StreamConsumer;
+use rdkafka::Message;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{error, info, trace};
+
+use crate::agent::capabilities::AgentCapabilities;
+use crate::agent::events::{EventKind, EventLog};
+use crate::agent::server::AgentState;
+
+/// Heartbeats missed before a run of the agent is considered dead.
+pub const LEASE_HEARTBEATS: u32 = 3;
+
+/// Role of a run in a warm standby pair.
+pub fn role(leader: bool) -> &'static str {
+    if leader {
+        "leader"
+    } else {
+        "standby"
+    }
+}
+
+/// Leader election between the runs of an agent sharing an agent ID (`agent.standby`),
+/// over the heartbeats of the agents topic: the leader is the longest running agent
+/// among those which published a heartbeat within the lease.
+#[derive(Debug, Clone)]
+pub struct Election {
+    agent_id: String,
+    run_id: String,
+    started_at_ns: u64,
+    lease_ns: u64,
+    // Start and latest heartbeat of the other runs, by run ID
+    peers: HashMap<String, (u64, u64)>,
+}
+
+impl Election {
+    pub fn new(agent_id: &str, run_id: &str, started_at_ns: u64, lease: Duration) -> Self {
+        Election {
+            agent_id: agent_id.to_string(),
+            run_id: run_id.to_string(),
+            started_at_ns,
+            lease_ns: lease.as_nanos() as u64,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Record a heartbeat of the agents topic.
+    pub fn observe(&mut self, record: &AgentCapabilities) {
+        if record.agent_id != self.agent_id
+            || record.run_id.is_empty()
+            || record.run_id == self.run_id
+        {
+            return;
+        }
+        let peer = self
+            .peers
+            .entry(record.run_id.clone())
+            .or_insert((record.started_at_ns, record.published_at_ns));
+        peer.1 = peer.1.max(record.published_at_ns);
+    }
+
+    /// Whether this run leads at `now_ns`. A run only claims the leadership once it has
+    /// listened to the heartbeats for a whole lease, so that it does not overlap with a
+    /// leader it has not heard from yet.
+    pub fn is_leader(&self, now_ns: u64) -> bool {
+        if now_ns < self.started_at_ns.saturating_add(self.lease_ns) {
+            return false;
+        }
+        // Runs predating the election (no start time) are considered the oldest
+        let seniority = (self.started_at_ns, self.run_id.as_str());
+        !self
+            .peers
+            .iter()
+            .any(|(run_id, (started_at_ns, published_at_ns))| {
+                now_ns.saturating_sub(*published_at_ns) < self.lease_ns
+                    && (*started_at_ns, run_id.as_str()) < seniority
+            })
+    }
+}
+
+fn now_ns() -> u64 {
+    chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64
+}
+
+/// Elect the leader of the agents sharing this agent ID, publishing the role of this run
+/// to `leader`. Only the leader consumes probes, the standby takes over once the heartbeats
+/// of the leader stop for a lease.
+pub async fn standby_loop(
+    consumer: StreamConsumer,
+    mut election: Election,
+    leader: Arc<watch::Sender<bool>>,
+    state: Arc<AgentState>,
+    events: EventLog,
+) {
+    let agent_id = election.agent_id.clone();
+    // Leases expire without any message, so the role is checked periodically
+    let mut check = tokio::time::interval(
+        Duration::from_nanos(election.lease_ns / 4).max(Duration::from_secs(1)),
+    );
+    state.set_role(Some(role(false)));
+    gauge!("saimiris_agent_leader", "agent" => agent_id.clone()).set(0.0);
+    loop {
+        tokio::select! {
+            message = consumer.recv() => match message {
+                Ok(message) => {
+                    let Some(payload) = message.payload() else {
+                        continue;
+                    };
+                    match serde_json::from_slice::<AgentCapabilities>(payload) {
+                        Ok(record) => election.observe(&record),
+                        Err(e) => {
+                            trace!("Invalid agent capabilities: {}. Ignored.", e);
+                            continue;
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("Kafka agents consumer error: {}. Retrying in 5s...", e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            },
+            _ = check.tick() => {}
+        }
+
+        let is_leader = election.is_leader(now_ns());
+        if leader.send_replace(is_leader) != is_leader {
+            info!("Agent {} is now the {}", agent_id, role(is_leader));
+            state.set_role(Some(role(is_leader)));
+            gauge!("saimiris_agent_leader", "agent" => agent_id.clone()).set(if is_leader {
+                1.0
+            } else {
+                0.0
+            });
+            events.emit(
+                EventKind::RoleChanged,
+                None,
+                BTreeMap::from([("role".to_string(), role(is_leader).to_string())]),
+            );
+        }
+    }
+}
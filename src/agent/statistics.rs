@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tracing::info;
+
+/// Probes of a caracat instance, since the agent started.
+#[derive(Debug, Default)]
+pub struct SendStatistics {
+    pub probes_read: AtomicU64,
+    pub probes_sent: AtomicU64,
+    pub probes_failed: AtomicU64,
+    pub probes_filtered: AtomicU64,
+}
+
+/// Replies captured on an interface, since the agent started.
+#[derive(Debug, Default)]
+pub struct ReceiveStatistics {
+    pub replies_received: AtomicU64,
+    pub replies_invalid: AtomicU64,
+}
+
+/// Kafka messages of replies, since the agent started.
+#[derive(Debug, Default)]
+pub struct ProduceStatistics {
+    pub messages_produced: AtomicU64,
+    pub messages_failed: AtomicU64,
+}
+
+impl fmt::Display for SendStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "probes_read={},probes_sent={},probes_failed={},probes_filtered={}",
+            self.probes_read.load(Ordering::Relaxed),
+            self.probes_sent.load(Ordering::Relaxed),
+            self.probes_failed.load(Ordering::Relaxed),
+            self.probes_filtered.load(Ordering::Relaxed)
+        )
+    }
+}
+
+impl fmt::Display for ReceiveStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "replies_received={},replies_invalid={}",
+            self.replies_received.load(Ordering::Relaxed),
+            self.replies_invalid.load(Ordering::Relaxed)
+        )
+    }
+}
+
+impl fmt::Display for ProduceStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "messages_produced={},messages_failed={}",
+            self.messages_produced.load(Ordering::Relaxed),
+            self.messages_failed.load(Ordering::Relaxed)
+        )
+    }
+}
+
+// Statistics of the instances (by instance label) and of the interfaces, and of the producer
+static SEND_STATISTICS: LazyLock<Mutex<BTreeMap<String, Arc<SendStatistics>>>> =
+    LazyLock::new(Default::default);
+static RECEIVE_STATISTICS: LazyLock<Mutex<BTreeMap<String, Arc<ReceiveStatistics>>>> =
+    LazyLock::new(Default::default);
+static PRODUCE_STATISTICS: LazyLock<Arc<ProduceStatistics>> = LazyLock::new(Default::default);
+
+/// Statistics of an instance, shared by its sender workers.
+pub fn send_statistics(instance: &str) -> Arc<SendStatistics> {
+    SEND_STATISTICS
+        .lock()
+        .unwrap()
+        .entry(instance.to_string())
+        .or_default()
+        .clone()
+}
+
+/// Statistics of the replies captured on an interface.
+pub fn receive_statistics(interface: &str) -> Arc<ReceiveStatistics> {
+    RECEIVE_STATISTICS
+        .lock()
+        .unwrap()
+        .entry(interface.to_string())
+        .or_default()
+        .clone()
+}
+
+/// Statistics of the Kafka producer of the replies.
+pub fn produce_statistics() -> Arc<ProduceStatistics> {
+    PRODUCE_STATISTICS.clone()
+}
+
+/// Summary lines of the statistics: one per instance, one per interface, then the producer.
+pub fn summary() -> Vec<String> {
+    let mut lines = Vec::new();
+    for (instance, statistics) in SEND_STATISTICS.lock().unwrap().iter() {
+        lines.push(format!("instance={},{}", instance, statistics));
+    }
+    for (interface, statistics) in RECEIVE_STATISTICS.lock().unwrap().iter() {
+        lines.push(format!("interface={},{}", interface, statistics));
+    }
+    lines.push(PRODUCE_STATISTICS.to_string());
+    lines
+}
+
+/// Log the summary of the statistics every `interval`, for operators without Prometheus.
+pub async fn log_loop(interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        for line in summary() {
+            info!("{}", line);
+        }
+    }
+}
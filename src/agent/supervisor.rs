@@ -0,0 +1,190 @@
+use metrics::{counter, gauge};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::agent::events::{EventKind, EventLog};
+use crate::agent::server::AgentState;
+
+/// Delay before the first restart of a failed loop.
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Maximum delay between two restarts of a failed loop.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Why a send or receive loop exited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoopExit {
+    /// Stopped, or its channel was closed: the loop is not restarted
+    Stopped,
+    /// Failed (e.g. its interface disappeared): the loop is restarted after a backoff
+    Failed(String),
+}
+
+/// Exponential backoff between the restarts of a failed loop.
+/// A loop running for the maximum delay since its restart is healthy again.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff {
+            initial,
+            max: max.max(initial),
+            next: initial,
+        }
+    }
+
+    /// Delay before the next restart, doubled for the following one up to the maximum.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    /// Restart from the initial delay, once the loop is healthy.
+    pub fn reset(&mut self) {
+        self.next = self.initial;
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::new(INITIAL_BACKOFF, MAX_BACKOFF)
+    }
+}
+
+/// Health of a supervised loop, reflected in the readiness of the agent and in the
+/// `saimiris_loop_failed` gauge.
+#[derive(Debug, Clone)]
+pub struct LoopHealth {
+    state: Arc<AgentState>,
+    name: String,
+    events: EventLog,
+}
+
+impl LoopHealth {
+    pub fn new(state: Arc<AgentState>, name: impl Into<String>) -> Self {
+        LoopHealth {
+            state,
+            name: name.into(),
+            events: EventLog::disabled(),
+        }
+    }
+
+    /// Emit a `loop_restart` event whenever the loop is restarted.
+    pub fn with_events(mut self, events: EventLog) -> Self {
+        self.events = events;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Mark the loop as failed, until it is healthy again.
+    pub fn failed(&self, reason: &str) {
+        warn!("Loop {} failed: {}", self.name, reason);
+        self.state.set_loop_failed(&self.name, true);
+        gauge!("saimiris_loop_failed", "loop" => self.name.clone()).set(1.0);
+    }
+
+    /// Count a restart of the loop, after it failed for `reason`.
+    pub fn restarted(&self, reason: &str) {
+        info!("Restarting loop {}", self.name);
+        counter!("saimiris_loop_restarts_total", "loop" => self.name.clone()).increment(1);
+        self.events.emit(
+            EventKind::LoopRestart,
+            None,
+            BTreeMap::from([
+                ("loop".to_string(), self.name.clone()),
+                ("reason".to_string(), reason.to_string()),
+            ]),
+        );
+    }
+
+    /// Mark the loop as healthy, if it had failed.
+    pub fn healthy(&self) {
+        if self.state.set_loop_failed(&self.name, false) {
+            info!("Loop {} is healthy again", self.name);
+            gauge!("saimiris_loop_failed", "loop" => self.name.clone()).set(0.0);
+        }
+    }
+}
+
+/// Run a loop, a panic making it exit as failed so that it is restarted by `supervise`.
+pub async fn catch_panic<F>(exit: F) -> LoopExit
+where
+    F: Future<Output = LoopExit>,
+{
+    let mut exit = std::pin::pin!(exit);
+    std::future::poll_fn(|cx| {
+        match panic::catch_unwind(AssertUnwindSafe(|| exit.as_mut().poll(cx))) {
+            Ok(poll) => poll,
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                Poll::Ready(LoopExit::Failed(format!("panicked: {}", message)))
+            }
+        }
+    })
+    .await
+}
+
+/// Run a loop, restarting it with backoff whenever it fails, until it stops or `cancel` is
+/// cancelled. `start` spawns the loop and returns a future resolving once it exits.
+pub async fn supervise<S, F>(
+    health: LoopHealth,
+    cancel: CancellationToken,
+    mut backoff: Backoff,
+    mut start: S,
+) where
+    S: FnMut() -> F,
+    F: Future<Output = LoopExit>,
+{
+    loop {
+        let exit = start();
+        tokio::pin!(exit);
+        let exit = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return,
+            exit = &mut exit => exit,
+            _ = tokio::time::sleep(backoff.max()) => {
+                health.healthy();
+                backoff.reset();
+                tokio::select! {
+                    biased;
+                    _ = cancel.cancelled() => return,
+                    exit = &mut exit => exit,
+                }
+            }
+        };
+
+        let reason = match exit {
+            LoopExit::Stopped => return,
+            LoopExit::Failed(reason) => reason,
+        };
+        health.failed(&reason);
+        tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return,
+            _ = tokio::time::sleep(backoff.next_delay()) => {}
+        }
+        health.restarted(&reason);
+    }
+}
@@ -0,0 +1,314 @@
+use anyhow::Result;
+
+use crate::config::CaracatConfig;
+
+#[cfg(all(target_os = "linux", feature = "tx-ring"))]
+pub use linux::TxRingSender;
+
+/// Frames of the TX ring, written by the sender then flushed to the kernel at once.
+pub const TX_RING_FRAMES: usize = 4096;
+/// Size of a frame of the TX ring, the largest probe (TTL 255) taking less than 400 bytes.
+pub const TX_RING_FRAME_SIZE: usize = 2048;
+
+/// Backend sending the probes of a caracat instance (`sender_backend`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SenderBackend {
+    /// The probes built by caracat, sent in batches with one `sendmmsg` system call per burst
+    /// on Linux, and with caracat's libpcap sender elsewhere
+    #[default]
+    Caracat,
+    /// caracat's libpcap sender, one write per probe
+    Pcap,
+    /// Experimental: the probes built by caracat are written to a PACKET_MMAP TX ring, and
+    /// handed to the kernel with one system call per burst, bypassing the qdisc
+    TxRing,
+}
+
+impl SenderBackend {
+    pub fn new(config: &CaracatConfig) -> Result<Self> {
+        match config.sender_backend.as_str() {
+            "" | "caracat" => Ok(SenderBackend::Caracat),
+            "pcap" => Ok(SenderBackend::Pcap),
+            "tx_ring" if !cfg!(target_os = "linux") => {
+                anyhow::bail!("sender_backend 'tx_ring' is only supported on Linux")
+            }
+            "tx_ring" if !cfg!(feature = "tx-ring") => anyhow::bail!(
+                "sender_backend 'tx_ring' requires saimiris to be built with the `tx-ring` feature"
+            ),
+            "tx_ring" => Ok(SenderBackend::TxRing),
+            other => anyhow::bail!(
+                "Invalid sender_backend '{}'. Expected 'caracat', 'pcap' or 'tx_ring'",
+                other
+            ),
+        }
+    }
+
+    /// Whether the probes are sent with their DSCP: the packets built by the agent are marked,
+    /// while caracat's libpcap sender sends every probe with the default traffic class.
+    pub fn marks_dscp(self) -> bool {
+        match self {
+            SenderBackend::Caracat => cfg!(target_os = "linux"),
+            SenderBackend::Pcap => false,
+            SenderBackend::TxRing => true,
+        }
+    }
+}
+
+/// Set the DSCP of the IPv4 or IPv6 header at the start of `l3`, keeping its ECN bits,
+/// and update the IPv4 header checksum.
+pub fn set_dscp(l3: &mut [u8], dscp: u8) {
+    let dscp = (dscp & 0x3f) << 2;
+    match l3[0] >> 4 {
+        4 => {
+            l3[1] = dscp | (l3[1] & 0x03);
+            let header_size = (l3[0] & 0x0f) as usize * 4;
+            l3[10..12].fill(0);
+            let checksum = ipv4_checksum(&l3[..header_size]);
+            l3[10..12].copy_from_slice(&checksum.to_be_bytes());
+        }
+        6 => {
+            // The traffic class spans the first two bytes, after the version
+            let traffic_class = dscp | ((l3[1] >> 4) & 0x03);
+            l3[0] = (l3[0] & 0xf0) | (traffic_class >> 4);
+            l3[1] = (l3[1] & 0x0f) | (traffic_class << 4);
+        }
+        _ => {}
+    }
+}
+
+/// Internet checksum of an IPv4 header, whose checksum field is zeroed.
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u32)
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(all(target_os = "linux", feature = "tx-ring"))]
+mod linux {
+    use anyhow::{Context, Result};
+    use caracat::models::Probe;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::os::fd::{AsRawFd, OwnedFd};
+    use std::sync::atomic::{fence, Ordering};
+    use tracing::info;
+
+    use super::{TX_RING_FRAMES, TX_RING_FRAME_SIZE};
+    use crate::agent::mmsg::{bind_packet_socket, open_packet_socket, PacketBuilder};
+
+    // From linux/if_packet.h
+    const SOL_PACKET: libc::c_int = 263;
+    const PACKET_VERSION: libc::c_int = 10;
+    const PACKET_TX_RING: libc::c_int = 13;
+    const PACKET_QDISC_BYPASS: libc::c_int = 20;
+    const TPACKET_V2: libc::c_int = 1;
+    const TP_STATUS_AVAILABLE: u32 = 0;
+    const TP_STATUS_SEND_REQUEST: u32 = 1;
+    const TP_STATUS_WRONG_FORMAT: u32 = 4;
+    // Frames per block of the ring, allocated contiguously by the kernel
+    const FRAMES_PER_BLOCK: usize = 32;
+    // Offset of the frame data, TPACKET_ALIGN(sizeof(tpacket2_hdr))
+    const FRAME_DATA_OFFSET: usize = 32;
+
+    // struct tpacket_req
+    #[repr(C)]
+    struct TpacketReq {
+        tp_block_size: u32,
+        tp_block_nr: u32,
+        tp_frame_size: u32,
+        tp_frame_nr: u32,
+    }
+
+    // struct tpacket2_hdr, up to the data length
+    #[repr(C)]
+    struct Tpacket2Hdr {
+        tp_status: u32,
+        tp_len: u32,
+    }
+
+    /// Sends the probes through a TPACKET_V2 TX ring: the frames of a burst are written to the
+    /// ring, then sent by the kernel on `flush`. The packets are those of caracat's sender.
+    pub struct TxRingSender {
+        fd: OwnedFd,
+        ring: *mut u8,
+        // Next frame to write, and the frames written since the last flush
+        frame: usize,
+        pending: usize,
+        builder: PacketBuilder,
+        dry_run: bool,
+    }
+
+    // The ring is only accessed by the send loop thread owning the sender
+    unsafe impl Send for TxRingSender {}
+
+    fn setsockopt<T>(fd: &OwnedFd, option: libc::c_int, value: &T, action: &str) -> Result<()> {
+        let result = unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                SOL_PACKET,
+                option,
+                value as *const T as *const libc::c_void,
+                std::mem::size_of::<T>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(std::io::Error::last_os_error()).context(action.to_string());
+        }
+        Ok(())
+    }
+
+    impl TxRingSender {
+        /// Same parameters as caracat's `Sender::new`, the addresses being resolved likewise.
+        pub fn new(
+            interface: &str,
+            ipv4_src_addr: Option<Ipv4Addr>,
+            ipv6_src_addr: Option<Ipv6Addr>,
+            instance_id: u16,
+            dry_run: bool,
+        ) -> Result<Self> {
+            let builder = PacketBuilder::new(interface, ipv4_src_addr, ipv6_src_addr, instance_id)?;
+
+            let fd = open_packet_socket()?;
+            setsockopt(
+                &fd,
+                PACKET_VERSION,
+                &TPACKET_V2,
+                "Failed to select TPACKET_V2",
+            )?;
+            // The probes are paced by the agent, they skip the qdisc of the interface
+            setsockopt(
+                &fd,
+                PACKET_QDISC_BYPASS,
+                &(1 as libc::c_int),
+                "Failed to bypass the qdisc",
+            )?;
+            let request = TpacketReq {
+                tp_block_size: (TX_RING_FRAME_SIZE * FRAMES_PER_BLOCK) as u32,
+                tp_block_nr: (TX_RING_FRAMES / FRAMES_PER_BLOCK) as u32,
+                tp_frame_size: TX_RING_FRAME_SIZE as u32,
+                tp_frame_nr: TX_RING_FRAMES as u32,
+            };
+            setsockopt(
+                &fd,
+                PACKET_TX_RING,
+                &request,
+                "Failed to set up the TX ring",
+            )?;
+
+            let ring = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    TX_RING_FRAME_SIZE * TX_RING_FRAMES,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    fd.as_raw_fd(),
+                    0,
+                )
+            };
+            if ring == libc::MAP_FAILED {
+                return Err(std::io::Error::last_os_error()).context("Failed to map the TX ring");
+            }
+            // From now on, the ring is unmapped when the sender is dropped
+            let sender = TxRingSender {
+                fd,
+                ring: ring as *mut u8,
+                frame: 0,
+                pending: 0,
+                builder,
+                dry_run,
+            };
+            bind_packet_socket(&sender.fd, interface)?;
+
+            info!(
+                "TX ring of {} frames on {}, src_ip_v4={} src_ip_v6={}",
+                TX_RING_FRAMES,
+                interface,
+                sender.builder.src_ip_v4(),
+                sender.builder.src_ip_v6()
+            );
+            Ok(sender)
+        }
+
+        fn frame_header(&self, frame: usize) -> *mut Tpacket2Hdr {
+            unsafe { self.ring.add(frame * TX_RING_FRAME_SIZE) as *mut Tpacket2Hdr }
+        }
+
+        fn frame_status(&self, frame: usize) -> u32 {
+            unsafe { std::ptr::addr_of!((*self.frame_header(frame)).tp_status).read_volatile() }
+        }
+
+        /// Build the packet of the probe, as caracat's `Sender::send` but marked with `dscp`,
+        /// and write it to the next frame of the ring. The ring is flushed when full.
+        pub fn send(&mut self, probe: &Probe, dscp: u8) -> Result<()> {
+            if self.dry_run {
+                self.builder.build(probe, dscp, |_| ());
+                return Ok(());
+            }
+            self.wait_frame()?;
+            let frame = unsafe { self.ring.add(self.frame * TX_RING_FRAME_SIZE) };
+            self.builder.build(probe, dscp, |data| unsafe {
+                std::ptr::copy_nonoverlapping(
+                    data.as_ptr(),
+                    frame.add(FRAME_DATA_OFFSET),
+                    data.len(),
+                );
+                let header = frame as *mut Tpacket2Hdr;
+                std::ptr::addr_of_mut!((*header).tp_len).write_volatile(data.len() as u32);
+                fence(Ordering::Release);
+                std::ptr::addr_of_mut!((*header).tp_status).write_volatile(TP_STATUS_SEND_REQUEST);
+            });
+            self.frame = (self.frame + 1) % TX_RING_FRAMES;
+            self.pending += 1;
+            if self.pending == TX_RING_FRAMES {
+                self.flush()?;
+            }
+            Ok(())
+        }
+
+        /// Wait for the kernel to release the next frame, sent on a previous flush.
+        fn wait_frame(&mut self) -> Result<()> {
+            loop {
+                match self.frame_status(self.frame) {
+                    TP_STATUS_AVAILABLE => break,
+                    TP_STATUS_WRONG_FORMAT => {
+                        unsafe {
+                            std::ptr::addr_of_mut!((*self.frame_header(self.frame)).tp_status)
+                                .write_volatile(TP_STATUS_AVAILABLE)
+                        };
+                        anyhow::bail!("Frame rejected by the kernel");
+                    }
+                    _ => self.flush()?,
+                }
+            }
+            fence(Ordering::Acquire);
+            Ok(())
+        }
+
+        /// Hand the frames written since the last flush to the kernel, and wait for them to be
+        /// sent.
+        pub fn flush(&mut self) -> Result<()> {
+            self.pending = 0;
+            let result = unsafe { libc::send(self.fd.as_raw_fd(), std::ptr::null(), 0, 0) };
+            if result < 0 {
+                return Err(std::io::Error::last_os_error()).context("Failed to send the TX ring");
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for TxRingSender {
+        fn drop(&mut self) {
+            let _ = self.flush();
+            unsafe {
+                libc::munmap(
+                    self.ring as *mut libc::c_void,
+                    TX_RING_FRAME_SIZE * TX_RING_FRAMES,
+                )
+            };
+        }
+    }
+}
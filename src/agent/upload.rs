@@ -0,0 +1,237 @@
+use anyhow::Result;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use metrics::counter;
+use reqwest::{Client, StatusCode};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, error, info, warn};
+
+use crate::agent::{chaos, gateway};
+use crate::config::GatewayConfig;
+
+/// Compressed replies, uploaded to the gateway under their upload ID and sequence number.
+/// Uploading a chunk twice is harmless, so failed uploads are simply resumed from the
+/// oldest chunk not acknowledged by the gateway.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub upload_id: String,
+    pub sequence: u64,
+    pub body: Vec<u8>,
+}
+
+impl Chunk {
+    pub fn file_name(&self) -> String {
+        format!("{}-{:06}.gz", self.upload_id, self.sequence)
+    }
+
+    /// Upload ID and sequence number of a spooled chunk.
+    pub fn parse_file_name(name: &str) -> Option<(String, u64)> {
+        let (upload_id, sequence) = name.strip_suffix(".gz")?.rsplit_once('-')?;
+        Some((upload_id.to_string(), sequence.parse().ok()?))
+    }
+}
+
+pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+pub fn chunk_url(gateway_url: &str, agent_id: &str, chunk: &Chunk) -> String {
+    format!(
+        "{}/agent-api/agent/{}/replies/{}/{}",
+        gateway_url.trim_end_matches('/'),
+        agent_id,
+        chunk.upload_id,
+        chunk.sequence
+    )
+}
+
+/// Chunks left in the spool directory by a previous run, oldest first.
+pub fn read_spool(dir: &Path) -> Result<Vec<Chunk>> {
+    let mut chunks = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some((upload_id, sequence)) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(Chunk::parse_file_name)
+        else {
+            continue;
+        };
+        chunks.push((
+            fs::metadata(&path)?.modified()?,
+            Chunk {
+                upload_id,
+                sequence,
+                body: fs::read(&path)?,
+            },
+        ));
+    }
+    chunks.sort_by(|(a_time, a), (b_time, b)| {
+        (a_time, &a.upload_id, a.sequence).cmp(&(b_time, &b.upload_id, b.sequence))
+    });
+    Ok(chunks.into_iter().map(|(_, chunk)| chunk).collect())
+}
+
+fn remove_spooled(spool_dir: Option<&PathBuf>, chunk: &Chunk) {
+    if let Some(dir) = spool_dir {
+        if let Err(e) = fs::remove_file(dir.join(chunk.file_name())) {
+            warn!(
+                "Failed to remove spooled chunk {}: {}",
+                chunk.file_name(),
+                e
+            );
+        }
+    }
+}
+
+async fn put_chunk(
+    client: &Client,
+    gateway_url: &str,
+    agent_id: &str,
+    agent_key: &str,
+    chunk: &Chunk,
+) -> Result<()> {
+    let response = client
+        .put(chunk_url(gateway_url, agent_id, chunk))
+        .header("authorization", format!("Bearer {}", agent_key))
+        .header("content-type", "application/octet-stream")
+        .header("content-encoding", "gzip")
+        .body(chunk.body.clone())
+        .send()
+        .await?;
+    chaos::delay_gateway_response().await;
+    // The gateway already has the chunk
+    if response.status().is_success() || response.status() == StatusCode::CONFLICT {
+        Ok(())
+    } else {
+        anyhow::bail!(
+            "Failed to upload replies chunk {}: HTTP {}",
+            chunk.file_name(),
+            response.status()
+        )
+    }
+}
+
+/// Buffer the serialized replies, and upload them to the gateway in compressed chunks at
+/// every upload interval, or once the maximum chunk size is reached. Chunks failing to
+/// upload are retried at the next interval, and survive restarts with a spool directory.
+pub async fn upload_loop(
+    gateway_url: String,
+    agent_id: String,
+    agent_key: String,
+    config: GatewayConfig,
+    mut rx: Receiver<Vec<u8>>,
+) {
+    let chunk_bytes = config.upload_chunk_bytes.max(1);
+    let max_pending_chunks = config.upload_max_pending_chunks.max(1);
+    let mut upload = interval(Duration::from_secs(config.upload_interval.max(1)));
+    upload.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let spool_dir = config.upload_spool_dir;
+    let client = gateway::client();
+
+    let upload_id = uuid::Uuid::new_v4().simple().to_string();
+    let mut pending: VecDeque<Chunk> = VecDeque::new();
+    if let Some(dir) = &spool_dir {
+        if let Err(e) = fs::create_dir_all(dir) {
+            error!("Failed to create spool directory {}: {}", dir.display(), e);
+        }
+        match read_spool(dir) {
+            Ok(chunks) => {
+                if !chunks.is_empty() {
+                    info!("Resuming the upload of {} spooled chunks", chunks.len());
+                }
+                pending.extend(chunks);
+            }
+            Err(e) => error!("Failed to read spool directory {}: {}", dir.display(), e),
+        }
+    }
+
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut sequence = 0;
+    let mut closed = false;
+    loop {
+        let flush = tokio::select! {
+            message = rx.recv(), if !closed => match message {
+                Some(message) => {
+                    buffer.extend_from_slice(&message);
+                    buffer.len() >= chunk_bytes
+                }
+                None => {
+                    info!("Reply channel closed, uploading the remaining replies to the gateway");
+                    closed = true;
+                    true
+                }
+            },
+            _ = upload.tick() => true,
+        };
+        if !flush {
+            continue;
+        }
+
+        if !buffer.is_empty() {
+            match compress(&buffer) {
+                Ok(body) => {
+                    let chunk = Chunk {
+                        upload_id: upload_id.clone(),
+                        sequence,
+                        body,
+                    };
+                    sequence += 1;
+                    if let Some(dir) = &spool_dir {
+                        if let Err(e) = fs::write(dir.join(chunk.file_name()), &chunk.body) {
+                            warn!("Failed to spool chunk {}: {}", chunk.file_name(), e);
+                        }
+                    }
+                    pending.push_back(chunk);
+                }
+                Err(e) => error!("Failed to compress replies: {}", e),
+            }
+            buffer.clear();
+            while pending.len() > max_pending_chunks {
+                if let Some(chunk) = pending.pop_front() {
+                    warn!(
+                        "Too many chunks pending upload, dropping {}",
+                        chunk.file_name()
+                    );
+                    remove_spooled(spool_dir.as_ref(), &chunk);
+                    counter!("saimiris_gateway_chunks_total", "agent" => agent_id.clone(), "status" => "dropped")
+                        .increment(1);
+                }
+            }
+        }
+
+        while let Some(chunk) = pending.pop_front() {
+            match put_chunk(&client, &gateway_url, &agent_id, &agent_key, &chunk).await {
+                Ok(()) => {
+                    debug!(
+                        "Uploaded {} bytes of replies in {}",
+                        chunk.body.len(),
+                        chunk.file_name()
+                    );
+                    remove_spooled(spool_dir.as_ref(), &chunk);
+                    counter!("saimiris_gateway_chunks_total", "agent" => agent_id.clone(), "status" => "success")
+                        .increment(1);
+                }
+                Err(e) => {
+                    error!("{}. Retrying at the next upload.", e);
+                    counter!("saimiris_gateway_chunks_total", "agent" => agent_id.clone(), "status" => "failure")
+                        .increment(1);
+                    pending.push_front(chunk);
+                    break;
+                }
+            }
+        }
+
+        if closed && pending.is_empty() {
+            return;
+        }
+    }
+}
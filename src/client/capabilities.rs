@@ -0,0 +1,92 @@
+use anyhow::Result;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::error::KafkaError;
+use rdkafka::{Message, Offset, TopicPartitionList};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio::time::{timeout_at, Instant};
+use tracing::{debug, warn};
+
+use crate::agent::capabilities::AgentCapabilities;
+use crate::auth::KafkaAuth;
+use crate::config::AppConfig;
+
+const AGENTS_TOPIC_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn create_consumer(config: &AppConfig, auth: KafkaAuth) -> Result<StreamConsumer> {
+    let mut client_config = ClientConfig::new();
+    client_config
+        .set("bootstrap.servers", config.kafka.brokers.clone())
+        // Offsets are never committed, the topic is always read from the beginning
+        .set(
+            "group.id",
+            format!("saimiris-client-{}", uuid::Uuid::new_v4().simple()),
+        )
+        .set("enable.auto.commit", "false")
+        .set("enable.partition.eof", "true");
+    if let KafkaAuth::SasalPlainText(scram_auth) = auth {
+        client_config
+            .set("sasl.username", scram_auth.username)
+            .set("sasl.password", scram_auth.password)
+            .set("sasl.mechanisms", scram_auth.mechanism)
+            .set("security.protocol", "SASL_PLAINTEXT");
+    }
+    config.kafka.apply_properties(&mut client_config);
+    Ok(client_config.create()?)
+}
+
+/// Read the latest capabilities published by every agent on the agents topic.
+pub async fn read_agent_capabilities(
+    config: &AppConfig,
+    auth: KafkaAuth,
+    topic: &str,
+) -> Result<HashMap<String, AgentCapabilities>> {
+    let consumer = create_consumer(config, auth)?;
+    let metadata = consumer.fetch_metadata(Some(topic), AGENTS_TOPIC_TIMEOUT)?;
+    let partitions = metadata
+        .topics()
+        .first()
+        .map(|t| t.partitions().len())
+        .unwrap_or_default();
+    if partitions == 0 {
+        anyhow::bail!("Agents topic {} not found", topic);
+    }
+
+    let mut assignment = TopicPartitionList::new();
+    for partition in 0..partitions {
+        assignment.add_partition_offset(topic, partition as i32, Offset::Beginning)?;
+    }
+    consumer.assign(&assignment)?;
+
+    let mut capabilities = HashMap::new();
+    let mut read_partitions = HashSet::new();
+    let deadline = Instant::now() + AGENTS_TOPIC_TIMEOUT;
+    while read_partitions.len() < partitions {
+        let message = match timeout_at(deadline, consumer.recv()).await {
+            Ok(Ok(message)) => message,
+            Ok(Err(KafkaError::PartitionEOF(partition))) => {
+                read_partitions.insert(partition);
+                continue;
+            }
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => anyhow::bail!("Timed out reading the agents topic {}", topic),
+        };
+        match message.payload() {
+            Some(payload) => match serde_json::from_slice::<AgentCapabilities>(payload) {
+                Ok(agent) => {
+                    capabilities.insert(agent.agent_id.clone(), agent);
+                }
+                Err(e) => warn!("Invalid agent capabilities: {}", e),
+            },
+            // Tombstone of a decommissioned agent
+            None => {
+                if let Some(key) = message.key() {
+                    capabilities.remove(String::from_utf8_lossy(key).as_ref());
+                }
+            }
+        }
+    }
+    debug!("{} agents published their capabilities", capabilities.len());
+    Ok(capabilities)
+}
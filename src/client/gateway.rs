@@ -0,0 +1,337 @@
+use anyhow::Result;
+use reqwest::Client;
+use serde::Serialize;
+use std::io::{stdout, Write};
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::agent::gateway::{fetch_agents, http_client, RegisteredAgent};
+use crate::client::producer::{distribute_probes, retry_backoff, MeasurementInfo, ProbeWithSource};
+use crate::client::summary::{ProduceStats, SubmissionSummary};
+use crate::config::{AppConfig, ClientConfig};
+
+/// Probes of a measurement submitted to the gateway for one agent. The probes have the fields
+/// of the inline probes of the measurements assigned by the gateway.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbesChunk {
+    pub agent: String,
+    pub src_ip: Option<String>,
+    pub instance: Option<String>,
+    // Position of the chunk among those of the agent, the last one completing the submission
+    pub sequence: u64,
+    pub last: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub round: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dscp: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expand_ttl: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub src_ports: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+    pub probes: Vec<serde_json::Value>,
+}
+
+/// JSON object of a probe, as read by the client in the JSONL format.
+pub fn probe_json((probe, src_addr): &ProbeWithSource) -> serde_json::Value {
+    let mut value = serde_json::json!({
+        "dst_addr": probe.dst_addr,
+        "src_port": probe.src_port,
+        "dst_port": probe.dst_port,
+        "ttl": probe.ttl,
+        "protocol": format!("{:?}", probe.protocol),
+    });
+    if let Some(src_addr) = src_addr {
+        value["src_addr"] = serde_json::json!(src_addr);
+    }
+    value
+}
+
+/// Split the probes of an agent into chunks of up to `gateway_chunk_probes` probes. An agent
+/// without probes is sent an empty last chunk, so that the gateway knows the submission complete.
+pub fn probes_chunks(
+    agent: &MeasurementInfo,
+    probes: &[ProbeWithSource],
+    client_config: &ClientConfig,
+) -> Vec<ProbesChunk> {
+    let tags = &client_config.probe_tags;
+    let chunks: Vec<&[ProbeWithSource]> = if probes.is_empty() {
+        vec![probes]
+    } else {
+        probes
+            .chunks(client_config.gateway_chunk_probes.max(1))
+            .collect()
+    };
+    let count = chunks.len();
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, probes)| ProbesChunk {
+            agent: agent.name.clone(),
+            src_ip: agent.src_ip.clone(),
+            instance: agent.instance.clone(),
+            sequence: i as u64,
+            last: i + 1 == count,
+            round: (tags.round != 0).then_some(tags.round),
+            dscp: (tags.dscp != 0).then_some(tags.dscp),
+            expand_ttl: client_config.expand_ttl.map(|range| range.to_string()),
+            src_ports: client_config.src_ports.map(|policy| policy.to_string()),
+            priority: client_config.priority,
+            weight: client_config.weight,
+            probes: probes.iter().map(probe_json).collect(),
+        })
+        .collect()
+}
+
+pub fn probes_url(gateway_url: &str, measurement_id: &str) -> String {
+    format!(
+        "{}/api/measurements/{}/probes",
+        gateway_url.trim_end_matches('/'),
+        measurement_id
+    )
+}
+
+/// Gateway URL, key and HTTP client of the client requests to the gateway.
+fn gateway_client(config: &AppConfig, action: &str) -> Result<(String, String, Client)> {
+    match &config.gateway {
+        Some(gateway) => match (&gateway.url, &gateway.agent_key) {
+            (Some(url), Some(agent_key)) => {
+                Ok((url.clone(), agent_key.clone(), http_client(gateway)?))
+            }
+            _ => anyhow::bail!("{} requires gateway.url and gateway.agent_key", action),
+        },
+        None => anyhow::bail!("{} requires a gateway configuration", action),
+    }
+}
+
+/// Post a chunk, retrying up to `retries` times with a backoff. Returns the size of the
+/// chunk, in bytes.
+async fn post_chunk(
+    client: &Client,
+    url: &str,
+    agent_key: &str,
+    chunk: &ProbesChunk,
+    retries: u32,
+) -> Result<usize> {
+    let body = serde_json::to_vec(chunk)?;
+    let mut attempt = 0;
+    loop {
+        let error = match client
+            .post(url)
+            .header("authorization", format!("Bearer {}", agent_key))
+            .header("content-type", "application/json")
+            .body(body.clone())
+            .send()
+            .await
+        {
+            Ok(response) if response.status().is_success() => return Ok(body.len()),
+            // Not worth retrying, e.g. an unknown agent
+            Ok(response) if response.status().is_client_error() => {
+                anyhow::bail!(
+                    "Failed to submit chunk {} of agent {}: HTTP {}",
+                    chunk.sequence,
+                    chunk.agent,
+                    response.status()
+                )
+            }
+            Ok(response) => format!("HTTP {}", response.status()),
+            Err(e) => e.to_string(),
+        };
+        if attempt >= retries {
+            anyhow::bail!(
+                "Failed to submit chunk {} of agent {}: {}",
+                chunk.sequence,
+                chunk.agent,
+                error
+            );
+        }
+        let backoff = retry_backoff(attempt);
+        warn!(
+            "Failed to submit chunk {} of agent {}: {}. Retrying in {:?}",
+            chunk.sequence, chunk.agent, error, backoff
+        );
+        sleep(backoff).await;
+        attempt += 1;
+    }
+}
+
+/// Submit the probes to the gateway, which assigns them to the agents, instead of producing
+/// them to Kafka, recording the chunks in `summary`. Returns the agents which were sent probes.
+pub async fn submit_via_gateway(
+    config: &AppConfig,
+    client_config: &ClientConfig,
+    probes: Vec<ProbeWithSource>,
+    summary: &mut SubmissionSummary,
+) -> Result<Vec<String>> {
+    let (gateway_url, agent_key, client) = gateway_client(config, "Submitting via the gateway")?;
+    let agents = &client_config.measurement_infos;
+    let measurement_id = agents
+        .first()
+        .and_then(|agent| agent.measurement_id.clone())
+        .ok_or_else(|| anyhow::anyhow!("Submitting via the gateway requires a measurement ID"))?;
+    let url = probes_url(&gateway_url, &measurement_id);
+
+    // With `Distribution::Replicate`, every agent is sent every probe
+    let probes_sets = distribute_probes(probes, agents.len(), client_config.distribution);
+    let jobs: Vec<(&MeasurementInfo, &[ProbeWithSource])> = if probes_sets.len() == 1 {
+        agents
+            .iter()
+            .map(|agent| (agent, &probes_sets[0][..]))
+            .collect()
+    } else {
+        agents
+            .iter()
+            .zip(&probes_sets)
+            .map(|(agent, probes)| (agent, &probes[..]))
+            .collect()
+    };
+
+    let total: usize = jobs.iter().map(|(_, probes)| probes.len()).sum();
+    let mut submitted = 0;
+    for (agent, probes) in &jobs {
+        let chunks = probes_chunks(agent, probes, client_config);
+        let count = chunks.len();
+        let agent_name = std::slice::from_ref(&agent.name);
+        let mut agent_submitted = 0;
+        for chunk in chunks {
+            let bytes = match post_chunk(
+                &client,
+                &url,
+                &agent_key,
+                &chunk,
+                client_config.produce_retries,
+            )
+            .await
+            {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    // The following chunks of the agent are not submitted
+                    let stats = ProduceStats {
+                        failures: 1,
+                        ..Default::default()
+                    };
+                    let remaining = probes.len() - agent_submitted;
+                    summary.record(agent_name, remaining, remaining, stats);
+                    return Err(e);
+                }
+            };
+            let stats = ProduceStats {
+                messages: 1,
+                bytes: bytes as u64,
+                failures: 0,
+            };
+            summary.record(agent_name, chunk.probes.len(), 0, stats);
+            agent_submitted += chunk.probes.len();
+            submitted += chunk.probes.len();
+            info!(
+                "measurement_id={},agent={},chunk={}/{},submitted_probes={}/{} ({:.1}%)",
+                measurement_id,
+                agent.name,
+                chunk.sequence + 1,
+                count,
+                submitted,
+                total,
+                100.0 * submitted as f64 / total.max(1) as f64
+            );
+        }
+    }
+    Ok(jobs.iter().map(|(agent, _)| agent.name.clone()).collect())
+}
+
+/// Rows of the agents table, one per caracat instance: agent, healthy, last check, instance,
+/// IPv4 prefix, IPv6 prefix and probing rate.
+pub fn agents_rows(agents: &[RegisteredAgent]) -> Vec<[String; 7]> {
+    let dash = || "-".to_string();
+    let mut rows = Vec::new();
+    for agent in agents {
+        let healthy = match &agent.health {
+            Some(health) if health.healthy => "yes",
+            Some(_) => "no",
+            None => "unknown",
+        };
+        let last_check = agent
+            .health
+            .as_ref()
+            .and_then(|health| health.last_check.clone())
+            .unwrap_or_else(dash);
+        let row = |instance, ipv4_prefix, ipv6_prefix, rate| {
+            [
+                agent.id.clone(),
+                healthy.to_string(),
+                last_check.clone(),
+                instance,
+                ipv4_prefix,
+                ipv6_prefix,
+                rate,
+            ]
+        };
+        if agent.config.is_empty() {
+            rows.push(row(dash(), dash(), dash(), dash()));
+        }
+        for instance in &agent.config {
+            // Named as in the logs and metrics of the agent
+            rows.push(row(
+                instance
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("instance_{}", instance.instance_id)),
+                instance.src_ipv4_prefix.clone().unwrap_or_else(dash),
+                instance.src_ipv6_prefix.clone().unwrap_or_else(dash),
+                instance.probing_rate.to_string(),
+            ));
+        }
+    }
+    rows
+}
+
+/// Print the rows with aligned columns, under a header.
+pub fn write_table<W: Write>(out: &mut W, rows: &[[String; 7]]) -> std::io::Result<()> {
+    let header = [
+        "AGENT",
+        "HEALTHY",
+        "LAST CHECK",
+        "INSTANCE",
+        "IPV4 PREFIX",
+        "IPV6 PREFIX",
+        "RATE (PPS)",
+    ];
+    let mut widths = header.map(str::len);
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let header = header.map(str::to_string);
+    for row in std::iter::once(&header).chain(rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        writeln!(out, "{}", cells.join("  ").trim_end())?;
+    }
+    Ok(())
+}
+
+/// `client list-agents` command: print the agents registered on the gateway, as a table or
+/// as JSON.
+pub async fn list_agents(config: &AppConfig, json: bool) -> Result<()> {
+    let (gateway_url, agent_key, client) = gateway_client(config, "Listing the agents")?;
+    let mut agents = fetch_agents(&client, &gateway_url, &agent_key)
+        .await
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    agents.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let mut out = stdout().lock();
+    if json {
+        serde_json::to_writer_pretty(&mut out, &agents)?;
+        writeln!(out)?;
+    } else {
+        write_table(&mut out, &agents_rows(&agents))?;
+    }
+    info!("{} agents registered on the gateway", agents.len());
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::client::rib::mix;
+
+/// Addresses of the prefix of a destination over which its flows are spread: its /24 in
+/// IPv4, its /120 in IPv6, as diamond-miner.
+pub const FLOW_PREFIX_SIZE: u64 = 256;
+/// Step between the addresses of consecutive flows of the interval mapper.
+const INTERVAL_STEP: u64 = 32;
+
+/// Mapping of the flow IDs of a destination to a destination address within its prefix and a
+/// source port offset. Beyond the addresses of the prefix, the flows keep the last address
+/// and vary the source port, as diamond-miner's flow mappers, so that MDA-style topology
+/// discovery reaches the load-balanced paths towards the prefix with few flows.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FlowMapper {
+    /// Flows on the source port only, towards the destination itself (Paris traceroute)
+    #[default]
+    Ports,
+    /// Addresses of the prefix in order
+    Sequential,
+    /// Addresses of the prefix 32 apart, then shifted by one, spreading the first flows over
+    /// the prefix
+    Interval,
+    /// Addresses of the prefix with their last byte bit-reversed
+    ReverseByte,
+    /// Addresses of the prefix in a random order, reproducible per prefix
+    Random,
+}
+
+impl FlowMapper {
+    /// Destination address and source port offset of the flow `flow_id` of `destination`.
+    pub fn flow(&self, destination: IpAddr, flow_id: u64) -> (IpAddr, u16) {
+        if *self == FlowMapper::Ports {
+            return (destination, flow_id as u16);
+        }
+        let network = prefix_network(destination);
+        let (offset, port_offset) = if flow_id < FLOW_PREFIX_SIZE {
+            (self.address_offset(network, flow_id), 0)
+        } else {
+            (
+                self.address_offset(network, FLOW_PREFIX_SIZE - 1),
+                (flow_id - FLOW_PREFIX_SIZE + 1) as u16,
+            )
+        };
+        let address = match destination {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(network as u32 + offset as u32)),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(network + offset as u128)),
+        };
+        (address, port_offset)
+    }
+
+    /// Offset of the flow `flow_id` (below `FLOW_PREFIX_SIZE`) within the prefix starting at
+    /// `network`.
+    fn address_offset(&self, network: u128, flow_id: u64) -> u64 {
+        match self {
+            FlowMapper::Ports | FlowMapper::Sequential => flow_id,
+            FlowMapper::Interval => {
+                let period = FLOW_PREFIX_SIZE / INTERVAL_STEP;
+                (flow_id % period) * INTERVAL_STEP + flow_id / period
+            }
+            FlowMapper::ReverseByte => (flow_id as u8).reverse_bits() as u64,
+            FlowMapper::Random => random_permutation(network)[flow_id as usize] as u64,
+        }
+    }
+}
+
+/// First address of the prefix of `destination` over which its flows are spread.
+fn prefix_network(destination: IpAddr) -> u128 {
+    match destination {
+        IpAddr::V4(address) => (u32::from(address) & !(FLOW_PREFIX_SIZE as u32 - 1)) as u128,
+        IpAddr::V6(address) => u128::from(address) & !(FLOW_PREFIX_SIZE as u128 - 1),
+    }
+}
+
+/// Permutation of the offsets of the prefix starting at `network` (Fisher-Yates).
+fn random_permutation(network: u128) -> [u8; FLOW_PREFIX_SIZE as usize] {
+    let mut permutation = [0u8; FLOW_PREFIX_SIZE as usize];
+    for (i, offset) in permutation.iter_mut().enumerate() {
+        *offset = i as u8;
+    }
+    let mut state = mix(network as u64 ^ mix((network >> 64) as u64));
+    for i in (1..permutation.len()).rev() {
+        state = mix(state);
+        permutation.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+    permutation
+}
@@ -1,69 +1,575 @@
 use anyhow::Result;
 use caracat::models::Probe;
-use csv::ReaderBuilder;
-use std::io::{stdin, BufRead};
-use tracing::trace;
+use csv::{ByteRecord, ReaderBuilder};
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::io::{stdin, stdout, BufRead, Read};
+use std::net::IpAddr;
+use tracing::{error, info, trace, warn};
 
+use crate::agent::capabilities::{check_agents, Requirements};
+use crate::agent::expand::expand_target;
 use crate::auth::{KafkaAuth, SaslAuth};
-use crate::client::producer::produce;
-use crate::config::{AppConfig, ClientConfig};
+use crate::client::capabilities::read_agent_capabilities;
+use crate::client::gateway::submit_via_gateway;
+use crate::client::manifest::FailureManifest;
+use crate::client::producer::{cancel_measurement, produce, ProbeWithSource, ProduceOptions};
+use crate::client::summary::SubmissionSummary;
+use crate::client::wait::wait_for_completion;
+use crate::config::{AppConfig, ClientConfig, ProbesFormat};
+use crate::join::{write_probe_index, ProbeIndexRecord};
+use crate::measurement::MeasurementSpec;
 
-pub fn read_probes_from_csv<R: BufRead>(buf_reader: R) -> Result<Vec<Probe>> {
-    let probes = Vec::new();
+/// Size of the batches of CSV read at once, so that large files are not held in memory.
+const CSV_BATCH_BYTES: usize = 64 * 1024 * 1024;
+/// Size of the chunks of a batch parsed in parallel.
+const CSV_CHUNK_BYTES: usize = 1024 * 1024;
+/// Approximate size of a CSV probe, to pre-size the probes buffers.
+const CSV_PROBE_BYTES: usize = 32;
+/// Fields of a CSV probe, optionally followed by its source address.
+const CSV_PROBE_FIELDS: usize = 5;
+
+/// JSON probe, with its optional source address.
+#[derive(Deserialize)]
+struct JsonProbe {
+    #[serde(flatten)]
+    probe: Probe,
+    #[serde(default)]
+    src_addr: Option<IpAddr>,
+}
+
+/// Parse a CSV probe, with its source address in an optional sixth field.
+fn parse_csv_record(record: &mut ByteRecord) -> Result<ProbeWithSource> {
+    let src_addr = match record.len() {
+        CSV_PROBE_FIELDS => None,
+        len if len == CSV_PROBE_FIELDS + 1 => {
+            let field = std::str::from_utf8(&record[CSV_PROBE_FIELDS])?;
+            let src_addr = match field {
+                "" => None,
+                field => Some(field.parse::<IpAddr>().map_err(|e| {
+                    anyhow::anyhow!(e).context(format!("Invalid source address {}", field))
+                })?),
+            };
+            record.truncate(CSV_PROBE_FIELDS);
+            src_addr
+        }
+        len => anyhow::bail!(
+            "Expected {} or {} fields, found {}",
+            CSV_PROBE_FIELDS,
+            CSV_PROBE_FIELDS + 1,
+            len
+        ),
+    };
+    Ok((record.deserialize(None)?, src_addr))
+}
+
+/// Parse the CSV probes of a chunk, starting at line `first_line` of the input.
+fn parse_csv_chunk(chunk: &[u8], first_line: usize) -> Result<Vec<ProbeWithSource>> {
     let mut rdr = ReaderBuilder::new()
         .has_headers(false)
+        .flexible(true)
         .trim(csv::Trim::All)
-        .from_reader(buf_reader);
-
-    rdr.deserialize().enumerate().try_fold(
-        probes,
-        |mut acc, (i, result): (usize, Result<Probe, _>)| {
-            acc.push(result.map_err(|e: csv::Error| {
-                anyhow::anyhow!(e).context(format!(
+        .from_reader(chunk);
+    let mut probes = Vec::with_capacity(chunk.len() / CSV_PROBE_BYTES);
+    // A single record is reused for all the lines of the chunk
+    let mut record = ByteRecord::new();
+    loop {
+        let probe = match rdr.read_byte_record(&mut record) {
+            Ok(false) => return Ok(probes),
+            Ok(true) => parse_csv_record(&mut record),
+            Err(e) => Err(e.into()),
+        };
+        match probe {
+            Ok(probe) => probes.push(probe),
+            Err(e) => {
+                let line = first_line
+                    + e.downcast_ref::<csv::Error>()
+                        .and_then(|e| e.position())
+                        .or(record.position())
+                        .map_or(0, |position| position.line() as usize - 1);
+                return Err(e.context(format!(
                     "Failed to deserialize probe from CSV at line {}",
-                    i + 1
-                ))
-            })?);
-            Ok(acc)
-        },
-    )
+                    line
+                )));
+            }
+        }
+    }
 }
 
-pub async fn handle(config: &AppConfig, client_config: ClientConfig) -> Result<()> {
-    trace!("Client handler");
-    trace!("{:?}", config);
+/// Split `data` in chunks of about `chunk_bytes`, at line boundaries.
+/// Probes never contain quoted newlines, so every line is a record.
+pub fn split_lines(data: &[u8], chunk_bytes: usize) -> Vec<&[u8]> {
+    let mut chunks = Vec::with_capacity(data.len() / chunk_bytes.max(1) + 1);
+    let mut start = 0;
+    while start < data.len() {
+        let end = (start + chunk_bytes.max(1)).min(data.len());
+        let end = data[end..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(data.len(), |i| end + i + 1);
+        chunks.push(&data[start..end]);
+        start = end;
+    }
+    chunks
+}
 
-    // Configure Kafka authentication
-    let auth = match config.kafka.auth_protocol.as_str() {
-        "PLAINTEXT" => KafkaAuth::PlainText,
-        "SASL_PLAINTEXT" => KafkaAuth::SasalPlainText(SaslAuth {
+/// Parse CSV probes held in memory, in chunks of `chunk_bytes` parsed in parallel,
+/// keeping the order of the probes. Returns the probes and the number of lines.
+pub fn parse_csv_batch(
+    data: &[u8],
+    first_line: usize,
+    chunk_bytes: usize,
+) -> Result<(Vec<ProbeWithSource>, usize)> {
+    let chunks = split_lines(data, chunk_bytes);
+    let lines: Vec<usize> = chunks
+        .par_iter()
+        .map(|chunk| chunk.iter().filter(|&&b| b == b'\n').count())
+        .collect();
+    let first_lines: Vec<usize> = lines
+        .iter()
+        .scan(first_line, |line, count| {
+            let first = *line;
+            *line += count;
+            Some(first)
+        })
+        .collect();
+
+    let parsed: Vec<Vec<ProbeWithSource>> = if chunks.len() <= 1 {
+        chunks
+            .iter()
+            .map(|chunk| parse_csv_chunk(chunk, first_line))
+            .collect::<Result<_>>()?
+    } else {
+        chunks
+            .par_iter()
+            .zip(first_lines.par_iter())
+            .map(|(chunk, first_line)| parse_csv_chunk(chunk, *first_line))
+            .collect::<Result<_>>()?
+    };
+
+    let mut probes = Vec::with_capacity(parsed.iter().map(Vec::len).sum());
+    for chunk in parsed {
+        probes.extend(chunk);
+    }
+    Ok((probes, lines.iter().sum()))
+}
+
+/// Read CSV probes in batches, each parsed in parallel.
+pub fn read_sourced_probes_from_csv<R: BufRead>(mut buf_reader: R) -> Result<Vec<ProbeWithSource>> {
+    let mut probes = Vec::new();
+    let mut batch: Vec<u8> = Vec::with_capacity(CSV_BATCH_BYTES);
+    let mut first_line = 1;
+    loop {
+        // Fill the batch, then parse it up to its last complete line
+        let read = (&mut buf_reader)
+            .take((CSV_BATCH_BYTES - batch.len()) as u64)
+            .read_to_end(&mut batch)?;
+        let end = match read {
+            0 => batch.len(),
+            _ => batch
+                .iter()
+                .rposition(|&b| b == b'\n')
+                .map_or(batch.len(), |i| i + 1),
+        };
+        let (parsed, lines) = parse_csv_batch(&batch[..end], first_line, CSV_CHUNK_BYTES)?;
+        probes.extend(parsed);
+        first_line += lines;
+        batch.drain(..end);
+        if read == 0 {
+            return Ok(probes);
+        }
+    }
+}
+
+pub fn read_probes_from_csv<R: BufRead>(buf_reader: R) -> Result<Vec<Probe>> {
+    Ok(without_sources(read_sourced_probes_from_csv(buf_reader)?))
+}
+
+pub fn read_sourced_probes_from_jsonl<R: BufRead>(buf_reader: R) -> Result<Vec<ProbeWithSource>> {
+    let mut probes = Vec::new();
+    for (i, line) in buf_reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let probe: JsonProbe = serde_json::from_str(&line).map_err(|e| {
+            anyhow::anyhow!(e).context(format!(
+                "Failed to deserialize probe from JSON at line {}",
+                i + 1
+            ))
+        })?;
+        probes.push((probe.probe, probe.src_addr));
+    }
+    Ok(probes)
+}
+
+pub fn read_probes_from_jsonl<R: BufRead>(buf_reader: R) -> Result<Vec<Probe>> {
+    Ok(without_sources(read_sourced_probes_from_jsonl(buf_reader)?))
+}
+
+/// Read probes, along with their source address if given.
+pub fn read_sourced_probes<R: BufRead>(
+    buf_reader: R,
+    format: ProbesFormat,
+) -> Result<Vec<ProbeWithSource>> {
+    match format {
+        ProbesFormat::Csv => read_sourced_probes_from_csv(buf_reader),
+        ProbesFormat::Jsonl => read_sourced_probes_from_jsonl(buf_reader),
+    }
+}
+
+/// Read probes, ignoring their source address.
+pub fn read_probes<R: BufRead>(buf_reader: R, format: ProbesFormat) -> Result<Vec<Probe>> {
+    Ok(without_sources(read_sourced_probes(buf_reader, format)?))
+}
+
+fn without_sources(probes: Vec<ProbeWithSource>) -> Vec<Probe> {
+    probes.into_iter().map(|(probe, _)| probe).collect()
+}
+
+pub fn kafka_auth(config: &AppConfig) -> Result<KafkaAuth> {
+    match config.kafka.auth_protocol.as_str() {
+        "PLAINTEXT" => Ok(KafkaAuth::PlainText),
+        "SASL_PLAINTEXT" => Ok(KafkaAuth::SasalPlainText(SaslAuth {
             username: config.kafka.auth_sasl_username.clone(),
             password: config.kafka.auth_sasl_password.clone(),
             mechanism: config.kafka.auth_sasl_mechanism.clone(),
-        }),
-        _ => {
-            return Err(anyhow::anyhow!(
-                "Invalid Kafka producer authentication protocol"
-            ))
-        }
+        })),
+        _ => Err(anyhow::anyhow!(
+            "Invalid Kafka producer authentication protocol"
+        )),
+    }
+}
+
+/// Fail fast if the agents published capabilities which do not meet the requirements.
+async fn check_compatibility(
+    config: &AppConfig,
+    auth: KafkaAuth,
+    agents: &[String],
+    requirements: &Requirements,
+) -> Result<()> {
+    let Some(agents_topic) = &config.kafka.agents_topic else {
+        return Ok(());
     };
+    let capabilities = read_agent_capabilities(config, auth, agents_topic).await?;
+    let unknown = check_agents(&capabilities, agents, requirements)?;
+    if !unknown.is_empty() {
+        warn!(
+            "Agents {} did not publish their capabilities, assuming they are compatible",
+            unknown.join(",")
+        );
+    }
+    Ok(())
+}
+
+/// Cancel a measurement on the given agents.
+pub async fn cancel(config: &AppConfig, agents: &[String], measurement_id: &str) -> Result<()> {
+    trace!("Client cancel handler");
+
+    let auth = kafka_auth(config)?;
+    check_compatibility(config, auth.clone(), agents, &Requirements::cancellation()).await?;
+    cancel_measurement(config, auth, agents, measurement_id).await?;
+    Ok(())
+}
+
+pub async fn handle(config: &AppConfig, client_config: ClientConfig) -> Result<()> {
+    trace!("Client handler");
+    trace!("{:?}", config);
 
     // Read probes from file or stdin
-    let probes = match client_config.probes_file {
+    let probes = match &client_config.probes_file {
         Some(probes_file) => {
             let file = std::fs::File::open(probes_file)?;
             let buf_reader = std::io::BufReader::new(file);
-            read_probes_from_csv(buf_reader)?
+            read_sourced_probes(buf_reader, client_config.probes_format)?
         }
         None => {
             let stdin = stdin();
             let buf_reader = stdin.lock();
-            read_probes_from_csv(buf_reader)?
+            read_sourced_probes(buf_reader, client_config.probes_format)?
         }
     };
 
+    submit(config, client_config, probes).await
+}
+
+/// Produce the probes to Kafka. Returns the agents which were sent probes.
+async fn submit_to_kafka(
+    config: &AppConfig,
+    auth: KafkaAuth,
+    client_config: &ClientConfig,
+    probes: Vec<ProbeWithSource>,
+    agent_names: Vec<String>,
+    measurement_id: Option<String>,
+    summary: &mut SubmissionSummary,
+) -> Result<Vec<String>> {
+    // Only resubmit the probes of a previous failure manifest
+    let retry_manifest = match &client_config.retry_manifest {
+        Some(path) => {
+            let manifest = FailureManifest::read(path)?;
+            manifest.check_agents(&agent_names)?;
+            if manifest.distribution != client_config.distribution {
+                return Err(anyhow::anyhow!(
+                    "Distribution {:?} does not match the distribution of the failure manifest: {:?}",
+                    client_config.distribution,
+                    manifest.distribution
+                ));
+            }
+            info!(
+                "Resubmitting {} probes from failure manifest {}",
+                manifest.failed_probes(),
+                path.display()
+            );
+            Some(manifest)
+        }
+        None => None,
+    };
+
     // Produce Kafka messages
-    produce(config, auth, client_config.measurement_infos, probes).await;
+    let (agents, failed_probes) = produce(
+        config,
+        auth,
+        client_config.measurement_infos.clone(),
+        probes,
+        client_config.probe_tags.clone(),
+        ProduceOptions {
+            distribution: client_config.distribution,
+            max_in_flight: client_config.max_in_flight,
+            produce_rate: client_config.produce_rate,
+            retries: client_config.produce_retries,
+            expand_ttl: client_config.expand_ttl,
+            src_ports: client_config.src_ports,
+            priority: client_config.priority,
+            weight: client_config.weight,
+            dry_run: client_config.dry_run,
+        },
+        retry_manifest
+            .as_ref()
+            .map(|manifest| &manifest.failures[..]),
+        summary,
+    )
+    .await;
+
+    // Report the probes to resubmit
+    if !failed_probes.is_empty() {
+        for failed in &failed_probes {
+            error!(
+                "agents={},probes={}..{} could not be delivered",
+                failed.agents.join(","),
+                failed.probes.start,
+                failed.probes.end
+            );
+        }
+        if let Some(path) = &client_config.failure_manifest {
+            let manifest = FailureManifest {
+                measurement_id,
+                distribution: client_config.distribution,
+                agents: agent_names,
+                failures: failed_probes.clone(),
+            };
+            manifest.write(path)?;
+            info!(
+                "Wrote failure manifest {}, resubmit with --retry-manifest",
+                path.display()
+            );
+        }
+        return Err(anyhow::anyhow!(
+            "{} probes could not be delivered to Kafka",
+            failed_probes.iter().map(|f| f.probes.len()).sum::<usize>()
+        ));
+    }
+
+    Ok(agents)
+}
+
+/// Measurement of the submission, taken from the first agent (all the agents share it).
+fn measurement_id(client_config: &ClientConfig) -> Option<String> {
+    client_config
+        .measurement_infos
+        .first()
+        .and_then(|agent| agent.measurement_id.clone())
+}
+
+/// Submit probes to the agents of `client_config`, and print the summary of the submission.
+pub async fn submit(
+    config: &AppConfig,
+    client_config: ClientConfig,
+    probes: Vec<ProbeWithSource>,
+) -> Result<()> {
+    let mut summary = SubmissionSummary::new(probes.len());
+    let result = deliver(config, &client_config, probes, &mut summary).await;
+
+    // The summary is printed even if some probes could not be delivered
+    if client_config.json_summary {
+        summary.write_json(&mut stdout().lock())?;
+    } else {
+        summary.write_text(&mut stdout().lock())?;
+    }
+    let agents = result?;
+    complete(config, &client_config, agents).await
+}
+
+/// Submit probes to the agents of `client_config`, as `submit` does, without printing the
+/// summary of the submission: the entry point of the services submitting measurements.
+/// The agents are given by `parse_and_validate_client_args`, and the options by the
+/// `ClientConfig` builders.
+pub async fn submit_probes(
+    config: &AppConfig,
+    client_config: ClientConfig,
+    probes: Vec<ProbeWithSource>,
+) -> Result<SubmissionSummary> {
+    let mut summary = SubmissionSummary::new(probes.len());
+    let agents = deliver(config, &client_config, probes, &mut summary).await?;
+    complete(config, &client_config, agents).await?;
+    Ok(summary)
+}
+
+/// Write the probe index and deliver the probes to the agents. Returns the agents which
+/// were sent probes.
+async fn deliver(
+    config: &AppConfig,
+    client_config: &ClientConfig,
+    probes: Vec<ProbeWithSource>,
+    summary: &mut SubmissionSummary,
+) -> Result<Vec<String>> {
+    // Configure Kafka authentication
+    let auth = kafka_auth(config)?;
+
+    let measurement_id = measurement_id(client_config);
+    let agent_names: Vec<String> = client_config
+        .measurement_infos
+        .iter()
+        .map(|agent| agent.name.clone())
+        .collect();
+
+    if client_config.via_gateway {
+        // The gateway reports no probes undelivered
+        if client_config.failure_manifest.is_some() || client_config.retry_manifest.is_some() {
+            anyhow::bail!("Failure manifests are not supported when submitting via the gateway");
+        }
+    } else if !client_config.dry_run {
+        check_compatibility(
+            config,
+            auth.clone(),
+            &agent_names,
+            &Requirements::submission(&client_config.probe_tags, measurement_id.as_deref())
+                .with_sources(probes.iter().any(|(_, src_addr)| src_addr.is_some()))
+                .with_ttl_expansion(client_config.expand_ttl.is_some())
+                .with_src_ports(client_config.src_ports.is_some())
+                .with_instance_pinning(
+                    client_config
+                        .measurement_infos
+                        .iter()
+                        .any(|agent| agent.instance.is_some()),
+                ),
+        )
+        .await?;
+    }
+
+    // Record the submitted probes for later joins with the replies
+    if let Some(index_file) = client_config
+        .index_file
+        .as_ref()
+        .filter(|_| !client_config.dry_run)
+    {
+        let submitted_at_ns = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64;
+        let record = |probe: &Probe| {
+            // Recorded with the source port selected by the agents
+            let probe = &Probe {
+                dst_addr: probe.dst_addr,
+                src_port: client_config
+                    .src_ports
+                    .map_or(probe.src_port, |policy| policy.src_port(probe)),
+                dst_port: probe.dst_port,
+                ttl: probe.ttl,
+                protocol: probe.protocol,
+            };
+            let mut tags = client_config.index_tags.clone();
+            if let Some(destination_tags) = client_config.destination_tags.get(&probe.dst_addr) {
+                tags.extend(destination_tags.clone());
+            }
+            ProbeIndexRecord::new(
+                probe,
+                &client_config.probe_tags,
+                measurement_id.clone(),
+                tags,
+                submitted_at_ns,
+            )
+        };
+        let records: Vec<ProbeIndexRecord> = match client_config.expand_ttl {
+            // Targets are recorded as the probes the agents expand them into
+            Some(range) => probes
+                .iter()
+                .flat_map(|(probe, _)| expand_target(probe, range))
+                .map(|probe| record(&probe))
+                .collect(),
+            None => probes.iter().map(|(probe, _)| record(probe)).collect(),
+        };
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(index_file)?;
+        write_probe_index(&mut file, &records)?;
+        info!(
+            "Wrote {} probes to index {}",
+            records.len(),
+            index_file.display()
+        );
+    }
+
+    let agents = if client_config.via_gateway {
+        submit_via_gateway(config, client_config, probes, summary).await?
+    } else {
+        submit_to_kafka(
+            config,
+            auth,
+            client_config,
+            probes,
+            agent_names,
+            measurement_id,
+            summary,
+        )
+        .await?
+    };
+    if !summary.is_success() {
+        anyhow::bail!(
+            "{} messages could not be delivered",
+            summary.produce_failures
+        );
+    }
+    Ok(agents)
+}
+
+/// Wait for the agents to send all the probes, if requested.
+async fn complete(
+    config: &AppConfig,
+    client_config: &ClientConfig,
+    agents: Vec<String>,
+) -> Result<()> {
+    if client_config.dry_run {
+        info!("Dry run, no probes were produced");
+        return Ok(());
+    }
+
+    if client_config.wait {
+        let measurement_id = measurement_id(client_config)
+            .ok_or_else(|| anyhow::anyhow!("Waiting for completion requires a measurement ID"))?;
+        let measurement = MeasurementSpec::new(measurement_id, agents);
+        let statuses =
+            wait_for_completion(config, &measurement, client_config.wait_timeout).await?;
+        for (agent, status) in &statuses {
+            info!(
+                "measurement_id={},agent={},sent_probes={},is_complete={}",
+                measurement.id, agent, status.sent_probes, status.is_complete
+            );
+        }
+        info!(
+            "measurement_id={},agents={},sent_probes={}",
+            measurement.id,
+            statuses.len(),
+            statuses.values().map(|s| s.sent_probes as u64).sum::<u64>()
+        );
+    }
 
     Ok(())
 }
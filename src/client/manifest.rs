@@ -0,0 +1,51 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::client::producer::FailedProbes;
+use crate::config::Distribution;
+
+/// Probes which could not be delivered, written on client exit so that they can be resubmitted.
+///
+/// The probe indices are relative to the set of probes sent to each group of agents, so a retry
+/// must use the same probes file, agents (in the same order) and distribution.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailureManifest {
+    #[serde(default)]
+    pub measurement_id: Option<String>,
+    pub distribution: Distribution,
+    pub agents: Vec<String>,
+    pub failures: Vec<FailedProbes>,
+}
+
+impl FailureManifest {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create failure manifest {}", path.display()))?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Failed to open failure manifest {}", path.display()))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("Invalid failure manifest {}", path.display()))
+    }
+
+    /// Check that the manifest can be replayed against the given agents.
+    pub fn check_agents(&self, agents: &[String]) -> Result<()> {
+        if self.agents != agents {
+            anyhow::bail!(
+                "Agents {} do not match the agents of the failure manifest: {}",
+                agents.join(","),
+                self.agents.join(",")
+            );
+        }
+        Ok(())
+    }
+
+    pub fn failed_probes(&self) -> usize {
+        self.failures.iter().map(|f| f.probes.len()).sum()
+    }
+}
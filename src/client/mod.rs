@@ -1,4 +1,17 @@
+pub mod capabilities;
+pub mod gateway;
+pub mod generate;
 pub mod handler;
+pub mod manifest;
+pub mod ping;
 pub mod producer;
+pub mod rib;
+pub mod summary;
+pub mod traceroute;
+pub mod wait;
 
 pub use handler::handle;
+// Library API: submit probes from other services, as the client does
+pub use handler::{read_sourced_probes, submit_probes as submit};
+pub use producer::ProbeWithSource;
+pub use summary::SubmissionSummary;
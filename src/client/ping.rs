@@ -0,0 +1,89 @@
+use anyhow::Result;
+use caracat::models::{Probe, L4};
+use std::io::{stdin, BufRead};
+use std::net::IpAddr;
+use tracing::{info, trace};
+
+use crate::client::handler::submit;
+use crate::config::{AppConfig, ClientConfig};
+
+pub const DEFAULT_PING_COUNT: u16 = 3;
+pub const DEFAULT_PING_TTL: u8 = 64;
+/// Source port of the first echo request to a destination, incremented for the next ones
+/// (caracat encodes it in the ICMP checksum, so that the replies can be told apart).
+const PING_SRC_PORT: u16 = 24000;
+const PING_DST_PORT: u16 = 33434;
+
+/// Read destinations, one address per line. Empty lines and `#` comments are ignored.
+pub fn read_destinations<R: BufRead>(buf_reader: R) -> Result<Vec<IpAddr>> {
+    let mut destinations = Vec::new();
+    for (i, line) in buf_reader.lines().enumerate() {
+        let line = line?;
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let destination: IpAddr = line.parse().map_err(|e| {
+            anyhow::anyhow!("Invalid destination '{}' at line {}: {}", line, i + 1, e)
+        })?;
+        destinations.push(destination);
+    }
+    Ok(destinations)
+}
+
+/// ICMP echo probes, `count` per destination.
+pub fn ping_probes(destinations: &[IpAddr], count: u16, ttl: u8) -> Vec<Probe> {
+    let mut probes = Vec::with_capacity(destinations.len() * count as usize);
+    for destination in destinations {
+        let protocol = match destination {
+            IpAddr::V4(_) => L4::ICMP,
+            IpAddr::V6(_) => L4::ICMPv6,
+        };
+        for i in 0..count {
+            probes.push(Probe {
+                dst_addr: *destination,
+                src_port: PING_SRC_PORT.wrapping_add(i),
+                dst_port: PING_DST_PORT,
+                ttl,
+                protocol,
+            });
+        }
+    }
+    probes
+}
+
+/// Ping the destinations from the agents of `client_config`.
+pub async fn handle(
+    config: &AppConfig,
+    client_config: ClientConfig,
+    count: u16,
+    ttl: u8,
+) -> Result<()> {
+    trace!("Ping handler");
+
+    // Read destinations from file or stdin
+    let destinations = match &client_config.probes_file {
+        Some(destinations_file) => {
+            let file = std::fs::File::open(destinations_file)?;
+            read_destinations(std::io::BufReader::new(file))?
+        }
+        None => read_destinations(stdin().lock())?,
+    };
+    if destinations.is_empty() {
+        anyhow::bail!("No destinations to ping");
+    }
+
+    let probes = ping_probes(&destinations, count, ttl);
+    info!(
+        "Pinging {} destinations with {} echo requests each (TTL {})",
+        destinations.len(),
+        count,
+        ttl
+    );
+    submit(
+        config,
+        client_config,
+        probes.into_iter().map(|probe| (probe, None)).collect(),
+    )
+    .await
+}
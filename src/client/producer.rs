@@ -1,79 +1,289 @@
 use caracat::models::Probe;
 use rdkafka::config::ClientConfig;
-use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::message::{Header, Headers, OwnedHeaders};
 use rdkafka::producer::{FutureProducer, FutureRecord};
+use serde::{Deserialize, Serialize};
 use serde_json;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv6Addr};
+use std::ops::Range;
 use std::time::Duration;
-use tracing::{error, info};
+use tokio::task::{Id, JoinError, JoinSet};
+use tokio::time::{interval, sleep, MissedTickBehavior};
+use tracing::{error, info, warn};
 
+use crate::agent::expand::TtlRange;
+use crate::agent::ports::{Fnv1a, PortPolicy};
 use crate::auth::KafkaAuth;
-use crate::config::AppConfig;
-use crate::probe::serialize_probe;
+use crate::client::summary::{ProduceStats, SubmissionSummary};
+use crate::config::{AppConfig, Distribution, KeyStrategy};
+use crate::probe::{serialize_tagged_probe, ProbeTags};
+use crate::protocol::{
+    CANCEL_MEASUREMENT_HEADER, END_OF_MEASUREMENT_HEADER, EXPAND_TTL_HEADER, MEASUREMENT_ID_HEADER,
+    PRIORITY_HEADER, SRC_PORTS_HEADER, WEIGHT_HEADER,
+};
+use crate::schema_registry::{frame, subject, SchemaRegistry, FRAME_LEN, PROBE_SCHEMA};
+use crate::signing::{sign, SIGNATURE_HEADER, SIGNATURE_KEY_ID_HEADER, SIGNATURE_LEN};
 
 #[derive(Debug, Clone)]
 pub struct MeasurementInfo {
     pub name: String,
     pub src_ip: Option<String>,
+    // Caracat instance of the agent sending the probes, instead of the prefix-based selection
+    pub instance: Option<String>,
     // Measurement tracking fields
     pub measurement_id: Option<String>,
 }
 
+/// How the client distributes and produces the probes messages
+#[derive(Debug, Clone, Copy)]
+pub struct ProduceOptions {
+    pub distribution: Distribution,
+    // Maximum number of messages awaiting delivery
+    pub max_in_flight: usize,
+    // Maximum number of messages produced per second
+    pub produce_rate: Option<f64>,
+    // Retries of a message on transient delivery errors
+    pub retries: u32,
+    // Probes are targets, expanded by the agents into one probe per TTL of the range
+    pub expand_ttl: Option<TtlRange>,
+    // Source ports selected per flow by the agents
+    pub src_ports: Option<PortPolicy>,
+    // Priority of the probes on the agents, sent before those of a lower priority
+    pub priority: Option<u32>,
+    // Weight of the measurement in the fair scheduling of the agents
+    pub weight: Option<u32>,
+    // Build the messages without producing them
+    pub dry_run: bool,
+}
+
+/// Probes which could not be delivered to Kafka
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedProbes {
+    pub agents: Vec<String>,
+    // Indices of the probes in the set sent to these agents
+    // (the order of the input probes with `Distribution::Replicate`)
+    pub probes: Range<usize>,
+}
+
+const INITIAL_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(10);
+
+impl Default for ProduceOptions {
+    fn default() -> Self {
+        ProduceOptions {
+            distribution: Distribution::default(),
+            max_in_flight: 1,
+            produce_rate: None,
+            retries: 3,
+            expand_ttl: None,
+            src_ports: None,
+            priority: None,
+            weight: None,
+            dry_run: false,
+        }
+    }
+}
+
+/// Whether a delivery error is worth retrying (broker rebalancing, timeouts, ...)
+pub fn is_transient_error(error: &KafkaError) -> bool {
+    matches!(
+        error.rdkafka_error_code(),
+        Some(
+            RDKafkaErrorCode::MessageTimedOut
+                | RDKafkaErrorCode::QueueFull
+                | RDKafkaErrorCode::RequestTimedOut
+                | RDKafkaErrorCode::NotLeaderForPartition
+                | RDKafkaErrorCode::LeaderNotAvailable
+                | RDKafkaErrorCode::BrokerNotAvailable
+                | RDKafkaErrorCode::NetworkException
+                | RDKafkaErrorCode::NotEnoughReplicas
+                | RDKafkaErrorCode::NotEnoughReplicasAfterAppend
+                | RDKafkaErrorCode::AllBrokersDown
+                | RDKafkaErrorCode::BrokerTransportFailure
+        )
+    )
+}
+
+/// Delay before the `attempt`-th retry (starting at 1), doubling at each attempt.
+pub fn retry_backoff(attempt: u32) -> Duration {
+    INITIAL_RETRY_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_RETRY_BACKOFF)
+}
+
+/// A probe, with its own source address if given.
+pub type ProbeWithSource = (Probe, Option<IpAddr>);
+
+/// A probe to submit, optionally with its own source address.
+pub trait SourcedProbe {
+    fn probe(&self) -> &Probe;
+    fn src_addr(&self) -> Option<IpAddr>;
+}
+
+impl SourcedProbe for Probe {
+    fn probe(&self) -> &Probe {
+        self
+    }
+
+    fn src_addr(&self) -> Option<IpAddr> {
+        None
+    }
+}
+
+impl SourcedProbe for ProbeWithSource {
+    fn probe(&self) -> &Probe {
+        &self.0
+    }
+
+    fn src_addr(&self) -> Option<IpAddr> {
+        self.1
+    }
+}
+
+/// Place untagged probes into Kafka messages of at most `message_max_bytes`.
 pub fn create_messages(probes: Vec<Probe>, message_max_bytes: usize) -> Vec<Vec<u8>> {
+    create_tagged_messages(&probes, &ProbeTags::default(), message_max_bytes)
+        .into_iter()
+        .map(|(_, message)| message)
+        .collect()
+}
+
+/// Place probes into Kafka messages, tagging each of them with `tags`, along with the number
+/// of probes in each message.
+pub fn create_tagged_messages(
+    probes: &[Probe],
+    tags: &ProbeTags,
+    message_max_bytes: usize,
+) -> Vec<(usize, Vec<u8>)> {
+    create_indexed_messages(probes, tags, message_max_bytes)
+        .into_iter()
+        .map(|(range, message)| (range.len(), message))
+        .collect()
+}
+
+/// Place probes into Kafka messages, along with the indices of the probes in each message.
+pub fn create_indexed_messages<P: SourcedProbe>(
+    probes: &[P],
+    tags: &ProbeTags,
+    message_max_bytes: usize,
+) -> Vec<(Range<usize>, Vec<u8>)> {
     let mut messages = Vec::new();
     let mut current_message = Vec::new();
-    for probe in probes {
+    let mut current_start = 0;
+    for (i, probe) in probes.iter().enumerate() {
         // Serialize the probe
-        let message_bin = serialize_probe(&probe);
+        let message_bin = match probe.src_addr() {
+            Some(src_addr) => serialize_tagged_probe(
+                probe.probe(),
+                &ProbeTags {
+                    src_addr: Some(src_addr),
+                    ..tags.clone()
+                },
+            ),
+            None => serialize_tagged_probe(probe.probe(), tags),
+        };
 
-        // Max message size is 1048576 bytes (including headers)
-        if current_message.len() + message_bin.len() > message_max_bytes {
-            messages.push(current_message);
+        // `message_max_bytes` is the room left for the probes (see `message_budget`)
+        if !current_message.is_empty()
+            && current_message.len() + message_bin.len() > message_max_bytes
+        {
+            messages.push((current_start..i, current_message));
             current_message = Vec::new();
+            current_start = i;
         }
 
         current_message.extend_from_slice(&message_bin);
     }
     if !current_message.is_empty() {
-        messages.push(current_message);
+        messages.push((current_start..probes.len(), current_message));
     }
 
     messages
 }
 
-pub async fn produce(
-    config: &AppConfig,
-    auth: KafkaAuth,
-    agents: Vec<MeasurementInfo>,
-    probes: Vec<Probe>,
-) {
-    let producer: &FutureProducer = match auth {
-        KafkaAuth::PlainText => &ClientConfig::new()
-            .set("bootstrap.servers", config.kafka.brokers.clone())
-            .set("message.timeout.ms", "5000")
-            .create()
-            .expect("Producer creation error"),
-        KafkaAuth::SasalPlainText(scram_auth) => &ClientConfig::new()
-            .set("bootstrap.servers", config.kafka.brokers.clone())
-            .set("message.timeout.ms", "5000")
-            .set("sasl.username", scram_auth.username)
-            .set("sasl.password", scram_auth.password)
-            .set("sasl.mechanisms", scram_auth.mechanism)
-            .set("security.protocol", "SASL_PLAINTEXT")
-            .create()
-            .expect("Producer creation error"),
+/// Record batch and record framing around the key, headers and payload of a message
+/// (lengths, offsets, timestamps, CRC, ...), rounded up.
+const RECORD_OVERHEAD: usize = 128;
+/// Varint lengths of the key and value of a header, at most.
+const HEADER_OVERHEAD: usize = 10;
+
+/// Length of headers in a record.
+pub fn headers_len<'a>(headers: impl IntoIterator<Item = (&'a str, Option<&'a [u8]>)>) -> usize {
+    headers
+        .into_iter()
+        .map(|(key, value)| HEADER_OVERHEAD + key.len() + value.map_or(0, |value| value.len()))
+        .sum()
+}
+
+/// Bytes of probes fitting in a message of at most `message_max_bytes`, once the record
+/// overhead, the key and headers (`headers_len`), and the schema framing (`framing`) are
+/// accounted for. The brokers check the size of the compressed batches, so room is also
+/// left for the worst-case expansion of `compression` on incompressible probes.
+pub fn message_budget(
+    message_max_bytes: usize,
+    headers_len: usize,
+    framing: usize,
+    compression: Option<&str>,
+) -> usize {
+    let available = message_max_bytes.saturating_sub(RECORD_OVERHEAD + headers_len + framing);
+    // Bound of the compressed size of n bytes: overhead + n * numerator / denominator
+    let (overhead, numerator, denominator) = match compression {
+        // Stored deflate blocks of 16383 bytes with a 5 bytes header, gzip header and trailer
+        Some("gzip") => (23, 16388, 16383),
+        // Literal runs, and the preamble
+        Some("snappy") => (32, 7, 6),
+        // LZ4_compressBound, and the frame header and trailer
+        Some("lz4") => (32, 256, 255),
+        // ZSTD_compressBound, and the frame header and block headers
+        Some("zstd") => (128, 257, 256),
+        _ => (0, 1, 1),
     };
+    available.saturating_sub(overhead) * denominator / numerator
+}
 
-    let topic = config.kafka.in_topics.split(',').collect::<Vec<&str>>()[0];
+/// Split probes between `agents` agents. With `Distribution::Replicate`, all the probes are
+/// returned in a single set meant for every agent.
+pub fn distribute_probes<P: SourcedProbe>(
+    probes: Vec<P>,
+    agents: usize,
+    distribution: Distribution,
+) -> Vec<Vec<P>> {
+    if distribution == Distribution::Replicate || agents <= 1 {
+        return vec![probes];
+    }
+
+    let mut shards: Vec<Vec<P>> = (0..agents).map(|_| Vec::new()).collect();
+    for (i, probe) in probes.into_iter().enumerate() {
+        let shard = match distribution {
+            Distribution::RoundRobin => i % agents,
+            _ => {
+                // Stable across releases, so that a destination stays with the same agent
+                let mut hash = Fnv1a::default();
+                hash.write_addr(probe.probe().dst_addr);
+                (hash.finish() % agents as u64) as usize
+            }
+        };
+        shards[shard].push(probe);
+    }
+    shards
+}
 
-    // Construct headers
+/// Headers of the probes messages sent to `agents`, as read by the agents. The
+/// `end_of_measurement` header is added to each message by `end_of_measurement_headers`.
+pub fn probes_headers(agents: &[MeasurementInfo], options: &ProduceOptions) -> OwnedHeaders {
     let mut headers = OwnedHeaders::new();
 
     // Add agent-specific headers
-    for agent in &agents {
+    for agent in agents {
         // Serialize all agent info into a single header value
-        let agent_info_json = serde_json::json!({
+        let mut agent_info_json = serde_json::json!({
             "src_ip": agent.src_ip,
         });
+        if let Some(instance) = &agent.instance {
+            agent_info_json["instance"] = serde_json::json!(instance);
+        }
         let agent_info_str = agent_info_json.to_string();
 
         headers = headers.insert(Header {
@@ -87,15 +297,399 @@ pub async fn produce(
     if let Some(first_agent) = agents.first() {
         if let Some(ref measurement_id) = first_agent.measurement_id {
             headers = headers.insert(Header {
-                key: "measurement_id",
+                key: MEASUREMENT_ID_HEADER,
                 value: Some(measurement_id),
             });
         }
     }
 
-    // Place probes into Kafka messages
+    if let Some(range) = options.expand_ttl {
+        headers = headers.insert(Header {
+            key: EXPAND_TTL_HEADER,
+            value: Some(&range.to_string()),
+        });
+    }
+    if let Some(policy) = options.src_ports {
+        headers = headers.insert(Header {
+            key: SRC_PORTS_HEADER,
+            value: Some(&policy.to_string()),
+        });
+    }
+    if let Some(priority) = options.priority {
+        headers = headers.insert(Header {
+            key: PRIORITY_HEADER,
+            value: Some(&priority.to_string()),
+        });
+    }
+    if let Some(weight) = options.weight {
+        headers = headers.insert(Header {
+            key: WEIGHT_HEADER,
+            value: Some(&weight.to_string()),
+        });
+    }
+
+    headers
+}
+
+/// Headers of a message, marking the last one of the measurement.
+pub fn end_of_measurement_headers(headers: &OwnedHeaders, is_last_message: bool) -> OwnedHeaders {
+    headers.clone().insert(Header {
+        key: END_OF_MEASUREMENT_HEADER,
+        value: Some(&is_last_message.to_string()),
+    })
+}
+
+/// Sign a message with the client key (`kafka.signing_key`), if any.
+pub fn sign_message(config: &AppConfig, headers: OwnedHeaders, payload: &[u8]) -> OwnedHeaders {
+    let (Some(key_id), Some(key)) = (&config.kafka.signing_key_id, &config.kafka.signing_key)
+    else {
+        return headers;
+    };
+    let signature = sign(
+        key,
+        payload,
+        headers.iter().map(|header| (header.key, header.value)),
+    );
+    headers
+        .insert(Header {
+            key: SIGNATURE_KEY_ID_HEADER,
+            value: Some(key_id),
+        })
+        .insert(Header {
+            key: SIGNATURE_HEADER,
+            value: Some(&signature),
+        })
+}
+
+fn create_producer(config: &AppConfig, auth: KafkaAuth) -> FutureProducer {
+    let mut client_config = ClientConfig::new();
+    client_config
+        .set("bootstrap.servers", config.kafka.brokers.clone())
+        .set("message.timeout.ms", "5000");
+    if let Some(compression) = &config.kafka.compression {
+        client_config.set("compression.type", compression);
+    }
+    if let KafkaAuth::SasalPlainText(scram_auth) = auth {
+        client_config
+            .set("sasl.username", scram_auth.username)
+            .set("sasl.password", scram_auth.password)
+            .set("sasl.mechanisms", scram_auth.mechanism)
+            .set("security.protocol", "SASL_PLAINTEXT");
+    }
+    config.kafka.apply_properties(&mut client_config);
+    client_config.create().expect("Producer creation error")
+}
+
+/// Headers of a message asking `agents` to cancel a measurement.
+pub fn cancellation_headers(agents: &[String], measurement_id: &str) -> OwnedHeaders {
+    let mut headers = OwnedHeaders::new();
+    for agent in agents {
+        headers = headers.insert(Header {
+            key: agent,
+            value: Some("{}"),
+        });
+    }
+    headers.insert(Header {
+        key: CANCEL_MEASUREMENT_HEADER,
+        value: Some(measurement_id),
+    })
+}
+
+/// Ask `agents` to cancel a measurement, dropping its probes not sent yet.
+pub async fn cancel_measurement(
+    config: &AppConfig,
+    auth: KafkaAuth,
+    agents: &[String],
+    measurement_id: &str,
+) -> Result<(), KafkaError> {
+    let producer = create_producer(config, auth);
+
+    // Agents grouped by the topic they consume probes from
+    let mut topics: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for agent in agents {
+        topics
+            .entry(config.kafka.client_in_topic(agent))
+            .or_default()
+            .push(agent.clone());
+    }
+
+    for (topic, agents) in topics {
+        info!(
+            "topic={},measurement_id={},agents={}",
+            topic,
+            measurement_id,
+            agents.join(",")
+        );
+
+        let (partition, offset) = producer
+            .send(
+                FutureRecord::to(&topic)
+                    .payload("")
+                    .key("")
+                    .headers(sign_message(
+                        config,
+                        cancellation_headers(&agents, measurement_id),
+                        b"",
+                    )),
+                Duration::from_secs(0),
+            )
+            .await
+            .map(|delivery| (delivery.partition, delivery.offset))
+            .map_err(|(error, _)| error)?;
+        info!(
+            "successfully sent cancellation to partition {} at offset {}",
+            partition, offset
+        );
+    }
+    Ok(())
+}
+
+/// Produce the probes messages, recording them in `summary`. Returns the agents which were
+/// sent probes, and the probes which could not be delivered.
+#[allow(clippy::too_many_arguments)]
+pub async fn produce(
+    config: &AppConfig,
+    auth: KafkaAuth,
+    agents: Vec<MeasurementInfo>,
+    probes: Vec<ProbeWithSource>,
+    tags: ProbeTags,
+    options: ProduceOptions,
+    retry: Option<&[FailedProbes]>,
+    summary: &mut SubmissionSummary,
+) -> (Vec<String>, Vec<FailedProbes>) {
+    // Kafka is not contacted in a dry run
+    let producer = (!options.dry_run).then(|| create_producer(config, auth));
+    let schema_registry = config
+        .kafka
+        .schema_registry
+        .clone()
+        .map(SchemaRegistry::new);
+
+    // Each set of probes is sent to its agents, with its own headers
+    let probes_sets = distribute_probes(probes, agents.len(), options.distribution);
+    let jobs: Vec<(&[MeasurementInfo], Vec<ProbeWithSource>)> = if probes_sets.len() == 1 {
+        let probes = probes_sets.into_iter().next().unwrap();
+        if config.kafka.in_topic_template.is_some() {
+            // With a topic per agent, each agent is sent its own copy of the probes
+            agents
+                .iter()
+                .map(|agent| (std::slice::from_ref(agent), probes.clone()))
+                .collect()
+        } else {
+            vec![(&agents[..], probes)]
+        }
+    } else {
+        agents
+            .iter()
+            .zip(probes_sets)
+            .filter(|(_, probes)| !probes.is_empty())
+            .map(|(agent, probes)| (std::slice::from_ref(agent), probes))
+            .collect()
+    };
+
+    // Agents which were sent probes, and probes which could not be delivered
+    let mut targeted_agents = Vec::new();
+    let mut failed_probes = Vec::new();
+    for (job_agents, probes) in jobs {
+        let topic = &config.kafka.client_in_topic(&job_agents[0].name);
+        let headers = probes_headers(job_agents, &options);
+        let key_agents = job_agents
+            .iter()
+            .map(|agent| agent.name.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
+        let measurement_id = job_agents[0].measurement_id.clone();
+        let job_agents: Vec<String> = job_agents.iter().map(|agent| agent.name.clone()).collect();
+
+        // When retrying, only resubmit the probes which previously failed for these agents
+        let (indices, probes) = match retry {
+            Some(failures) => {
+                let ranges: Vec<Range<usize>> = failures
+                    .iter()
+                    .filter(|failed| failed.agents == job_agents)
+                    .map(|failed| failed.probes.clone())
+                    .collect();
+                let (indices, probes) = select_probes(probes, &ranges);
+                (Some(indices), probes)
+            }
+            None => (None, probes),
+        };
+        if probes.is_empty() {
+            continue;
+        }
+
+        // Messages are framed with the ID of the probe schema, registered for the topic
+        let schema_id = match &schema_registry {
+            // The messages of a dry run are framed with a placeholder schema ID
+            Some(_) if options.dry_run => Ok(Some(0)),
+            Some(registry) => registry
+                .register(&subject(topic), PROBE_SCHEMA)
+                .await
+                .map(Some),
+            None => Ok(None),
+        };
+        let (failed_ranges, stats) = match schema_id {
+            Ok(schema_id) => {
+                send_probes(
+                    config,
+                    producer.as_ref(),
+                    topic,
+                    headers,
+                    &key_agents,
+                    measurement_id.as_deref(),
+                    &probes,
+                    &tags,
+                    &options,
+                    schema_id,
+                )
+                .await
+            }
+            Err(e) => {
+                error!("{:#}", e);
+                let stats = ProduceStats {
+                    failures: 1,
+                    ..Default::default()
+                };
+                (vec![0..probes.len()], stats)
+            }
+        };
+        summary.record(
+            &job_agents,
+            probes.len(),
+            failed_ranges.iter().map(|range| range.len()).sum(),
+            stats,
+        );
+        info!(
+            "agents={},messages_produced={},bytes_sent={},produce_failures={}",
+            key_agents, stats.messages, stats.bytes, stats.failures
+        );
+        for failed_range in failed_ranges {
+            // Failures are reported against the indices of the probes before selection
+            let ranges = match &indices {
+                Some(indices) => original_ranges(failed_range, indices),
+                None => vec![failed_range],
+            };
+            failed_probes.extend(ranges.into_iter().map(|probes| FailedProbes {
+                agents: job_agents.clone(),
+                probes,
+            }));
+        }
+        targeted_agents.extend(job_agents);
+    }
+    (targeted_agents, failed_probes)
+}
+
+/// Keep the probes whose index is within `ranges`, along with their indices.
+pub fn select_probes<P>(probes: Vec<P>, ranges: &[Range<usize>]) -> (Vec<usize>, Vec<P>) {
+    probes
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| ranges.iter().any(|range| range.contains(i)))
+        .unzip()
+}
+
+/// Map a range of selected probes back to contiguous ranges of their original indices.
+pub fn original_ranges(selected: Range<usize>, indices: &[usize]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for &index in indices.get(selected).unwrap_or_default() {
+        match ranges.last_mut() {
+            Some(range) if range.end == index => range.end += 1,
+            _ => ranges.push(index..index + 1),
+        }
+    }
+    ranges
+}
+
+type Delivery = (usize, Result<(i32, i64), KafkaError>);
+
+/// Log the delivery of a message and count it in `stats`, returning the probes of the
+/// message if it failed. The probes of each message in flight are kept by task in
+/// `in_flight_probes`, so that they are known even if the task did not complete.
+fn log_delivery(
+    result: Result<(Id, Delivery), JoinError>,
+    in_flight_probes: &mut HashMap<Id, Range<usize>>,
+    stats: &mut ProduceStats,
+) -> Option<Range<usize>> {
+    let id = match &result {
+        Ok((id, _)) => *id,
+        Err(error) => error.id(),
+    };
+    let probes = in_flight_probes.remove(&id).unwrap_or_default();
+    match result {
+        Ok((_, (bytes, Ok((partition, offset))))) => {
+            info!(
+                "successfully sent message to partition {} at offset {}",
+                partition, offset
+            );
+            stats.messages += 1;
+            stats.bytes += bytes as u64;
+            None
+        }
+        Ok((_, (_, Err(error)))) => {
+            error!(
+                "failed to send probes {}..{}: {}",
+                probes.start, probes.end, error
+            );
+            stats.failures += 1;
+            Some(probes)
+        }
+        Err(error) => {
+            error!(
+                "failed to send probes {}..{}: {}",
+                probes.start, probes.end, error
+            );
+            stats.failures += 1;
+            Some(probes)
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn send_probes(
+    config: &AppConfig,
+    // None in a dry run, the messages are only built and counted
+    producer: Option<&FutureProducer>,
+    topic: &str,
+    headers: OwnedHeaders,
+    // Agents and measurement of the probes, keying the messages
+    agents: &str,
+    measurement_id: Option<&str>,
+    probes: &[ProbeWithSource],
+    tags: &ProbeTags,
+    options: &ProduceOptions,
+    schema_id: Option<u32>,
+) -> (Vec<Range<usize>>, ProduceStats) {
+    // Validated with the configuration
+    let key_strategy = KeyStrategy::parse(&config.kafka.key_strategy).unwrap_or_default();
+    // Place probes into Kafka messages, leaving room for the key, the headers (including the
+    // end_of_measurement and signature headers added below), the schema framing, and the
+    // expansion of the compression
     let probes_len = probes.len();
-    let messages = create_messages(probes, config.kafka.message_max_bytes);
+    // Longest key: the /48 prefix with the most digits for `probe_dst_prefix`
+    let key_len = key_strategy
+        .key(
+            agents,
+            measurement_id,
+            Some(IpAddr::from(Ipv6Addr::from(u128::MAX))),
+        )
+        .map_or(0, |key| key.len());
+    let signing_len = match (&config.kafka.signing_key_id, &config.kafka.signing_key) {
+        (Some(key_id), Some(_)) => headers_len([
+            (SIGNATURE_KEY_ID_HEADER, Some(key_id.as_bytes())),
+            (SIGNATURE_HEADER, Some([0u8; SIGNATURE_LEN].as_slice())),
+        ]),
+        _ => 0,
+    };
+    let message_headers_len = headers_len(headers.iter().map(|header| (header.key, header.value)))
+        + headers_len([(END_OF_MEASUREMENT_HEADER, Some("false".as_bytes()))])
+        + signing_len;
+    let message_max_bytes = message_budget(
+        config.kafka.message_max_bytes,
+        key_len + message_headers_len,
+        schema_id.map_or(0, |_| FRAME_LEN),
+        config.kafka.compression.as_deref(),
+    );
+    let messages = create_indexed_messages(probes, tags, message_max_bytes);
 
     info!(
         "topic={},messages={},probes={}",
@@ -104,37 +698,114 @@ pub async fn produce(
         probes_len,
     );
 
-    // Send to Kafka
-    for (message_index, message) in messages.iter().enumerate() {
-        let is_last_message = message_index == messages.len() - 1;
-
-        // Clone headers and add end_of_measurement for this specific message
-        let mut message_headers = headers.clone();
-        message_headers = message_headers.insert(Header {
-            key: "end_of_measurement",
-            value: Some(&is_last_message.to_string()),
+    // Optionally pace the messages
+    let mut pacing = options
+        .produce_rate
+        .filter(|rate| *rate > 0.0 && producer.is_some())
+        .map(|rate| {
+            let mut pacing = interval(Duration::from_secs_f64(1.0 / rate));
+            pacing.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            pacing
         });
 
-        let delivery_status = producer
-            .send(
-                FutureRecord::to(topic)
-                    .payload(message)
-                    .key(&format!(""))
-                    .headers(message_headers),
-                Duration::from_secs(0),
-            )
-            .await;
-
-        match delivery_status {
-            Ok(delivery) => {
-                info!(
-                    "successfully sent message to partition {} at offset {}",
-                    delivery.partition, delivery.offset
-                );
-            }
-            Err((error, _)) => {
-                error!("failed to send message: {}", error);
+    // Send to Kafka, with up to `max_in_flight` messages awaiting delivery
+    let messages_len = messages.len();
+    let mut in_flight = JoinSet::new();
+    let mut in_flight_probes = HashMap::new();
+    let mut failed = Vec::new();
+    let mut stats = ProduceStats::default();
+    for (message_index, (message_probes, message)) in messages.into_iter().enumerate() {
+        let is_last_message = message_index == messages_len - 1;
+
+        if let Some(ref mut pacing) = pacing {
+            pacing.tick().await;
+        }
+
+        // The last message marks the end of the measurement, so it is only sent
+        // once all the previous ones are delivered
+        while in_flight.len() >= options.max_in_flight.max(1)
+            || (is_last_message && !in_flight.is_empty())
+        {
+            if let Some(result) = in_flight.join_next_with_id().await {
+                failed.extend(log_delivery(result, &mut in_flight_probes, &mut stats));
             }
         }
+
+        let message = match schema_id {
+            Some(schema_id) => frame(schema_id, &message),
+            None => message,
+        };
+
+        // Add end_of_measurement for this specific message, signed along with the payload.
+        // It is held back when previous messages failed, as the measurement is incomplete
+        // until their probes are resubmitted
+        if is_last_message && !failed.is_empty() {
+            warn!(
+                "{} messages failed, not marking the end of the measurement",
+                failed.len()
+            );
+        }
+        let message_headers =
+            end_of_measurement_headers(&headers, is_last_message && failed.is_empty());
+        let message_headers = sign_message(config, message_headers, &message);
+        let key = key_strategy.key(
+            agents,
+            measurement_id,
+            probes
+                .get(message_probes.start)
+                .map(|(probe, _)| probe.dst_addr),
+        );
+
+        let Some(producer) = producer.cloned() else {
+            info!(
+                "dry run: topic={},probes={}..{},bytes={},headers={}",
+                topic,
+                message_probes.start,
+                message_probes.end,
+                message.len(),
+                message_headers.count()
+            );
+            stats.messages += 1;
+            stats.bytes += message.len() as u64;
+            continue;
+        };
+        let topic = topic.to_string();
+        let retries = options.retries;
+        let task_probes = message_probes.clone();
+        let task = in_flight.spawn(async move {
+            let mut attempt = 0;
+            loop {
+                let mut record = FutureRecord::to(&topic)
+                    .payload(&message)
+                    .headers(message_headers.clone());
+                if let Some(key) = &key {
+                    record = record.key(key.as_str());
+                }
+                let result = producer
+                    .send(record, Duration::from_secs(0))
+                    .await
+                    .map(|delivery| (delivery.partition, delivery.offset))
+                    .map_err(|(error, _)| error);
+
+                match result {
+                    Err(error) if attempt < retries && is_transient_error(&error) => {
+                        attempt += 1;
+                        let backoff = retry_backoff(attempt);
+                        warn!(
+                            "transient error sending probes {}..{}: {}. Retrying in {:?} ({}/{})",
+                            task_probes.start, task_probes.end, error, backoff, attempt, retries
+                        );
+                        sleep(backoff).await;
+                    }
+                    result => return (message.len(), result),
+                }
+            }
+        });
+        in_flight_probes.insert(task.id(), message_probes);
+    }
+
+    while let Some(result) = in_flight.join_next_with_id().await {
+        failed.extend(log_delivery(result, &mut in_flight_probes, &mut stats));
     }
+    (failed, stats)
 }
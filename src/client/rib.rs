@@ -0,0 +1,332 @@
+use anyhow::{Context, Result};
+use flate2::read::MultiGzDecoder;
+use ipnet::IpNet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use tracing::{info, trace, warn};
+
+use crate::agent::expand::TtlRange;
+use crate::client::generate::FlowMapper;
+use crate::client::handler::submit;
+use crate::client::traceroute::traceroute_probes;
+use crate::config::{AppConfig, ClientConfig};
+
+pub const DEFAULT_TARGETS_PER_PREFIX: u16 = 1;
+
+// MRT record types and subtypes (RFC 6396)
+const MRT_TABLE_DUMP_V2: u16 = 13;
+const MRT_RIB_IPV4_UNICAST: u16 = 2;
+const MRT_RIB_IPV6_UNICAST: u16 = 4;
+const MRT_HEADER_LEN: usize = 12;
+// BGP AS_PATH attribute, and its segment types (RFC 4271)
+const BGP_ATTR_AS_PATH: u8 = 2;
+const BGP_ATTR_EXTENDED_LENGTH: u8 = 0x10;
+const AS_SEQUENCE: u8 = 2;
+
+/// Format of the routing table input.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RibFormat {
+    /// MRT TABLE_DUMP_V2 RIB dump, optionally gzipped (e.g. from RouteViews or RIPE RIS)
+    #[default]
+    Mrt,
+    /// One prefix and its origin ASN per line, e.g. '192.0.2.0/24 64500'
+    Prefixes,
+}
+
+/// A prefix announced in the routing table, with the ASN originating it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutedPrefix {
+    pub prefix: IpNet,
+    pub origin_asn: u32,
+}
+
+/// Read a prefix list, one prefix and its origin ASN per line, separated by spaces, a comma
+/// or a `|`. The ASN may be prefixed with `AS`. Empty lines and `#` comments are ignored.
+pub fn read_prefix_list<R: BufRead>(buf_reader: R) -> Result<Vec<RoutedPrefix>> {
+    let mut prefixes = Vec::new();
+    for (i, line) in buf_reader.lines().enumerate() {
+        let line = line?;
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line
+            .split(|c: char| c.is_whitespace() || c == ',' || c == '|')
+            .filter(|field| !field.is_empty())
+            .collect();
+        let [prefix, origin_asn] = fields[..] else {
+            anyhow::bail!(
+                "Invalid prefix list entry '{}' at line {}. Expected format: 'PREFIX ASN'",
+                line,
+                i + 1
+            );
+        };
+        let prefix: IpNet = prefix
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid prefix '{}' at line {}: {}", prefix, i + 1, e))?;
+        let origin_asn = origin_asn
+            .trim_start_matches("AS")
+            .trim_start_matches("as")
+            .parse()
+            .map_err(|e| {
+                anyhow::anyhow!("Invalid ASN '{}' at line {}: {}", origin_asn, i + 1, e)
+            })?;
+        prefixes.push(RoutedPrefix {
+            prefix: prefix.trunc(),
+            origin_asn,
+        });
+    }
+    Ok(prefixes)
+}
+
+/// Origin ASN of a TABLE_DUMP_V2 path attributes blob: the last ASN of the AS_PATH, if it
+/// ends with a sequence (the origin of a path ending with an AS_SET is ambiguous).
+fn origin_asn(mut attributes: &[u8]) -> Option<u32> {
+    while attributes.len() >= 3 {
+        let (flags, kind) = (attributes[0], attributes[1]);
+        let (length, header) = if flags & BGP_ATTR_EXTENDED_LENGTH != 0 {
+            (
+                u16::from_be_bytes(attributes.get(2..4)?.try_into().ok()?) as usize,
+                4,
+            )
+        } else {
+            (attributes[2] as usize, 3)
+        };
+        let value = attributes.get(header..header + length)?;
+        attributes = &attributes[header + length..];
+        if kind != BGP_ATTR_AS_PATH {
+            continue;
+        }
+
+        // TABLE_DUMP_V2 paths are encoded with 4-byte ASNs
+        let mut origin = None;
+        let mut segments = value;
+        while segments.len() >= 2 {
+            let (segment_type, count) = (segments[0], segments[1] as usize);
+            let asns = segments.get(2..2 + 4 * count)?;
+            segments = &segments[2 + 4 * count..];
+            origin = match asns.chunks_exact(4).last() {
+                Some(asn) if segment_type == AS_SEQUENCE => {
+                    Some(u32::from_be_bytes(asn.try_into().ok()?))
+                }
+                Some(_) => None,
+                None => origin,
+            };
+        }
+        return origin;
+    }
+    None
+}
+
+/// Prefix of a TABLE_DUMP_V2 RIB entry, and the remainder of the entry.
+fn rib_prefix(body: &[u8], ipv6: bool) -> Option<(IpNet, &[u8])> {
+    let length = *body.get(4)?;
+    let bytes = (length as usize).div_ceil(8);
+    let octets = body.get(5..5 + bytes)?;
+    let prefix = if ipv6 {
+        let mut address = [0u8; 16];
+        address.get_mut(..bytes)?.copy_from_slice(octets);
+        IpNet::new(IpAddr::V6(Ipv6Addr::from(address)), length).ok()?
+    } else {
+        let mut address = [0u8; 4];
+        address.get_mut(..bytes)?.copy_from_slice(octets);
+        IpNet::new(IpAddr::V4(Ipv4Addr::from(address)), length).ok()?
+    };
+    Some((prefix.trunc(), &body[5 + bytes..]))
+}
+
+/// Prefix and origin ASN of a TABLE_DUMP_V2 RIB entry, from the first of its routes with
+/// an origin.
+fn rib_entry(body: &[u8], ipv6: bool) -> Option<RoutedPrefix> {
+    let (prefix, rest) = rib_prefix(body, ipv6)?;
+    let count = u16::from_be_bytes(rest.get(..2)?.try_into().ok()?);
+    let mut routes = &rest[2..];
+    for _ in 0..count {
+        // Peer index (2 bytes) and originated time (4 bytes), then the attributes
+        let length = u16::from_be_bytes(routes.get(6..8)?.try_into().ok()?) as usize;
+        let attributes = routes.get(8..8 + length)?;
+        routes = &routes[8 + length..];
+        if let Some(origin_asn) = origin_asn(attributes) {
+            return Some(RoutedPrefix { prefix, origin_asn });
+        }
+    }
+    None
+}
+
+/// Read the IPv4 and IPv6 unicast prefixes of an MRT TABLE_DUMP_V2 RIB dump, with their
+/// origin ASN. Records of other types, and prefixes without an origin, are skipped.
+pub fn read_mrt<R: Read>(mut reader: R) -> Result<Vec<RoutedPrefix>> {
+    let mut prefixes = Vec::new();
+    let mut skipped = 0;
+    let mut header = [0u8; MRT_HEADER_LEN];
+    loop {
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let kind = u16::from_be_bytes([header[4], header[5]]);
+        let subtype = u16::from_be_bytes([header[6], header[7]]);
+        let length = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+        let mut body = vec![0u8; length];
+        reader
+            .read_exact(&mut body)
+            .context("Truncated MRT record")?;
+
+        let ipv6 = match (kind, subtype) {
+            (MRT_TABLE_DUMP_V2, MRT_RIB_IPV4_UNICAST) => false,
+            (MRT_TABLE_DUMP_V2, MRT_RIB_IPV6_UNICAST) => true,
+            _ => continue,
+        };
+        match rib_entry(&body, ipv6) {
+            Some(prefix) => prefixes.push(prefix),
+            None => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        trace!("Skipped {} RIB entries without an origin ASN", skipped);
+    }
+    Ok(prefixes)
+}
+
+/// Read the routing table of `path` in `format`. Gzipped files are decompressed.
+pub fn read_rib(path: &Path, format: RibFormat) -> Result<Vec<RoutedPrefix>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open RIB file {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let reader: Box<dyn BufRead> = if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    };
+    match format {
+        RibFormat::Mrt => read_mrt(reader),
+        RibFormat::Prefixes => read_prefix_list(reader),
+    }
+}
+
+// SplitMix64, so that the targets of a prefix are spread but reproducible
+pub(crate) fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Sample up to `count` addresses of `prefix`, one in each of `count` equal slices of the
+/// prefix. The address within a slice is derived from `seed`, and avoids the first and last
+/// addresses of the slice when it is large enough (network and broadcast addresses).
+pub fn sample_targets(prefix: &IpNet, count: u16, seed: u64) -> Vec<IpAddr> {
+    let (network, host_bits) = match prefix.network() {
+        IpAddr::V4(address) => (u32::from(address) as u128, 32 - prefix.prefix_len() as u32),
+        IpAddr::V6(address) => (u128::from(address), 128 - prefix.prefix_len() as u32),
+    };
+    // Number of addresses of the prefix, minus one so that a /0 does not overflow
+    let last = if host_bits == 128 {
+        u128::MAX
+    } else {
+        (1u128 << host_bits) - 1
+    };
+    let count = (count as u128).min(last.saturating_add(1)).max(1);
+    let slice = last / count + if last % count == count - 1 { 1 } else { 0 };
+
+    (0..count)
+        .map(|i| {
+            let start = i * slice;
+            let key = seed ^ mix(network as u64 ^ (network >> 64) as u64) ^ mix(i as u64);
+            let hash = ((mix(key) as u128) << 64) | mix(!key) as u128;
+            let offset = if slice >= 4 {
+                1 + hash % (slice - 2)
+            } else {
+                hash % slice
+            };
+            let address = network + start + offset;
+            match prefix {
+                IpNet::V4(_) => IpAddr::V4(Ipv4Addr::from(address as u32)),
+                IpNet::V6(_) => IpAddr::V6(Ipv6Addr::from(address)),
+            }
+        })
+        .collect()
+}
+
+/// Targets sampled in each announced prefix, with the prefix they were sampled from.
+/// Default routes are skipped, and duplicate prefixes keep their first origin.
+pub fn rib_targets(
+    prefixes: &[RoutedPrefix],
+    targets_per_prefix: u16,
+    seed: u64,
+) -> Vec<(IpAddr, &RoutedPrefix)> {
+    let mut seen = HashSet::new();
+    prefixes
+        .iter()
+        .filter(|routed| routed.prefix.prefix_len() > 0 && seen.insert(routed.prefix))
+        .flat_map(|routed| {
+            sample_targets(&routed.prefix, targets_per_prefix, seed)
+                .into_iter()
+                .map(move |target| (target, routed))
+        })
+        .collect()
+}
+
+/// Index tags of the targets: the prefix they were sampled from, and its origin ASN.
+pub fn target_tags(
+    targets: &[(IpAddr, &RoutedPrefix)],
+) -> HashMap<IpAddr, BTreeMap<String, String>> {
+    targets
+        .iter()
+        .map(|(target, routed)| {
+            (
+                *target,
+                BTreeMap::from([
+                    ("origin_asn".to_string(), routed.origin_asn.to_string()),
+                    ("prefix".to_string(), routed.prefix.to_string()),
+                ]),
+            )
+        })
+        .collect()
+}
+
+/// Traceroute targets sampled in the prefixes of a routing table, from the agents of
+/// `client_config`.
+#[allow(clippy::too_many_arguments)]
+pub async fn handle(
+    config: &AppConfig,
+    client_config: ClientConfig,
+    rib_file: &Path,
+    format: RibFormat,
+    targets_per_prefix: u16,
+    seed: u64,
+    range: TtlRange,
+    flows: u16,
+    mapper: FlowMapper,
+) -> Result<()> {
+    trace!("RIB handler");
+
+    let prefixes = read_rib(rib_file, format)?;
+    let targets = rib_targets(&prefixes, targets_per_prefix, seed);
+    if targets.is_empty() {
+        anyhow::bail!("No prefixes to probe in {}", rib_file.display());
+    }
+    if client_config.index_file.is_none() {
+        warn!("The origin ASNs of the targets are only recorded with --index-file");
+    }
+
+    let destinations: Vec<IpAddr> = targets.iter().map(|(target, _)| *target).collect();
+    let client_config = client_config.with_destination_tags(target_tags(&targets));
+    let probes = traceroute_probes(&destinations, range, flows, mapper);
+    info!(
+        "Tracing {} targets in {} prefixes with {} flows each (TTL {})",
+        destinations.len(),
+        prefixes.len(),
+        flows,
+        range
+    );
+    submit(
+        config,
+        client_config,
+        probes.into_iter().map(|probe| (probe, None)).collect(),
+    )
+    .await
+}
@@ -0,0 +1,113 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Messages produced for a set of probes, and the messages which could not be delivered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProduceStats {
+    pub messages: usize,
+    pub bytes: u64,
+    pub failures: usize,
+}
+
+/// Whether all the probes of an agent were delivered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentStatus {
+    #[default]
+    Delivered,
+    Failed,
+}
+
+/// Probes and messages submitted to an agent.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AgentSummary {
+    pub status: AgentStatus,
+    pub probes: usize,
+    pub failed_probes: usize,
+    pub messages: usize,
+    pub bytes: u64,
+}
+
+/// Summary of a submission, printed once the probes are produced (or posted to the gateway).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SubmissionSummary {
+    pub probes_read: usize,
+    pub messages_produced: usize,
+    pub bytes_sent: u64,
+    pub produce_failures: usize,
+    pub agents: BTreeMap<String, AgentSummary>,
+}
+
+impl SubmissionSummary {
+    pub fn new(probes_read: usize) -> Self {
+        SubmissionSummary {
+            probes_read,
+            ..Default::default()
+        }
+    }
+
+    /// Record the probes sent to `agents`, of which `failed_probes` could not be delivered.
+    /// Messages sent to several agents at once are counted once in the totals.
+    pub fn record(
+        &mut self,
+        agents: &[String],
+        probes: usize,
+        failed_probes: usize,
+        stats: ProduceStats,
+    ) {
+        self.messages_produced += stats.messages;
+        self.bytes_sent += stats.bytes;
+        self.produce_failures += stats.failures;
+        for agent in agents {
+            let summary = self.agents.entry(agent.clone()).or_default();
+            summary.probes += probes;
+            summary.failed_probes += failed_probes;
+            summary.messages += stats.messages;
+            summary.bytes += stats.bytes;
+            if failed_probes > 0 || stats.failures > 0 {
+                summary.status = AgentStatus::Failed;
+            }
+        }
+    }
+
+    /// Whether every message was delivered.
+    pub fn is_success(&self) -> bool {
+        self.produce_failures == 0
+            && self
+                .agents
+                .values()
+                .all(|agent| agent.status == AgentStatus::Delivered)
+    }
+
+    /// Print the summary as `key=value` lines, the totals then one line per agent.
+    pub fn write_text<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        writeln!(
+            out,
+            "probes_read={},messages_produced={},bytes_sent={},produce_failures={}",
+            self.probes_read, self.messages_produced, self.bytes_sent, self.produce_failures
+        )?;
+        for (agent, summary) in &self.agents {
+            writeln!(
+                out,
+                "agent={},status={},probes={},failed_probes={},messages={},bytes={}",
+                agent,
+                match summary.status {
+                    AgentStatus::Delivered => "delivered",
+                    AgentStatus::Failed => "failed",
+                },
+                summary.probes,
+                summary.failed_probes,
+                summary.messages,
+                summary.bytes
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Print the summary as a JSON object.
+    pub fn write_json<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        serde_json::to_writer(&mut *out, self)?;
+        writeln!(out)
+    }
+}
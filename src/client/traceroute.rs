@@ -0,0 +1,83 @@
+use anyhow::Result;
+use caracat::models::{Probe, L4};
+use std::io::stdin;
+use std::net::IpAddr;
+use tracing::{info, trace};
+
+use crate::agent::expand::{expand_target, TtlRange};
+use crate::client::generate::FlowMapper;
+use crate::client::handler::submit;
+use crate::client::ping::read_destinations;
+use crate::config::{AppConfig, ClientConfig};
+
+pub const DEFAULT_TRACEROUTE_MIN_TTL: u8 = 1;
+pub const DEFAULT_TRACEROUTE_MAX_TTL: u8 = 32;
+pub const DEFAULT_TRACEROUTE_FLOWS: u16 = 1;
+/// Source port of the first flow, offset for the next ones by the flow mapper. The ports of a
+/// flow are constant across TTLs, so that load balancers route all its probes on the same path
+/// (Paris traceroute).
+const TRACEROUTE_SRC_PORT: u16 = 24000;
+const TRACEROUTE_DST_PORT: u16 = 33434;
+
+/// UDP traceroute probes, one per TTL of `range` for each of the `flows` of each destination,
+/// mapped to a destination address and a source port by `mapper`.
+pub fn traceroute_probes(
+    destinations: &[IpAddr],
+    range: TtlRange,
+    flows: u16,
+    mapper: FlowMapper,
+) -> Vec<Probe> {
+    let mut probes = Vec::with_capacity(destinations.len() * flows as usize * range.len());
+    for destination in destinations {
+        for flow in 0..flows {
+            let (dst_addr, port_offset) = mapper.flow(*destination, flow as u64);
+            let target = Probe {
+                dst_addr,
+                src_port: TRACEROUTE_SRC_PORT.wrapping_add(port_offset),
+                dst_port: TRACEROUTE_DST_PORT,
+                ttl: range.min,
+                protocol: L4::UDP,
+            };
+            probes.extend(expand_target(&target, range));
+        }
+    }
+    probes
+}
+
+/// Traceroute the destinations from the agents of `client_config`.
+pub async fn handle(
+    config: &AppConfig,
+    client_config: ClientConfig,
+    range: TtlRange,
+    flows: u16,
+    mapper: FlowMapper,
+) -> Result<()> {
+    trace!("Traceroute handler");
+
+    // Read destinations from file or stdin
+    let destinations = match &client_config.probes_file {
+        Some(destinations_file) => {
+            let file = std::fs::File::open(destinations_file)?;
+            read_destinations(std::io::BufReader::new(file))?
+        }
+        None => read_destinations(stdin().lock())?,
+    };
+    if destinations.is_empty() {
+        anyhow::bail!("No destinations to traceroute");
+    }
+
+    let probes = traceroute_probes(&destinations, range, flows, mapper);
+    info!(
+        "Tracing {} destinations with {} flows each (TTL {}, {:?} flow mapper)",
+        destinations.len(),
+        flows,
+        range,
+        mapper
+    );
+    submit(
+        config,
+        client_config,
+        probes.into_iter().map(|probe| (probe, None)).collect(),
+    )
+    .await
+}
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
+
+use crate::agent::gateway::{fetch_measurement_status, http_client, MeasurementStatusUpdate};
+use crate::config::AppConfig;
+use crate::measurement::MeasurementSpec;
+
+const WAIT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Poll the gateway until every agent of the measurement reports it as complete.
+/// Returns the last status reported by each agent.
+pub async fn wait_for_completion(
+    config: &AppConfig,
+    measurement: &MeasurementSpec,
+    timeout: Option<Duration>,
+) -> Result<BTreeMap<String, MeasurementStatusUpdate>> {
+    let (agents, measurement_id) = (&measurement.agents, measurement.id.as_str());
+    let (gateway_url, agent_key, client) = match &config.gateway {
+        Some(gateway) => match (&gateway.url, &gateway.agent_key) {
+            (Some(url), Some(agent_key)) => (url.clone(), agent_key.clone(), http_client(gateway)?),
+            _ => anyhow::bail!("Waiting for completion requires gateway.url and gateway.agent_key"),
+        },
+        None => anyhow::bail!("Waiting for completion requires a gateway configuration"),
+    };
+
+    let start_time = Instant::now();
+    let mut statuses: BTreeMap<String, MeasurementStatusUpdate> = BTreeMap::new();
+
+    loop {
+        for agent in agents {
+            if statuses
+                .get(agent)
+                .is_some_and(|status| status.status().is_final())
+            {
+                continue;
+            }
+            match fetch_measurement_status(&client, &gateway_url, agent, &agent_key, measurement_id)
+                .await
+            {
+                Ok(status) => {
+                    debug!(
+                        "agent={},measurement_id={},sent_probes={},is_complete={}",
+                        agent, measurement_id, status.sent_probes, status.is_complete
+                    );
+                    statuses.insert(agent.clone(), status);
+                }
+                // The agent may not have reported yet
+                Err(e) => debug!("Failed to fetch status of agent {}: {}", agent, e),
+            }
+        }
+
+        let completed = agents
+            .iter()
+            .filter(|agent| {
+                statuses
+                    .get(*agent)
+                    .is_some_and(|status| status.status().is_final())
+            })
+            .count();
+        if completed == agents.len() {
+            return Ok(statuses);
+        }
+
+        if let Some(timeout) = timeout {
+            if start_time.elapsed() >= timeout {
+                warn!(
+                    "Timed out waiting for measurement {}: {}/{} agents completed",
+                    measurement_id,
+                    completed,
+                    agents.len()
+                );
+                anyhow::bail!("Timed out waiting for measurement {}", measurement_id);
+            }
+        }
+
+        info!(
+            "Waiting for measurement {}: {}/{} agents completed",
+            measurement_id,
+            completed,
+            agents.len()
+        );
+        sleep(WAIT_POLL_INTERVAL).await;
+    }
+}
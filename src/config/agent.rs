@@ -1,22 +1,124 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use crate::agent::measurement_labels::DEFAULT_MEASUREMENT_LABELS_LIMIT;
+use crate::agent::ports::PortPolicy;
 
 // --- Constants ---
 const DEFAULT_AGENT_METRICS_ADDRESS: &str = "0.0.0.0:8080";
+const DEFAULT_AGENT_ID_FILE: &str = "/var/lib/saimiris/agent_id";
+const DEFAULT_AGENT_AUDIT_LOG_MAX_BYTES: u64 = 100 * 1024 * 1024;
+const DEFAULT_AGENT_AUDIT_LOG_MAX_FILES: usize = 10;
 
 #[derive(Debug, Clone, serde::Deserialize, Default)]
 pub struct RawAgentConfig {
     #[serde(default)]
     pub id: String,
+    #[serde(default = "default_agent_id_file")]
+    pub id_file: PathBuf,
     #[serde(default = "default_agent_metrics_address")]
     pub metrics_address: String,
+    #[serde(default)]
+    pub integrity_key: Option<String>,
+    #[serde(default)]
+    pub http_auth_tokens: HashMap<String, String>,
+    #[serde(default)]
+    pub refuse_duplicate_id: bool,
+    #[serde(default)]
+    pub max_total_pps: Option<u64>,
+    #[serde(default)]
+    pub max_dst_prefix_pps: Option<u64>,
+    #[serde(default)]
+    pub max_probes_per_measurement: Option<u64>,
+    #[serde(default)]
+    pub signing_keys: HashMap<String, String>,
+    #[serde(default)]
+    pub standby: bool,
+    #[serde(default)]
+    pub statistics_interval: u64,
+    #[serde(default = "default_measurement_labels_limit")]
+    pub measurement_labels_limit: usize,
+    #[serde(default)]
+    pub blocklist_file: Option<PathBuf>,
+    #[serde(default)]
+    pub dedup_window: Option<usize>,
+    #[serde(default)]
+    pub audit_log_dir: Option<PathBuf>,
+    #[serde(default = "default_agent_audit_log_max_bytes")]
+    pub audit_log_max_bytes: u64,
+    #[serde(default = "default_agent_audit_log_max_files")]
+    pub audit_log_max_files: usize,
+    #[serde(default)]
+    pub src_ports: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct AgentConfig {
     pub id: String,
+    // File persisting the ID generated when `id` is empty
+    pub id_file: PathBuf,
+    // Address of the HTTP server (metrics, status and health endpoints)
     pub metrics_address: SocketAddr,
+    // Key of the HMAC integrity encoding
+    pub integrity_key: Option<String>,
+    // Bearer token required by HTTP route name (`metrics`, `status`, `health`)
+    pub http_auth_tokens: HashMap<String, String>,
+    // Pause the agent when another agent runs with the same ID (requires `kafka.agents_topic`)
+    pub refuse_duplicate_id: bool,
+    // Probing rate cap across all the caracat instances (packets per second)
+    pub max_total_pps: Option<u64>,
+    // Probing rate cap towards any destination /24 (IPv4) or /48 (IPv6), across all the
+    // caracat instances (packets per second)
+    pub max_dst_prefix_pps: Option<u64>,
+    // Probes sent per measurement across all the caracat instances, the next probes of the
+    // measurement are dropped
+    pub max_probes_per_measurement: Option<u64>,
+    // Key of each client signing its probes messages, by key ID.
+    // When set, unsigned or badly signed probes messages are rejected
+    pub signing_keys: HashMap<String, String>,
+    // Run as a warm standby pair with the other agents sharing this agent ID: only the
+    // elected leader consumes probes (requires `kafka.agents_topic`)
+    pub standby: bool,
+    // Seconds between two INFO summaries of the statistics of the instances (disabled if 0)
+    pub statistics_interval: u64,
+    // Distinct measurements with their own `measurement_id` and `client_id` labels on the
+    // probe and reply counters, the next ones are labelled `other` (no labels if 0)
+    pub measurement_labels_limit: usize,
+    // Do-not-probe prefix list, one prefix per line: the probes towards these prefixes are
+    // dropped by every instance
+    pub blocklist_file: Option<PathBuf>,
+    // Probes remembered by each instance to drop the probes repeated inside a measurement
+    // (same destination, TTL, ports, protocol and round), disabled if not set
+    pub dedup_window: Option<usize>,
+    // Directory of the audit log of the probes messages consumed (client, measurement, probes,
+    // source IP and decision), disabled if not set
+    pub audit_log_dir: Option<PathBuf>,
+    // Size of an audit log file before it is rotated
+    pub audit_log_max_bytes: u64,
+    // Rotated audit log files kept, the oldest are deleted beyond
+    pub audit_log_max_files: usize,
+    // Source ports selected per flow for the probes of the messages without a `src_ports`
+    // header (`MIN-MAX[:STRATEGY]`), the source ports of the probes are kept if not set
+    pub src_ports: Option<PortPolicy>,
+}
+
+fn default_agent_id_file() -> PathBuf {
+    PathBuf::from(DEFAULT_AGENT_ID_FILE)
 }
 
 fn default_agent_metrics_address() -> String {
     DEFAULT_AGENT_METRICS_ADDRESS.to_string()
 }
+
+fn default_measurement_labels_limit() -> usize {
+    DEFAULT_MEASUREMENT_LABELS_LIMIT
+}
+
+fn default_agent_audit_log_max_bytes() -> u64 {
+    DEFAULT_AGENT_AUDIT_LOG_MAX_BYTES
+}
+
+fn default_agent_audit_log_max_files() -> usize {
+    DEFAULT_AGENT_AUDIT_LOG_MAX_FILES
+}
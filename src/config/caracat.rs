@@ -5,6 +5,27 @@ const DEFAULT_CARACAT_INSTANCE_ID: u16 = 0;
 const DEFAULT_CARACAT_PACKETS: u64 = 1;
 const DEFAULT_CARACAT_PROBING_RATE: u64 = 100;
 const DEFAULT_RATE_LIMITING_METHOD: &str = "auto";
+const DEFAULT_CARACAT_SEND_BATCH_SIZE: u64 = 64;
+const DEFAULT_INTEGRITY_ENCODING: &str = "instance_id";
+const DEFAULT_CARACAT_SENDER_THREADS: u64 = 1;
+const DEFAULT_REPLY_FILTER: &str = "all";
+const DEFAULT_RECEIVER_BACKEND: &str = "pcap";
+const DEFAULT_SENDER_BACKEND: &str = "caracat";
+const DEFAULT_RECEIVER_RING_BLOCKS: u64 = 64;
+const DEFAULT_CARACAT_FAILOVER_THRESHOLD: u64 = 100;
+const DEFAULT_CARACAT_FAILOVER_COOLDOWN: u64 = 60;
+const DEFAULT_CARACAT_EMISSION_CHECK_TIMEOUT: u64 = 5;
+const DEFAULT_FAIRNESS_IPV4_PREFIX_LEN: u8 = 24;
+const DEFAULT_FAIRNESS_IPV6_PREFIX_LEN: u8 = 48;
+const DEFAULT_PCAP_DUMP_MAX_BYTES: u64 = 100 * 1024 * 1024;
+const DEFAULT_PCAP_DUMP_MAX_FILES: u64 = 5;
+
+/// Probing rate of each source address within a prefix.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SourceRateLimit {
+    pub prefix: String,
+    pub probing_rate: u64,
+}
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, Default)]
 pub struct CaracatConfig {
@@ -22,6 +43,15 @@ pub struct CaracatConfig {
     pub max_ttl: Option<u8>,
     #[serde(default)]
     pub integrity_check: bool,
+    #[serde(default = "default_integrity_encoding")]
+    pub integrity_encoding: String,
+    #[serde(default)]
+    pub integrity_accept_instance_id: bool,
+    // Per address family override of `integrity_check`
+    #[serde(default)]
+    pub integrity_check_ipv4: Option<bool>,
+    #[serde(default)]
+    pub integrity_check_ipv6: Option<bool>,
     #[serde(default = "default_caracat_interface")]
     pub interface: String,
     #[serde(default)]
@@ -32,8 +62,82 @@ pub struct CaracatConfig {
     pub packets: u64,
     #[serde(default = "default_caracat_probing_rate")]
     pub probing_rate: u64,
+    #[serde(default)]
+    pub source_rate_limits: Vec<SourceRateLimit>,
     #[serde(default = "default_rate_limiting_method")]
     pub rate_limiting_method: String,
+    // Probes handed to the kernel at once (but with the `pcap` backend), and sent between the
+    // pause and cancellation checks of a SendLoop
+    #[serde(default = "default_caracat_send_batch_size")]
+    pub send_batch_size: u64,
+    #[serde(default = "default_caracat_sender_threads")]
+    pub sender_threads: u64,
+    // Emission of the probes (`caracat`, `pcap`, or the experimental `tx_ring` of the `tx-ring`
+    // feature)
+    #[serde(default = "default_sender_backend")]
+    pub sender_backend: String,
+    #[serde(default = "default_reply_filter")]
+    pub reply_filter: String,
+    #[serde(default)]
+    pub reply_icmp_allowlist: Vec<String>,
+    #[serde(default)]
+    pub reply_exclude_unreachable: bool,
+    // Capture of the replies (`pcap` or `tpacket_v3`), and the 1 MiB blocks of the TPACKET_V3 ring
+    #[serde(default = "default_receiver_backend")]
+    pub receiver_backend: String,
+    #[serde(default = "default_receiver_ring_blocks")]
+    pub receiver_ring_blocks: u64,
+    // pcap file receiving the raw reply packets captured on the interface, rotated once it
+    // reaches `pcap_dump_max_bytes`, with `pcap_dump_max_files` rotated files kept
+    #[serde(default)]
+    pub pcap_dump: Option<String>,
+    #[serde(default = "default_pcap_dump_max_bytes")]
+    pub pcap_dump_max_bytes: u64,
+    #[serde(default = "default_pcap_dump_max_files")]
+    pub pcap_dump_max_files: u64,
+    // Probing policy, on top of the TTL bounds
+    #[serde(default)]
+    pub dst_denylist: Vec<String>,
+    #[serde(default)]
+    pub dst_allowlist: Vec<String>,
+    // Prefix list file of the allowed destinations, on top of `dst_allowlist`
+    #[serde(default)]
+    pub dst_allowlist_file: Option<String>,
+    #[serde(default)]
+    pub allowed_protocols: Vec<String>,
+    #[serde(default)]
+    pub max_probes_per_destination: Option<u64>,
+    // Failover to another instance (by name) when this one cannot send
+    #[serde(default)]
+    pub backup_instance: Option<String>,
+    #[serde(default = "default_caracat_failover_threshold")]
+    pub failover_threshold: u64,
+    #[serde(default = "default_caracat_failover_cooldown")]
+    pub failover_cooldown: u64,
+    // Capture one in `emission_check_sample_every` probes sent on the interface, and verify
+    // its wire format (disabled if 0)
+    #[serde(default)]
+    pub emission_check_sample_every: u64,
+    // Seconds to capture a sampled probe before reporting it missing
+    #[serde(default = "default_caracat_emission_check_timeout")]
+    pub emission_check_timeout: u64,
+    // Interleave the probes of a message across destination networks (`prefix` or `asn`)
+    #[serde(default)]
+    pub fairness: Option<String>,
+    #[serde(default = "default_fairness_ipv4_prefix_len")]
+    pub fairness_ipv4_prefix_len: u8,
+    #[serde(default = "default_fairness_ipv6_prefix_len")]
+    pub fairness_ipv6_prefix_len: u8,
+    // Origin ASN of the destination prefixes, one 'PREFIX ASN' per line
+    #[serde(default)]
+    pub fairness_asn_file: Option<String>,
+    // Probes per second to each network of `fairness`, across the messages of the instance
+    #[serde(default)]
+    pub fairness_max_pps: Option<u64>,
+    // Order of the messages of concurrent measurements (`fifo`, or `fair` for a weighted
+    // round-robin across measurements)
+    #[serde(default)]
+    pub measurement_scheduling: Option<String>,
 }
 
 pub fn default_caracat_batch_size() -> u64 {
@@ -60,6 +164,62 @@ pub fn default_rate_limiting_method() -> String {
     DEFAULT_RATE_LIMITING_METHOD.to_string()
 }
 
+pub fn default_caracat_send_batch_size() -> u64 {
+    DEFAULT_CARACAT_SEND_BATCH_SIZE
+}
+
+pub fn default_caracat_sender_threads() -> u64 {
+    DEFAULT_CARACAT_SENDER_THREADS
+}
+
+pub fn default_sender_backend() -> String {
+    DEFAULT_SENDER_BACKEND.to_string()
+}
+
+pub fn default_reply_filter() -> String {
+    DEFAULT_REPLY_FILTER.to_string()
+}
+
+pub fn default_receiver_backend() -> String {
+    DEFAULT_RECEIVER_BACKEND.to_string()
+}
+
+pub fn default_receiver_ring_blocks() -> u64 {
+    DEFAULT_RECEIVER_RING_BLOCKS
+}
+
+pub fn default_caracat_failover_threshold() -> u64 {
+    DEFAULT_CARACAT_FAILOVER_THRESHOLD
+}
+
+pub fn default_caracat_failover_cooldown() -> u64 {
+    DEFAULT_CARACAT_FAILOVER_COOLDOWN
+}
+
+pub fn default_caracat_emission_check_timeout() -> u64 {
+    DEFAULT_CARACAT_EMISSION_CHECK_TIMEOUT
+}
+
+pub fn default_fairness_ipv4_prefix_len() -> u8 {
+    DEFAULT_FAIRNESS_IPV4_PREFIX_LEN
+}
+
+pub fn default_fairness_ipv6_prefix_len() -> u8 {
+    DEFAULT_FAIRNESS_IPV6_PREFIX_LEN
+}
+
+pub fn default_pcap_dump_max_bytes() -> u64 {
+    DEFAULT_PCAP_DUMP_MAX_BYTES
+}
+
+pub fn default_pcap_dump_max_files() -> u64 {
+    DEFAULT_PCAP_DUMP_MAX_FILES
+}
+
+pub fn default_integrity_encoding() -> String {
+    DEFAULT_INTEGRITY_ENCODING.to_string()
+}
+
 impl CaracatConfig {
     /// Validates and normalizes the configuration, setting defaults for zero values
     pub fn validate_and_normalize(&mut self) {
@@ -81,5 +241,47 @@ impl CaracatConfig {
         if self.rate_limiting_method.is_empty() {
             self.rate_limiting_method = default_rate_limiting_method();
         }
+        if self.send_batch_size == 0 {
+            self.send_batch_size = default_caracat_send_batch_size();
+        }
+        if self.sender_threads == 0 {
+            self.sender_threads = default_caracat_sender_threads();
+        }
+        if self.sender_backend.is_empty() {
+            self.sender_backend = default_sender_backend();
+        }
+        if self.integrity_encoding.is_empty() {
+            self.integrity_encoding = default_integrity_encoding();
+        }
+        if self.reply_filter.is_empty() {
+            self.reply_filter = default_reply_filter();
+        }
+        if self.receiver_backend.is_empty() {
+            self.receiver_backend = default_receiver_backend();
+        }
+        if self.receiver_ring_blocks == 0 {
+            self.receiver_ring_blocks = default_receiver_ring_blocks();
+        }
+        if self.pcap_dump_max_bytes == 0 {
+            self.pcap_dump_max_bytes = default_pcap_dump_max_bytes();
+        }
+        if self.pcap_dump_max_files == 0 {
+            self.pcap_dump_max_files = default_pcap_dump_max_files();
+        }
+        if self.failover_threshold == 0 {
+            self.failover_threshold = default_caracat_failover_threshold();
+        }
+        if self.failover_cooldown == 0 {
+            self.failover_cooldown = default_caracat_failover_cooldown();
+        }
+        if self.emission_check_timeout == 0 {
+            self.emission_check_timeout = default_caracat_emission_check_timeout();
+        }
+        if self.fairness_ipv4_prefix_len == 0 {
+            self.fairness_ipv4_prefix_len = default_fairness_ipv4_prefix_len();
+        }
+        if self.fairness_ipv6_prefix_len == 0 {
+            self.fairness_ipv6_prefix_len = default_fairness_ipv6_prefix_len();
+        }
     }
 }
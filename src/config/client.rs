@@ -1,12 +1,100 @@
 use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 
+use crate::agent::expand::TtlRange;
+use crate::agent::ports::PortPolicy;
 use crate::client::producer::MeasurementInfo;
+use crate::probe::ProbeTags;
+
+const DEFAULT_PRODUCE_RETRIES: u32 = 3;
+pub const DEFAULT_GATEWAY_CHUNK_PROBES: usize = 10_000;
+
+/// Format of the probes read by the client
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbesFormat {
+    /// Positional CSV: dst_addr,src_port,dst_port,ttl,protocol
+    #[default]
+    Csv,
+    /// One JSON object per line, with the same fields as the CSV format
+    Jsonl,
+}
+
+/// How the probes are distributed when several agents are specified
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Distribution {
+    /// Every agent sends every probe
+    #[default]
+    Replicate,
+    /// Each agent sends the probes towards a subset of the destinations (hash of the destination)
+    Shard,
+    /// Probes are dealt to the agents in turn
+    RoundRobin,
+}
 
 #[derive(Debug)]
 pub struct ClientConfig {
     pub measurement_infos: Vec<MeasurementInfo>,
     pub probes_file: Option<PathBuf>,
+    pub probes_format: ProbesFormat,
+    pub distribution: Distribution,
+    // Maximum number of messages awaiting delivery, and of messages produced per second
+    pub max_in_flight: usize,
+    pub produce_rate: Option<f64>,
+    // Retries of a message on transient delivery errors
+    pub produce_retries: u32,
+    // Manifest of the undelivered probes to write on failure, and to resubmit
+    pub failure_manifest: Option<PathBuf>,
+    pub retry_manifest: Option<PathBuf>,
+    // Wait for the agents to complete the measurement, with an optional timeout
+    pub wait: bool,
+    pub wait_timeout: Option<Duration>,
+    // Tags applied to every submitted probe
+    pub probe_tags: ProbeTags,
+    // Submit targets, expanded by the agents into one probe per TTL of the range
+    pub expand_ttl: Option<TtlRange>,
+    // Source ports selected per flow by the agents, instead of those of the probes
+    pub src_ports: Option<PortPolicy>,
+    // Priority of the probes on the agents, sent before those of a lower priority
+    pub priority: Option<u32>,
+    // Weight of the measurement in the fair scheduling of the agents
+    pub weight: Option<u32>,
+    // Probe index written at submission time, to be joined with replies later
+    pub index_file: Option<PathBuf>,
+    pub index_tags: BTreeMap<String, String>,
+    // Tags recorded in the index with the probes to a destination, on top of the index tags
+    pub destination_tags: HashMap<IpAddr, BTreeMap<String, String>>,
+    // Submit the probes to the gateway instead of Kafka, in chunks of up to this many probes
+    pub via_gateway: bool,
+    pub gateway_chunk_probes: usize,
+    // Print the submission summary as JSON instead of text
+    pub json_summary: bool,
+    // Build the probes messages without producing them, nor writing the probe index
+    pub dry_run: bool,
 }
 
 pub fn parse_and_validate_client_args(
@@ -20,6 +108,7 @@ pub fn parse_and_validate_client_args(
 
     // Parse agents in format: agent1:ip1,agent2:ip2
     // Handle IPv6 addresses in brackets: agent1:[2001:db8::1]
+    // Target a named caracat instance of the agent: agent1/instance:ip1
     let measurement_infos: Vec<MeasurementInfo> = agents
         .split(',')
         .map(|agent_spec| {
@@ -52,6 +141,20 @@ pub fn parse_and_validate_client_args(
                 (parts[0].trim(), parts[1].trim())
             };
 
+            let (agent_name, instance) = match agent_name.split_once('/') {
+                Some((agent_name, instance)) => {
+                    let instance = instance.trim();
+                    if instance.is_empty() {
+                        return Err(anyhow::anyhow!(
+                            "Empty instance name in specification '{}'",
+                            agent_spec
+                        ));
+                    }
+                    (agent_name.trim(), Some(instance.to_string()))
+                }
+                None => (agent_name, None),
+            };
+
             if agent_name.is_empty() {
                 return Err(anyhow::anyhow!(
                     "Empty agent name in specification '{}'",
@@ -74,6 +177,7 @@ pub fn parse_and_validate_client_args(
             Ok(MeasurementInfo {
                 name: agent_name.to_string(),
                 src_ip: Some(ip_str.to_string()),
+                instance,
                 // Default measurement tracking value - can be overridden later
                 measurement_id: None,
             })
@@ -87,6 +191,27 @@ pub fn parse_and_validate_client_args(
     Ok(ClientConfig {
         measurement_infos,
         probes_file,
+        probes_format: ProbesFormat::default(),
+        distribution: Distribution::default(),
+        max_in_flight: 1,
+        produce_rate: None,
+        produce_retries: DEFAULT_PRODUCE_RETRIES,
+        failure_manifest: None,
+        retry_manifest: None,
+        wait: false,
+        wait_timeout: None,
+        probe_tags: ProbeTags::default(),
+        expand_ttl: None,
+        src_ports: None,
+        priority: None,
+        weight: None,
+        index_file: None,
+        index_tags: BTreeMap::new(),
+        destination_tags: HashMap::new(),
+        via_gateway: false,
+        gateway_chunk_probes: DEFAULT_GATEWAY_CHUNK_PROBES,
+        json_summary: false,
+        dry_run: false,
     })
 }
 
@@ -98,6 +223,150 @@ impl ClientConfig {
         }
         self
     }
+
+    /// Pin the probes to the caracat instance named `instance` on every agent,
+    /// unless an instance is given in the agent specification
+    pub fn with_instance(mut self, instance: Option<String>) -> Self {
+        if let Some(instance) = instance {
+            for agent in &mut self.measurement_infos {
+                agent.instance.get_or_insert_with(|| instance.clone());
+            }
+        }
+        self
+    }
+
+    /// Set the format of the probes file (or stdin)
+    pub fn with_probes_format(mut self, probes_format: ProbesFormat) -> Self {
+        self.probes_format = probes_format;
+        self
+    }
+
+    /// Set how the probes are distributed across the agents
+    pub fn with_distribution(mut self, distribution: Distribution) -> Self {
+        self.distribution = distribution;
+        self
+    }
+
+    /// Pipeline up to `max_in_flight` messages, optionally paced to `produce_rate` messages per second
+    pub fn with_produce_controls(
+        mut self,
+        max_in_flight: usize,
+        produce_rate: Option<f64>,
+    ) -> Self {
+        self.max_in_flight = max_in_flight.max(1);
+        self.produce_rate = produce_rate;
+        self
+    }
+
+    /// Retry messages up to `produce_retries` times on transient delivery errors
+    pub fn with_produce_retries(mut self, produce_retries: u32) -> Self {
+        self.produce_retries = produce_retries;
+        self
+    }
+
+    /// Write the undelivered probes to `failure_manifest`, and only resubmit
+    /// the probes listed in `retry_manifest`
+    pub fn with_manifests(
+        mut self,
+        failure_manifest: Option<PathBuf>,
+        retry_manifest: Option<PathBuf>,
+    ) -> Self {
+        self.failure_manifest = failure_manifest;
+        self.retry_manifest = retry_manifest;
+        self
+    }
+
+    /// Wait for the agents to complete the measurement after producing the probes
+    pub fn with_wait(mut self, wait: bool, wait_timeout_secs: Option<u64>) -> Self {
+        self.wait = wait;
+        self.wait_timeout = wait_timeout_secs.map(Duration::from_secs);
+        self
+    }
+
+    /// Tag all submitted probes with the given round
+    pub fn with_round(mut self, round: Option<u32>) -> Self {
+        self.probe_tags.round = round.unwrap_or_default();
+        self
+    }
+
+    /// Mark all submitted probes with the given DSCP
+    pub fn with_dscp(mut self, dscp: Option<u8>) -> Self {
+        self.probe_tags.dscp = dscp.unwrap_or_default();
+        self
+    }
+
+    /// Submit the probes as targets, expanded by the agents into one probe per TTL of `expand_ttl`
+    pub fn with_expand_ttl(mut self, expand_ttl: Option<TtlRange>) -> Self {
+        self.expand_ttl = expand_ttl;
+        self
+    }
+
+    /// Let the agents select the source port of each probe per flow, within the range of `src_ports`
+    pub fn with_src_ports(mut self, src_ports: Option<PortPolicy>) -> Self {
+        self.src_ports = src_ports;
+        self
+    }
+
+    /// Send the probes before those of a lower priority on the agents (0 by default)
+    pub fn with_priority(mut self, priority: Option<u32>) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Give the measurement `weight` times the probes of the others in the fair scheduling of the
+    /// agents (1 by default)
+    pub fn with_weight(mut self, weight: Option<u32>) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Submit the probes to the gateway instead of Kafka, in chunks of up to `chunk_probes` probes
+    pub fn with_gateway_submission(mut self, via_gateway: bool, chunk_probes: usize) -> Self {
+        self.via_gateway = via_gateway;
+        self.gateway_chunk_probes = chunk_probes.max(1);
+        self
+    }
+
+    /// Print the submission summary as JSON
+    pub fn with_json_summary(mut self, json_summary: bool) -> Self {
+        self.json_summary = json_summary;
+        self
+    }
+
+    /// Build the probes messages without producing them
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Write the submitted probes to an index file, with user tags in `KEY=VALUE` format
+    pub fn with_probe_index(
+        mut self,
+        index_file: Option<PathBuf>,
+        tags: &[String],
+    ) -> Result<Self> {
+        for tag in tags {
+            let (key, value) = tag.split_once('=').ok_or_else(|| {
+                anyhow::anyhow!("Invalid tag '{}'. Expected format: 'KEY=VALUE'", tag)
+            })?;
+            if key.trim().is_empty() {
+                return Err(anyhow::anyhow!("Empty tag key in '{}'", tag));
+            }
+            self.index_tags
+                .insert(key.trim().to_string(), value.trim().to_string());
+        }
+        self.index_file = index_file;
+        Ok(self)
+    }
+
+    /// Record tags in the index with the probes to each destination
+    pub fn with_destination_tags(
+        mut self,
+        destination_tags: HashMap<IpAddr, BTreeMap<String, String>>,
+    ) -> Self {
+        self.destination_tags = destination_tags;
+        self
+    }
 }
 
 #[cfg(test)]
@@ -240,6 +509,35 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("Expected format"));
     }
 
+    #[test]
+    fn test_agent_instance() {
+        let config = parse_and_validate_client_args(
+            "agent1/fast:192.168.1.1,agent2:10.0.0.1,agent3/slow:[2001:db8::1]",
+            None,
+        )
+        .unwrap();
+        let infos = &config.measurement_infos;
+        assert_eq!(infos[0].name, "agent1");
+        assert_eq!(infos[0].instance, Some("fast".to_string()));
+        assert_eq!(infos[1].name, "agent2");
+        assert_eq!(infos[1].instance, None);
+        assert_eq!(infos[2].name, "agent3");
+        assert_eq!(infos[2].instance, Some("slow".to_string()));
+        assert_eq!(infos[2].src_ip, Some("2001:db8::1".to_string()));
+
+        // The instance of the specification takes precedence
+        let config = config.with_instance(Some("default".to_string()));
+        let instances: Vec<_> = config
+            .measurement_infos
+            .iter()
+            .map(|agent| agent.instance.as_deref())
+            .collect();
+        assert_eq!(instances, vec![Some("fast"), Some("default"), Some("slow")]);
+
+        assert!(parse_and_validate_client_args("agent1/:192.168.1.1", None).is_err());
+        assert!(parse_and_validate_client_args("/fast:192.168.1.1", None).is_err());
+    }
+
     #[test]
     fn test_single_agent() {
         let result = parse_and_validate_client_args("agent1:192.168.1.1", None);
@@ -254,6 +552,26 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_probe_index_tags() {
+        let config = parse_and_validate_client_args("agent1:192.168.1.1", None)
+            .unwrap()
+            .with_probe_index(
+                Some(PathBuf::from("index.jsonl")),
+                &["campaign=ark".to_string(), " team = nxthdr ".to_string()],
+            )
+            .unwrap();
+
+        assert_eq!(config.index_file, Some(PathBuf::from("index.jsonl")));
+        assert_eq!(config.index_tags.get("campaign"), Some(&"ark".to_string()));
+        assert_eq!(config.index_tags.get("team"), Some(&"nxthdr".to_string()));
+
+        let result = parse_and_validate_client_args("agent1:192.168.1.1", None)
+            .unwrap()
+            .with_probe_index(None, &["campaign".to_string()]);
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_whitespace_handling() {
         let result =
@@ -273,4 +591,37 @@ mod tests {
             Some("10.0.0.1".to_string())
         );
     }
+
+    #[test]
+    fn test_wait_for_completion() {
+        let config = parse_and_validate_client_args("agent1:192.168.1.1", None)
+            .unwrap()
+            .with_wait(true, Some(60));
+        assert!(config.wait);
+        assert_eq!(config.wait_timeout, Some(Duration::from_secs(60)));
+
+        let config = parse_and_validate_client_args("agent1:192.168.1.1", None).unwrap();
+        assert!(!config.wait);
+        assert_eq!(config.wait_timeout, None);
+    }
+
+    #[test]
+    fn test_produce_controls() {
+        let config = parse_and_validate_client_args("agent1:192.168.1.1", None).unwrap();
+        assert_eq!(config.max_in_flight, 1);
+        assert_eq!(config.produce_rate, None);
+
+        let config = config.with_produce_controls(0, Some(10.0));
+        assert_eq!(config.max_in_flight, 1);
+        assert_eq!(config.produce_rate, Some(10.0));
+    }
+
+    #[test]
+    fn test_produce_retries() {
+        let config = parse_and_validate_client_args("agent1:192.168.1.1", None).unwrap();
+        assert_eq!(config.produce_retries, 3);
+
+        let config = config.with_produce_retries(0);
+        assert_eq!(config.produce_retries, 0);
+    }
 }
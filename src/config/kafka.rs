@@ -1,3 +1,11 @@
+use anyhow::Result;
+use ipnet::IpNet;
+use rdkafka::config::ClientConfig;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::auth::{KafkaAuth, SaslAuth};
+
 // --- Constants ---
 const DEFAULT_KAFKA_BROKERS: &str = "localhost:9092";
 const DEFAULT_KAFKA_AUTH_PROTOCOL: &str = "PLAINTEXT";
@@ -7,9 +15,33 @@ const DEFAULT_KAFKA_AUTH_SASL_MECHANISM: &str = "SCRAM-SHA-512";
 const DEFAULT_KAFKA_MESSAGE_MAX_BYTES: usize = 990_000;
 const DEFAULT_KAFKA_IN_TOPICS: &str = "saimiris-probes";
 const DEFAULT_KAFKA_IN_GROUP_ID: &str = "saimiris-agent";
+const DEFAULT_KAFKA_IN_AUTO_OFFSET_RESET: &str = "latest";
+const DEFAULT_KAFKA_IN_SESSION_TIMEOUT_MS: u32 = 6000;
+const DEFAULT_KAFKA_COMMIT_STRATEGY: &str = "after-queue";
+const DEFAULT_KAFKA_IN_PAUSE_WATERMARK: f64 = 0.9;
+const DEFAULT_KAFKA_IN_RESUME_WATERMARK: f64 = 0.5;
+const KAFKA_AUTO_OFFSET_RESET_VALUES: [&str; 7] = [
+    "smallest",
+    "earliest",
+    "beginning",
+    "largest",
+    "latest",
+    "end",
+    "error",
+];
+const KAFKA_COMPRESSION_VALUES: [&str; 5] = ["none", "gzip", "snappy", "lz4", "zstd"];
 const DEFAULT_KAFKA_OUT_TOPIC: &str = "saimiris-replies";
+const DEFAULT_KAFKA_OUT_FORMAT: &str = "capnp";
+const DEFAULT_KAFKA_KEY_STRATEGY: &str = "none";
+// Destination prefixes keying the messages with the `probe_dst_prefix` strategy
+const KEY_IPV4_PREFIX_LEN: u8 = 24;
+const KEY_IPV6_PREFIX_LEN: u8 = 48;
 const DEFAULT_KAFKA_OUT_BATCH_WAIT_TIME: u64 = 1000;
 const DEFAULT_KAFKA_OUT_BATCH_WAIT_INTERVAL: u64 = 100;
+const DEFAULT_KAFKA_SPOOF_SAMPLE_EVERY: u64 = 100;
+const DEFAULT_KAFKA_EVENTS_MAX_RATE: u64 = 10;
+const DEFAULT_KAFKA_AGENTS_HEARTBEAT_INTERVAL: u64 = 60;
+const DEFAULT_SCHEMA_REGISTRY_SCHEMA_TYPE: &str = "CAPNP";
 
 #[derive(Debug, Clone, serde::Deserialize, Default)]
 pub struct KafkaConfig {
@@ -27,16 +59,340 @@ pub struct KafkaConfig {
     pub message_max_bytes: usize,
     #[serde(default = "default_kafka_in_topics")]
     pub in_topics: String,
+    // Priority of each of the `in_topics` (0 if not set): a topic is only consumed while the
+    // topics of a higher priority have no messages to consume
+    #[serde(default)]
+    pub in_topic_priorities: HashMap<String, u32>,
+    // Topic per agent, e.g. "saimiris-probes-{agent}", used instead of `in_topics`
+    #[serde(default)]
+    pub in_topic_template: Option<String>,
     #[serde(default = "default_kafka_in_group_id")]
     pub in_group_id: String,
+    // Where new consumer groups start consuming: "earliest", "latest" or "error"
+    #[serde(default)]
+    pub in_auto_offset_reset: Option<String>,
+    // Consumer settings, librdkafka defaults if not set
+    #[serde(default)]
+    pub in_fetch_min_bytes: Option<u32>,
+    #[serde(default)]
+    pub in_max_poll_interval_ms: Option<u32>,
+    #[serde(default)]
+    pub in_session_timeout_ms: Option<u32>,
+    // Fill of the fullest sender queue (from 0 to 1) above which the probes partitions are
+    // paused, and below which they are resumed
+    #[serde(default)]
+    pub in_pause_watermark: Option<f64>,
+    #[serde(default)]
+    pub in_resume_watermark: Option<f64>,
+    // When the probes offsets are committed: "auto", "after-queue" or "after-send"
+    #[serde(default = "default_kafka_commit_strategy")]
+    pub commit_strategy: String,
     #[serde(default = "default_kafka_out_enable")]
     pub out_enable: bool,
     #[serde(default = "default_kafka_out_topic")]
     pub out_topic: String,
+    // Encoding of the replies: "capnp" or "json" (an object per message)
+    #[serde(default = "default_kafka_out_format")]
+    pub out_format: String,
     #[serde(default = "default_kafka_out_batch_wait_time")]
     pub out_batch_wait_time: u64,
     #[serde(default = "default_kafka_out_batch_wait_interval")]
     pub out_batch_wait_interval: u64,
+    // Cluster the replies are produced to, the one of the probes if not set
+    #[serde(default)]
+    pub out: Option<KafkaClusterConfig>,
+    // Compression of the produced messages (probes and replies): "none", "gzip", "snappy",
+    // "lz4" or "zstd", librdkafka default (none) if not set
+    #[serde(default)]
+    pub compression: Option<String>,
+    // Key of the produced probes and replies messages, setting their partition: "none",
+    // "agent_id", "measurement_id" or "probe_dst_prefix"
+    #[serde(default = "default_kafka_key_strategy")]
+    pub key_strategy: String,
+    // Topic receiving a sample of the replies failing the integrity check while quoting our prefixes
+    #[serde(default)]
+    pub spoof_topic: Option<String>,
+    #[serde(default = "default_kafka_spoof_sample_every")]
+    pub spoof_sample_every: u64,
+    // Topic receiving structured agent events (startup, measurements, ...)
+    #[serde(default)]
+    pub events_topic: Option<String>,
+    // Maximum number of events published per second
+    #[serde(default = "default_kafka_events_max_rate")]
+    pub events_max_rate: u64,
+    // Topic on which operators publish pause/resume/drain commands for the agents
+    #[serde(default)]
+    pub control_topic: Option<String>,
+    // Compacted topic where the agents publish their capabilities, checked by the client
+    #[serde(default)]
+    pub agents_topic: Option<String>,
+    // Seconds between two publications of the agent capabilities, used to detect duplicate agent IDs
+    #[serde(default = "default_kafka_agents_heartbeat_interval")]
+    pub agents_heartbeat_interval: u64,
+    // Key signing the probes messages produced by the client, and its ID in `agent.signing_keys`
+    #[serde(default)]
+    pub signing_key_id: Option<String>,
+    #[serde(default)]
+    pub signing_key: Option<String>,
+    // librdkafka properties (e.g. "linger.ms"), set as is on every consumer and producer
+    #[serde(default)]
+    pub properties: HashMap<String, String>,
+    // Registry of the schemas of the probes and replies messages, framed with their schema ID
+    #[serde(default)]
+    pub schema_registry: Option<SchemaRegistryConfig>,
+}
+
+/// Kafka cluster the replies are produced to (`kafka.out`), when they are not produced to the
+/// cluster the probes are consumed from.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct KafkaClusterConfig {
+    pub brokers: String,
+    #[serde(default = "default_kafka_auth_protocol")]
+    pub auth_protocol: String,
+    #[serde(default = "default_kafka_auth_sasl_username")]
+    pub auth_sasl_username: String,
+    #[serde(default = "default_kafka_auth_sasl_password")]
+    pub auth_sasl_password: String,
+    #[serde(default = "default_kafka_auth_sasl_mechanism")]
+    pub auth_sasl_mechanism: String,
+    // librdkafka properties set on the producers of this cluster, instead of `kafka.properties`
+    #[serde(default)]
+    pub properties: HashMap<String, String>,
+}
+
+impl KafkaClusterConfig {
+    pub fn auth(&self) -> Result<KafkaAuth> {
+        match self.auth_protocol.as_str() {
+            "PLAINTEXT" => Ok(KafkaAuth::PlainText),
+            "SASL_PLAINTEXT" => Ok(KafkaAuth::SasalPlainText(SaslAuth {
+                username: self.auth_sasl_username.clone(),
+                password: self.auth_sasl_password.clone(),
+                mechanism: self.auth_sasl_mechanism.clone(),
+            })),
+            other => anyhow::bail!(
+                "Invalid kafka.out.auth_protocol '{}'. Expected PLAINTEXT or SASL_PLAINTEXT",
+                other
+            ),
+        }
+    }
+}
+
+/// Confluent-compatible schema registry.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SchemaRegistryConfig {
+    // e.g. "http://schema-registry:8081"
+    pub url: String,
+    // Basic authentication
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    // Type of the registered Cap'n Proto schemas, handled by a schema provider of the registry
+    #[serde(default = "default_schema_registry_schema_type")]
+    pub schema_type: String,
+}
+
+/// Key of the produced messages (`kafka.key_strategy`), so that the messages sharing a key
+/// land in the same partition.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyStrategy {
+    /// No key, the messages are spread across the partitions.
+    #[default]
+    None,
+    /// Agent sending the replies, or agents the probes are sent to.
+    AgentId,
+    /// Measurement of the probes or replies (no key outside of a measurement).
+    MeasurementId,
+    /// /24 (IPv4) or /48 (IPv6) of the probe destination, the first one of the message
+    /// for the probes.
+    ProbeDstPrefix,
+}
+
+impl KeyStrategy {
+    pub fn parse(strategy: &str) -> Result<Self> {
+        match strategy {
+            "" | "none" => Ok(KeyStrategy::None),
+            "agent_id" => Ok(KeyStrategy::AgentId),
+            "measurement_id" => Ok(KeyStrategy::MeasurementId),
+            "probe_dst_prefix" => Ok(KeyStrategy::ProbeDstPrefix),
+            other => anyhow::bail!(
+                "Invalid kafka.key_strategy '{}'. Expected one of: none, agent_id, measurement_id, probe_dst_prefix",
+                other
+            ),
+        }
+    }
+
+    /// Key of a message of `agent_id`, with the probes or replies of `measurement_id` to
+    /// `dst_addr`, `None` if the message is not keyed.
+    pub fn key(
+        &self,
+        agent_id: &str,
+        measurement_id: Option<&str>,
+        dst_addr: Option<IpAddr>,
+    ) -> Option<String> {
+        match self {
+            KeyStrategy::None => None,
+            KeyStrategy::AgentId => Some(agent_id.to_string()),
+            KeyStrategy::MeasurementId => measurement_id.map(str::to_string),
+            KeyStrategy::ProbeDstPrefix => {
+                let dst_addr = dst_addr?;
+                let prefix_len = match dst_addr {
+                    IpAddr::V4(_) => KEY_IPV4_PREFIX_LEN,
+                    IpAddr::V6(_) => KEY_IPV6_PREFIX_LEN,
+                };
+                IpNet::new(dst_addr, prefix_len)
+                    .ok()
+                    .map(|prefix| prefix.trunc().to_string())
+            }
+        }
+    }
+}
+
+/// Placeholder for the agent ID in `in_topic_template`.
+pub const AGENT_PLACEHOLDER: &str = "{agent}";
+
+impl KafkaConfig {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(template) = &self.in_topic_template {
+            if !template.contains(AGENT_PLACEHOLDER) {
+                anyhow::bail!(
+                    "kafka.in_topic_template '{}' must contain {}",
+                    template,
+                    AGENT_PLACEHOLDER
+                );
+            }
+        }
+        if !self.in_topic_priorities.is_empty() {
+            if self.in_topic_template.is_some() {
+                anyhow::bail!(
+                    "kafka.in_topic_priorities is not supported with kafka.in_topic_template"
+                );
+            }
+            let in_topics: Vec<&str> = self.in_topics.split(',').collect();
+            if let Some(topic) = self
+                .in_topic_priorities
+                .keys()
+                .find(|topic| !in_topics.contains(&topic.as_str()))
+            {
+                anyhow::bail!(
+                    "kafka.in_topic_priorities topic '{}' is not one of kafka.in_topics",
+                    topic
+                );
+            }
+        }
+        if let Some(reset) = &self.in_auto_offset_reset {
+            if !KAFKA_AUTO_OFFSET_RESET_VALUES.contains(&reset.as_str()) {
+                anyhow::bail!(
+                    "Invalid kafka.in_auto_offset_reset '{}'. Expected one of: {}",
+                    reset,
+                    KAFKA_AUTO_OFFSET_RESET_VALUES.join(", ")
+                );
+            }
+        }
+        let (pause, resume) = (self.pause_watermark(), self.resume_watermark());
+        if !(resume > 0.0 && resume < pause && pause <= 1.0) {
+            anyhow::bail!(
+                "Invalid kafka.in_pause_watermark {} or kafka.in_resume_watermark {}. Expected 0 < resume < pause <= 1",
+                pause,
+                resume
+            );
+        }
+        crate::agent::commit::CommitStrategy::parse(&self.commit_strategy)?;
+        crate::reply::ReplyFormat::parse(&self.out_format)?;
+        KeyStrategy::parse(&self.key_strategy)?;
+        if let Some(compression) = &self.compression {
+            if !KAFKA_COMPRESSION_VALUES.contains(&compression.as_str()) {
+                anyhow::bail!(
+                    "Invalid kafka.compression '{}'. Expected one of: {}",
+                    compression,
+                    KAFKA_COMPRESSION_VALUES.join(", ")
+                );
+            }
+        }
+        match (&self.signing_key_id, &self.signing_key) {
+            (Some(_), Some(key)) if key.is_empty() => {
+                anyhow::bail!("kafka.signing_key must not be empty")
+            }
+            (Some(_), None) | (None, Some(_)) => {
+                anyhow::bail!("kafka.signing_key_id and kafka.signing_key must be set together")
+            }
+            _ => {}
+        }
+        if let Some(registry) = &self.schema_registry {
+            if registry.url.is_empty() {
+                anyhow::bail!("kafka.schema_registry.url must not be empty");
+            }
+            if registry.username.is_some() != registry.password.is_some() {
+                anyhow::bail!(
+                    "kafka.schema_registry.username and kafka.schema_registry.password must be set together"
+                );
+            }
+        }
+        if self.properties.keys().any(|key| key.trim().is_empty()) {
+            anyhow::bail!("kafka.properties keys must not be empty");
+        }
+        if let Some(out) = &self.out {
+            if out.brokers.trim().is_empty() {
+                anyhow::bail!("kafka.out.brokers must not be empty");
+            }
+            out.auth()?;
+            if out.properties.keys().any(|key| key.trim().is_empty()) {
+                anyhow::bail!("kafka.out.properties keys must not be empty");
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the `properties` on a consumer or producer configuration, overriding
+    /// the settings derived from the other options.
+    pub fn apply_properties(&self, client_config: &mut ClientConfig) {
+        for (key, value) in &self.properties {
+            client_config.set(key, value);
+        }
+    }
+
+    pub fn auto_offset_reset(&self) -> &str {
+        self.in_auto_offset_reset
+            .as_deref()
+            .unwrap_or(DEFAULT_KAFKA_IN_AUTO_OFFSET_RESET)
+    }
+
+    pub fn session_timeout_ms(&self) -> u32 {
+        self.in_session_timeout_ms
+            .unwrap_or(DEFAULT_KAFKA_IN_SESSION_TIMEOUT_MS)
+    }
+
+    pub fn pause_watermark(&self) -> f64 {
+        self.in_pause_watermark
+            .unwrap_or(DEFAULT_KAFKA_IN_PAUSE_WATERMARK)
+    }
+
+    pub fn resume_watermark(&self) -> f64 {
+        self.in_resume_watermark
+            .unwrap_or(DEFAULT_KAFKA_IN_RESUME_WATERMARK)
+    }
+
+    /// Topics the agent consumes probes from: its own topic with a topic template,
+    /// `in_topics` otherwise.
+    pub fn agent_in_topics(&self, agent_id: &str) -> Vec<String> {
+        match &self.in_topic_template {
+            Some(template) => vec![template.replace(AGENT_PLACEHOLDER, agent_id)],
+            None => self.in_topics.split(',').map(|t| t.to_string()).collect(),
+        }
+    }
+
+    /// Topic the client produces the probes of an agent to.
+    pub fn client_in_topic(&self, agent_id: &str) -> String {
+        match &self.in_topic_template {
+            Some(template) => template.replace(AGENT_PLACEHOLDER, agent_id),
+            None => self
+                .in_topics
+                .split(',')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
 }
 
 // --- Default value functions ---
@@ -72,6 +428,10 @@ fn default_kafka_in_group_id() -> String {
     DEFAULT_KAFKA_IN_GROUP_ID.to_string()
 }
 
+fn default_kafka_commit_strategy() -> String {
+    DEFAULT_KAFKA_COMMIT_STRATEGY.to_string()
+}
+
 fn default_kafka_out_enable() -> bool {
     true
 }
@@ -80,6 +440,14 @@ fn default_kafka_out_topic() -> String {
     DEFAULT_KAFKA_OUT_TOPIC.to_string()
 }
 
+fn default_kafka_out_format() -> String {
+    DEFAULT_KAFKA_OUT_FORMAT.to_string()
+}
+
+fn default_kafka_key_strategy() -> String {
+    DEFAULT_KAFKA_KEY_STRATEGY.to_string()
+}
+
 fn default_kafka_out_batch_wait_time() -> u64 {
     DEFAULT_KAFKA_OUT_BATCH_WAIT_TIME
 }
@@ -87,3 +455,19 @@ fn default_kafka_out_batch_wait_time() -> u64 {
 fn default_kafka_out_batch_wait_interval() -> u64 {
     DEFAULT_KAFKA_OUT_BATCH_WAIT_INTERVAL
 }
+
+fn default_kafka_spoof_sample_every() -> u64 {
+    DEFAULT_KAFKA_SPOOF_SAMPLE_EVERY
+}
+
+fn default_kafka_events_max_rate() -> u64 {
+    DEFAULT_KAFKA_EVENTS_MAX_RATE
+}
+
+fn default_kafka_agents_heartbeat_interval() -> u64 {
+    DEFAULT_KAFKA_AGENTS_HEARTBEAT_INTERVAL
+}
+
+fn default_schema_registry_schema_type() -> String {
+    DEFAULT_SCHEMA_REGISTRY_SCHEMA_TYPE.to_string()
+}
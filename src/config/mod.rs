@@ -2,17 +2,23 @@ pub mod agent;
 pub mod caracat;
 pub mod client;
 pub mod kafka;
+pub mod s3;
 
 use anyhow::Result;
 use config::Config;
 use ipnet::{Ipv4Net, Ipv6Net};
 use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use tokio::net::lookup_host;
 
+use crate::agent::ports::PortPolicy;
+use crate::logging::LogFormat;
+
 pub use agent::{AgentConfig, RawAgentConfig};
-pub use caracat::CaracatConfig;
-pub use client::{parse_and_validate_client_args, ClientConfig};
-pub use kafka::KafkaConfig;
+pub use caracat::{CaracatConfig, SourceRateLimit};
+pub use client::{parse_and_validate_client_args, ClientConfig, Distribution, ProbesFormat};
+pub use kafka::{KafkaClusterConfig, KafkaConfig, KeyStrategy, SchemaRegistryConfig};
+pub use s3::S3Config;
 
 // --- IP prefix validation utilities ---
 pub fn validate_ip_against_prefixes(
@@ -77,6 +83,16 @@ fn load_config_source(config_path: &str) -> Result<Config> {
         .map_err(Into::into)
 }
 
+/// Logs format of the agent (`agent.log_format`), read before the rest of the configuration
+/// so that its logs are formatted too.
+pub fn log_format(config_path: &str) -> Result<LogFormat> {
+    match load_config_source(config_path)?.get("agent.log_format") {
+        Ok(log_format) => Ok(log_format),
+        Err(config::ConfigError::NotFound(_)) => Ok(LogFormat::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
 pub async fn resolve_address(address: String) -> Result<SocketAddr> {
     match lookup_host(&address).await?.next() {
         Some(addr) => Ok(addr),
@@ -85,6 +101,16 @@ pub async fn resolve_address(address: String) -> Result<SocketAddr> {
 }
 
 // --- Gateway config (shared between agent and potentially client) ---
+const DEFAULT_GATEWAY_POLL_INTERVAL: u64 = 10;
+const DEFAULT_GATEWAY_UPLOAD_INTERVAL: u64 = 10;
+const DEFAULT_GATEWAY_UPLOAD_CHUNK_BYTES: usize = 4 * 1024 * 1024;
+const DEFAULT_GATEWAY_UPLOAD_MAX_PENDING_CHUNKS: usize = 100;
+const DEFAULT_GATEWAY_COMMANDS_WAIT: u64 = 30;
+const DEFAULT_GATEWAY_HEALTHCHECK_INTERVAL: u64 = 30;
+const DEFAULT_GATEWAY_REGISTRATION_RETRY_BACKOFF: u64 = 5;
+const DEFAULT_GATEWAY_REGISTRATION_RETRY_MAX_BACKOFF: u64 = 300;
+const DEFAULT_GATEWAY_HEALTHCHECK_JITTER: f64 = 0.1;
+
 #[derive(Debug, Clone, serde::Deserialize, Default)]
 pub struct GatewayConfig {
     #[serde(default)]
@@ -93,6 +119,102 @@ pub struct GatewayConfig {
     pub agent_key: Option<String>,
     #[serde(default)]
     pub agent_secret: Option<String>,
+    // Pull the assigned measurements from the gateway instead of consuming probes from Kafka
+    #[serde(default)]
+    pub poll_measurements: bool,
+    // Seconds between two polls of the assigned measurements
+    #[serde(default = "default_gateway_poll_interval")]
+    pub poll_interval: u64,
+    // Upload the replies to the gateway, in compressed chunks
+    #[serde(default)]
+    pub upload_replies: bool,
+    // Maximum time (in seconds) before the buffered replies are uploaded
+    #[serde(default = "default_gateway_upload_interval")]
+    pub upload_interval: u64,
+    // Maximum size of the uncompressed replies of a chunk
+    #[serde(default = "default_gateway_upload_chunk_bytes")]
+    pub upload_chunk_bytes: usize,
+    // Chunks kept for retry while uploads fail, the oldest are dropped beyond
+    #[serde(default = "default_gateway_upload_max_pending_chunks")]
+    pub upload_max_pending_chunks: usize,
+    // Directory where the chunks are kept until uploaded, to resume the uploads after a restart
+    #[serde(default)]
+    pub upload_spool_dir: Option<PathBuf>,
+    // Proxy of the requests to the gateway (the probes are still sent from the local
+    // interfaces): "http://", "https://", "socks5://" or "socks5h://" URL
+    #[serde(default)]
+    pub proxy: Option<String>,
+    #[serde(default)]
+    pub proxy_username: Option<String>,
+    #[serde(default)]
+    pub proxy_password: Option<String>,
+    // PEM bundle of the CA certificates trusted for the gateway, in addition to the system roots
+    #[serde(default)]
+    pub tls_ca_cert: Option<PathBuf>,
+    // PEM client certificate (chain) and private key, for a gateway requiring mutual TLS
+    #[serde(default)]
+    pub tls_cert: Option<PathBuf>,
+    #[serde(default)]
+    pub tls_key: Option<PathBuf>,
+    // Pull the caracat instances from the gateway at startup, instead of the `caracat`
+    // section, which is used if they cannot be fetched
+    #[serde(default)]
+    pub fetch_caracat_config: bool,
+    // Receive the commands of the gateway (pause, resume, drain, set_rate,
+    // cancel_measurement), long polling for up to `commands_wait` seconds
+    #[serde(default)]
+    pub remote_commands: bool,
+    #[serde(default = "default_gateway_commands_wait")]
+    pub commands_wait: u64,
+    // Seconds between two healthchecks
+    #[serde(default = "default_gateway_healthcheck_interval")]
+    pub healthcheck_interval: u64,
+    // Seconds before retrying a failed registration or healthcheck, doubled on each
+    // consecutive failure up to `registration_retry_max_backoff`
+    #[serde(default = "default_gateway_registration_retry_backoff")]
+    pub registration_retry_backoff: u64,
+    #[serde(default = "default_gateway_registration_retry_max_backoff")]
+    pub registration_retry_max_backoff: u64,
+    // Fraction by which the delays are randomly shortened or lengthened, so that a fleet
+    // of agents restarted together doesn't hit the gateway at the same time
+    #[serde(default = "default_gateway_healthcheck_jitter")]
+    pub healthcheck_jitter: f64,
+}
+
+fn default_gateway_poll_interval() -> u64 {
+    DEFAULT_GATEWAY_POLL_INTERVAL
+}
+
+fn default_gateway_upload_interval() -> u64 {
+    DEFAULT_GATEWAY_UPLOAD_INTERVAL
+}
+
+fn default_gateway_upload_chunk_bytes() -> usize {
+    DEFAULT_GATEWAY_UPLOAD_CHUNK_BYTES
+}
+
+fn default_gateway_upload_max_pending_chunks() -> usize {
+    DEFAULT_GATEWAY_UPLOAD_MAX_PENDING_CHUNKS
+}
+
+fn default_gateway_commands_wait() -> u64 {
+    DEFAULT_GATEWAY_COMMANDS_WAIT
+}
+
+fn default_gateway_healthcheck_interval() -> u64 {
+    DEFAULT_GATEWAY_HEALTHCHECK_INTERVAL
+}
+
+fn default_gateway_registration_retry_backoff() -> u64 {
+    DEFAULT_GATEWAY_REGISTRATION_RETRY_BACKOFF
+}
+
+fn default_gateway_registration_retry_max_backoff() -> u64 {
+    DEFAULT_GATEWAY_REGISTRATION_RETRY_MAX_BACKOFF
+}
+
+fn default_gateway_healthcheck_jitter() -> f64 {
+    DEFAULT_GATEWAY_HEALTHCHECK_JITTER
 }
 
 // --- Main app config structure ---
@@ -106,6 +228,8 @@ pub struct RawAppConfig {
     caracat: Vec<CaracatConfig>,
     #[serde(default)]
     kafka: KafkaConfig,
+    #[serde(default)]
+    s3: Option<S3Config>,
 }
 
 #[derive(Debug, Clone)]
@@ -114,6 +238,28 @@ pub struct AppConfig {
     pub gateway: Option<GatewayConfig>,
     pub caracat: Vec<CaracatConfig>,
     pub kafka: KafkaConfig,
+    pub s3: Option<S3Config>,
+}
+
+/// Validate and normalize the caracat instances of an agent.
+pub fn validate_caracat_configs(
+    caracat_configs: &mut [CaracatConfig],
+    agent_id: &str,
+    integrity_key: Option<&str>,
+) -> Result<()> {
+    for cfg in caracat_configs.iter_mut() {
+        cfg.validate_and_normalize();
+        crate::agent::integrity::encoding_id(cfg, agent_id, integrity_key)?;
+        crate::agent::reply_filter::ReplyFilter::new(cfg)?;
+        crate::agent::packet_ring::ReceiverBackend::new(cfg)?;
+        crate::agent::tx_ring::SenderBackend::new(cfg)?;
+        crate::agent::policy::ProbePolicy::new(cfg)?;
+        crate::agent::ratelimit::SourceRateLimiter::new(cfg)?;
+        crate::agent::fairness::FairnessScheduler::new(cfg)?;
+        crate::agent::scheduling::MeasurementQueues::for_instance(cfg)?;
+    }
+    crate::agent::failover::validate_backups(caracat_configs)?;
+    Ok(())
 }
 
 // --- Main app config loading ---
@@ -132,9 +278,52 @@ pub async fn app_config(config_path: &str) -> Result<AppConfig> {
         raw_config.caracat
     };
 
-    // Validate CaracatConfig fields for each caracat config
-    for cfg in &mut caracat_configs {
-        cfg.validate_and_normalize();
+    validate_caracat_configs(
+        &mut caracat_configs,
+        &raw_config.agent.id,
+        raw_config.agent.integrity_key.as_deref(),
+    )?;
+    if raw_config.agent.max_total_pps == Some(0) {
+        anyhow::bail!("Invalid agent.max_total_pps. Expected > 0");
+    }
+    if raw_config.agent.max_dst_prefix_pps == Some(0) {
+        anyhow::bail!("Invalid agent.max_dst_prefix_pps. Expected > 0");
+    }
+    if raw_config.agent.max_probes_per_measurement == Some(0) {
+        anyhow::bail!("Invalid agent.max_probes_per_measurement. Expected > 0");
+    }
+    if raw_config.agent.dedup_window == Some(0) {
+        anyhow::bail!("Invalid agent.dedup_window. Expected > 0");
+    }
+    if raw_config.agent.audit_log_max_bytes == 0 {
+        anyhow::bail!("Invalid agent.audit_log_max_bytes. Expected > 0");
+    }
+    let src_ports = raw_config
+        .agent
+        .src_ports
+        .as_deref()
+        .map(str::parse::<PortPolicy>)
+        .transpose()
+        .map_err(|e| e.context("Invalid agent.src_ports"))?;
+
+    crate::signing::SignatureVerifier::new(&raw_config.agent.signing_keys)?;
+    if raw_config.agent.standby {
+        if raw_config.kafka.agents_topic.is_none() {
+            anyhow::bail!("agent.standby requires kafka.agents_topic to be set");
+        }
+        if raw_config
+            .gateway
+            .as_ref()
+            .is_some_and(|gateway| gateway.poll_measurements)
+        {
+            anyhow::bail!("agent.standby is not supported with gateway.poll_measurements");
+        }
+    }
+
+    raw_config.kafka.validate()?;
+    if let Some(gateway) = &raw_config.gateway {
+        crate::agent::gateway::http_client(gateway)?;
+        crate::agent::gateway::HealthcheckSchedule::new(gateway)?;
     }
 
     let gateway = raw_config.gateway;
@@ -142,10 +331,28 @@ pub async fn app_config(config_path: &str) -> Result<AppConfig> {
     Ok(AppConfig {
         agent: AgentConfig {
             id: raw_config.agent.id,
+            id_file: raw_config.agent.id_file,
             metrics_address: resolved_metrics_address,
+            integrity_key: raw_config.agent.integrity_key,
+            http_auth_tokens: raw_config.agent.http_auth_tokens,
+            refuse_duplicate_id: raw_config.agent.refuse_duplicate_id,
+            max_total_pps: raw_config.agent.max_total_pps,
+            max_dst_prefix_pps: raw_config.agent.max_dst_prefix_pps,
+            max_probes_per_measurement: raw_config.agent.max_probes_per_measurement,
+            signing_keys: raw_config.agent.signing_keys,
+            standby: raw_config.agent.standby,
+            statistics_interval: raw_config.agent.statistics_interval,
+            measurement_labels_limit: raw_config.agent.measurement_labels_limit,
+            blocklist_file: raw_config.agent.blocklist_file,
+            dedup_window: raw_config.agent.dedup_window,
+            audit_log_dir: raw_config.agent.audit_log_dir,
+            audit_log_max_bytes: raw_config.agent.audit_log_max_bytes,
+            audit_log_max_files: raw_config.agent.audit_log_max_files,
+            src_ports,
         },
         gateway,
         caracat: caracat_configs,
         kafka: raw_config.kafka,
+        s3: raw_config.s3,
     })
 }
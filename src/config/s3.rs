@@ -0,0 +1,58 @@
+// --- Constants ---
+const DEFAULT_S3_REGION: &str = "us-east-1";
+const DEFAULT_S3_PREFIX: &str = "saimiris/";
+const DEFAULT_S3_ROTATION_INTERVAL: u64 = 300;
+const DEFAULT_S3_MAX_OBJECT_BYTES: usize = 64 * 1024 * 1024;
+const DEFAULT_S3_MAX_PENDING_OBJECTS: usize = 100;
+
+/// S3-compatible object storage (AWS S3, MinIO, ...) receiving the replies.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct S3Config {
+    // e.g. "https://s3.us-east-1.amazonaws.com" or "http://minio:9000"
+    pub endpoint: String,
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    pub bucket: String,
+    // Prefix of the object keys
+    #[serde(default = "default_s3_prefix")]
+    pub prefix: String,
+    pub access_key: String,
+    pub secret_key: String,
+    // Address the bucket in the path rather than in the host name (required by MinIO)
+    #[serde(default = "default_s3_path_style")]
+    pub path_style: bool,
+    // Maximum time (in seconds) before the buffered replies are uploaded
+    #[serde(default = "default_s3_rotation_interval")]
+    pub rotation_interval: u64,
+    // Maximum size of an object, replies are uploaded earlier once reached
+    #[serde(default = "default_s3_max_object_bytes")]
+    pub max_object_bytes: usize,
+    // Objects kept for retry while uploads fail, the oldest are dropped beyond
+    #[serde(default = "default_s3_max_pending_objects")]
+    pub max_pending_objects: usize,
+}
+
+// --- Default value functions ---
+fn default_s3_region() -> String {
+    DEFAULT_S3_REGION.to_string()
+}
+
+fn default_s3_prefix() -> String {
+    DEFAULT_S3_PREFIX.to_string()
+}
+
+fn default_s3_path_style() -> bool {
+    true
+}
+
+fn default_s3_rotation_interval() -> u64 {
+    DEFAULT_S3_ROTATION_INTERVAL
+}
+
+fn default_s3_max_object_bytes() -> usize {
+    DEFAULT_S3_MAX_OBJECT_BYTES
+}
+
+fn default_s3_max_pending_objects() -> usize {
+    DEFAULT_S3_MAX_PENDING_OBJECTS
+}
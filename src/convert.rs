@@ -0,0 +1,316 @@
+use anyhow::{Context, Result};
+use std::io::{stdin, stdout, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::reply::{ReplyReader, ReplyRecord};
+
+/// Replies per row group of the Parquet output.
+#[cfg(feature = "parquet")]
+const ROW_GROUP_REPLIES: usize = 64 * 1024;
+
+/// Format of the converted replies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConvertFormat {
+    /// CSV, with a header
+    Csv,
+    /// One JSON object per line, as printed by `saimiris inspect replies`
+    Jsonl,
+    /// Parquet, with a row group per 65536 replies
+    Parquet,
+}
+
+/// A column of the CSV and Parquet outputs, with its type.
+pub enum ReplyColumn {
+    UInt8(fn(&ReplyRecord) -> u8),
+    UInt16(fn(&ReplyRecord) -> u16),
+    UInt32(fn(&ReplyRecord) -> u32),
+    UInt64(fn(&ReplyRecord) -> u64),
+    String(fn(&ReplyRecord) -> String),
+}
+
+impl ReplyColumn {
+    fn value(&self, reply: &ReplyRecord) -> String {
+        match self {
+            ReplyColumn::UInt8(value) => value(reply).to_string(),
+            ReplyColumn::UInt16(value) => value(reply).to_string(),
+            ReplyColumn::UInt32(value) => value(reply).to_string(),
+            ReplyColumn::UInt64(value) => value(reply).to_string(),
+            ReplyColumn::String(value) => value(reply),
+        }
+    }
+}
+
+/// Columns of the replies in the CSV and Parquet outputs, with the field names of the capnp
+/// schema. The MPLS labels, the ICMP extension objects and the fields of newer agents are
+/// JSON-encoded, empty if none.
+pub fn reply_columns() -> Vec<(&'static str, ReplyColumn)> {
+    use ReplyColumn::{String as Str, UInt16, UInt32, UInt64, UInt8};
+    vec![
+        ("time_received_ns", UInt64(|r| r.time_received_ns)),
+        ("agent_id", Str(|r| r.agent_id.clone())),
+        ("reply_src_addr", Str(|r| r.reply_src_addr.to_string())),
+        ("reply_dst_addr", Str(|r| r.reply_dst_addr.to_string())),
+        ("reply_id", UInt16(|r| r.reply_id)),
+        ("reply_size", UInt16(|r| r.reply_size)),
+        ("reply_ttl", UInt8(|r| r.reply_ttl)),
+        ("reply_quoted_ttl", UInt8(|r| r.reply_quoted_ttl)),
+        ("reply_protocol", UInt8(|r| r.reply_protocol)),
+        ("reply_icmp_type", UInt8(|r| r.reply_icmp_type)),
+        ("reply_icmp_code", UInt8(|r| r.reply_icmp_code)),
+        (
+            "reply_mpls_labels",
+            Str(|r| {
+                if r.reply_mpls_labels.is_empty() {
+                    String::new()
+                } else {
+                    serde_json::to_string(&r.reply_mpls_labels).unwrap_or_default()
+                }
+            }),
+        ),
+        ("probe_src_addr", Str(|r| r.probe_src_addr.to_string())),
+        ("probe_dst_addr", Str(|r| r.probe_dst_addr.to_string())),
+        ("probe_id", UInt16(|r| r.probe_id)),
+        ("probe_size", UInt16(|r| r.probe_size)),
+        ("probe_ttl", UInt8(|r| r.probe_ttl)),
+        ("probe_protocol", UInt8(|r| r.probe_protocol)),
+        ("probe_src_port", UInt16(|r| r.probe_src_port)),
+        ("probe_dst_port", UInt16(|r| r.probe_dst_port)),
+        ("rtt", UInt16(|r| r.rtt)),
+        ("round", UInt32(|r| r.round)),
+        ("measurement_id", Str(|r| r.measurement_id.clone())),
+        ("instance_id", UInt16(|r| r.instance_id)),
+        (
+            "reply_icmp_extensions",
+            Str(|r| {
+                if r.reply_icmp_extensions.is_empty() {
+                    String::new()
+                } else {
+                    serde_json::to_string(&r.reply_icmp_extensions).unwrap_or_default()
+                }
+            }),
+        ),
+        (
+            "extensions",
+            Str(|r| {
+                r.extensions
+                    .as_ref()
+                    .map(|extensions| serde_json::to_string(extensions).unwrap_or_default())
+                    .unwrap_or_default()
+            }),
+        ),
+    ]
+}
+
+/// Values of the columns of a reply, in the order of `reply_columns`.
+pub fn reply_row(columns: &[(&'static str, ReplyColumn)], reply: &ReplyRecord) -> Vec<String> {
+    columns
+        .iter()
+        .map(|(_, column)| column.value(reply))
+        .collect()
+}
+
+/// Writes the replies in the chosen format.
+enum ReplyWriter<W: Write + Send> {
+    Csv(csv::Writer<W>, Vec<(&'static str, ReplyColumn)>),
+    Jsonl(W),
+    #[cfg(feature = "parquet")]
+    Parquet(parquet_output::ParquetWriter<W>),
+}
+
+impl<W: Write + Send> ReplyWriter<W> {
+    fn new(format: ConvertFormat, out: W) -> Result<Self> {
+        match format {
+            ConvertFormat::Csv => {
+                let columns = reply_columns();
+                let mut writer = csv::Writer::from_writer(out);
+                writer.write_record(columns.iter().map(|(name, _)| name))?;
+                Ok(ReplyWriter::Csv(writer, columns))
+            }
+            ConvertFormat::Jsonl => Ok(ReplyWriter::Jsonl(out)),
+            #[cfg(feature = "parquet")]
+            ConvertFormat::Parquet => Ok(ReplyWriter::Parquet(parquet_output::ParquetWriter::new(
+                out,
+            )?)),
+            #[cfg(not(feature = "parquet"))]
+            ConvertFormat::Parquet => {
+                anyhow::bail!(
+                    "Parquet output requires saimiris to be built with the `parquet` feature"
+                )
+            }
+        }
+    }
+
+    fn write(&mut self, reply: ReplyRecord) -> Result<()> {
+        match self {
+            ReplyWriter::Csv(writer, columns) => writer.write_record(reply_row(columns, &reply))?,
+            ReplyWriter::Jsonl(out) => {
+                serde_json::to_writer(&mut *out, &reply)?;
+                out.write_all(b"\n")?;
+            }
+            #[cfg(feature = "parquet")]
+            ReplyWriter::Parquet(writer) => writer.write(reply)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            ReplyWriter::Csv(mut writer, _) => writer.flush()?,
+            ReplyWriter::Jsonl(mut out) => out.flush()?,
+            #[cfg(feature = "parquet")]
+            ReplyWriter::Parquet(writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_output {
+    use anyhow::Result;
+    use parquet::data_type::{ByteArray, ByteArrayType, Int32Type, Int64Type};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use super::{reply_columns, ReplyColumn, ROW_GROUP_REPLIES};
+    use crate::reply::ReplyRecord;
+
+    /// Writes the replies buffered up to a row group at once.
+    pub struct ParquetWriter<W: Write + Send> {
+        writer: SerializedFileWriter<W>,
+        columns: Vec<(&'static str, ReplyColumn)>,
+        replies: Vec<ReplyRecord>,
+    }
+
+    /// Parquet schema of the columns, unsigned integers being annotated as such.
+    fn schema(columns: &[(&'static str, ReplyColumn)]) -> String {
+        let fields: Vec<String> = columns
+            .iter()
+            .map(|(name, column)| match column {
+                ReplyColumn::UInt8(_) => format!("required int32 {} (INTEGER(8,false));", name),
+                ReplyColumn::UInt16(_) => format!("required int32 {} (INTEGER(16,false));", name),
+                ReplyColumn::UInt32(_) => format!("required int32 {} (INTEGER(32,false));", name),
+                ReplyColumn::UInt64(_) => format!("required int64 {} (INTEGER(64,false));", name),
+                ReplyColumn::String(_) => format!("required binary {} (STRING);", name),
+            })
+            .collect();
+        format!("message reply {{ {} }}", fields.join(" "))
+    }
+
+    impl<W: Write + Send> ParquetWriter<W> {
+        pub fn new(out: W) -> Result<Self> {
+            let columns = reply_columns();
+            let schema = Arc::new(parse_message_type(&schema(&columns))?);
+            let properties = Arc::new(WriterProperties::builder().build());
+            Ok(ParquetWriter {
+                writer: SerializedFileWriter::new(out, schema, properties)?,
+                columns,
+                replies: Vec::with_capacity(ROW_GROUP_REPLIES),
+            })
+        }
+
+        pub fn write(&mut self, reply: ReplyRecord) -> Result<()> {
+            self.replies.push(reply);
+            if self.replies.len() >= ROW_GROUP_REPLIES {
+                self.write_row_group()?;
+            }
+            Ok(())
+        }
+
+        fn write_row_group(&mut self) -> Result<()> {
+            let replies = &self.replies;
+            let mut row_group = self.writer.next_row_group()?;
+            for (_, column) in &self.columns {
+                let Some(mut writer) = row_group.next_column()? else {
+                    break;
+                };
+                // Unsigned integers are stored as signed ones of the same size, the schema
+                // annotates them as unsigned
+                let int32 = |value: &dyn Fn(&ReplyRecord) -> i32| -> Vec<i32> {
+                    replies.iter().map(value).collect()
+                };
+                match column {
+                    ReplyColumn::UInt8(value) => writer.typed::<Int32Type>().write_batch(
+                        &int32(&|r| value(r).into()),
+                        None,
+                        None,
+                    )?,
+                    ReplyColumn::UInt16(value) => writer.typed::<Int32Type>().write_batch(
+                        &int32(&|r| value(r).into()),
+                        None,
+                        None,
+                    )?,
+                    ReplyColumn::UInt32(value) => writer.typed::<Int32Type>().write_batch(
+                        &int32(&|r| value(r) as i32),
+                        None,
+                        None,
+                    )?,
+                    ReplyColumn::UInt64(value) => {
+                        let values: Vec<i64> = replies.iter().map(|r| value(r) as i64).collect();
+                        writer
+                            .typed::<Int64Type>()
+                            .write_batch(&values, None, None)?
+                    }
+                    ReplyColumn::String(value) => {
+                        let values: Vec<ByteArray> = replies
+                            .iter()
+                            .map(|r| ByteArray::from(value(r).into_bytes()))
+                            .collect();
+                        writer
+                            .typed::<ByteArrayType>()
+                            .write_batch(&values, None, None)?
+                    }
+                };
+                writer.close()?;
+            }
+            row_group.close()?;
+            self.replies.clear();
+            Ok(())
+        }
+
+        pub fn finish(mut self) -> Result<()> {
+            if !self.replies.is_empty() {
+                self.write_row_group()?;
+            }
+            self.writer.close()?;
+            Ok(())
+        }
+    }
+}
+
+/// Convert the replies of capnp streams (files, or stdin if none), such as dumps of the
+/// replies topic, to `format`, written to `output` (stdout if not provided).
+pub fn handle(inputs: &[PathBuf], output: Option<&Path>, format: ConvertFormat) -> Result<()> {
+    let out: Box<dyn Write + Send> = match output {
+        Some(output) => Box::new(
+            std::fs::File::create(output)
+                .with_context(|| format!("Failed to create {}", output.display()))?,
+        ),
+        None => Box::new(stdout()),
+    };
+    let mut writer = ReplyWriter::new(format, BufWriter::new(out))?;
+
+    let mut converted = 0;
+    if inputs.is_empty() {
+        for reply in ReplyReader::new(stdin().lock()) {
+            writer.write(reply?)?;
+            converted += 1;
+        }
+    }
+    for input in inputs {
+        let file = std::fs::File::open(input)
+            .with_context(|| format!("Failed to open {}", input.display()))?;
+        for reply in ReplyReader::new(BufReader::new(file)) {
+            let reply = reply.with_context(|| format!("Failed to convert {}", input.display()))?;
+            writer.write(reply)?;
+            converted += 1;
+        }
+    }
+    writer.finish()?;
+
+    info!("replies={}", converted);
+    Ok(())
+}
@@ -0,0 +1,174 @@
+//! ICMP extension objects (RFC 4884) appended by the routers to their Time Exceeded and
+//! Destination Unreachable messages, such as MPLS label stacks (RFC 4950) or interface
+//! information (RFC 5837). caracat does not parse them, the agent reads them from the
+//! captured packets.
+use caracat::models::MPLSLabel;
+use pcap::Linktype;
+
+/// Class of the MPLS label stack objects (RFC 4950).
+pub const MPLS_LABEL_STACK_CLASS: u8 = 1;
+/// Class of the interface information objects (RFC 5837).
+pub const INTERFACE_INFORMATION_CLASS: u8 = 2;
+
+/// Version of the extension structure.
+const EXTENSION_VERSION: u8 = 2;
+/// Original datagram quoted by the routers predating RFC 4884, which append the extension
+/// structure without setting the length of the datagram.
+const NON_COMPLIANT_DATAGRAM_LEN: usize = 128;
+
+/// An ICMP extension object, with its payload as received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IcmpExtension {
+    pub class_num: u8,
+    pub c_type: u8,
+    pub payload: Vec<u8>,
+}
+
+impl IcmpExtension {
+    pub fn is_mpls_label_stack(&self) -> bool {
+        self.class_num == MPLS_LABEL_STACK_CLASS && self.c_type == 1
+    }
+
+    /// Object as received, with its header.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let len = (4 + self.payload.len()) as u16;
+        let mut bytes = Vec::with_capacity(len as usize);
+        bytes.extend_from_slice(&len.to_be_bytes());
+        bytes.push(self.class_num);
+        bytes.push(self.c_type);
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
+    /// Object from its bytes, with its header, if its length is consistent.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let len = u16::from_be_bytes([*bytes.first()?, *bytes.get(1)?]) as usize;
+        if len < 4 || len != bytes.len() {
+            return None;
+        }
+        Some(IcmpExtension {
+            class_num: bytes[2],
+            c_type: bytes[3],
+            payload: bytes[4..].to_vec(),
+        })
+    }
+
+    /// Labels of an MPLS label stack object, empty for the other objects.
+    pub fn mpls_labels(&self) -> Vec<MPLSLabel> {
+        if !self.is_mpls_label_stack() {
+            return vec![];
+        }
+        self.payload
+            .chunks_exact(4)
+            .map(|entry| {
+                let entry = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]);
+                MPLSLabel {
+                    label: entry >> 12,
+                    experimental: ((entry >> 9) & 0x7) as u8,
+                    bottom_of_stack: (entry >> 8) & 0x1 == 1,
+                    ttl: entry as u8,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Extension objects of a captured packet, empty if it is not an ICMP Time Exceeded or
+/// Destination Unreachable message with extensions.
+pub fn parse_packet(data: &[u8], linktype: Linktype) -> Vec<IcmpExtension> {
+    let ip = match linktype {
+        Linktype::ETHERNET => data.get(14..),
+        Linktype::NULL => data.get(4..),
+        _ => None,
+    };
+    ip.map(parse_ip).unwrap_or_default()
+}
+
+/// Extension objects of an IPv4 or IPv6 packet, as `parse_packet`.
+pub fn parse_ip(ip: &[u8]) -> Vec<IcmpExtension> {
+    let Some(&first) = ip.first() else {
+        return vec![];
+    };
+    // ICMP message, the length of its original datagram in words (of 4 bytes in ICMPv4, of
+    // 8 bytes in ICMPv6), and the offset of this length in the ICMP header
+    let (icmp, word_len, length_offset) = match first >> 4 {
+        4 => {
+            let header_len = (first & 0x0f) as usize * 4;
+            let total_len = match ip.get(2..4) {
+                Some(len) => u16::from_be_bytes([len[0], len[1]]) as usize,
+                None => return vec![],
+            };
+            if ip.get(9) != Some(&1) {
+                return vec![];
+            }
+            // The Ethernet padding is not part of the message
+            match ip.get(header_len..total_len.min(ip.len())) {
+                Some(icmp) if matches!(icmp.first(), Some(3 | 11)) => (icmp, 4, 5),
+                _ => return vec![],
+            }
+        }
+        6 => {
+            let payload_len = match ip.get(4..6) {
+                Some(len) => u16::from_be_bytes([len[0], len[1]]) as usize,
+                None => return vec![],
+            };
+            if ip.get(6) != Some(&58) {
+                return vec![];
+            }
+            match ip.get(40..(40 + payload_len).min(ip.len())) {
+                Some(icmp) if matches!(icmp.first(), Some(1 | 3)) => (icmp, 8, 4),
+                _ => return vec![],
+            }
+        }
+        _ => return vec![],
+    };
+
+    let Some(datagram) = icmp.get(8..) else {
+        return vec![];
+    };
+    let extension = match icmp[length_offset] as usize * word_len {
+        0 => datagram.get(NON_COMPLIANT_DATAGRAM_LEN..),
+        len => datagram.get(len..),
+    };
+    extension.map(parse_extension_structure).unwrap_or_default()
+}
+
+/// Objects of an extension structure, empty if it is not one. A zero checksum is accepted, as
+/// sent by some routers.
+fn parse_extension_structure(extension: &[u8]) -> Vec<IcmpExtension> {
+    if extension.len() < 4 || extension[0] >> 4 != EXTENSION_VERSION {
+        return vec![];
+    }
+    let checksum = u16::from_be_bytes([extension[2], extension[3]]);
+    if checksum != 0 && !is_checksum_valid(extension) {
+        return vec![];
+    }
+
+    let mut objects = vec![];
+    let mut rest = &extension[4..];
+    while rest.len() >= 4 {
+        let len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        if len < 4 || len > rest.len() {
+            break;
+        }
+        objects.push(IcmpExtension {
+            class_num: rest[2],
+            c_type: rest[3],
+            payload: rest[4..len].to_vec(),
+        });
+        rest = &rest[len..];
+    }
+    objects
+}
+
+/// Internet checksum of `data`, its checksum field included.
+fn is_checksum_valid(data: &[u8]) -> bool {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u32)
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum == 0xffff
+}
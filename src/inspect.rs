@@ -0,0 +1,325 @@
+use anyhow::{Context, Result};
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::Headers;
+use rdkafka::{Message, Offset, TopicPartitionList};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{stdin, stdout, Read, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+use tracing::info;
+
+use crate::client::capabilities::create_consumer;
+use crate::client::handler::kafka_auth;
+use crate::config::AppConfig;
+use crate::join::protocol_name;
+use crate::probe::deserialize_tagged_probes;
+use crate::protocol::MEASUREMENT_ID_HEADER;
+use crate::reply::{deserialize_replies, ReplyRecord};
+use crate::schema_registry::unframe;
+
+const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Payload of the inspected messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PayloadKind {
+    /// Probes messages, produced by the client
+    Probes,
+    /// Replies messages, produced by the agents
+    Replies,
+}
+
+/// Messages to print. Probes messages are filtered on their headers, replies individually.
+#[derive(Debug, Clone, Default)]
+pub struct InspectFilter {
+    pub agent: Option<String>,
+    pub measurement_id: Option<String>,
+}
+
+impl InspectFilter {
+    fn matches_headers(&self, headers: &BTreeMap<String, String>) -> bool {
+        // Payloads read from a file carry no headers to filter on
+        if headers.is_empty() {
+            return true;
+        }
+        if let Some(agent) = &self.agent {
+            if !headers.contains_key(agent) {
+                return false;
+            }
+        }
+        if let Some(measurement_id) = &self.measurement_id {
+            if headers.get(MEASUREMENT_ID_HEADER) != Some(measurement_id) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn matches_reply(&self, reply: &ReplyRecord) -> bool {
+        self.agent
+            .as_ref()
+            .is_none_or(|agent| reply.agent_id == *agent)
+            && self
+                .measurement_id
+                .as_ref()
+                .is_none_or(|measurement_id| reply.measurement_id == *measurement_id)
+    }
+}
+
+/// A probe as decoded from a probes message, with its tags.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InspectedProbe {
+    pub dst_addr: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub ttl: u8,
+    pub protocol: String,
+    #[serde(skip_serializing_if = "is_zero_u32")]
+    pub round: u32,
+    #[serde(skip_serializing_if = "is_zero_u8")]
+    pub dscp: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub src_addr: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+}
+
+fn is_zero_u32(value: &u32) -> bool {
+    *value == 0
+}
+
+fn is_zero_u8(value: &u8) -> bool {
+    *value == 0
+}
+
+/// A decoded message, with its Kafka coordinates when read from Kafka.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InspectedMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partition: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i64>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    // ID of the schema framing the payload, with `kafka.schema_registry`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probes: Option<Vec<InspectedProbe>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replies: Option<Vec<ReplyRecord>>,
+}
+
+/// Decode a payload, or return `None` if none of its content matches the filter.
+pub fn inspect_payload(
+    kind: PayloadKind,
+    payload: &[u8],
+    headers: BTreeMap<String, String>,
+    filter: &InspectFilter,
+) -> Result<Option<InspectedMessage>> {
+    let mut message = InspectedMessage {
+        topic: None,
+        partition: None,
+        offset: None,
+        headers,
+        schema_id: None,
+        probes: None,
+        replies: None,
+    };
+    match kind {
+        PayloadKind::Probes => {
+            if !filter.matches_headers(&message.headers) {
+                return Ok(None);
+            }
+            let probes = deserialize_tagged_probes(payload.to_vec())
+                .context("Failed to decode probes")?
+                .into_iter()
+                .map(|(probe, tags)| InspectedProbe {
+                    dst_addr: probe.dst_addr,
+                    src_port: probe.src_port,
+                    dst_port: probe.dst_port,
+                    ttl: probe.ttl,
+                    protocol: protocol_name(probe.protocol).to_string(),
+                    round: tags.round,
+                    dscp: tags.dscp,
+                    src_addr: tags.src_addr,
+                    instance: tags.instance,
+                })
+                .collect();
+            message.probes = Some(probes);
+        }
+        PayloadKind::Replies => {
+            // A JSON reply (`kafka.out_format: json`) starts with `{`, which would be the
+            // segment count of a Cap'n Proto message of 124 segments
+            let replies = if payload.first() == Some(&b'{') {
+                vec![serde_json::from_slice(payload).context("Failed to decode JSON reply")?]
+            } else {
+                deserialize_replies(payload.to_vec()).context("Failed to decode replies")?
+            };
+            let replies: Vec<ReplyRecord> = replies
+                .into_iter()
+                .filter(|reply| filter.matches_reply(reply))
+                .collect();
+            if replies.is_empty() {
+                return Ok(None);
+            }
+            message.replies = Some(replies);
+        }
+    }
+    Ok(Some(message))
+}
+
+fn print_message<W: Write>(writer: &mut W, message: &InspectedMessage) -> Result<()> {
+    serde_json::to_writer_pretty(&mut *writer, message)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Topics of the inspected messages, unless given: the probes topics (of the filtered agent
+/// with a topic template), or the replies topic.
+fn default_topics(
+    config: &AppConfig,
+    kind: PayloadKind,
+    filter: &InspectFilter,
+) -> Result<Vec<String>> {
+    match kind {
+        PayloadKind::Replies => Ok(vec![config.kafka.out_topic.clone()]),
+        PayloadKind::Probes => match (&config.kafka.in_topic_template, &filter.agent) {
+            (Some(_), None) => {
+                anyhow::bail!("--agent or --topic is required with kafka.in_topic_template")
+            }
+            (_, Some(agent)) => Ok(config.kafka.agent_in_topics(agent)),
+            (None, None) => Ok(config
+                .kafka
+                .in_topics
+                .split(',')
+                .map(|topic| topic.to_string())
+                .collect()),
+        },
+    }
+}
+
+/// Decode and print the messages of a file, holding a single payload.
+pub fn inspect_file(path: &Path, kind: PayloadKind, filter: &InspectFilter) -> Result<()> {
+    let mut payload = Vec::new();
+    if path == Path::new("-") {
+        stdin().lock().read_to_end(&mut payload)?;
+    } else {
+        std::fs::File::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?
+            .read_to_end(&mut payload)?;
+    }
+    if let Some(message) = inspect_payload(kind, &payload, BTreeMap::new(), filter)? {
+        print_message(&mut stdout().lock(), &message)?;
+    }
+    Ok(())
+}
+
+/// Decode and print the messages of Kafka topics, from the beginning. Stops at the end of the
+/// topics, unless `follow` is set, or once `limit` messages are printed.
+pub async fn inspect_kafka(
+    config: &AppConfig,
+    kind: PayloadKind,
+    topics: Vec<String>,
+    filter: &InspectFilter,
+    limit: Option<usize>,
+    follow: bool,
+) -> Result<()> {
+    let topics = if topics.is_empty() {
+        default_topics(config, kind, filter)?
+    } else {
+        topics
+    };
+
+    let consumer: StreamConsumer = create_consumer(config, kafka_auth(config)?)?;
+    let mut assignment = TopicPartitionList::new();
+    for topic in &topics {
+        let metadata = consumer.fetch_metadata(Some(topic), METADATA_TIMEOUT)?;
+        let partitions = metadata
+            .topics()
+            .first()
+            .map(|t| t.partitions().len())
+            .unwrap_or_default();
+        if partitions == 0 {
+            anyhow::bail!("Topic {} not found", topic);
+        }
+        for partition in 0..partitions {
+            assignment.add_partition_offset(topic, partition as i32, Offset::Beginning)?;
+        }
+    }
+    consumer.assign(&assignment)?;
+    info!("Inspecting {:?} messages of {}", kind, topics.join(","));
+
+    // End of partition events, to stop once every partition is read
+    let partitions = assignment.count();
+    let mut read_partitions = 0;
+    let mut printed = 0;
+    let mut stdout = stdout().lock();
+    while follow || read_partitions < partitions {
+        if limit.is_some_and(|limit| printed >= limit) {
+            break;
+        }
+        let message = match consumer.recv().await {
+            Ok(message) => message,
+            Err(KafkaError::PartitionEOF(_)) => {
+                read_partitions += 1;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut headers = BTreeMap::new();
+        if let Some(message_headers) = message.headers() {
+            for header in message_headers.iter() {
+                let value = header
+                    .value
+                    .map(|value| String::from_utf8_lossy(value).to_string())
+                    .unwrap_or_default();
+                headers.insert(header.key.to_string(), value);
+            }
+        }
+        let Some(payload) = message.payload() else {
+            continue;
+        };
+        // Payloads framed with their schema ID cannot be told apart from Cap'n Proto messages,
+        // they are only unframed with a schema registry
+        let (schema_id, payload) = match unframe(payload) {
+            Some((schema_id, payload)) if config.kafka.schema_registry.is_some() => {
+                (Some(schema_id), payload)
+            }
+            _ => (None, payload),
+        };
+        let inspected = match inspect_payload(kind, payload, headers, filter) {
+            Ok(Some(inspected)) => inspected,
+            Ok(None) => continue,
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to decode message {}:{}:{}: {:#}",
+                    message.topic(),
+                    message.partition(),
+                    message.offset(),
+                    e
+                );
+                continue;
+            }
+        };
+        print_message(
+            &mut stdout,
+            &InspectedMessage {
+                topic: Some(message.topic().to_string()),
+                partition: Some(message.partition()),
+                offset: Some(message.offset()),
+                schema_id,
+                ..inspected
+            },
+        )?;
+        printed += 1;
+    }
+    stdout.flush()?;
+    info!("messages={}", printed);
+    Ok(())
+}
@@ -0,0 +1,196 @@
+use anyhow::{Context, Result};
+use caracat::models::{Probe, L4};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{stdin, stdout, BufRead, Read, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::probe::ProbeTags;
+use crate::reply::{deserialize_replies, ReplyRecord};
+
+/// Metadata of a submitted probe, stored by the client to be joined with replies later.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProbeIndexRecord {
+    pub dst_addr: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub ttl: u8,
+    pub protocol: String,
+    pub round: u32,
+    #[serde(default)]
+    pub measurement_id: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// Submission time, in nanoseconds since the Unix epoch
+    pub submitted_at_ns: u64,
+}
+
+/// A reply enriched with the metadata of the probe that elicited it (if found).
+#[derive(Debug, Clone, Serialize)]
+pub struct JoinedReply {
+    #[serde(flatten)]
+    pub reply: ReplyRecord,
+    pub probe: Option<ProbeIndexRecord>,
+}
+
+pub fn protocol_name(protocol: L4) -> &'static str {
+    match protocol {
+        L4::UDP => "udp",
+        L4::ICMP => "icmp",
+        L4::ICMPv6 => "icmpv6",
+    }
+}
+
+impl ProbeIndexRecord {
+    pub fn new(
+        probe: &Probe,
+        probe_tags: &ProbeTags,
+        measurement_id: Option<String>,
+        tags: BTreeMap<String, String>,
+        submitted_at_ns: u64,
+    ) -> Self {
+        ProbeIndexRecord {
+            dst_addr: probe.dst_addr,
+            src_port: probe.src_port,
+            dst_port: probe.dst_port,
+            ttl: probe.ttl,
+            protocol: protocol_name(probe.protocol).to_string(),
+            round: probe_tags.round,
+            measurement_id,
+            tags,
+            submitted_at_ns,
+        }
+    }
+}
+
+/// Append probe index records as JSON lines.
+pub fn write_probe_index<W: Write>(writer: &mut W, records: &[ProbeIndexRecord]) -> Result<()> {
+    for record in records {
+        serde_json::to_writer(&mut *writer, record)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+type IndexKey = (IpAddr, u16, u16, u8);
+
+/// Probe index keyed by probe signature (destination, ports and TTL), as quoted in replies.
+#[derive(Debug, Default)]
+pub struct ProbeIndex {
+    records: HashMap<IndexKey, Vec<ProbeIndexRecord>>,
+}
+
+impl ProbeIndex {
+    pub fn from_jsonl<R: BufRead>(reader: R) -> Result<Self> {
+        let mut index = ProbeIndex::default();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: ProbeIndexRecord = serde_json::from_str(&line)
+                .with_context(|| format!("Failed to parse probe index record at line {}", i + 1))?;
+            index.insert(record);
+        }
+        Ok(index)
+    }
+
+    pub fn insert(&mut self, record: ProbeIndexRecord) {
+        let key = (
+            record.dst_addr,
+            record.src_port,
+            record.dst_port,
+            record.ttl,
+        );
+        let entries = self.records.entry(key).or_default();
+        // Keep submissions sorted by time
+        let position = entries.partition_point(|r| r.submitted_at_ns <= record.submitted_at_ns);
+        entries.insert(position, record);
+    }
+
+    /// Find the latest submission of the probe that elicited `reply`.
+    /// If `window_ns` is set, the reply must have been received within this delay after submission.
+    pub fn lookup(&self, reply: &ReplyRecord, window_ns: Option<u64>) -> Option<&ProbeIndexRecord> {
+        let key = (
+            reply.probe_dst_addr,
+            reply.probe_src_port,
+            reply.probe_dst_port,
+            reply.probe_ttl,
+        );
+        self.records.get(&key)?.iter().rev().find(|record| {
+            // Replies tagged by the agent must match the round of the submission
+            if reply.round != 0 && record.round != reply.round {
+                return false;
+            }
+            // Likewise for the measurement of the submission
+            if !reply.measurement_id.is_empty()
+                && record
+                    .measurement_id
+                    .as_ref()
+                    .is_some_and(|id| *id != reply.measurement_id)
+            {
+                return false;
+            }
+            match window_ns {
+                Some(window_ns) => {
+                    reply.time_received_ns >= record.submitted_at_ns
+                        && reply.time_received_ns - record.submitted_at_ns <= window_ns
+                }
+                None => true,
+            }
+        })
+    }
+
+    pub fn join(&self, reply: ReplyRecord, window_ns: Option<u64>) -> JoinedReply {
+        let probe = self.lookup(&reply, window_ns).cloned();
+        JoinedReply { reply, probe }
+    }
+}
+
+pub fn handle(
+    index_file: &Path,
+    replies_file: Option<PathBuf>,
+    window_secs: Option<u64>,
+) -> Result<()> {
+    let index_reader = std::io::BufReader::new(
+        std::fs::File::open(index_file)
+            .with_context(|| format!("Failed to open probe index {}", index_file.display()))?,
+    );
+    let index = ProbeIndex::from_jsonl(index_reader)?;
+
+    let mut replies_bytes = Vec::new();
+    match replies_file {
+        Some(replies_file) => {
+            std::fs::File::open(replies_file)?.read_to_end(&mut replies_bytes)?;
+        }
+        None => {
+            stdin().lock().read_to_end(&mut replies_bytes)?;
+        }
+    }
+    let replies = deserialize_replies(replies_bytes)?;
+
+    let window_ns = window_secs.map(|secs| secs * 1_000_000_000);
+    let mut stdout = stdout().lock();
+    let mut matched = 0;
+    let replies_len = replies.len();
+    // Replies of newer agents, whose unknown fields are kept as extensions
+    let extended = replies.iter().filter(|r| r.extensions.is_some()).count();
+    for reply in replies {
+        let joined = index.join(reply, window_ns);
+        if joined.probe.is_some() {
+            matched += 1;
+        }
+        serde_json::to_writer(&mut stdout, &joined)?;
+        stdout.write_all(b"\n")?;
+    }
+    stdout.flush()?;
+
+    info!(
+        "replies={},matched={},extended={}",
+        replies_len, matched, extended
+    );
+    Ok(())
+}
@@ -1,11 +1,27 @@
+//! Internet-scale measurements pipeline. The `saimiris` command line is built on this
+//! library, which other Rust services can use instead of running the binary:
+//! - `agent::run` runs an agent with its configuration (see `config::app_config`), its other
+//!   modules being internal (public with the `internals` feature, for the integration tests),
+//! - `client::submit` submits probes to agents (see `config::parse_and_validate_client_args`),
+//! - `probe` and `reply` serialize and deserialize the probes and replies messages,
+//!   `protocol` names their Kafka headers.
 pub mod agent;
 pub mod auth;
 pub mod client;
 pub mod config;
+pub mod convert;
+pub mod icmp_extension;
+pub mod inspect;
+pub mod join;
+pub mod logging;
+pub mod measurement;
 pub mod probe;
 pub mod probe_capnp;
+pub mod protocol;
 pub mod reply;
 pub mod reply_capnp;
+pub mod schema_registry;
+pub mod signing;
 pub use auth::*;
 pub use config::*;
 pub use probe::*;
@@ -1,23 +1,29 @@
-mod agent;
-mod auth;
-mod client;
-mod config;
-mod probe;
-mod probe_capnp;
-mod reply;
-mod reply_capnp;
+#[cfg(unix)]
+mod service;
 
 use anyhow::Result;
 use clap::{Args, CommandFactory, Parser, Subcommand};
 use clap_verbosity_flag::{InfoLevel, Verbosity};
-use metrics::describe_counter;
-use metrics_exporter_prometheus::PrometheusBuilder;
+use metrics_exporter_prometheus::PrometheusHandle;
 use std::io::{stdin, IsTerminal};
-use std::net::SocketAddr;
 use std::path::PathBuf;
 use tracing::{error, trace};
 
-use crate::config::{app_config, parse_and_validate_client_args};
+use saimiris::agent::{PortPolicy, TtlRange};
+use saimiris::client::generate::FlowMapper;
+use saimiris::client::ping::{DEFAULT_PING_COUNT, DEFAULT_PING_TTL};
+use saimiris::client::rib::{RibFormat, DEFAULT_TARGETS_PER_PREFIX};
+use saimiris::client::traceroute::{
+    DEFAULT_TRACEROUTE_FLOWS, DEFAULT_TRACEROUTE_MAX_TTL, DEFAULT_TRACEROUTE_MIN_TTL,
+};
+use saimiris::config::client::DEFAULT_GATEWAY_CHUNK_PROBES;
+use saimiris::config::{
+    app_config, parse_and_validate_client_args, AppConfig, Distribution, ProbesFormat,
+};
+use saimiris::convert::ConvertFormat;
+use saimiris::inspect::{InspectFilter, PayloadKind};
+use saimiris::logging::{JsonFields, JsonFormat, LogFormat};
+use saimiris::{agent, client, convert, inspect, join};
 
 #[derive(Debug, Parser)]
 #[clap(name = "Saimiris", version)]
@@ -35,25 +41,372 @@ enum Command {
         /// Configuration file
         #[arg(short, long)]
         config: String,
+
+        /// Run as a managed service (Unix): plain logs and clean exit when asked to stop by the service manager
+        #[arg(long)]
+        service: bool,
     },
 
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
     Client {
+        #[clap(subcommand)]
+        command: Option<ClientCommand>,
+
         /// Configuration file
-        #[arg(short, long)]
-        config: String,
+        #[arg(short, long, required = true)]
+        config: Option<String>,
 
         /// Probes file (read stdin if not provided)
         #[arg(short, long)]
         probes_file: Option<PathBuf>,
 
+        /// Probes format
+        #[arg(long, value_enum, default_value_t = ProbesFormat::Csv)]
+        format: ProbesFormat,
+
         /// Agent specifications in format 'agent1:ip1,agent2:ip2'.
-        /// For IPv6 addresses, use brackets: 'agent1:[2001:db8::1],agent2:192.168.1.1'
+        /// For IPv6 addresses, use brackets: 'agent1:[2001:db8::1],agent2:192.168.1.1'.
+        /// Target a named caracat instance of an agent with 'agent1/instance:ip1'
+        #[arg(index = 1, value_name = "AGENTS", required = true)]
+        agents: Option<String>,
+
+        /// How the probes are distributed across the agents
+        #[arg(long, value_enum, default_value_t = Distribution::Replicate)]
+        distribution: Distribution,
+
+        /// Maximum number of messages awaiting delivery to Kafka
+        #[arg(long, default_value_t = 1)]
+        max_in_flight: usize,
+
+        /// Maximum number of messages produced per second
+        #[arg(long)]
+        produce_rate: Option<f64>,
+
+        /// Retries of a message on transient delivery errors, with exponential backoff
+        #[arg(long, default_value_t = 3)]
+        produce_retries: u32,
+
+        /// Write the probes which could not be delivered to this manifest file
+        #[arg(long)]
+        failure_manifest: Option<PathBuf>,
+
+        /// Only resubmit the probes listed in this failure manifest
+        #[arg(long)]
+        retry_manifest: Option<PathBuf>,
+
+        /// Measurement ID for tracking probe batches
+        #[arg(long)]
+        measurement_id: Option<String>,
+
+        /// Wait until all agents report the measurement as complete (requires a gateway)
+        #[arg(long, requires = "measurement_id")]
+        wait: bool,
+
+        /// Give up waiting after this many seconds
+        #[arg(long, requires = "wait")]
+        wait_timeout: Option<u64>,
+
+        /// Round number attached to the probes and copied into their replies
+        #[arg(long)]
+        round: Option<u32>,
+
+        /// DSCP (0-63) of the probes, to measure DSCP-dependent routing and remarking
+        #[arg(long, value_parser = clap::value_parser!(u8).range(0..64))]
+        dscp: Option<u8>,
+
+        /// Name of the caracat instance sending the probes on each agent (unless given in
+        /// the agent specification), instead of the instance selected by the source IP prefixes
+        #[arg(long)]
+        instance: Option<String>,
+
+        /// Submit the probes as targets, expanded by the agents into one probe per TTL
+        /// of the range 'MIN-MAX' (the TTL of the probes is ignored)
+        #[arg(long, value_name = "MIN-MAX")]
+        expand_ttl: Option<TtlRange>,
+
+        /// Let the agents select the source port of each probe within 'MIN-MAX', by hashing
+        /// its flow ('flow', by default) or its destination ('destination')
+        #[arg(long, value_name = "MIN-MAX[:STRATEGY]")]
+        src_ports: Option<PortPolicy>,
+
+        /// Priority of the probes on the agents: they are sent before the probes of a lower
+        /// priority, such as those of background measurements (0 by default)
+        #[arg(long)]
+        priority: Option<u32>,
+
+        /// Weight of the measurement on the agents with the fair measurement scheduling: it gets
+        /// this many times the probes of the other measurements per round (1 by default)
+        #[arg(long, requires = "measurement_id")]
+        weight: Option<u32>,
+
+        /// Append the submitted probes to this index file, for later joins with the replies
+        #[arg(long)]
+        index_file: Option<PathBuf>,
+
+        /// Tag recorded with the probes in the index file, in format 'KEY=VALUE' (repeatable)
+        #[arg(long = "tag", value_name = "KEY=VALUE", requires = "index_file")]
+        tags: Vec<String>,
+
+        /// Submit the probes to the gateway instead of producing them to Kafka
+        #[arg(long, requires = "measurement_id")]
+        via_gateway: bool,
+
+        /// Probes per request when submitting via the gateway
+        #[arg(long, default_value_t = DEFAULT_GATEWAY_CHUNK_PROBES, requires = "via_gateway")]
+        gateway_chunk_probes: usize,
+
+        /// Print the submission summary as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Parse the probes and build the Kafka messages, without producing them
+        #[arg(long, conflicts_with_all = ["via_gateway", "wait"])]
+        dry_run: bool,
+    },
+
+    /// Ping destinations from the agents, with ICMP echo requests
+    Ping {
+        /// Configuration file
+        #[arg(short, long)]
+        config: String,
+
+        /// Destinations file, one address per line (read stdin if not provided)
+        #[arg(short, long)]
+        destinations_file: Option<PathBuf>,
+
+        /// Agent specifications, as for the client
+        #[arg(index = 1, value_name = "AGENTS")]
+        agents: String,
+
+        /// Echo requests per destination
+        #[arg(short = 'n', long, default_value_t = DEFAULT_PING_COUNT, value_parser = clap::value_parser!(u16).range(1..))]
+        count: u16,
+
+        /// TTL of the echo requests
+        #[arg(long, default_value_t = DEFAULT_PING_TTL, value_parser = clap::value_parser!(u8).range(1..))]
+        ttl: u8,
+
+        /// Measurement ID for tracking probe batches
+        #[arg(long)]
+        measurement_id: Option<String>,
+
+        /// Wait until all agents report the measurement as complete (requires a gateway)
+        #[arg(long, requires = "measurement_id")]
+        wait: bool,
+    },
+
+    /// Traceroute destinations from the agents, with UDP probes (Paris traceroute)
+    Traceroute {
+        /// Configuration file
+        #[arg(short, long)]
+        config: String,
+
+        /// Destinations file, one address per line (read stdin if not provided)
+        #[arg(short, long)]
+        destinations_file: Option<PathBuf>,
+
+        /// Agent specifications, as for the client
+        #[arg(index = 1, value_name = "AGENTS")]
+        agents: String,
+
+        /// First TTL probed
+        #[arg(long, default_value_t = DEFAULT_TRACEROUTE_MIN_TTL, value_parser = clap::value_parser!(u8).range(1..))]
+        min_ttl: u8,
+
+        /// Last TTL probed
+        #[arg(long, default_value_t = DEFAULT_TRACEROUTE_MAX_TTL, value_parser = clap::value_parser!(u8).range(1..))]
+        max_ttl: u8,
+
+        /// Flows per destination, each with its own source port
+        #[arg(long, default_value_t = DEFAULT_TRACEROUTE_FLOWS, value_parser = clap::value_parser!(u16).range(1..))]
+        flows: u16,
+
+        /// Mapping of the flows of a destination to addresses of its /24 (IPv4) or /120 (IPv6)
+        /// and source ports, for MDA-style topology discovery
+        #[arg(long, value_enum, default_value_t = FlowMapper::Ports)]
+        flow_mapper: FlowMapper,
+
+        /// Measurement ID for tracking probe batches
+        #[arg(long)]
+        measurement_id: Option<String>,
+
+        /// Wait until all agents report the measurement as complete (requires a gateway)
+        #[arg(long, requires = "measurement_id")]
+        wait: bool,
+    },
+
+    /// Traceroute targets sampled in the prefixes of a routing table (MRT RIB dump or prefix list)
+    Rib {
+        /// Configuration file
+        #[arg(short, long)]
+        config: String,
+
+        /// Routing table file, optionally gzipped
+        #[arg(short, long)]
+        rib_file: PathBuf,
+
+        /// Routing table format
+        #[arg(long, value_enum, default_value_t = RibFormat::Mrt)]
+        format: RibFormat,
+
+        /// Agent specifications, as for the client
         #[arg(index = 1, value_name = "AGENTS")]
         agents: String,
 
+        /// Targets sampled per prefix, each in its own slice of the prefix
+        #[arg(long, default_value_t = DEFAULT_TARGETS_PER_PREFIX, value_parser = clap::value_parser!(u16).range(1..))]
+        targets_per_prefix: u16,
+
+        /// Seed of the target sampling, to probe other addresses of the prefixes
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+
+        /// First TTL probed
+        #[arg(long, default_value_t = DEFAULT_TRACEROUTE_MIN_TTL, value_parser = clap::value_parser!(u8).range(1..))]
+        min_ttl: u8,
+
+        /// Last TTL probed
+        #[arg(long, default_value_t = DEFAULT_TRACEROUTE_MAX_TTL, value_parser = clap::value_parser!(u8).range(1..))]
+        max_ttl: u8,
+
+        /// Flows per target, each with its own source port
+        #[arg(long, default_value_t = DEFAULT_TRACEROUTE_FLOWS, value_parser = clap::value_parser!(u16).range(1..))]
+        flows: u16,
+
+        /// Mapping of the flows of a target to addresses of its /24 (IPv4) or /120 (IPv6) and
+        /// source ports, as for traceroute
+        #[arg(long, value_enum, default_value_t = FlowMapper::Ports)]
+        flow_mapper: FlowMapper,
+
+        /// Write the probes to an index file, tagged with the prefix and origin ASN of their target
+        #[arg(long)]
+        index_file: Option<PathBuf>,
+
         /// Measurement ID for tracking probe batches
         #[arg(long)]
         measurement_id: Option<String>,
+
+        /// Wait until all agents report the measurement as complete (requires a gateway)
+        #[arg(long, requires = "measurement_id")]
+        wait: bool,
+    },
+
+    /// Cancel a measurement, dropping the probes the agents have not sent yet
+    Cancel {
+        /// Configuration file
+        #[arg(short, long)]
+        config: String,
+
+        /// Agent IDs in format 'agent1,agent2'
+        #[arg(index = 1, value_name = "AGENTS")]
+        agents: String,
+
+        /// Measurement ID to cancel
+        #[arg(long)]
+        measurement_id: String,
+    },
+
+    /// Inspect the probing policy of an agent
+    Policy {
+        #[clap(subcommand)]
+        command: PolicyCommand,
+    },
+
+    /// Join replies with the probe metadata indexed by the client
+    Join {
+        /// Probe index file written by the client
+        #[arg(short, long)]
+        index_file: PathBuf,
+
+        /// Replies file, as a stream of capnp messages (read stdin if not provided)
+        #[arg(short, long)]
+        replies_file: Option<PathBuf>,
+
+        /// Only match replies received within this many seconds after submission
+        #[arg(long)]
+        window: Option<u64>,
+    },
+
+    /// Convert replies from capnp streams (e.g. dumps of the replies topic) to another format
+    Convert {
+        /// Replies files, as streams of capnp messages (read stdin if not provided)
+        #[arg(index = 1, value_name = "FILES")]
+        inputs: Vec<PathBuf>,
+
+        /// Output format
+        #[arg(long, value_enum)]
+        to: ConvertFormat,
+
+        /// Output file (write stdout if not provided)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Decode and print probes or replies messages, from Kafka or a file
+    Inspect {
+        /// Payload of the messages
+        #[arg(index = 1, value_enum)]
+        kind: PayloadKind,
+
+        /// Configuration file, to read the messages from Kafka
+        #[arg(short, long, required_unless_present = "file")]
+        config: Option<String>,
+
+        /// File holding a single payload, instead of Kafka ('-' for stdin)
+        #[arg(short, long, conflicts_with_all = ["topic", "follow"])]
+        file: Option<PathBuf>,
+
+        /// Topics in format 'topic1,topic2' (the probes or replies topics of the configuration if not provided)
+        #[arg(long)]
+        topic: Option<String>,
+
+        /// Only print the probes messages to this agent, or its replies
+        #[arg(long)]
+        agent: Option<String>,
+
+        /// Only print the probes messages or replies of this measurement
+        #[arg(long)]
+        measurement_id: Option<String>,
+
+        /// Stop after printing this many messages
+        #[arg(long)]
+        limit: Option<usize>,
+
+        /// Keep printing new messages once the end of the topics is reached
+        #[arg(long)]
+        follow: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ClientCommand {
+    /// List the agents registered on the gateway, with their health, prefixes and rates
+    ListAgents {
+        /// Configuration file, with the gateway section
+        #[arg(short, long)]
+        config: String,
+
+        /// Print the agents as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum PolicyCommand {
+    /// Report the probes the agent would reject, and why, without sending them
+    Check {
+        /// Agent configuration file
+        #[arg(short, long)]
+        config: String,
+
+        /// Probes file
+        #[arg(short, long)]
+        probes: PathBuf,
+
+        /// Probes format
+        #[arg(long, value_enum, default_value_t = ProbesFormat::Csv)]
+        format: ProbesFormat,
     },
 }
 
@@ -62,81 +415,126 @@ struct GlobalOpts {
     /// Verbosity level
     #[clap(flatten)]
     verbose: Verbosity<InfoLevel>,
+
+    /// Logs format, `agent.log_format` of the agent configuration by default
+    #[arg(long, global = true, value_enum)]
+    log_format: Option<LogFormat>,
 }
 
-fn set_tracing(cli: &GlobalOpts) -> Result<()> {
-    let subscriber = tracing_subscriber::fmt()
-        .compact()
-        .with_file(true)
-        .with_line_number(true)
-        .with_max_level(cli.verbose)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)?;
+fn set_tracing(cli: &GlobalOpts, service: bool, log_format: LogFormat) -> Result<()> {
+    match log_format {
+        LogFormat::Text => {
+            let subscriber = tracing_subscriber::fmt()
+                .compact()
+                .with_ansi(!service)
+                .with_file(true)
+                .with_line_number(true)
+                .with_max_level(cli.verbose)
+                .finish();
+            tracing::subscriber::set_global_default(subscriber)?;
+        }
+        LogFormat::Json => {
+            let subscriber = tracing_subscriber::fmt()
+                .event_format(JsonFormat)
+                .fmt_fields(JsonFields)
+                .with_max_level(cli.verbose)
+                .finish();
+            tracing::subscriber::set_global_default(subscriber)?;
+        }
+    }
+    Ok(())
+}
+
+/// Run the agent under a service manager, until it stops or is asked to stop.
+#[cfg(unix)]
+async fn run_service(app_config: &AppConfig, metrics_handle: PrometheusHandle) -> Result<()> {
+    // Started once configured, its readiness to probe being reported by `/readyz`
+    service::notify("READY=1");
+    tokio::select! {
+        result = agent::handle(app_config, metrics_handle) => match result {
+            Ok(_) => (),
+            Err(e) => error!("Error: {}", e),
+        },
+        result = service::shutdown_signal() => match result {
+            Ok(_) => tracing::info!("Stopping agent"),
+            Err(e) => error!("Error: {}", e),
+        },
+    }
+    service::notify("STOPPING=1");
     Ok(())
 }
 
-fn set_metrics(metrics_address: SocketAddr) {
-    let prom_builder = PrometheusBuilder::new();
-    prom_builder
-        .with_http_listener(metrics_address)
-        .install()
-        .expect("Failed to install Prometheus metrics exporter");
-
-    // Producer metrics
-    metrics::describe_counter!(
-        "saimiris_kafka_messages_total",
-        "Total number of Kafka messages produced"
-    );
-
-    // Receiver Metrics
-    describe_counter!(
-        "saimiris_receiver_received_valid_total",
-        "Total number of valid replies received from the caracat receiver thread"
-    );
-    describe_counter!(
-        "saimiris_receiver_received_invalid_total",
-        "Total number of invalid replies received that failed the integrity check"
-    );
-
-    // Sender Metrics
-    describe_counter!(
-        "saimiris_sender_read_total",
-        "Total number of probes read from the sender thread"
-    );
-    describe_counter!(
-        "saimiris_sender_probes_sent_total",
-        "Total number of probes sent by the sender thread"
-    );
-    describe_counter!(
-        "saimiris_sender_failed_total",
-        "Total number of errors encountered by the sender thread while sending probes"
-    );
-    describe_counter!(
-        "saimiris_sender_filtered_total",
-        "Total number of probes filtered by the sender thread (low/high TTL)"
-    );
+/// There is no Windows service control handler: the service mode is only supported on Unix.
+#[cfg(not(unix))]
+async fn run_service(_app_config: &AppConfig, _metrics_handle: PrometheusHandle) -> Result<()> {
+    anyhow::bail!("--service is only supported on Unix (systemd, launchd)")
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = App::parse();
-    set_tracing(&cli.global_opts)?;
+    let service = matches!(cli.command, Command::Agent { service: true, .. });
+    let log_format = match (&cli.global_opts.log_format, &cli.command) {
+        (Some(log_format), _) => *log_format,
+        (None, Command::Agent { config, .. }) => saimiris::config::log_format(config)?,
+        (None, _) => LogFormat::default(),
+    };
+    set_tracing(&cli.global_opts, service, log_format)?;
 
     match cli.command {
-        Command::Agent { config } => {
+        Command::Agent { config, service } => {
             let app_config = app_config(&config).await?;
             trace!("{:?}", app_config);
-            set_metrics(app_config.agent.metrics_address);
-            match agent::handle(&app_config).await {
+            let metrics_handle = agent::install_recorder()?;
+            if service {
+                run_service(&app_config, metrics_handle).await?;
+            } else {
+                match agent::handle(&app_config, metrics_handle).await {
+                    Ok(_) => (),
+                    Err(e) => error!("Error: {}", e),
+                }
+            }
+        }
+        Command::Client {
+            command: Some(ClientCommand::ListAgents { config, json }),
+            ..
+        } => {
+            let app_config = app_config(&config).await?;
+            trace!("{:?}", app_config);
+
+            match client::gateway::list_agents(&app_config, json).await {
                 Ok(_) => (),
                 Err(e) => error!("Error: {}", e),
             }
         }
         Command::Client {
-            config,
-            agents,
+            command: None,
+            config: Some(config),
+            agents: Some(agents),
             probes_file,
+            format,
+            distribution,
+            max_in_flight,
+            produce_rate,
+            produce_retries,
+            failure_manifest,
+            retry_manifest,
             measurement_id,
+            wait,
+            wait_timeout,
+            round,
+            dscp,
+            instance,
+            expand_ttl,
+            src_ports,
+            priority,
+            weight,
+            index_file,
+            tags,
+            via_gateway,
+            gateway_chunk_probes,
+            json,
+            dry_run,
         } => {
             if probes_file.is_none() && stdin().is_terminal() {
                 App::command().print_help().unwrap();
@@ -145,16 +543,246 @@ async fn main() -> Result<()> {
 
             // Parse and validate client arguments
             let client_config = parse_and_validate_client_args(&agents, probes_file)?
-                .with_measurement_tracking(measurement_id);
+                .with_probes_format(format)
+                .with_distribution(distribution)
+                .with_produce_controls(max_in_flight, produce_rate)
+                .with_produce_retries(produce_retries)
+                .with_manifests(failure_manifest, retry_manifest)
+                .with_measurement_tracking(measurement_id)
+                .with_wait(wait, wait_timeout)
+                .with_round(round)
+                .with_dscp(dscp)
+                .with_instance(instance)
+                .with_expand_ttl(expand_ttl)
+                .with_src_ports(src_ports)
+                .with_priority(priority)
+                .with_weight(weight)
+                .with_probe_index(index_file, &tags)?
+                .with_gateway_submission(via_gateway, gateway_chunk_probes)
+                .with_json_summary(json)
+                .with_dry_run(dry_run);
 
             let app_config = app_config(&config).await?;
             trace!("{:?}", app_config);
 
             match client::handle(&app_config, client_config).await {
+                Ok(_) => (),
+                Err(e) => {
+                    error!("Error: {}", e);
+                    ::std::process::exit(1);
+                }
+            }
+        }
+        // The configuration and the agents are required by clap without a subcommand
+        Command::Client { .. } => unreachable!(),
+        Command::Ping {
+            config,
+            destinations_file,
+            agents,
+            count,
+            ttl,
+            measurement_id,
+            wait,
+        } => {
+            if destinations_file.is_none() && stdin().is_terminal() {
+                App::command().print_help().unwrap();
+                ::std::process::exit(2);
+            }
+
+            let client_config = parse_and_validate_client_args(&agents, destinations_file)?
+                .with_measurement_tracking(measurement_id)
+                .with_wait(wait, None);
+
+            let app_config = app_config(&config).await?;
+            trace!("{:?}", app_config);
+
+            match client::ping::handle(&app_config, client_config, count, ttl).await {
+                Ok(_) => (),
+                Err(e) => {
+                    error!("Error: {}", e);
+                    ::std::process::exit(1);
+                }
+            }
+        }
+        Command::Traceroute {
+            config,
+            destinations_file,
+            agents,
+            min_ttl,
+            max_ttl,
+            flows,
+            flow_mapper,
+            measurement_id,
+            wait,
+        } => {
+            if min_ttl > max_ttl {
+                anyhow::bail!("--min-ttl must not be greater than --max-ttl");
+            }
+            if destinations_file.is_none() && stdin().is_terminal() {
+                App::command().print_help().unwrap();
+                ::std::process::exit(2);
+            }
+
+            let client_config = parse_and_validate_client_args(&agents, destinations_file)?
+                .with_measurement_tracking(measurement_id)
+                .with_wait(wait, None);
+
+            let app_config = app_config(&config).await?;
+            trace!("{:?}", app_config);
+
+            let range = TtlRange {
+                min: min_ttl,
+                max: max_ttl,
+            };
+            match client::traceroute::handle(&app_config, client_config, range, flows, flow_mapper)
+                .await
+            {
+                Ok(_) => (),
+                Err(e) => {
+                    error!("Error: {}", e);
+                    ::std::process::exit(1);
+                }
+            }
+        }
+        Command::Rib {
+            config,
+            rib_file,
+            format,
+            agents,
+            targets_per_prefix,
+            seed,
+            min_ttl,
+            max_ttl,
+            flows,
+            flow_mapper,
+            index_file,
+            measurement_id,
+            wait,
+        } => {
+            if min_ttl > max_ttl {
+                anyhow::bail!("--min-ttl must not be greater than --max-ttl");
+            }
+
+            let client_config = parse_and_validate_client_args(&agents, None)?
+                .with_measurement_tracking(measurement_id)
+                .with_wait(wait, None)
+                .with_probe_index(index_file, &[])?;
+
+            let app_config = app_config(&config).await?;
+            trace!("{:?}", app_config);
+
+            let range = TtlRange {
+                min: min_ttl,
+                max: max_ttl,
+            };
+            match client::rib::handle(
+                &app_config,
+                client_config,
+                &rib_file,
+                format,
+                targets_per_prefix,
+                seed,
+                range,
+                flows,
+                flow_mapper,
+            )
+            .await
+            {
+                Ok(_) => (),
+                Err(e) => {
+                    error!("Error: {}", e);
+                    ::std::process::exit(1);
+                }
+            }
+        }
+        Command::Cancel {
+            config,
+            agents,
+            measurement_id,
+        } => {
+            let agents: Vec<String> = agents
+                .split(',')
+                .map(|agent| agent.trim().to_string())
+                .filter(|agent| !agent.is_empty())
+                .collect();
+            if agents.is_empty() {
+                anyhow::bail!("At least one agent must be specified");
+            }
+
+            let app_config = app_config(&config).await?;
+            trace!("{:?}", app_config);
+
+            match client::handler::cancel(&app_config, &agents, &measurement_id).await {
                 Ok(_) => (),
                 Err(e) => error!("Error: {}", e),
             }
         }
+        Command::Policy {
+            command:
+                PolicyCommand::Check {
+                    config,
+                    probes,
+                    format,
+                },
+        } => {
+            let app_config = app_config(&config).await?;
+            trace!("{:?}", app_config);
+
+            match agent::check_policy(&app_config, &probes, format) {
+                Ok(_) => (),
+                Err(e) => error!("Error: {}", e),
+            }
+        }
+        Command::Join {
+            index_file,
+            replies_file,
+            window,
+        } => match join::handle(&index_file, replies_file, window) {
+            Ok(_) => (),
+            Err(e) => error!("Error: {}", e),
+        },
+        Command::Convert { inputs, to, output } => {
+            match convert::handle(&inputs, output.as_deref(), to) {
+                Ok(_) => (),
+                Err(e) => error!("Error: {}", e),
+            }
+        }
+        Command::Inspect {
+            kind,
+            config,
+            file,
+            topic,
+            agent,
+            measurement_id,
+            limit,
+            follow,
+        } => {
+            let filter = InspectFilter {
+                agent,
+                measurement_id,
+            };
+            let result = match (file, config) {
+                (Some(file), _) => inspect::inspect_file(&file, kind, &filter),
+                (None, Some(config)) => {
+                    let app_config = app_config(&config).await?;
+                    trace!("{:?}", app_config);
+                    let topics = topic
+                        .map(|topic| {
+                            topic
+                                .split(',')
+                                .map(|topic| topic.trim().to_string())
+                                .filter(|topic| !topic.is_empty())
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                    inspect::inspect_kafka(&app_config, kind, topics, &filter, limit, follow).await
+                }
+                (None, None) => unreachable!("--config is required without --file"),
+            };
+            if let Err(e) = result {
+                error!("Error: {}", e);
+            }
+        }
     }
 
     Ok(())
@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Status of a measurement on an agent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MeasurementStatus {
+    /// Its probes are being prepared, none were sent yet.
+    #[default]
+    Pending,
+    /// Its probes are being sent.
+    Running,
+    /// Its last message was sent by every worker.
+    Completed,
+    /// Its remaining probes are dropped.
+    Cancelled,
+}
+
+impl MeasurementStatus {
+    /// Whether no more probes of the measurement are sent.
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            MeasurementStatus::Completed | MeasurementStatus::Cancelled
+        )
+    }
+
+    /// Whether a measurement in this status can move to `next`. A completed measurement
+    /// starts over if its ID is reused, a cancelled one never does.
+    pub fn can_transition_to(&self, next: MeasurementStatus) -> bool {
+        use MeasurementStatus::*;
+        matches!(
+            (self, next),
+            (Pending, Running)
+                | (Pending, Cancelled)
+                | (Running, Running)
+                | (Running, Completed)
+                | (Running, Cancelled)
+                | (Completed, Cancelled)
+                | (Completed, Pending)
+                | (Completed, Running)
+        )
+    }
+}
+
+/// A measurement as submitted by the client: its ID, and the agents sending its probes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MeasurementSpec {
+    pub id: String,
+    pub agents: Vec<String>,
+}
+
+impl MeasurementSpec {
+    pub fn new(id: String, agents: Vec<String>) -> Self {
+        MeasurementSpec { id, agents }
+    }
+}
+
+/// When a measurement started to be sent, and when it was submitted by the client.
+#[derive(Debug, Clone, Copy)]
+pub struct MeasurementTiming {
+    pub started: Instant,
+    pub submitted_at_ms: Option<i64>,
+}
+
+impl MeasurementTiming {
+    /// Wall-clock duration from the first probe sent to now.
+    pub fn send_duration(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Delay from the client submission to `now_ms`, in milliseconds since the Unix epoch.
+    pub fn completion_latency(&self, now_ms: i64) -> Option<Duration> {
+        let submitted_at_ms = self.submitted_at_ms?;
+        u64::try_from(now_ms - submitted_at_ms)
+            .ok()
+            .map(Duration::from_millis)
+    }
+}
+
+/// A measurement tracked by an agent, with the probes sent by the workers of a caracat
+/// instance.
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    pub id: String,
+    status: MeasurementStatus,
+    sent_probes: u32,
+    // Workers which sent their share of the last message
+    finished_workers: usize,
+    timing: Option<MeasurementTiming>,
+}
+
+impl Measurement {
+    pub fn new(id: &str) -> Self {
+        Measurement {
+            id: id.to_string(),
+            status: MeasurementStatus::Pending,
+            sent_probes: 0,
+            finished_workers: 0,
+            timing: None,
+        }
+    }
+
+    pub fn status(&self) -> MeasurementStatus {
+        self.status
+    }
+
+    pub fn sent_probes(&self) -> u32 {
+        self.sent_probes
+    }
+
+    fn transition(&mut self, next: MeasurementStatus) {
+        debug_assert!(
+            self.status.can_transition_to(next),
+            "invalid measurement transition {:?} -> {:?}",
+            self.status,
+            next
+        );
+        if self.status == MeasurementStatus::Completed {
+            // The measurement ID is reused
+            *self = Measurement::new(&self.id);
+        }
+        self.status = next;
+    }
+
+    /// Mark the measurement as being sent. The submission time is the earliest of its
+    /// messages.
+    pub fn start(&mut self, submitted_at_ms: Option<i64>) {
+        match self.status {
+            MeasurementStatus::Cancelled => return,
+            MeasurementStatus::Completed => self.transition(MeasurementStatus::Pending),
+            _ => {}
+        }
+        let timing = self.timing.get_or_insert(MeasurementTiming {
+            started: Instant::now(),
+            submitted_at_ms,
+        });
+        timing.submitted_at_ms = match (timing.submitted_at_ms, submitted_at_ms) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    /// Record the probes sent by one worker for a measurement message. The measurement is
+    /// complete once all `workers` have sent their share of the last message.
+    pub fn record(&mut self, sent: u32, end_of_measurement: bool, workers: usize) {
+        if self.status == MeasurementStatus::Cancelled {
+            return;
+        }
+        self.transition(MeasurementStatus::Running);
+        self.sent_probes += sent;
+        if end_of_measurement {
+            self.finished_workers += 1;
+            if self.finished_workers >= workers {
+                self.transition(MeasurementStatus::Completed);
+            }
+        }
+    }
+
+    /// Cancel the measurement, so that its remaining probes are dropped.
+    /// Returns the number of probes sent so far.
+    pub fn cancel(&mut self) -> u32 {
+        let sent_probes = match self.status {
+            MeasurementStatus::Running => self.sent_probes,
+            // Cancelled again, e.g. on every caracat instance
+            MeasurementStatus::Cancelled => return 0,
+            _ => 0,
+        };
+        debug_assert!(self.status.can_transition_to(MeasurementStatus::Cancelled));
+        *self = Measurement {
+            status: MeasurementStatus::Cancelled,
+            ..Measurement::new(&self.id)
+        };
+        sent_probes
+    }
+
+    /// Timing of the measurement once complete, forgotten afterwards.
+    pub fn take_timing(&mut self) -> Option<MeasurementTiming> {
+        match self.status {
+            MeasurementStatus::Completed => self.timing.take(),
+            _ => None,
+        }
+    }
+
+    /// Whether the measurement no longer needs to be tracked: completed, with its timing
+    /// taken. Cancelled measurements are kept, to drop their remaining probes.
+    pub fn is_done(&self) -> bool {
+        self.status == MeasurementStatus::Completed && self.timing.is_none()
+    }
+}
@@ -5,9 +5,39 @@ use caracat::models::Probe;
 use std::convert::TryInto;
 use std::io::Cursor;
 use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Arc;
 
 use crate::probe_capnp::probe;
 
+/// Saimiris-specific fields carried alongside a caracat probe.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProbeTags {
+    /// Round of the probe, for iterative algorithms (0 if unset).
+    pub round: u32,
+    /// DSCP of the probe, for DSCP-dependent routing and remarking (0 if unset).
+    pub dscp: u8,
+    /// Source address of the probe, instead of the source of the message.
+    pub src_addr: Option<IpAddr>,
+    /// Name of the caracat instance sending the probe, instead of the prefix-based selection.
+    pub instance: Option<String>,
+}
+
+impl ProbeTags {
+    pub fn is_empty(&self) -> bool {
+        *self == ProbeTags::default()
+    }
+}
+
+/// What the replies of a sent probe are attributed to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProbeContext {
+    pub tags: ProbeTags,
+    /// Measurement of the probe, shared by all the probes of a message.
+    pub measurement_id: Option<Arc<str>>,
+    /// Caracat instance sending the probe.
+    pub instance_id: u16,
+}
+
 pub fn serialize_ip_addr(ip: IpAddr) -> Vec<u8> {
     match ip {
         IpAddr::V4(addr) => addr.to_ipv6_mapped().octets().to_vec(),
@@ -25,6 +55,10 @@ pub fn serialize_protocol(protocol: caracat::models::L4) -> probe::Protocol {
 }
 
 pub fn serialize_probe(probe: &Probe) -> Vec<u8> {
+    serialize_tagged_probe(probe, &ProbeTags::default())
+}
+
+pub fn serialize_tagged_probe(probe: &Probe, tags: &ProbeTags) -> Vec<u8> {
     let mut message = Builder::new_default();
     {
         let mut p = message.init_root::<probe::Builder>();
@@ -33,6 +67,14 @@ pub fn serialize_probe(probe: &Probe) -> Vec<u8> {
         p.set_dst_port(probe.dst_port);
         p.set_ttl(probe.ttl);
         p.set_protocol(serialize_protocol(probe.protocol));
+        p.set_round(tags.round);
+        p.set_dscp(tags.dscp);
+        if let Some(src_addr) = tags.src_addr {
+            p.set_src_addr(&serialize_ip_addr(src_addr));
+        }
+        if let Some(instance) = &tags.instance {
+            p.set_instance(instance.as_str());
+        }
     }
 
     serialize::write_message_to_words(&message)
@@ -49,7 +91,7 @@ fn deserialize_protocol(protocol: probe::Protocol) -> Result<caracat::models::L4
     }
 }
 
-fn deserialize_ip_addr(data: &[u8]) -> Result<IpAddr> {
+pub fn deserialize_ip_addr(data: &[u8]) -> Result<IpAddr> {
     let bytes: [u8; 16] = data.try_into().map_err(|_| {
         anyhow!(
             "Invalid IP address byte length: expected 16, got {}",
@@ -64,6 +106,24 @@ fn deserialize_ip_addr(data: &[u8]) -> Result<IpAddr> {
     }
 }
 
+fn deserialize_tags_from_reader(p: probe::Reader) -> ProbeTags {
+    ProbeTags {
+        round: p.get_round(),
+        dscp: p.get_dscp(),
+        src_addr: p
+            .get_src_addr()
+            .ok()
+            .filter(|bytes| !bytes.is_empty())
+            .and_then(|bytes| deserialize_ip_addr(bytes).ok()),
+        instance: p
+            .get_instance()
+            .ok()
+            .and_then(|text| text.to_str().ok())
+            .filter(|name| !name.is_empty())
+            .map(str::to_string),
+    }
+}
+
 fn deserialize_single_probe_from_reader(p: probe::Reader) -> Result<Probe> {
     let dst_addr_bytes = p.get_dst_addr().context("Failed to get dst_addr")?;
     let dst_addr = deserialize_ip_addr(dst_addr_bytes)?;
@@ -84,7 +144,6 @@ fn deserialize_single_probe_from_reader(p: probe::Reader) -> Result<Probe> {
     })
 }
 
-#[allow(dead_code)]
 pub fn deserialize_probe(probe_bytes: Vec<u8>) -> Result<Probe> {
     let mut cursor = Cursor::new(probe_bytes);
     let message_reader = serialize::read_message(&mut cursor, ReaderOptions::new())
@@ -96,6 +155,13 @@ pub fn deserialize_probe(probe_bytes: Vec<u8>) -> Result<Probe> {
 }
 
 pub fn deserialize_probes(probes_bytes: Vec<u8>) -> Result<Vec<Probe>> {
+    Ok(deserialize_tagged_probes(probes_bytes)?
+        .into_iter()
+        .map(|(probe, _)| probe)
+        .collect())
+}
+
+pub fn deserialize_tagged_probes(probes_bytes: Vec<u8>) -> Result<Vec<(Probe, ProbeTags)>> {
     let mut probes = Vec::new();
     let mut cursor = Cursor::new(probes_bytes);
 
@@ -107,7 +173,7 @@ pub fn deserialize_probes(probes_bytes: Vec<u8>) -> Result<Vec<Probe>> {
                     .context("Failed to get probe root reader in stream")?;
                 let probe = deserialize_single_probe_from_reader(p)
                     .context("Failed to deserialize probe from reader in stream")?;
-                probes.push(probe);
+                probes.push((probe, deserialize_tags_from_reader(p)));
             }
             Err(e) => {
                 if e.kind == ErrorKind::PrematureEndOfFile {
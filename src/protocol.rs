@@ -0,0 +1,22 @@
+//! Kafka headers of the probes messages, written by the clients and read by the agents.
+//! Besides these, a message carries one header per agent it is intended for, keyed by the
+//! agent ID, and the signature headers of `signing`.
+
+/// Header holding the ID of the measurement of the probes.
+pub const MEASUREMENT_ID_HEADER: &str = "measurement_id";
+/// Header marking the last message of a measurement (`true` or `false`).
+pub const END_OF_MEASUREMENT_HEADER: &str = "end_of_measurement";
+/// Header giving the weight of the measurement of the probes in the fair scheduling of
+/// the agents (1 by default).
+pub const WEIGHT_HEADER: &str = "weight";
+/// Header carrying the ID of the measurement to cancel.
+pub const CANCEL_MEASUREMENT_HEADER: &str = "cancel_measurement";
+/// Header marking the probes of a message as targets, expanded by the agent into
+/// one probe per TTL of the range.
+pub const EXPAND_TTL_HEADER: &str = "expand_ttl";
+/// Header selecting the source ports of the probes of a message, overriding the
+/// `src_ports` of the agent.
+pub const SRC_PORTS_HEADER: &str = "src_ports";
+/// Header giving the priority of the probes of a message (0 by default).
+/// The messages of a higher priority are sent first.
+pub const PRIORITY_HEADER: &str = "priority";
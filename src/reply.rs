@@ -1,11 +1,213 @@
-use capnp::message::Builder;
-use capnp::serialize;
+use anyhow::{Context, Result};
+use capnp::message::{Builder, ReaderOptions};
+use capnp::traits::{HasStructSize, IntoInternalStructReader};
+use capnp::{serialize, ErrorKind};
 use caracat::models::Reply;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::{BufRead, Cursor};
+use std::net::IpAddr;
 
-use crate::probe::serialize_ip_addr;
+use crate::icmp_extension::IcmpExtension;
+use crate::probe::{deserialize_ip_addr, serialize_ip_addr, ProbeContext};
 use crate::reply_capnp::reply;
 
-pub fn serialize_reply(agent_id: String, reply: &Reply) -> Vec<u8> {
+/// A reply as decoded from the Kafka output, with field names matching the capnp schema.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReplyRecord {
+    pub time_received_ns: u64,
+    pub agent_id: String,
+    pub reply_src_addr: IpAddr,
+    pub reply_dst_addr: IpAddr,
+    pub reply_id: u16,
+    pub reply_size: u16,
+    pub reply_ttl: u8,
+    pub reply_quoted_ttl: u8,
+    pub reply_protocol: u8,
+    pub reply_icmp_type: u8,
+    pub reply_icmp_code: u8,
+    pub reply_mpls_labels: Vec<MplsRecord>,
+    pub probe_src_addr: IpAddr,
+    pub probe_dst_addr: IpAddr,
+    pub probe_id: u16,
+    pub probe_size: u16,
+    pub probe_ttl: u8,
+    pub probe_protocol: u8,
+    pub probe_src_port: u16,
+    pub probe_dst_port: u16,
+    pub rtt: u16,
+    pub round: u32,
+    // Empty if unknown
+    pub measurement_id: String,
+    pub instance_id: u16,
+    // ICMP extension objects other than the MPLS label stacks
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reply_icmp_extensions: Vec<IcmpExtensionRecord>,
+    // Fields of newer agents, unknown to this version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<ReplyExtensions>,
+}
+
+/// Fields of a reply produced by a newer agent, which this version does not know.
+/// They are kept opaque, so that converting replies does not silently drop them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplyExtensions {
+    /// Data section past the known fields, hex-encoded
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub data: String,
+    /// Pointer fields past the known ones by index, each as a hex-encoded capnp message
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pointers: BTreeMap<u16, String>,
+}
+
+impl ReplyExtensions {
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty() && self.pointers.is_empty()
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MplsRecord {
+    pub label: u32,
+    pub exp: u8,
+    pub s_bit: bool,
+    pub ttl: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IcmpExtensionRecord {
+    pub class_num: u8,
+    pub c_type: u8,
+    // Hex-encoded
+    pub payload: String,
+}
+
+impl From<&IcmpExtension> for IcmpExtensionRecord {
+    fn from(extension: &IcmpExtension) -> Self {
+        IcmpExtensionRecord {
+            class_num: extension.class_num,
+            c_type: extension.c_type,
+            payload: to_hex(&extension.payload),
+        }
+    }
+}
+
+/// A reply captured by the agent, with the ICMP extension objects other than the MPLS label
+/// stacks (which are in `reply_mpls_labels`).
+#[derive(Debug)]
+pub struct CapturedReply {
+    pub reply: Reply,
+    pub extensions: Vec<IcmpExtension>,
+}
+
+impl From<Reply> for CapturedReply {
+    fn from(reply: Reply) -> Self {
+        CapturedReply {
+            reply,
+            extensions: vec![],
+        }
+    }
+}
+
+/// Encoding of the replies produced to Kafka.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplyFormat {
+    /// Cap'n Proto messages, concatenated in batches
+    #[default]
+    Capnp,
+    /// A JSON object per message, for consumers which cannot read Cap'n Proto
+    Json,
+}
+
+impl ReplyFormat {
+    pub fn parse(format: &str) -> Result<Self> {
+        match format {
+            "" | "capnp" => Ok(ReplyFormat::Capnp),
+            "json" => Ok(ReplyFormat::Json),
+            other => anyhow::bail!(
+                "Invalid kafka.out_format '{}'. Expected one of: capnp, json",
+                other
+            ),
+        }
+    }
+}
+
+impl ReplyRecord {
+    /// Record of a reply sent by this agent, attributed with its probe context.
+    pub fn new(agent_id: String, captured: &CapturedReply, context: &ProbeContext) -> Self {
+        let reply = &captured.reply;
+        ReplyRecord {
+            time_received_ns: reply.capture_timestamp.as_nanos() as u64,
+            agent_id,
+            reply_src_addr: reply.reply_src_addr,
+            reply_dst_addr: reply.reply_dst_addr,
+            reply_id: reply.reply_id,
+            reply_size: reply.reply_size,
+            reply_ttl: reply.reply_ttl,
+            reply_quoted_ttl: reply.quoted_ttl,
+            reply_protocol: reply.reply_protocol,
+            reply_icmp_type: reply.reply_icmp_type,
+            reply_icmp_code: reply.reply_icmp_code,
+            reply_mpls_labels: reply
+                .reply_mpls_labels
+                .iter()
+                .map(|mpls_label| MplsRecord {
+                    label: mpls_label.label,
+                    exp: mpls_label.experimental,
+                    s_bit: mpls_label.bottom_of_stack,
+                    ttl: mpls_label.ttl,
+                })
+                .collect(),
+            probe_src_addr: reply.probe_src_addr,
+            probe_dst_addr: reply.probe_dst_addr,
+            probe_id: reply.probe_id,
+            probe_size: reply.probe_size,
+            probe_ttl: reply.probe_ttl,
+            probe_protocol: reply.probe_protocol,
+            probe_src_port: reply.probe_src_port,
+            probe_dst_port: reply.probe_dst_port,
+            rtt: reply.rtt,
+            round: context.tags.round,
+            measurement_id: context
+                .measurement_id
+                .as_deref()
+                .unwrap_or_default()
+                .to_string(),
+            instance_id: context.instance_id,
+            reply_icmp_extensions: captured.extensions.iter().map(Into::into).collect(),
+            extensions: None,
+        }
+    }
+}
+
+/// Serialize a reply in the given format.
+pub fn serialize_reply_as(
+    format: ReplyFormat,
+    agent_id: String,
+    reply: &CapturedReply,
+    context: &ProbeContext,
+) -> Vec<u8> {
+    match format {
+        ReplyFormat::Capnp => serialize_reply(agent_id, reply, context),
+        ReplyFormat::Json => serde_json::to_vec(&ReplyRecord::new(agent_id, reply, context))
+            .expect("replies are serializable to JSON"),
+    }
+}
+
+pub fn serialize_reply(
+    agent_id: String,
+    captured: &CapturedReply,
+    context: &ProbeContext,
+) -> Vec<u8> {
+    let reply = &captured.reply;
     let mut message = Builder::new_default();
     {
         let mut r = message.init_root::<reply::Builder>();
@@ -47,7 +249,194 @@ pub fn serialize_reply(agent_id: String, reply: &Reply) -> Vec<u8> {
 
         // RTT
         r.set_rtt(reply.rtt);
+
+        // Attribution of the originating probe
+        r.set_round(context.tags.round);
+        if let Some(measurement_id) = &context.measurement_id {
+            r.set_measurement_id(&**measurement_id);
+        }
+        r.set_instance_id(context.instance_id);
+
+        // ICMP extension objects, other than the MPLS label stacks
+        if !captured.extensions.is_empty() {
+            let mut extensions = r
+                .reborrow()
+                .init_reply_icmp_extensions(captured.extensions.len() as u32);
+            for (i, extension) in captured.extensions.iter().enumerate() {
+                extensions.set(i as u32, &extension.to_bytes());
+            }
+        }
     }
 
     serialize::write_message_to_words(&message)
 }
+
+/// Fields of `r` past the ones of the reply schema known to this version.
+fn deserialize_reply_extensions(r: reply::Reader) -> Result<Option<ReplyExtensions>> {
+    let known = <reply::Builder as HasStructSize>::STRUCT_SIZE;
+    let reader = r.into_internal_struct_reader();
+    let mut extensions = ReplyExtensions::default();
+
+    // Fields of newer agents left to their default value are zero, and need not be kept
+    let data = reader.get_data_section_as_blob();
+    let known_data_bytes = known.data as usize * 8;
+    if data.len() > known_data_bytes && data[known_data_bytes..].iter().any(|byte| *byte != 0) {
+        extensions.data = to_hex(&data[known_data_bytes..]);
+    }
+
+    for index in known.pointers..reader.get_pointer_section_size() {
+        let pointer = reader.get_pointer_field(index as usize);
+        if pointer.is_null() {
+            continue;
+        }
+        let mut message = Builder::new_default();
+        message
+            .set_root(capnp::any_pointer::Reader::new(pointer))
+            .with_context(|| format!("Failed to copy unknown reply pointer field {}", index))?;
+        extensions
+            .pointers
+            .insert(index, to_hex(&serialize::write_message_to_words(&message)));
+    }
+
+    Ok((!extensions.is_empty()).then_some(extensions))
+}
+
+fn deserialize_single_reply_from_reader(r: reply::Reader) -> Result<ReplyRecord> {
+    let mut reply_mpls_labels = Vec::new();
+    for mpls in r
+        .get_reply_mpls_label()
+        .context("Failed to get MPLS labels")?
+        .iter()
+    {
+        reply_mpls_labels.push(MplsRecord {
+            label: mpls.get_label(),
+            exp: mpls.get_exp(),
+            s_bit: mpls.get_s_bit(),
+            ttl: mpls.get_ttl(),
+        });
+    }
+
+    let mut reply_icmp_extensions = Vec::new();
+    if r.has_reply_icmp_extensions() {
+        for extension in r
+            .get_reply_icmp_extensions()
+            .context("Failed to get ICMP extensions")?
+            .iter()
+        {
+            let extension = extension.context("Failed to get ICMP extension")?;
+            let extension =
+                IcmpExtension::from_bytes(extension).context("Invalid ICMP extension object")?;
+            reply_icmp_extensions.push(IcmpExtensionRecord::from(&extension));
+        }
+    }
+
+    Ok(ReplyRecord {
+        time_received_ns: r.get_time_received_ns(),
+        agent_id: r
+            .get_agent_id()
+            .context("Failed to get agent_id")?
+            .to_str()
+            .context("Invalid agent_id")?
+            .to_string(),
+        reply_src_addr: deserialize_ip_addr(
+            r.get_reply_src_addr()
+                .context("Failed to get reply_src_addr")?,
+        )?,
+        reply_dst_addr: deserialize_ip_addr(
+            r.get_reply_dst_addr()
+                .context("Failed to get reply_dst_addr")?,
+        )?,
+        reply_id: r.get_reply_id(),
+        reply_size: r.get_reply_size(),
+        reply_ttl: r.get_reply_ttl(),
+        reply_quoted_ttl: r.get_reply_quoted_ttl(),
+        reply_protocol: r.get_reply_protocol(),
+        reply_icmp_type: r.get_reply_icmp_type(),
+        reply_icmp_code: r.get_reply_icmp_code(),
+        reply_mpls_labels,
+        probe_src_addr: deserialize_ip_addr(
+            r.get_probe_src_addr()
+                .context("Failed to get probe_src_addr")?,
+        )?,
+        probe_dst_addr: deserialize_ip_addr(
+            r.get_probe_dst_addr()
+                .context("Failed to get probe_dst_addr")?,
+        )?,
+        probe_id: r.get_probe_id(),
+        probe_size: r.get_probe_size(),
+        probe_ttl: r.get_probe_ttl(),
+        probe_protocol: r.get_probe_protocol(),
+        probe_src_port: r.get_probe_src_port(),
+        probe_dst_port: r.get_probe_dst_port(),
+        rtt: r.get_rtt(),
+        round: r.get_round(),
+        measurement_id: if r.has_measurement_id() {
+            r.get_measurement_id()
+                .context("Failed to get measurement_id")?
+                .to_str()
+                .context("Invalid measurement_id")?
+                .to_string()
+        } else {
+            String::new()
+        },
+        instance_id: r.get_instance_id(),
+        reply_icmp_extensions,
+        extensions: deserialize_reply_extensions(r)?,
+    })
+}
+
+/// Replies read one at a time from a stream of Cap'n Proto messages, such as a dump of the
+/// replies topic, without holding the whole stream in memory. A truncated last message ends
+/// the stream, the first invalid message ends it with an error.
+pub struct ReplyReader<R> {
+    reader: R,
+    done: bool,
+}
+
+impl<R: BufRead> ReplyReader<R> {
+    pub fn new(reader: R) -> Self {
+        ReplyReader {
+            reader,
+            done: false,
+        }
+    }
+
+    fn read_reply(&mut self) -> Option<Result<ReplyRecord>> {
+        // The stream ends between two messages
+        match self.reader.fill_buf() {
+            Ok([]) => return None,
+            Ok(_) => {}
+            Err(e) => return Some(Err(e).context("Failed to read reply stream")),
+        }
+        let message_reader = match serialize::read_message(&mut self.reader, ReaderOptions::new()) {
+            Ok(message_reader) => message_reader,
+            Err(e) if e.kind == ErrorKind::PrematureEndOfFile => return None,
+            Err(e) => return Some(Err(e).context("Failed to read capnp message from stream")),
+        };
+        let reply = message_reader
+            .get_root::<reply::Reader>()
+            .context("Failed to get reply root reader in stream")
+            .and_then(|r| {
+                deserialize_single_reply_from_reader(r)
+                    .context("Failed to deserialize reply from reader in stream")
+            });
+        Some(reply)
+    }
+}
+
+impl<R: BufRead> Iterator for ReplyReader<R> {
+    type Item = Result<ReplyRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let reply = self.read_reply();
+        self.done = !matches!(reply, Some(Ok(_)));
+        reply
+    }
+}
+
+pub fn deserialize_replies(replies_bytes: Vec<u8>) -> Result<Vec<ReplyRecord>> {
+    ReplyReader::new(Cursor::new(replies_bytes)).collect()
+}
@@ -174,11 +174,35 @@ pub mod reply {
         pub fn get_rtt(self) -> u16 {
             self.reader.get_data_field::<u16>(14)
         }
+        #[inline]
+        pub fn get_round(self) -> u32 {
+            self.reader.get_data_field::<u32>(8)
+        }
+        #[inline]
+        pub fn get_measurement_id(self) -> ::capnp::Result<::capnp::text::Reader<'a>> {
+            ::capnp::traits::FromPointerReader::get_from_pointer(&self.reader.get_pointer_field(6), ::core::option::Option::None)
+        }
+        #[inline]
+        pub fn has_measurement_id(&self) -> bool {
+            !self.reader.get_pointer_field(6).is_null()
+        }
+        #[inline]
+        pub fn get_instance_id(self) -> u16 {
+            self.reader.get_data_field::<u16>(15)
+        }
+        #[inline]
+        pub fn get_reply_icmp_extensions(self) -> ::capnp::Result<::capnp::data_list::Reader<'a>> {
+            ::capnp::traits::FromPointerReader::get_from_pointer(&self.reader.get_pointer_field(7), ::core::option::Option::None)
+        }
+        #[inline]
+        pub fn has_reply_icmp_extensions(&self) -> bool {
+            !self.reader.get_pointer_field(7).is_null()
+        }
     }
 
     pub struct Builder<'a> { builder: ::capnp::private::layout::StructBuilder<'a> }
     impl <> ::capnp::traits::HasStructSize for Builder<'_,>  {
-        const STRUCT_SIZE: ::capnp::private::layout::StructSize = ::capnp::private::layout::StructSize { data: 4, pointers: 6 };
+        const STRUCT_SIZE: ::capnp::private::layout::StructSize = ::capnp::private::layout::StructSize { data: 5, pointers: 8 };
     }
     impl <> ::capnp::traits::HasTypeId for Builder<'_,>  {
         const TYPE_ID: u64 = _private::TYPE_ID;
@@ -444,6 +468,54 @@ pub mod reply {
         pub fn set_rtt(&mut self, value: u16)  {
             self.builder.set_data_field::<u16>(14, value);
         }
+        #[inline]
+        pub fn get_round(self) -> u32 {
+            self.builder.get_data_field::<u32>(8)
+        }
+        #[inline]
+        pub fn set_round(&mut self, value: u32)  {
+            self.builder.set_data_field::<u32>(8, value);
+        }
+        #[inline]
+        pub fn get_measurement_id(self) -> ::capnp::Result<::capnp::text::Builder<'a>> {
+            ::capnp::traits::FromPointerBuilder::get_from_pointer(self.builder.get_pointer_field(6), ::core::option::Option::None)
+        }
+        #[inline]
+        pub fn set_measurement_id(&mut self, value: impl ::capnp::traits::SetterInput<::capnp::text::Owned>)  {
+            ::capnp::traits::SetterInput::set_pointer_builder(self.builder.reborrow().get_pointer_field(6), value, false).unwrap()
+        }
+        #[inline]
+        pub fn init_measurement_id(self, size: u32) -> ::capnp::text::Builder<'a> {
+            self.builder.get_pointer_field(6).init_text(size)
+        }
+        #[inline]
+        pub fn has_measurement_id(&self) -> bool {
+            !self.builder.is_pointer_field_null(6)
+        }
+        #[inline]
+        pub fn get_instance_id(self) -> u16 {
+            self.builder.get_data_field::<u16>(15)
+        }
+        #[inline]
+        pub fn set_instance_id(&mut self, value: u16)  {
+            self.builder.set_data_field::<u16>(15, value);
+        }
+        #[inline]
+        pub fn get_reply_icmp_extensions(self) -> ::capnp::Result<::capnp::data_list::Builder<'a>> {
+            ::capnp::traits::FromPointerBuilder::get_from_pointer(self.builder.get_pointer_field(7), ::core::option::Option::None)
+        }
+        #[inline]
+        pub fn set_reply_icmp_extensions(&mut self, value: impl ::capnp::traits::SetterInput<::capnp::data_list::Owned>) -> ::capnp::Result<()> {
+            ::capnp::traits::SetterInput::set_pointer_builder(self.builder.reborrow().get_pointer_field(7), value, false)
+        }
+        #[inline]
+        pub fn init_reply_icmp_extensions(self, size: u32) -> ::capnp::data_list::Builder<'a> {
+            ::capnp::traits::FromPointerBuilder::init_pointer(self.builder.get_pointer_field(7), size)
+        }
+        #[inline]
+        pub fn has_reply_icmp_extensions(&self) -> bool {
+            !self.builder.is_pointer_field_null(7)
+        }
     }
 
     pub struct Pipeline { _typeless: ::capnp::any_pointer::Pipeline }
@@ -834,6 +906,10 @@ pub mod reply {
                 18 => <u16 as ::capnp::introspect::Introspect>::introspect(),
                 19 => <u16 as ::capnp::introspect::Introspect>::introspect(),
                 20 => <u16 as ::capnp::introspect::Introspect>::introspect(),
+                21 => <u32 as ::capnp::introspect::Introspect>::introspect(),
+                22 => <::capnp::text::Owned as ::capnp::introspect::Introspect>::introspect(),
+                23 => <u16 as ::capnp::introspect::Introspect>::introspect(),
+                24 => <::capnp::data_list::Owned as ::capnp::introspect::Introspect>::introspect(),
                 _ => ::capnp::introspect::panic_invalid_field_index(index),
             }
         }
@@ -847,9 +923,9 @@ pub mod reply {
             MEMBERS_BY_DISCRIMINANT,
             MEMBERS_BY_NAME
         );
-        pub(crate) static NONUNION_MEMBERS : &[u16] = &[0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20];
+        pub(crate) static NONUNION_MEMBERS : &[u16] = &[0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24];
         pub(crate) static MEMBERS_BY_DISCRIMINANT : &[u16] = &[];
-        pub(crate) static MEMBERS_BY_NAME : &[u16] = &[1,13,19,14,17,15,12,18,16,3,10,9,4,11,8,7,5,2,6,20,0];
+        pub(crate) static MEMBERS_BY_NAME : &[u16] = &[1,23,22,13,19,14,17,15,12,18,16,3,10,24,9,4,11,8,7,5,2,6,21,20,0];
         pub(crate) const TYPE_ID: u64 = 0xdc6b_439a_4945_fcd7;
     }
 }
@@ -0,0 +1,176 @@
+use anyhow::{Context, Result};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::config::SchemaRegistryConfig;
+
+/// First byte of the messages framed with their schema ID (Confluent wire format).
+pub const MAGIC_BYTE: u8 = 0;
+/// Magic byte and big-endian schema ID prepended to the payloads.
+pub const FRAME_LEN: usize = 5;
+
+pub const PROBE_SCHEMA: &str = include_str!("../schemas/probe.capnp");
+pub const REPLY_SCHEMA: &str = include_str!("../schemas/reply.capnp");
+
+const CONTENT_TYPE: &str = "application/vnd.schemaregistry.v1+json";
+
+/// Why a consumed message fails the schema validation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaError {
+    /// The message is not framed with a schema ID
+    Unframed,
+    /// The schema ID is not registered for the subject of the topic
+    UnknownSchema(u32),
+}
+
+impl SchemaError {
+    /// Label of the error in the metrics.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            SchemaError::Unframed => "schema_unframed",
+            SchemaError::UnknownSchema(_) => "schema_unknown",
+        }
+    }
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaError::Unframed => write!(f, "message is not framed with a schema ID"),
+            SchemaError::UnknownSchema(id) => {
+                write!(f, "schema {} is not registered for the topic", id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// Subject of the values of a topic (`TopicNameStrategy`).
+pub fn subject(topic: &str) -> String {
+    format!("{}-value", topic)
+}
+
+/// Prepend the magic byte and the schema ID to a payload.
+pub fn frame(schema_id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(FRAME_LEN + payload.len());
+    framed.push(MAGIC_BYTE);
+    framed.extend_from_slice(&schema_id.to_be_bytes());
+    framed.extend_from_slice(payload);
+    framed
+}
+
+/// Schema ID and payload of a framed message.
+pub fn unframe(message: &[u8]) -> Option<(u32, &[u8])> {
+    if message.len() < FRAME_LEN || message[0] != MAGIC_BYTE {
+        return None;
+    }
+    let schema_id = u32::from_be_bytes(message[1..FRAME_LEN].try_into().ok()?);
+    Some((schema_id, &message[FRAME_LEN..]))
+}
+
+#[derive(Deserialize)]
+struct RegisteredSchema {
+    id: u32,
+}
+
+/// Client of a Confluent-compatible schema registry, registering the schemas of the probes
+/// and replies, and validating the schema ID of the consumed messages.
+pub struct SchemaRegistry {
+    client: Client,
+    config: SchemaRegistryConfig,
+    // Subjects of the schema IDs already looked up
+    subjects: Mutex<HashMap<u32, Vec<String>>>,
+}
+
+impl SchemaRegistry {
+    pub fn new(config: SchemaRegistryConfig) -> Self {
+        SchemaRegistry {
+            client: Client::new(),
+            config,
+            subjects: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn authenticated(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.config.username {
+            Some(username) => request.basic_auth(username, self.config.password.as_ref()),
+            None => request,
+        }
+    }
+
+    /// Register a schema under a subject, returning its ID. Registering a schema already
+    /// registered returns its existing ID.
+    pub async fn register(&self, subject: &str, schema: &str) -> Result<u32> {
+        let url = format!(
+            "{}/subjects/{}/versions",
+            self.config.url.trim_end_matches('/'),
+            subject
+        );
+        let response = self
+            .authenticated(self.client.post(&url))
+            .header("content-type", CONTENT_TYPE)
+            .json(&serde_json::json!({
+                "schemaType": self.config.schema_type,
+                "schema": schema,
+            }))
+            .send()
+            .await
+            .with_context(|| format!("Failed to register the schema of {}", subject))?;
+        if !response.status().is_success() {
+            anyhow::bail!(
+                "Failed to register the schema of {}: HTTP {}: {}",
+                subject,
+                response.status(),
+                response.text().await.unwrap_or_default()
+            );
+        }
+        Ok(response.json::<RegisteredSchema>().await?.id)
+    }
+
+    /// Subjects under which a schema ID is registered.
+    async fn subjects(&self, schema_id: u32) -> Result<Vec<String>> {
+        if let Some(subjects) = self.subjects.lock().unwrap().get(&schema_id) {
+            return Ok(subjects.clone());
+        }
+        let url = format!(
+            "{}/schemas/ids/{}/subjects",
+            self.config.url.trim_end_matches('/'),
+            schema_id
+        );
+        let response = self
+            .authenticated(self.client.get(&url))
+            .send()
+            .await
+            .context("Failed to look up the schema")?;
+        let subjects = match response.status() {
+            StatusCode::NOT_FOUND => Vec::new(),
+            status if status.is_success() => response.json::<Vec<String>>().await?,
+            status => anyhow::bail!("Failed to look up schema {}: HTTP {}", schema_id, status),
+        };
+        self.subjects
+            .lock()
+            .unwrap()
+            .insert(schema_id, subjects.clone());
+        Ok(subjects)
+    }
+
+    /// Check that a message consumed from `topic` is framed with a schema of the topic,
+    /// returning its payload. The outer error is raised when the registry is unavailable.
+    pub async fn validate<'a>(
+        &self,
+        topic: &str,
+        message: &'a [u8],
+    ) -> Result<Result<&'a [u8], SchemaError>> {
+        let Some((schema_id, payload)) = unframe(message) else {
+            return Ok(Err(SchemaError::Unframed));
+        };
+        if self.subjects(schema_id).await?.contains(&subject(topic)) {
+            Ok(Ok(payload))
+        } else {
+            Ok(Err(SchemaError::UnknownSchema(schema_id)))
+        }
+    }
+}
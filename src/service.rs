@@ -0,0 +1,41 @@
+use anyhow::Result;
+use tracing::info;
+
+/// Wait for the service manager (systemd, launchd, ...) to request the agent to stop.
+pub async fn shutdown_signal() -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = sigterm.recv() => info!("Received SIGTERM"),
+        _ = sigint.recv() => info!("Received SIGINT"),
+    }
+    Ok(())
+}
+
+/// Notify systemd of the state of the agent (e.g. `READY=1` or `STOPPING=1`), for units of
+/// `Type=notify`. Nothing is sent without `NOTIFY_SOCKET` (e.g. under launchd).
+pub fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let result = UnixDatagram::unbound().and_then(|datagram| {
+        // Abstract socket, named after the '@'
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+            use std::os::unix::net::SocketAddr;
+            if let Some(name) = socket.as_encoded_bytes().strip_prefix(b"@") {
+                let address = SocketAddr::from_abstract_name(name)?;
+                return datagram.send_to_addr(state.as_bytes(), &address);
+            }
+        }
+        datagram.send_to(state.as_bytes(), &socket)
+    });
+    if let Err(e) = result {
+        tracing::warn!("Failed to notify systemd of {}: {}", state, e);
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::Result;
+use ring::hmac;
+use std::collections::HashMap;
+
+use crate::reply::to_hex;
+
+/// Header holding the ID of the key signing a probes message.
+pub const SIGNATURE_KEY_ID_HEADER: &str = "signature_key_id";
+/// Header holding the HMAC-SHA256 of a probes message, hex-encoded.
+pub const SIGNATURE_HEADER: &str = "signature";
+/// Length of the `signature` header value (hex-encoded HMAC-SHA256).
+pub const SIGNATURE_LEN: usize = 64;
+
+/// Why a message is rejected by the signature verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    /// The message carries no signature
+    Missing,
+    /// The message is signed with a key unknown to the agent
+    UnknownKey(String),
+    /// The signature does not match the message
+    Invalid(String),
+}
+
+impl SignatureError {
+    /// Label of the error in the metrics.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            SignatureError::Missing => "missing",
+            SignatureError::UnknownKey(_) => "unknown_key",
+            SignatureError::Invalid(_) => "invalid",
+        }
+    }
+}
+
+impl std::fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "message is not signed"),
+            SignatureError::UnknownKey(id) => write!(f, "unknown signing key '{}'", id),
+            SignatureError::Invalid(id) => write!(f, "invalid signature with key '{}'", id),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+/// Content covered by the signature: the headers (but the signature ones), in a canonical
+/// order so that they can be reordered by Kafka, then the payload. Each field is
+/// length-prefixed, so that no two messages share the same content.
+fn signed_content<'a>(
+    payload: &[u8],
+    headers: impl IntoIterator<Item = (&'a str, Option<&'a [u8]>)>,
+) -> Vec<u8> {
+    let mut headers: Vec<_> = headers
+        .into_iter()
+        .filter(|(key, _)| *key != SIGNATURE_KEY_ID_HEADER && *key != SIGNATURE_HEADER)
+        .collect();
+    headers.sort();
+
+    let mut content = Vec::new();
+    for (key, value) in headers {
+        content.extend_from_slice(&(key.len() as u32).to_be_bytes());
+        content.extend_from_slice(key.as_bytes());
+        match value {
+            Some(value) => {
+                content.push(1);
+                content.extend_from_slice(&(value.len() as u32).to_be_bytes());
+                content.extend_from_slice(value);
+            }
+            None => content.push(0),
+        }
+    }
+    content.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    content.extend_from_slice(payload);
+    content
+}
+
+/// Signature of a message, to be set in the `signature` header.
+pub fn sign<'a>(
+    key: &str,
+    payload: &[u8],
+    headers: impl IntoIterator<Item = (&'a str, Option<&'a [u8]>)>,
+) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+    to_hex(hmac::sign(&key, &signed_content(payload, headers)).as_ref())
+}
+
+fn from_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// Verifies the signature of the probes messages, with the key of each client
+/// (`agent.signing_keys`).
+#[derive(Clone, Default)]
+pub struct SignatureVerifier {
+    keys: HashMap<String, hmac::Key>,
+}
+
+impl SignatureVerifier {
+    pub fn new(keys: &HashMap<String, String>) -> Result<Self> {
+        let keys = keys
+            .iter()
+            .map(|(id, key)| {
+                if key.is_empty() {
+                    anyhow::bail!("Empty agent.signing_keys key for client '{}'", id);
+                }
+                Ok((
+                    id.clone(),
+                    hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes()),
+                ))
+            })
+            .collect::<Result<_>>()?;
+        Ok(SignatureVerifier { keys })
+    }
+
+    /// Whether messages must be signed, i.e. signing keys are configured.
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    /// Verify a message, returning the ID of its signing key.
+    pub fn verify<'a>(
+        &self,
+        payload: &[u8],
+        headers: impl IntoIterator<Item = (&'a str, Option<&'a [u8]>)>,
+    ) -> Result<String, SignatureError> {
+        let headers: Vec<_> = headers.into_iter().collect();
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(key, _)| *key == name)
+                .and_then(|(_, value)| *value)
+        };
+        let (Some(key_id), Some(signature)) =
+            (header(SIGNATURE_KEY_ID_HEADER), header(SIGNATURE_HEADER))
+        else {
+            return Err(SignatureError::Missing);
+        };
+
+        let key_id = String::from_utf8_lossy(key_id).to_string();
+        let key = self
+            .keys
+            .get(&key_id)
+            .ok_or_else(|| SignatureError::UnknownKey(key_id.clone()))?;
+        let signature =
+            from_hex(signature).ok_or_else(|| SignatureError::Invalid(key_id.clone()))?;
+        hmac::verify(key, &signed_content(payload, headers), &signature)
+            .map_err(|_| SignatureError::Invalid(key_id.clone()))?;
+        Ok(key_id)
+    }
+}
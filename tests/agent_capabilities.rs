@@ -0,0 +1,166 @@
+//! Unit tests for the agent capabilities checked by the client before submission
+use saimiris::agent::capabilities::{
+    check_agents, AgentCapabilities, Requirements, FEATURE_CANCELLATION, FEATURE_DSCP,
+    FEATURE_MEASUREMENT_TRACKING, FEATURE_SRC_ADDR,
+};
+use saimiris::agent::duplicate::is_duplicate;
+use saimiris::probe::ProbeTags;
+use std::collections::HashMap;
+
+fn original_agent(agent_id: &str) -> AgentCapabilities {
+    AgentCapabilities {
+        agent_id: agent_id.to_string(),
+        version: "0.1.0".to_string(),
+        probe_schema_versions: vec![1],
+        features: vec![],
+        run_id: String::new(),
+        published_at_ns: 0,
+        started_at_ns: 0,
+    }
+}
+
+#[test]
+fn test_current_capabilities_roundtrip() {
+    let capabilities = AgentCapabilities::current("agent1");
+    assert!(capabilities.probe_schema_versions.contains(&2));
+    assert!(capabilities
+        .features
+        .contains(&FEATURE_MEASUREMENT_TRACKING.to_string()));
+
+    let json = serde_json::to_string(&capabilities).unwrap();
+    let parsed: AgentCapabilities = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, capabilities);
+}
+
+#[test]
+fn test_submission_requirements() {
+    let requirements = Requirements::submission(&ProbeTags::default(), None);
+    assert_eq!(requirements.probe_schema_version, 1);
+    assert!(requirements.features.is_empty());
+
+    let requirements = Requirements::submission(
+        &ProbeTags {
+            round: 3,
+            ..Default::default()
+        },
+        Some("m1"),
+    );
+    assert_eq!(requirements.probe_schema_version, 2);
+    assert_eq!(requirements.features, vec![FEATURE_MEASUREMENT_TRACKING]);
+}
+
+#[test]
+fn test_check_capabilities() {
+    let current = AgentCapabilities::current("agent1");
+    let original = original_agent("agent2");
+
+    let plain = Requirements::submission(&ProbeTags::default(), None);
+    assert!(current.check(&plain).is_ok());
+    assert!(original.check(&plain).is_ok());
+
+    let with_round = Requirements::submission(
+        &ProbeTags {
+            round: 1,
+            ..Default::default()
+        },
+        None,
+    );
+    assert!(current.check(&with_round).is_ok());
+    let error = original.check(&with_round).unwrap_err().to_string();
+    assert!(error.contains("probe schema version 2"), "{}", error);
+
+    let error = original
+        .check(&Requirements::cancellation())
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains(FEATURE_CANCELLATION), "{}", error);
+}
+
+#[test]
+fn test_dscp_requirements() {
+    let requirements = Requirements::submission(
+        &ProbeTags {
+            dscp: 46,
+            ..Default::default()
+        },
+        None,
+    );
+    assert_eq!(requirements.probe_schema_version, 3);
+    assert_eq!(requirements.features, vec![FEATURE_DSCP]);
+
+    // caracat cannot mark the probes, only the TX ring sender backend
+    let error = AgentCapabilities::current("agent1")
+        .check(&requirements)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains(FEATURE_DSCP), "{}", error);
+    assert!(AgentCapabilities::current("agent1")
+        .with_dscp(true)
+        .check(&requirements)
+        .is_ok());
+}
+
+#[test]
+fn test_src_addr_requirements() {
+    let requirements = Requirements::submission(
+        &ProbeTags {
+            round: 1,
+            ..Default::default()
+        },
+        None,
+    );
+    assert_eq!(requirements.clone().with_sources(false), requirements);
+
+    let requirements = requirements.with_sources(true);
+    assert_eq!(requirements.probe_schema_version, 4);
+    assert_eq!(requirements.features, vec![FEATURE_SRC_ADDR]);
+    assert!(AgentCapabilities::current("agent1")
+        .check(&requirements)
+        .is_ok());
+    assert!(original_agent("agent2").check(&requirements).is_err());
+}
+
+#[test]
+fn test_check_agents_reports_unknown_agents() {
+    let capabilities = HashMap::from([
+        ("agent1".to_string(), AgentCapabilities::current("agent1")),
+        ("agent2".to_string(), original_agent("agent2")),
+    ]);
+    let agents = vec!["agent1".to_string(), "agent3".to_string()];
+    let requirements = Requirements::submission(
+        &ProbeTags {
+            round: 1,
+            ..Default::default()
+        },
+        None,
+    );
+    assert_eq!(
+        check_agents(&capabilities, &agents, &requirements).unwrap(),
+        vec!["agent3".to_string()]
+    );
+
+    let agents = vec!["agent1".to_string(), "agent2".to_string()];
+    assert!(check_agents(&capabilities, &agents, &requirements).is_err());
+}
+
+#[test]
+fn test_duplicate_agent_id() {
+    let record = AgentCapabilities {
+        run_id: "run2".to_string(),
+        published_at_ns: 2_000,
+        ..AgentCapabilities::current("agent1")
+    };
+    assert!(is_duplicate(&record, "agent1", "run1", 1_000));
+
+    // Our own records, records of other agents, and records of previous runs
+    assert!(!is_duplicate(&record, "agent1", "run2", 1_000));
+    assert!(!is_duplicate(&record, "agent2", "run1", 1_000));
+    assert!(!is_duplicate(&record, "agent1", "run1", 3_000));
+
+    // Agents predating the run ID cannot be told apart
+    let record = AgentCapabilities {
+        published_at_ns: 2_000,
+        ..AgentCapabilities::current("agent1")
+    };
+    assert!(!is_duplicate(&record, "agent1", "run1", 1_000));
+}
@@ -0,0 +1,33 @@
+//! Tests for the agent ID generated when none is configured
+use saimiris::agent::identity::{generate_agent_id, persisted_agent_id, ID_LEN};
+use tempfile::tempdir;
+
+#[test]
+fn test_generate_agent_id() {
+    let id = generate_agent_id();
+    assert_eq!(id.len(), ID_LEN);
+    assert!(id
+        .chars()
+        .all(|c| c.is_ascii_digit() || c.is_ascii_lowercase()));
+    assert_ne!(id, generate_agent_id());
+}
+
+#[test]
+fn test_persisted_agent_id() {
+    let dir = tempdir().unwrap();
+    let id_file = dir.path().join("state").join("agent_id");
+
+    // Generated on the first run, then kept
+    let id = persisted_agent_id(&id_file).unwrap();
+    assert_eq!(std::fs::read_to_string(&id_file).unwrap().trim(), id);
+    assert_eq!(persisted_agent_id(&id_file).unwrap(), id);
+
+    // An ID written by hand is used as is
+    std::fs::write(&id_file, "agent1\n").unwrap();
+    assert_eq!(persisted_agent_id(&id_file).unwrap(), "agent1");
+
+    // An empty file gets a new ID
+    std::fs::write(&id_file, "").unwrap();
+    let id = persisted_agent_id(&id_file).unwrap();
+    assert_eq!(id.len(), ID_LEN);
+}
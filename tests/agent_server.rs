@@ -0,0 +1,240 @@
+use http_body_util::BodyExt;
+use hyper::{Method, StatusCode};
+use metrics_exporter_prometheus::PrometheusBuilder;
+use saimiris::agent::control::AgentMode;
+use saimiris::agent::events::EventLog;
+use saimiris::agent::server::{AgentState, LoopKind, Server};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+fn server(auth_tokens: HashMap<String, String>) -> (Server, std::sync::Arc<AgentState>) {
+    let state = AgentState::new("agent1".to_string(), 2);
+    let metrics = PrometheusBuilder::new().build_recorder().handle();
+    (Server::new(state.clone(), metrics, auth_tokens), state)
+}
+
+#[tokio::test]
+async fn test_server_routes() {
+    let (server, state) = server(HashMap::new());
+
+    assert_eq!(
+        server.route(&Method::GET, "/metrics", None, None).status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        server.route(&Method::GET, "/healthz", None, None).status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        server.route(&Method::GET, "/unknown", None, None).status(),
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        server.route(&Method::POST, "/status", None, None).status(),
+        StatusCode::METHOD_NOT_ALLOWED
+    );
+
+    // Readiness follows the agent state
+    assert_eq!(
+        server.route(&Method::GET, "/readyz", None, None).status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    state.set_ready(true);
+    let _send_loop = state.register_loop(LoopKind::Send);
+    let _receive_loop = state.register_loop(LoopKind::Receive);
+    assert_eq!(
+        server.route(&Method::GET, "/readyz", None, None).status(),
+        StatusCode::OK
+    );
+
+    let body = server
+        .route(&Method::GET, "/status", None, None)
+        .into_body()
+        .collect()
+        .await
+        .unwrap()
+        .to_bytes();
+    let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(status["agent_id"], "agent1");
+    assert_eq!(status["ready"], true);
+    assert_eq!(status["caracat_instances"], 2);
+    assert_eq!(status["send_loops"], 1);
+    assert_eq!(status["receive_loops"], 1);
+}
+
+#[test]
+fn test_readiness_running_loops() {
+    let (server, state) = server(HashMap::new());
+    state.set_ready(true);
+
+    // Ready with at least one SendLoop and one ReceiveLoop running
+    let send_loops = vec![
+        state.register_loop(LoopKind::Send),
+        state.register_loop(LoopKind::Send),
+    ];
+    assert_eq!(
+        server.route(&Method::GET, "/readyz", None, None).status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    let receive_loop = state.register_loop(LoopKind::Receive);
+    assert_eq!(
+        server.route(&Method::GET, "/readyz", None, None).status(),
+        StatusCode::OK
+    );
+
+    // No longer ready once all the threads of a kind have exited
+    drop(receive_loop);
+    assert_eq!(state.running_loops(LoopKind::Receive), 0);
+    assert_eq!(
+        server.route(&Method::GET, "/readyz", None, None).status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    // Liveness does not depend on the loops
+    assert_eq!(
+        server.route(&Method::GET, "/healthz", None, None).status(),
+        StatusCode::OK
+    );
+    drop(send_loops);
+    assert_eq!(state.running_loops(LoopKind::Send), 0);
+}
+
+#[tokio::test]
+async fn test_server_route_auth() {
+    let mut auth_tokens = HashMap::new();
+    auth_tokens.insert("status".to_string(), "secret".to_string());
+    let (server, _) = server(auth_tokens);
+
+    assert_eq!(
+        server.route(&Method::GET, "/status", None, None).status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        server
+            .route(&Method::GET, "/status", Some("Bearer wrong"), None)
+            .status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        server
+            .route(&Method::GET, "/status", Some("Bearer secret"), None)
+            .status(),
+        StatusCode::OK
+    );
+    // Tokens of another length are rejected as well
+    assert_eq!(
+        server
+            .route(&Method::GET, "/status", Some("Bearer secret2"), None)
+            .status(),
+        StatusCode::UNAUTHORIZED
+    );
+    // Routes without a token stay open
+    assert_eq!(
+        server.route(&Method::GET, "/metrics", None, None).status(),
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn test_server_control() {
+    let (mode_tx, mode_rx) = watch::channel(AgentMode::default());
+    let mode_tx = Arc::new(mode_tx);
+
+    // Not served without a control token
+    let (open_server, _) = server(HashMap::new());
+    let open_server = open_server.with_control(mode_tx.clone(), EventLog::disabled());
+    assert_eq!(
+        open_server
+            .route(&Method::POST, "/control/pause", None, None)
+            .status(),
+        StatusCode::NOT_FOUND
+    );
+
+    let mut auth_tokens = HashMap::new();
+    auth_tokens.insert("control".to_string(), "secret".to_string());
+    let (control_server, _) = server(auth_tokens);
+    let server = control_server.with_control(mode_tx, EventLog::disabled());
+    assert_eq!(
+        server
+            .route(&Method::POST, "/control/pause", None, None)
+            .status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        server
+            .route(&Method::GET, "/control/pause", Some("Bearer secret"), None)
+            .status(),
+        StatusCode::METHOD_NOT_ALLOWED
+    );
+    assert_eq!(*mode_rx.borrow(), AgentMode::Running);
+
+    let response = server.route(&Method::POST, "/control/pause", Some("Bearer secret"), None);
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let mode: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(mode["mode"], "paused");
+    assert_eq!(*mode_rx.borrow(), AgentMode::Paused);
+
+    server.route(
+        &Method::POST,
+        "/control/resume",
+        Some("Bearer secret"),
+        None,
+    );
+    assert_eq!(*mode_rx.borrow(), AgentMode::Running);
+}
+
+#[tokio::test]
+async fn test_server_degraded() {
+    let (server, state) = server(HashMap::new());
+    state.set_ready(true);
+    let _send_loop = state.register_loop(LoopKind::Send);
+    let _receive_loop = state.register_loop(LoopKind::Receive);
+    state.set_degraded(true);
+
+    // Degraded agents stay alive, but are no longer ready
+    assert_eq!(
+        server.route(&Method::GET, "/healthz", None, None).status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        server.route(&Method::GET, "/readyz", None, None).status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    let body = server
+        .route(&Method::GET, "/status", None, None)
+        .into_body()
+        .collect()
+        .await
+        .unwrap()
+        .to_bytes();
+    let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(status["degraded"], true);
+}
+
+#[tokio::test]
+async fn test_server_openmetrics() {
+    let (server, _) = server(HashMap::new());
+
+    let response = server.route(&Method::GET, "/metrics", None, None);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/plain; version=0.0.4"
+    );
+
+    // Scrapers accepting OpenMetrics get the exemplars
+    let response = server.route(
+        &Method::GET,
+        "/metrics",
+        None,
+        Some("application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5"),
+    );
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("application/openmetrics-text"));
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(body.ends_with(b"# EOF\n"));
+}
@@ -1,7 +1,10 @@
 //! Unit tests for agent logic (saimiris)
-use saimiris::agent::handler::determine_target_sender;
+use caracat::models::{Probe, L4};
+use saimiris::agent::control::{parse_control_message, AgentMode, ControlCommand};
+use saimiris::agent::handler::{determine_pinned_sender, determine_target_sender, group_probes};
 use saimiris::agent::sender::ProbesWithSource;
 use saimiris::config::CaracatConfig;
+use saimiris::probe::ProbeTags;
 use std::collections::HashMap;
 use tokio::sync::mpsc::channel;
 
@@ -176,3 +179,164 @@ fn test_determine_target_sender_only_prefix_no_default() {
     let result = determine_target_sender(&map, &caracat_configs, None);
     assert!(result.is_err());
 }
+
+#[test]
+fn test_group_probes_by_source() {
+    let probes: Vec<Probe> = (1..=4)
+        .map(|ttl| Probe {
+            dst_addr: "::1".parse().unwrap(),
+            src_port: 24000,
+            dst_port: 33434,
+            ttl,
+            protocol: L4::UDP,
+        })
+        .collect();
+    let source = |src_addr: Option<&str>| ProbeTags {
+        src_addr: src_addr.map(|addr| addr.parse().unwrap()),
+        ..Default::default()
+    };
+    let tags = vec![
+        source(None),
+        source(Some("192.168.1.2")),
+        source(None),
+        source(Some("192.168.1.2")),
+    ];
+
+    // The source of a probe overrides the source of the message
+    let header_src_ip = "192.168.1.1".to_string();
+    let groups = group_probes(probes.clone(), tags.clone(), Some(&header_src_ip), None);
+    let groups: Vec<(Option<String>, Vec<u8>)> = groups
+        .into_iter()
+        .map(|group| {
+            (
+                group.source_ip,
+                group.probes.iter().map(|p| p.ttl).collect(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        groups,
+        vec![
+            (Some("192.168.1.1".to_string()), vec![1, 3]),
+            (Some("192.168.1.2".to_string()), vec![2, 4]),
+        ]
+    );
+
+    let groups = group_probes(probes, tags, None, None);
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].source_ip, None);
+    assert_eq!(
+        groups[1].tags[0].src_addr,
+        Some("192.168.1.2".parse().unwrap())
+    );
+}
+
+#[test]
+fn test_group_probes_by_instance() {
+    let probes: Vec<Probe> = (1..=3)
+        .map(|ttl| Probe {
+            dst_addr: "::1".parse().unwrap(),
+            src_port: 24000,
+            dst_port: 33434,
+            ttl,
+            protocol: L4::UDP,
+        })
+        .collect();
+    let tags = vec![
+        ProbeTags::default(),
+        ProbeTags {
+            instance: Some("slow".to_string()),
+            ..Default::default()
+        },
+        ProbeTags::default(),
+    ];
+
+    // The instance of a probe overrides the instance of the message
+    let header_instance = "fast".to_string();
+    let groups = group_probes(probes, tags, None, Some(&header_instance));
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].instance.as_deref(), Some("fast"));
+    assert_eq!(groups[0].probes.len(), 2);
+    assert_eq!(groups[1].instance.as_deref(), Some("slow"));
+    assert_eq!(groups[1].probes[0].ttl, 2);
+}
+
+#[test]
+fn test_determine_pinned_sender() {
+    let (tx_fast, _rx_fast) = channel::<ProbesWithSource>(100);
+    let (tx_slow, _rx_slow) = channel::<ProbesWithSource>(100);
+    let mut map = HashMap::new();
+    map.insert("instance_0".to_string(), tx_fast.clone());
+    map.insert("instance_1".to_string(), tx_slow.clone());
+
+    // Both instances share the same prefix
+    let caracat_configs = vec![
+        CaracatConfig {
+            name: Some("fast".to_string()),
+            instance_id: 0,
+            src_ipv4_prefix: Some("192.168.1.0/24".to_string()),
+            ..Default::default()
+        },
+        CaracatConfig {
+            name: Some("slow".to_string()),
+            instance_id: 1,
+            src_ipv4_prefix: Some("192.168.1.0/24".to_string()),
+            ..Default::default()
+        },
+    ];
+
+    let source_ip = "192.168.1.100".to_string();
+    let (sender, use_source_ip) =
+        determine_pinned_sender(&map, &caracat_configs, "slow", Some(&source_ip)).unwrap();
+    assert!(sender.unwrap().same_channel(&tx_slow));
+    assert!(use_source_ip);
+
+    let (sender, use_source_ip) =
+        determine_pinned_sender(&map, &caracat_configs, "fast", None).unwrap();
+    assert!(sender.unwrap().same_channel(&tx_fast));
+    assert!(!use_source_ip);
+
+    // Unknown instance, or source outside the prefixes of the instance
+    assert!(determine_pinned_sender(&map, &caracat_configs, "other", None).is_err());
+    let source_ip = "10.0.0.1".to_string();
+    assert!(determine_pinned_sender(&map, &caracat_configs, "slow", Some(&source_ip)).is_err());
+}
+
+#[test]
+fn test_parse_control_message() {
+    let payload = br#"{"agent_id": "agent1", "command": "pause"}"#;
+    assert_eq!(
+        parse_control_message(payload, "agent1").unwrap(),
+        Some(ControlCommand::Pause)
+    );
+    assert_eq!(parse_control_message(payload, "agent2").unwrap(), None);
+
+    // Commands can address every agent
+    let payload = br#"{"agent_id": "*", "command": "drain"}"#;
+    assert_eq!(
+        parse_control_message(payload, "agent2").unwrap(),
+        Some(ControlCommand::Drain)
+    );
+
+    assert!(
+        parse_control_message(br#"{"agent_id": "agent1", "command": "stop"}"#, "agent1").is_err()
+    );
+    assert!(parse_control_message(b"pause", "agent1").is_err());
+}
+
+#[test]
+fn test_agent_mode_transitions() {
+    let mode = AgentMode::default();
+    assert!(mode.consumes_probes() && mode.sends_probes());
+
+    let mode = mode.apply(ControlCommand::Pause);
+    assert_eq!(mode, AgentMode::Paused);
+    assert!(!mode.consumes_probes() && !mode.sends_probes());
+
+    // Draining sends the queued probes without consuming new ones
+    let mode = mode.apply(ControlCommand::Drain);
+    assert_eq!(mode, AgentMode::Draining);
+    assert!(!mode.consumes_probes() && mode.sends_probes());
+
+    assert_eq!(mode.apply(ControlCommand::Resume), AgentMode::Running);
+}
@@ -0,0 +1,75 @@
+//! Tests for the audit log of the probes messages consumed by the agent
+use saimiris::agent::audit::{AuditDecision, AuditEntry, AuditLog, AUDIT_LOG_FILE};
+use saimiris::agent::commit::MessageOffset;
+use std::path::Path;
+use tempfile::tempdir;
+
+fn entry(offset: i64) -> AuditEntry {
+    AuditEntry {
+        client_id: Some("client1".to_string()),
+        measurement_id: Some("m1".to_string()),
+        probes: Some(10),
+        source_ip: Some("192.0.2.1".to_string()),
+        ..AuditEntry::new(MessageOffset {
+            topic: "saimiris-probes".to_string(),
+            partition: 0,
+            offset,
+        })
+    }
+}
+
+fn read_entries(path: &Path) -> Vec<AuditEntry> {
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_audit_log_entries() {
+    let dir = tempdir().unwrap();
+    let mut audit_log = AuditLog::open(dir.path(), 1024 * 1024, 2).unwrap();
+    audit_log.write(&entry(1)).unwrap();
+    audit_log.write(&entry(2).rejected("unknown_key")).unwrap();
+    drop(audit_log);
+
+    // Appended to once reopened
+    let mut audit_log = AuditLog::open(dir.path(), 1024 * 1024, 2).unwrap();
+    audit_log.write(&entry(3)).unwrap();
+
+    let entries = read_entries(&audit_log.path());
+    assert_eq!(entries.len(), 3);
+    assert_eq!(
+        entries[0],
+        AuditEntry {
+            timestamp: entries[0].timestamp.clone(),
+            ..entry(1)
+        }
+    );
+    assert_eq!(entries[1].decision, AuditDecision::Rejected);
+    assert_eq!(entries[1].reason.as_deref(), Some("unknown_key"));
+    assert_eq!(entries[2].offset, 3);
+}
+
+#[test]
+fn test_audit_log_rotation() {
+    let dir = tempdir().unwrap();
+    let line_len = serde_json::to_vec(&entry(10)).unwrap().len() as u64 + 1;
+    // Two entries per file, two rotated files kept
+    let mut audit_log = AuditLog::open(dir.path(), 2 * line_len, 2).unwrap();
+    for offset in 10..17 {
+        audit_log.write(&entry(offset)).unwrap();
+    }
+
+    let offsets = |file: &str| -> Vec<i64> {
+        read_entries(&dir.path().join(file))
+            .iter()
+            .map(|entry| entry.offset)
+            .collect()
+    };
+    assert_eq!(offsets(AUDIT_LOG_FILE), vec![16]);
+    assert_eq!(offsets(&format!("{}.1", AUDIT_LOG_FILE)), vec![14, 15]);
+    assert_eq!(offsets(&format!("{}.2", AUDIT_LOG_FILE)), vec![12, 13]);
+    assert!(!dir.path().join(format!("{}.3", AUDIT_LOG_FILE)).exists());
+}
@@ -0,0 +1,95 @@
+//! Tests for the consumer backpressure on the sender queues
+use saimiris::agent::backpressure::{queue_fill, Backpressure};
+use saimiris::agent::events::{EventKind, EventLog};
+use saimiris::config::KafkaConfig;
+use tokio::sync::mpsc::channel;
+
+#[test]
+fn test_backpressure_hysteresis() {
+    let mut backpressure = Backpressure::new(0.9, 0.5);
+    assert!(!backpressure.is_paused());
+    assert_eq!(backpressure.update(0.5), None);
+    assert_eq!(backpressure.update(0.9), Some(true));
+    assert!(backpressure.is_paused());
+
+    // Paused until drained below the resume watermark
+    assert_eq!(backpressure.update(0.95), None);
+    assert_eq!(backpressure.update(0.7), None);
+    assert!(backpressure.is_paused());
+    assert_eq!(backpressure.update(0.5), Some(false));
+    assert_eq!(backpressure.update(0.7), None);
+    assert!(!backpressure.is_paused());
+}
+
+#[test]
+fn test_queue_fill() {
+    let (small, _small_rx) = channel(4);
+    let (large, _large_rx) = channel(100);
+    assert_eq!(queue_fill([&small, &large]), 0.0);
+    assert_eq!(
+        queue_fill(Vec::<&tokio::sync::mpsc::Sender<u32>>::new()),
+        0.0
+    );
+
+    for i in 0..3 {
+        small.try_send(i).unwrap();
+    }
+    large.try_send(0).unwrap();
+    // The fullest queue
+    assert_eq!(queue_fill([&small, &large]), 0.75);
+}
+
+#[test]
+fn test_backpressure_pause_resume() {
+    let (events_tx, mut events_rx) = channel(10);
+    let mut backpressure = Backpressure::new(0.9, 0.5).with_events(EventLog::new(
+        "agent1".to_string(),
+        Some(events_tx),
+        10,
+    ));
+    let (tx, mut rx) = channel(10);
+
+    // Paused once the queue is filled up to the pause watermark
+    for i in 0..8 {
+        tx.try_send(i).unwrap();
+    }
+    assert_eq!(backpressure.update(queue_fill([&tx])), None);
+    tx.try_send(8).unwrap();
+    assert_eq!(backpressure.update(queue_fill([&tx])), Some(true));
+    let event = events_rx.try_recv().unwrap();
+    assert_eq!(event.kind, EventKind::ThrottlingEngaged);
+    assert_eq!(event.details["queue_fill"], "0.90");
+
+    // Resumed once drained down to the resume watermark
+    for _ in 0..3 {
+        rx.try_recv().unwrap();
+    }
+    assert_eq!(backpressure.update(queue_fill([&tx])), None);
+    rx.try_recv().unwrap();
+    assert_eq!(backpressure.update(queue_fill([&tx])), Some(false));
+    assert!(!backpressure.is_paused());
+    assert!(events_rx.try_recv().is_err());
+}
+
+#[test]
+fn test_kafka_config_watermarks() {
+    let config = KafkaConfig::default();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.pause_watermark(), 0.9);
+    assert_eq!(config.resume_watermark(), 0.5);
+
+    for (pause, resume) in [(0.5, 0.9), (0.8, 0.8), (1.5, 0.5), (0.9, 0.0)] {
+        let config = KafkaConfig {
+            in_pause_watermark: Some(pause),
+            in_resume_watermark: Some(resume),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err(), "{} {}", pause, resume);
+    }
+    let config = KafkaConfig {
+        in_pause_watermark: Some(1.0),
+        in_resume_watermark: Some(0.2),
+        ..Default::default()
+    };
+    assert!(config.validate().is_ok());
+}
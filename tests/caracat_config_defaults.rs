@@ -26,6 +26,10 @@ async fn test_caracat_config_defaults_enforced() {
     writeln!(file, "    packets: 0").unwrap();
     writeln!(file, "    probing_rate: 0").unwrap();
     writeln!(file, "    rate_limiting_method: ''").unwrap();
+    writeln!(file, "    send_batch_size: 0").unwrap();
+    writeln!(file, "    integrity_encoding: ''").unwrap();
+    writeln!(file, "    sender_threads: 0").unwrap();
+    writeln!(file, "    reply_filter: ''").unwrap();
     drop(file);
 
     let config = app_config(config_path.to_str().unwrap()).await.unwrap();
@@ -41,6 +45,10 @@ async fn test_caracat_config_defaults_enforced() {
     assert_eq!(caracat.packets, 1);
     assert_eq!(caracat.probing_rate, 100);
     assert_eq!(caracat.rate_limiting_method, "auto");
+    assert_eq!(caracat.send_batch_size, 64);
+    assert_eq!(caracat.integrity_encoding, "instance_id");
+    assert_eq!(caracat.sender_threads, 1);
+    assert_eq!(caracat.reply_filter, "all");
 }
 
 #[tokio::test]
@@ -69,4 +77,5 @@ async fn test_caracat_config_defaults_if_none_provided() {
     assert_eq!(caracat.packets, 1);
     assert_eq!(caracat.probing_rate, 100);
     assert_eq!(caracat.rate_limiting_method, "auto");
+    assert_eq!(caracat.send_batch_size, 64);
 }
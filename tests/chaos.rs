@@ -0,0 +1,37 @@
+//! Unit tests for the fault injection points
+use saimiris::agent::chaos::{drop_reply, fail_sender_creation, panic_sender, Fault};
+
+#[test]
+fn test_fault_percentage() {
+    let fault = Fault::new(25);
+    let injected = (0..100).filter(|_| fault.inject()).count();
+    assert_eq!(injected, 25);
+
+    // Evenly spread
+    let fault = Fault::new(50);
+    let injected: Vec<bool> = (0..4).map(|_| fault.inject()).collect();
+    assert_eq!(injected, vec![false, true, false, true]);
+
+    assert!((0..10).all(|_| Fault::new(100).inject()));
+    assert!((0..10).all(|_| !Fault::default().inject()));
+}
+
+#[test]
+fn test_fault_from_env() {
+    std::env::set_var("SAIMIRIS_TEST_FAULT_PERCENT", "100");
+    assert!(Fault::from_env("SAIMIRIS_TEST_FAULT_PERCENT").inject());
+    std::env::set_var("SAIMIRIS_TEST_FAULT_PERCENT", "often");
+    assert!(!Fault::from_env("SAIMIRIS_TEST_FAULT_PERCENT").inject());
+    assert!(!Fault::from_env("SAIMIRIS_TEST_FAULT_UNSET").inject());
+}
+
+#[cfg(not(feature = "testing"))]
+#[test]
+fn test_no_injection_without_feature() {
+    std::env::set_var(saimiris::agent::chaos::DROP_REPLIES_ENV, "100");
+    std::env::set_var(saimiris::agent::chaos::FAIL_SENDER_ENV, "100");
+    std::env::set_var(saimiris::agent::chaos::PANIC_SENDER_ENV, "100");
+    assert!(!drop_reply());
+    assert!(!fail_sender_creation());
+    assert!(!panic_sender());
+}
@@ -0,0 +1,76 @@
+//! Tests for the client dry run, building the probes messages without producing them
+use caracat::models::Probe;
+use saimiris::auth::KafkaAuth;
+use saimiris::client::producer::{produce, ProbeWithSource, ProduceOptions};
+use saimiris::client::summary::SubmissionSummary;
+use saimiris::config::{app_config, parse_and_validate_client_args, Distribution};
+use std::fs::File;
+use std::io::Write;
+use tempfile::tempdir;
+
+fn probes(count: usize) -> Vec<ProbeWithSource> {
+    (0..count)
+        .map(|i| {
+            let probe = Probe {
+                dst_addr: format!("10.0.{}.{}", i / 256, i % 256).parse().unwrap(),
+                src_port: 24000,
+                dst_port: 33434,
+                ttl: 32,
+                protocol: caracat::models::L4::UDP,
+            };
+            (probe, None)
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_dry_run_builds_messages() {
+    let dir = tempdir().unwrap();
+    let config_path = dir.path().join("test_config.yml");
+    let mut file = File::create(&config_path).unwrap();
+    writeln!(file, "agent:").unwrap();
+    writeln!(file, "  metrics_address: '0.0.0.0:8080'").unwrap();
+    writeln!(file, "kafka:").unwrap();
+    // No broker listens there, nothing is produced
+    writeln!(file, "  brokers: '127.0.0.1:1'").unwrap();
+    writeln!(file, "  message_max_bytes: 4096").unwrap();
+    drop(file);
+    let config = app_config(config_path.to_str().unwrap()).await.unwrap();
+    let client_config =
+        parse_and_validate_client_args("agent1:192.0.2.1,agent2:192.0.2.2", None).unwrap();
+
+    let mut summary = SubmissionSummary::new(1000);
+    let (agents, failed) = produce(
+        &config,
+        KafkaAuth::PlainText,
+        client_config.measurement_infos,
+        probes(1000),
+        Default::default(),
+        ProduceOptions {
+            distribution: Distribution::Shard,
+            dry_run: true,
+            ..Default::default()
+        },
+        None,
+        &mut summary,
+    )
+    .await;
+
+    assert_eq!(agents, vec!["agent1", "agent2"]);
+    assert!(failed.is_empty());
+    assert!(summary.is_success());
+    // The probes do not fit in a single message
+    assert!(summary.messages_produced > 2);
+    assert_eq!(
+        summary
+            .agents
+            .values()
+            .map(|agent| agent.probes)
+            .sum::<usize>(),
+        1000
+    );
+    for agent in summary.agents.values() {
+        assert!(agent.messages > 0);
+        assert!(agent.bytes <= agent.messages as u64 * 4096);
+    }
+}
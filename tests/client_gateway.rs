@@ -0,0 +1,76 @@
+//! Tests for the submission of the probes via the gateway
+use caracat::models::{Probe, L4};
+use saimiris::agent::gateway::AssignedMeasurement;
+use saimiris::client::gateway::{probe_json, probes_chunks, probes_url};
+use saimiris::client::producer::ProbeWithSource;
+use saimiris::config::parse_and_validate_client_args;
+use std::net::{IpAddr, Ipv4Addr};
+
+fn probe(ttl: u8, src_addr: Option<IpAddr>) -> ProbeWithSource {
+    (
+        Probe {
+            dst_addr: "8.8.8.8".parse().unwrap(),
+            src_port: 24000,
+            dst_port: 33434,
+            ttl,
+            protocol: L4::UDP,
+        },
+        src_addr,
+    )
+}
+
+#[test]
+fn test_probe_json() {
+    let src_addr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    let probes = [probe(12, None), probe(13, Some(src_addr))];
+    assert_eq!(probe_json(&probes[0]).get("src_addr"), None);
+    assert_eq!(probe_json(&probes[1])["src_addr"], "192.0.2.1");
+
+    // The probes are read back as the inline probes of an assigned measurement
+    let measurement: AssignedMeasurement = serde_json::from_value(serde_json::json!({
+        "measurement_id": "m1",
+        "probes": probes.iter().map(probe_json).collect::<Vec<_>>(),
+    }))
+    .unwrap();
+    let read: Vec<Probe> = probes.iter().map(|(probe, _)| probe.clone()).collect();
+    assert_eq!(measurement.probes, read);
+}
+
+#[test]
+fn test_probes_chunks() {
+    let client_config = parse_and_validate_client_args("agent1/eth0:192.0.2.1", None)
+        .unwrap()
+        .with_measurement_tracking(Some("m1".to_string()))
+        .with_round(Some(2))
+        .with_gateway_submission(true, 2);
+    let agent = &client_config.measurement_infos[0];
+    let probes: Vec<ProbeWithSource> = (1..=5).map(|ttl| probe(ttl, None)).collect();
+
+    let chunks = probes_chunks(agent, &probes, &client_config);
+    let sizes: Vec<usize> = chunks.iter().map(|chunk| chunk.probes.len()).collect();
+    assert_eq!(sizes, vec![2, 2, 1]);
+    let sequences: Vec<u64> = chunks.iter().map(|chunk| chunk.sequence).collect();
+    assert_eq!(sequences, vec![0, 1, 2]);
+    assert!(chunks[..2].iter().all(|chunk| !chunk.last));
+    assert!(chunks[2].last);
+
+    let chunk = serde_json::to_value(&chunks[0]).unwrap();
+    assert_eq!(chunk["agent"], "agent1");
+    assert_eq!(chunk["src_ip"], "192.0.2.1");
+    assert_eq!(chunk["instance"], "eth0");
+    assert_eq!(chunk["round"], 2);
+    assert_eq!(chunk.get("dscp"), None);
+
+    // An agent without probes completes its submission with an empty chunk
+    let chunks = probes_chunks(agent, &[], &client_config);
+    assert_eq!(chunks.len(), 1);
+    assert!(chunks[0].last && chunks[0].probes.is_empty());
+}
+
+#[test]
+fn test_probes_url() {
+    assert_eq!(
+        probes_url("https://gateway/", "m1"),
+        "https://gateway/api/measurements/m1/probes"
+    );
+}
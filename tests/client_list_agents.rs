@@ -0,0 +1,68 @@
+//! Tests for the listing of the agents registered on the gateway
+use saimiris::agent::gateway::RegisteredAgent;
+use saimiris::client::gateway::{agents_rows, write_table};
+
+fn agents() -> Vec<RegisteredAgent> {
+    serde_json::from_str(
+        r#"[
+            {
+                "id": "agent1",
+                "config": [
+                    {"name": "eth0", "src_ipv4_prefix": "192.0.2.0/24", "src_ipv6_prefix": "2001:db8::/48", "probing_rate": 10000},
+                    {"instance_id": 2, "src_ipv4_prefix": "198.51.100.0/24", "probing_rate": 500}
+                ],
+                "health": {"healthy": true, "last_check": "2026-01-01T00:00:00Z", "message": null}
+            },
+            {"id": "agent2", "health": {"healthy": false}},
+            {"id": "agent3"}
+        ]"#,
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_agents_rows() {
+    let rows = agents_rows(&agents());
+    assert_eq!(rows.len(), 4);
+    assert_eq!(
+        rows[0],
+        [
+            "agent1",
+            "yes",
+            "2026-01-01T00:00:00Z",
+            "eth0",
+            "192.0.2.0/24",
+            "2001:db8::/48",
+            "10000"
+        ]
+    );
+    assert_eq!(
+        rows[1],
+        [
+            "agent1",
+            "yes",
+            "2026-01-01T00:00:00Z",
+            "instance_2",
+            "198.51.100.0/24",
+            "-",
+            "500"
+        ]
+    );
+    // Agents without instances are listed too
+    assert_eq!(rows[2], ["agent2", "no", "-", "-", "-", "-", "-"]);
+    assert_eq!(rows[3], ["agent3", "unknown", "-", "-", "-", "-", "-"]);
+}
+
+#[test]
+fn test_write_table() {
+    let mut out = Vec::new();
+    write_table(&mut out, &agents_rows(&agents()[1..2])).unwrap();
+    let table = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("AGENT   HEALTHY  LAST CHECK  INSTANCE"));
+    assert!(lines[1].starts_with("agent2  no       -           -"));
+    // Columns are aligned on the header
+    let instance = lines[0].find("INSTANCE").unwrap();
+    assert_eq!(&lines[1][instance..instance + 1], "-");
+}
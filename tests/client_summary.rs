@@ -0,0 +1,73 @@
+//! Tests for the summary of the client submissions
+use saimiris::client::summary::{AgentStatus, ProduceStats, SubmissionSummary};
+
+fn agents(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+#[test]
+fn test_summary_delivered() {
+    let mut summary = SubmissionSummary::new(100);
+    let stats = ProduceStats {
+        messages: 2,
+        bytes: 2048,
+        failures: 0,
+    };
+    // A message sent to several agents is counted once in the totals
+    summary.record(&agents(&["agent1", "agent2"]), 100, 0, stats);
+    assert!(summary.is_success());
+    assert_eq!(summary.messages_produced, 2);
+    assert_eq!(summary.bytes_sent, 2048);
+    assert_eq!(summary.agents.len(), 2);
+    assert_eq!(summary.agents["agent2"].probes, 100);
+    assert_eq!(summary.agents["agent2"].status, AgentStatus::Delivered);
+}
+
+#[test]
+fn test_summary_failed() {
+    let mut summary = SubmissionSummary::new(100);
+    let delivered = ProduceStats {
+        messages: 1,
+        bytes: 512,
+        failures: 0,
+    };
+    let failed = ProduceStats {
+        messages: 1,
+        bytes: 512,
+        failures: 1,
+    };
+    summary.record(&agents(&["agent1"]), 50, 0, delivered);
+    summary.record(&agents(&["agent2"]), 50, 25, failed);
+    assert!(!summary.is_success());
+    assert_eq!(summary.produce_failures, 1);
+    assert_eq!(summary.agents["agent1"].status, AgentStatus::Delivered);
+    assert_eq!(summary.agents["agent2"].status, AgentStatus::Failed);
+    assert_eq!(summary.agents["agent2"].failed_probes, 25);
+}
+
+#[test]
+fn test_summary_output() {
+    let mut summary = SubmissionSummary::new(10);
+    let stats = ProduceStats {
+        messages: 1,
+        bytes: 320,
+        failures: 0,
+    };
+    summary.record(&agents(&["agent1"]), 10, 0, stats);
+
+    let mut text = Vec::new();
+    summary.write_text(&mut text).unwrap();
+    assert_eq!(
+        String::from_utf8(text).unwrap(),
+        "probes_read=10,messages_produced=1,bytes_sent=320,produce_failures=0\n\
+         agent=agent1,status=delivered,probes=10,failed_probes=0,messages=1,bytes=320\n"
+    );
+
+    let mut json = Vec::new();
+    summary.write_json(&mut json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json["probes_read"], 10);
+    assert_eq!(json["produce_failures"], 0);
+    assert_eq!(json["agents"]["agent1"]["status"], "delivered");
+    assert_eq!(json["agents"]["agent1"]["bytes"], 320);
+}
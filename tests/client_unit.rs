@@ -1,8 +1,22 @@
 //! Unit tests for client utilities (CSV parsing, batching)
-use caracat::models::Probe;
-use saimiris::client::handler::read_probes_from_csv;
-use saimiris::client::producer::create_messages;
+use caracat::models::{Probe, L4};
+use saimiris::agent::expand::TtlRange;
+use saimiris::client::generate::FlowMapper;
+use saimiris::client::handler::{
+    parse_csv_batch, read_probes_from_csv, read_probes_from_jsonl, read_sourced_probes_from_csv,
+    read_sourced_probes_from_jsonl, split_lines,
+};
+use saimiris::client::manifest::FailureManifest;
+use saimiris::client::ping::{ping_probes, read_destinations};
+use saimiris::client::producer::{
+    create_indexed_messages, create_messages, create_tagged_messages, distribute_probes,
+    headers_len, message_budget, original_ranges, retry_backoff, select_probes, FailedProbes,
+};
+use saimiris::client::traceroute::traceroute_probes;
+use saimiris::config::Distribution;
+use saimiris::probe::ProbeTags;
 use std::io::Cursor;
+use std::time::Duration;
 
 #[test]
 fn test_read_probes_from_csv_valid() {
@@ -32,9 +46,353 @@ fn test_read_probes_from_csv_malformed() {
     assert!(result.is_err());
 }
 
+#[test]
+fn test_split_lines() {
+    let data = b"a,1\nb,2\nc,3\nd";
+    assert_eq!(
+        split_lines(data, 5),
+        vec![&b"a,1\nb,2\n"[..], &b"c,3\nd"[..]]
+    );
+    assert_eq!(split_lines(data, 100), vec![&data[..]]);
+    assert!(split_lines(b"", 5).is_empty());
+}
+
+#[test]
+fn test_parse_csv_batch_keeps_order() {
+    let csv: String = (1..=100)
+        .map(|ttl| format!("::1,1234,4321,{},ICMP\n", ttl))
+        .collect();
+    // Small chunks, parsed in parallel
+    let (probes, lines) = parse_csv_batch(csv.as_bytes(), 1, 64).unwrap();
+    assert_eq!(lines, 100);
+    let ttls: Vec<u8> = probes.iter().map(|(probe, _)| probe.ttl).collect();
+    assert_eq!(ttls, (1..=100).collect::<Vec<u8>>());
+}
+
+#[test]
+fn test_parse_csv_batch_error_line() {
+    let mut csv: String = (1..=50)
+        .map(|ttl| format!("::1,1234,4321,{},ICMP\n", ttl))
+        .collect();
+    csv.push_str("::1,1234,4321,not-a-ttl,ICMP\n");
+    let error = parse_csv_batch(csv.as_bytes(), 1, 64).unwrap_err();
+    assert!(
+        error.to_string().contains("line 51"),
+        "unexpected error: {}",
+        error
+    );
+}
+
+#[test]
+fn test_read_sourced_probes_from_csv() {
+    let csv = "::1,1234,4321,64,ICMP,2001:db8::1\n::1,1234,4321,65,ICMP,\n::1,1234,4321,66,ICMP\n";
+    let probes = read_sourced_probes_from_csv(Cursor::new(csv)).unwrap();
+    let sources: Vec<_> = probes.iter().map(|(_, src_addr)| *src_addr).collect();
+    assert_eq!(
+        sources,
+        vec![Some("2001:db8::1".parse().unwrap()), None, None]
+    );
+
+    let invalid = "::1,1234,4321,64,ICMP,not-an-address\n";
+    assert!(read_sourced_probes_from_csv(Cursor::new(invalid)).is_err());
+    let extra = "::1,1234,4321,64,ICMP,2001:db8::1,extra\n";
+    assert!(read_sourced_probes_from_csv(Cursor::new(extra)).is_err());
+}
+
+#[test]
+fn test_read_sourced_probes_from_jsonl() {
+    let jsonl = concat!(
+        r#"{"dst_addr": "::1", "src_port": 1234, "dst_port": 4321, "ttl": 64, "protocol": "ICMP", "src_addr": "2001:db8::1"}"#,
+        "\n",
+        r#"{"dst_addr": "::1", "src_port": 1234, "dst_port": 4321, "ttl": 65, "protocol": "ICMP"}"#,
+        "\n",
+    );
+    let probes = read_sourced_probes_from_jsonl(Cursor::new(jsonl)).unwrap();
+    assert_eq!(probes.len(), 2);
+    assert_eq!(probes[0].1, Some("2001:db8::1".parse().unwrap()));
+    assert_eq!(probes[1].1, None);
+    assert_eq!(probes[1].0.ttl, 65);
+}
+
+#[test]
+fn test_read_probes_from_jsonl_valid() {
+    let jsonl = concat!(
+        r#"{"dst_addr": "::1", "src_port": 1234, "dst_port": 4321, "ttl": 64, "protocol": "ICMP"}"#,
+        "\n\n",
+        r#"{"dst_addr": "::1", "src_port": 1234, "dst_port": 4321, "ttl": 65, "protocol": "ICMP"}"#,
+        "\n",
+    );
+    let csv = "::1,1234,4321,64,ICMP\n::1,1234,4321,65,ICMP\n";
+    let probes = read_probes_from_jsonl(Cursor::new(jsonl)).unwrap();
+    assert_eq!(probes.len(), 2);
+    assert_eq!(
+        format!("{:?}", probes),
+        format!("{:?}", read_probes_from_csv(Cursor::new(csv)).unwrap())
+    );
+}
+
+#[test]
+fn test_read_probes_from_jsonl_malformed() {
+    let jsonl = r#"{"dst_addr": "::1", "ttl": 64}"#;
+    let result = read_probes_from_jsonl(Cursor::new(jsonl));
+    assert!(result.is_err());
+}
+
 #[test]
 fn test_create_messages_empty() {
     let probes: Vec<Probe> = vec![];
     let batches = create_messages(probes, 100);
     assert!(batches.is_empty());
 }
+
+fn probes_to(destinations: usize) -> Vec<Probe> {
+    (0..destinations)
+        .flat_map(|i| {
+            (1..=3).map(move |ttl| Probe {
+                dst_addr: format!("10.0.{}.1", i).parse().unwrap(),
+                src_port: 24000,
+                dst_port: 33434,
+                ttl,
+                protocol: caracat::models::L4::UDP,
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn test_distribute_probes_replicate() {
+    let sets = distribute_probes(probes_to(10), 3, Distribution::Replicate);
+    assert_eq!(sets.len(), 1);
+    assert_eq!(sets[0].len(), 30);
+}
+
+#[test]
+fn test_distribute_probes_shard() {
+    let sets = distribute_probes(probes_to(10), 3, Distribution::Shard);
+    assert_eq!(sets.len(), 3);
+    assert_eq!(sets.iter().map(|s| s.len()).sum::<usize>(), 30);
+    // All the probes towards a destination go to the same agent
+    for (i, set) in sets.iter().enumerate() {
+        for probe in set {
+            for other in sets.iter().skip(i + 1) {
+                assert!(other.iter().all(|p| p.dst_addr != probe.dst_addr));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_distribute_probes_round_robin() {
+    let sets = distribute_probes(probes_to(10), 4, Distribution::RoundRobin);
+    let lens: Vec<usize> = sets.iter().map(|s| s.len()).collect();
+    assert_eq!(lens, vec![8, 8, 7, 7]);
+}
+
+#[test]
+fn test_create_indexed_messages_ranges() {
+    let probes = probes_to(10);
+    let single = create_indexed_messages(&probes, &ProbeTags::default(), 1_000_000);
+    assert_eq!(single.len(), 1);
+    assert_eq!(single[0].0, 0..probes.len());
+
+    // Small messages: the ranges cover every probe, in order and without overlap
+    let message_max_bytes = single[0].1.len() / 4;
+    let messages = create_indexed_messages(&probes, &ProbeTags::default(), message_max_bytes);
+    assert!(messages.len() > 1);
+    let mut next = 0;
+    for (range, message) in &messages {
+        assert_eq!(range.start, next);
+        assert!(!range.is_empty());
+        assert!(!message.is_empty());
+        next = range.end;
+    }
+    assert_eq!(next, probes.len());
+}
+
+#[test]
+fn test_create_tagged_messages_counts() {
+    let probes = probes_to(10);
+    let single = create_tagged_messages(&probes, &ProbeTags::default(), 1_000_000);
+    let message_max_bytes = single[0].1.len() / 4;
+    let messages = create_tagged_messages(&probes, &ProbeTags::default(), message_max_bytes);
+    assert!(messages.len() > 1);
+    assert_eq!(
+        messages.iter().map(|(count, _)| count).sum::<usize>(),
+        probes.len()
+    );
+    for (count, message) in &messages {
+        assert!(*count > 0);
+        assert!(message.len() <= message_max_bytes);
+    }
+
+    // A probe larger than the budget gets a message of its own, never an empty one
+    let messages = create_tagged_messages(&probes, &ProbeTags::default(), 1);
+    assert_eq!(messages.len(), probes.len());
+    assert!(messages.iter().all(|(count, _)| *count == 1));
+}
+
+#[test]
+fn test_message_budget() {
+    assert_eq!(
+        headers_len([
+            ("end_of_measurement", Some("false".as_bytes())),
+            ("empty", None)
+        ]),
+        10 + 18 + 5 + 10 + 5
+    );
+
+    // The headers, the framing and the record overhead are left out of the probes
+    let uncompressed = message_budget(990_000, 0, 0, None);
+    assert!(uncompressed < 990_000);
+    assert_eq!(message_budget(990_000, 100, 5, None), uncompressed - 105);
+    assert_eq!(message_budget(990_000, 0, 0, Some("none")), uncompressed);
+    assert_eq!(message_budget(10, 100, 5, None), 0);
+
+    // Incompressible probes may expand: the budget leaves room for it
+    for (compression, ratio) in [
+        ("gzip", 16388.0 / 16383.0),
+        ("snappy", 7.0 / 6.0),
+        ("lz4", 256.0 / 255.0),
+        ("zstd", 257.0 / 256.0),
+    ] {
+        let budget = message_budget(990_000, 0, 0, Some(compression));
+        assert!(budget < uncompressed, "{}", compression);
+        assert!(
+            budget as f64 * ratio + 128.0 <= uncompressed as f64,
+            "{}",
+            compression
+        );
+    }
+}
+
+#[test]
+fn test_retry_backoff() {
+    assert_eq!(retry_backoff(1), Duration::from_millis(100));
+    assert_eq!(retry_backoff(2), Duration::from_millis(200));
+    assert_eq!(retry_backoff(4), Duration::from_millis(800));
+    // The backoff is capped
+    assert_eq!(retry_backoff(20), Duration::from_secs(10));
+    assert_eq!(retry_backoff(u32::MAX), Duration::from_secs(10));
+}
+
+#[test]
+fn test_select_probes_and_original_ranges() {
+    let probes = probes_to(4);
+    let (indices, selected) = select_probes(probes, &[1..3, 5..6, 8..12]);
+    assert_eq!(indices, vec![1, 2, 5, 8, 9, 10, 11]);
+    assert_eq!(selected.len(), indices.len());
+
+    // Failed ranges of the selected probes map back to the original indices
+    assert_eq!(original_ranges(0..7, &indices), vec![1..3, 5..6, 8..12]);
+    assert_eq!(original_ranges(2..4, &indices), vec![5..6, 8..9]);
+    assert!(original_ranges(7..9, &indices).is_empty());
+}
+
+#[test]
+fn test_failure_manifest_roundtrip() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("failures.json");
+    let manifest = FailureManifest {
+        measurement_id: Some("m1".to_string()),
+        distribution: Distribution::RoundRobin,
+        agents: vec!["agent1".to_string(), "agent2".to_string()],
+        failures: vec![FailedProbes {
+            agents: vec!["agent2".to_string()],
+            probes: 100..200,
+        }],
+    };
+    manifest.write(&path).unwrap();
+
+    let read = FailureManifest::read(&path).unwrap();
+    assert_eq!(read, manifest);
+    assert_eq!(read.failed_probes(), 100);
+    assert!(read
+        .check_agents(&["agent1".to_string(), "agent2".to_string()])
+        .is_ok());
+    // Agents must be given in the same order to reproduce the distribution
+    assert!(read
+        .check_agents(&["agent2".to_string(), "agent1".to_string()])
+        .is_err());
+}
+
+#[test]
+fn test_read_destinations() {
+    let input = "8.8.8.8\n\n# resolvers\n2001:4860:4860::8888 # google\n";
+    let destinations = read_destinations(Cursor::new(input)).unwrap();
+    assert_eq!(
+        destinations,
+        vec![
+            "8.8.8.8".parse::<std::net::IpAddr>().unwrap(),
+            "2001:4860:4860::8888".parse().unwrap()
+        ]
+    );
+
+    let error = read_destinations(Cursor::new("8.8.8.8\nnot-an-address\n")).unwrap_err();
+    assert!(error.to_string().contains("line 2"), "{}", error);
+}
+
+#[test]
+fn test_ping_probes() {
+    let destinations = vec!["8.8.8.8".parse().unwrap(), "::1".parse().unwrap()];
+    let probes = ping_probes(&destinations, 3, 64);
+    assert_eq!(probes.len(), 6);
+    assert!(probes.iter().all(|probe| probe.ttl == 64));
+    assert_eq!(
+        format!(
+            "{:?}",
+            probes.iter().map(|p| p.protocol).collect::<Vec<_>>()
+        ),
+        format!(
+            "{:?}",
+            [
+                L4::ICMP,
+                L4::ICMP,
+                L4::ICMP,
+                L4::ICMPv6,
+                L4::ICMPv6,
+                L4::ICMPv6
+            ]
+        )
+    );
+    // Echo requests to a destination are told apart by their source port
+    let ports: Vec<u16> = probes[..3].iter().map(|probe| probe.src_port).collect();
+    assert_eq!(ports, vec![24000, 24001, 24002]);
+}
+
+#[test]
+fn test_traceroute_probes() {
+    let destinations = vec![
+        "8.8.8.8".parse().unwrap(),
+        "2001:4860:4860::8888".parse().unwrap(),
+    ];
+    let probes = traceroute_probes(
+        &destinations,
+        TtlRange { min: 2, max: 4 },
+        2,
+        FlowMapper::Ports,
+    );
+    assert_eq!(probes.len(), 2 * 2 * 3);
+    assert!(probes
+        .iter()
+        .all(|probe| matches!(probe.protocol, L4::UDP) && probe.dst_port == 33434));
+
+    // The source port identifies the flow, and is constant across TTLs
+    let flows: Vec<(u16, u8)> = probes[..6]
+        .iter()
+        .map(|probe| (probe.src_port, probe.ttl))
+        .collect();
+    assert_eq!(
+        flows,
+        vec![
+            (24000, 2),
+            (24000, 3),
+            (24000, 4),
+            (24001, 2),
+            (24001, 3),
+            (24001, 4)
+        ]
+    );
+    assert!(probes[6..]
+        .iter()
+        .all(|probe| probe.dst_addr == destinations[1]));
+}
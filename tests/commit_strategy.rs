@@ -0,0 +1,115 @@
+//! Tests for the commit strategies of the agent consumer
+use saimiris::agent::commit::{
+    enqueue, enqueue_backoff, CommitStrategy, Committer, MessageOffset, OffsetTracker,
+};
+use saimiris::config::KafkaConfig;
+use std::time::Duration;
+use tokio::sync::mpsc::channel;
+
+fn offset(partition: i32, offset: i64) -> MessageOffset {
+    MessageOffset {
+        topic: "saimiris-probes".to_string(),
+        partition,
+        offset,
+    }
+}
+
+#[test]
+fn test_parse_commit_strategy() {
+    assert_eq!(CommitStrategy::parse("auto").unwrap(), CommitStrategy::Auto);
+    assert_eq!(
+        CommitStrategy::parse("after-queue").unwrap(),
+        CommitStrategy::AfterQueue
+    );
+    assert_eq!(
+        CommitStrategy::parse("after-send").unwrap(),
+        CommitStrategy::AfterSend
+    );
+    assert!(CommitStrategy::parse("after_send").is_err());
+}
+
+#[test]
+fn test_kafka_config_commit_strategy() {
+    assert!(KafkaConfig::default().validate().is_ok());
+    let config = KafkaConfig {
+        commit_strategy: "never".to_string(),
+        ..Default::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_offset_tracker_commits_in_order() {
+    let mut tracker = OffsetTracker::default();
+    for o in 10..13 {
+        tracker.track(&offset(0, o));
+    }
+    // Out of order acknowledgments are held until the previous messages are acknowledged
+    assert_eq!(tracker.ack(&offset(0, 12)), None);
+    assert_eq!(tracker.ack(&offset(0, 11)), None);
+    assert_eq!(tracker.ack(&offset(0, 10)), Some(13));
+    // Unknown offsets are ignored
+    assert_eq!(tracker.ack(&offset(0, 42)), None);
+}
+
+#[test]
+fn test_offset_tracker_partitions_are_independent() {
+    let mut tracker = OffsetTracker::default();
+    tracker.track(&offset(0, 5));
+    tracker.track(&offset(1, 7));
+    assert_eq!(tracker.ack(&offset(1, 7)), Some(8));
+    assert_eq!(tracker.ack(&offset(0, 5)), Some(6));
+}
+
+#[test]
+fn test_committer_auto_and_after_queue() {
+    let (mut committer, _rx) = Committer::new(CommitStrategy::Auto);
+    assert!(committer.probes_ack(offset(0, 1)).is_none());
+    assert_eq!(committer.processed(offset(0, 1)), None);
+
+    let (mut committer, _rx) = Committer::new(CommitStrategy::AfterQueue);
+    assert!(committer.probes_ack(offset(0, 1)).is_none());
+    assert_eq!(committer.processed(offset(0, 1)), Some(offset(0, 2)));
+}
+
+#[test]
+fn test_committer_after_send() {
+    let (mut committer, mut rx) = Committer::new(CommitStrategy::AfterSend);
+    let ack = committer.probes_ack(offset(0, 1)).unwrap();
+    let shard = ack.clone();
+
+    // Messages without probes are held behind the unsent probes
+    assert_eq!(committer.processed(offset(0, 2)), None);
+
+    drop(ack);
+    assert!(rx.try_recv().is_err());
+    drop(shard);
+    let sent = rx.try_recv().unwrap();
+    assert_eq!(sent, offset(0, 1));
+    assert_eq!(committer.sent(sent), Some(offset(0, 3)));
+}
+
+#[test]
+fn test_enqueue_backoff() {
+    assert_eq!(enqueue_backoff(1), Duration::from_millis(10));
+    assert_eq!(enqueue_backoff(3), Duration::from_millis(40));
+    assert_eq!(enqueue_backoff(20), Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn test_enqueue_retries_while_full() {
+    let (tx, mut rx) = channel(1);
+    assert_eq!(enqueue(&tx, 1).await, Ok(0));
+
+    // The channel is full until the receiver catches up
+    let receiver = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(25)).await;
+        let first = rx.recv().await;
+        (first, rx.recv().await)
+    });
+    assert!(enqueue(&tx, 2).await.unwrap() > 0);
+    assert_eq!(receiver.await.unwrap(), (Some(1), Some(2)));
+
+    // The probes are handed back when the sender has exited
+    assert_eq!(enqueue(&tx, 3).await, Err(3));
+}
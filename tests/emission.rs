@@ -0,0 +1,204 @@
+use caracat::models::{Probe, L4};
+use saimiris::agent::emission::{
+    ip_packet, verify_packet, EmissionChecker, EmissionChecks, EmissionMismatch, EmissionStatus,
+    ExpectedProbe,
+};
+use saimiris::config::CaracatConfig;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant};
+
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for part in parts {
+        for word in part.chunks(2) {
+            sum += u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u32;
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn probe(ttl: u8) -> Probe {
+    Probe {
+        dst_addr: "198.51.100.1".parse().unwrap(),
+        src_port: 24000,
+        dst_port: 33434,
+        ttl,
+        protocol: L4::UDP,
+    }
+}
+
+/// IPv4 UDP packet of a probe, with valid checksums.
+fn udp_packet(src: Ipv4Addr, probe: &Probe) -> Vec<u8> {
+    let IpAddr::V4(dst) = probe.dst_addr else {
+        unreachable!()
+    };
+    let payload = [0x12, 0x34];
+    let udp_len = (8 + payload.len()) as u16;
+    let mut udp = Vec::new();
+    udp.extend_from_slice(&probe.src_port.to_be_bytes());
+    udp.extend_from_slice(&probe.dst_port.to_be_bytes());
+    udp.extend_from_slice(&udp_len.to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(&payload);
+    let mut pseudo_header = Vec::new();
+    pseudo_header.extend_from_slice(&src.octets());
+    pseudo_header.extend_from_slice(&dst.octets());
+    pseudo_header.extend_from_slice(&[0, 17]);
+    pseudo_header.extend_from_slice(&udp_len.to_be_bytes());
+    let udp_checksum = checksum(&[&pseudo_header, &udp]);
+    udp[6..8].copy_from_slice(&udp_checksum.to_be_bytes());
+
+    let mut ip = vec![0x45, 0, 0, 0, 0, 1, 0, 0, probe.ttl, 17, 0, 0];
+    ip[2..4].copy_from_slice(&(20 + udp_len).to_be_bytes());
+    ip.extend_from_slice(&src.octets());
+    ip.extend_from_slice(&dst.octets());
+    let ip_checksum = checksum(&[&ip]);
+    ip[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+    ip.extend_from_slice(&udp);
+    ip
+}
+
+fn expected(ttl: u8, src_addr: Option<&str>) -> ExpectedProbe {
+    ExpectedProbe {
+        probe: probe(ttl),
+        src_addr: src_addr.map(|addr| addr.parse().unwrap()),
+    }
+}
+
+#[test]
+fn test_verify_packet() {
+    let src: Ipv4Addr = "192.0.2.1".parse().unwrap();
+    let packet = udp_packet(src, &probe(5));
+    assert_eq!(
+        verify_packet(&expected(5, Some("192.0.2.1")), &[], &packet),
+        Ok(())
+    );
+    assert_eq!(
+        verify_packet(&expected(6, Some("192.0.2.1")), &[], &packet),
+        Err(EmissionMismatch::Ttl {
+            expected: 6,
+            actual: 5
+        })
+    );
+    assert!(matches!(
+        verify_packet(&expected(5, Some("192.0.2.2")), &[], &packet),
+        Err(EmissionMismatch::SourceAddress { .. })
+    ));
+
+    // Without a source address, the probe must be sent from the instance prefixes
+    let prefixes = ["192.0.2.0/24".parse().unwrap()];
+    assert_eq!(
+        verify_packet(&expected(5, None), &prefixes, &packet),
+        Ok(())
+    );
+    let other_prefixes = ["203.0.113.0/24".parse().unwrap()];
+    assert!(verify_packet(&expected(5, None), &other_prefixes, &packet).is_err());
+
+    // Altered checksums
+    let mut altered = packet.clone();
+    altered[10] ^= 0xff;
+    assert_eq!(
+        verify_packet(&expected(5, None), &[], &altered),
+        Err(EmissionMismatch::IpChecksum)
+    );
+    let mut altered = packet.clone();
+    altered[28] ^= 0xff;
+    assert_eq!(
+        verify_packet(&expected(5, None), &[], &altered),
+        Err(EmissionMismatch::L4Checksum)
+    );
+
+    assert_eq!(
+        verify_packet(&expected(5, None), &[], &packet[..24]),
+        Err(EmissionMismatch::Truncated)
+    );
+    assert_eq!(
+        verify_packet(&expected(5, None), &[], &[0x00, 0x01]),
+        Err(EmissionMismatch::Malformed)
+    );
+}
+
+#[test]
+fn test_ip_packet() {
+    let packet = udp_packet("192.0.2.1".parse().unwrap(), &probe(5));
+    let mut frame = vec![0; 12];
+    frame.extend_from_slice(&[0x81, 0x00, 0x00, 0x01, 0x08, 0x00]);
+    frame.extend_from_slice(&packet);
+    // Ethernet, with a VLAN tag
+    assert_eq!(ip_packet(1, &frame), Some(packet.as_slice()));
+    // Raw IP
+    assert_eq!(ip_packet(101, &packet), Some(packet.as_slice()));
+    // Not IP
+    frame[16..18].copy_from_slice(&[0x08, 0x06]);
+    assert_eq!(ip_packet(1, &frame), None);
+}
+
+fn checker(checks: &EmissionChecks) -> EmissionChecker {
+    let config = CaracatConfig {
+        name: Some("main".to_string()),
+        emission_check_sample_every: 2,
+        emission_check_timeout: 5,
+        ..Default::default()
+    };
+    EmissionChecker::new("agent1", &config, checks.clone()).unwrap()
+}
+
+#[test]
+fn test_emission_checker() {
+    let checks = EmissionChecks::default();
+    let checker = checker(&checks);
+    assert_eq!(
+        checks.statuses(),
+        BTreeMap::from([("main".to_string(), EmissionStatus::Unverified)])
+    );
+
+    let src: IpAddr = "192.0.2.1".parse().unwrap();
+    // One in two probes is sampled, starting with the first one
+    checker.sample(&probe(5), Some(src));
+    checker.sample(&probe(6), Some(src));
+
+    // Another probe of the same flow is not a mismatch yet
+    let IpAddr::V4(src_v4) = src else {
+        unreachable!()
+    };
+    assert_eq!(checker.observe(&udp_packet(src_v4, &probe(7))), None);
+    // Nor is a probe which was not sampled
+    assert_eq!(checker.observe(&udp_packet(src_v4, &probe(6))), None);
+    assert_eq!(
+        checker.observe(&udp_packet(src_v4, &probe(5))),
+        Some(EmissionStatus::Verified)
+    );
+    assert_eq!(checks.failing(), Vec::<String>::new());
+    assert_eq!(checks.statuses()["main"], EmissionStatus::Verified);
+
+    // Sampled probes not captured as sent are reported when they expire
+    checker.sample(&probe(8), Some(src));
+    checker.sample(&probe(9), Some(src));
+    checker.observe(&udp_packet(src_v4, &probe(9)));
+    assert_eq!(checker.expire(Instant::now()), 0);
+    assert_eq!(checker.expire(Instant::now() + Duration::from_secs(10)), 1);
+    assert_eq!(checks.statuses()["main"], EmissionStatus::Mismatch);
+    assert_eq!(checks.failing(), vec!["main".to_string()]);
+
+    checker.sample(&probe(10), Some(src));
+    checker.sample(&probe(11), Some(src));
+    assert_eq!(checker.expire(Instant::now() + Duration::from_secs(10)), 1);
+    assert_eq!(checks.statuses()["main"], EmissionStatus::Missing);
+}
+
+#[test]
+fn test_emission_checker_disabled() {
+    let checks = EmissionChecks::default();
+    assert!(EmissionChecker::new("agent1", &CaracatConfig::default(), checks.clone()).is_none());
+    let dry_run = CaracatConfig {
+        emission_check_sample_every: 1,
+        dry_run: true,
+        ..Default::default()
+    };
+    assert!(EmissionChecker::new("agent1", &dry_run, checks.clone()).is_none());
+    assert!(checks.statuses().is_empty());
+}
@@ -0,0 +1,244 @@
+//! End-to-end tests of the client and a dry-run agent through Kafka, run with
+//! `cargo test --features integration --test end_to_end` against the broker of
+//! `integration/compose.yml` (or `SAIMIRIS_KAFKA_BROKERS`)
+#![cfg(feature = "integration")]
+
+use metrics_exporter_prometheus::PrometheusBuilder;
+use rdkafka::admin::{AdminClient, AdminOptions, NewTopic, TopicReplication};
+use rdkafka::client::DefaultClientContext;
+use rdkafka::ClientConfig;
+use saimiris::agent::gateway::fetch_measurement_status;
+use saimiris::agent::statistics;
+use saimiris::config::{app_config, parse_and_validate_client_args};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::tempdir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+const PROBES: usize = 10;
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+fn brokers() -> String {
+    std::env::var("SAIMIRIS_KAFKA_BROKERS").unwrap_or_else(|_| "localhost:9092".to_string())
+}
+
+async fn create_topic(topic: &str) {
+    let admin: AdminClient<DefaultClientContext> = ClientConfig::new()
+        .set("bootstrap.servers", brokers())
+        .create()
+        .unwrap();
+    let results = admin
+        .create_topics(
+            &[NewTopic::new(topic, 1, TopicReplication::Fixed(1))],
+            &AdminOptions::new(),
+        )
+        .await
+        .unwrap();
+    for result in results {
+        result.unwrap();
+    }
+}
+
+/// Configuration shared by the agent and the client: signed probes messages on a dedicated
+/// topic, sent by a dry-run instance
+fn write_config(path: &Path, agent_id: &str, instance: &str, topic: &str, gateway: Option<&str>) {
+    let mut file = File::create(&path).unwrap();
+    writeln!(file, "agent:").unwrap();
+    writeln!(file, "  id: {}", agent_id).unwrap();
+    writeln!(file, "  metrics_address: '127.0.0.1:0'").unwrap();
+    writeln!(file, "  signing_keys: {{client1: secret1}}").unwrap();
+    writeln!(file, "caracat:").unwrap();
+    writeln!(file, "  - name: {}", instance).unwrap();
+    writeln!(file, "    interface: lo").unwrap();
+    writeln!(file, "    src_ipv4_prefix: 127.0.0.0/8").unwrap();
+    writeln!(file, "    src_ipv6_prefix: '::1/128'").unwrap();
+    writeln!(file, "    integrity_check: false").unwrap();
+    writeln!(file, "    dry_run: true").unwrap();
+    writeln!(file, "kafka:").unwrap();
+    writeln!(file, "  brokers: '{}'", brokers()).unwrap();
+    writeln!(file, "  auth_protocol: PLAINTEXT").unwrap();
+    writeln!(file, "  in_topics: {}", topic).unwrap();
+    writeln!(file, "  in_group_id: {}", topic).unwrap();
+    writeln!(file, "  in_auto_offset_reset: earliest").unwrap();
+    writeln!(file, "  out_enable: false").unwrap();
+    writeln!(file, "  signing_key_id: client1").unwrap();
+    writeln!(file, "  signing_key: secret1").unwrap();
+    if let Some(url) = gateway {
+        writeln!(file, "gateway:").unwrap();
+        writeln!(file, "  url: '{}'", url).unwrap();
+        writeln!(file, "  agent_key: key1").unwrap();
+    }
+}
+
+fn write_probes(dir: &Path) -> PathBuf {
+    let path = dir.join("probes.csv");
+    let mut file = File::create(&path).unwrap();
+    for ttl in 1..=PROBES {
+        writeln!(file, "127.0.0.1,24000,33434,{},ICMP", ttl).unwrap();
+    }
+    path
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_client_probes_consumed_by_agent() {
+    let run = uuid::Uuid::new_v4().simple().to_string();
+    let agent_id = format!("e2e-{}", &run[..8]);
+    let instance = format!("e2e-{}", &run[..8]);
+    let topic = format!("saimiris-probes-e2e-{}", run);
+    create_topic(&topic).await;
+
+    let dir = tempdir().unwrap();
+    let config_path = dir.path().join("saimiris.yml");
+    write_config(&config_path, &agent_id, &instance, &topic, None);
+    let config = app_config(config_path.to_str().unwrap()).await.unwrap();
+
+    let probes_path = write_probes(dir.path());
+
+    // The probes are produced before the agent starts, and consumed from the earliest offset
+    let client_config =
+        parse_and_validate_client_args(&format!("{}:127.0.0.1", agent_id), Some(probes_path))
+            .unwrap();
+    saimiris::client::handle(&config, client_config)
+        .await
+        .unwrap();
+
+    let metrics = PrometheusBuilder::new().build_recorder().handle();
+    let statistics = statistics::send_statistics(&instance);
+    let probes_read = async {
+        while statistics.probes_read.load(Ordering::Relaxed) < PROBES as u64 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    tokio::select! {
+        result = saimiris::agent::handle(&config, metrics) => {
+            panic!("Agent stopped: {:?}", result);
+        }
+        result = tokio::time::timeout(TIMEOUT, probes_read) => {
+            assert!(
+                result.is_ok(),
+                "Probes not consumed by the agent: {}",
+                statistics
+            );
+        }
+    }
+    assert_eq!(
+        statistics.probes_read.load(Ordering::Relaxed),
+        PROBES as u64
+    );
+}
+
+/// Gateway storing the measurement statuses reported by the agents, and serving them to the
+/// clients waiting for completion
+async fn spawn_gateway() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let statuses: Arc<Mutex<HashMap<String, String>>> = Arc::default();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let statuses = statuses.clone();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 4096];
+                let head_len = loop {
+                    if let Some(i) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break i + 4;
+                    }
+                    let n = stream.read(&mut buffer).await.unwrap();
+                    if n == 0 {
+                        return;
+                    }
+                    request.extend_from_slice(&buffer[..n]);
+                };
+                let head = String::from_utf8_lossy(&request[..head_len]).to_string();
+                let content_len = head
+                    .lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                    .map_or(0, |(_, value)| value.trim().parse().unwrap());
+                while request.len() < head_len + content_len {
+                    let n = stream.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..n]);
+                }
+                let body = String::from_utf8_lossy(&request[head_len..]).to_string();
+
+                let mut request_line = head.split_whitespace();
+                let (method, path) = (request_line.next().unwrap(), request_line.next().unwrap());
+                let response = match (method, path.ends_with("/status")) {
+                    ("POST", true) => {
+                        statuses.lock().unwrap().insert(path.to_string(), body);
+                        Some(String::new())
+                    }
+                    ("GET", true) => statuses.lock().unwrap().get(path).cloned(),
+                    _ => None,
+                };
+                let response = match response {
+                    Some(body) => format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    ),
+                    None => "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                        .to_string(),
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+            });
+        }
+    });
+    url
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_client_waits_for_measurement_completion() {
+    let run = uuid::Uuid::new_v4().simple().to_string();
+    let agent_id = format!("e2e-{}", &run[..8]);
+    let instance = format!("e2e-{}", &run[..8]);
+    let topic = format!("saimiris-probes-e2e-{}", run);
+    let measurement_id = format!("m-{}", &run[..8]);
+    create_topic(&topic).await;
+
+    let gateway_url = spawn_gateway().await;
+    let dir = tempdir().unwrap();
+    let config_path = dir.path().join("saimiris.yml");
+    write_config(
+        &config_path,
+        &agent_id,
+        &instance,
+        &topic,
+        Some(&gateway_url),
+    );
+    let config = app_config(config_path.to_str().unwrap()).await.unwrap();
+
+    // The client returns once the agent reports the measurement as complete to the gateway,
+    // which requires the agent to read the measurement headers of the client
+    let client_config = parse_and_validate_client_args(
+        &format!("{}:127.0.0.1", agent_id),
+        Some(write_probes(dir.path())),
+    )
+    .unwrap()
+    .with_measurement_tracking(Some(measurement_id.clone()))
+    .with_wait(true, Some(TIMEOUT.as_secs()));
+    let metrics = PrometheusBuilder::new().build_recorder().handle();
+    tokio::select! {
+        result = saimiris::agent::handle(&config, metrics) => {
+            panic!("Agent stopped: {:?}", result);
+        }
+        result = saimiris::client::handle(&config, client_config) => {
+            result.unwrap();
+        }
+    }
+
+    let client = reqwest::Client::new();
+    let status =
+        fetch_measurement_status(&client, &gateway_url, &agent_id, "key1", &measurement_id)
+            .await
+            .unwrap();
+    assert!(status.is_complete);
+    assert_eq!(status.sent_probes, PROBES as u32);
+}
@@ -0,0 +1,121 @@
+use metrics::Label;
+use saimiris::agent::exemplars::{trace_id_from_traceparent, Exemplar, Exemplars};
+
+const TEXT: &str = r#"# HELP saimiris_sender_sent_total Total number of probes sent
+# TYPE saimiris_sender_sent_total counter
+saimiris_sender_sent_total{agent="agent1"} 120
+
+# TYPE saimiris_sender_pps gauge
+saimiris_sender_pps{agent="agent1"} 10
+
+# TYPE saimiris_measurement_send_duration_seconds histogram
+saimiris_measurement_send_duration_seconds_bucket{agent="agent1",le="1"} 0
+saimiris_measurement_send_duration_seconds_bucket{agent="agent1",le="10"} 1
+saimiris_measurement_send_duration_seconds_bucket{agent="agent1",le="60"} 2
+saimiris_measurement_send_duration_seconds_bucket{agent="agent1",le="+Inf"} 2
+saimiris_measurement_send_duration_seconds_sum{agent="agent1"} 35
+saimiris_measurement_send_duration_seconds_count{agent="agent1"} 2
+
+# TYPE saimiris_agent_restarts counter
+saimiris_agent_restarts{agent="agent1"} 1
+"#;
+
+fn labels() -> Vec<Label> {
+    vec![Label::new("agent", "agent1")]
+}
+
+#[test]
+fn test_render_openmetrics() {
+    let exemplars = Exemplars::default();
+    exemplars.record(
+        "saimiris_sender_sent_total",
+        &labels(),
+        Exemplar::new("m1", Some("4bf92f3577b34da6a3ce929d0e0e4736"), 20.0),
+    );
+    exemplars.record(
+        "saimiris_measurement_send_duration_seconds",
+        &labels(),
+        Exemplar::new("m1", None, 5.0),
+    );
+    exemplars.record(
+        "saimiris_measurement_send_duration_seconds",
+        &labels(),
+        Exemplar::new("m2", None, 30.0),
+    );
+
+    let output = exemplars.render_openmetrics(TEXT);
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.last(), Some(&"# EOF"));
+    assert!(!lines.contains(&""));
+
+    // Counter families are named without their suffix
+    assert!(!lines.contains(&"# TYPE saimiris_sender_sent_total counter"));
+    assert!(lines.contains(&"# TYPE saimiris_sender_sent counter"));
+    assert!(lines.contains(&"# HELP saimiris_sender_sent Total number of probes sent"));
+    assert!(lines.contains(&"# TYPE saimiris_agent_restarts unknown"));
+
+    let sample = |prefix: &str| {
+        lines
+            .iter()
+            .find(|line| line.starts_with(prefix))
+            .copied()
+            .unwrap()
+    };
+    assert!(sample("saimiris_sender_sent_total{").contains(
+        r#"} 120 # {measurement_id="m1",trace_id="4bf92f3577b34da6a3ce929d0e0e4736"} 20 "#
+    ));
+    assert_eq!(
+        sample("saimiris_sender_pps{"),
+        r#"saimiris_sender_pps{agent="agent1"} 10"#
+    );
+
+    // Each bucket gets the exemplar falling into it
+    assert!(
+        !sample(r#"saimiris_measurement_send_duration_seconds_bucket{agent="agent1",le="1"}"#)
+            .contains('#')
+    );
+    assert!(
+        sample(r#"saimiris_measurement_send_duration_seconds_bucket{agent="agent1",le="10"}"#)
+            .contains(r#"# {measurement_id="m1"} 5 "#)
+    );
+    assert!(
+        sample(r#"saimiris_measurement_send_duration_seconds_bucket{agent="agent1",le="60"}"#)
+            .contains(r#"# {measurement_id="m2"} 30 "#)
+    );
+    assert!(!sample("saimiris_measurement_send_duration_seconds_sum").contains('#'));
+}
+
+#[test]
+fn test_render_openmetrics_other_series() {
+    let exemplars = Exemplars::default();
+    exemplars.record(
+        "saimiris_sender_sent_total",
+        &[Label::new("agent", "agent2")],
+        Exemplar::new("m1", None, 1.0),
+    );
+    // Exemplars whose labels are too long are not exposed
+    exemplars.record(
+        "saimiris_measurement_send_duration_seconds",
+        &labels(),
+        Exemplar::new(&"m".repeat(200), None, 5.0),
+    );
+    let output = exemplars.render_openmetrics(TEXT);
+    assert!(!output.contains(" # {"));
+}
+
+#[test]
+fn test_trace_id_from_traceparent() {
+    assert_eq!(
+        trace_id_from_traceparent("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01"),
+        Some("4bf92f3577b34da6a3ce929d0e0e4736".to_string())
+    );
+    assert_eq!(
+        trace_id_from_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+        None
+    );
+    assert_eq!(
+        trace_id_from_traceparent("00-4bf92f35-00f067aa0ba902b7-01"),
+        None
+    );
+    assert_eq!(trace_id_from_traceparent("not a traceparent"), None);
+}
@@ -0,0 +1,80 @@
+use saimiris::agent::failover::{
+    check_compatible_prefixes, validate_backups, FailoverState, FailureCounter,
+};
+use saimiris::config::CaracatConfig;
+
+fn instance(name: &str, instance_id: u16, ipv4_prefix: Option<&str>) -> CaracatConfig {
+    CaracatConfig {
+        name: Some(name.to_string()),
+        instance_id,
+        src_ipv4_prefix: ipv4_prefix.map(str::to_string),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_compatible_prefixes() {
+    let primary = instance("primary", 1, Some("192.0.2.0/25"));
+    assert!(check_compatible_prefixes(&primary, &instance("b", 2, Some("192.0.2.0/24"))).is_ok());
+    assert!(
+        check_compatible_prefixes(&primary, &instance("b", 2, Some("192.0.2.128/25"))).is_err()
+    );
+    assert!(check_compatible_prefixes(&primary, &instance("b", 2, None)).is_err());
+
+    // A primary without prefixes sends from the default source address, as any backup
+    assert!(check_compatible_prefixes(&instance("a", 1, None), &primary).is_ok());
+}
+
+#[test]
+fn test_validate_backups() {
+    let mut primary = instance("primary", 1, Some("192.0.2.0/24"));
+    let backup = instance("backup", 2, Some("192.0.2.0/24"));
+    assert!(validate_backups(&[primary.clone(), backup.clone()]).is_ok());
+
+    primary.backup_instance = Some("backup".to_string());
+    assert!(validate_backups(&[primary.clone(), backup.clone()]).is_ok());
+
+    // Unknown backup
+    assert!(validate_backups(&[primary.clone()]).is_err());
+
+    // Own backup
+    let mut own = primary.clone();
+    own.backup_instance = Some("primary".to_string());
+    assert!(validate_backups(&[own]).is_err());
+
+    // No chained backups
+    let mut chained = backup.clone();
+    chained.backup_instance = Some("primary".to_string());
+    assert!(validate_backups(&[primary, chained]).is_err());
+}
+
+#[test]
+fn test_failover_state() {
+    let state = FailoverState::default();
+    assert!(!state.is_active("eth0"));
+    assert!(state.activate("eth0"));
+    assert!(!state.activate("eth0"));
+    assert!(state.activate("eth1"));
+    assert!(state.elapsed("eth0").is_some());
+    assert_eq!(state.active(), vec!["eth0".to_string(), "eth1".to_string()]);
+
+    // Shared by the workers
+    let other = state.clone();
+    assert!(other.deactivate("eth0"));
+    assert!(!state.deactivate("eth0"));
+    assert_eq!(state.active(), vec!["eth1".to_string()]);
+}
+
+#[test]
+fn test_failure_counter() {
+    let mut counter = FailureCounter::new(3);
+    assert!(!counter.record(false));
+    assert!(!counter.record(false));
+    // A success resets the consecutive failures
+    assert!(!counter.record(true));
+    assert!(!counter.record(false));
+    assert!(!counter.record(false));
+    assert!(counter.record(false));
+    counter.reset();
+    assert!(!counter.record(false));
+}
@@ -0,0 +1,162 @@
+use caracat::models::{Probe, L4};
+use saimiris::agent::fairness::{FairnessKey, FairnessScheduler};
+use saimiris::config::CaracatConfig;
+use std::io::Write;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+fn probe(dst_addr: &str) -> Probe {
+    Probe {
+        dst_addr: dst_addr.parse().unwrap(),
+        src_port: 24000,
+        dst_port: 33434,
+        ttl: 32,
+        protocol: L4::ICMP,
+    }
+}
+
+fn config(fairness: Option<&str>, asn_file: Option<String>) -> CaracatConfig {
+    let mut config = CaracatConfig {
+        fairness: fairness.map(str::to_string),
+        fairness_asn_file: asn_file,
+        ..Default::default()
+    };
+    config.validate_and_normalize();
+    config
+}
+
+fn destinations(probes: &[(Probe, usize)]) -> Vec<String> {
+    probes
+        .iter()
+        .map(|(probe, _)| probe.dst_addr.to_string())
+        .collect()
+}
+
+#[test]
+fn test_fairness_disabled_by_default() {
+    assert!(FairnessScheduler::new(&config(None, None))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_fairness_validation() {
+    assert!(FairnessScheduler::new(&config(Some("random"), None)).is_err());
+    assert!(FairnessScheduler::new(&config(Some("asn"), None)).is_err());
+    assert!(FairnessScheduler::new(&config(Some("prefix"), Some("asns.txt".to_string()))).is_err());
+    assert!(
+        FairnessScheduler::new(&config(Some("asn"), Some("/nonexistent".to_string()))).is_err()
+    );
+
+    let mut invalid = config(Some("prefix"), None);
+    invalid.fairness_ipv4_prefix_len = 33;
+    assert!(FairnessScheduler::new(&invalid).is_err());
+
+    // The rate is per network of the scheduler
+    let mut invalid = config(None, None);
+    invalid.fairness_max_pps = Some(100);
+    assert!(FairnessScheduler::new(&invalid).is_err());
+    let mut invalid = config(Some("prefix"), None);
+    invalid.fairness_max_pps = Some(0);
+    assert!(FairnessScheduler::new(&invalid).is_err());
+}
+
+#[test]
+fn test_fairness_interleaves_prefixes() {
+    let scheduler = FairnessScheduler::new(&config(Some("prefix"), None))
+        .unwrap()
+        .unwrap();
+    // Sorted input: all the probes of a /24 in a row
+    let probes: Vec<(Probe, usize)> = [
+        "192.0.2.1",
+        "192.0.2.2",
+        "192.0.2.3",
+        "198.51.100.1",
+        "198.51.100.2",
+        "2001:db8::1",
+        "2001:db8:0:1::1",
+    ]
+    .iter()
+    .enumerate()
+    .map(|(i, dst_addr)| (probe(dst_addr), i))
+    .collect();
+
+    let interleaved = scheduler.interleave(probes);
+    // The /48 of the IPv6 destinations is shared
+    assert_eq!(
+        destinations(&interleaved),
+        vec![
+            "192.0.2.1",
+            "198.51.100.1",
+            "2001:db8::1",
+            "192.0.2.2",
+            "198.51.100.2",
+            "2001:db8:0:1::1",
+            "192.0.2.3",
+        ]
+    );
+    // The values follow their probe
+    assert_eq!(
+        interleaved.iter().map(|(_, i)| *i).collect::<Vec<_>>(),
+        vec![0, 3, 5, 1, 4, 6, 2]
+    );
+}
+
+#[test]
+fn test_fairness_keys_by_origin_asn() {
+    let mut asn_file = tempfile::NamedTempFile::new().unwrap();
+    writeln!(asn_file, "192.0.2.0/24 64500").unwrap();
+    writeln!(asn_file, "198.51.100.0/24 64500").unwrap();
+    writeln!(asn_file, "198.51.100.128/25 64501").unwrap();
+    let config = config(
+        Some("asn"),
+        Some(asn_file.path().to_string_lossy().to_string()),
+    );
+    let scheduler = FairnessScheduler::new(&config).unwrap().unwrap();
+
+    let key = |dst_addr: &str| scheduler.key(&dst_addr.parse::<IpAddr>().unwrap());
+    assert_eq!(key("192.0.2.1"), FairnessKey::Asn(64500));
+    assert_eq!(key("198.51.100.1"), FairnessKey::Asn(64500));
+    // The most specific prefix applies
+    assert_eq!(key("198.51.100.200"), FairnessKey::Asn(64501));
+    // Unknown destinations are keyed by prefix
+    assert_eq!(
+        key("203.0.113.7"),
+        FairnessKey::Prefix("203.0.113.0/24".parse().unwrap())
+    );
+
+    let probes = vec![
+        (probe("192.0.2.1"), 0),
+        (probe("198.51.100.1"), 1),
+        (probe("198.51.100.200"), 2),
+    ];
+    assert_eq!(
+        destinations(&scheduler.interleave(probes)),
+        vec!["192.0.2.1", "198.51.100.200", "198.51.100.1"]
+    );
+}
+
+#[test]
+fn test_fairness_caps_the_rate_per_network() {
+    let mut capped = config(Some("prefix"), None);
+    capped.fairness_max_pps = Some(2);
+    let scheduler = FairnessScheduler::new(&capped).unwrap().unwrap();
+    let dst: IpAddr = "192.0.2.1".parse().unwrap();
+    let neighbor: IpAddr = "192.0.2.200".parse().unwrap();
+    let other: IpAddr = "198.51.100.1".parse().unwrap();
+    let start = Instant::now();
+
+    assert_eq!(scheduler.take(&dst, start), Duration::ZERO);
+    // Across the messages, whatever their order: the clones share the rate
+    assert_eq!(scheduler.clone().take(&neighbor, start), Duration::ZERO);
+    assert_eq!(scheduler.take(&dst, start), Duration::from_secs(1));
+    assert_eq!(scheduler.take(&other, start), Duration::ZERO);
+
+    // Without fairness_max_pps, the probes are only interleaved
+    let scheduler = FairnessScheduler::new(&config(Some("prefix"), None))
+        .unwrap()
+        .unwrap();
+    for _ in 0..10 {
+        assert_eq!(scheduler.take(&dst, start), Duration::ZERO);
+    }
+}
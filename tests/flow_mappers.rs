@@ -0,0 +1,115 @@
+//! Unit tests for the mapping of the traceroute flows to destinations and source ports
+use saimiris::agent::expand::TtlRange;
+use saimiris::client::generate::{FlowMapper, FLOW_PREFIX_SIZE};
+use saimiris::client::traceroute::traceroute_probes;
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+fn addr(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+/// Last byte of the destinations of the flows of the prefix, which must all be distinct.
+fn prefix_offsets(mapper: FlowMapper, destination: IpAddr) -> Vec<u8> {
+    let offsets: Vec<u8> = (0..FLOW_PREFIX_SIZE)
+        .map(|flow_id| {
+            let (address, port_offset) = mapper.flow(destination, flow_id);
+            assert_eq!(port_offset, 0);
+            match address {
+                IpAddr::V4(address) => address.octets()[3],
+                IpAddr::V6(address) => address.octets()[15],
+            }
+        })
+        .collect();
+    assert_eq!(offsets.iter().collect::<HashSet<_>>().len(), 256);
+    offsets
+}
+
+#[test]
+fn test_ports_mapper() {
+    let destination = addr("192.0.2.77");
+    assert_eq!(FlowMapper::Ports.flow(destination, 0), (destination, 0));
+    assert_eq!(FlowMapper::Ports.flow(destination, 300), (destination, 300));
+}
+
+#[test]
+fn test_sequential_mapper() {
+    let destination = addr("192.0.2.77");
+    assert_eq!(
+        FlowMapper::Sequential.flow(destination, 0),
+        (addr("192.0.2.0"), 0)
+    );
+    assert_eq!(
+        FlowMapper::Sequential.flow(destination, 5),
+        (addr("192.0.2.5"), 0)
+    );
+    // Beyond the addresses of the prefix, the source port varies
+    assert_eq!(
+        FlowMapper::Sequential.flow(destination, 256),
+        (addr("192.0.2.255"), 1)
+    );
+    assert_eq!(
+        FlowMapper::Sequential.flow(destination, 300),
+        (addr("192.0.2.255"), 45)
+    );
+    assert_eq!(
+        FlowMapper::Sequential.flow(addr("2001:db8::1234"), 5),
+        (addr("2001:db8::1205"), 0)
+    );
+    assert_eq!(
+        prefix_offsets(FlowMapper::Sequential, destination),
+        (0..=255).collect::<Vec<u8>>()
+    );
+}
+
+#[test]
+fn test_interval_mapper() {
+    let offsets = prefix_offsets(FlowMapper::Interval, addr("192.0.2.77"));
+    assert_eq!(offsets[..9], [0, 32, 64, 96, 128, 160, 192, 224, 1]);
+}
+
+#[test]
+fn test_reverse_byte_mapper() {
+    let offsets = prefix_offsets(FlowMapper::ReverseByte, addr("2001:db8::1"));
+    assert_eq!(offsets[..5], [0, 128, 64, 192, 32]);
+}
+
+#[test]
+fn test_random_mapper() {
+    let offsets = prefix_offsets(FlowMapper::Random, addr("192.0.2.77"));
+    // Reproducible per prefix, and different across prefixes
+    assert_eq!(
+        prefix_offsets(FlowMapper::Random, addr("192.0.2.1")),
+        offsets
+    );
+    assert_ne!(
+        prefix_offsets(FlowMapper::Random, addr("198.51.100.1")),
+        offsets
+    );
+    assert_ne!(offsets, (0..=255).collect::<Vec<u8>>());
+}
+
+#[test]
+fn test_traceroute_probes_mapped() {
+    let probes = traceroute_probes(
+        &[addr("192.0.2.77")],
+        TtlRange { min: 1, max: 2 },
+        3,
+        FlowMapper::Sequential,
+    );
+    let flows: Vec<(IpAddr, u16, u8)> = probes
+        .iter()
+        .map(|probe| (probe.dst_addr, probe.src_port, probe.ttl))
+        .collect();
+    assert_eq!(
+        flows,
+        vec![
+            (addr("192.0.2.0"), 24000, 1),
+            (addr("192.0.2.0"), 24000, 2),
+            (addr("192.0.2.1"), 24000, 1),
+            (addr("192.0.2.1"), 24000, 2),
+            (addr("192.0.2.2"), 24000, 1),
+            (addr("192.0.2.2"), 24000, 2),
+        ]
+    );
+}
@@ -0,0 +1,71 @@
+//! Tests for the caracat configuration fetched from the gateway
+use reqwest::Client;
+use saimiris::agent::gateway::fetch_caracat_configs;
+use saimiris::config::validate_caracat_configs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Gateway answering a single request with `status` and `body`, returning the request.
+async fn gateway(
+    status: &'static str,
+    body: &'static str,
+) -> (String, tokio::task::JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let task = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let n = stream.read(&mut buffer).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..n]);
+        }
+        let response = format!(
+            "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8(request).unwrap()
+    });
+    (url, task)
+}
+
+#[tokio::test]
+async fn test_fetch_caracat_configs() {
+    let (url, task) = gateway(
+        "200 OK",
+        r#"[{"name": "eth0", "interface": "eth0", "probing_rate": 5000, "src_ipv4_prefix": "192.0.2.0/24"}]"#,
+    )
+    .await;
+    let mut configs = fetch_caracat_configs(&Client::new(), &url, "agent1", "key")
+        .await
+        .unwrap();
+    assert_eq!(configs.len(), 1);
+    assert_eq!(configs[0].interface, "eth0");
+    assert_eq!(configs[0].probing_rate, 5000);
+    // The options not set by the gateway take their default value
+    assert_eq!(configs[0].batch_size, 100);
+    assert!(validate_caracat_configs(&mut configs, "agent1", None).is_ok());
+
+    let request = task.await.unwrap().to_lowercase();
+    assert!(request.starts_with("get /agent-api/agent/agent1/config"));
+    assert!(request.contains("authorization: bearer key"));
+}
+
+#[tokio::test]
+async fn test_fetch_caracat_configs_errors() {
+    for (status, body) in [
+        ("404 Not Found", ""),
+        ("200 OK", "[]"),
+        ("200 OK", r#"{"interface": "eth0"}"#),
+    ] {
+        let (url, _task) = gateway(status, body).await;
+        let result = fetch_caracat_configs(&Client::new(), &url, "agent1", "key").await;
+        assert!(result.is_err(), "{} {}", status, body);
+    }
+}
@@ -0,0 +1,136 @@
+//! Tests for the commands pushed by the gateway
+use reqwest::Client;
+use saimiris::agent::control::AgentMode;
+use saimiris::agent::events::EventLog;
+use saimiris::agent::gateway::{fetch_commands, CommandTargets, GatewayCommand};
+use saimiris::agent::ratelimit::SharedRateLimiter;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, watch};
+
+fn targets(
+    total_rate: Option<SharedRateLimiter>,
+) -> (
+    CommandTargets,
+    watch::Receiver<AgentMode>,
+    mpsc::Receiver<String>,
+) {
+    let (mode, mode_rx) = watch::channel(AgentMode::default());
+    let (cancellations, cancellations_rx) = mpsc::channel(10);
+    let targets = CommandTargets {
+        mode: Arc::new(mode),
+        events: EventLog::disabled(),
+        total_rate,
+        cancellations,
+    };
+    (targets, mode_rx, cancellations_rx)
+}
+
+#[test]
+fn test_parse_commands() {
+    let commands: Vec<GatewayCommand> = serde_json::from_str(
+        r#"[
+            {"command": "pause"},
+            {"command": "set_rate", "probing_rate": 5000},
+            {"command": "cancel_measurement", "measurement_id": "m1"}
+        ]"#,
+    )
+    .unwrap();
+    assert_eq!(
+        commands,
+        vec![
+            GatewayCommand::Pause,
+            GatewayCommand::SetRate { probing_rate: 5000 },
+            GatewayCommand::CancelMeasurement {
+                measurement_id: "m1".to_string()
+            },
+        ]
+    );
+    assert!(serde_json::from_str::<GatewayCommand>(r#"{"command": "reboot"}"#).is_err());
+    assert!(serde_json::from_str::<GatewayCommand>(r#"{"command": "set_rate"}"#).is_err());
+}
+
+#[test]
+fn test_apply_commands() {
+    let (targets, mode_rx, mut cancellations) = targets(None);
+    targets.apply(GatewayCommand::Pause);
+    assert_eq!(*mode_rx.borrow(), AgentMode::Paused);
+    targets.apply(GatewayCommand::Drain);
+    assert_eq!(*mode_rx.borrow(), AgentMode::Draining);
+    targets.apply(GatewayCommand::Resume);
+    assert_eq!(*mode_rx.borrow(), AgentMode::Running);
+
+    targets.apply(GatewayCommand::CancelMeasurement {
+        measurement_id: "m1".to_string(),
+    });
+    assert_eq!(cancellations.try_recv().unwrap(), "m1");
+
+    // Without agent.max_total_pps, the rate is left unchanged
+    targets.apply(GatewayCommand::SetRate { probing_rate: 10 });
+}
+
+#[test]
+fn test_set_rate() {
+    let total_rate = SharedRateLimiter::new(100);
+    let (targets, _, _) = targets(Some(total_rate.clone()));
+    // 1 token in the bucket, 10 owed
+    let now = Instant::now();
+    assert_eq!(total_rate.take(now, 11), Duration::from_millis(100));
+
+    targets.apply(GatewayCommand::SetRate { probing_rate: 10 });
+    let delay = total_rate.take(now, 10);
+    assert!(delay >= Duration::from_secs(1), "{:?}", delay);
+    // An invalid rate is ignored
+    targets.apply(GatewayCommand::SetRate { probing_rate: 0 });
+    assert!(total_rate.take(now, 1) >= Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn test_fetch_commands() {
+    const BODY: &str = r#"[{"command": "drain"}]"#;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let gateway = tokio::spawn(async move {
+        let mut requests = Vec::new();
+        for response in [
+            "HTTP/1.1 204 No Content\r\n\r\n".to_string(),
+            format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
+                BODY.len(),
+                BODY
+            ),
+        ] {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buffer).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..n]);
+            }
+            stream.write_all(response.as_bytes()).await.unwrap();
+            requests.push(String::from_utf8(request).unwrap());
+        }
+        requests
+    });
+
+    // A client per request, each on its own connection
+    let wait = Duration::from_secs(30);
+    assert!(fetch_commands(&Client::new(), &url, "agent1", "key", wait)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        fetch_commands(&Client::new(), &url, "agent1", "key", wait)
+            .await
+            .unwrap(),
+        vec![GatewayCommand::Drain]
+    );
+
+    let requests = gateway.await.unwrap();
+    assert!(requests[0].starts_with("GET /agent-api/agent/agent1/commands?wait=30 "));
+}
@@ -0,0 +1,53 @@
+//! Tests for the schedule of the gateway healthchecks
+use saimiris::agent::gateway::HealthcheckSchedule;
+use saimiris::config::GatewayConfig;
+use std::time::Duration;
+
+fn config(interval: u64, backoff: u64, max_backoff: u64, jitter: f64) -> GatewayConfig {
+    GatewayConfig {
+        healthcheck_interval: interval,
+        registration_retry_backoff: backoff,
+        registration_retry_max_backoff: max_backoff,
+        healthcheck_jitter: jitter,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_exponential_backoff() {
+    let mut schedule = HealthcheckSchedule::new(&config(30, 5, 60, 0.0)).unwrap();
+    assert_eq!(schedule.success(), Duration::from_secs(30));
+    let backoffs: Vec<u64> = (0..6).map(|_| schedule.failure().as_secs()).collect();
+    assert_eq!(backoffs, vec![5, 10, 20, 40, 60, 60]);
+
+    // The backoff starts over after a successful cycle
+    assert_eq!(schedule.success(), Duration::from_secs(30));
+    assert_eq!(schedule.failure(), Duration::from_secs(5));
+
+    // Without overflowing after many failures
+    for _ in 0..100 {
+        schedule.failure();
+    }
+    assert_eq!(schedule.failure(), Duration::from_secs(60));
+}
+
+#[test]
+fn test_jitter() {
+    let mut schedule = HealthcheckSchedule::new(&config(100, 10, 10, 0.2)).unwrap();
+    for _ in 0..100 {
+        let delay = schedule.success();
+        assert!(delay >= Duration::from_secs(80) && delay <= Duration::from_secs(120));
+        let delay = schedule.failure();
+        assert!(delay >= Duration::from_secs(8) && delay <= Duration::from_secs(12));
+    }
+}
+
+#[test]
+fn test_schedule_validation() {
+    assert!(HealthcheckSchedule::new(&config(30, 5, 300, 0.1)).is_ok());
+    assert!(HealthcheckSchedule::new(&config(0, 5, 300, 0.1)).is_err());
+    assert!(HealthcheckSchedule::new(&config(30, 0, 300, 0.1)).is_err());
+    assert!(HealthcheckSchedule::new(&config(30, 60, 30, 0.1)).is_err());
+    assert!(HealthcheckSchedule::new(&config(30, 5, 300, 1.0)).is_err());
+    assert!(HealthcheckSchedule::new(&config(30, 5, 300, -0.1)).is_err());
+}
@@ -0,0 +1,46 @@
+//! Tests for the measurements polled from the gateway (pull model)
+use reqwest::Client;
+use saimiris::agent::gateway::AssignedMeasurement;
+use saimiris::agent::poll::measurement_probes;
+use saimiris::config::ProbesFormat;
+
+#[test]
+fn test_assigned_measurement_defaults() {
+    let measurement: AssignedMeasurement =
+        serde_json::from_str(r#"{"measurement_id": "m1"}"#).unwrap();
+    assert_eq!(measurement.measurement_id, "m1");
+    assert_eq!(measurement.src_ip, None);
+    assert!(measurement.probes.is_empty());
+    assert_eq!(measurement.probes_url, None);
+    assert_eq!(measurement.probes_format, ProbesFormat::Csv);
+}
+
+#[test]
+fn test_assigned_measurement_download_handle() {
+    let measurement: AssignedMeasurement = serde_json::from_str(
+        r#"{"measurement_id": "m2", "src_ip": "192.0.2.1", "probes_url": "https://gateway/probes/m2", "probes_format": "jsonl"}"#,
+    )
+    .unwrap();
+    assert_eq!(measurement.src_ip.as_deref(), Some("192.0.2.1"));
+    assert_eq!(
+        measurement.probes_url.as_deref(),
+        Some("https://gateway/probes/m2")
+    );
+    assert_eq!(measurement.probes_format, ProbesFormat::Jsonl);
+}
+
+#[tokio::test]
+async fn test_inline_measurement_probes() {
+    let measurement: AssignedMeasurement = serde_json::from_str(
+        r#"{"measurement_id": "m3", "probes": [
+            {"dst_addr": "8.8.8.8", "src_port": 24000, "dst_port": 33434, "ttl": 12, "protocol": "UDP"},
+            {"dst_addr": "8.8.4.4", "src_port": 24000, "dst_port": 33434, "ttl": 13, "protocol": "UDP"}
+        ]}"#,
+    )
+    .unwrap();
+    let probes = measurement_probes(&Client::new(), &measurement, "key")
+        .await
+        .unwrap();
+    assert_eq!(probes.len(), 2);
+    assert_eq!(probes[1].ttl, 13);
+}
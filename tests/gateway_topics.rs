@@ -0,0 +1,27 @@
+//! Tests for the probes topics assigned by the gateway
+use saimiris::agent::gateway::HealthResponse;
+
+fn assigned(body: &str) -> Option<Vec<String>> {
+    serde_json::from_str::<HealthResponse>(body)
+        .unwrap()
+        .assigned_topics()
+}
+
+#[test]
+fn test_assigned_topics() {
+    assert_eq!(
+        assigned(r#"{"topics": ["saimiris-probes-urgent", " saimiris-probes "]}"#),
+        Some(vec![
+            "saimiris-probes-urgent".to_string(),
+            "saimiris-probes".to_string()
+        ])
+    );
+
+    // The configured topics are kept without an assignment
+    assert_eq!(assigned("{}"), None);
+    assert_eq!(assigned(r#"{"status": "ok"}"#), None);
+    assert_eq!(assigned(r#"{"topics": null}"#), None);
+    assert_eq!(assigned(r#"{"topics": []}"#), None);
+    assert_eq!(assigned(r#"{"topics": ["", " "]}"#), None);
+    assert_eq!(HealthResponse::default().assigned_topics(), None);
+}
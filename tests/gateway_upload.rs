@@ -0,0 +1,63 @@
+//! Unit tests for the gateway reply upload
+use flate2::read::GzDecoder;
+use saimiris::agent::upload::{chunk_url, compress, read_spool, Chunk};
+use std::fs;
+use std::io::Read;
+
+fn chunk(upload_id: &str, sequence: u64) -> Chunk {
+    Chunk {
+        upload_id: upload_id.to_string(),
+        sequence,
+        body: vec![1, 2, 3],
+    }
+}
+
+#[test]
+fn test_chunk_file_name_roundtrip() {
+    let chunk = chunk("0123abcd", 42);
+    assert_eq!(chunk.file_name(), "0123abcd-000042.gz");
+    assert_eq!(
+        Chunk::parse_file_name(&chunk.file_name()),
+        Some(("0123abcd".to_string(), 42))
+    );
+
+    assert_eq!(Chunk::parse_file_name("0123abcd-000042"), None);
+    assert_eq!(Chunk::parse_file_name("0123abcd-x.gz"), None);
+    assert_eq!(Chunk::parse_file_name("notes.txt"), None);
+}
+
+#[test]
+fn test_compress() {
+    let data = b"replies".repeat(100);
+    let compressed = compress(&data).unwrap();
+    assert!(compressed.len() < data.len());
+
+    let mut decompressed = Vec::new();
+    GzDecoder::new(compressed.as_slice())
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert_eq!(decompressed, data);
+}
+
+#[test]
+fn test_chunk_url() {
+    assert_eq!(
+        chunk_url("https://gateway/", "agent1", &chunk("0123abcd", 7)),
+        "https://gateway/agent-api/agent/agent1/replies/0123abcd/7"
+    );
+}
+
+#[test]
+fn test_read_spool() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = chunk("0123abcd", 0);
+    let second = chunk("0123abcd", 1);
+    fs::write(dir.path().join(second.file_name()), &second.body).unwrap();
+    fs::write(dir.path().join(first.file_name()), &first.body).unwrap();
+    fs::write(dir.path().join("notes.txt"), b"ignored").unwrap();
+
+    let chunks = read_spool(dir.path()).unwrap();
+    assert_eq!(chunks.len(), 2);
+    assert!(chunks.contains(&first));
+    assert!(chunks.contains(&second));
+}
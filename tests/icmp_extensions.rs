@@ -0,0 +1,127 @@
+//! Tests for the parsing of the ICMP extension objects (RFC 4884) of the replies
+use caracat::models::Reply;
+use saimiris::icmp_extension::{parse_ip, IcmpExtension, INTERFACE_INFORMATION_CLASS};
+use saimiris::probe::ProbeContext;
+use saimiris::reply::{deserialize_replies, serialize_reply, CapturedReply};
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u32)
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Extension structure with an MPLS label stack (labels 16 and 17) and an interface
+/// information object.
+fn extension_structure() -> Vec<u8> {
+    let mut extension = vec![0x20, 0x00, 0x00, 0x00];
+    // MPLS label stack: label 16, exp 0, TTL 1, then label 17, bottom of stack, TTL 1
+    extension.extend_from_slice(&[0x00, 0x0c, 0x01, 0x01]);
+    extension.extend_from_slice(&((16 << 12) | 1u32).to_be_bytes());
+    extension.extend_from_slice(&((17 << 12) | (1 << 8) | 1u32).to_be_bytes());
+    // Interface information: ifIndex 42
+    extension.extend_from_slice(&[0x00, 0x08, INTERFACE_INFORMATION_CLASS, 0x08]);
+    extension.extend_from_slice(&42u32.to_be_bytes());
+    let sum = checksum(&extension);
+    extension[2..4].copy_from_slice(&sum.to_be_bytes());
+    extension
+}
+
+/// IPv4 Time Exceeded message quoting a 128 bytes datagram, `length` being the length of the
+/// quote announced in the ICMP header (0 for the routers predating RFC 4884).
+fn time_exceeded(length: u8, extension: &[u8]) -> Vec<u8> {
+    let mut icmp = vec![11, 0, 0, 0, 0, length, 0, 0];
+    icmp.extend_from_slice(&[0x45; 128]);
+    icmp.extend_from_slice(extension);
+    let total_len = (20 + icmp.len()) as u16;
+    let mut ip = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 1, 0, 0];
+    ip[2..4].copy_from_slice(&total_len.to_be_bytes());
+    ip.extend_from_slice(&[192, 0, 2, 1, 192, 0, 2, 100]);
+    ip.extend_from_slice(&icmp);
+    ip
+}
+
+#[test]
+fn test_parse_extensions() {
+    for length in [32, 0] {
+        let extensions = parse_ip(&time_exceeded(length, &extension_structure()));
+        assert_eq!(extensions.len(), 2);
+        assert!(extensions[0].is_mpls_label_stack());
+        let labels = extensions[0].mpls_labels();
+        assert_eq!(
+            labels.iter().map(|label| label.label).collect::<Vec<_>>(),
+            vec![16, 17]
+        );
+        assert!(!labels[0].bottom_of_stack);
+        assert!(labels[1].bottom_of_stack);
+        assert_eq!(labels[1].ttl, 1);
+        assert_eq!(
+            extensions[1],
+            IcmpExtension {
+                class_num: INTERFACE_INFORMATION_CLASS,
+                c_type: 0x08,
+                payload: vec![0, 0, 0, 42],
+            }
+        );
+        assert!(extensions[1].mpls_labels().is_empty());
+    }
+}
+
+#[test]
+fn test_parse_invalid_extensions() {
+    // No extension structure
+    assert!(parse_ip(&time_exceeded(32, &[])).is_empty());
+    // Invalid checksum
+    let mut extension = extension_structure();
+    extension[2] ^= 0xff;
+    assert!(parse_ip(&time_exceeded(32, &extension)).is_empty());
+    // Zero checksum, as sent by some routers
+    extension[2..4].copy_from_slice(&[0, 0]);
+    assert_eq!(parse_ip(&time_exceeded(32, &extension)).len(), 2);
+    // Not an ICMP error
+    let mut echo_reply = time_exceeded(32, &extension_structure());
+    echo_reply[20] = 0;
+    assert!(parse_ip(&echo_reply).is_empty());
+}
+
+#[test]
+fn test_extension_bytes() {
+    let extension = IcmpExtension {
+        class_num: INTERFACE_INFORMATION_CLASS,
+        c_type: 0x08,
+        payload: vec![0, 0, 0, 42],
+    };
+    let bytes = extension.to_bytes();
+    assert_eq!(bytes, vec![0, 8, 2, 8, 0, 0, 0, 42]);
+    assert_eq!(IcmpExtension::from_bytes(&bytes), Some(extension));
+    assert_eq!(IcmpExtension::from_bytes(&bytes[..6]), None);
+}
+
+#[test]
+fn test_reply_extensions_round_trip() {
+    let captured = CapturedReply {
+        reply: Reply {
+            reply_src_addr: "192.0.2.1".parse().unwrap(),
+            probe_dst_addr: "8.8.8.8".parse().unwrap(),
+            ..Default::default()
+        },
+        extensions: vec![IcmpExtension {
+            class_num: INTERFACE_INFORMATION_CLASS,
+            c_type: 0x08,
+            payload: vec![0, 0, 0, 42],
+        }],
+    };
+    let message = serialize_reply("agent1".to_string(), &captured, &ProbeContext::default());
+    let replies = deserialize_replies(message).unwrap();
+    assert_eq!(replies.len(), 1);
+    let extensions = &replies[0].reply_icmp_extensions;
+    assert_eq!(extensions.len(), 1);
+    assert_eq!(extensions[0].class_num, INTERFACE_INFORMATION_CLASS);
+    assert_eq!(extensions[0].payload, "0000002a");
+    // Not an unknown field of a newer agent
+    assert_eq!(replies[0].extensions, None);
+}
@@ -0,0 +1,129 @@
+//! Unit tests for decoding Kafka payloads
+use caracat::models::{Probe, L4};
+use saimiris::inspect::{inspect_payload, InspectFilter, PayloadKind};
+use saimiris::probe::{serialize_tagged_probe, ProbeTags};
+use saimiris::reply::{ReplyFormat, ReplyRecord};
+use std::collections::BTreeMap;
+
+fn probes_payload() -> Vec<u8> {
+    let probe = Probe {
+        dst_addr: "8.8.8.8".parse().unwrap(),
+        src_port: 24000,
+        dst_port: 33434,
+        ttl: 12,
+        protocol: L4::UDP,
+    };
+    let mut payload = serialize_tagged_probe(&probe, &ProbeTags::default());
+    payload.extend(serialize_tagged_probe(
+        &probe,
+        &ProbeTags {
+            round: 2,
+            ..Default::default()
+        },
+    ));
+    payload
+}
+
+fn headers() -> BTreeMap<String, String> {
+    BTreeMap::from([
+        (
+            "agent1".to_string(),
+            r#"{"src_ip":"192.0.2.1"}"#.to_string(),
+        ),
+        ("measurement_id".to_string(), "m1".to_string()),
+    ])
+}
+
+#[test]
+fn test_inspect_probes() {
+    let message = inspect_payload(
+        PayloadKind::Probes,
+        &probes_payload(),
+        headers(),
+        &InspectFilter::default(),
+    )
+    .unwrap()
+    .unwrap();
+    let probes = message.probes.unwrap();
+    assert_eq!(probes.len(), 2);
+    assert_eq!(probes[0].protocol, "udp");
+    assert_eq!(probes[1].round, 2);
+
+    // Unset tags are not printed
+    let json = serde_json::to_value(&probes[0]).unwrap();
+    assert!(json.get("round").is_none());
+    assert_eq!(json["ttl"], 12);
+}
+
+#[test]
+fn test_inspect_probes_filter() {
+    let filter = |agent: &str, measurement_id: &str| InspectFilter {
+        agent: Some(agent.to_string()),
+        measurement_id: Some(measurement_id.to_string()),
+    };
+    let inspect = |filter: InspectFilter| {
+        inspect_payload(PayloadKind::Probes, &probes_payload(), headers(), &filter).unwrap()
+    };
+    assert!(inspect(filter("agent1", "m1")).is_some());
+    assert!(inspect(filter("agent2", "m1")).is_none());
+    assert!(inspect(filter("agent1", "m2")).is_none());
+}
+
+#[test]
+fn test_inspect_invalid_payload() {
+    // Probes are not replies
+    assert!(inspect_payload(
+        PayloadKind::Replies,
+        &probes_payload(),
+        BTreeMap::new(),
+        &InspectFilter::default()
+    )
+    .is_err());
+}
+
+#[test]
+fn test_inspect_json_reply() {
+    assert_eq!(ReplyFormat::parse("capnp").unwrap(), ReplyFormat::Capnp);
+    assert_eq!(ReplyFormat::parse("json").unwrap(), ReplyFormat::Json);
+    assert!(ReplyFormat::parse("avro").is_err());
+
+    let reply = serde_json::json!({
+        "time_received_ns": 1_000,
+        "agent_id": "agent1",
+        "reply_src_addr": "192.0.2.1",
+        "reply_dst_addr": "192.0.2.100",
+        "reply_id": 0,
+        "reply_size": 56,
+        "reply_ttl": 60,
+        "reply_quoted_ttl": 1,
+        "reply_protocol": 1,
+        "reply_icmp_type": 11,
+        "reply_icmp_code": 0,
+        "reply_mpls_labels": [],
+        "probe_src_addr": "192.0.2.100",
+        "probe_dst_addr": "8.8.8.8",
+        "probe_id": 0,
+        "probe_size": 28,
+        "probe_ttl": 5,
+        "probe_protocol": 17,
+        "probe_src_port": 24000,
+        "probe_dst_port": 33434,
+        "rtt": 100,
+        "round": 0,
+        "measurement_id": "m1",
+        "instance_id": 0
+    });
+    let payload = serde_json::to_vec(&reply).unwrap();
+    let message = inspect_payload(
+        PayloadKind::Replies,
+        &payload,
+        BTreeMap::new(),
+        &InspectFilter::default(),
+    )
+    .unwrap()
+    .unwrap();
+    let replies: Vec<ReplyRecord> = message.replies.unwrap();
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].measurement_id, "m1");
+    assert_eq!(serde_json::to_value(&replies[0]).unwrap(), reply);
+}
@@ -0,0 +1,78 @@
+use saimiris::agent::integrity::{accepted_ids, encoding_id, family_label, is_checked};
+use saimiris::config::CaracatConfig;
+use std::net::IpAddr;
+
+fn caracat_config(encoding: &str, accept_instance_id: bool) -> CaracatConfig {
+    CaracatConfig {
+        instance_id: 42,
+        integrity_encoding: encoding.to_string(),
+        integrity_accept_instance_id: accept_instance_id,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_instance_id_encoding() {
+    let config = caracat_config("instance_id", false);
+    assert_eq!(encoding_id(&config, "agent1", None).unwrap(), 42);
+    assert_eq!(accepted_ids(&config, "agent1", None).unwrap(), vec![42]);
+}
+
+#[test]
+fn test_hmac_encoding() {
+    let config = caracat_config("hmac", false);
+    assert!(encoding_id(&config, "agent1", None).is_err());
+
+    let id = encoding_id(&config, "agent1", Some("secret")).unwrap();
+    // Deterministic for a given key, and keyed per agent
+    assert_eq!(id, encoding_id(&config, "agent1", Some("secret")).unwrap());
+    assert_ne!(id, encoding_id(&config, "agent1", Some("other")).unwrap());
+    assert_ne!(id, encoding_id(&config, "agent2", Some("secret")).unwrap());
+    assert_eq!(
+        accepted_ids(&config, "agent1", Some("secret")).unwrap(),
+        vec![id]
+    );
+}
+
+#[test]
+fn test_hmac_encoding_transition() {
+    let config = caracat_config("hmac", true);
+    let id = encoding_id(&config, "agent1", Some("secret")).unwrap();
+    assert_eq!(
+        accepted_ids(&config, "agent1", Some("secret")).unwrap(),
+        vec![id, 42]
+    );
+}
+
+#[test]
+fn test_unknown_encoding() {
+    let config = caracat_config("crc", false);
+    assert!(encoding_id(&config, "agent1", None).is_err());
+}
+
+#[test]
+fn test_integrity_check_per_family() {
+    let v4: IpAddr = "192.0.2.1".parse().unwrap();
+    let mapped: IpAddr = "::ffff:192.0.2.1".parse().unwrap();
+    let v6: IpAddr = "2001:db8::1".parse().unwrap();
+    assert_eq!(family_label(v4), "ipv4");
+    assert_eq!(family_label(mapped), "ipv4");
+    assert_eq!(family_label(v6), "ipv6");
+
+    let config = CaracatConfig {
+        integrity_check: true,
+        integrity_check_ipv6: Some(false),
+        ..Default::default()
+    };
+    assert!(is_checked(&config, v4));
+    assert!(is_checked(&config, mapped));
+    assert!(!is_checked(&config, v6));
+
+    let config = CaracatConfig {
+        integrity_check: false,
+        integrity_check_ipv4: Some(true),
+        ..Default::default()
+    };
+    assert!(is_checked(&config, v4));
+    assert!(!is_checked(&config, v6));
+}
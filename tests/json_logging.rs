@@ -0,0 +1,47 @@
+//! Unit tests for the JSON logs format
+use saimiris::logging::{set_agent_id, JsonFields, JsonFormat};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use tracing::{info, info_span};
+
+/// Writer of the logs lines, shared with the test.
+#[derive(Clone, Default)]
+struct Lines(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Lines {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_json_logs_fields() {
+    let lines = Lines::default();
+    let writer = lines.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .event_format(JsonFormat)
+        .fmt_fields(JsonFields)
+        .with_writer(move || writer.clone())
+        .finish();
+    set_agent_id("agent1");
+
+    tracing::subscriber::with_default(subscriber, || {
+        let _instance = info_span!("instance", instance_id = 3).entered();
+        let _measurement = info_span!("measurement", measurement_id = "m1").entered();
+        info!(probes = 10, "Sent {} probes", 10);
+    });
+
+    let output = String::from_utf8(lines.0.lock().unwrap().clone()).unwrap();
+    let line: serde_json::Value = serde_json::from_str(output.lines().next().unwrap()).unwrap();
+    assert_eq!(line["level"], "INFO");
+    assert_eq!(line["agent_id"], "agent1");
+    assert_eq!(line["instance_id"], 3);
+    assert_eq!(line["measurement_id"], "m1");
+    assert_eq!(line["probes"], 10);
+    assert_eq!(line["message"], "Sent 10 probes");
+    assert!(line["timestamp"].as_str().unwrap().ends_with('Z'));
+}
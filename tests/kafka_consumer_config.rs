@@ -0,0 +1,100 @@
+//! Tests for the Kafka consumer settings of the agent
+use saimiris::config::{app_config, KafkaConfig};
+use std::fs::File;
+use std::io::Write;
+use tempfile::tempdir;
+
+#[test]
+fn test_consumer_defaults() {
+    let config = KafkaConfig::default();
+    assert_eq!(config.auto_offset_reset(), "latest");
+    assert_eq!(config.session_timeout_ms(), 6000);
+    assert_eq!(config.in_fetch_min_bytes, None);
+    assert_eq!(config.in_max_poll_interval_ms, None);
+    assert!(config.validate().is_ok());
+}
+
+#[test]
+fn test_invalid_auto_offset_reset() {
+    let config = KafkaConfig {
+        in_auto_offset_reset: Some("oldest".to_string()),
+        ..Default::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_consumer_settings_from_config() {
+    let dir = tempdir().unwrap();
+    let config_path = dir.path().join("test_config.yml");
+    let mut file = File::create(&config_path).unwrap();
+    writeln!(file, "agent:").unwrap();
+    writeln!(file, "  metrics_address: '0.0.0.0:8080'").unwrap();
+    writeln!(file, "kafka:").unwrap();
+    writeln!(file, "  in_auto_offset_reset: earliest").unwrap();
+    writeln!(file, "  in_fetch_min_bytes: 1024").unwrap();
+    writeln!(file, "  in_max_poll_interval_ms: 600000").unwrap();
+    writeln!(file, "  in_session_timeout_ms: 10000").unwrap();
+    drop(file);
+
+    let config = app_config(config_path.to_str().unwrap()).await.unwrap();
+    assert_eq!(config.kafka.auto_offset_reset(), "earliest");
+    assert_eq!(config.kafka.in_fetch_min_bytes, Some(1024));
+    assert_eq!(config.kafka.in_max_poll_interval_ms, Some(600000));
+    assert_eq!(config.kafka.session_timeout_ms(), 10000);
+}
+
+#[tokio::test]
+async fn test_invalid_consumer_settings_rejected() {
+    let dir = tempdir().unwrap();
+    let config_path = dir.path().join("test_config.yml");
+    let mut file = File::create(&config_path).unwrap();
+    writeln!(file, "agent:").unwrap();
+    writeln!(file, "  metrics_address: '0.0.0.0:8080'").unwrap();
+    writeln!(file, "kafka:").unwrap();
+    writeln!(file, "  in_auto_offset_reset: oldest").unwrap();
+    drop(file);
+
+    assert!(app_config(config_path.to_str().unwrap()).await.is_err());
+}
+
+#[test]
+fn test_properties_applied() {
+    let config = KafkaConfig {
+        properties: [
+            ("linger.ms".to_string(), "50".to_string()),
+            ("fetch.max.bytes".to_string(), "1048576".to_string()),
+        ]
+        .into(),
+        ..Default::default()
+    };
+    assert!(config.validate().is_ok());
+
+    let mut client_config = rdkafka::config::ClientConfig::new();
+    client_config.set("linger.ms", "5");
+    config.apply_properties(&mut client_config);
+    assert_eq!(client_config.get("linger.ms"), Some("50"));
+    assert_eq!(client_config.get("fetch.max.bytes"), Some("1048576"));
+}
+
+#[test]
+fn test_empty_property_key_rejected() {
+    let config = KafkaConfig {
+        properties: [(" ".to_string(), "1".to_string())].into(),
+        ..Default::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_compression() {
+    let config = |compression: &str| KafkaConfig {
+        compression: Some(compression.to_string()),
+        ..Default::default()
+    };
+    assert!(KafkaConfig::default().compression.is_none());
+    for compression in ["none", "gzip", "snappy", "lz4", "zstd"] {
+        assert!(config(compression).validate().is_ok());
+    }
+    assert!(config("brotli").validate().is_err());
+}
@@ -0,0 +1,84 @@
+//! Tests for the Kafka cluster the replies are produced to
+use saimiris::auth::KafkaAuth;
+use saimiris::config::{app_config, KafkaClusterConfig, KafkaConfig};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use tempfile::tempdir;
+
+fn out_cluster(brokers: &str, auth_protocol: &str) -> KafkaClusterConfig {
+    KafkaClusterConfig {
+        brokers: brokers.to_string(),
+        auth_protocol: auth_protocol.to_string(),
+        auth_sasl_username: "saimiris".to_string(),
+        auth_sasl_password: "saimiris".to_string(),
+        auth_sasl_mechanism: "SCRAM-SHA-512".to_string(),
+        properties: HashMap::new(),
+    }
+}
+
+#[test]
+fn test_invalid_out_cluster() {
+    let config = KafkaConfig {
+        out: Some(out_cluster("", "PLAINTEXT")),
+        ..Default::default()
+    };
+    assert!(config.validate().is_err());
+
+    let config = KafkaConfig {
+        out: Some(out_cluster("replies:9092", "SSL")),
+        ..Default::default()
+    };
+    assert!(config.validate().is_err());
+
+    let config = KafkaConfig {
+        out: Some(out_cluster("replies:9092", "SASL_PLAINTEXT")),
+        ..Default::default()
+    };
+    assert!(config.validate().is_ok());
+}
+
+#[tokio::test]
+async fn test_out_cluster_from_config() {
+    let dir = tempdir().unwrap();
+    let config_path = dir.path().join("test_config.yml");
+    let mut file = File::create(&config_path).unwrap();
+    writeln!(file, "agent:").unwrap();
+    writeln!(file, "  metrics_address: '0.0.0.0:8080'").unwrap();
+    writeln!(file, "kafka:").unwrap();
+    writeln!(file, "  brokers: 'probes:9092'").unwrap();
+    writeln!(file, "  out:").unwrap();
+    writeln!(file, "    brokers: 'replies:9092'").unwrap();
+    writeln!(file, "    auth_protocol: SASL_PLAINTEXT").unwrap();
+    writeln!(file, "    auth_sasl_username: replies").unwrap();
+    writeln!(file, "    auth_sasl_password: secret").unwrap();
+    writeln!(file, "    properties: {{linger.ms: '50'}}").unwrap();
+    drop(file);
+
+    let config = app_config(config_path.to_str().unwrap()).await.unwrap();
+    assert_eq!(config.kafka.brokers, "probes:9092");
+    let out = config.kafka.out.unwrap();
+    assert_eq!(out.brokers, "replies:9092");
+    assert_eq!(out.properties["linger.ms"], "50");
+    match out.auth().unwrap() {
+        KafkaAuth::SasalPlainText(auth) => {
+            assert_eq!(auth.username, "replies");
+            assert_eq!(auth.password, "secret");
+            assert_eq!(auth.mechanism, "SCRAM-SHA-512");
+        }
+        KafkaAuth::PlainText => panic!("Expected SASL authentication"),
+    }
+}
+
+#[tokio::test]
+async fn test_out_cluster_unset() {
+    let dir = tempdir().unwrap();
+    let config_path = dir.path().join("test_config.yml");
+    let mut file = File::create(&config_path).unwrap();
+    writeln!(file, "agent:").unwrap();
+    writeln!(file, "  metrics_address: '0.0.0.0:8080'").unwrap();
+    drop(file);
+
+    let config = app_config(config_path.to_str().unwrap()).await.unwrap();
+    assert!(config.kafka.out.is_none());
+}
@@ -0,0 +1,30 @@
+//! Unit tests for the Kafka probes topics of the agents and the client
+use saimiris::config::KafkaConfig;
+
+#[test]
+fn test_shared_in_topics() {
+    let config = KafkaConfig {
+        in_topics: "saimiris-probes,saimiris-probes-2".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(
+        config.agent_in_topics("agent1"),
+        vec!["saimiris-probes", "saimiris-probes-2"]
+    );
+    // The client produces to the first topic
+    assert_eq!(config.client_in_topic("agent1"), "saimiris-probes");
+}
+
+#[test]
+fn test_in_topic_template() {
+    let config = KafkaConfig {
+        in_topics: "saimiris-probes".to_string(),
+        in_topic_template: Some("saimiris-probes-{agent}".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        config.agent_in_topics("agent1"),
+        vec!["saimiris-probes-agent1"]
+    );
+    assert_eq!(config.client_in_topic("agent2"), "saimiris-probes-agent2");
+}
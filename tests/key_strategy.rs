@@ -0,0 +1,66 @@
+//! Unit tests for the keys of the produced probes and replies messages
+use saimiris::config::{KafkaConfig, KeyStrategy};
+use std::net::IpAddr;
+
+fn dst(addr: &str) -> Option<IpAddr> {
+    Some(addr.parse().unwrap())
+}
+
+#[test]
+fn test_key_strategy_parse() {
+    assert_eq!(KeyStrategy::parse("").unwrap(), KeyStrategy::None);
+    assert_eq!(KeyStrategy::parse("none").unwrap(), KeyStrategy::None);
+    assert_eq!(
+        KeyStrategy::parse("agent_id").unwrap(),
+        KeyStrategy::AgentId
+    );
+    assert_eq!(
+        KeyStrategy::parse("measurement_id").unwrap(),
+        KeyStrategy::MeasurementId
+    );
+    assert_eq!(
+        KeyStrategy::parse("probe_dst_prefix").unwrap(),
+        KeyStrategy::ProbeDstPrefix
+    );
+    assert!(KeyStrategy::parse("random").is_err());
+
+    let config = KafkaConfig {
+        key_strategy: "agent".to_string(),
+        ..Default::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_key_strategy_keys() {
+    let measurement = Some("m1");
+    assert_eq!(
+        KeyStrategy::None.key("agent1", measurement, dst("192.0.2.1")),
+        None
+    );
+    assert_eq!(
+        KeyStrategy::AgentId.key("agent1", measurement, dst("192.0.2.1")),
+        Some("agent1".to_string())
+    );
+    assert_eq!(
+        KeyStrategy::MeasurementId.key("agent1", measurement, dst("192.0.2.1")),
+        Some("m1".to_string())
+    );
+    // Messages outside of a measurement are not keyed
+    assert_eq!(
+        KeyStrategy::MeasurementId.key("agent1", None, dst("192.0.2.1")),
+        None
+    );
+    assert_eq!(
+        KeyStrategy::ProbeDstPrefix.key("agent1", measurement, dst("192.0.2.77")),
+        Some("192.0.2.0/24".to_string())
+    );
+    assert_eq!(
+        KeyStrategy::ProbeDstPrefix.key("agent1", measurement, dst("2001:db8:1:2::1")),
+        Some("2001:db8:1::/48".to_string())
+    );
+    assert_eq!(
+        KeyStrategy::ProbeDstPrefix.key("agent1", measurement, None),
+        None
+    );
+}
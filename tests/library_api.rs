@@ -0,0 +1,54 @@
+//! Tests for the library API used by other services, without the command line
+use caracat::models::{Probe, L4};
+use saimiris::client::{read_sourced_probes, submit, ProbeWithSource};
+use saimiris::config::{app_config, parse_and_validate_client_args, ProbesFormat};
+use std::fs::File;
+use std::io::{Cursor, Write};
+use tempfile::tempdir;
+
+#[test]
+fn test_read_sourced_probes() {
+    let input = "192.0.2.1,24000,33434,32,UDP\n192.0.2.2,24000,33434,32,UDP,198.51.100.1\n";
+    let probes = read_sourced_probes(Cursor::new(input), ProbesFormat::default()).unwrap();
+    assert_eq!(probes.len(), 2);
+    assert_eq!(probes[0].1, None);
+    assert_eq!(probes[1].1, Some("198.51.100.1".parse().unwrap()));
+}
+
+#[tokio::test]
+async fn test_submit_returns_summary() {
+    let dir = tempdir().unwrap();
+    let config_path = dir.path().join("test_config.yml");
+    let mut file = File::create(&config_path).unwrap();
+    writeln!(file, "agent:").unwrap();
+    writeln!(file, "  metrics_address: '0.0.0.0:8080'").unwrap();
+    writeln!(file, "kafka:").unwrap();
+    // No broker listens there, nothing is produced
+    writeln!(file, "  brokers: '127.0.0.1:1'").unwrap();
+    drop(file);
+    let config = app_config(config_path.to_str().unwrap()).await.unwrap();
+
+    let probes: Vec<ProbeWithSource> = (1..=10)
+        .map(|ttl| {
+            let probe = Probe {
+                dst_addr: "192.0.2.1".parse().unwrap(),
+                src_port: 24000,
+                dst_port: 33434,
+                ttl,
+                protocol: L4::UDP,
+            };
+            (probe, None)
+        })
+        .collect();
+    let client_config = parse_and_validate_client_args("agent1:192.0.2.1,agent2:192.0.2.2", None)
+        .unwrap()
+        .with_dry_run(true);
+
+    let summary = submit(&config, client_config, probes).await.unwrap();
+    assert_eq!(summary.probes_read, 10);
+    assert!(summary.is_success());
+    assert_eq!(
+        summary.agents.keys().collect::<Vec<_>>(),
+        vec!["agent1", "agent2"]
+    );
+}
@@ -0,0 +1,100 @@
+//! Tests for the stopping of the SendLoops through their cancellation token
+use saimiris::agent::control::AgentMode;
+use saimiris::agent::correlation::CorrelationTable;
+use saimiris::agent::prefix_set::PrefixSet;
+use saimiris::agent::sender::{MeasurementProgress, ProbesWithSource, SendLoop};
+use saimiris::agent::server::{AgentState, LoopKind};
+use saimiris::config::{app_config, AppConfig, CaracatConfig};
+use std::fs::File;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::tempdir;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+async fn config() -> AppConfig {
+    let dir = tempdir().unwrap();
+    let config_path = dir.path().join("test_config.yml");
+    let mut file = File::create(&config_path).unwrap();
+    writeln!(file, "agent:").unwrap();
+    writeln!(file, "  id: agent1").unwrap();
+    writeln!(file, "  metrics_address: '0.0.0.0:8080'").unwrap();
+    drop(file);
+    app_config(config_path.to_str().unwrap()).await.unwrap()
+}
+
+fn send_loop(
+    rx: Receiver<ProbesWithSource>,
+    config: &AppConfig,
+    state: &Arc<AgentState>,
+    cancel: CancellationToken,
+) -> SendLoop {
+    let (_mode_tx, mode_rx) = watch::channel(AgentMode::Running);
+    SendLoop::new(
+        rx,
+        CaracatConfig::default(),
+        config,
+        Arc::new(Mutex::new(CorrelationTable::new(1_000))),
+        Arc::new(Mutex::new(MeasurementProgress::default())),
+        mode_rx,
+        0,
+        None,
+        None,
+        None,
+        None,
+        Arc::new(PrefixSet::default()),
+        None,
+        None,
+        state.register_loop(LoopKind::Send),
+        cancel,
+    )
+    .unwrap()
+}
+
+async fn wait_for_loops(state: &AgentState, running: usize) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while state.running_loops(LoopKind::Send) != running {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_send_loop_stop() {
+    let config = config().await;
+    let state = AgentState::new("agent1".to_string(), 1);
+    let (_tx, rx) = tokio::sync::mpsc::channel(1);
+    let send_loop = send_loop(rx, &config, &state, CancellationToken::new());
+    assert_eq!(state.running_loops(LoopKind::Send), 1);
+
+    // Stopped while waiting for probes, the channel still open
+    tokio::time::timeout(Duration::from_secs(5), send_loop.stop())
+        .await
+        .unwrap();
+    assert_eq!(state.running_loops(LoopKind::Send), 0);
+}
+
+#[tokio::test]
+async fn test_send_loops_cancelled_with_agent() {
+    let config = config().await;
+    let state = AgentState::new("agent1".to_string(), 1);
+    let shutdown = CancellationToken::new();
+    let mut senders = Vec::new();
+    for _ in 0..2 {
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        // Dropped without being stopped, the SendLoops keep running
+        let _send_loop = send_loop(rx, &config, &state, shutdown.child_token());
+        senders.push(tx);
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(state.running_loops(LoopKind::Send), 2);
+
+    // Until the agent stops
+    drop(shutdown.drop_guard());
+    wait_for_loops(&state, 0).await;
+    assert!(senders.iter().all(|tx| tx.is_closed()));
+}
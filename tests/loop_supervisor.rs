@@ -0,0 +1,192 @@
+//! Tests for the supervisor restarting the failed send and receive loops
+use hyper::{Method, StatusCode};
+use metrics_exporter_prometheus::PrometheusBuilder;
+use saimiris::agent::events::{EventKind, EventLog};
+use saimiris::agent::server::{AgentState, LoopKind, Server};
+use saimiris::agent::supervisor::{supervise, Backoff, LoopExit, LoopHealth};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+#[test]
+fn test_backoff() {
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+    let delays: Vec<u64> = (0..5).map(|_| backoff.next_delay().as_secs()).collect();
+    assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+    backoff.reset();
+    assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn test_supervise_restarts_failed_loop() {
+    let state = AgentState::new("agent1".to_string(), 1);
+    let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+    let health = LoopHealth::new(state.clone(), "receive:eth0").with_events(EventLog::new(
+        "agent1".to_string(),
+        Some(tx),
+        10,
+    ));
+    let starts = Arc::new(AtomicUsize::new(0));
+
+    // Fails twice, then runs until it is healthy again and stops
+    let loop_starts = starts.clone();
+    let start = move || {
+        let start = loop_starts.fetch_add(1, Ordering::Relaxed);
+        async move {
+            if start < 2 {
+                return LoopExit::Failed("interface eth0 is down".to_string());
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            LoopExit::Stopped
+        }
+    };
+    let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(50));
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        supervise(health, CancellationToken::new(), backoff, start),
+    )
+    .await
+    .unwrap();
+    assert_eq!(starts.load(Ordering::Relaxed), 3);
+    assert!(state.failed_loops().is_empty());
+
+    // One event per restart
+    for _ in 0..2 {
+        let event = rx.try_recv().unwrap();
+        assert_eq!(event.kind, EventKind::LoopRestart);
+        assert_eq!(event.details["loop"], "receive:eth0");
+        assert_eq!(event.details["reason"], "interface eth0 is down");
+    }
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_supervise_failed_loop_not_ready() {
+    let state = AgentState::new("agent1".to_string(), 1);
+    state.set_ready(true);
+    let _loops = [
+        state.register_loop(LoopKind::Send),
+        state.register_loop(LoopKind::Receive),
+    ];
+    let metrics = PrometheusBuilder::new().build_recorder().handle();
+    let server = Server::new(state.clone(), metrics, HashMap::new());
+    assert_eq!(
+        server.route(&Method::GET, "/readyz", None, None).status(),
+        StatusCode::OK
+    );
+
+    let cancel = CancellationToken::new();
+    let health = LoopHealth::new(state.clone(), "receive:eth0");
+    let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(50));
+    let supervisor = tokio::spawn(supervise(health, cancel.clone(), backoff, || async {
+        LoopExit::Failed("receiver creation failed".to_string())
+    }));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Not ready while the loop keeps failing
+    assert_eq!(state.failed_loops(), vec!["receive:eth0"]);
+    assert_eq!(
+        server.route(&Method::GET, "/readyz", None, None).status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    // No longer restarted once the agent stops
+    cancel.cancel();
+    tokio::time::timeout(Duration::from_secs(5), supervisor)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[cfg(feature = "testing")]
+#[tokio::test]
+async fn test_panicking_send_loop_restarted() {
+    use caracat::models::{Probe, L4};
+    use saimiris::agent::chaos::PANIC_SENDER_ENV;
+    use saimiris::agent::control::AgentMode;
+    use saimiris::agent::correlation::CorrelationTable;
+    use saimiris::agent::prefix_set::PrefixSet;
+    use saimiris::agent::sender::{MeasurementProgress, ProbesWithSource, SendLoop};
+    use saimiris::agent::statistics;
+    use saimiris::config::{app_config, CaracatConfig};
+    use std::io::Write;
+    use std::sync::Mutex;
+    use tokio::sync::watch;
+
+    // Every message panics the SendLoop, after being read
+    std::env::set_var(PANIC_SENDER_ENV, "100");
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("test_config.yml");
+    let mut file = std::fs::File::create(&config_path).unwrap();
+    writeln!(file, "agent:").unwrap();
+    writeln!(file, "  id: agent1").unwrap();
+    writeln!(file, "  metrics_address: '0.0.0.0:8080'").unwrap();
+    drop(file);
+    let app_config = app_config(config_path.to_str().unwrap()).await.unwrap();
+    let state = AgentState::new("agent1".to_string(), 1);
+    let config = CaracatConfig {
+        name: Some("panicking".to_string()),
+        ..Default::default()
+    };
+    let statistics = statistics::send_statistics("panicking");
+    let (tx, rx) = tokio::sync::mpsc::channel(1);
+    let (_mode_tx, mode_rx) = watch::channel(AgentMode::Running);
+    let send_loop = SendLoop::new(
+        rx,
+        config,
+        &app_config,
+        Arc::new(Mutex::new(CorrelationTable::new(1_000))),
+        Arc::new(Mutex::new(MeasurementProgress::default())),
+        mode_rx,
+        0,
+        None,
+        None,
+        None,
+        None,
+        Arc::new(PrefixSet::default()),
+        None,
+        None,
+        state.register_loop(LoopKind::Send),
+        CancellationToken::new(),
+    )
+    .unwrap();
+
+    let message = || ProbesWithSource {
+        probes: vec![Probe {
+            dst_addr: "192.0.2.1".parse().unwrap(),
+            src_port: 24000,
+            dst_port: 33434,
+            ttl: 32,
+            protocol: L4::ICMP,
+        }],
+        tags: Vec::new(),
+        source_ip: String::new(),
+        measurement_info: None,
+        priority: 0,
+        ack: None,
+    };
+    let read = |probes: u64| {
+        let statistics = statistics.clone();
+        tokio::time::timeout(Duration::from_secs(5), async move {
+            while statistics.probes_read.load(Ordering::Relaxed) < probes {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+    };
+
+    tx.send(message()).await.unwrap();
+    read(1).await.unwrap();
+    // Restarted after the backoff, still counted as running and reading its channel
+    assert_eq!(state.running_loops(LoopKind::Send), 1);
+    tx.send(message()).await.unwrap();
+    read(2).await.unwrap();
+    assert_eq!(state.failed_loops(), vec!["send:panicking:0"]);
+    assert!(!tx.is_closed());
+
+    tokio::time::timeout(Duration::from_secs(5), send_loop.stop())
+        .await
+        .unwrap();
+    assert_eq!(state.running_loops(LoopKind::Send), 0);
+}
@@ -0,0 +1,68 @@
+//! Unit tests for the bounded-cardinality measurement labels of the counters
+use metrics::Label;
+use saimiris::agent::measurement_labels::{MeasurementLabels, NONE_LABEL, OTHER_LABEL};
+
+fn values(labels: Vec<Label>) -> Vec<(String, String)> {
+    labels
+        .iter()
+        .map(|label| (label.key().to_string(), label.value().to_string()))
+        .collect()
+}
+
+fn expected(measurement_id: &str, client_id: &str) -> Vec<(String, String)> {
+    vec![
+        ("measurement_id".to_string(), measurement_id.to_string()),
+        ("client_id".to_string(), client_id.to_string()),
+    ]
+}
+
+#[test]
+fn test_measurement_labels() {
+    let labels = MeasurementLabels::new(10);
+    assert_eq!(
+        values(labels.labels(Some("m1"), Some("client-a"))),
+        expected("m1", "client-a")
+    );
+    assert_eq!(
+        values(labels.labels(Some("m2"), None)),
+        expected("m2", NONE_LABEL)
+    );
+    assert_eq!(
+        values(labels.labels(None, None)),
+        expected(NONE_LABEL, NONE_LABEL)
+    );
+}
+
+#[test]
+fn test_measurement_labels_remember_client() {
+    let labels = MeasurementLabels::new(10);
+    labels.labels(Some("m1"), Some("client-a"));
+    // The replies only know the measurement
+    assert_eq!(
+        values(labels.labels(Some("m1"), None)),
+        expected("m1", "client-a")
+    );
+}
+
+#[test]
+fn test_measurement_labels_limit() {
+    let labels = MeasurementLabels::new(2);
+    labels.labels(Some("m1"), Some("client-a"));
+    labels.labels(Some("m2"), Some("client-a"));
+    assert_eq!(
+        values(labels.labels(Some("m3"), Some("client-b"))),
+        expected(OTHER_LABEL, OTHER_LABEL)
+    );
+    // The measurements already labelled keep their labels
+    assert_eq!(
+        values(labels.labels(Some("m2"), None)),
+        expected("m2", "client-a")
+    );
+}
+
+#[test]
+fn test_measurement_labels_disabled() {
+    let labels = MeasurementLabels::new(0);
+    assert!(labels.labels(Some("m1"), Some("client-a")).is_empty());
+    assert!(labels.labels(None, None).is_empty());
+}
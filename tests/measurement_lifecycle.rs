@@ -0,0 +1,89 @@
+//! Tests for the lifecycle of the measurements
+use saimiris::agent::gateway::MeasurementStatusUpdate;
+use saimiris::measurement::{Measurement, MeasurementSpec, MeasurementStatus};
+
+#[test]
+fn test_measurement_status_transitions() {
+    use MeasurementStatus::*;
+    assert!(Pending.can_transition_to(Running));
+    assert!(Running.can_transition_to(Completed));
+    assert!(Running.can_transition_to(Cancelled));
+    // A reused measurement ID starts over, unless cancelled
+    assert!(Completed.can_transition_to(Running));
+    assert!(!Cancelled.can_transition_to(Running));
+    assert!(!Cancelled.can_transition_to(Pending));
+    assert!(!Pending.can_transition_to(Completed));
+
+    assert!(!Running.is_final());
+    assert!(Completed.is_final());
+    assert!(Cancelled.is_final());
+}
+
+#[test]
+fn test_measurement_lifecycle() {
+    let mut measurement = Measurement::new("m1");
+    assert_eq!(measurement.status(), MeasurementStatus::Pending);
+    measurement.start(Some(1_000));
+    assert_eq!(measurement.status(), MeasurementStatus::Pending);
+    // Timed once complete only
+    assert!(measurement.take_timing().is_none());
+
+    measurement.record(10, false, 2);
+    assert_eq!(measurement.status(), MeasurementStatus::Running);
+    measurement.record(3, true, 2);
+    assert_eq!(measurement.status(), MeasurementStatus::Running);
+    measurement.record(2, true, 2);
+    assert_eq!(measurement.status(), MeasurementStatus::Completed);
+    assert_eq!(measurement.sent_probes(), 15);
+    assert!(!measurement.is_done());
+    assert_eq!(
+        measurement.take_timing().unwrap().submitted_at_ms,
+        Some(1_000)
+    );
+    assert!(measurement.is_done());
+
+    // Reused measurement ID
+    measurement.record(1, false, 2);
+    assert_eq!(measurement.status(), MeasurementStatus::Running);
+    assert_eq!(measurement.sent_probes(), 1);
+}
+
+#[test]
+fn test_measurement_cancel() {
+    let mut measurement = Measurement::new("m1");
+    measurement.start(None);
+    measurement.record(10, false, 1);
+    assert_eq!(measurement.cancel(), 10);
+    assert_eq!(measurement.status(), MeasurementStatus::Cancelled);
+    assert_eq!(measurement.cancel(), 0);
+
+    // The remaining probes are not tracked
+    measurement.start(None);
+    measurement.record(5, true, 1);
+    assert_eq!(measurement.status(), MeasurementStatus::Cancelled);
+    assert_eq!(measurement.sent_probes(), 0);
+    assert!(measurement.take_timing().is_none());
+    assert!(!measurement.is_done());
+}
+
+#[test]
+fn test_measurement_status_update() {
+    for status in [
+        MeasurementStatus::Running,
+        MeasurementStatus::Completed,
+        MeasurementStatus::Cancelled,
+    ] {
+        let update = MeasurementStatusUpdate::new(status, 42);
+        assert_eq!(update.status(), status);
+    }
+    let update = MeasurementStatusUpdate::new(MeasurementStatus::Cancelled, 42);
+    assert!(update.is_complete && update.is_cancelled);
+    assert_eq!(
+        MeasurementStatusUpdate::new(MeasurementStatus::Running, 0).status(),
+        MeasurementStatus::Pending
+    );
+
+    let spec = MeasurementSpec::new("m1".to_string(), vec!["agent1".to_string()]);
+    assert_eq!(spec.id, "m1");
+    assert_eq!(spec.agents, vec!["agent1"]);
+}
@@ -0,0 +1,52 @@
+//! Tests for the maximum number of probes per measurement of the agent
+use saimiris::agent::gateway::MeasurementStatusUpdate;
+use saimiris::agent::quota::MeasurementQuota;
+use saimiris::measurement::MeasurementStatus;
+
+#[test]
+fn test_measurement_quota() {
+    let quota = MeasurementQuota::new(10);
+    assert_eq!(quota.take("m1", 6), (6, false));
+    // The probes beyond the maximum are dropped, the violation reported once
+    assert_eq!(quota.take("m1", 6), (4, true));
+    assert_eq!(quota.take("m1", 6), (0, false));
+    assert_eq!(quota.used("m1"), 10);
+
+    // Measurements have their own quota
+    assert_eq!(quota.take("m2", 10), (10, false));
+    assert_eq!(quota.take("m2", 0), (0, false));
+}
+
+#[test]
+fn test_measurement_quota_shared() {
+    // SendLoops of different instances share the quota
+    let quota = MeasurementQuota::new(10);
+    let other = quota.clone();
+    assert_eq!(quota.take("m1", 8), (8, false));
+    assert_eq!(other.take("m1", 8), (2, true));
+}
+
+#[test]
+fn test_measurement_quota_forget() {
+    let quota = MeasurementQuota::new(10);
+    assert_eq!(quota.take("m1", 12), (10, true));
+    // A measurement reusing the ID starts over
+    quota.forget("m1");
+    assert_eq!(quota.used("m1"), 0);
+    assert_eq!(quota.take("m1", 5), (5, false));
+}
+
+#[test]
+fn test_measurement_limit_exceeded_status() {
+    let update = MeasurementStatusUpdate::new(MeasurementStatus::Running, 42);
+    let json = serde_json::to_value(&update).unwrap();
+    assert!(json.get("max_probes_exceeded").is_none());
+
+    let update = MeasurementStatusUpdate {
+        max_probes_exceeded: Some(100),
+        ..MeasurementStatusUpdate::new(MeasurementStatus::Running, 100)
+    };
+    let json = serde_json::to_value(&update).unwrap();
+    assert_eq!(json["max_probes_exceeded"], 100);
+    assert_eq!(update.status(), MeasurementStatus::Running);
+}
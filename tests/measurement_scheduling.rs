@@ -0,0 +1,203 @@
+//! Tests for the priority and weighted round-robin scheduling of concurrent measurements
+use caracat::models::{Probe, L4};
+use saimiris::agent::gateway::MeasurementInfo;
+use saimiris::agent::scheduling::{schedule_loop, MeasurementQueues};
+use saimiris::agent::sender::ProbesWithSource;
+use saimiris::config::CaracatConfig;
+use tokio::sync::mpsc::channel;
+
+fn message(measurement_id: Option<&str>, probes: usize, weight: Option<u32>) -> ProbesWithSource {
+    let probe = Probe {
+        dst_addr: "192.0.2.1".parse().unwrap(),
+        src_port: 24000,
+        dst_port: 33434,
+        ttl: 32,
+        protocol: L4::ICMP,
+    };
+    ProbesWithSource {
+        probes: vec![probe; probes],
+        tags: Vec::new(),
+        source_ip: String::new(),
+        measurement_info: measurement_id.map(|measurement_id| MeasurementInfo {
+            measurement_id: measurement_id.to_string(),
+            end_of_measurement: false,
+            submitted_at_ms: None,
+            trace_id: None,
+            client_id: None,
+            weight,
+        }),
+        priority: 0,
+        ack: None,
+    }
+}
+
+fn prioritized(message: ProbesWithSource, priority: u32) -> ProbesWithSource {
+    ProbesWithSource {
+        priority,
+        ..message
+    }
+}
+
+fn measurement_id(message: &ProbesWithSource) -> &str {
+    message
+        .measurement_info
+        .as_ref()
+        .map(|info| info.measurement_id.as_str())
+        .unwrap_or_default()
+}
+
+fn drain(queues: &mut MeasurementQueues) -> Vec<String> {
+    std::iter::from_fn(|| queues.pop())
+        .map(|message| measurement_id(&message).to_string())
+        .collect()
+}
+
+#[test]
+fn test_small_measurement_not_starved() {
+    let mut queues = MeasurementQueues::new(100);
+    for _ in 0..5 {
+        queues.push(message(Some("bulk"), 100, None));
+    }
+    queues.push(message(Some("small"), 10, None));
+    queues.push(message(Some("small"), 10, None));
+    assert_eq!(queues.len(), 7);
+
+    assert_eq!(
+        drain(&mut queues),
+        vec!["bulk", "small", "small", "bulk", "bulk", "bulk", "bulk"]
+    );
+    assert!(queues.is_empty());
+}
+
+#[test]
+fn test_weighted_round_robin() {
+    let mut queues = MeasurementQueues::new(100);
+    for _ in 0..4 {
+        queues.push(message(Some("bulk"), 100, None));
+        queues.push(message(Some("interactive"), 100, Some(2)));
+    }
+    // Twice the probes per round for the measurement of weight 2
+    assert_eq!(
+        drain(&mut queues),
+        vec![
+            "bulk",
+            "interactive",
+            "interactive",
+            "bulk",
+            "interactive",
+            "interactive",
+            "bulk",
+            "bulk"
+        ]
+    );
+}
+
+#[test]
+fn test_large_messages_accumulate_deficit() {
+    let mut queues = MeasurementQueues::new(100);
+    queues.push(message(Some("large"), 250, None));
+    queues.push(message(None, 50, None));
+    queues.push(message(None, 50, None));
+    queues.push(message(None, 50, None));
+    queues.push(message(None, 50, None));
+    queues.push(message(None, 50, None));
+    queues.push(message(None, 50, None));
+    // The large message is sent once its deficit covers its probes, after three rounds
+    assert_eq!(drain(&mut queues), vec!["", "", "", "", "large", "", ""]);
+}
+
+#[test]
+fn test_measurement_order_kept() {
+    let mut queues = MeasurementQueues::new(10);
+    for probes in [1, 2, 3] {
+        queues.push(message(Some("m1"), probes, None));
+    }
+    let sizes: Vec<usize> = std::iter::from_fn(|| queues.pop())
+        .map(|message| message.probes.len())
+        .collect();
+    assert_eq!(sizes, vec![1, 2, 3]);
+}
+
+#[test]
+fn test_measurement_scheduling_config() {
+    let config = |scheduling: Option<&str>| CaracatConfig {
+        measurement_scheduling: scheduling.map(str::to_string),
+        ..Default::default()
+    };
+    // Messages sent in order by default
+    for scheduling in [None, Some("fifo"), Some("FIFO")] {
+        let mut queues = MeasurementQueues::for_instance(&config(scheduling)).unwrap();
+        queues.push(message(Some("bulk"), 5_000, None));
+        queues.push(message(Some("bulk"), 5_000, None));
+        queues.push(message(Some("small"), 10, None));
+        assert_eq!(drain(&mut queues), vec!["bulk", "bulk", "small"]);
+    }
+    let mut queues = MeasurementQueues::for_instance(&config(Some("fair"))).unwrap();
+    queues.push(message(Some("bulk"), 5_000, None));
+    queues.push(message(Some("bulk"), 5_000, None));
+    queues.push(message(Some("small"), 10, None));
+    assert_eq!(drain(&mut queues), vec!["small", "bulk", "bulk"]);
+
+    assert!(MeasurementQueues::for_instance(&config(Some("lottery"))).is_err());
+}
+
+#[test]
+fn test_priority_preempts_between_messages() {
+    let mut queues = MeasurementQueues::fifo();
+    queues.push(message(Some("campaign"), 100, None));
+    queues.push(message(Some("campaign"), 100, None));
+    assert_eq!(
+        queues
+            .pop()
+            .map(|m| measurement_id(&m).to_string())
+            .unwrap(),
+        "campaign"
+    );
+
+    // The diagnostic probes are sent before the remaining messages of the campaign
+    queues.push(prioritized(message(Some("diagnostic"), 10, None), 10));
+    queues.push(prioritized(message(None, 10, None), 5));
+    queues.push(message(Some("campaign"), 100, None));
+    assert_eq!(
+        drain(&mut queues),
+        vec!["diagnostic", "", "campaign", "campaign"]
+    );
+    assert!(queues.is_empty());
+}
+
+#[test]
+fn test_priority_with_fair_scheduling() {
+    let mut queues = MeasurementQueues::new(100);
+    for _ in 0..2 {
+        queues.push(message(Some("bulk"), 100, None));
+        queues.push(prioritized(message(Some("urgent1"), 100, None), 1));
+        queues.push(prioritized(message(Some("urgent2"), 100, None), 1));
+    }
+    // Round-robin among the measurements of the highest priority first
+    assert_eq!(
+        drain(&mut queues),
+        vec!["urgent1", "urgent2", "urgent1", "urgent2", "bulk", "bulk"]
+    );
+}
+
+#[tokio::test]
+async fn test_schedule_loop() {
+    let (tx, rx) = channel(100);
+    let (tx_scheduled, mut rx_scheduled) = channel(1);
+    for _ in 0..3 {
+        tx.send(message(Some("bulk"), 1_000, None)).await.unwrap();
+    }
+    tx.send(message(Some("small"), 10, None)).await.unwrap();
+    drop(tx);
+    tokio::spawn(schedule_loop(
+        rx,
+        tx_scheduled,
+        MeasurementQueues::new(1_000),
+    ));
+
+    let mut scheduled = Vec::new();
+    while let Some(message) = rx_scheduled.recv().await {
+        scheduled.push(measurement_id(&message).to_string());
+    }
+    assert_eq!(scheduled, vec!["bulk", "small", "bulk", "bulk"]);
+}
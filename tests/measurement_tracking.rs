@@ -1,8 +1,14 @@
 use std::collections::HashMap;
 
 use caracat::models::Probe;
+use rdkafka::message::Headers;
+use saimiris::agent::events::{EventKind, EventLog};
 use saimiris::agent::gateway::MeasurementInfo;
-use saimiris::agent::sender::ProbesWithSource;
+use saimiris::agent::handler::{parse_cancellation, parse_measurement_info};
+use saimiris::agent::sender::{shard_probes, MeasurementProgress, ProbesWithSource};
+use saimiris::client::producer::{end_of_measurement_headers, probes_headers, ProduceOptions};
+use saimiris::probe::ProbeTags;
+use saimiris::protocol::CANCEL_MEASUREMENT_HEADER;
 
 #[tokio::test]
 async fn test_measurement_info_parsing() {
@@ -10,6 +16,10 @@ async fn test_measurement_info_parsing() {
     let measurement_info = MeasurementInfo {
         measurement_id: "test-measurement-123".to_string(),
         end_of_measurement: false,
+        submitted_at_ms: None,
+        trace_id: None,
+        client_id: None,
+        weight: None,
     };
 
     assert_eq!(measurement_info.measurement_id, "test-measurement-123");
@@ -30,12 +40,19 @@ async fn test_probes_with_source_measurement_info() {
     let measurement_info = Some(MeasurementInfo {
         measurement_id: "test-measurement-456".to_string(),
         end_of_measurement: true,
+        submitted_at_ms: None,
+        trace_id: None,
+        client_id: None,
+        weight: None,
     });
 
     let probes_with_source = ProbesWithSource {
         probes,
+        tags: Vec::new(),
         source_ip: "192.168.1.1".to_string(),
         measurement_info: measurement_info.clone(),
+        priority: 0,
+        ack: None,
     };
 
     assert_eq!(probes_with_source.probes.len(), 1);
@@ -67,6 +84,10 @@ async fn test_kafka_header_parsing() {
         Some(MeasurementInfo {
             measurement_id: measurement_id.clone(),
             end_of_measurement,
+            submitted_at_ms: None,
+            trace_id: None,
+            client_id: None,
+            weight: None,
         })
     } else {
         None
@@ -100,6 +121,10 @@ async fn test_end_to_end_measurement_tracking() {
         Some(MeasurementInfo {
             measurement_id: measurement_id.clone(),
             end_of_measurement,
+            submitted_at_ms: None,
+            trace_id: None,
+            client_id: None,
+            weight: None,
         })
     } else {
         None
@@ -137,8 +162,11 @@ async fn test_end_to_end_measurement_tracking() {
 
     let probes_with_source = ProbesWithSource {
         probes,
+        tags: Vec::new(),
         source_ip: "192.168.1.100".to_string(),
         measurement_info: Some(info.clone()),
+        priority: 0,
+        ack: None,
     };
 
     // 4. Verify that probes and measurement info are correctly packaged
@@ -246,3 +274,218 @@ async fn test_client_without_measurement_tracking() {
     assert_eq!(agent.src_ip, Some("10.0.0.1".to_string()));
     assert_eq!(agent.measurement_id, None);
 }
+
+#[tokio::test]
+async fn test_sharded_probes_keep_destinations_together() {
+    let probes: Vec<Probe> = (0..32)
+        .flat_map(|i| {
+            (1..=4).map(move |ttl| Probe {
+                dst_addr: format!("10.0.0.{}", i).parse().unwrap(),
+                src_port: 24000,
+                dst_port: 33434,
+                ttl,
+                protocol: caracat::models::L4::UDP,
+            })
+        })
+        .collect();
+    let tags: Vec<ProbeTags> = probes
+        .iter()
+        .map(|p| ProbeTags {
+            round: p.ttl as u32,
+            ..Default::default()
+        })
+        .collect();
+
+    let shards = shard_probes(
+        ProbesWithSource {
+            probes: probes.clone(),
+            tags,
+            source_ip: String::new(),
+            measurement_info: Some(MeasurementInfo {
+                measurement_id: "test-measurement-shard".to_string(),
+                end_of_measurement: true,
+                submitted_at_ms: None,
+                trace_id: None,
+                client_id: None,
+                weight: None,
+            }),
+            priority: 0,
+            ack: None,
+        },
+        4,
+    );
+
+    assert_eq!(shards.len(), 4);
+    assert_eq!(
+        shards.iter().map(|s| s.probes.len()).sum::<usize>(),
+        probes.len()
+    );
+    for (i, shard) in shards.iter().enumerate() {
+        assert!(shard.measurement_info.is_some());
+        assert_eq!(shard.tags.len(), shard.probes.len());
+        for (probe, probe_tags) in shard.probes.iter().zip(shard.tags.iter()) {
+            assert_eq!(probe_tags.round, probe.ttl as u32);
+            // All the probes towards a destination are in the same shard
+            for other in shards.iter().skip(i + 1) {
+                assert!(other.probes.iter().all(|p| p.dst_addr != probe.dst_addr));
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_measurement_progress_across_workers() {
+    let mut progress = MeasurementProgress::default();
+    assert_eq!(progress.record("m1", 10, false, 2), (10, false));
+    assert_eq!(progress.record("m1", 5, false, 2), (15, false));
+    // The measurement is complete once every worker sent its share of the last message
+    assert_eq!(progress.record("m1", 3, true, 2), (18, false));
+    assert_eq!(progress.record("m1", 2, true, 2), (20, true));
+    // State is cleaned up after completion
+    assert_eq!(progress.record("m1", 1, false, 2), (1, false));
+}
+
+#[tokio::test]
+async fn test_measurement_progress_events() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+    let events = EventLog::new("agent1".to_string(), Some(tx), 10);
+    let mut progress = MeasurementProgress::with_events(events);
+
+    progress.record("m1", 10, false, 1);
+    progress.record("m1", 5, true, 1);
+
+    let accepted = rx.try_recv().unwrap();
+    assert_eq!(accepted.kind, EventKind::MeasurementAccepted);
+    assert_eq!(accepted.measurement_id.as_deref(), Some("m1"));
+    let completed = rx.try_recv().unwrap();
+    assert_eq!(completed.kind, EventKind::MeasurementCompleted);
+    assert_eq!(completed.details.get("sent_probes").unwrap(), "15");
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_event_log_rate_limit() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+    let events = EventLog::new("agent1".to_string(), Some(tx), 2);
+    for _ in 0..5 {
+        events.emit(EventKind::LoopRestart, None, Default::default());
+    }
+    assert!(rx.try_recv().is_ok());
+    assert!(rx.try_recv().is_ok());
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_measurement_progress_cancel() {
+    let mut progress = MeasurementProgress::default();
+    progress.record("m1", 10, false, 1);
+    progress.record("m2", 4, false, 1);
+
+    assert!(!progress.is_cancelled("m1"));
+    assert_eq!(progress.cancel("m1"), 10);
+    assert!(progress.is_cancelled("m1"));
+    assert_eq!(progress.record("m1", 5, true, 1), (0, false));
+    // Other measurements are not affected
+    assert!(!progress.is_cancelled("m2"));
+    assert_eq!(progress.record("m2", 1, true, 1), (5, true));
+    // Cancelling an unknown measurement still drops its future probes
+    assert_eq!(progress.cancel("m3"), 0);
+    assert!(progress.is_cancelled("m3"));
+}
+
+#[tokio::test]
+async fn test_measurement_progress_timing() {
+    let mut progress = MeasurementProgress::default();
+    // The earliest submission of the measurement messages is kept
+    progress.start("m1", Some(2_000));
+    progress.start("m1", Some(1_000));
+    progress.start("m1", None);
+    assert_eq!(progress.record("m1", 10, true, 1), (10, true));
+
+    let timing = progress.take_timing("m1").unwrap();
+    assert_eq!(timing.submitted_at_ms, Some(1_000));
+    assert_eq!(
+        timing.completion_latency(3_500),
+        Some(std::time::Duration::from_millis(2_500))
+    );
+    // Clock skew between the client and the agent
+    assert_eq!(timing.completion_latency(500), None);
+    assert!(progress.take_timing("m1").is_none());
+
+    // Cancelled measurements are not timed
+    progress.start("m2", None);
+    progress.cancel("m2");
+    assert!(progress.take_timing("m2").is_none());
+    progress.start("m2", None);
+    assert!(progress.take_timing("m2").is_none());
+}
+
+#[tokio::test]
+async fn test_cancellation_header_parsing() {
+    let headers: Vec<(&str, Option<&[u8]>)> = vec![
+        ("agent1", Some(b"{}".as_slice())),
+        (CANCEL_MEASUREMENT_HEADER, Some(b"m1".as_slice())),
+    ];
+    assert_eq!(
+        parse_cancellation(headers.clone(), "agent1"),
+        Some("m1".to_string())
+    );
+    // Not intended for this agent
+    assert_eq!(parse_cancellation(headers, "agent2"), None);
+
+    // Regular probes messages are not cancellations
+    let headers: Vec<(&str, Option<&[u8]>)> = vec![
+        ("agent1", Some(b"{}".as_slice())),
+        ("measurement_id", Some(b"m1".as_slice())),
+    ];
+    assert_eq!(parse_cancellation(headers, "agent1"), None);
+
+    // Empty measurement IDs are ignored
+    let headers: Vec<(&str, Option<&[u8]>)> = vec![
+        ("agent1", None),
+        (CANCEL_MEASUREMENT_HEADER, Some(b"".as_slice())),
+    ];
+    assert_eq!(parse_cancellation(headers, "agent1"), None);
+}
+
+#[tokio::test]
+async fn test_client_headers_read_by_agent() {
+    let agents = vec![saimiris::client::producer::MeasurementInfo {
+        name: "agent1".to_string(),
+        src_ip: Some("192.0.2.1".to_string()),
+        instance: None,
+        measurement_id: Some("m1".to_string()),
+    }];
+    let options = ProduceOptions {
+        weight: Some(3),
+        ..Default::default()
+    };
+    let headers = probes_headers(&agents, &options);
+
+    for is_last_message in [false, true] {
+        let message_headers = end_of_measurement_headers(&headers, is_last_message);
+        let headers: Vec<(&str, Option<&[u8]>)> = message_headers
+            .iter()
+            .map(|header| (header.key, header.value))
+            .collect();
+        let info = parse_measurement_info(headers.clone(), Some(1000)).unwrap();
+        assert_eq!(info.measurement_id, "m1");
+        assert_eq!(info.end_of_measurement, is_last_message);
+        assert_eq!(info.weight, Some(3));
+        assert_eq!(info.submitted_at_ms, Some(1000));
+        // Probes messages are not cancellations
+        assert_eq!(parse_cancellation(headers, "agent1"), None);
+    }
+
+    // Without a measurement ID, the probes are not tracked
+    let agents = vec![saimiris::client::producer::MeasurementInfo {
+        measurement_id: None,
+        ..agents[0].clone()
+    }];
+    let headers = end_of_measurement_headers(&probes_headers(&agents, &options), true);
+    assert!(parse_measurement_info(
+        headers.iter().map(|header| (header.key, header.value)),
+        None
+    )
+    .is_none());
+}
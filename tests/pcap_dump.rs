@@ -0,0 +1,65 @@
+//! Tests for the rotating pcap dump of the captured replies
+use pcap::{Capture, Linktype, Packet, PacketHeader};
+use saimiris::agent::pcap_dump::PcapDump;
+use std::path::Path;
+use tempfile::tempdir;
+
+const PACKET_LEN: usize = 64;
+
+fn write_packet(dump: &mut PcapDump, id: u8) {
+    let header = PacketHeader {
+        ts: libc::timeval {
+            tv_sec: 1_700_000_000,
+            tv_usec: 0,
+        },
+        caplen: PACKET_LEN as u32,
+        len: PACKET_LEN as u32,
+    };
+    let data = [id; PACKET_LEN];
+    dump.write(&Packet::new(&header, &data));
+}
+
+fn read_packets(path: &Path) -> Vec<u8> {
+    let mut capture = Capture::from_file(path).unwrap();
+    let mut ids = Vec::new();
+    while let Ok(packet) = capture.next_packet() {
+        assert_eq!(packet.data.len(), PACKET_LEN);
+        ids.push(packet.data[0]);
+    }
+    ids
+}
+
+#[test]
+fn test_pcap_dump_rotation() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("replies.pcap");
+    // Header of the file, and two packets with their header per file
+    let max_bytes = 24 + 2 * (16 + PACKET_LEN as u64);
+    let mut dump = PcapDump::new(&path, Linktype::ETHERNET, max_bytes, 2).unwrap();
+    for id in 0..7 {
+        write_packet(&mut dump, id);
+    }
+    drop(dump);
+
+    assert_eq!(read_packets(&path), vec![6]);
+    assert_eq!(read_packets(&dir.path().join("replies.pcap.1")), vec![4, 5]);
+    assert_eq!(read_packets(&dir.path().join("replies.pcap.2")), vec![2, 3]);
+    assert!(!dir.path().join("replies.pcap.3").exists());
+}
+
+#[test]
+fn test_pcap_dump_previous_run_rotated() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("replies.pcap");
+    let mut dump = PcapDump::new(&path, Linktype::ETHERNET, 1024 * 1024, 5).unwrap();
+    write_packet(&mut dump, 1);
+    dump.flush();
+    assert_eq!(read_packets(dump.path()), vec![1]);
+    drop(dump);
+
+    let mut dump = PcapDump::new(&path, Linktype::ETHERNET, 1024 * 1024, 5).unwrap();
+    write_packet(&mut dump, 2);
+    drop(dump);
+    assert_eq!(read_packets(&path), vec![2]);
+    assert_eq!(read_packets(&dir.path().join("replies.pcap.1")), vec![1]);
+}
@@ -0,0 +1,62 @@
+//! Tests for the prefix sets of the blocklist and allowlists
+use saimiris::agent::prefix_set::PrefixSet;
+use std::io::Write;
+use std::net::IpAddr;
+use tempfile::NamedTempFile;
+
+fn addr(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+}
+
+#[test]
+fn test_prefix_set_parse() {
+    let prefixes = PrefixSet::parse(
+        "# Opt-out requests\n\
+         192.0.2.0/24\n\
+         198.51.100.7   # single address\n\
+         \n\
+         2001:db8::/32\n",
+    )
+    .unwrap();
+    assert_eq!(prefixes.len(), 3);
+
+    assert!(prefixes.contains(&addr("192.0.2.1")));
+    assert!(prefixes.contains(&addr("192.0.2.255")));
+    assert!(!prefixes.contains(&addr("192.0.3.0")));
+    assert!(prefixes.contains(&addr("198.51.100.7")));
+    assert!(!prefixes.contains(&addr("198.51.100.8")));
+    assert!(prefixes.contains(&addr("2001:db8::1")));
+    assert!(!prefixes.contains(&addr("2001:db9::1")));
+    assert_eq!(
+        prefixes.find(&addr("192.0.2.1")),
+        Some("192.0.2.0/24".parse().unwrap())
+    );
+}
+
+#[test]
+fn test_prefix_set_overlapping_prefixes() {
+    // Nested prefixes are aggregated
+    let prefixes = PrefixSet::parse("10.0.0.0/8\n10.1.0.0/16\n12.0.0.0/8\n").unwrap();
+    assert_eq!(prefixes.len(), 2);
+    assert!(prefixes.contains(&addr("10.1.2.3")));
+    assert!(prefixes.contains(&addr("10.200.0.1")));
+    assert!(prefixes.contains(&addr("12.0.0.1")));
+    assert!(!prefixes.contains(&addr("11.0.0.1")));
+    assert!(!prefixes.contains(&addr("9.255.255.255")));
+}
+
+#[test]
+fn test_prefix_set_invalid_prefix() {
+    let error = PrefixSet::parse("192.0.2.0/24\nnot-a-prefix\n").unwrap_err();
+    assert!(error.to_string().contains("line 2"));
+}
+
+#[test]
+fn test_prefix_set_load() {
+    let mut file = NamedTempFile::new().unwrap();
+    writeln!(file, "203.0.113.0/24").unwrap();
+    let prefixes = PrefixSet::load(file.path()).unwrap();
+    assert!(prefixes.contains(&addr("203.0.113.42")));
+
+    assert!(PrefixSet::load(std::path::Path::new("/nonexistent/blocklist")).is_err());
+}
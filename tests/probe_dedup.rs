@@ -0,0 +1,65 @@
+//! Tests for the suppression of the probes repeated inside a measurement
+use caracat::models::{Probe, L4};
+use saimiris::agent::dedup::ProbeDedup;
+use saimiris::probe::ProbeTags;
+
+fn probe(dst_addr: &str, ttl: u8) -> (Probe, ProbeTags) {
+    let probe = Probe {
+        dst_addr: dst_addr.parse().unwrap(),
+        src_port: 24000,
+        dst_port: 33434,
+        ttl,
+        protocol: L4::UDP,
+    };
+    (probe, ProbeTags::default())
+}
+
+#[test]
+fn test_dedup_repeated_probes() {
+    let dedup = ProbeDedup::new(100);
+    let mut probes = vec![probe("192.0.2.1", 1), probe("192.0.2.1", 2)];
+    assert_eq!(dedup.dedup("m1", &mut probes), 0);
+    assert_eq!(probes.len(), 2);
+
+    // Submitted again, and repeated within the message
+    let mut probes = vec![
+        probe("192.0.2.1", 1),
+        probe("192.0.2.2", 1),
+        probe("192.0.2.2", 1),
+    ];
+    assert_eq!(dedup.dedup("m1", &mut probes), 2);
+    assert_eq!(probes.len(), 1);
+    assert_eq!(probes[0].0.dst_addr.to_string(), "192.0.2.2");
+    assert_eq!(dedup.len(), 3);
+}
+
+#[test]
+fn test_dedup_per_measurement_and_round() {
+    let dedup = ProbeDedup::new(100);
+    assert_eq!(dedup.dedup("m1", &mut vec![probe("192.0.2.1", 1)]), 0);
+    // Other measurements may send the same probes
+    assert_eq!(dedup.dedup("m2", &mut vec![probe("192.0.2.1", 1)]), 0);
+    // And so may the next rounds of the measurement
+    let (next_round, _) = probe("192.0.2.1", 1);
+    let tags = ProbeTags {
+        round: 1,
+        ..Default::default()
+    };
+    assert_eq!(dedup.dedup("m1", &mut vec![(next_round, tags)]), 0);
+    assert_eq!(dedup.dedup("m1", &mut vec![probe("192.0.2.1", 1)]), 1);
+}
+
+#[test]
+fn test_dedup_window() {
+    let dedup = ProbeDedup::new(2);
+    let mut probes = vec![
+        probe("192.0.2.1", 1),
+        probe("192.0.2.2", 1),
+        probe("192.0.2.3", 1),
+    ];
+    assert_eq!(dedup.dedup("m1", &mut probes), 0);
+    assert_eq!(dedup.len(), 2);
+    // The oldest probe is forgotten
+    assert_eq!(dedup.dedup("m1", &mut vec![probe("192.0.2.1", 1)]), 0);
+    assert_eq!(dedup.dedup("m1", &mut vec![probe("192.0.2.3", 1)]), 1);
+}
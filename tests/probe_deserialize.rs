@@ -14,3 +14,35 @@ fn test_deserialize_probes_empty() {
     assert!(result.is_ok());
     assert!(result.unwrap().is_empty());
 }
+
+#[test]
+fn test_deserialize_tagged_probes_round_trip() {
+    use caracat::models::{Probe, L4};
+    use saimiris::probe::{deserialize_tagged_probes, serialize_tagged_probe, ProbeTags};
+
+    let probe = Probe {
+        dst_addr: "8.8.8.8".parse().unwrap(),
+        src_port: 24000,
+        dst_port: 33434,
+        ttl: 12,
+        protocol: L4::UDP,
+    };
+    let tags = ProbeTags {
+        round: 3,
+        dscp: 46,
+        src_addr: Some("192.0.2.1".parse().unwrap()),
+        instance: Some("slow".to_string()),
+    };
+
+    let mut bytes = serialize_tagged_probe(&probe, &tags);
+    bytes.extend(serialize_tagged_probe(&probe, &ProbeTags::default()));
+
+    let probes = deserialize_tagged_probes(bytes).unwrap();
+    assert_eq!(probes.len(), 2);
+    assert_eq!(probes[0].0.dst_addr, probe.dst_addr);
+    assert_eq!(probes[0].1.round, 3);
+    assert_eq!(probes[0].1.dscp, 46);
+    assert_eq!(probes[0].1.src_addr, tags.src_addr);
+    assert_eq!(probes[0].1.instance, tags.instance);
+    assert!(probes[1].1.is_empty());
+}
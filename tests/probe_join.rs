@@ -0,0 +1,105 @@
+//! Unit tests for joining replies with the client probe index
+use saimiris::join::{ProbeIndex, ProbeIndexRecord};
+use saimiris::reply::ReplyRecord;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+fn index_record(round: u32, submitted_at_ns: u64) -> ProbeIndexRecord {
+    ProbeIndexRecord {
+        dst_addr: "8.8.8.8".parse().unwrap(),
+        src_port: 24000,
+        dst_port: 33434,
+        ttl: 5,
+        protocol: "udp".to_string(),
+        round,
+        measurement_id: Some("measurement-1".to_string()),
+        tags: BTreeMap::from([("campaign".to_string(), "test".to_string())]),
+        submitted_at_ns,
+    }
+}
+
+fn reply(round: u32, time_received_ns: u64) -> ReplyRecord {
+    ReplyRecord {
+        time_received_ns,
+        agent_id: "agent1".to_string(),
+        reply_src_addr: "192.0.2.1".parse().unwrap(),
+        reply_dst_addr: "192.0.2.100".parse().unwrap(),
+        reply_id: 0,
+        reply_size: 56,
+        reply_ttl: 60,
+        reply_quoted_ttl: 1,
+        reply_protocol: 1,
+        reply_icmp_type: 11,
+        reply_icmp_code: 0,
+        reply_mpls_labels: vec![],
+        probe_src_addr: "192.0.2.100".parse().unwrap(),
+        probe_dst_addr: "8.8.8.8".parse().unwrap(),
+        probe_id: 0,
+        probe_size: 28,
+        probe_ttl: 5,
+        probe_protocol: 17,
+        probe_src_port: 24000,
+        probe_dst_port: 33434,
+        rtt: 100,
+        round,
+        measurement_id: "measurement-1".to_string(),
+        instance_id: 0,
+        reply_icmp_extensions: vec![],
+        extensions: None,
+    }
+}
+
+#[test]
+fn test_join_latest_submission_within_window() {
+    let mut index = ProbeIndex::default();
+    index.insert(index_record(1, 1_000));
+    index.insert(index_record(2, 5_000));
+
+    let joined = index.join(reply(0, 6_000), None);
+    assert_eq!(joined.probe.unwrap().round, 2);
+
+    // Only the first submission is within the window
+    let joined = index.join(reply(0, 2_000), Some(2_000));
+    assert_eq!(joined.probe.unwrap().round, 1);
+
+    let joined = index.join(reply(0, 10_000), Some(2_000));
+    assert!(joined.probe.is_none());
+}
+
+#[test]
+fn test_join_matches_round_reported_by_agent() {
+    let mut index = ProbeIndex::default();
+    index.insert(index_record(1, 1_000));
+    index.insert(index_record(2, 5_000));
+
+    let joined = index.join(reply(1, 6_000), None);
+    assert_eq!(joined.probe.unwrap().round, 1);
+}
+
+#[test]
+fn test_join_matches_measurement_reported_by_agent() {
+    let mut index = ProbeIndex::default();
+    index.insert(index_record(1, 1_000));
+
+    let joined = index.join(reply(0, 2_000), None);
+    assert!(joined.probe.is_some());
+
+    let mut other = reply(0, 2_000);
+    other.measurement_id = "measurement-2".to_string();
+    assert!(index.join(other, None).probe.is_none());
+
+    // Replies of unknown measurement match any submission
+    let mut unknown = reply(0, 2_000);
+    unknown.measurement_id = String::new();
+    assert!(index.join(unknown, None).probe.is_some());
+}
+
+#[test]
+fn test_probe_index_from_jsonl() {
+    let record = index_record(1, 1_000);
+    let jsonl = format!("{}\n\n", serde_json::to_string(&record).unwrap());
+    let index = ProbeIndex::from_jsonl(Cursor::new(jsonl)).unwrap();
+
+    let joined = index.join(reply(0, 2_000), None);
+    assert_eq!(joined.probe, Some(record));
+}
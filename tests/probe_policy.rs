@@ -0,0 +1,159 @@
+//! Tests for the agent probing policy and its dry-run audit
+use caracat::models::{Probe, L4};
+use saimiris::agent::policy::{audit, ProbePolicy, Rejection};
+use saimiris::config::app_config;
+use saimiris::config::CaracatConfig;
+use std::fs::File;
+use std::io::Write;
+use tempfile::tempdir;
+
+fn probe(dst_addr: &str, ttl: u8, protocol: L4) -> Probe {
+    Probe {
+        dst_addr: dst_addr.parse().unwrap(),
+        src_port: 24000,
+        dst_port: 33434,
+        ttl,
+        protocol,
+    }
+}
+
+#[test]
+fn test_default_policy_accepts_everything() {
+    let policy = ProbePolicy::new(&CaracatConfig::default()).unwrap();
+    let probes = vec![
+        probe("10.0.0.1", 1, L4::ICMP),
+        probe("2001:db8::1", 255, L4::ICMPv6),
+    ];
+    assert_eq!(policy.evaluate(&probes), vec![None, None]);
+}
+
+#[test]
+fn test_policy_rejections() {
+    let policy = ProbePolicy::new(&CaracatConfig {
+        min_ttl: Some(2),
+        max_ttl: Some(32),
+        dst_denylist: vec!["10.0.0.0/8".to_string()],
+        dst_allowlist: vec!["10.0.0.0/8".to_string(), "192.0.2.0/24".to_string()],
+        allowed_protocols: vec!["icmp".to_string()],
+        ..Default::default()
+    })
+    .unwrap();
+
+    assert_eq!(
+        policy.check(&probe("192.0.2.1", 1, L4::ICMP)),
+        Some(Rejection::TtlTooLow(2))
+    );
+    assert_eq!(
+        policy.check(&probe("192.0.2.1", 33, L4::ICMP)),
+        Some(Rejection::TtlTooHigh(32))
+    );
+    assert_eq!(
+        policy.check(&probe("10.1.2.3", 8, L4::ICMP)),
+        Some(Rejection::DestinationDenied("10.0.0.0/8".parse().unwrap()))
+    );
+    assert_eq!(
+        policy.check(&probe("198.51.100.1", 8, L4::ICMP)),
+        Some(Rejection::DestinationNotAllowed)
+    );
+    assert_eq!(
+        policy.check(&probe("192.0.2.1", 8, L4::UDP)),
+        Some(Rejection::ProtocolNotAllowed)
+    );
+    assert_eq!(policy.check(&probe("192.0.2.1", 8, L4::ICMP)), None);
+}
+
+#[test]
+fn test_policy_destination_rate_ceiling() {
+    let policy = ProbePolicy::new(&CaracatConfig {
+        max_probes_per_destination: Some(2),
+        ..Default::default()
+    })
+    .unwrap();
+    let probes = vec![
+        probe("192.0.2.1", 1, L4::ICMP),
+        probe("192.0.2.1", 2, L4::ICMP),
+        probe("192.0.2.2", 1, L4::ICMP),
+        probe("192.0.2.1", 3, L4::ICMP),
+    ];
+    assert_eq!(
+        policy.evaluate(&probes),
+        vec![None, None, None, Some(Rejection::DestinationRateCeiling(2))]
+    );
+}
+
+#[test]
+fn test_policy_allowlist_file() {
+    let dir = tempdir().unwrap();
+    let allowlist_path = dir.path().join("allowlist.txt");
+    let mut file = File::create(&allowlist_path).unwrap();
+    writeln!(file, "# Agreed address space").unwrap();
+    writeln!(file, "198.51.100.0/24").unwrap();
+    writeln!(file, "2001:db8::/32").unwrap();
+    drop(file);
+
+    // The prefixes of the file are allowed on top of `dst_allowlist`
+    let policy = ProbePolicy::new(&CaracatConfig {
+        dst_allowlist: vec!["192.0.2.0/24".to_string()],
+        dst_allowlist_file: Some(allowlist_path.to_str().unwrap().to_string()),
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(policy.check(&probe("192.0.2.1", 8, L4::ICMP)), None);
+    assert_eq!(policy.check(&probe("198.51.100.1", 8, L4::ICMP)), None);
+    assert_eq!(policy.check(&probe("2001:db8::1", 8, L4::ICMPv6)), None);
+    assert_eq!(
+        policy.check(&probe("203.0.113.1", 8, L4::ICMP)),
+        Some(Rejection::DestinationNotAllowed)
+    );
+
+    // An empty file would allow every destination
+    let empty_path = dir.path().join("empty.txt");
+    File::create(&empty_path).unwrap();
+    assert!(ProbePolicy::new(&CaracatConfig {
+        dst_allowlist_file: Some(empty_path.to_str().unwrap().to_string()),
+        ..Default::default()
+    })
+    .is_err());
+}
+
+#[test]
+fn test_invalid_policy() {
+    assert!(ProbePolicy::new(&CaracatConfig {
+        dst_denylist: vec!["10.0.0.0/33".to_string()],
+        ..Default::default()
+    })
+    .is_err());
+    assert!(ProbePolicy::new(&CaracatConfig {
+        allowed_protocols: vec!["tcp".to_string()],
+        ..Default::default()
+    })
+    .is_err());
+}
+
+#[tokio::test]
+async fn test_audit_per_instance() {
+    let dir = tempdir().unwrap();
+    let config_path = dir.path().join("test_config.yml");
+    let mut file = File::create(&config_path).unwrap();
+    writeln!(file, "agent:").unwrap();
+    writeln!(file, "  metrics_address: '0.0.0.0:8080'").unwrap();
+    writeln!(file, "caracat:").unwrap();
+    writeln!(file, "  - name: strict").unwrap();
+    writeln!(file, "    max_ttl: 16").unwrap();
+    writeln!(file, "    dst_denylist: ['192.0.2.0/25']").unwrap();
+    writeln!(file, "  - name: open").unwrap();
+    drop(file);
+
+    let config = app_config(config_path.to_str().unwrap()).await.unwrap();
+    let probes = vec![
+        probe("192.0.2.1", 8, L4::ICMP),
+        probe("192.0.2.200", 8, L4::ICMP),
+        probe("192.0.2.200", 20, L4::ICMP),
+    ];
+    let violations = audit(&config, &probes).unwrap();
+    assert_eq!(violations.len(), 2);
+    assert!(violations.iter().all(|v| v.instance == "strict"));
+    assert_eq!(violations[0].index, 0);
+    assert_eq!(violations[1].index, 2);
+    assert_eq!(violations[1].rejection, Rejection::TtlTooHigh(16));
+}
@@ -0,0 +1,26 @@
+//! Tests for the consumer lag and queue depth metrics
+use rdkafka::Offset;
+use saimiris::agent::lag::{partition_lag, queue_depth};
+use tokio::sync::mpsc::channel;
+
+#[test]
+fn test_partition_lag() {
+    assert_eq!(partition_lag(Offset::Offset(90), 100), Some(10));
+    assert_eq!(partition_lag(Offset::Offset(100), 100), Some(0));
+    // High watermark fetched before the position moved
+    assert_eq!(partition_lag(Offset::Offset(105), 100), Some(0));
+    // Nothing consumed yet
+    assert_eq!(partition_lag(Offset::Invalid, 100), None);
+}
+
+#[test]
+fn test_queue_depth() {
+    let (tx, mut rx) = channel(10);
+    assert_eq!(queue_depth(&tx), 0);
+    for i in 0..3 {
+        tx.try_send(i).unwrap();
+    }
+    assert_eq!(queue_depth(&tx), 3);
+    rx.try_recv().unwrap();
+    assert_eq!(queue_depth(&tx), 2);
+}
@@ -0,0 +1,127 @@
+use saimiris::agent::ratelimit::{
+    DestinationRateLimiter, SharedRateLimiter, SourceRateLimiter, TokenBucket,
+};
+use saimiris::config::{CaracatConfig, SourceRateLimit};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+fn limits(limits: &[(&str, u64)]) -> CaracatConfig {
+    CaracatConfig {
+        batch_size: 10,
+        source_rate_limits: limits
+            .iter()
+            .map(|(prefix, probing_rate)| SourceRateLimit {
+                prefix: prefix.to_string(),
+                probing_rate: *probing_rate,
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_token_bucket() {
+    let mut bucket = TokenBucket::new(100, 2);
+    let start = Instant::now();
+    // Burst, then one token every 10ms
+    assert_eq!(bucket.take(start), Duration::ZERO);
+    assert_eq!(bucket.take(start), Duration::ZERO);
+    let delay = bucket.take(start);
+    assert!(delay > Duration::from_millis(9) && delay < Duration::from_millis(11));
+
+    // Owed tokens are paid back before the bucket refills
+    let later = start + Duration::from_millis(10);
+    let delay = bucket.take(later);
+    assert!(delay > Duration::from_millis(9) && delay < Duration::from_millis(11));
+
+    // The bucket refills up to the burst
+    let idle = start + Duration::from_secs(10);
+    assert_eq!(bucket.take(idle), Duration::ZERO);
+    assert_eq!(bucket.take(idle), Duration::ZERO);
+    assert!(!bucket.take(idle).is_zero());
+}
+
+#[test]
+fn test_source_rate_limiter() {
+    let mut limiter =
+        SourceRateLimiter::new(&limits(&[("192.0.2.0/24", 100), ("192.0.2.0/28", 10)])).unwrap();
+    let source: IpAddr = "192.0.2.1".parse().unwrap();
+    // The most specific prefix applies
+    assert_eq!(limiter.rate(&source), Some(10));
+    assert_eq!(limiter.rate(&"192.0.2.100".parse().unwrap()), Some(100));
+    assert_eq!(limiter.rate(&"198.51.100.1".parse().unwrap()), None);
+    assert!(limiter.bucket(&"198.51.100.1".parse().unwrap()).is_none());
+
+    // Each source address has its own bucket
+    let start = Instant::now();
+    for _ in 0..10 {
+        assert!(limiter.bucket(&source).unwrap().take(start).is_zero());
+    }
+    assert!(!limiter.bucket(&source).unwrap().take(start).is_zero());
+    let other: IpAddr = "192.0.2.2".parse().unwrap();
+    assert!(limiter.bucket(&other).unwrap().take(start).is_zero());
+}
+
+#[test]
+fn test_source_rate_limiter_invalid() {
+    assert!(SourceRateLimiter::new(&limits(&[("not-a-prefix", 100)])).is_err());
+    assert!(SourceRateLimiter::new(&limits(&[("192.0.2.0/24", 0)])).is_err());
+    assert!(SourceRateLimiter::new(&limits(&[])).is_ok());
+}
+
+#[test]
+fn test_shared_rate_limiter() {
+    // 10ms worth of probes in a burst
+    let limiter = SharedRateLimiter::new(1000);
+    let start = Instant::now() + Duration::from_secs(1);
+    assert!(limiter.take(start, 10).is_zero());
+
+    // Clones share the same budget, across instances
+    let other = limiter.clone();
+    let delay = other.take(start, 100);
+    assert!(delay > Duration::from_millis(99) && delay < Duration::from_millis(101));
+}
+
+#[test]
+fn test_destination_rate_limiter_prefixes() {
+    let prefix = |addr: &str| DestinationRateLimiter::prefix(&addr.parse::<IpAddr>().unwrap());
+    assert_eq!(prefix("192.0.2.77"), "192.0.2.0/24".parse().unwrap());
+    assert_eq!(
+        prefix("2001:db8:1:2::1"),
+        "2001:db8:1::/48".parse().unwrap()
+    );
+}
+
+#[test]
+fn test_destination_rate_limiter_sliding_window() {
+    let limiter = DestinationRateLimiter::new(2);
+    let dst: IpAddr = "192.0.2.1".parse().unwrap();
+    let neighbor: IpAddr = "192.0.2.200".parse().unwrap();
+    let other: IpAddr = "198.51.100.1".parse().unwrap();
+    let start = Instant::now();
+
+    assert_eq!(limiter.take(&dst, start), Duration::ZERO);
+    assert_eq!(limiter.take(&neighbor, start), Duration::ZERO);
+    // The /24 is at its rate until the next window, other prefixes are not
+    assert_eq!(limiter.take(&dst, start), Duration::from_secs(1));
+    assert_eq!(limiter.take(&other, start), Duration::ZERO);
+
+    // The probes of the previous window slide out of the last second
+    let next = start + Duration::from_secs(1);
+    let delay = limiter.take(&dst, next);
+    assert!(
+        delay > Duration::from_millis(499) && delay < Duration::from_millis(501),
+        "{:?}",
+        delay
+    );
+    assert_eq!(
+        limiter.take(&dst, next + Duration::from_millis(500)),
+        Duration::ZERO
+    );
+
+    // Idle prefixes start over
+    let idle = start + Duration::from_secs(5);
+    assert_eq!(limiter.take(&dst, idle), Duration::ZERO);
+    assert_eq!(limiter.take(&dst, idle), Duration::ZERO);
+    assert!(!limiter.take(&dst, idle).is_zero());
+}
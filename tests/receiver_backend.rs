@@ -0,0 +1,41 @@
+//! Unit tests for the selection of the reply capture backend
+use saimiris::agent::packet_ring::ReceiverBackend;
+use saimiris::config::{validate_caracat_configs, CaracatConfig};
+
+fn backend(receiver_backend: &str) -> anyhow::Result<ReceiverBackend> {
+    ReceiverBackend::new(&CaracatConfig {
+        receiver_backend: receiver_backend.to_string(),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn test_pcap_is_the_default_backend() {
+    let mut configs = vec![CaracatConfig::default()];
+    validate_caracat_configs(&mut configs, "agent", None).unwrap();
+    assert_eq!(configs[0].receiver_backend, "pcap");
+    assert_eq!(configs[0].receiver_ring_blocks, 64);
+    assert_eq!(
+        ReceiverBackend::new(&configs[0]).unwrap(),
+        ReceiverBackend::Pcap
+    );
+    assert_eq!(backend("pcap").unwrap(), ReceiverBackend::Pcap);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_tpacket_v3_backend() {
+    assert_eq!(backend("tpacket_v3").unwrap(), ReceiverBackend::TpacketV3);
+}
+
+#[test]
+fn test_unknown_backend_is_rejected() {
+    let error = backend("af_xdp").unwrap_err().to_string();
+    assert!(error.contains("Invalid receiver_backend 'af_xdp'"));
+
+    let mut configs = vec![CaracatConfig {
+        receiver_backend: "PF_RING".to_string(),
+        ..Default::default()
+    }];
+    assert!(validate_caracat_configs(&mut configs, "agent", None).is_err());
+}
@@ -0,0 +1,111 @@
+//! Tests for the conversion of capnp reply streams to other formats
+use capnp::message::Builder;
+use capnp::serialize;
+use saimiris::convert::{handle, reply_columns, reply_row, ConvertFormat};
+use saimiris::probe::serialize_ip_addr;
+use saimiris::reply::{ReplyReader, ReplyRecord};
+use saimiris::reply_capnp::reply;
+use std::io::Cursor;
+use tempfile::tempdir;
+
+fn reply_message(probe_ttl: u8) -> Vec<u8> {
+    let mut message = Builder::new_default();
+    {
+        let mut r = message.init_root::<reply::Builder>();
+        r.set_agent_id("agent1");
+        r.set_time_received_ns(1_700_000_000_000_000_000);
+        r.set_reply_src_addr(&serialize_ip_addr("192.0.2.1".parse().unwrap()));
+        r.set_reply_dst_addr(&serialize_ip_addr("192.0.2.100".parse().unwrap()));
+        r.set_probe_src_addr(&serialize_ip_addr("192.0.2.100".parse().unwrap()));
+        r.set_probe_dst_addr(&serialize_ip_addr("2001:db8::1".parse().unwrap()));
+        r.set_probe_ttl(probe_ttl);
+        r.set_rtt(125);
+        r.set_measurement_id("measurement-1");
+        let mut labels = r.reborrow().init_reply_mpls_label(1);
+        labels.reborrow().get(0).set_label(16);
+    }
+    serialize::write_message_to_words(&message)
+}
+
+/// A stream of replies, as dumped from the replies topic.
+fn reply_stream(count: u8) -> Vec<u8> {
+    (1..=count).flat_map(reply_message).collect()
+}
+
+#[test]
+fn test_reply_reader() {
+    let replies: Vec<ReplyRecord> = ReplyReader::new(Cursor::new(reply_stream(3)))
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(replies.len(), 3);
+    assert_eq!(replies[2].probe_ttl, 3);
+
+    // A truncated last message ends the stream
+    let mut stream = reply_stream(2);
+    stream.extend_from_slice(&reply_message(3)[..16]);
+    assert_eq!(ReplyReader::new(Cursor::new(stream)).count(), 2);
+
+    // An invalid message ends it with an error
+    let mut stream = reply_stream(1);
+    stream.extend_from_slice(&[0xff; 16]);
+    let results: Vec<_> = ReplyReader::new(Cursor::new(stream)).collect();
+    assert_eq!(results.len(), 2);
+    assert!(results[1].is_err());
+}
+
+#[test]
+fn test_reply_row() {
+    let reply = ReplyReader::new(Cursor::new(reply_message(7)))
+        .next()
+        .unwrap()
+        .unwrap();
+    let columns = reply_columns();
+    let row = reply_row(&columns, &reply);
+    assert_eq!(row.len(), columns.len());
+    let value = |name: &str| &row[columns.iter().position(|(n, _)| *n == name).unwrap()];
+    assert_eq!(value("time_received_ns"), "1700000000000000000");
+    assert_eq!(value("probe_dst_addr"), "2001:db8::1");
+    assert_eq!(value("probe_ttl"), "7");
+    assert_eq!(value("measurement_id"), "measurement-1");
+    assert_eq!(
+        value("reply_mpls_labels"),
+        r#"[{"label":16,"exp":0,"s_bit":false,"ttl":0}]"#
+    );
+    assert_eq!(value("extensions"), "");
+}
+
+#[test]
+fn test_convert_files() {
+    let dir = tempdir().unwrap();
+    let inputs = vec![dir.path().join("a.bin"), dir.path().join("b.bin")];
+    std::fs::write(&inputs[0], reply_stream(2)).unwrap();
+    std::fs::write(&inputs[1], reply_stream(1)).unwrap();
+
+    let csv = dir.path().join("replies.csv");
+    handle(&inputs, Some(&csv), ConvertFormat::Csv).unwrap();
+    let csv = std::fs::read_to_string(csv).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with("time_received_ns,agent_id,reply_src_addr"));
+    assert!(lines[1].starts_with("1700000000000000000,agent1,192.0.2.1,192.0.2.100"));
+
+    let jsonl = dir.path().join("replies.jsonl");
+    handle(&inputs, Some(&jsonl), ConvertFormat::Jsonl).unwrap();
+    let replies: Vec<ReplyRecord> = std::fs::read_to_string(jsonl)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(
+        replies.iter().map(|r| r.probe_ttl).collect::<Vec<_>>(),
+        vec![1, 2, 1]
+    );
+}
+
+#[cfg(not(feature = "parquet"))]
+#[test]
+fn test_parquet_requires_feature() {
+    let dir = tempdir().unwrap();
+    let output = dir.path().join("replies.parquet");
+    assert!(handle(&[], Some(&output), ConvertFormat::Parquet).is_err());
+}
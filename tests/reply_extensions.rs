@@ -0,0 +1,118 @@
+//! Unit tests for the replies of newer agents, with fields unknown to this version
+use capnp::message::Builder;
+use saimiris::probe::serialize_ip_addr;
+use saimiris::reply::deserialize_replies;
+use saimiris::reply_capnp::reply;
+
+/// Segment of a reply with the known fields only.
+fn reply_segment() -> Vec<u64> {
+    let mut message = Builder::new_default();
+    {
+        let mut r = message.init_root::<reply::Builder>();
+        r.set_agent_id("agent1");
+        r.set_reply_src_addr(&serialize_ip_addr("192.0.2.1".parse().unwrap()));
+        r.set_reply_dst_addr(&serialize_ip_addr("192.0.2.100".parse().unwrap()));
+        r.set_probe_src_addr(&serialize_ip_addr("192.0.2.100".parse().unwrap()));
+        r.set_probe_dst_addr(&serialize_ip_addr("8.8.8.8".parse().unwrap()));
+        r.set_probe_ttl(5);
+        r.set_measurement_id("measurement-1");
+    }
+    let segments = message.get_segments_for_output();
+    assert_eq!(segments.len(), 1);
+    segments[0]
+        .chunks(8)
+        .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+        .collect()
+}
+
+fn to_message(segment: &[u64]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes.extend_from_slice(&(segment.len() as u32).to_le_bytes());
+    for word in segment {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    bytes
+}
+
+/// Offset of a struct or list pointer at `position`, to its target.
+fn target(word: u64, position: usize) -> usize {
+    (position as i64 + 1 + ((word as u32 as i32) >> 2) as i64) as usize
+}
+
+fn pointer(word: u64, position: usize, target: usize) -> u64 {
+    let offset = target as i64 - (position as i64 + 1);
+    (word & !0xffff_ffff) | (((offset as i32) << 2) as u32 as u64) | (word & 3)
+}
+
+/// Rewrite the reply as a newer agent would, with an extra data word and an extra text field.
+fn newer_reply_segment() -> Vec<u64> {
+    let mut segment = reply_segment();
+    let root = segment[0];
+    let start = target(root, 0);
+    let (data, pointers) = (5, 8);
+
+    // Copy the struct at the end of the segment, with the extra fields
+    let new_start = segment.len();
+    for i in 0..data {
+        segment.push(segment[start + i]);
+    }
+    segment.push(0x2a);
+    for i in 0..pointers {
+        let word = segment[start + data + i];
+        let position = new_start + data + 1 + i;
+        segment.push(if word == 0 {
+            0
+        } else {
+            pointer(word, position, target(word, start + data + i))
+        });
+    }
+    // Text "hi", right after the pointer section
+    segment.push(1 | (2 << 32) | (3 << 35));
+    segment.push(u64::from_le_bytes(*b"hi\0\0\0\0\0\0"));
+
+    segment[0] = pointer(
+        ((data as u64 + 1) << 32) | ((pointers as u64 + 1) << 48),
+        0,
+        new_start,
+    );
+    segment
+}
+
+#[test]
+fn test_reply_without_extensions() {
+    let replies = deserialize_replies(to_message(&reply_segment())).unwrap();
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].extensions, None);
+    assert!(!serde_json::to_string(&replies[0])
+        .unwrap()
+        .contains("extensions"));
+}
+
+#[test]
+fn test_reply_extensions_preserved() {
+    let replies = deserialize_replies(to_message(&newer_reply_segment())).unwrap();
+    assert_eq!(replies.len(), 1);
+    let reply = &replies[0];
+    // Known fields are still decoded
+    assert_eq!(reply.agent_id, "agent1");
+    assert_eq!(
+        reply.probe_dst_addr,
+        "8.8.8.8".parse::<std::net::IpAddr>().unwrap()
+    );
+    assert_eq!(reply.measurement_id, "measurement-1");
+
+    let extensions = reply.extensions.as_ref().unwrap();
+    assert_eq!(extensions.data, "2a00000000000000");
+    assert_eq!(
+        extensions.pointers.keys().copied().collect::<Vec<_>>(),
+        vec![8]
+    );
+    // The field is a message of its own, holding the text
+    assert!(extensions.pointers[&8].ends_with("6869000000000000"));
+
+    // Extensions survive a JSON round trip
+    let json = serde_json::to_string(reply).unwrap();
+    let decoded: saimiris::reply::ReplyRecord = serde_json::from_str(&json).unwrap();
+    assert_eq!(&decoded, reply);
+}
@@ -0,0 +1,78 @@
+//! Unit tests for the agent reply filter
+use saimiris::agent::reply_filter::ReplyFilter;
+use saimiris::config::CaracatConfig;
+
+const ICMP: u8 = 1;
+const ICMPV6: u8 = 58;
+const TCP: u8 = 6;
+
+fn filter(reply_filter: &str, allowlist: &[&str], exclude_unreachable: bool) -> ReplyFilter {
+    ReplyFilter::new(&CaracatConfig {
+        reply_filter: reply_filter.to_string(),
+        reply_icmp_allowlist: allowlist.iter().map(|s| s.to_string()).collect(),
+        reply_exclude_unreachable: exclude_unreachable,
+        ..Default::default()
+    })
+    .unwrap()
+}
+
+#[test]
+fn test_default_filter_accepts_everything() {
+    let filter = filter("all", &[], false);
+    assert!(filter.is_noop());
+    assert!(filter.accepts(ICMP, 11, 0));
+    assert!(filter.accepts(ICMP, 3, 3));
+    assert!(filter.accepts(TCP, 0, 0));
+}
+
+#[test]
+fn test_time_exceeded_only() {
+    let filter = filter("time-exceeded-only", &[], false);
+    assert!(filter.accepts(ICMP, 11, 0));
+    assert!(filter.accepts(ICMPV6, 3, 0));
+    assert!(!filter.accepts(ICMP, 3, 3));
+    assert!(!filter.accepts(ICMP, 0, 0));
+    assert!(!filter.accepts(ICMPV6, 1, 4));
+    assert!(!filter.accepts(TCP, 0, 0));
+}
+
+#[test]
+fn test_exclude_unreachable() {
+    let filter = filter("all", &[], true);
+    assert!(!filter.accepts(ICMP, 3, 3));
+    assert!(!filter.accepts(ICMPV6, 1, 4));
+    // ICMPv6 type 3 is time exceeded
+    assert!(filter.accepts(ICMPV6, 3, 0));
+    assert!(filter.accepts(ICMP, 0, 0));
+}
+
+#[test]
+fn test_icmp_allowlist() {
+    let filter = filter("all", &["11", "3:3"], false);
+    assert!(filter.accepts(ICMP, 11, 1));
+    assert!(filter.accepts(ICMP, 3, 3));
+    assert!(!filter.accepts(ICMP, 3, 1));
+    assert!(!filter.accepts(ICMP, 0, 0));
+    // Non-ICMP replies are not subject to the allowlist
+    assert!(filter.accepts(TCP, 0, 0));
+}
+
+#[test]
+fn test_invalid_filter_settings() {
+    for config in [
+        CaracatConfig {
+            reply_filter: "echo-only".to_string(),
+            ..Default::default()
+        },
+        CaracatConfig {
+            reply_icmp_allowlist: vec!["time-exceeded".to_string()],
+            ..Default::default()
+        },
+        CaracatConfig {
+            reply_icmp_allowlist: vec!["3:".to_string()],
+            ..Default::default()
+        },
+    ] {
+        assert!(ReplyFilter::new(&config).is_err());
+    }
+}
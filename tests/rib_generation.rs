@@ -0,0 +1,175 @@
+//! Tests for the generation of targets from routing tables (MRT RIB dumps and prefix lists)
+use ipnet::IpNet;
+use saimiris::client::rib::{
+    read_mrt, read_prefix_list, rib_targets, sample_targets, target_tags, RoutedPrefix,
+};
+use std::io::Cursor;
+use std::net::IpAddr;
+
+/// A TABLE_DUMP_V2 RIB record of `prefix`, with one route per AS path segments list.
+fn mrt_rib_record(prefix: &str, routes: &[&[(u8, &[u32])]]) -> Vec<u8> {
+    let prefix: IpNet = prefix.parse().unwrap();
+    let (subtype, octets): (u16, Vec<u8>) = match prefix.network() {
+        IpAddr::V4(address) => (2, address.octets().to_vec()),
+        IpAddr::V6(address) => (4, address.octets().to_vec()),
+    };
+    let mut body = Vec::new();
+    body.extend_from_slice(&0u32.to_be_bytes());
+    body.push(prefix.prefix_len());
+    body.extend_from_slice(&octets[..(prefix.prefix_len() as usize).div_ceil(8)]);
+    body.extend_from_slice(&(routes.len() as u16).to_be_bytes());
+    for segments in routes {
+        let mut as_path = Vec::new();
+        for (segment_type, asns) in *segments {
+            as_path.push(*segment_type);
+            as_path.push(asns.len() as u8);
+            for asn in *asns {
+                as_path.extend_from_slice(&asn.to_be_bytes());
+            }
+        }
+        // ORIGIN attribute, then AS_PATH
+        let mut attributes = vec![0x40, 1, 1, 0, 0x40, 2, as_path.len() as u8];
+        attributes.extend_from_slice(&as_path);
+        body.extend_from_slice(&0u16.to_be_bytes());
+        body.extend_from_slice(&0u32.to_be_bytes());
+        body.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+        body.extend_from_slice(&attributes);
+    }
+    mrt_record(13, subtype, &body)
+}
+
+fn mrt_record(kind: u16, subtype: u16, body: &[u8]) -> Vec<u8> {
+    let mut record = Vec::new();
+    record.extend_from_slice(&1_700_000_000u32.to_be_bytes());
+    record.extend_from_slice(&kind.to_be_bytes());
+    record.extend_from_slice(&subtype.to_be_bytes());
+    record.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    record.extend_from_slice(body);
+    record
+}
+
+fn routed(prefix: &str, origin_asn: u32) -> RoutedPrefix {
+    RoutedPrefix {
+        prefix: prefix.parse().unwrap(),
+        origin_asn,
+    }
+}
+
+#[test]
+fn test_read_mrt_rib_dump() {
+    let mut dump = Vec::new();
+    // Peer index table, skipped
+    dump.extend(mrt_record(13, 1, &[0; 8]));
+    dump.extend(mrt_rib_record(
+        "192.0.2.0/24",
+        &[&[(2, &[3356, 64500])], &[(2, &[174, 64500])]],
+    ));
+    dump.extend(mrt_rib_record("2001:db8::/32", &[&[(2, &[6939, 64501])]]));
+    // Path ending with an AS_SET: ambiguous origin, unless another route has one
+    dump.extend(mrt_rib_record(
+        "198.51.100.0/24",
+        &[&[(2, &[3356]), (1, &[64502, 64503])]],
+    ));
+    dump.extend(mrt_rib_record(
+        "203.0.113.0/25",
+        &[&[(2, &[3356]), (1, &[64502])], &[(2, &[174, 64504])]],
+    ));
+
+    let prefixes = read_mrt(Cursor::new(dump)).unwrap();
+    assert_eq!(
+        prefixes,
+        vec![
+            routed("192.0.2.0/24", 64500),
+            routed("2001:db8::/32", 64501),
+            routed("203.0.113.0/25", 64504),
+        ]
+    );
+}
+
+#[test]
+fn test_read_mrt_truncated_record() {
+    let mut record = mrt_rib_record("192.0.2.0/24", &[&[(2, &[64500])]]);
+    record.truncate(record.len() - 2);
+    assert!(read_mrt(Cursor::new(record)).is_err());
+}
+
+#[test]
+fn test_read_prefix_list() {
+    let list = "# prefix origin\n192.0.2.1/24 64500\n\n2001:db8::/32,AS64501\n198.51.100.0/24|64502 # comment\n";
+    let prefixes = read_prefix_list(Cursor::new(list)).unwrap();
+    assert_eq!(
+        prefixes,
+        vec![
+            routed("192.0.2.0/24", 64500),
+            routed("2001:db8::/32", 64501),
+            routed("198.51.100.0/24", 64502),
+        ]
+    );
+
+    assert!(read_prefix_list(Cursor::new("192.0.2.0/24\n")).is_err());
+    assert!(read_prefix_list(Cursor::new("192.0.2.0/24 AS-X\n")).is_err());
+    assert!(read_prefix_list(Cursor::new("192.0.2.0 64500\n")).is_err());
+}
+
+#[test]
+fn test_sample_targets_within_prefix() {
+    let prefix: IpNet = "192.0.2.0/24".parse().unwrap();
+    let targets = sample_targets(&prefix, 4, 0);
+    assert_eq!(targets.len(), 4);
+    for (i, target) in targets.iter().enumerate() {
+        let IpAddr::V4(address) = target else {
+            panic!("Expected an IPv4 target");
+        };
+        // One target in each /26, avoiding its first and last addresses
+        let offset = address.octets()[3] as usize;
+        assert!(offset > i * 64 && offset < (i + 1) * 64 - 1, "{}", target);
+    }
+
+    // Reproducible for a seed, and different across seeds
+    assert_eq!(sample_targets(&prefix, 4, 0), targets);
+    let prefix: IpNet = "2001:db8::/32".parse().unwrap();
+    assert_ne!(sample_targets(&prefix, 1, 0), sample_targets(&prefix, 1, 1));
+    assert!(prefix.contains(&sample_targets(&prefix, 1, 1)[0]));
+}
+
+#[test]
+fn test_sample_targets_small_prefixes() {
+    let host: IpNet = "192.0.2.1/32".parse().unwrap();
+    assert_eq!(
+        sample_targets(&host, 3, 0),
+        vec!["192.0.2.1".parse::<IpAddr>().unwrap()]
+    );
+
+    let mut pair = sample_targets(&"192.0.2.2/31".parse().unwrap(), 8, 0);
+    pair.sort();
+    assert_eq!(
+        pair,
+        vec![
+            "192.0.2.2".parse::<IpAddr>().unwrap(),
+            "192.0.2.3".parse::<IpAddr>().unwrap()
+        ]
+    );
+}
+
+#[test]
+fn test_rib_targets_tags() {
+    let prefixes = vec![
+        routed("192.0.2.0/24", 64500),
+        routed("192.0.2.0/24", 64501),
+        routed("0.0.0.0/0", 64502),
+        routed("2001:db8::/32", 64503),
+    ];
+    let targets = rib_targets(&prefixes, 2, 0);
+    // Duplicate prefixes keep their first origin, and default routes are skipped
+    assert_eq!(targets.len(), 4);
+    assert!(targets
+        .iter()
+        .all(|(target, routed)| routed.prefix.contains(target)));
+
+    let tags = target_tags(&targets);
+    let (target, _) = targets[0];
+    assert_eq!(tags[&target]["origin_asn"], "64500");
+    assert_eq!(tags[&target]["prefix"], "192.0.2.0/24");
+    let (target, _) = targets[3];
+    assert_eq!(tags[&target]["origin_asn"], "64503");
+}
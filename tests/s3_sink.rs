@@ -0,0 +1,59 @@
+//! Unit tests for the S3 reply sink
+use chrono::TimeZone;
+use saimiris::agent::s3::{object_key, signing_key, S3Client};
+use saimiris::config::S3Config;
+
+fn s3_config(endpoint: &str, path_style: bool) -> S3Config {
+    S3Config {
+        endpoint: endpoint.to_string(),
+        region: "us-east-1".to_string(),
+        bucket: "replies".to_string(),
+        prefix: "saimiris/".to_string(),
+        access_key: "access".to_string(),
+        secret_key: "secret".to_string(),
+        path_style,
+        rotation_interval: 300,
+        max_object_bytes: 1024,
+        max_pending_objects: 10,
+    }
+}
+
+#[test]
+fn test_signing_key() {
+    // Example from the AWS Signature Version 4 documentation
+    let key = signing_key(
+        "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+        "20120215",
+        "us-east-1",
+        "iam",
+    );
+    let key: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+    assert_eq!(
+        key,
+        "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+    );
+}
+
+#[test]
+fn test_object_key() {
+    let time = chrono::Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 5).unwrap();
+    assert_eq!(
+        object_key("saimiris/", "agent1", time, 42),
+        "saimiris/agent1/2024/03/01/20240301T123005Z-000042.bin"
+    );
+}
+
+#[test]
+fn test_object_url() {
+    let client = S3Client::new(s3_config("http://minio:9000", true));
+    assert_eq!(
+        client.object_url("saimiris/a b.bin").unwrap().as_str(),
+        "http://minio:9000/replies/saimiris/a%20b.bin"
+    );
+
+    let client = S3Client::new(s3_config("https://s3.us-east-1.amazonaws.com", false));
+    assert_eq!(
+        client.object_url("saimiris/agent1.bin").unwrap().as_str(),
+        "https://replies.s3.us-east-1.amazonaws.com/saimiris/agent1.bin"
+    );
+}
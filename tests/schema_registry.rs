@@ -0,0 +1,50 @@
+//! Unit tests for the schema registry framing
+use saimiris::config::{KafkaConfig, SchemaRegistryConfig};
+use saimiris::schema_registry::{
+    frame, subject, unframe, SchemaError, FRAME_LEN, PROBE_SCHEMA, REPLY_SCHEMA,
+};
+
+fn registry(username: Option<&str>, password: Option<&str>) -> KafkaConfig {
+    KafkaConfig {
+        schema_registry: Some(SchemaRegistryConfig {
+            url: "http://schema-registry:8081".to_string(),
+            username: username.map(str::to_string),
+            password: password.map(str::to_string),
+            schema_type: "CAPNP".to_string(),
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_frame_roundtrip() {
+    let payload = b"probes";
+    let framed = frame(42, payload);
+    assert_eq!(framed.len(), FRAME_LEN + payload.len());
+    assert_eq!(&framed[..FRAME_LEN], &[0, 0, 0, 0, 42]);
+    assert_eq!(unframe(&framed), Some((42, &payload[..])));
+}
+
+#[test]
+fn test_unframe_invalid() {
+    assert_eq!(unframe(&[0, 0, 0]), None);
+    assert_eq!(unframe(&[1, 0, 0, 0, 42, 0]), None);
+    // A frame without payload
+    assert_eq!(unframe(&[0, 0, 0, 1, 0]), Some((256, &[][..])));
+}
+
+#[test]
+fn test_subject() {
+    assert_eq!(subject("saimiris-probes"), "saimiris-probes-value");
+    assert!(PROBE_SCHEMA.contains("struct Probe"));
+    assert!(REPLY_SCHEMA.contains("struct Reply"));
+    assert_eq!(SchemaError::Unframed.reason(), "schema_unframed");
+    assert_eq!(SchemaError::UnknownSchema(1).reason(), "schema_unknown");
+}
+
+#[test]
+fn test_schema_registry_config() {
+    assert!(registry(None, None).validate().is_ok());
+    assert!(registry(Some("user"), Some("secret")).validate().is_ok());
+    assert!(registry(Some("user"), None).validate().is_err());
+}
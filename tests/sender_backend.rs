@@ -0,0 +1,79 @@
+//! Unit tests for the selection of the probe sender backend
+use saimiris::agent::tx_ring::{set_dscp, SenderBackend};
+use saimiris::config::{validate_caracat_configs, CaracatConfig};
+
+fn backend(sender_backend: &str) -> anyhow::Result<SenderBackend> {
+    SenderBackend::new(&CaracatConfig {
+        sender_backend: sender_backend.to_string(),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn test_caracat_is_the_default_backend() {
+    let mut configs = vec![CaracatConfig::default()];
+    validate_caracat_configs(&mut configs, "agent", None).unwrap();
+    assert_eq!(configs[0].sender_backend, "caracat");
+    assert_eq!(
+        SenderBackend::new(&configs[0]).unwrap(),
+        SenderBackend::Caracat
+    );
+}
+
+#[test]
+fn test_pcap_backend() {
+    assert_eq!(backend("pcap").unwrap(), SenderBackend::Pcap);
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "tx-ring"))]
+fn test_tx_ring_backend() {
+    assert_eq!(backend("tx_ring").unwrap(), SenderBackend::TxRing);
+}
+
+#[test]
+#[cfg(not(feature = "tx-ring"))]
+fn test_tx_ring_backend_requires_the_feature() {
+    let error = backend("tx_ring").unwrap_err().to_string();
+    assert!(error.contains("tx_ring"));
+}
+
+#[test]
+fn test_unknown_backend_is_rejected() {
+    let error = backend("io_uring").unwrap_err().to_string();
+    assert!(error.contains("Invalid sender_backend 'io_uring'"));
+}
+
+#[test]
+fn test_dscp_marking_backends() {
+    assert_eq!(
+        SenderBackend::Caracat.marks_dscp(),
+        cfg!(target_os = "linux")
+    );
+    assert!(!SenderBackend::Pcap.marks_dscp());
+    assert!(SenderBackend::TxRing.marks_dscp());
+}
+
+#[test]
+fn test_set_dscp_ipv4() {
+    let mut header = [
+        0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8, 0x00,
+        0x01, 0xc0, 0xa8, 0x00, 0xc7,
+    ];
+    // Expedited Forwarding, with the header checksum updated
+    set_dscp(&mut header, 46);
+    assert_eq!(header[1], 0xb8);
+    assert_eq!(header[10..12], [0xb7, 0xa9]);
+    set_dscp(&mut header, 0);
+    assert_eq!(header[1], 0x00);
+    assert_eq!(header[10..12], [0xb8, 0x61]);
+}
+
+#[test]
+fn test_set_dscp_ipv6() {
+    // Version 6, ECN 1 and flow label 0x12345
+    let mut header = [0u8; 40];
+    header[..4].copy_from_slice(&[0x60, 0x11, 0x23, 0x45]);
+    set_dscp(&mut header, 46);
+    assert_eq!(header[..4], [0x6b, 0x91, 0x23, 0x45]);
+}
@@ -0,0 +1,97 @@
+use saimiris::signing::{
+    sign, SignatureError, SignatureVerifier, SIGNATURE_HEADER, SIGNATURE_KEY_ID_HEADER,
+};
+use std::collections::HashMap;
+
+fn verifier() -> SignatureVerifier {
+    SignatureVerifier::new(&HashMap::from([
+        ("client1".to_string(), "secret1".to_string()),
+        ("client2".to_string(), "secret2".to_string()),
+    ]))
+    .unwrap()
+}
+
+fn signed<'a>(
+    key_id: &'a str,
+    signature: &'a str,
+    headers: &[(&'a str, Option<&'a [u8]>)],
+) -> Vec<(&'a str, Option<&'a [u8]>)> {
+    let mut headers = headers.to_vec();
+    headers.push((SIGNATURE_KEY_ID_HEADER, Some(key_id.as_bytes())));
+    headers.push((SIGNATURE_HEADER, Some(signature.as_bytes())));
+    headers
+}
+
+#[test]
+fn test_signed_message() {
+    let payload = b"probes";
+    let headers = [
+        ("agent1", Some(br#"{"src_ip":"192.0.2.1"}"#.as_slice())),
+        ("end_of_measurement", Some(b"true".as_slice())),
+    ];
+    let signature = sign("secret1", payload, headers);
+    let verifier = verifier();
+    assert!(verifier.is_enabled());
+    assert_eq!(
+        verifier.verify(payload, signed("client1", &signature, &headers)),
+        Ok("client1".to_string())
+    );
+
+    // Headers may be reordered
+    let reordered = [headers[1], headers[0]];
+    assert!(verifier
+        .verify(payload, signed("client1", &signature, &reordered))
+        .is_ok());
+
+    // The payload and the headers cannot be altered
+    assert_eq!(
+        verifier.verify(b"other", signed("client1", &signature, &headers)),
+        Err(SignatureError::Invalid("client1".to_string()))
+    );
+    let altered = [
+        ("agent1", Some(br#"{"src_ip":"192.0.2.2"}"#.as_slice())),
+        headers[1],
+    ];
+    assert!(verifier
+        .verify(payload, signed("client1", &signature, &altered))
+        .is_err());
+    let added = [headers[0], headers[1], ("agent2", Some(b"{}".as_slice()))];
+    assert!(verifier
+        .verify(payload, signed("client1", &signature, &added))
+        .is_err());
+
+    // Nor signed with the key of another client
+    assert!(verifier
+        .verify(payload, signed("client2", &signature, &headers))
+        .is_err());
+}
+
+#[test]
+fn test_rejected_messages() {
+    let verifier = verifier();
+    let headers = [("agent1", Some(b"{}".as_slice()))];
+    let error = verifier.verify(b"", headers).unwrap_err();
+    assert_eq!(error, SignatureError::Missing);
+    assert_eq!(error.reason(), "missing");
+
+    let signature = sign("secret3", b"", headers);
+    let error = verifier
+        .verify(b"", signed("client3", &signature, &headers))
+        .unwrap_err();
+    assert_eq!(error.reason(), "unknown_key");
+
+    let error = verifier
+        .verify(b"", signed("client1", "not-hex", &headers))
+        .unwrap_err();
+    assert_eq!(error.reason(), "invalid");
+}
+
+#[test]
+fn test_verifier_keys() {
+    assert!(!SignatureVerifier::new(&HashMap::new())
+        .unwrap()
+        .is_enabled());
+    assert!(
+        SignatureVerifier::new(&HashMap::from([("client1".to_string(), String::new())])).is_err()
+    );
+}
@@ -0,0 +1,54 @@
+use saimiris::agent::spoof::SpoofDetector;
+use saimiris::config::CaracatConfig;
+use std::net::IpAddr;
+
+fn addr(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn test_spoof_detector_prefixes() {
+    let configs = vec![
+        CaracatConfig {
+            src_ipv4_prefix: Some("192.0.2.0/24".to_string()),
+            ..Default::default()
+        },
+        CaracatConfig {
+            src_ipv6_prefix: Some("2001:db8::/32".to_string()),
+            ..Default::default()
+        },
+    ];
+    let detector = SpoofDetector::new(&configs, 0).unwrap();
+
+    assert!(detector.is_our_addr(addr("192.0.2.10"), addr("192.0.2.10")));
+    // IPv4-mapped addresses, as reported by caracat
+    assert!(detector.is_our_addr(addr("::ffff:192.0.2.10"), addr("::ffff:192.0.2.10")));
+    assert!(detector.is_our_addr(addr("2001:db8::1"), addr("2001:db8::1")));
+    assert!(!detector.is_our_addr(addr("198.51.100.1"), addr("192.0.2.10")));
+}
+
+#[test]
+fn test_spoof_detector_without_prefixes() {
+    let detector = SpoofDetector::new(&[CaracatConfig::default()], 0).unwrap();
+    assert!(detector.is_our_addr(addr("192.0.2.10"), addr("::ffff:192.0.2.10")));
+    assert!(!detector.is_our_addr(addr("198.51.100.1"), addr("192.0.2.10")));
+}
+
+#[test]
+fn test_spoof_detector_invalid_prefix() {
+    let configs = vec![CaracatConfig {
+        src_ipv4_prefix: Some("not-a-prefix".to_string()),
+        ..Default::default()
+    }];
+    assert!(SpoofDetector::new(&configs, 0).is_err());
+}
+
+#[test]
+fn test_spoof_detector_sampling() {
+    let mut detector = SpoofDetector::new(&[], 3).unwrap();
+    let sampled: Vec<bool> = (0..7).map(|_| detector.observe()).collect();
+    assert_eq!(sampled, vec![true, false, false, true, false, false, true]);
+
+    let mut detector = SpoofDetector::new(&[], 0).unwrap();
+    assert!(!detector.observe());
+}
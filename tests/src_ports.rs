@@ -0,0 +1,87 @@
+//! Unit tests for the selection of the source ports of the probes by the agent
+use caracat::models::{Probe, L4};
+use saimiris::agent::ports::{apply_port_policy, PortPolicy, PortStrategy};
+use std::collections::HashSet;
+
+fn probe(dst_addr: &str, src_port: u16, ttl: u8) -> Probe {
+    Probe {
+        dst_addr: dst_addr.parse().unwrap(),
+        src_port,
+        dst_port: 33434,
+        ttl,
+        protocol: L4::UDP,
+    }
+}
+
+#[test]
+fn test_parse_port_policy() {
+    let policy: PortPolicy = "24000-24999".parse().unwrap();
+    assert_eq!(
+        policy,
+        PortPolicy {
+            min: 24000,
+            max: 24999,
+            strategy: PortStrategy::Flow,
+        }
+    );
+    assert_eq!(policy.to_string(), "24000-24999:flow");
+    assert_eq!(policy.to_string().parse::<PortPolicy>().unwrap(), policy);
+    assert_eq!(
+        " 1000 - 2000 :destination"
+            .parse::<PortPolicy>()
+            .unwrap()
+            .strategy,
+        PortStrategy::Destination
+    );
+
+    assert!("24000".parse::<PortPolicy>().is_err());
+    assert!("0-100".parse::<PortPolicy>().is_err());
+    assert!("2000-1000".parse::<PortPolicy>().is_err());
+    assert!("1000-70000".parse::<PortPolicy>().is_err());
+    assert!("1000-2000:random".parse::<PortPolicy>().is_err());
+}
+
+#[test]
+fn test_flow_strategy() {
+    let policy: PortPolicy = "24000-24999:flow".parse().unwrap();
+    let mut probes: Vec<Probe> = (1..=32).map(|ttl| probe("192.0.2.1", 0, ttl)).collect();
+    probes.extend((0..16).map(|flow| probe("192.0.2.1", flow, 1)));
+    apply_port_policy(&mut probes, policy);
+
+    // The probes of a traceroute share a flow
+    let traceroute: HashSet<u16> = probes[..32].iter().map(|p| p.src_port).collect();
+    assert_eq!(traceroute.len(), 1);
+    // The source ports of the client are flow IDs, spread over the range
+    let flows: HashSet<u16> = probes[32..].iter().map(|p| p.src_port).collect();
+    assert!(flows.len() > 8);
+    assert!(probes
+        .iter()
+        .all(|p| (policy.min..=policy.max).contains(&p.src_port)));
+    // Stable across submissions
+    assert_eq!(
+        policy.src_port(&probe("192.0.2.1", 0, 5)),
+        probes[0].src_port
+    );
+}
+
+#[test]
+fn test_destination_strategy() {
+    let policy: PortPolicy = "24000-24999:destination".parse().unwrap();
+    let mut probes: Vec<Probe> = (0..16).map(|flow| probe("2001:db8::1", flow, 1)).collect();
+    probes.extend((1..=16).map(|i| probe(&format!("2001:db8::{:x}", i + 1), 0, 1)));
+    apply_port_policy(&mut probes, policy);
+
+    // All the probes towards a destination share a flow
+    let destination: HashSet<u16> = probes[..16].iter().map(|p| p.src_port).collect();
+    assert_eq!(destination.len(), 1);
+    let destinations: HashSet<u16> = probes[16..].iter().map(|p| p.src_port).collect();
+    assert!(destinations.len() > 8);
+}
+
+#[test]
+fn test_single_port_range() {
+    let policy: PortPolicy = "33000-33000".parse().unwrap();
+    let mut probes = vec![probe("192.0.2.1", 1, 1), probe("192.0.2.2", 2, 2)];
+    apply_port_policy(&mut probes, policy);
+    assert!(probes.iter().all(|p| p.src_port == 33000));
+}
@@ -0,0 +1,66 @@
+//! Unit tests for the leader election of warm standby pairs
+use saimiris::agent::capabilities::AgentCapabilities;
+use saimiris::agent::standby::Election;
+use std::time::Duration;
+
+const SECOND: u64 = 1_000_000_000;
+
+fn heartbeat(
+    agent_id: &str,
+    run_id: &str,
+    started_at_ns: u64,
+    published_at_ns: u64,
+) -> AgentCapabilities {
+    AgentCapabilities {
+        run_id: run_id.to_string(),
+        started_at_ns,
+        published_at_ns,
+        ..AgentCapabilities::current(agent_id)
+    }
+}
+
+fn election(run_id: &str, started_at_ns: u64) -> Election {
+    Election::new("agent1", run_id, started_at_ns, Duration::from_secs(30))
+}
+
+#[test]
+fn test_leader_after_lease() {
+    let election = election("run1", 100 * SECOND);
+    // The heartbeats of a leader may not be heard yet
+    assert!(!election.is_leader(110 * SECOND));
+    assert!(election.is_leader(130 * SECOND));
+}
+
+#[test]
+fn test_oldest_run_leads() {
+    let mut leader = election("run1", 100 * SECOND);
+    let mut standby = election("run2", 200 * SECOND);
+    leader.observe(&heartbeat("agent1", "run2", 200 * SECOND, 240 * SECOND));
+    standby.observe(&heartbeat("agent1", "run1", 100 * SECOND, 240 * SECOND));
+    assert!(leader.is_leader(250 * SECOND));
+    assert!(!standby.is_leader(250 * SECOND));
+
+    // The standby takes over once the heartbeats of the leader stop for a lease
+    assert!(!standby.is_leader(269 * SECOND));
+    assert!(standby.is_leader(270 * SECOND));
+
+    // A restarted leader is standby
+    let mut restarted = election("run3", 300 * SECOND);
+    restarted.observe(&heartbeat("agent1", "run2", 200 * SECOND, 320 * SECOND));
+    standby.observe(&heartbeat("agent1", "run3", 300 * SECOND, 320 * SECOND));
+    assert!(!restarted.is_leader(340 * SECOND));
+    assert!(standby.is_leader(340 * SECOND));
+}
+
+#[test]
+fn test_ignored_heartbeats() {
+    let mut election = election("run1", 100 * SECOND);
+    // Other agents, and our own heartbeats
+    election.observe(&heartbeat("agent2", "run2", 0, 140 * SECOND));
+    election.observe(&heartbeat("agent1", "run1", 0, 140 * SECOND));
+    assert!(election.is_leader(150 * SECOND));
+
+    // Agents predating the election keep the leadership
+    election.observe(&heartbeat("agent1", "run0", 0, 140 * SECOND));
+    assert!(!election.is_leader(150 * SECOND));
+}
@@ -0,0 +1,43 @@
+//! Unit tests for the periodic statistics summary of the agent
+use saimiris::agent::statistics::{
+    produce_statistics, receive_statistics, send_statistics, summary, SendStatistics,
+};
+use std::sync::atomic::Ordering;
+
+#[test]
+fn test_send_statistics_display() {
+    let statistics = SendStatistics::default();
+    statistics.probes_read.fetch_add(10, Ordering::Relaxed);
+    statistics.probes_sent.fetch_add(7, Ordering::Relaxed);
+    statistics.probes_failed.fetch_add(1, Ordering::Relaxed);
+    statistics.probes_filtered.fetch_add(2, Ordering::Relaxed);
+    assert_eq!(
+        statistics.to_string(),
+        "probes_read=10,probes_sent=7,probes_failed=1,probes_filtered=2"
+    );
+}
+
+#[test]
+fn test_summary() {
+    // The workers of an instance share its statistics
+    send_statistics("summary_instance")
+        .probes_sent
+        .fetch_add(3, Ordering::Relaxed);
+    send_statistics("summary_instance")
+        .probes_sent
+        .fetch_add(2, Ordering::Relaxed);
+    receive_statistics("summary0")
+        .replies_received
+        .fetch_add(4, Ordering::Relaxed);
+    produce_statistics()
+        .messages_produced
+        .fetch_add(1, Ordering::Relaxed);
+
+    let lines = summary();
+    assert!(lines.contains(
+        &"instance=summary_instance,probes_read=0,probes_sent=5,probes_failed=0,probes_filtered=0"
+            .to_string()
+    ));
+    assert!(lines.contains(&"interface=summary0,replies_received=4,replies_invalid=0".to_string()));
+    assert!(lines.last().unwrap().starts_with("messages_produced="));
+}
@@ -0,0 +1,86 @@
+//! Tests for the priority of the input topics
+use saimiris::agent::lag::PartitionLag;
+use saimiris::agent::priority::TopicPriorities;
+use saimiris::config::KafkaConfig;
+use std::collections::{HashMap, HashSet};
+
+fn lag(topic: &str, partition: i32, lag: i64) -> PartitionLag {
+    PartitionLag {
+        topic: topic.to_string(),
+        partition,
+        lag,
+    }
+}
+
+fn priorities(priorities: &[(&str, u32)]) -> HashMap<String, u32> {
+    priorities
+        .iter()
+        .map(|(topic, priority)| (topic.to_string(), *priority))
+        .collect()
+}
+
+#[test]
+fn test_same_priority() {
+    assert!(TopicPriorities::new(&HashMap::new()).is_none());
+    assert!(TopicPriorities::new(&priorities(&[("urgent", 0), ("bulk", 0)])).is_none());
+}
+
+#[test]
+fn test_deferred_topics() {
+    let priorities = TopicPriorities::new(&priorities(&[("urgent", 10), ("normal", 5)])).unwrap();
+    assert_eq!(priorities.priority("urgent"), 10);
+    assert_eq!(priorities.priority("bulk"), 0);
+    let topics = ["urgent", "normal", "bulk"];
+
+    // The lower priority topics wait for the urgent probes to be consumed
+    let lags = [
+        lag("urgent", 0, 0),
+        lag("urgent", 1, 3),
+        lag("normal", 0, 7),
+    ];
+    assert_eq!(
+        priorities.deferred_topics(topics, &lags),
+        HashSet::from(["normal".to_string(), "bulk".to_string()])
+    );
+    // Including the topics without a position yet
+    assert_eq!(
+        priorities.deferred_topics(topics, &lags[..2]),
+        HashSet::from(["normal".to_string(), "bulk".to_string()])
+    );
+
+    let lags = [lag("urgent", 0, 0), lag("normal", 0, 7), lag("bulk", 0, 7)];
+    assert_eq!(
+        priorities.deferred_topics(topics, &lags),
+        HashSet::from(["bulk".to_string()])
+    );
+
+    // Nothing to consume, no topic is deferred
+    let lags = [lag("urgent", 0, 0), lag("normal", 0, 0)];
+    assert!(priorities.deferred_topics(topics, &lags).is_empty());
+    assert!(priorities.deferred_topics(topics, &[]).is_empty());
+}
+
+#[test]
+fn test_kafka_config_topic_priorities() {
+    let config = KafkaConfig {
+        in_topics: "urgent,bulk".to_string(),
+        in_topic_priorities: priorities(&[("urgent", 1)]),
+        ..Default::default()
+    };
+    assert!(config.validate().is_ok());
+
+    let config = KafkaConfig {
+        in_topics: "urgent,bulk".to_string(),
+        in_topic_priorities: priorities(&[("other", 1)]),
+        ..Default::default()
+    };
+    assert!(config.validate().is_err());
+
+    let config = KafkaConfig {
+        in_topics: "urgent,bulk".to_string(),
+        in_topic_priorities: priorities(&[("urgent", 1)]),
+        in_topic_template: Some("saimiris-probes-{agent}".to_string()),
+        ..Default::default()
+    };
+    assert!(config.validate().is_err());
+}
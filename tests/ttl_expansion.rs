@@ -0,0 +1,70 @@
+//! Unit tests for the expansion of targets into probes by the agent
+use caracat::models::{Probe, L4};
+use saimiris::agent::expand::{expand_targets, TtlRange};
+use saimiris::probe::ProbeTags;
+
+fn target(dst_addr: &str) -> Probe {
+    Probe {
+        dst_addr: dst_addr.parse().unwrap(),
+        src_port: 24000,
+        dst_port: 33434,
+        ttl: 0,
+        protocol: L4::UDP,
+    }
+}
+
+#[test]
+fn test_parse_ttl_range() {
+    let range: TtlRange = "1-32".parse().unwrap();
+    assert_eq!(range, TtlRange { min: 1, max: 32 });
+    assert_eq!(range.len(), 32);
+    assert_eq!(range.to_string(), "1-32");
+    assert_eq!(" 5 - 5 ".parse::<TtlRange>().unwrap().len(), 1);
+
+    assert!("32".parse::<TtlRange>().is_err());
+    assert!("0-32".parse::<TtlRange>().is_err());
+    assert!("32-1".parse::<TtlRange>().is_err());
+    assert!("1-256".parse::<TtlRange>().is_err());
+}
+
+#[test]
+fn test_expand_targets() {
+    let targets = vec![target("::1"), target("::2")];
+    let tags = vec![
+        ProbeTags {
+            round: 1,
+            ..Default::default()
+        },
+        ProbeTags {
+            round: 2,
+            ..Default::default()
+        },
+    ];
+    let (probes, tags) = expand_targets(targets, tags, TtlRange { min: 3, max: 5 });
+    assert_eq!(probes.len(), 6);
+    assert_eq!(tags.len(), 6);
+
+    let expanded: Vec<(String, u8, u32)> = probes
+        .iter()
+        .zip(&tags)
+        .map(|(probe, tags)| (probe.dst_addr.to_string(), probe.ttl, tags.round))
+        .collect();
+    assert_eq!(
+        expanded,
+        vec![
+            ("::1".to_string(), 3, 1),
+            ("::1".to_string(), 4, 1),
+            ("::1".to_string(), 5, 1),
+            ("::2".to_string(), 3, 2),
+            ("::2".to_string(), 4, 2),
+            ("::2".to_string(), 5, 2),
+        ]
+    );
+}
+
+#[test]
+fn test_expand_targets_without_tags() {
+    let (probes, tags) = expand_targets(vec![target("::1")], Vec::new(), "1-2".parse().unwrap());
+    assert_eq!(probes.len(), 2);
+    assert!(tags.iter().all(ProbeTags::is_empty));
+}